@@ -0,0 +1,60 @@
+// Compares the cost of validating the antiforgery claims of invocations with and without an
+// `AntiforgeryCache`, both for a stream of distinct invocations, which the cache can't spare
+// any work, and for an invocation whose claims are validated again, e.g. when it's redelivered
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use wascap::prelude::KeyPair;
+use wascc_host::{AntiforgeryCache, Invocation, WasccEntity};
+
+fn invocation(key: &KeyPair) -> Invocation {
+    Invocation::new(
+        key,
+        WasccEntity::Actor("MB4OLDIC3TCZ4Q4TGGOVAZC43VXFE2JQVRAXQMQFXUCREOOFEKOKZTY2".to_string()),
+        WasccEntity::Capability {
+            capid: "wascc:keyvalue".to_string(),
+            binding: "default".to_string(),
+        },
+        "Get",
+        vec![0; 256],
+    )
+}
+
+fn antiforgery(c: &mut Criterion) {
+    let key = KeyPair::new_server();
+    let signers = vec![key.public_key()];
+    c.bench_function("validate_antiforgery_with_signers", |b| {
+        b.iter_batched(
+            || invocation(&key),
+            |inv| {
+                black_box(inv)
+                    .validate_antiforgery_with_signers(&signers)
+                    .unwrap()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    let cache = AntiforgeryCache::default();
+    c.bench_function("validate_antiforgery_cached", |b| {
+        b.iter_batched(
+            || invocation(&key),
+            |inv| black_box(inv).validate_antiforgery_cached(&cache).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+
+    let inv = invocation(&key);
+    c.bench_function("validate_antiforgery_with_signers_again", |b| {
+        b.iter(|| {
+            black_box(&inv)
+                .validate_antiforgery_with_signers(&signers)
+                .unwrap()
+        })
+    });
+    c.bench_function("validate_antiforgery_cached_again", |b| {
+        b.iter(|| black_box(&inv).validate_antiforgery_cached(&cache).unwrap())
+    });
+}
+
+criterion_group!(benches, antiforgery);
+criterion_main!(benches);
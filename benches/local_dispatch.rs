@@ -0,0 +1,46 @@
+// Compares the latency of invoking an actor running in the calling host over the lattice, with
+// and without `LatticeConfig::prefer_local` handing the invocation straight to the actor
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::collections::HashMap;
+use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+use wascc_codec::serialize;
+use wascc_host::memlattice::MemBroker;
+use wascc_host::{Actor, Host, HostBuilder};
+
+fn host(prefer_local: bool) -> (Host, String) {
+    let host = HostBuilder::new()
+        .with_mem_broker(MemBroker::new())
+        .with_lattice_namespace("benchlocal")
+        .with_lattice_prefer_local(prefer_local)
+        .build();
+    let actor = Actor::from_file("./examples/.assets/echo.wasm").unwrap();
+    let pk = actor.public_key();
+    host.add_actor(actor).unwrap();
+    (host, pk)
+}
+
+fn local_dispatch(c: &mut Criterion) {
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/bench".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })
+    .unwrap();
+
+    for (name, prefer_local) in &[
+        ("invoke_over_lattice", false),
+        ("invoke_prefer_local", true),
+    ] {
+        let (host, pk) = host(*prefer_local);
+        c.bench_function(name, |b| {
+            b.iter(|| host.call_actor(&pk, OP_HANDLE_REQUEST, &req).unwrap())
+        });
+        host.shutdown().unwrap();
+    }
+}
+
+criterion_group!(benches, local_dispatch);
+criterion_main!(benches);
@@ -0,0 +1,67 @@
+// Compares how long a host takes to start the actors of a manifest holding ten copies of the
+// echo actor when the manifest is applied serially and with `Host::apply_manifest_parallel`
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use wascap::prelude::*;
+use wascc_host::{Host, HostManifest};
+
+const COPIES: usize = 10;
+
+// Each copy is signed with its own subject, so the host treats them as distinct actors
+fn manifest(dir: &Path) -> HostManifest {
+    std::fs::create_dir_all(dir).unwrap();
+    let bytes = std::fs::read("./examples/.assets/echo.wasm").unwrap();
+    let actors: Vec<_> = (0..COPIES)
+        .map(|i| {
+            let (issuer, module) = (KeyPair::new_account(), KeyPair::new_module());
+            let claims = ClaimsBuilder::<Actor>::new()
+                .issuer(&issuer.public_key())
+                .subject(&module.public_key())
+                .with_metadata(Actor {
+                    name: Some(format!("echo{}", i)),
+                    ..Default::default()
+                })
+                .build();
+            let path = dir.join(format!("echo{}.wasm", i));
+            std::fs::write(&path, wasm::embed_claims(&bytes, &claims, &issuer).unwrap()).unwrap();
+            path.to_string_lossy().to_string()
+        })
+        .collect();
+    serde_json::from_value(serde_json::json!({ "actors": actors })).unwrap()
+}
+
+fn manifest_parallel(c: &mut Criterion) {
+    let dir = std::env::temp_dir().join(format!("wascc-bench-manifest-{}", std::process::id()));
+    let manifest = manifest(&dir);
+
+    let mut group = c.benchmark_group("apply_manifest");
+    group.sample_size(10);
+    for (name, concurrency) in &[("serial", 1), ("parallel", 4)] {
+        group.bench_function(*name, |b| {
+            b.iter_custom(|iters| {
+                let mut total = Duration::from_secs(0);
+                for _ in 0..iters {
+                    let host = Host::new();
+                    let start = Instant::now();
+                    if *concurrency == 1 {
+                        host.apply_manifest_with_report(manifest.clone(), true)
+                            .unwrap();
+                    } else {
+                        host.apply_manifest_parallel(manifest.clone(), *concurrency)
+                            .unwrap();
+                    }
+                    total += start.elapsed();
+                    host.shutdown().unwrap();
+                }
+                total
+            })
+        });
+    }
+    group.finish();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+criterion_group!(benches, manifest_parallel);
+criterion_main!(benches);
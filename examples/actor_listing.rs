@@ -0,0 +1,81 @@
+// Compares the allocations made by listing a host's actors with `Host::actors` against
+// `Host::for_each_actor`. Both copy every actor's claims, so that no lock on the host is held
+// while the caller looks at them. Run with `cargo run --release --example actor_listing`
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use wascap::prelude::*;
+use wascc_host::Host;
+
+const ACTORS: usize = 100;
+const POLLS: usize = 100;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let host = Host::new();
+    let bytes = std::fs::read("./examples/.assets/echo.wasm")?;
+    for i in 0..ACTORS {
+        let (issuer, module) = (KeyPair::new_account(), KeyPair::new_module());
+        let claims = ClaimsBuilder::<Actor>::new()
+            .issuer(&issuer.public_key())
+            .subject(&module.public_key())
+            .with_metadata(Actor {
+                name: Some(format!("echo-{}", i)),
+                caps: Some(vec![caps::HTTP_SERVER.to_string()]),
+                tags: Some(vec!["listing".to_string()]),
+                ..Default::default()
+            })
+            .build();
+        let embedded = wasm::embed_claims(&bytes, &claims, &issuer)?;
+        host.add_actor(wascc_host::Actor::from_slice(&embedded)?)?;
+    }
+
+    let measure = |f: &dyn Fn() -> usize| {
+        let before = ALLOCATIONS.load(Ordering::SeqCst);
+        let mut names = 0;
+        for _ in 0..POLLS {
+            names += f();
+        }
+        assert_eq!(ACTORS * POLLS, names);
+        ALLOCATIONS.load(Ordering::SeqCst) - before
+    };
+    let cloned = measure(&|| {
+        host.actors()
+            .iter()
+            .filter(|(_, c)| c.metadata.as_ref().unwrap().name.is_some())
+            .count()
+    });
+    let borrowed = measure(&|| {
+        let mut n = 0;
+        host.for_each_actor(|_, c| {
+            if c.metadata.as_ref().unwrap().name.is_some() {
+                n += 1;
+            }
+        });
+        n
+    });
+    println!(
+        "{} polls of {} actors: actors() made {} allocations, for_each_actor() made {}",
+        POLLS, ACTORS, cloned, borrowed
+    );
+
+    host.shutdown()?;
+    Ok(())
+}
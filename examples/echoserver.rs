@@ -24,7 +24,8 @@ fn main() -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
         generate_port_config(8082),
     )?;
 
-    std::thread::park();
+    // Blocks until ctrl-c, then removes the actors and provider before exiting
+    host.run_until_signal()?;
 
     Ok(())
 }
@@ -15,6 +15,7 @@ fn main() -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
     host.apply_manifest(HostManifest::from_path(
         "./examples/sample_manifest.yaml",
         true,
+        false,
     )?)?;
 
     std::thread::park();
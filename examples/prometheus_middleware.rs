@@ -31,6 +31,8 @@ mod prometheus_example {
             metrics_server_addr: Some(server_addr),
             pushgateway_config: None,
             moving_average_window_size: None,
+            max_operation_labels: None,
+            legacy_metric_names: false,
         };
         host.add_middleware(PrometheusMiddleware::new(config).unwrap());
 
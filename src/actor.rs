@@ -1,9 +1,10 @@
 use crate::authz;
+use crate::imports::ImportReport;
 use crate::Result;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
-use wascap::jwt::Token;
+use wascap::jwt::{Claims, Token};
 
 /// An actor is a WebAssembly module that conforms to the waSCC protocols and can securely
 /// consume capabilities exposed by native or portable capability providers
@@ -11,6 +12,7 @@ use wascap::jwt::Token;
 pub struct Actor {
     pub(crate) token: Token<wascap::jwt::Actor>,
     pub(crate) bytes: Vec<u8>,
+    pub(crate) imports: ImportReport,
 }
 
 impl Actor {
@@ -18,9 +20,19 @@ impl Actor {
     /// an unsigned module, or a module signed improperly, will result in an error
     pub fn from_slice(buf: &[u8]) -> Result<Actor> {
         let token = authz::extract_claims(&buf)?;
+        // The module has already been parsed to extract its claims, so a failure here is a
+        // limitation of the import scan rather than a broken module
+        let imports = ImportReport::from_module(&buf).unwrap_or_else(|e| {
+            warn!(
+                "Imports of actor {} not checked: {}",
+                token.claims.subject, e
+            );
+            ImportReport::default()
+        });
         Ok(Actor {
             token,
             bytes: buf.to_vec(),
+            imports,
         })
     }
 
@@ -59,6 +71,18 @@ impl Actor {
         }
     }
 
+    /// Obtain a report of the functions the actor's module imports, which the host checks when
+    /// the actor is added (see `HostBuilder::with_strict_imports`)
+    pub fn imports(&self) -> &ImportReport {
+        &self.imports
+    }
+
+    /// Obtain the claims in the actor's embedded token, e.g. to register them with a host ahead of
+    /// the actor via `Host::register_actor_claims`
+    pub fn claims(&self) -> Claims<wascap::jwt::Actor> {
+        self.token.claims.clone()
+    }
+
     /// Obtain the list of tags in the actor's token
     pub fn tags(&self) -> Vec<String> {
         match self.token.claims.metadata.as_ref().unwrap().tags {
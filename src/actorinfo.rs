@@ -0,0 +1,178 @@
+// Operational data about the actors running in a host, kept up to date by each actor's thread
+
+use crate::imports::ImportReport;
+use crate::inthost::InvocationResponse;
+use crate::locks::{MutexExt, RwLockExt};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Operational data about an actor running in a host, counted from when it was started
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActorRuntimeInfo {
+    pub started_at: SystemTime,
+    /// How long the actor took to become ready for invocations once its thread started, which
+    /// is mostly the time taken to compile its module
+    pub start_duration: Duration,
+    /// The number of invocations the actor has handled
+    pub invocations: u64,
+    /// The number of invocations that failed
+    pub errors: u64,
+    /// The error of the most recent failed invocation, if any
+    pub last_error: Option<String>,
+    /// The time of the most recent failed invocation, if any
+    pub last_error_at: Option<SystemTime>,
+    /// The time of the most recent invocation, if any
+    pub last_invocation_at: Option<SystemTime>,
+    /// The imports of the actor's module
+    pub imports: ImportReport,
+    /// The annotations the actor was added with or has been given since, see
+    /// `Host::add_actor_with_annotations`
+    pub annotations: HashMap<String, String>,
+}
+
+// Counters are atomics so that recording an invocation never waits on a lock; only errors,
+// which carry a message, take one
+pub(crate) struct ActorCounters {
+    started_at: SystemTime,
+    start_duration: Duration,
+    invocations: AtomicU64,
+    errors: AtomicU64,
+    last_invocation_millis: AtomicU64, // milliseconds since the epoch, 0 if never invoked
+    last_error: Mutex<Option<(String, SystemTime)>>,
+    imports: ImportReport,
+}
+
+fn epoch_millis(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+impl ActorCounters {
+    fn new(start_duration: Duration, imports: ImportReport) -> ActorCounters {
+        ActorCounters {
+            started_at: SystemTime::now(),
+            start_duration,
+            invocations: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            last_invocation_millis: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+            imports,
+        }
+    }
+
+    pub fn record(&self, response: &InvocationResponse) {
+        let millis = epoch_millis(SystemTime::now());
+        let now = UNIX_EPOCH + Duration::from_millis(millis);
+        self.invocations.fetch_add(1, Ordering::Relaxed);
+        self.last_invocation_millis.store(millis, Ordering::Relaxed);
+        if let Some(ref e) = response.error {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+            *self.last_error.lock_or_recover() = Some((e.to_string(), now));
+        }
+    }
+
+    pub fn info(&self, annotations: HashMap<String, String>) -> ActorRuntimeInfo {
+        let last_error = self.last_error.lock_or_recover().clone();
+        ActorRuntimeInfo {
+            started_at: self.started_at,
+            start_duration: self.start_duration,
+            invocations: self.invocations.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            last_error_at: last_error.as_ref().map(|(_, t)| *t),
+            last_error: last_error.map(|(e, _)| e),
+            last_invocation_at: match self.last_invocation_millis.load(Ordering::Relaxed) {
+                0 => None,
+                ms => Some(UNIX_EPOCH + Duration::from_millis(ms)),
+            },
+            imports: self.imports.clone(),
+            annotations,
+        }
+    }
+}
+
+/// The counters of every actor running in a host, and the annotations embedders have attached
+/// to them. Annotations are set before an actor's thread starts, so they're kept apart from the
+/// counters
+#[derive(Default)]
+pub(crate) struct ActorRuntime {
+    actors: RwLock<HashMap<String, Arc<ActorCounters>>>,
+    annotations: RwLock<HashMap<String, HashMap<String, String>>>,
+}
+
+impl ActorRuntime {
+    /// Starts counting for a newly started actor, replacing the counters of any earlier instance
+    pub fn start(
+        &self,
+        actor: &str,
+        start_duration: Duration,
+        imports: ImportReport,
+    ) -> Arc<ActorCounters> {
+        let counters = Arc::new(ActorCounters::new(start_duration, imports));
+        self.actors
+            .write_or_recover()
+            .insert(actor.to_string(), counters.clone());
+        counters
+    }
+
+    /// Stops counting for an actor and forgets its annotations, unless it has since been
+    /// started again
+    pub fn stop(&self, actor: &str, counters: &Arc<ActorCounters>) {
+        let mut lock = self.actors.write_or_recover();
+        if lock.get(actor).map_or(false, |c| Arc::ptr_eq(c, counters)) {
+            lock.remove(actor);
+            self.annotations.write_or_recover().remove(actor);
+        }
+    }
+
+    pub fn info(&self, actor: &str) -> Option<ActorRuntimeInfo> {
+        let annotations = self.annotations(actor).unwrap_or_default();
+        self.actors
+            .read_or_recover()
+            .get(actor)
+            .map(|c| c.info(annotations))
+    }
+
+    /// Replaces all of an actor's annotations
+    pub fn annotate(&self, actor: &str, annotations: HashMap<String, String>) {
+        self.annotations
+            .write_or_recover()
+            .insert(actor.to_string(), annotations);
+    }
+
+    pub fn set_annotation(&self, actor: &str, key: &str, value: &str) {
+        self.annotations
+            .write_or_recover()
+            .entry(actor.to_string())
+            .or_default()
+            .insert(key.to_string(), value.to_string());
+    }
+
+    pub fn forget_annotations(&self, actor: &str) {
+        self.annotations.write_or_recover().remove(actor);
+    }
+
+    /// The actor's annotations, or `None` if it has none
+    pub fn annotations(&self, actor: &str) -> Option<HashMap<String, String>> {
+        self.annotations
+            .read_or_recover()
+            .get(actor)
+            .filter(|a| !a.is_empty())
+            .cloned()
+    }
+
+    /// The actors with the given annotation, in sorted order
+    pub fn annotated(&self, key: &str, value: &str) -> Vec<String> {
+        let mut actors: Vec<_> = self
+            .annotations
+            .read_or_recover()
+            .iter()
+            .filter(|(_, a)| a.get(key).map(String::as_str) == Some(value))
+            .map(|(pk, _)| pk.to_string())
+            .collect();
+        actors.sort();
+        actors
+    }
+}
@@ -0,0 +1,282 @@
+// A local, read-only administrative endpoint for hosts running without a lattice. Enabled with
+// the `admin_api` feature and `HostBuilder::with_admin_socket`, it serves the host's info,
+// actors, capability providers, bindings (separately, or together as one consistent snapshot),
+// and statistics as JSON over HTTP, on either a TCP address or a Unix domain socket, plus a
+// `POST /shutdown` guarded by a bearer token. The server runs on its own thread and is stopped
+// when the host shuts down.
+
+use crate::{errors, Host, HostSnapshot, Result};
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::server::accept::Accept;
+use hyper::server::Builder;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+enum Listener {
+    Tcp(std::net::TcpListener),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener, PathBuf),
+}
+
+// Binds the admin endpoint to the given address (a socket address such as `127.0.0.1:9900`,
+// or otherwise the path of a Unix domain socket) and starts serving it. The listener is bound
+// before returning, so requests made as soon as the host is built are queued rather than refused
+pub(crate) fn start(host: &Host, addr: &str, token: Option<String>) -> Result<()> {
+    let listener = bind(addr)?;
+    let (kill_switch, kill_switch_rx) = tokio::sync::oneshot::channel::<()>();
+    let host2 = host.clone();
+    let thread_handle = std::thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().expect("Failed to create tokio runtime");
+        rt.block_on(async move {
+            let stopped = async {
+                let _ = kill_switch_rx.await;
+            };
+            let res = match listener {
+                Listener::Tcp(l) => match Server::from_tcp(l) {
+                    Ok(builder) => serve(builder, host2, token, stopped).await,
+                    Err(e) => Err(e),
+                },
+                #[cfg(unix)]
+                Listener::Unix(l, path) => {
+                    let res = match tokio::net::UnixListener::from_std(l) {
+                        Ok(mut l) => {
+                            let incoming = hyper::server::accept::from_stream(l.incoming());
+                            serve(Server::builder(incoming), host2, token, stopped).await
+                        }
+                        Err(e) => {
+                            error!("Failed to listen on admin socket: {}", e);
+                            Ok(())
+                        }
+                    };
+                    let _ = std::fs::remove_file(path);
+                    res
+                }
+            };
+            if let Err(e) = res {
+                error!("Admin server error: {}", e);
+            }
+        })
+    });
+    info!("Serving the admin API on {}", addr);
+
+    host.on_shutdown(move || {
+        if kill_switch.send(()).is_err() {
+            error!("Error terminating the admin server");
+        }
+        if thread_handle.join().is_err() {
+            error!("Error terminating the admin server thread");
+        }
+    });
+    Ok(())
+}
+
+fn bind(addr: &str) -> Result<Listener> {
+    let failed = |e: std::io::Error| {
+        errors::new(errors::ErrorKind::MiscHost(format!(
+            "Failed to bind the admin API to {}: {}",
+            addr, e
+        )))
+    };
+    if let Ok(sockaddr) = addr.parse::<SocketAddr>() {
+        return std::net::TcpListener::bind(sockaddr)
+            .map(Listener::Tcp)
+            .map_err(failed);
+    }
+    #[cfg(unix)]
+    {
+        std::os::unix::net::UnixListener::bind(addr)
+            .map(|l| Listener::Unix(l, PathBuf::from(addr)))
+            .map_err(failed)
+    }
+    #[cfg(not(unix))]
+    Err(errors::new(errors::ErrorKind::MiscHost(format!(
+        "The admin API address {} is not a valid socket address",
+        addr
+    ))))
+}
+
+async fn serve<I>(
+    builder: Builder<I>,
+    host: Host,
+    token: Option<String>,
+    stopped: impl std::future::Future<Output = ()>,
+) -> hyper::error::Result<()>
+where
+    I: Accept,
+    I::Conn: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    builder
+        .serve(make_service_fn(move |_: &I::Conn| {
+            let host = host.clone();
+            let token = token.clone();
+            async move {
+                Ok::<_, hyper::error::Error>(service_fn(move |req| {
+                    serve_request(req, host.clone(), token.clone())
+                }))
+            }
+        }))
+        .with_graceful_shutdown(stopped)
+        .await
+}
+
+async fn serve_request(
+    req: Request<Body>,
+    host: Host,
+    token: Option<String>,
+) -> hyper::error::Result<Response<Body>> {
+    let res = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/info") => json_response(json!(host.info())),
+        (&Method::GET, "/actors") => json_response(actors(&host.snapshot())),
+        (&Method::GET, "/capabilities") => json_response(capabilities(&host.snapshot())),
+        (&Method::GET, "/bindings") => json_response(bindings(&host.snapshot())),
+        (&Method::GET, "/snapshot") => json_response(snapshot(&host.snapshot())),
+        (&Method::GET, "/stats") => json_response(stats(&host)),
+        (&Method::POST, "/shutdown") => {
+            if authorized(&req, token.as_deref()) {
+                info!(
+                    "Shutting down host {} at the request of the admin API",
+                    host.id()
+                );
+                // Shutting down stops this server, so it can't happen on the server's thread
+                std::thread::spawn(move || {
+                    if let Err(e) = host.shutdown() {
+                        error!("Failed to shut down host: {}", e);
+                    }
+                });
+                status_response(StatusCode::ACCEPTED)
+            } else {
+                status_response(StatusCode::UNAUTHORIZED)
+            }
+        }
+        _ => status_response(StatusCode::NOT_FOUND),
+    };
+    Ok(res)
+}
+
+// Shutting down requires the configured token as a bearer token, and is refused outright if
+// no token was configured
+fn authorized(req: &Request<Body>, token: Option<&str>) -> bool {
+    let token = match token {
+        Some(t) => t,
+        None => return false,
+    };
+    req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|presented| {
+            ring::constant_time::verify_slices_are_equal(presented.as_bytes(), token.as_bytes())
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+// The actors, capability providers, bindings, and labels as of a single moment, so that every
+// binding listed is to a provider that's listed too
+fn snapshot(snapshot: &HostSnapshot) -> Value {
+    json!({
+        "actors": actors(snapshot),
+        "capabilities": capabilities(snapshot),
+        "bindings": bindings(snapshot),
+        "labels": snapshot.labels,
+        "taken_at": snapshot
+            .taken_at
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0),
+    })
+}
+
+fn actors(snapshot: &HostSnapshot) -> Value {
+    // Snapshots list actors in order of their public keys
+    Value::Array(
+        snapshot
+            .actors
+            .iter()
+            .map(|(pk, claims)| json!({ "id": pk, "claims": claims }))
+            .collect(),
+    )
+}
+
+fn capabilities(snapshot: &HostSnapshot) -> Value {
+    let mut caps: Vec<_> = snapshot
+        .capabilities
+        .iter()
+        .map(|((binding, capid), descriptor)| {
+            json!({ "capid": capid, "binding": binding, "descriptor": descriptor })
+        })
+        .collect();
+    caps.sort_by_key(|c| c.to_string());
+    Value::Array(caps)
+}
+
+// Configuration values often hold credentials, so only their names are listed
+fn bindings(snapshot: &HostSnapshot) -> Value {
+    let mut bindings: Vec<_> = snapshot
+        .bindings
+        .iter()
+        .map(|b| {
+            json!({
+                "actor": b.actor,
+                "capid": b.capid,
+                "binding": b.binding,
+                "config_name": b.config_name,
+                "values": b.value_names,
+            })
+        })
+        .collect();
+    bindings.sort_by_key(|b| b.to_string());
+    Value::Array(bindings)
+}
+
+fn stats(host: &Host) -> Value {
+    let mut providers: Vec<_> = host
+        .capabilities()
+        .keys()
+        .filter_map(|(binding, capid)| {
+            host.provider_stats(capid, binding).map(|s| {
+                json!({
+                    "capid": capid,
+                    "binding": binding,
+                    "bound_actors": s.bound_actors,
+                    "invocations": s.invocations,
+                    "errors": s.errors,
+                    "last_invocation_at": s.last_invocation_at
+                        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                        .map(|d| d.as_millis() as u64),
+                })
+            })
+        })
+        .collect();
+    providers.sort_by_key(|p| p.to_string());
+    let counts = host.stats();
+    json!({
+        "actors": host.actors().len(),
+        "in_flight": host.in_flight(),
+        "providers": providers,
+        "authz_denials": counts.authz_denials,
+        "middleware_halts": counts.middleware_halts,
+    })
+}
+
+fn json_response(body: Value) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
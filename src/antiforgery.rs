@@ -0,0 +1,230 @@
+//! A bounded cache of the invocation claims tokens a host has verified. Each invocation carries
+//! its own signed claims, and the same signed claims are often checked more than once, e.g. when
+//! an invocation is retried or redelivered, or validated both by a host and by the middleware
+//! that handles it. A token is verified with wascap the first time it's seen, and once it's in
+//! the cache its signature isn't verified again; its validity period is still checked. The
+//! checks that tie the claims to a particular invocation are never cached
+
+use crate::locks::MutexExt;
+use crate::Result;
+use ring::digest::{digest, SHA256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use wascap::jwt::Claims;
+use wascap::prelude::Invocation;
+
+/// The number of verified claims tokens an antiforgery cache holds by default
+pub const DEFAULT_ANTIFORGERY_CACHE_CAPACITY: usize = 1024;
+
+/// Remembers the invocation claims tokens whose signatures it has verified, keyed by a digest
+/// of the whole encoded token, evicting the least recently used token once it holds `capacity`
+/// of them. Pass it to `Invocation::validate_antiforgery_cached`, or have a host use one for
+/// the invocations it receives with `HostBuilder::with_antiforgery_cache`
+pub struct AntiforgeryCache {
+    capacity: usize,
+    verified: Mutex<Lru>,
+}
+
+// Verified claims by token digest, along with the order in which they were last used. Each
+// lookup is O(log capacity)
+#[derive(Default)]
+struct Lru {
+    claims: HashMap<Vec<u8>, (Claims<Invocation>, u64)>,
+    by_use: BTreeMap<u64, Vec<u8>>,
+    uses: u64,
+}
+
+impl Lru {
+    fn get(&mut self, key: &[u8]) -> Option<Claims<Invocation>> {
+        self.uses += 1;
+        let uses = self.uses;
+        let (claims, last_used) = self.claims.get_mut(key)?;
+        let key = self.by_use.remove(&*last_used).unwrap_or_default();
+        *last_used = uses;
+        self.by_use.insert(uses, key);
+        Some(claims.clone())
+    }
+
+    fn insert(&mut self, key: Vec<u8>, claims: Claims<Invocation>, capacity: usize) {
+        self.uses += 1;
+        if let Some((_, last_used)) = self.claims.remove(&key) {
+            self.by_use.remove(&last_used);
+        }
+        while self.claims.len() >= capacity {
+            let oldest = match self.by_use.keys().next() {
+                Some(u) => *u,
+                None => break,
+            };
+            if let Some(evicted) = self.by_use.remove(&oldest) {
+                self.claims.remove(&evicted);
+            }
+        }
+        self.by_use.insert(self.uses, key.clone());
+        self.claims.insert(key, (claims, self.uses));
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some((_, last_used)) = self.claims.remove(key) {
+            self.by_use.remove(&last_used);
+        }
+    }
+}
+
+impl Default for AntiforgeryCache {
+    fn default() -> Self {
+        AntiforgeryCache::new(DEFAULT_ANTIFORGERY_CACHE_CAPACITY)
+    }
+}
+
+impl AntiforgeryCache {
+    /// Creates a cache holding at most `capacity` verified claims tokens
+    pub fn new(capacity: usize) -> AntiforgeryCache {
+        AntiforgeryCache {
+            capacity,
+            verified: Mutex::new(Lru::default()),
+        }
+    }
+
+    /// The number of verified claims tokens currently cached
+    pub fn len(&self) -> usize {
+        self.verified.lock_or_recover().claims.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Returns the claims in the encoded token once they've been verified as
+    // `verify_invocation_token` verifies them. A token that's already cached isn't verified
+    // again, but is refused if it has since expired
+    pub(crate) fn verify(&self, encoded: &str) -> Result<Claims<Invocation>> {
+        let key = token_key(encoded);
+        let cached = self.verified.lock_or_recover().get(&key);
+        if let Some(claims) = cached {
+            let now = now_secs();
+            let expired = claims.expires.map_or(false, |exp| exp < now);
+            let cannot_use_yet = claims.not_before.map_or(false, |nbf| nbf > now);
+            if expired {
+                self.verified.lock_or_recover().remove(&key);
+            }
+            return crate::inthost::check_invocation_token(claims, true, expired, cannot_use_yet);
+        }
+        // Verified without holding the lock
+        let claims = crate::inthost::verify_invocation_token(encoded)?;
+        if self.capacity > 0 {
+            self.verified
+                .lock_or_recover()
+                .insert(key, claims.clone(), self.capacity);
+        }
+        Ok(claims)
+    }
+}
+
+fn token_key(encoded: &str) -> Vec<u8> {
+    digest(&SHA256, encoded.as_bytes()).as_ref().to_vec()
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{token_key, AntiforgeryCache};
+    use crate::{Invocation, WasccEntity};
+    use wascap::prelude::KeyPair;
+
+    fn inv(key: &KeyPair, msg: &[u8]) -> Invocation {
+        Invocation::new(
+            key,
+            WasccEntity::Actor("Ma".to_string()),
+            WasccEntity::Capability {
+                capid: "wascc:keyvalue".to_string(),
+                binding: "default".to_string(),
+            },
+            "Get",
+            msg.to_vec(),
+        )
+    }
+
+    #[test]
+    fn tampered_invocations_rejected_when_cached() {
+        let key = KeyPair::new_server();
+        let cache = AntiforgeryCache::default();
+        let good = inv(&key, b"hello");
+        assert!(good.validate_antiforgery_cached(&cache).is_ok());
+        // Validating the same invocation again uses the cached claims
+        assert!(good.validate_antiforgery_cached(&cache).is_ok());
+        assert_eq!(1, cache.len());
+
+        // Every tampered copy carries claims that are already in the cache
+        let mut payload = good.clone();
+        payload.msg = b"goodbye".to_vec();
+        assert!(payload.validate_antiforgery_cached(&cache).is_err());
+
+        let mut target = good.clone();
+        target.target = WasccEntity::Actor("Mb".to_string());
+        assert!(target.validate_antiforgery_cached(&cache).is_err());
+
+        let mut operation = good.clone();
+        operation.operation = "Del".to_string();
+        assert!(operation.validate_antiforgery_cached(&cache).is_err());
+
+        let mut id = good.clone();
+        id.id = "another".to_string();
+        assert!(id.validate_antiforgery_cached(&cache).is_err());
+
+        let mut host = good.clone();
+        host.host_id = KeyPair::new_server().public_key();
+        assert!(host.validate_antiforgery_cached(&cache).is_err());
+
+        // Claims carrying another token's signature aren't taken for that token, and aren't
+        // cached once they fail verification
+        let mut forged = good.clone();
+        let other = inv(&KeyPair::new_server(), b"hello");
+        let sig = other.encoded_claims.rsplit('.').next().unwrap();
+        let mut parts: Vec<_> = good.encoded_claims.split('.').collect();
+        parts[2] = sig;
+        forged.encoded_claims = parts.join(".");
+        assert!(forged.validate_antiforgery_cached(&cache).is_err());
+        assert!(forged.validate_antiforgery_cached(&cache).is_err());
+        assert_eq!(1, cache.len());
+        assert!(good.validate_antiforgery_cached(&cache).is_ok());
+    }
+
+    #[test]
+    fn least_recently_used_tokens_evicted() {
+        let cache = AntiforgeryCache::new(4);
+        let key = KeyPair::new_server();
+        let invs: Vec<_> = (0..6u8).map(|i| inv(&key, &[i])).collect();
+        for inv in &invs[..4] {
+            assert!(inv.validate_antiforgery_cached(&cache).is_ok());
+        }
+        // Using the first token again leaves the second as the least recently used
+        assert!(invs[0].validate_antiforgery_cached(&cache).is_ok());
+        assert!(invs[4].validate_antiforgery_cached(&cache).is_ok());
+        assert_eq!(4, cache.len());
+        {
+            let verified = cache.verified.lock().unwrap();
+            assert!(verified
+                .claims
+                .contains_key(&token_key(&invs[0].encoded_claims)));
+            assert!(!verified
+                .claims
+                .contains_key(&token_key(&invs[1].encoded_claims)));
+            assert_eq!(4, verified.by_use.len());
+        }
+        for inv in &invs {
+            assert!(inv.validate_antiforgery_cached(&cache).is_ok());
+            assert!(cache.len() <= 4);
+        }
+
+        let disabled = AntiforgeryCache::new(0);
+        assert!(invs[5].validate_antiforgery_cached(&disabled).is_ok());
+        assert!(disabled.is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+//! # Invocation Audit
+//!
+//! A bounded, in-memory record of the most recent invocations handled by each actor, retrieved
+//! with `Host::actor_recent_invocations`. This is intended for answering questions like "what
+//! did this actor do in the last minute" without having to enable trace logging for the entire
+//! host. The number of entries kept per actor can be changed (or the audit disabled entirely)
+//! with `HostBuilder::with_audit_capacity`.
+
+use crate::locks::{MutexExt, RwLockExt};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// The default number of invocations recorded for each actor
+pub const DEFAULT_AUDIT_CAPACITY: usize = 256;
+
+/// A record of a single invocation handled by an actor
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvocationAuditEntry {
+    /// When the invocation was received by the actor
+    pub timestamp: SystemTime,
+    /// The URL of the entity (actor or capability provider) that sent the invocation
+    pub origin: String,
+    /// The operation that was invoked
+    pub operation: String,
+    /// The size of the invocation payload, in bytes
+    pub payload_size: usize,
+    /// The error produced by the invocation, or `None` if it succeeded
+    pub error: Option<String>,
+    /// How long the actor (and any middleware) took to handle the invocation
+    pub duration: Duration,
+}
+
+pub(crate) struct AuditLog {
+    capacity: usize,
+    actors: RwLock<HashMap<String, Arc<Mutex<VecDeque<InvocationAuditEntry>>>>>,
+}
+
+impl AuditLog {
+    /// Creates an audit log keeping up to `capacity` entries per actor. A capacity
+    /// of 0 disables the audit
+    pub fn new(capacity: usize) -> AuditLog {
+        AuditLog {
+            capacity,
+            actors: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, actor: &str, entry: InvocationAuditEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        let entries = {
+            let existing = self.actors.read_or_recover().get(actor).cloned();
+            match existing {
+                Some(e) => e,
+                None => self
+                    .actors
+                    .write_or_recover()
+                    .entry(actor.to_string())
+                    .or_insert_with(|| Arc::new(Mutex::new(VecDeque::with_capacity(self.capacity))))
+                    .clone(),
+            }
+        };
+        let mut entries = entries.lock_or_recover();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// The recorded entries for the actor, oldest first
+    pub fn entries(&self, actor: &str) -> Vec<InvocationAuditEntry> {
+        self.actors
+            .read_or_recover()
+            .get(actor)
+            .map(|e| e.lock_or_recover().iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn forget(&self, actor: &str) {
+        self.actors.write_or_recover().remove(actor);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AuditLog, InvocationAuditEntry};
+    use std::time::{Duration, SystemTime};
+
+    fn entry(operation: &str) -> InvocationAuditEntry {
+        InvocationAuditEntry {
+            timestamp: SystemTime::now(),
+            origin: "wasmbus://wascc/http_server/default".to_string(),
+            operation: operation.to_string(),
+            payload_size: 0,
+            error: None,
+            duration: Duration::from_millis(1),
+        }
+    }
+
+    #[test]
+    fn oldest_entries_evicted() {
+        let log = AuditLog::new(2);
+        log.record("Ma", entry("one"));
+        log.record("Ma", entry("two"));
+        log.record("Ma", entry("three"));
+        log.record("Mb", entry("four"));
+
+        let ops: Vec<_> = log.entries("Ma").into_iter().map(|e| e.operation).collect();
+        assert_eq!(ops, vec!["two", "three"]);
+        assert_eq!(1, log.entries("Mb").len());
+
+        log.forget("Ma");
+        assert!(log.entries("Ma").is_empty());
+    }
+
+    #[test]
+    fn zero_capacity_disables() {
+        let log = AuditLog::new(0);
+        log.record("Ma", entry("one"));
+        assert!(log.entries("Ma").is_empty());
+    }
+}
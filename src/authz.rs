@@ -1,13 +1,26 @@
+//! Types used to customize how the host authorizes actors and their invocations
+
 use crate::errors;
+use crate::locks::RwLockExt;
 use crate::{Host, Result, WasccEntity};
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use wascap::jwt::Token;
 use wascap::prelude::*;
 
 pub(crate) type ClaimsMap = Arc<RwLock<HashMap<String, Claims<wascap::jwt::Actor>>>>;
 
+/// The `requested_by` passed to `Authorizer::can_terminate` when an actor is removed through
+/// the host's own API, e.g. `Host::remove_actor`
+pub const REQUESTED_BY_HOST: &str = "host";
+
+/// The `requested_by` passed to `Authorizer::can_terminate` when an actor is terminated by an
+/// unsigned lattice control plane command. Commands signed by a trusted issuer are passed as
+/// `control:{issuer}` instead, where the issuer is the signer's public key
+pub const REQUESTED_BY_CONTROL_PLANE: &str = "control";
+
 /// An authorizer is responsible for determining whether an actor can be loaded as well as
 /// whether an actor can invoke another entity. For invocation checks, the authorizer is only ever invoked _after_
 /// an initial capability attestation check has been performed and _passed_. This has the net effect of making it
@@ -21,6 +34,180 @@ pub trait Authorizer: Sync + Send {
     /// including the operation that occurs during `bind_actor`. Developers should be aware of this because
     /// if `set_authorizer` is done _after_ actor binding, it could potentially allow an unauthorized binding.
     fn can_invoke(&self, claims: &Claims<Actor>, target: &WasccEntity, operation: &str) -> bool;
+    /// The variant of `can_load` that the host actually calls, which also receives information about
+    /// the host performing the load. The default implementation ignores the context and delegates to `can_load`
+    fn can_load_ctx(&self, claims: &Claims<Actor>, _ctx: &AuthorizationContext) -> bool {
+        self.can_load(claims)
+    }
+    /// The variant of `can_invoke` that the host actually calls, which also receives information about
+    /// the host performing the invocation. The default implementation ignores the context and delegates to `can_invoke`
+    fn can_invoke_ctx(
+        &self,
+        claims: &Claims<Actor>,
+        target: &WasccEntity,
+        operation: &str,
+        _ctx: &AuthorizationContext,
+    ) -> bool {
+        self.can_invoke(claims, target, operation)
+    }
+    /// This check is performed before a running actor is replaced with a new module, whether by
+    /// `replace_actor` or by a file watcher. The default implementation allows every replacement
+    fn can_replace(&self, _old_claims: &Claims<Actor>, _new_claims: &Claims<Actor>) -> bool {
+        true
+    }
+    /// This check is performed before an actor is removed at someone's request, either through
+    /// the host's API (`requested_by` is `REQUESTED_BY_HOST`) or by a lattice control plane
+    /// command (see `REQUESTED_BY_CONTROL_PLANE`). It is not performed when the host shuts down.
+    /// The default implementation allows every termination
+    fn can_terminate(&self, _claims: &Claims<Actor>, _requested_by: &str) -> bool {
+        true
+    }
+}
+
+/// Information about the host on which an authorization decision is being made, allowing
+/// authorizers to implement policies like "actors from issuer X may only run on hosts labeled env=prod".
+/// The default authorizer ignores this context.
+#[derive(Debug, Clone, Default)]
+pub struct AuthorizationContext {
+    /// The public key of the host
+    pub host_id: String,
+    /// The host's labels, including the intrinsic `hostcore.*` labels
+    pub labels: HashMap<String, String>,
+    /// The lattice namespace of the host, if any
+    pub namespace: Option<String>,
+}
+
+pub(crate) fn authorization_context(
+    host_id: &str,
+    labels: &Arc<RwLock<HashMap<String, String>>>,
+    ns: Option<&str>,
+) -> AuthorizationContext {
+    AuthorizationContext {
+        host_id: host_id.to_string(),
+        labels: labels.read_or_recover().clone(),
+        namespace: ns.map(|s| s.to_string()),
+    }
+}
+
+/// An authorizer composed of other authorizers. An action is allowed only if _every_
+/// authorizer in the chain allows it, and evaluation stops at the first denial.
+#[derive(Default)]
+pub struct AuthorizerChain {
+    authorizers: Vec<(String, Box<dyn Authorizer>)>,
+}
+
+impl AuthorizerChain {
+    /// Creates an empty chain. An empty chain allows everything (subject to the host's
+    /// own capability attestation checks)
+    pub fn new() -> AuthorizerChain {
+        AuthorizerChain {
+            authorizers: Vec::new(),
+        }
+    }
+
+    /// Appends an authorizer to the chain. The name is used to identify the authorizer
+    /// in log messages when it denies an action
+    pub fn add(mut self, name: &str, authorizer: impl Authorizer + 'static) -> AuthorizerChain {
+        self.authorizers
+            .push((name.to_string(), Box::new(authorizer)));
+        self
+    }
+
+    fn allows(
+        &self,
+        check: impl Fn(&dyn Authorizer) -> bool,
+        describe: impl Fn() -> String,
+    ) -> bool {
+        match self.authorizers.iter().find(|(_, a)| !check(a.as_ref())) {
+            Some((name, _)) => {
+                info!("Authorizer '{}' denied {}", name, describe());
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+impl From<Vec<Box<dyn Authorizer>>> for AuthorizerChain {
+    /// Creates a chain from a list of authorizers, naming each one by its position in the list
+    fn from(authorizers: Vec<Box<dyn Authorizer>>) -> AuthorizerChain {
+        AuthorizerChain {
+            authorizers: authorizers
+                .into_iter()
+                .enumerate()
+                .map(|(i, a)| (format!("authorizer[{}]", i), a))
+                .collect(),
+        }
+    }
+}
+
+impl Authorizer for AuthorizerChain {
+    fn can_load(&self, claims: &Claims<Actor>) -> bool {
+        self.allows(
+            |a| a.can_load(claims),
+            || format!("load of {}", claims.subject),
+        )
+    }
+
+    fn can_invoke(&self, claims: &Claims<Actor>, target: &WasccEntity, operation: &str) -> bool {
+        self.allows(
+            |a| a.can_invoke(claims, target, operation),
+            || {
+                format!(
+                    "{} calling {} on {}",
+                    claims.subject,
+                    operation,
+                    target.url()
+                )
+            },
+        )
+    }
+
+    fn can_load_ctx(&self, claims: &Claims<Actor>, ctx: &AuthorizationContext) -> bool {
+        self.allows(
+            |a| a.can_load_ctx(claims, ctx),
+            || format!("load of {}", claims.subject),
+        )
+    }
+
+    fn can_invoke_ctx(
+        &self,
+        claims: &Claims<Actor>,
+        target: &WasccEntity,
+        operation: &str,
+        ctx: &AuthorizationContext,
+    ) -> bool {
+        self.allows(
+            |a| a.can_invoke_ctx(claims, target, operation, ctx),
+            || {
+                format!(
+                    "{} calling {} on {}",
+                    claims.subject,
+                    operation,
+                    target.url()
+                )
+            },
+        )
+    }
+
+    fn can_replace(&self, old_claims: &Claims<Actor>, new_claims: &Claims<Actor>) -> bool {
+        self.allows(
+            |a| a.can_replace(old_claims, new_claims),
+            || format!("replacement of {}", old_claims.subject),
+        )
+    }
+
+    fn can_terminate(&self, claims: &Claims<Actor>, requested_by: &str) -> bool {
+        self.allows(
+            |a| a.can_terminate(claims, requested_by),
+            || {
+                format!(
+                    "termination of {} requested by {}",
+                    claims.subject, requested_by
+                )
+            },
+        )
+    }
 }
 
 pub(crate) struct DefaultAuthorizer {}
@@ -48,8 +235,7 @@ impl Authorizer for DefaultAuthorizer {
 }
 
 pub(crate) fn get_all_claims(map: ClaimsMap) -> Vec<(String, Claims<wascap::jwt::Actor>)> {
-    map.read()
-        .unwrap()
+    map.read_or_recover()
         .iter()
         .map(|(pk, claims)| (pk.clone(), claims.clone()))
         .collect()
@@ -67,13 +253,50 @@ pub(crate) fn can_invoke(
     if claims.subject == capability_id {
         return true;
     }
+    let capability_id = normalize_capid(capability_id);
     claims
         .metadata
         .as_ref()
         .unwrap()
         .caps
         .as_ref()
-        .map_or(false, |caps| caps.contains(&capability_id.to_string()))
+        .map_or(false, |caps| {
+            caps.iter().any(|c| normalize_capid(c) == capability_id)
+        })
+}
+
+// Hand-edited claims occasionally differ in case or stray whitespace from the
+// capability IDs used by providers, e.g. "wascc:Keyvalue" vs. "wascc:keyvalue"
+fn normalize_capid(capid: &str) -> String {
+    capid.trim().to_lowercase()
+}
+
+// Produces the explanation for a failed capability attestation check. The list of capabilities
+// for which the actor _is_ attested is only emitted to the debug log so it does not end up
+// in errors returned to (potentially untrusted) callers
+pub(crate) fn attestation_denial(
+    claims: &Claims<wascap::jwt::Actor>,
+    capability_id: &str,
+    binding: &str,
+    operation: &str,
+) -> String {
+    debug!(
+        "Actor {} denied {} on {},{} - attested capabilities: [{}]",
+        claims.subject,
+        operation,
+        capability_id,
+        binding,
+        claims
+            .metadata
+            .as_ref()
+            .and_then(|md| md.caps.clone())
+            .unwrap_or_default()
+            .join(",")
+    );
+    format!(
+        "actor {} is not attested for capability {} (operation {} on binding {})",
+        claims.subject, capability_id, operation, binding
+    )
 }
 
 // Extract claims from the JWT embedded in the wasm module's custom section
@@ -96,19 +319,139 @@ pub(crate) fn extract_claims(buf: &[u8]) -> Result<wascap::jwt::Token<wascap::jw
     }
 }
 
-pub(crate) fn enforce_validation(jwt: &str) -> Result<()> {
-    let v = validate_token::<wascap::jwt::Actor>(jwt)?;
-    if v.expired {
-        Err(errors::new(errors::ErrorKind::Authorization(
-            "Expired token".to_string(),
-        )))
-    } else if v.cannot_use_yet {
-        Err(errors::new(errors::ErrorKind::Authorization(format!(
-            "Module cannot be used before {}",
-            v.not_before_human
-        ))))
+/// An actor whose claims failed validation when it was added to a host with quarantine
+/// enabled. Times are in seconds since the Unix epoch, so that the claims' validity period can
+/// be compared with the clock of the host that rejected them
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuarantinedActor {
+    pub public_key: String,
+    pub reason: String,
+    pub not_before: Option<u64>,
+    pub expires: Option<u64>,
+    pub quarantined_at: u64,
+}
+
+fn since_the_epoch() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// Claims registered ahead of their actor with `Host::register_actor_claims`, which the host
+// forgets once `lapses` has passed
+pub(crate) struct Registration {
+    claims: Claims<Actor>,
+    lapses: Instant,
+}
+
+// Checks the validity period of the claims against the given time, allowing the clocks of the
+// issuer and the host to differ by up to `skew`. Returns the reason the claims can't be used
+fn validity_error(
+    claims: &Claims<wascap::jwt::Actor>,
+    now: u64,
+    skew: Duration,
+    not_before_human: &str,
+) -> Option<String> {
+    let skew = skew.as_secs();
+    if claims
+        .expires
+        .map_or(false, |exp| exp.saturating_add(skew) < now)
+    {
+        Some("Expired token".to_string())
+    } else if claims
+        .not_before
+        .map_or(false, |nbf| nbf > now.saturating_add(skew))
+    {
+        Some(format!("Module cannot be used before {}", not_before_human))
     } else {
-        Ok(())
+        None
+    }
+}
+
+pub(crate) fn enforce_validation(token: &Token<wascap::jwt::Actor>, skew: Duration) -> Result<()> {
+    let v = validate_token::<wascap::jwt::Actor>(&token.jwt)?;
+    match validity_error(&token.claims, since_the_epoch(), skew, &v.not_before_human) {
+        Some(reason) => Err(errors::new(errors::ErrorKind::Authorization(reason))),
+        None => Ok(()),
+    }
+}
+
+/// The capability IDs of the providers a host may load, set with
+/// `HostBuilder::with_capability_allowlist`. Without an allowlist any provider may be loaded,
+/// while an actor-only host (see `HostBuilder::actor_only`) may load none at all
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CapabilityAllowlist {
+    ids: Option<Vec<String>>,
+    actor_only: bool,
+}
+
+/// The reason given whenever an actor-only host refuses a capability provider or a call to one
+pub(crate) const CAPABILITIES_DISABLED: &str = "capabilities disabled on this host";
+
+impl CapabilityAllowlist {
+    /// Creates an allowlist of the given capability IDs, which also permits the extras
+    /// provider if `allow_extras` is set
+    pub(crate) fn new(ids: Option<Vec<String>>, allow_extras: bool) -> CapabilityAllowlist {
+        CapabilityAllowlist {
+            ids: ids.map(|ids| {
+                let mut ids: Vec<String> = ids.iter().map(|id| normalize_capid(id)).collect();
+                if allow_extras {
+                    ids.push(crate::extras::CAPABILITY_ID.to_string());
+                }
+                ids.sort();
+                ids.dedup();
+                ids
+            }),
+            actor_only: false,
+        }
+    }
+
+    /// Creates the allowlist of an actor-only host, which permits no providers
+    pub(crate) fn actor_only() -> CapabilityAllowlist {
+        CapabilityAllowlist {
+            ids: Some(Vec::new()),
+            actor_only: true,
+        }
+    }
+
+    pub(crate) fn is_actor_only(&self) -> bool {
+        self.actor_only
+    }
+
+    /// Fails if the host is actor-only, whichever provider is being loaded
+    pub(crate) fn check_enabled(&self, provider: &str) -> Result<()> {
+        if self.actor_only {
+            Err(errors::new(errors::ErrorKind::Authorization(format!(
+                "Cannot load capability provider {}: {} (actor-only mode)",
+                provider, CAPABILITIES_DISABLED
+            ))))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn permits(&self, capid: &str) -> bool {
+        self.ids
+            .as_ref()
+            .map_or(true, |ids| ids.contains(&normalize_capid(capid)))
+    }
+
+    pub(crate) fn check(&self, capid: &str) -> Result<()> {
+        self.check_enabled(capid)?;
+        if self.permits(capid) {
+            Ok(())
+        } else {
+            Err(errors::new(errors::ErrorKind::Authorization(format!(
+                "Capability provider {} is not on this host's capability allowlist",
+                capid
+            ))))
+        }
+    }
+
+    /// The normalized capability IDs in the allowlist, if there is one
+    pub(crate) fn ids(&self) -> Option<Vec<String>> {
+        self.ids.clone()
     }
 }
 
@@ -118,20 +461,309 @@ pub(crate) fn register_claims(
     claims: Claims<wascap::jwt::Actor>,
 ) {
     claims_map
-        .write()
-        .unwrap()
+        .write_or_recover()
         .insert(subject.to_string(), claims);
 }
 
 pub(crate) fn unregister_claims(claims_map: ClaimsMap, subject: &str) {
     {
-        let mut lock = claims_map.write().unwrap();
+        let mut lock = claims_map.write_or_recover();
         let _ = lock.remove(subject);
     }
 }
 
 impl Host {
     pub(crate) fn check_auth(&self, token: &Token<wascap::jwt::Actor>) -> bool {
-        self.authorizer.read().unwrap().can_load(&token.claims)
+        let ctx = self.authorization_context();
+        self.authorizer
+            .read_or_recover()
+            .can_load_ctx(&token.claims, &ctx)
+    }
+
+    // Validates the claims of an actor being added to this host. When quarantine is enabled, an
+    // actor that fails is recorded, replacing any earlier record, and one that passes is cleared
+    pub(crate) fn validate_actor_claims(&self, token: &Token<wascap::jwt::Actor>) -> Result<()> {
+        let res = enforce_validation(token, self.claims_skew);
+        if let Some(ref quarantine) = self.quarantine {
+            let mut lock = quarantine.write_or_recover();
+            lock.retain(|q| q.public_key != token.claims.subject);
+            if let Err(ref e) = res {
+                lock.push(QuarantinedActor {
+                    public_key: token.claims.subject.to_string(),
+                    reason: e.to_string(),
+                    not_before: token.claims.not_before,
+                    expires: token.claims.expires,
+                    quarantined_at: since_the_epoch(),
+                });
+            }
+        }
+        res
+    }
+
+    // Validates claims registered ahead of their actor, keeping only what the host needs of
+    // them until the actor is added: its subject, issuer, capabilities, and validity period.
+    // Without a token there's no signature to verify, so only the claims' validity period and
+    // the authorizer are checked
+    pub(crate) fn registration(&self, claims: Claims<Actor>) -> Result<Registration> {
+        if !claims.subject.starts_with('M') || claims.metadata.is_none() {
+            return Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                "{} doesn't identify an actor",
+                claims.subject
+            ))));
+        }
+        let not_before = claims.not_before.map(|t| t.to_string()).unwrap_or_default();
+        if let Some(reason) =
+            validity_error(&claims, since_the_epoch(), self.claims_skew, &not_before)
+        {
+            return Err(errors::new(errors::ErrorKind::Authorization(reason)));
+        }
+        let ctx = self.authorization_context();
+        if !self
+            .authorizer
+            .read_or_recover()
+            .can_load_ctx(&claims, &ctx)
+        {
+            return Err(errors::new(errors::ErrorKind::Authorization(
+                "Authorization hook denied access to module".into(),
+            )));
+        }
+        let caps = claims.metadata.and_then(|m| m.caps);
+        Ok(Registration {
+            claims: Claims {
+                expires: claims.expires,
+                id: claims.id,
+                issued_at: claims.issued_at,
+                issuer: claims.issuer,
+                subject: claims.subject,
+                not_before: claims.not_before,
+                metadata: Some(Actor {
+                    caps,
+                    ..Default::default()
+                }),
+            },
+            lapses: Instant::now() + self.claims_registration_ttl,
+        })
+    }
+
+    // The claims registered for the actor, unless the registration has lapsed or the claims
+    // have expired, in which case they're forgotten
+    pub(crate) fn registered_claims(&self, actor: &str) -> Option<Claims<Actor>> {
+        let mut registered = self.registered_claims.write_or_recover();
+        let live = registered.get(actor).map(|r| {
+            Instant::now() < r.lapses
+                && validity_error(&r.claims, since_the_epoch(), self.claims_skew, "").is_none()
+        })?;
+        if live {
+            registered.get(actor).map(|r| r.claims.clone())
+        } else {
+            registered.remove(actor);
+            None
+        }
+    }
+
+    // Checks the claims of an actor discovered in the lattice each time they're used, since
+    // they may have been cached long enough to expire, or for the authorizer to have revoked
+    // the actor since. Claims that fail are dropped from the cache. The claims of actors in this
+    // host were checked as they were added
+    #[cfg(feature = "lattice")]
+    pub(crate) fn check_discovered_claims(&self, claims: &Claims<Actor>) -> Result<()> {
+        if self.claims.read_or_recover().contains_key(&claims.subject) {
+            return Ok(());
+        }
+        let not_before = claims.not_before.map(|t| t.to_string()).unwrap_or_default();
+        let reason = validity_error(claims, since_the_epoch(), self.claims_skew, &not_before)
+            .or_else(|| {
+                let ctx = self.authorization_context();
+                if self.authorizer.read_or_recover().can_load_ctx(claims, &ctx) {
+                    None
+                } else {
+                    Some("Authorization hook denied access to module".to_string())
+                }
+            });
+        match reason {
+            Some(reason) => {
+                self.bus.forget_remote_claims(&claims.subject);
+                Err(errors::new(errors::ErrorKind::Authorization(reason)))
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Checks that claims registered ahead of the actor being added, if any, agree with the
+    // signed claims in its module on the issuer and capabilities. The signed claims are the ones
+    // the host goes by once the actor is added
+    pub(crate) fn check_registered_claims(&self, token: &Token<Actor>) -> Result<()> {
+        let registered = match self.registered_claims(&token.claims.subject) {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        let caps = |c: &Claims<Actor>| {
+            let mut caps = c
+                .metadata
+                .as_ref()
+                .and_then(|m| m.caps.clone())
+                .unwrap_or_default();
+            caps.sort();
+            caps
+        };
+        if registered.issuer != token.claims.issuer || caps(&registered) != caps(&token.claims) {
+            Err(errors::new(errors::ErrorKind::Authorization(format!(
+                "The claims registered for actor {} don't match the claims in its module",
+                token.claims.subject
+            ))))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn authorization_context(&self) -> AuthorizationContext {
+        authorization_context(&self.pk, &self.labels, self.ns.as_ref().map(String::as_str))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        attestation_denial, can_invoke, validity_error, AuthorizationContext, Authorizer,
+        AuthorizerChain, DefaultAuthorizer,
+    };
+    use crate::WasccEntity;
+    use std::time::Duration;
+    use wascap::prelude::{Actor, ClaimsBuilder, KeyPair};
+
+    fn claims_with_caps(caps: Vec<&str>) -> wascap::jwt::Claims<Actor> {
+        ClaimsBuilder::<Actor>::new()
+            .issuer(&KeyPair::new_account().public_key())
+            .subject(&KeyPair::new_module().public_key())
+            .with_metadata(Actor {
+                name: Some("test".to_string()),
+                caps: Some(caps.into_iter().map(|s| s.to_string()).collect()),
+                ..Default::default()
+            })
+            .build()
+    }
+
+    #[test]
+    fn validity_period_allows_skew() {
+        let mut claims = claims_with_caps(vec![]);
+        let now = 1_000_000;
+        let skew = Duration::from_secs(60);
+        assert!(validity_error(&claims, now, Duration::from_secs(0), "").is_none());
+
+        claims.expires = Some(now - 60);
+        assert!(validity_error(&claims, now, skew, "").is_none());
+        assert!(validity_error(&claims, now, Duration::from_secs(59), "").is_some());
+        claims.expires = Some(now - 61);
+        assert_eq!(
+            Some("Expired token".to_string()),
+            validity_error(&claims, now, skew, "")
+        );
+
+        claims.expires = None;
+        claims.not_before = Some(now + 60);
+        assert!(validity_error(&claims, now, skew, "").is_none());
+        claims.not_before = Some(now + 61);
+        assert_eq!(
+            Some("Module cannot be used before soon".to_string()),
+            validity_error(&claims, now, skew, "soon")
+        );
+    }
+
+    #[test]
+    fn attestation_check_ignores_case_and_whitespace() {
+        let claims = claims_with_caps(vec!["wascc:Keyvalue", " wascc:http_server "]);
+        assert!(can_invoke(&claims, "wascc:keyvalue", "AtomicAdd"));
+        assert!(can_invoke(&claims, "WASCC:KEYVALUE", "AtomicAdd"));
+        assert!(can_invoke(&claims, "wascc:http_server", "HandleRequest"));
+        assert!(!can_invoke(&claims, "wascc:messaging", "Publish"));
+    }
+
+    struct Denier {}
+
+    impl Authorizer for Denier {
+        fn can_load(&self, _claims: &wascap::jwt::Claims<Actor>) -> bool {
+            false
+        }
+        fn can_invoke(
+            &self,
+            _claims: &wascap::jwt::Claims<Actor>,
+            _target: &WasccEntity,
+            _operation: &str,
+        ) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn chain_denies_if_any_denies() {
+        let claims = claims_with_caps(vec!["wascc:keyvalue"]);
+        let target = WasccEntity::Capability {
+            capid: "wascc:keyvalue".to_string(),
+            binding: "default".to_string(),
+        };
+        let ctx = AuthorizationContext::default();
+
+        let allow = AuthorizerChain::new().add("default", DefaultAuthorizer::new());
+        assert!(allow.can_load_ctx(&claims, &ctx));
+        assert!(allow.can_invoke_ctx(&claims, &target, "AtomicAdd", &ctx));
+
+        let deny_last = AuthorizerChain::new()
+            .add("default", DefaultAuthorizer::new())
+            .add("denier", Denier {});
+        let deny_first = AuthorizerChain::from(vec![
+            Box::new(Denier {}) as Box<dyn Authorizer>,
+            Box::new(DefaultAuthorizer::new()),
+        ]);
+        for chain in &[deny_last, deny_first] {
+            assert!(!chain.can_load_ctx(&claims, &ctx));
+            assert!(!chain.can_invoke_ctx(&claims, &target, "AtomicAdd", &ctx));
+        }
+    }
+
+    #[test]
+    fn denial_explains_target() {
+        let claims = claims_with_caps(vec!["wascc:keyvalue"]);
+        let reason = attestation_denial(&claims, "wascc:messaging", "default", "Publish");
+        assert!(reason.contains("wascc:messaging"));
+        assert!(reason.contains("Publish"));
+        assert!(reason.contains("default"));
+        // Attested capabilities are only logged, never returned
+        assert!(!reason.contains("wascc:keyvalue"));
+    }
+
+    #[test]
+    fn capability_allowlist_normalizes_ids() {
+        use super::CapabilityAllowlist;
+
+        let open = CapabilityAllowlist::default();
+        assert!(open.permits("wascc:blobstore"));
+        assert!(open.ids().is_none());
+
+        let list = CapabilityAllowlist::new(Some(vec![" wascc:KeyValue".to_string()]), true);
+        assert!(list.permits("wascc:keyvalue"));
+        assert!(list.permits(crate::extras::CAPABILITY_ID));
+        assert!(!list.permits("wascc:blobstore"));
+        let err = list.check("wascc:blobstore").unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            crate::errors::ErrorKind::Authorization(_)
+        ));
+        assert_eq!(
+            Some(vec![
+                crate::extras::CAPABILITY_ID.to_string(),
+                "wascc:keyvalue".to_string()
+            ]),
+            list.ids()
+        );
+
+        let strict = CapabilityAllowlist::new(Some(vec!["wascc:keyvalue".to_string()]), false);
+        assert!(!strict.permits(crate::extras::CAPABILITY_ID));
+
+        let none = CapabilityAllowlist::actor_only();
+        assert!(none.is_actor_only());
+        assert!(!none.permits(crate::extras::CAPABILITY_ID));
+        let err = none.check("wascc:keyvalue").unwrap_err();
+        assert!(err.to_string().contains(super::CAPABILITIES_DISABLED));
+        assert_eq!(Some(vec![]), none.ids());
     }
 }
@@ -25,6 +25,9 @@ struct CliCommand {
     /// Whether to expand environment variables in the host manifest
     #[structopt(short = "e", long = "expand-env")]
     expand_env: bool,
+    /// Whether to reject a host manifest containing unrecognized fields
+    #[structopt(long = "strict")]
+    strict: bool,
 }
 
 #[cfg(feature = "manifest")]
@@ -40,7 +43,7 @@ fn main() -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let host = HostBuilder::new().build();
 
     if let Some(ref mp) = cmd.manifest_path {
-        let manifest = HostManifest::from_path(mp, cmd.expand_env)?;
+        let manifest = HostManifest::from_path(mp, cmd.expand_env, cmd.strict)?;
         host.apply_manifest(manifest)?;
         info!("Processed and applied host manifest");
     } else {
@@ -52,17 +55,7 @@ fn main() -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
         }
     }
 
-    let (term_s, term_r) = std::sync::mpsc::channel();
-
-    ctrlc::set_handler(move || {
-        term_s.send(()).unwrap();
-    })
-    .expect("Error setting Ctrl-C handler");
-
-    term_r.recv().expect("Failed awaiting termination signal");
-
-    info!("Shutting down host");
-    host.shutdown()?;
+    host.run_until_signal()?;
 
     Ok(())
 }
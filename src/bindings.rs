@@ -0,0 +1,398 @@
+// The set of bindings between actors and capability providers known to a host, along
+// with the configuration supplied for each binding
+
+use std::collections::HashMap;
+use wascc_codec::core::CapabilityConfiguration;
+
+/// Uniquely identifies the binding between an actor and a named instance of a
+/// capability provider. An actor may hold several bindings to the same provider instance,
+/// told apart by their configuration names (see `Host::set_binding_named`)
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub(crate) struct BindingKey {
+    pub actor: String,
+    pub capid: String,
+    pub binding: String,
+    // None for the binding set by `Host::set_binding`
+    pub config_name: Option<String>,
+}
+
+impl BindingKey {
+    pub fn new(actor: &str, capid: &str, binding: &str) -> BindingKey {
+        BindingKey::named(actor, capid, binding, None)
+    }
+
+    pub fn named(actor: &str, capid: &str, binding: &str, config_name: Option<&str>) -> BindingKey {
+        BindingKey {
+            actor: actor.to_string(),
+            capid: capid.to_string(),
+            binding: binding.to_string(),
+            config_name: config_name.map(str::to_string),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub(crate) struct Bindings {
+    map: HashMap<BindingKey, CapabilityConfiguration>,
+    // The hash of the configuration each provider instance last accepted for a binding, so that
+    // re-establishing bindings can skip those an instance already holds
+    acks: HashMap<(BindingKey, String), u64>,
+}
+
+impl Bindings {
+    /// Records a binding, returning the previous configuration if the binding already existed
+    pub fn insert(
+        &mut self,
+        actor: &str,
+        capid: &str,
+        binding: &str,
+        config: CapabilityConfiguration,
+    ) -> Option<CapabilityConfiguration> {
+        self.insert_named(actor, capid, binding, None, config)
+    }
+
+    /// Records a binding with the given configuration name, returning the previous
+    /// configuration if the binding already existed
+    pub fn insert_named(
+        &mut self,
+        actor: &str,
+        capid: &str,
+        binding: &str,
+        config_name: Option<&str>,
+        config: CapabilityConfiguration,
+    ) -> Option<CapabilityConfiguration> {
+        self.map.insert(
+            BindingKey::named(actor, capid, binding, config_name),
+            config,
+        )
+    }
+
+    pub fn remove(
+        &mut self,
+        actor: &str,
+        capid: &str,
+        binding: &str,
+    ) -> Option<CapabilityConfiguration> {
+        self.remove_named(actor, capid, binding, None)
+    }
+
+    pub fn remove_named(
+        &mut self,
+        actor: &str,
+        capid: &str,
+        binding: &str,
+        config_name: Option<&str>,
+    ) -> Option<CapabilityConfiguration> {
+        let key = BindingKey::named(actor, capid, binding, config_name);
+        self.acks.retain(|(k, _), _| *k != key);
+        self.map.remove(&key)
+    }
+
+    /// Removes the actor's bindings to the given provider instance, whatever their
+    /// configuration names
+    pub fn remove_all_named(&mut self, actor: &str, capid: &str, binding: &str) {
+        self.map
+            .retain(|k, _| !(k.actor == actor && k.capid == capid && k.binding == binding));
+        self.forget_acks(actor, capid, binding);
+    }
+
+    /// Removes every binding to the given provider instance
+    pub fn remove_provider(&mut self, capid: &str, binding: &str) {
+        self.map
+            .retain(|k, _| !(k.capid == capid && k.binding == binding));
+        self.acks
+            .retain(|(k, _), _| !(k.capid == capid && k.binding == binding));
+    }
+
+    /// Records that a provider instance accepted the configuration with the given hash (see
+    /// `config_hash`) for a binding
+    #[cfg(feature = "lattice")]
+    pub fn ack(&mut self, key: BindingKey, instance: &str, hash: u64) {
+        self.acks.insert((key, instance.to_string()), hash);
+    }
+
+    /// The hash of the configuration the provider instance last accepted for the binding
+    #[cfg(feature = "lattice")]
+    pub fn acked(&self, key: &BindingKey, instance: &str) -> Option<u64> {
+        self.acks.get(&(key.clone(), instance.to_string())).copied()
+    }
+
+    /// Forgets which configurations provider instances accepted for the actor's bindings to the
+    /// given provider, e.g. once the actor has been unbound from it
+    pub fn forget_acks(&mut self, actor: &str, capid: &str, binding: &str) {
+        self.acks
+            .retain(|(k, _), _| !(k.actor == actor && k.capid == capid && k.binding == binding));
+    }
+
+    pub fn get(&self, actor: &str, capid: &str, binding: &str) -> Option<&CapabilityConfiguration> {
+        self.get_named(actor, capid, binding, None)
+    }
+
+    pub fn get_named(
+        &self,
+        actor: &str,
+        capid: &str,
+        binding: &str,
+        config_name: Option<&str>,
+    ) -> Option<&CapabilityConfiguration> {
+        self.map
+            .get(&BindingKey::named(actor, capid, binding, config_name))
+    }
+
+    /// The configuration names of the actor's bindings to the given provider instance, with
+    /// `None` standing for the binding without a name
+    pub fn config_names(&self, actor: &str, capid: &str, binding: &str) -> Vec<Option<String>> {
+        self.map
+            .keys()
+            .filter(|k| k.actor == actor && k.capid == capid && k.binding == binding)
+            .map(|k| k.config_name.clone())
+            .collect()
+    }
+
+    pub fn contains(&self, actor: &str, capid: &str, binding: &str) -> bool {
+        self.map
+            .contains_key(&BindingKey::new(actor, capid, binding))
+    }
+
+    /// All of the bindings for the given actor
+    pub fn for_actor<'a>(
+        &'a self,
+        actor: &'a str,
+    ) -> impl Iterator<Item = (&'a BindingKey, &'a CapabilityConfiguration)> + 'a {
+        self.map.iter().filter(move |(k, _)| k.actor == actor)
+    }
+
+    /// All of the bindings to the given provider instance
+    pub fn for_provider<'a>(
+        &'a self,
+        capid: &'a str,
+        binding: &'a str,
+    ) -> impl Iterator<Item = (&'a BindingKey, &'a CapabilityConfiguration)> + 'a {
+        self.map
+            .iter()
+            .filter(move |(k, _)| k.capid == capid && k.binding == binding)
+    }
+
+    /// All of the bindings to any instance of the given capability
+    pub fn for_capability<'a>(
+        &'a self,
+        capid: &'a str,
+    ) -> impl Iterator<Item = (&'a BindingKey, &'a CapabilityConfiguration)> + 'a {
+        self.map.iter().filter(move |(k, _)| k.capid == capid)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&BindingKey, &CapabilityConfiguration)> {
+        self.map.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+/// The hash (see `config_hash`) of a configuration as delivered to a provider, leaving out the
+/// reserved values the host adds to every delivery (the binding's configuration name aside), so
+/// that it matches the hash of the configuration the binding was recorded with
+#[cfg(feature = "lattice")]
+pub(crate) fn delivered_config_hash(delivered: &CapabilityConfiguration) -> u64 {
+    let recorded = CapabilityConfiguration {
+        module: delivered.module.to_string(),
+        values: delivered
+            .values
+            .iter()
+            .filter(|(k, _)| {
+                !k.starts_with(crate::CONFIG_WASCC_RESERVED_PREFIX)
+                    || *k == crate::CONFIG_WASCC_CONFIG_NAME
+            })
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+    };
+    config_hash(&recorded)
+}
+
+/// A hash of the actor and values of a binding configuration, independent of the order of the
+/// values
+#[cfg(feature = "lattice")]
+pub(crate) fn config_hash(config: &CapabilityConfiguration) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut values: Vec<_> = config.values.iter().collect();
+    values.sort();
+    let mut hasher = DefaultHasher::new();
+    config.module.hash(&mut hasher);
+    values.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BindingKey, Bindings};
+    use std::collections::HashMap;
+    use wascc_codec::core::CapabilityConfiguration;
+
+    fn config(actor: &str) -> CapabilityConfiguration {
+        CapabilityConfiguration {
+            module: actor.to_string(),
+            values: HashMap::new(),
+        }
+    }
+
+    fn sample() -> Bindings {
+        let mut b = Bindings::default();
+        b.insert("Ma", "wascc:keyvalue", "default", config("Ma"));
+        b.insert("Ma", "wascc:http_server", "default", config("Ma"));
+        b.insert("Mb", "wascc:keyvalue", "default", config("Mb"));
+        b.insert("Mb", "wascc:keyvalue", "cache", config("Mb"));
+        b
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let mut b = sample();
+        assert_eq!(4, b.len());
+        assert!(b.contains("Ma", "wascc:keyvalue", "default"));
+        assert!(!b.contains("Ma", "wascc:keyvalue", "cache"));
+        assert_eq!("Mb", b.get("Mb", "wascc:keyvalue", "cache").unwrap().module);
+
+        // Re-inserting replaces rather than duplicates
+        assert!(b
+            .insert("Ma", "wascc:keyvalue", "default", config("Ma"))
+            .is_some());
+        assert_eq!(4, b.len());
+
+        assert!(b.remove("Ma", "wascc:keyvalue", "default").is_some());
+        assert!(b.remove("Ma", "wascc:keyvalue", "default").is_none());
+        assert_eq!(3, b.len());
+    }
+
+    #[test]
+    fn queries() {
+        let b = sample();
+        let mut keys: Vec<_> = b.for_actor("Mb").map(|(k, _)| k.clone()).collect();
+        keys.sort_by(|a, b| a.binding.cmp(&b.binding));
+        assert_eq!(
+            keys,
+            vec![
+                BindingKey::new("Mb", "wascc:keyvalue", "cache"),
+                BindingKey::new("Mb", "wascc:keyvalue", "default")
+            ]
+        );
+
+        let mut actors: Vec<_> = b
+            .for_provider("wascc:keyvalue", "default")
+            .map(|(k, _)| k.actor.to_string())
+            .collect();
+        actors.sort();
+        assert_eq!(actors, vec!["Ma", "Mb"]);
+        assert_eq!(3, b.for_capability("wascc:keyvalue").count());
+        assert_eq!(0, b.for_actor("Mc").count());
+    }
+
+    #[test]
+    fn named_bindings_kept_apart() {
+        let mut b = sample();
+        b.insert_named(
+            "Ma",
+            "wascc:messaging",
+            "default",
+            Some("orders"),
+            config("Ma"),
+        );
+        b.insert_named(
+            "Ma",
+            "wascc:messaging",
+            "default",
+            Some("audit"),
+            config("Ma"),
+        );
+        assert_eq!(6, b.len());
+        assert!(!b.contains("Ma", "wascc:messaging", "default"));
+        assert!(b
+            .get_named("Ma", "wascc:messaging", "default", Some("audit"))
+            .is_some());
+        let mut names = b.config_names("Ma", "wascc:messaging", "default");
+        names.sort();
+        assert_eq!(
+            names,
+            vec![Some("audit".to_string()), Some("orders".to_string())]
+        );
+
+        assert!(b
+            .remove_named("Ma", "wascc:messaging", "default", Some("orders"))
+            .is_some());
+        assert_eq!(1, b.config_names("Ma", "wascc:messaging", "default").len());
+
+        b.insert("Ma", "wascc:messaging", "default", config("Ma"));
+        b.remove_all_named("Ma", "wascc:messaging", "default");
+        assert_eq!(4, b.len());
+    }
+
+    #[test]
+    #[cfg(feature = "lattice")]
+    fn acks_follow_bindings() {
+        use super::config_hash;
+
+        let mut b = sample();
+        let key = BindingKey::new("Ma", "wascc:keyvalue", "default");
+        let hash = config_hash(b.get("Ma", "wascc:keyvalue", "default").unwrap());
+        assert!(b.acked(&key, "Nhost").is_none());
+        b.ack(key.clone(), "Nhost", hash);
+        assert_eq!(Some(hash), b.acked(&key, "Nhost"));
+        assert!(b.acked(&key, "Nother").is_none());
+
+        let mut values = HashMap::new();
+        values.insert("URL".to_string(), "redis://127.0.0.1".to_string());
+        values.insert("DB".to_string(), "0".to_string());
+        let changed = CapabilityConfiguration {
+            module: "Ma".to_string(),
+            values,
+        };
+        assert_ne!(hash, config_hash(&changed));
+
+        // Removing the binding, or the provider, forgets what was accepted
+        b.remove("Ma", "wascc:keyvalue", "default");
+        assert!(b.acked(&key, "Nhost").is_none());
+        b.ack(key.clone(), "Nhost", hash);
+        b.remove_provider("wascc:keyvalue", "default");
+        assert!(b.acked(&key, "Nhost").is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "lattice")]
+    fn delivered_hash_ignores_host_values() {
+        use super::{config_hash, delivered_config_hash};
+
+        let mut recorded = config("Ma");
+        recorded.values.insert(
+            crate::CONFIG_WASCC_CONFIG_NAME.to_string(),
+            "orders".to_string(),
+        );
+        let mut delivered = recorded.clone();
+        delivered
+            .values
+            .insert(crate::CONFIG_WASCC_HOST_ID.to_string(), "Nhost".to_string());
+        delivered
+            .values
+            .insert(crate::CONFIG_WASCC_UPDATE.to_string(), "true".to_string());
+        assert_eq!(config_hash(&recorded), delivered_config_hash(&delivered));
+
+        delivered
+            .values
+            .insert("URL".to_string(), "redis://10.0.0.1".to_string());
+        assert_ne!(config_hash(&recorded), delivered_config_hash(&delivered));
+    }
+
+    #[test]
+    fn remove_provider_only_removes_that_provider() {
+        let mut b = sample();
+        b.remove_provider("wascc:keyvalue", "default");
+        assert_eq!(2, b.len());
+        assert!(b.contains("Ma", "wascc:http_server", "default"));
+        assert!(b.contains("Mb", "wascc:keyvalue", "cache"));
+    }
+}
@@ -0,0 +1,211 @@
+// Signed control plane commands. When a host is configured with trusted issuers, the payload of
+// each command it receives must be a JWT, signed by one of those issuers, whose claims carry the
+// original command along with the subject it was sent to (so a signed command can't be replayed
+// to another host or as another kind of command), a unique ID, and an expiry time. A host
+// remembers the IDs of the commands it has accepted until they expire, so a command can't be
+// replayed to the same host either.
+
+use crate::locks::MutexExt;
+use crate::{errors, Result};
+use data_encoding::BASE64URL_NOPAD;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use wascap::prelude::KeyPair;
+
+const HEADER: &str = r#"{"typ":"jwt","alg":"Ed25519"}"#;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CommandClaims {
+    iss: String,
+    sub: String,
+    iat: u64,
+    exp: u64,
+    jti: String,
+    // The base64url-encoded command
+    cmd: String,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Signs a lattice control plane command, such as a JSON-encoded `LaunchCommand`, for delivery
+/// to hosts that only accept commands from trusted issuers (see
+/// `HostBuilder::with_control_issuers`). The returned bytes are published on the command's
+/// subject in place of the command itself. The signed command is only valid for that subject
+/// and until `valid_for` has elapsed, and a host accepts it only once
+pub fn sign_control_command(
+    issuer: &KeyPair,
+    subject: &str,
+    command: &[u8],
+    valid_for: Duration,
+) -> Result<Vec<u8>> {
+    let iat = now();
+    let claims = CommandClaims {
+        iss: issuer.public_key(),
+        sub: subject.to_string(),
+        iat,
+        exp: iat + valid_for.as_secs().max(1),
+        jti: uuid::Uuid::new_v4().to_string(),
+        cmd: BASE64URL_NOPAD.encode(command),
+    };
+    let claims = serde_json::to_vec(&claims).map_err(|e| {
+        errors::new(errors::ErrorKind::MiscHost(format!(
+            "Failed to encode command claims: {}",
+            e
+        )))
+    })?;
+    let head_and_claims = format!(
+        "{}.{}",
+        BASE64URL_NOPAD.encode(HEADER.as_bytes()),
+        BASE64URL_NOPAD.encode(&claims)
+    );
+    let sig = issuer.sign(head_and_claims.as_bytes()).map_err(|e| {
+        errors::new(errors::ErrorKind::MiscHost(format!(
+            "Failed to sign command: {}",
+            e
+        )))
+    })?;
+    Ok(format!("{}.{}", head_and_claims, BASE64URL_NOPAD.encode(&sig)).into_bytes())
+}
+
+// The IDs of the signed commands a host has accepted, each kept until its command expires
+#[derive(Default)]
+pub(crate) struct SeenCommands {
+    expiries: Mutex<HashMap<String, u64>>,
+}
+
+impl SeenCommands {
+    // Records the ID, unless it has already been seen, forgetting the IDs of expired commands
+    fn admit(&self, id: String, exp: u64, now: u64) -> bool {
+        let mut expiries = self.expiries.lock_or_recover();
+        expiries.retain(|_, e| *e > now);
+        if expiries.contains_key(&id) {
+            false
+        } else {
+            expiries.insert(id, exp);
+            true
+        }
+    }
+}
+
+// Verifies a signed command received on the subject, returning the issuer that signed it and
+// the command it carries
+pub(crate) fn verify(
+    issuers: &[String],
+    seen: &SeenCommands,
+    subject: &str,
+    data: &[u8],
+) -> std::result::Result<(String, Vec<u8>), String> {
+    let token = std::str::from_utf8(data).map_err(|_| "command is not signed".to_string())?;
+    let parts: Vec<_> = token.split('.').collect();
+    if parts.len() != 3 {
+        return Err("command is not signed".to_string());
+    }
+    let decode = |p: &str| {
+        BASE64URL_NOPAD
+            .decode(p.as_bytes())
+            .map_err(|_| "malformed command token".to_string())
+    };
+    let claims: CommandClaims = serde_json::from_slice(&decode(parts[1])?)
+        .map_err(|_| "malformed command token".to_string())?;
+    if !issuers.contains(&claims.iss) {
+        return Err(format!("issuer {} is not trusted", claims.iss));
+    }
+    let key = KeyPair::from_public_key(&claims.iss).map_err(|e| e.to_string())?;
+    key.verify(
+        format!("{}.{}", parts[0], parts[1]).as_bytes(),
+        &decode(parts[2])?,
+    )
+    .map_err(|_| "invalid signature".to_string())?;
+    let now = now();
+    if claims.exp <= now {
+        return Err("command has expired".to_string());
+    }
+    if claims.sub != subject {
+        return Err(format!("command was signed for {}", claims.sub));
+    }
+    let cmd = decode(&claims.cmd)?;
+    if !seen.admit(format!("{}:{}", claims.iss, claims.jti), claims.exp, now) {
+        return Err(format!("command {} was already received", claims.jti));
+    }
+    Ok((claims.iss, cmd))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{sign_control_command, verify, SeenCommands};
+    use std::time::Duration;
+    use wascap::prelude::KeyPair;
+
+    #[test]
+    fn signed_commands_verified() {
+        let issuer = KeyPair::new_operator();
+        let issuers = vec![issuer.public_key()];
+        let subject = "wasmbus.control.Nxxx.launch";
+        let cmd = br#"{"actor_id":"localhost/echo:v1"}"#;
+        let signed = sign_control_command(&issuer, subject, cmd, Duration::from_secs(60)).unwrap();
+        let seen = SeenCommands::default();
+        assert_eq!(
+            (issuer.public_key(), cmd.to_vec()),
+            verify(&issuers, &seen, subject, &signed).unwrap()
+        );
+
+        // Unsigned, for another subject, or by an untrusted issuer
+        let seen = SeenCommands::default();
+        assert!(verify(&issuers, &seen, subject, cmd).is_err());
+        assert!(
+            verify(&issuers, &seen, "wasmbus.control.Nyyy.launch", &signed)
+                .unwrap_err()
+                .contains("signed for")
+        );
+        let forger = KeyPair::new_operator();
+        let forged = sign_control_command(&forger, subject, cmd, Duration::from_secs(60)).unwrap();
+        assert!(verify(&issuers, &seen, subject, &forged)
+            .unwrap_err()
+            .contains("not trusted"));
+
+        // Claiming a trusted issuer without its key
+        let token = String::from_utf8(forged).unwrap();
+        let parts: Vec<_> = token.split('.').collect();
+        let signed_token = String::from_utf8(signed).unwrap();
+        let trusted_claims = signed_token.split('.').nth(1).unwrap();
+        let tampered = format!("{}.{}.{}", parts[0], trusted_claims, parts[2]);
+        assert_eq!(
+            "invalid signature",
+            verify(&issuers, &seen, subject, tampered.as_bytes()).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn replayed_commands_rejected() {
+        let issuer = KeyPair::new_operator();
+        let issuers = vec![issuer.public_key()];
+        let subject = "wasmbus.control.Nxxx.launch";
+        let cmd = br#"{"actor_id":"localhost/echo:v1"}"#;
+        let signed = sign_control_command(&issuer, subject, cmd, Duration::from_secs(60)).unwrap();
+        let seen = SeenCommands::default();
+        assert!(verify(&issuers, &seen, subject, &signed).is_ok());
+        assert!(verify(&issuers, &seen, subject, &signed)
+            .unwrap_err()
+            .contains("already received"));
+
+        // The same command signed again is a new command
+        let again = sign_control_command(&issuer, subject, cmd, Duration::from_secs(60)).unwrap();
+        assert!(verify(&issuers, &seen, subject, &again).is_ok());
+    }
+
+    #[test]
+    fn seen_ids_kept_until_expiry() {
+        let seen = SeenCommands::default();
+        assert!(seen.admit("a".to_string(), 10, 5));
+        assert!(!seen.admit("a".to_string(), 10, 9));
+        assert!(seen.admit("b".to_string(), 20, 10));
+        assert_eq!(1, seen.expiries.lock().unwrap().len());
+        assert!(seen.admit("a".to_string(), 30, 10));
+    }
+}
@@ -1,20 +1,39 @@
-use crate::errors;
+use super::{ExchangeError, LocalSubscriber};
+use crate::errors::{self, BusError};
+use crate::events::{EventBroker, HostEvent};
+use crate::locks::RwLockExt;
+use crate::stats::StatsCounters;
 use crate::{Invocation, InvocationResponse, Result};
-use crossbeam::{Receiver, Sender};
-use std::{collections::HashMap, sync::RwLock};
+use crossbeam::Receiver;
+use std::time::Duration;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
 
+// Each host owns its own bus, so subjects only need to be unique within a host
 pub(crate) struct InprocBus {
-    subscriptions: RwLock<HashMap<String, (Sender<Invocation>, Receiver<InvocationResponse>)>>,
+    subscriptions: RwLock<HashMap<String, LocalSubscriber>>,
+    events: EventBroker,
+    deadletters: super::DeadLetterLimiter,
+    stats: Arc<StatsCounters>,
 }
 
 impl InprocBus {
-    pub fn new() -> Self {
+    pub fn new(stats: Arc<StatsCounters>) -> Self {
         info!("Initialized Message Bus (internal)");
         InprocBus {
             subscriptions: RwLock::new(HashMap::new()),
+            events: EventBroker::default(),
+            deadletters: super::DeadLetterLimiter::default(),
+            stats,
         }
     }
 
+    pub(crate) fn stats(&self) -> &StatsCounters {
+        &self.stats
+    }
+
     pub fn disconnect(&self) {
         // No-op
     }
@@ -25,10 +44,10 @@ impl InprocBus {
         sender: crossbeam::Sender<Invocation>,
         receiver: crossbeam::Receiver<InvocationResponse>,
     ) -> Result<()> {
+        super::validate_subject(subject)?;
         self.subscriptions
-            .write()
-            .unwrap()
-            .insert(subject.to_string(), (sender, receiver));
+            .write_or_recover()
+            .insert(subject.to_string(), LocalSubscriber::new(sender, receiver));
         Ok(())
     }
 
@@ -41,28 +60,158 @@ impl InprocBus {
         self.subscribe(subject, sender, receiver)
     }
 
+    /// Subscribes a capability provider's root thread to both its root subject and its
+    /// configuration subject, answering invocations on either one at a time
+    pub(crate) fn subscribe_provider(
+        &self,
+        subject: &str,
+        config_subject: &str,
+        sender: crossbeam::Sender<Invocation>,
+        receiver: crossbeam::Receiver<InvocationResponse>,
+    ) -> Result<()> {
+        super::validate_subject(subject)?;
+        super::validate_subject(config_subject)?;
+        let local = LocalSubscriber::new(sender, receiver);
+        let mut subs = self.subscriptions.write_or_recover();
+        subs.insert(config_subject.to_string(), local.clone());
+        subs.insert(subject.to_string(), local);
+        Ok(())
+    }
+
+    // A single host only ever runs one instance of an actor, so there's nothing to share
+    pub fn subscribe_with_mode(
+        &self,
+        subject: &str,
+        _mode: &super::DeliveryMode,
+        sender: crossbeam::Sender<Invocation>,
+        receiver: crossbeam::Receiver<InvocationResponse>,
+    ) -> Result<()> {
+        self.subscribe(subject, sender, receiver)
+    }
+
     pub fn invoke(&self, subject: &str, inv: Invocation) -> Result<InvocationResponse> {
-        match self.subscriptions.read().unwrap().get(subject) {
-            Some(s) => {
-                s.0.send(inv).unwrap();
-                let r = s.1.recv().unwrap();
-                Ok(r)
+        self.deliver(subject, inv, None, None)
+    }
+
+    /// Invokes the subscriber of the subject, failing if it hasn't answered within the timeout
+    pub fn invoke_with_timeout(
+        &self,
+        subject: &str,
+        inv: Invocation,
+        timeout: Duration,
+    ) -> Result<InvocationResponse> {
+        self.deliver(subject, inv, Some(timeout), None)
+    }
+
+    /// Invokes the subscriber of the subject, which in a single host is always this host's own
+    pub(crate) fn invoke_own(
+        &self,
+        subject: &str,
+        inv: Invocation,
+        timeout: Duration,
+    ) -> Result<InvocationResponse> {
+        self.deliver(subject, inv, Some(timeout), None)
+    }
+
+    // Hands the invocation to the subject's subscriber, refusing it if `max_pending`
+    // invocations are already waiting when there's a limit
+    fn deliver(
+        &self,
+        subject: &str,
+        inv: Invocation,
+        timeout: Option<Duration>,
+        max_pending: Option<usize>,
+    ) -> Result<InvocationResponse> {
+        super::validate_subject(subject)?;
+        let disconnected = || {
+            errors::bus(BusError::Disconnected {
+                subject: subject.to_string(),
+            })
+        };
+        // The lock can't be held while waiting on the subscriber, which may be trying to
+        // unsubscribe as it terminates
+        let sub = self.subscriptions.read_or_recover().get(subject).cloned();
+        match sub {
+            // The subscriber's thread has gone away if either end of its channel is closed
+            Some(s) => match max_pending {
+                Some(max) => s.try_exchange(inv, timeout, max),
+                None => s.exchange(inv, timeout),
+            }
+            .map_err(|e| match e {
+                ExchangeError::Timeout => errors::bus(BusError::Timeout {
+                    subject: subject.to_string(),
+                }),
+                ExchangeError::Undelivered(inv) => {
+                    self.dead_letter(super::undeliverable_event(subject, &inv));
+                    disconnected()
+                }
+                // The invocation was delivered, so there's no dead letter to report
+                ExchangeError::Unanswered => {
+                    warn!(
+                        "Invocation on {} went unanswered, its destination thread stopped running.",
+                        subject
+                    );
+                    disconnected()
+                }
+                ExchangeError::Saturated => errors::bus(BusError::Saturated {
+                    subject: subject.to_string(),
+                }),
+            }),
+            None => {
+                self.dead_letter(super::undeliverable_event(subject, &inv));
+                Err(errors::bus(BusError::NoResponders {
+                    subject: subject.to_string(),
+                }))
             }
-            None => Err(errors::new(errors::ErrorKind::MiscHost(format!(
-                "Attempted bus call for {} with no subscribers",
-                subject
-            )))),
         }
     }
 
+    fn dead_letter(&self, event: HostEvent) {
+        if self.deadletters.admit() {
+            self.events.publish(event);
+        }
+    }
+
+    /// Invokes the subscriber of the subject only if fewer than `max_pending` invocations are
+    /// already waiting for it, failing immediately otherwise or if there is no subscriber
+    pub fn try_invoke(
+        &self,
+        subject: &str,
+        inv: Invocation,
+        max_pending: usize,
+    ) -> Result<InvocationResponse> {
+        self.deliver(subject, inv, None, Some(max_pending))
+    }
+
+    // There is only ever one subscriber for a subject in a single host
+    pub fn invoke_all(&self, subject: &str, inv: Invocation) -> Result<Vec<InvocationResponse>> {
+        self.invoke(subject, inv).map(|r| vec![r])
+    }
+
+    pub fn is_subscribed(&self, subject: &str) -> bool {
+        self.subscriptions.read_or_recover().contains_key(subject)
+    }
+
     pub fn unsubscribe(&self, subject: &str) -> Result<()> {
         self.subscriptions
-            .write()
-            .unwrap()
+            .write_or_recover()
             .remove(&subject.to_string());
         Ok(())
     }
 
+    // In-process subscriptions can't be torn down from outside the host
+    pub(crate) fn failed_subscriptions(&self) -> Vec<String> {
+        vec![]
+    }
+
+    pub(crate) fn publish_host_event(&self, event: HostEvent) {
+        self.events.publish(event);
+    }
+
+    pub(crate) fn host_events(&self) -> Receiver<HostEvent> {
+        self.events.subscribe()
+    }
+
     pub fn actor_subject(&self, actor: &str) -> String {
         super::actor_subject(None, actor)
     }
@@ -71,6 +220,10 @@ impl InprocBus {
         super::provider_subject(None, capid, binding)
     }
 
+    pub(crate) fn provider_config_subject(&self, capid: &str, binding: &str) -> String {
+        super::provider_config_subject(None, capid, binding)
+    }
+
     pub(crate) fn inventory_wildcard_subject(&self) -> String {
         super::inventory_wildcard_subject(None)
     }
@@ -88,3 +241,122 @@ impl InprocBus {
         super::provider_subject_bound_actor(None, capid, binding, calling_actor)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::InprocBus;
+    use crate::errors::{BusError, ErrorKind};
+    use crate::events::HostEvent;
+    use crate::{Invocation, InvocationResponse, WasccEntity};
+    use std::time::Duration;
+    use wascap::prelude::KeyPair;
+
+    fn inv() -> Invocation {
+        Invocation::new(
+            &KeyPair::new_server(),
+            WasccEntity::Actor("system".to_string()),
+            WasccEntity::Actor("Ma".to_string()),
+            "Test",
+            vec![],
+        )
+    }
+
+    fn bus_error(e: crate::errors::Error) -> BusError {
+        match e.into_kind() {
+            ErrorKind::Bus(b) => b,
+            k => panic!("expected a bus error, got {:?}", k),
+        }
+    }
+
+    #[test]
+    fn failures_classified() {
+        let bus = InprocBus::new(Default::default());
+        let subject = "wasmbus.actor.Ma";
+
+        let e = bus_error(bus.invoke(subject, inv()).unwrap_err());
+        assert_eq!(
+            e,
+            BusError::NoResponders {
+                subject: subject.to_string()
+            }
+        );
+        assert!(e.is_retryable());
+
+        // The subscriber's thread has exited
+        let (inv_s, inv_r) = crossbeam_channel::unbounded();
+        let (_resp_s, resp_r) = crossbeam_channel::unbounded();
+        bus.subscribe(subject, inv_s, resp_r).unwrap();
+        drop(inv_r);
+        let e = bus_error(bus.invoke(subject, inv()).unwrap_err());
+        assert_eq!(
+            e,
+            BusError::Disconnected {
+                subject: subject.to_string()
+            }
+        );
+
+        let e = bus_error(bus.invoke("wasmbus..Ma", inv()).unwrap_err());
+        assert!(!e.is_retryable());
+        assert_eq!("wasmbus..Ma", e.subject());
+    }
+
+    #[test]
+    fn late_responses_discarded() {
+        let bus = InprocBus::new(Default::default());
+        let subject = "wasmbus.actor.Ma";
+        // Only the first invocation is answered slowly
+        let (inv_s, inv_r) = crossbeam_channel::unbounded::<Invocation>();
+        let (resp_s, resp_r) = crossbeam_channel::unbounded();
+        std::thread::spawn(move || {
+            for (i, inv) in inv_r.iter().enumerate() {
+                if i == 0 {
+                    std::thread::sleep(Duration::from_millis(300));
+                }
+                let _ = resp_s.send(InvocationResponse::success(&inv, vec![]));
+            }
+        });
+        bus.subscribe(subject, inv_s, resp_r).unwrap();
+
+        let e = bus_error(
+            bus.invoke_with_timeout(subject, inv(), Duration::from_millis(50))
+                .unwrap_err(),
+        );
+        assert_eq!(
+            e,
+            BusError::Timeout {
+                subject: subject.to_string()
+            }
+        );
+        let second = inv();
+        let inv_r = bus
+            .invoke_with_timeout(subject, second.clone(), Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(second.id, inv_r.invocation_id);
+    }
+
+    #[test]
+    fn undeliverable_invocations_reported() {
+        let bus = InprocBus::new(Default::default());
+        let events = bus.host_events();
+        let subject = "wasmbus.actor.Ma";
+        for _ in 0..20 {
+            assert!(bus.invoke(subject, inv()).is_err());
+        }
+        let reported: Vec<_> = events.try_iter().collect();
+        // Reports are rate-limited
+        assert_eq!(10, reported.len());
+        match reported[0] {
+            HostEvent::InvocationUndeliverable {
+                subject: ref s,
+                ref target,
+                ref operation,
+                ..
+            } => {
+                assert_eq!(subject, s);
+                assert_eq!("wasmbus://Ma/Test", target);
+                assert_eq!("Test", operation);
+            }
+            ref e => panic!("unexpected event {:?}", e),
+        }
+    }
+}
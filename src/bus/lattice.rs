@@ -1,4 +1,13 @@
-use crate::{BindingsList, NativeCapability, RouteKey};
+use super::remoteclaims::{self, RemoteClaims};
+use super::{DeliveryMode, ExchangeError, LocalSubscriber};
+use crate::actorinfo::ActorRuntime;
+use crate::errors::{self, BusError};
+use crate::events::{EventBroker, HostEvent, LatticeEvent, LatticeEventStream, ReasonCode};
+use crate::locks::{MutexExt, RwLockExt};
+use crate::signer::InvocationSigner;
+use crate::snapshot::HostSnapshot;
+use crate::stats::StatsCounters;
+use crate::{bindings::Bindings, NativeCapability, RouteKey};
 use crate::{Invocation, InvocationResponse, Result};
 use crossbeam::{Receiver, Sender};
 use crossbeam_channel as channel;
@@ -9,13 +18,16 @@ use latticeclient::{
     },
     BusEvent, CloudEvent,
 };
-use nats;
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 use std::time::{Duration, SystemTime};
 
 use wascap::jwt::{Actor, Claims};
+use wascc_codec::core::{OP_BIND_ACTOR, OP_REMOVE_ACTOR};
 use wascc_codec::{capabilities::CapabilityDescriptor, deserialize, serialize};
 
 const LATTICE_HOST_KEY: &str = "LATTICE_HOST";
@@ -25,39 +37,220 @@ const DEFAULT_LATTICE_HOST: &str = "127.0.0.1";
 const LATTICE_RPC_TIMEOUT_KEY: &str = "LATTICE_RPC_TIMEOUT_MILLIS";
 const DEFAULT_LATTICE_RPC_TIMEOUT_MILLIS: u64 = 600;
 const LATTICE_CREDSFILE_KEY: &str = "LATTICE_CREDS_FILE";
+const LATTICE_CONTROL_WORKERS_KEY: &str = "LATTICE_CONTROL_WORKERS";
+// maximum number of actor/provider downloads and starts handled concurrently
+const DEFAULT_LATTICE_CONTROL_WORKERS: usize = 4;
+
+// The error in the reply to a launch command received while the host is shutting down
+const HOST_DRAINING: &str = "host draining";
+
+// The cloud event types of `LatticeEvent`s all start with this
+const EXTENDED_EVENT_PREFIX: &str = "com.wascc.lattice.ext.";
 
 const TERM_BACKOFF_MAX_TRIES: u8 = 3;
 const TERM_BACKOFF_DELAY_MS: u64 = 50;
 
-use crate::inthost::{CORELABEL_ARCH, CORELABEL_OS};
+// Re-establishing subscriptions that were torn down without the host unsubscribing. The delay
+// between attempts doubles up to the maximum, and once this many consecutive attempts have
+// failed the subject is reported as unhealthy (attempts continue regardless)
+const RESUBSCRIBE_MAX_FAILURES: u32 = 5;
+const RESUBSCRIBE_BACKOFF_MS: u64 = 100;
+const RESUBSCRIBE_BACKOFF_MAX_MS: u64 = 5_000;
+
+// How long a fan-out waits for further responses once one has arrived, as there's no knowing
+// how many subscribers will answer
+const FANOUT_QUIET_PERIOD: Duration = Duration::from_millis(200);
+
+use crate::inthost::{Origin, CORELABEL_ARCH, CORELABEL_OS};
 use latticeclient::controlplane::{
     LaunchProviderCommand, ProviderAuctionRequest, ProviderAuctionResponse,
     TerminateProviderCommand, LAUNCH_PROVIDER, PROVIDER_AUCTION_REQ, TERMINATE_PROVIDER,
 };
 use latticeclient::*;
-use nats::Message;
 use std::fs::File;
-use std::path::Path;
-use wapc::WasiParams;
-use wascap::prelude::KeyPair;
+use std::path::{Path, PathBuf};
+
+use super::transport::LatticeClient;
+pub(crate) use super::transport::{Connection, Handler, Message};
+
+/// Timeouts for requests this host sends over the lattice, and limits on what it remembers about
+/// the rest of the lattice. Timeouts left unset fall back to the general RPC timeout (see
+/// `HostBuilder::with_lattice_rpc_timeout`), so the default configuration waits equally long for
+/// every kind of request
+#[derive(Debug, Clone, Default)]
+pub struct LatticeConfig {
+    /// How long to wait for an actor or capability provider to answer an invocation
+    pub invocation_timeout: Option<Duration>,
+    /// How long to wait for capability providers to apply or remove a binding, which may involve
+    /// provisioning or releasing resources
+    pub binding_timeout: Option<Duration>,
+    /// How long to wait for answers to inventory queries and launch auctions
+    pub inventory_timeout: Option<Duration>,
+    /// How many claims of actors running on other hosts, looked up to authorize bindings, are
+    /// cached (256 by default). Claims of the actors running in this host are never evicted
+    pub remote_claims_capacity: Option<usize>,
+    /// How long the claims of an actor running on another host are cached (a minute by default)
+    pub remote_claims_ttl: Option<Duration>,
+    /// Whether invocations of actors and capability providers running in this host are handed
+    /// to them directly instead of going through the NATS server (off by default). Their
+    /// antiforgery claims are still checked, and anything not running in this host is still
+    /// invoked over the lattice, as is anything subscribed with `DeliveryMode::Broadcast` or
+    /// `DeliveryMode::NamedGroup`, whose invocations are meant for other subscribers too
+    pub prefer_local: bool,
+}
+
+/// How this host connects to the lattice. `HostBuilder::new` takes these from the
+/// `LATTICE_HOST`, `LATTICE_CREDS_FILE`, `LATTICE_RPC_TIMEOUT_MILLIS`, and
+/// `LATTICE_CONTROL_WORKERS` environment variables (see `LatticeOptions::from_env`), and
+/// `HostBuilder::with_lattice_options` replaces them, e.g. so that hosts in the same process can
+/// use different NATS servers
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatticeOptions {
+    /// The NATS server to connect to (`127.0.0.1` by default)
+    pub host: String,
+    /// The NATS credentials file to connect with, if any. Without one, the connection is
+    /// anonymous
+    pub creds_file: Option<PathBuf>,
+    /// How long to wait for a response to a request sent over the lattice (600ms by default).
+    /// The timeouts set in a `LatticeConfig` take precedence for their kinds of request
+    pub rpc_timeout: Duration,
+    /// How many actor and capability provider launches are handled at once (4 by default)
+    pub control_workers: usize,
+}
+
+impl Default for LatticeOptions {
+    fn default() -> Self {
+        LatticeOptions {
+            host: DEFAULT_LATTICE_HOST.to_string(),
+            creds_file: None,
+            rpc_timeout: Duration::from_millis(DEFAULT_LATTICE_RPC_TIMEOUT_MILLIS),
+            control_workers: DEFAULT_LATTICE_CONTROL_WORKERS,
+        }
+    }
+}
+
+impl LatticeOptions {
+    /// Reads the options from the environment. Variables that are unset, empty, or invalid
+    /// leave the default in place
+    pub fn from_env() -> LatticeOptions {
+        Self::from_vars(std::env::vars())
+    }
+
+    pub(crate) fn from_vars(vars: impl Iterator<Item = (String, String)>) -> LatticeOptions {
+        let vars: HashMap<String, String> = vars.filter(|(_, v)| !v.is_empty()).collect();
+        let defaults = LatticeOptions::default();
+        LatticeOptions {
+            host: vars.get(LATTICE_HOST_KEY).cloned().unwrap_or(defaults.host),
+            creds_file: vars.get(LATTICE_CREDSFILE_KEY).map(PathBuf::from),
+            rpc_timeout: vars
+                .get(LATTICE_RPC_TIMEOUT_KEY)
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.rpc_timeout),
+            control_workers: vars
+                .get(LATTICE_CONTROL_WORKERS_KEY)
+                .and_then(|v| v.parse().ok())
+                .filter(|n| *n > 0)
+                .unwrap_or(defaults.control_workers),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub(crate) enum ControlCommand {
-    TerminateActor(TerminateCommand),
+    // The command and who requested it, as passed to `Authorizer::can_terminate`
+    TerminateActor(TerminateCommand, String),
     TerminateProvider(TerminateProviderCommand),
     StartActor(LaunchCommand, Message),
     StartProvider(LaunchProviderCommand, Message),
 }
 
+// Creates a handler for the subject on the connection. The handler must hold on to the given
+// sender until it ends, which is how the bus notices the subscription has gone away
+type Resubscribe =
+    Arc<dyn Fn(&Connection, &str, Sender<()>) -> std::io::Result<Handler> + Send + Sync>;
+
+// Reports invocations that arrive for a subscriber that is no longer running. Only metadata
+// is published on the dead-letter subject, never the payload
+struct DeadLetters {
+    subject: String,
+    events: Arc<EventBroker>,
+    limiter: super::DeadLetterLimiter,
+}
+
+impl DeadLetters {
+    // The event must be an `InvocationUndeliverable`
+    fn report(&self, nc: &Connection, event: HostEvent, host_id: &str) {
+        if !self.limiter.admit() {
+            return;
+        }
+        if let HostEvent::InvocationUndeliverable {
+            ref subject,
+            ref invocation_id,
+            ref origin,
+            ref target,
+            ref operation,
+        } = event
+        {
+            let payload = serde_json::json!({
+                "subject": subject,
+                "invocation_id": invocation_id,
+                "origin": origin,
+                "target": target,
+                "operation": operation,
+                "host_id": host_id,
+            });
+            if let Err(e) = nc.publish(&self.subject, payload.to_string()) {
+                warn!("Failed to publish dead letter on {}: {}", self.subject, e);
+            }
+        }
+        self.events.publish(event);
+    }
+}
+
+struct Subscribed {
+    // Distinguishes this subscription from later ones to the same subject
+    id: u64,
+    handler: Handler,
+    resubscribe: Resubscribe,
+    // This host's subscriber, to which invocations can be handed without crossing the lattice
+    local: LocalSubscriber,
+    // Set only for queue subscriptions, the one kind whose invocations go to a single
+    // subscriber, and so the only kind whose invocations may be handed straight to it in place
+    // of being published
+    exclusive: bool,
+}
+
 pub(crate) struct DistributedBus {
-    nc: Arc<RwLock<Option<nats::Connection>>>,
-    subs: Arc<RwLock<HashMap<String, nats::subscription::Handler>>>,
+    nc: Arc<RwLock<Option<Connection>>>,
+    subs: Arc<RwLock<HashMap<String, Subscribed>>>,
+    // Subjects whose subscriptions have been lost and could not be re-established
+    failed_subs: Arc<RwLock<HashSet<String>>>,
+    next_sub_id: AtomicU64,
     terminators: Arc<RwLock<HashMap<String, Sender<bool>>>>,
-    req_timeout: Duration,
+    invocation_timeout: Duration,
+    binding_timeout: Duration,
+    inventory_timeout: Duration,
+    control_workers: usize,
     host_id: String,
-    lc: Arc<RwLock<latticeclient::Client>>,
+    lc: Arc<RwLock<LatticeClient>>,
     pub(crate) ns: Option<String>,
     claims: Arc<RwLock<HashMap<String, Claims<Actor>>>>,
+    // claims of actors running elsewhere in the lattice, never those of this host's actors
+    remote_claims: RemoteClaims,
+    events: Arc<EventBroker>,
+    deadletters: Arc<DeadLetters>,
+    // Checks that received invocations were signed by an accepted key
+    signer: Arc<InvocationSigner>,
+    // Set once the host starts shutting down, after which commands to start actors and
+    // providers are refused and auctions go unanswered
+    draining: Arc<AtomicBool>,
+    // Signalled once the control plane loop and its workers have stopped
+    cplane_exited: Mutex<Option<Receiver<()>>>,
+    // Counts of denials and halts, shared with the host
+    stats: Arc<StatsCounters>,
+    // Whether invocations of subscribers in this host bypass the NATS server
+    prefer_local: bool,
 }
 
 impl DistributedBus {
@@ -65,19 +258,30 @@ impl DistributedBus {
         host_id: String,
         claims: Arc<RwLock<HashMap<String, Claims<Actor>>>>,
         caps: Arc<RwLock<HashMap<RouteKey, CapabilityDescriptor>>>,
-        bindings: Arc<RwLock<BindingsList>>,
+        bindings: Arc<RwLock<Bindings>>,
         labels: Arc<RwLock<HashMap<String, String>>>,
         terminators: Arc<RwLock<HashMap<String, Sender<bool>>>>,
         ns: Option<String>,
         cplane_s: Sender<ControlCommand>,
         authz: Arc<RwLock<Box<dyn crate::authz::Authorizer>>>,
         image_map: Arc<RwLock<HashMap<String, String>>>,
+        actor_origins: Arc<RwLock<HashMap<String, Origin>>>,
+        provider_origins: Arc<RwLock<HashMap<RouteKey, Origin>>>,
+        actor_runtime: Arc<ActorRuntime>,
+        started: SystemTime,
+        signer: Arc<InvocationSigner>,
+        options: LatticeOptions,
+        config: LatticeConfig,
+        control_issuers: Vec<String>,
+        conn: Option<Connection>,
+        stats: Arc<StatsCounters>,
     ) -> Self {
-        let con = get_connection();
-        let to = get_timeout();
-        let lc = Arc::new(RwLock::new(latticeclient::Client::with_connection(
+        let con = conn.unwrap_or_else(|| get_connection(&options));
+        let to = options.rpc_timeout;
+        let inventory_timeout = config.inventory_timeout.unwrap_or(to);
+        let lc = Arc::new(RwLock::new(LatticeClient::with_connection(
             con.clone(),
-            to,
+            inventory_timeout,
             ns.clone(),
         )));
         let nc = Arc::new(RwLock::new(Some(con)));
@@ -91,6 +295,8 @@ impl DistributedBus {
             }
         );
 
+        let events = Arc::new(EventBroker::default());
+        let draining = Arc::new(AtomicBool::new(false));
         spawn_controlplane_handler(
             nc.clone(),
             host_id.clone(),
@@ -102,6 +308,9 @@ impl DistributedBus {
             cplane_s,
             authz,
             image_map.clone(),
+            control_issuers,
+            events.clone(),
+            draining.clone(),
         )
         .unwrap();
 
@@ -111,45 +320,90 @@ impl DistributedBus {
             claims.clone(),
             bindings.clone(),
             caps.clone(),
-            SystemTime::now(),
+            started,
             labels,
             ns.clone(),
-            image_map.clone(),
+            actor_origins,
+            provider_origins,
+            actor_runtime,
         )
         .unwrap();
+        let deadletters = Arc::new(DeadLetters {
+            subject: super::deadletter_subject(ns.as_ref().map(String::as_str)),
+            events: events.clone(),
+            limiter: super::DeadLetterLimiter::default(),
+        });
         DistributedBus {
             nc,
             subs: Arc::new(RwLock::new(HashMap::new())),
+            failed_subs: Arc::new(RwLock::new(HashSet::new())),
+            next_sub_id: AtomicU64::new(0),
             terminators,
-            req_timeout: to,
+            invocation_timeout: config.invocation_timeout.unwrap_or(to),
+            binding_timeout: config.binding_timeout.unwrap_or(to),
+            inventory_timeout,
+            control_workers: options.control_workers,
             host_id,
             lc,
             ns: ns.clone(),
             claims,
+            remote_claims: RemoteClaims::new(
+                config
+                    .remote_claims_capacity
+                    .unwrap_or(remoteclaims::DEFAULT_CAPACITY),
+                config
+                    .remote_claims_ttl
+                    .unwrap_or(remoteclaims::DEFAULT_TTL),
+            ),
+            events,
+            deadletters,
+            signer,
+            draining,
+            cplane_exited: Mutex::new(None),
+            stats,
+            prefer_local: config.prefer_local,
         }
     }
 
-    pub fn disconnect(&self) {
-        // Terminate the control plane command handler
+    pub(crate) fn stats(&self) -> &StatsCounters {
+        &self.stats
+    }
+
+    /// Stops accepting control plane commands, waiting (up to the timeout) for the control plane
+    /// loop to exit and for any actor or provider it was already starting to finish starting.
+    /// Commands to start actors or providers that arrive from now on are refused
+    pub(crate) fn stop_control_plane(&self, timeout: Duration) {
+        self.draining.store(true, Ordering::SeqCst);
         let cpsubject = format!(
             "{}.{}.{}",
             super::nsprefix(self.ns.as_ref().map(String::as_str)),
             latticeclient::controlplane::CPLANE_PREFIX,
             self.host_id
         );
-        self.terminators.read().unwrap()[&cpsubject]
-            .send(true)
-            .unwrap();
+        if let Some(t) = self.terminators.read_or_recover().get(&cpsubject) {
+            let _ = t.send(true);
+        }
+        if let Some(exited) = self.cplane_exited.lock_or_recover().take() {
+            if exited.recv_timeout(timeout).is_err() {
+                warn!("Control plane did not stop before the shutdown timeout");
+            }
+        }
+    }
 
+    pub fn disconnect(&self) {
         let mut backoffcount = 0_u8;
         // Wait until everything that can be gracefully shut off has been shut off
-        while self.terminators.read().unwrap().len() > 0 && backoffcount < TERM_BACKOFF_MAX_TRIES {
+        while self.terminators.read_or_recover().len() > 0 && backoffcount < TERM_BACKOFF_MAX_TRIES
+        {
             std::thread::sleep(std::time::Duration::from_millis(TERM_BACKOFF_DELAY_MS));
             backoffcount += 1;
         }
         let _ = self.publish_event(BusEvent::HostStopped(self.host_id.to_string()));
         std::thread::sleep(Duration::from_millis(300));
-        let mut lock = self.nc.write().unwrap();
+        // Subscriptions go away with the connection
+        self.subs.write_or_recover().clear();
+        self.failed_subs.write_or_recover().clear();
+        let mut lock = self.nc.write_or_recover();
         let conn = lock.take();
         if let Some(nc) = conn {
             nc.close();
@@ -157,7 +411,7 @@ impl DistributedBus {
     }
 
     pub fn instance_count(&self, actor: &str) -> Result<usize> {
-        match self.lc.read().unwrap().get_actors() {
+        match self.lc.read_or_recover().get_actors() {
             Ok(res) => {
                 let count = res.values().into_iter().fold(0, |acc, x| {
                     acc + x
@@ -172,27 +426,84 @@ impl DistributedBus {
         }
     }
 
+    /// Discovers the claims of an actor running in this host or elsewhere in the lattice. The
+    /// claims of actors found on other hosts are cached for a while, but only in the bounded
+    /// remote claims cache, never in this host's own claims map
     pub fn discover_claims(&self, actor: &str) -> Option<Claims<wascap::jwt::Actor>> {
-        let res = match self.lc.read().unwrap().get_actors() {
-            Ok(res) => {
-                let flattened = res.values().into_iter().flatten().collect::<Vec<_>>();
-                let mut claims = flattened.into_iter().filter(|c| c.subject == actor).take(1);
-                match claims.next() {
-                    Some(c) => Some(c.clone()),
-                    None => None,
-                }
-            }
+        if let Some(c) = self.claims.read_or_recover().get(actor) {
+            return Some(c.clone());
+        }
+        if let Some(c) = self.remote_claims.get(actor) {
+            return Some(c);
+        }
+        let res = match self.lc.read_or_recover().get_actors() {
+            Ok(res) => res.values().flatten().find(|c| c.subject == actor).cloned(),
             Err(_e) => None,
         };
-        if res.is_some() {
-            res
-        } else {
-            self.claims.read().unwrap().get(actor).cloned()
+        if let Some(ref c) = res {
+            self.remote_claims.insert(actor, c.clone());
+        }
+        res
+    }
+
+    /// Removes the cached claims of an actor running on another host, so that they're looked
+    /// up in the lattice again the next time they're needed
+    pub(crate) fn forget_remote_claims(&self, actor: &str) {
+        self.remote_claims.remove(actor);
+    }
+
+    /// The number of claims of actors running on other hosts currently cached
+    pub fn remote_claims_cached(&self) -> usize {
+        self.remote_claims.len()
+    }
+
+    /// Discovers the claims of an actor running in the given lattice namespace, which need
+    /// not be this host's namespace
+    pub fn discover_claims_in(
+        &self,
+        ns: Option<&str>,
+        actor: &str,
+    ) -> Option<Claims<wascap::jwt::Actor>> {
+        if ns == self.ns.as_ref().map(String::as_str) {
+            return self.discover_claims(actor);
+        }
+        match self.with_client(ns, |lc| lc.get_actors()) {
+            Ok(Ok(res)) => res.values().flatten().find(|c| c.subject == actor).cloned(),
+            _ => None,
+        }
+    }
+
+    // Runs the function with a lattice client for the given namespace, using this host's own
+    // client when the namespace is the host's namespace
+    fn with_client<T>(&self, ns: Option<&str>, f: impl FnOnce(&LatticeClient) -> T) -> Result<T> {
+        if ns == self.ns.as_ref().map(String::as_str) {
+            return Ok(f(&self.lc.read_or_recover()));
+        }
+        match self.nc.read_or_recover().as_ref() {
+            Some(nc) => {
+                let lc = LatticeClient::with_connection(
+                    nc.clone(),
+                    self.inventory_timeout,
+                    ns.map(|s| s.to_string()),
+                );
+                Ok(f(&lc))
+            }
+            None => Err(errors::bus(BusError::Disconnected {
+                subject: super::inventory_wildcard_subject(ns),
+            })),
+        }
+    }
+
+    /// The claims of every actor running in the lattice, one entry per running instance
+    pub fn lattice_actors(&self) -> Result<Vec<Claims<wascap::jwt::Actor>>> {
+        match self.lc.read_or_recover().get_actors() {
+            Ok(res) => Ok(res.values().flatten().cloned().collect()),
+            Err(e) => Err(format!("Failed to query actors from lattice: {}", e).into()),
         }
     }
 
     pub fn query_bindings(&self) -> Result<Vec<latticeclient::Binding>> {
-        match self.lc.read().unwrap().get_bindings() {
+        match self.lc.read_or_recover().get_bindings() {
             Ok(r) => {
                 let v: Vec<_> = r.values().fold(vec![], |mut acc, x| {
                     acc.extend_from_slice(x);
@@ -204,25 +515,25 @@ impl DistributedBus {
         }
     }
 
+    /// The bindings known to every other host in the lattice
+    pub fn query_remote_bindings(&self) -> Result<Vec<latticeclient::Binding>> {
+        match self.lc.read_or_recover().get_bindings() {
+            Ok(r) => Ok(r
+                .into_iter()
+                .filter(|(host, _)| *host != self.host_id)
+                .flat_map(|(_, b)| b)
+                .collect()),
+            Err(e) => Err(format!("Failed to query bindings from lattice : {}", e).into()),
+        }
+    }
+
     pub fn subscribe(
         &self,
         subject: &str,
         sender: Sender<Invocation>,
         receiver: Receiver<InvocationResponse>,
     ) -> Result<()> {
-        let sub = self
-            .nc
-            .read()
-            .unwrap()
-            .as_ref()
-            .unwrap()
-            .queue_subscribe(subject, subject)?
-            .with_handler(move |msg| {
-                handle_invocation(&msg, sender.clone(), receiver.clone());
-                Ok(())
-            });
-        self.subs.write().unwrap().insert(subject.to_string(), sub);
-        Ok(())
+        self.subscribe_with_mode(subject, &DeliveryMode::QueueGroup, sender, receiver)
     }
 
     pub fn nqsubscribe(
@@ -231,66 +542,409 @@ impl DistributedBus {
         sender: Sender<Invocation>,
         receiver: Receiver<InvocationResponse>,
     ) -> Result<()> {
-        let sub = self
-            .nc
-            .read()
-            .unwrap()
-            .as_ref()
-            .unwrap()
-            .subscribe(subject)?
-            .with_handler(move |msg| {
-                handle_invocation(&msg, sender.clone(), receiver.clone());
-                Ok(())
-            });
-        self.subs.write().unwrap().insert(subject.to_string(), sub);
+        self.subscribe_with_mode(subject, &DeliveryMode::Broadcast, sender, receiver)
+    }
+
+    /// Subscribes to the subject, sharing its invocations with other subscribers in the lattice
+    /// as the delivery mode dictates
+    pub fn subscribe_with_mode(
+        &self,
+        subject: &str,
+        mode: &DeliveryMode,
+        sender: Sender<Invocation>,
+        receiver: Receiver<InvocationResponse>,
+    ) -> Result<()> {
+        super::validate_subject(subject)?;
+        super::validate_delivery_mode(mode)?;
+        self.subscribe_local(subject, mode, LocalSubscriber::new(sender, receiver))
+    }
+
+    /// Subscribes a capability provider's root thread to its root subject, shared with the
+    /// provider's other instances, and to its configuration subject, which every instance
+    /// receives. Invocations on either are answered one at a time
+    pub(crate) fn subscribe_provider(
+        &self,
+        subject: &str,
+        config_subject: &str,
+        sender: Sender<Invocation>,
+        receiver: Receiver<InvocationResponse>,
+    ) -> Result<()> {
+        super::validate_subject(subject)?;
+        super::validate_subject(config_subject)?;
+        let local = LocalSubscriber::new(sender, receiver);
+        self.subscribe_local(config_subject, &DeliveryMode::Broadcast, local.clone())?;
+        self.subscribe_local(subject, &DeliveryMode::QueueGroup, local)
+    }
+
+    fn subscribe_local(
+        &self,
+        subject: &str,
+        mode: &DeliveryMode,
+        sub_local: LocalSubscriber,
+    ) -> Result<()> {
+        let (deadletters, signer) = (self.deadletters.clone(), self.signer.clone());
+        let event_subject = self.event_subject();
+        let mode = mode.clone();
+        let local = sub_local.clone();
+        let exclusive = match mode {
+            DeliveryMode::QueueGroup => true,
+            DeliveryMode::Broadcast | DeliveryMode::NamedGroup(_) => false,
+        };
+        self.add_subscription(
+            subject,
+            Arc::new(move |nc: &Connection, subject: &str, closed: Sender<()>| {
+                let local = sub_local.clone();
+                let (conn, deadletters) = (nc.clone(), deadletters.clone());
+                let (signer, event_subject) = (signer.clone(), event_subject.clone());
+                let sub = match mode {
+                    DeliveryMode::QueueGroup => nc.queue_subscribe(subject, subject)?,
+                    DeliveryMode::Broadcast => nc.subscribe(subject)?,
+                    DeliveryMode::NamedGroup(ref group) => nc.queue_subscribe(subject, group)?,
+                };
+                Ok(sub.with_handler(move |msg| {
+                    let _ = &closed;
+                    handle_invocation(&msg, &local, &conn, &deadletters, &signer, &event_subject);
+                    Ok(())
+                }))
+            }),
+            local,
+            exclusive,
+        )
+    }
+
+    // Subscribes, and keeps the subscription alive until `unsubscribe` or `disconnect` is called
+    // by re-subscribing whenever it goes away on its own (e.g. the server tears it down)
+    fn add_subscription(
+        &self,
+        subject: &str,
+        resubscribe: Resubscribe,
+        local: LocalSubscriber,
+        exclusive: bool,
+    ) -> Result<()> {
+        let (closed_s, closed_r) = channel::bounded(0);
+        let handler = resubscribe(&self.connection(subject)?, subject, closed_s)
+            .map_err(|e| errors::from_bus_io(subject, e))?;
+        let id = self.next_sub_id.fetch_add(1, Ordering::SeqCst);
+        self.subs.write_or_recover().insert(
+            subject.to_string(),
+            Subscribed {
+                id,
+                handler,
+                resubscribe,
+                local,
+                exclusive,
+            },
+        );
+        self.failed_subs.write_or_recover().remove(subject);
+        spawn_resubscriber(
+            subject.to_string(),
+            id,
+            closed_r,
+            self.nc.clone(),
+            self.subs.clone(),
+            self.failed_subs.clone(),
+            self.events.clone(),
+        );
         Ok(())
     }
 
     pub fn invoke(&self, subject: &str, inv: Invocation) -> Result<InvocationResponse> {
-        if self.nc.read().unwrap().as_ref().is_none() {
+        let timeout = self.timeout_for(&inv);
+        self.invoke_with_timeout(subject, inv, timeout)
+    }
+
+    /// Invokes the subscriber of the subject, waiting for the given time rather than the
+    /// configured timeout for the operation
+    pub fn invoke_with_timeout(
+        &self,
+        subject: &str,
+        inv: Invocation,
+        timeout: Duration,
+    ) -> Result<InvocationResponse> {
+        super::validate_subject(subject)?;
+        if self.prefer_local {
+            let local = self
+                .subs
+                .read_or_recover()
+                .get(subject)
+                .filter(|s| s.exclusive)
+                .map(|s| s.local.clone());
+            if let Some(local) = local {
+                return self.invoke_locally(subject, &local, inv, timeout);
+            }
+        }
+        let nc = self.connection(subject).map_err(|e| {
             error!(
                 "Attempted bus invoke with no bus connection: {} {:?}->{:?}",
                 inv.operation, inv.origin, inv.target
             );
-            Err(crate::errors::new(crate::errors::ErrorKind::MiscHost(
-                "Attempted a bus invocation without a live bus connection".to_string(),
-            )))
+            e
+        })?;
+        let resp = nc
+            .request_timeout(&subject, &encode(subject, &inv)?, timeout)
+            .map_err(|e| errors::from_bus_io(subject, e))?;
+        let inv_r: InvocationResponse = decode(subject, &resp.data)?;
+        match inv_r.error {
+            Some(ref e) if *e == super::no_subscriber_error(subject) => {
+                Err(errors::bus(BusError::NoResponders {
+                    subject: subject.to_string(),
+                }))
+            }
+            _ => Ok(inv_r),
+        }
+    }
+
+    // Hands the invocation straight to the subscriber running in this host, checking its
+    // antiforgery claims just as if it had arrived over NATS
+    fn invoke_locally(
+        &self,
+        subject: &str,
+        local: &LocalSubscriber,
+        inv: Invocation,
+        timeout: Duration,
+    ) -> Result<InvocationResponse> {
+        let nc = self.nc.read_or_recover().as_ref().cloned();
+        if let Some(inv_r) =
+            check_antiforgery(&inv, &self.signer, nc.as_ref(), &self.event_subject())
+        {
+            return Ok(inv_r);
+        }
+        let no_responders = || {
+            errors::bus(BusError::NoResponders {
+                subject: subject.to_string(),
+            })
+        };
+        match local.exchange(inv, Some(timeout)) {
+            Ok(inv_r) => Ok(inv_r),
+            Err(ExchangeError::Timeout) => Err(errors::bus(BusError::Timeout {
+                subject: subject.to_string(),
+            })),
+            Err(ExchangeError::Undelivered(inv)) => {
+                warn!(
+                    "Invocation on {} is undeliverable, its destination thread is no longer running.",
+                    subject
+                );
+                if let Some(ref nc) = nc {
+                    let event = super::undeliverable_event(subject, &inv);
+                    self.deadletters.report(nc, event, &inv.host_id);
+                }
+                Err(no_responders())
+            }
+            Err(ExchangeError::Unanswered) => {
+                warn!(
+                    "Invocation on {} went unanswered, its destination thread stopped running.",
+                    subject
+                );
+                Err(no_responders())
+            }
+            Err(ExchangeError::Saturated) => Err(errors::bus(BusError::Saturated {
+                subject: subject.to_string(),
+            })),
+        }
+    }
+
+    /// Invokes this host's own subscriber of the subject, never one in another host, failing
+    /// with `BusError::NoResponders` if this host has no subscriber
+    pub(crate) fn invoke_own(
+        &self,
+        subject: &str,
+        inv: Invocation,
+        timeout: Duration,
+    ) -> Result<InvocationResponse> {
+        super::validate_subject(subject)?;
+        let local = self
+            .subs
+            .read_or_recover()
+            .get(subject)
+            .map(|s| s.local.clone());
+        match local {
+            Some(local) => self.invoke_locally(subject, &local, inv, timeout),
+            None => Err(errors::bus(BusError::NoResponders {
+                subject: subject.to_string(),
+            })),
+        }
+    }
+
+    // Binding operations get their own timeout since providers may take a while to set up or
+    // tear down the resources for a binding
+    fn timeout_for(&self, inv: &Invocation) -> Duration {
+        if inv.operation == OP_BIND_ACTOR || inv.operation == OP_REMOVE_ACTOR {
+            self.binding_timeout
+        } else {
+            self.invocation_timeout
+        }
+    }
+
+    /// The lattice has no visibility into how many requests are waiting on a subscriber, so
+    /// this is the same as `invoke`, failing after the request timeout if nothing responds
+    pub fn try_invoke(
+        &self,
+        subject: &str,
+        inv: Invocation,
+        _max_pending: usize,
+    ) -> Result<InvocationResponse> {
+        self.invoke(subject, inv)
+    }
+
+    /// Sends the invocation to every subscriber of the subject (which must not be a queue
+    /// subscription), collecting responses until none has arrived for `FANOUT_QUIET_PERIOD` or
+    /// the timeout for the operation elapses. There's no telling how many subscribers there
+    /// are, so a subscriber slower than the quiet period still receives the invocation, but
+    /// its response is missed. A response that can't be decoded is returned as an error
+    /// response, so that it doesn't discard the others
+    pub fn invoke_all(&self, subject: &str, inv: Invocation) -> Result<Vec<InvocationResponse>> {
+        super::validate_subject(subject)?;
+        let nc = self.connection(subject)?;
+        let inbox = nc.new_inbox();
+        let sub = nc
+            .subscribe(&inbox)
+            .map_err(|e| errors::from_bus_io(subject, e))?;
+        nc.publish_request(subject, &inbox, &encode(subject, &inv)?)
+            .map_err(|e| errors::from_bus_io(subject, e))?;
+
+        let deadline = std::time::Instant::now() + self.timeout_for(&inv);
+        let mut responses: Vec<InvocationResponse> = Vec::new();
+        loop {
+            let now = std::time::Instant::now();
+            if now >= deadline {
+                break;
+            }
+            let wait = if responses.is_empty() {
+                deadline - now
+            } else {
+                FANOUT_QUIET_PERIOD.min(deadline - now)
+            };
+            match sub.next_timeout(wait) {
+                Ok(msg) => responses.push(match decode(subject, &msg.data) {
+                    Ok(inv_r) => inv_r,
+                    Err(e) => {
+                        warn!("Discarding undecodable response on {}: {}", subject, e);
+                        InvocationResponse::error(&inv, &e.to_string())
+                    }
+                }),
+                Err(_) => break,
+            }
+        }
+        let _ = sub.unsubscribe();
+        if responses.is_empty() {
+            // This version of NATS can't tell a request nobody is subscribed to apart from one
+            // whose subscribers are all too slow to answer
+            Err(errors::bus(BusError::NoResponders {
+                subject: subject.to_string(),
+            }))
         } else {
-            let resp = self.nc.read().unwrap().as_ref().unwrap().request_timeout(
-                &subject,
-                &serialize(inv)?,
-                self.req_timeout,
-            )?;
-            let ir: InvocationResponse = deserialize(&resp.data)?;
-            Ok(ir)
+            Ok(responses)
         }
     }
 
     pub fn unsubscribe(&self, subject: &str) -> Result<()> {
-        if let Some(sub) = self.subs.write().unwrap().remove(subject) {
-            sub.unsubscribe()?;
+        self.failed_subs.write_or_recover().remove(subject);
+        let sub = self.subs.write_or_recover().remove(subject);
+        if let Some(sub) = sub {
+            sub.handler
+                .unsubscribe()
+                .map_err(|e| errors::from_bus_io(subject, e))?;
         }
         Ok(())
     }
 
+    pub fn is_subscribed(&self, subject: &str) -> bool {
+        self.subs.read_or_recover().contains_key(subject)
+    }
+
+    /// Subjects whose subscriptions were lost and have failed to be re-established repeatedly
+    pub(crate) fn failed_subscriptions(&self) -> Vec<String> {
+        self.failed_subs.read_or_recover().iter().cloned().collect()
+    }
+
+    /// A handle to the NATS connection, or a `Disconnected` error naming the subject of the
+    /// message that couldn't be sent if the bus has been disconnected
+    fn connection(&self, subject: &str) -> Result<Connection> {
+        self.nc.read_or_recover().as_ref().cloned().ok_or_else(|| {
+            errors::bus(BusError::Disconnected {
+                subject: subject.to_string(),
+            })
+        })
+    }
+
     pub fn publish_event(&self, event: BusEvent) -> Result<()> {
+        let subject = self.event_subject();
         let cloud_event = CloudEvent::from(event);
         let payload = match serde_json::to_vec(&cloud_event) {
             Ok(p) => p,
             Err(e) => {
-                return Err(crate::errors::new(crate::errors::ErrorKind::Serialization(
-                    format!("{}", e),
-                )));
+                return Err(errors::bus(BusError::SerializationFailure {
+                    subject,
+                    reason: e.to_string(),
+                }));
             }
         };
-        let lock = self.nc.read().unwrap();
-        if let Some(ref nc) = lock.as_ref() {
-            nc.publish(&*self.event_subject(), &payload)?;
-            nc.flush()?;
+        // The connection is cloned so that the lock isn't held while flushing backs off
+        let nc = self.nc.read_or_recover().as_ref().cloned();
+        if let Some(ref nc) = nc {
+            nc.publish(&subject, &payload)
+                .and_then(|_| flush(nc))
+                .map_err(|e| errors::from_bus_io(&subject, e))?;
         }
         Ok(())
     }
 
+    /// Publishes an event that has no `BusEvent` counterpart as a cloud event of its own type
+    pub(crate) fn publish_lattice_event(&self, event: LatticeEvent) -> Result<()> {
+        let subject = self.event_subject();
+        let nc = self.nc.read_or_recover().as_ref().cloned();
+        if let Some(ref nc) = nc {
+            publish_extended_event(nc, &subject, &event)?;
+            flush(nc).map_err(|e| errors::from_bus_io(&subject, e))?;
+        }
+        Ok(())
+    }
+
+    /// Subscribes to the lattice event subject, forwarding every event to the returned stream
+    /// until it's dropped
+    pub fn lattice_events(&self) -> Result<LatticeEventStream<BusEvent>> {
+        self.subscribe_events(parse_event)
+    }
+
+    /// Subscribes to the lattice event subject, forwarding every extended event to the returned
+    /// stream until it's dropped
+    pub(crate) fn lattice_extended_events(&self) -> Result<LatticeEventStream<LatticeEvent>> {
+        self.subscribe_events(parse_extended_event)
+    }
+
+    // Events the parser passes over (`Ok(None)`) are of the other kind, and aren't malformed
+    fn subscribe_events<T: Send + 'static>(
+        &self,
+        parse: fn(&[u8]) -> std::result::Result<Option<T>, serde_json::Error>,
+    ) -> Result<LatticeEventStream<T>> {
+        let subject = self.event_subject();
+        let (s, r) = channel::unbounded();
+        let handler = self
+            .connection(&subject)?
+            .subscribe(&subject)
+            .map_err(|e| errors::from_bus_io(&subject, e))?
+            .with_handler(move |msg| {
+                match parse(&msg.data) {
+                    // The stream may have been dropped while this event was in flight
+                    Ok(Some(evt)) => {
+                        let _ = s.send(evt);
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!("Skipping malformed lattice event on {}: {}", msg.subject, e),
+                }
+                Ok(())
+            });
+        Ok(LatticeEventStream::new(r, handler))
+    }
+
+    pub(crate) fn publish_host_event(&self, event: HostEvent) {
+        self.events.publish(event);
+    }
+
+    pub(crate) fn host_events(&self) -> Receiver<HostEvent> {
+        self.events.subscribe()
+    }
+
     pub fn actor_subject(&self, actor: &str) -> String {
         super::actor_subject(self.ns.as_ref().map(String::as_str), actor)
     }
@@ -299,6 +953,10 @@ impl DistributedBus {
         super::provider_subject(self.ns.as_ref().map(String::as_str), capid, binding)
     }
 
+    pub(crate) fn provider_config_subject(&self, capid: &str, binding: &str) -> String {
+        super::provider_config_subject(self.ns.as_ref().map(String::as_str), capid, binding)
+    }
+
     pub(crate) fn inventory_wildcard_subject(&self) -> String {
         super::inventory_wildcard_subject(self.ns.as_ref().map(String::as_str))
     }
@@ -307,18 +965,25 @@ impl DistributedBus {
         super::event_subject(self.ns.as_ref().map(String::as_str))
     }
 
+    // The host metadata provider answers for the host it runs in, so the subjects on which it
+    // serves bound actors name this host rather than being shared with the rest of the lattice
     pub(crate) fn provider_subject_bound_actor(
         &self,
         capid: &str,
         binding: &str,
         calling_actor: &str,
     ) -> String {
-        super::provider_subject_bound_actor(
+        let subject = super::provider_subject_bound_actor(
             self.ns.as_ref().map(String::as_str),
             capid,
             binding,
             calling_actor,
-        )
+        );
+        if capid == crate::hostmeta::CAPABILITY_ID {
+            format!("{}.{}", subject, self.host_id)
+        } else {
+            subject
+        }
     }
 }
 
@@ -330,37 +995,45 @@ pub(crate) fn spawn_controlplane(
     host: &crate::Host,
     com_r: Receiver<ControlCommand>,
 ) -> Result<()> {
-    let wg = crossbeam_utils::sync::WaitGroup::new();
-    let claims = host.claims.clone();
-    let wasi: Option<WasiParams> = None;
-    let actor = true;
-    let binding: Option<String> = None;
     let bus = host.bus.clone();
-    let plugins = host.plugins.clone();
-    let mids = host.middlewares.clone();
-    let caps = host.caps.clone();
-    let bindings = host.bindings.clone();
-    let claimsmap = host.claims.clone();
     let terminators = host.terminators.clone();
-    let hk = KeyPair::from_seed(&host.sk).unwrap();
-    let auth = host.authorizer.clone();
     let image_map = host.image_map.clone();
-    let labels = host.labels.clone();
+    let host_id = host.id();
+    let authz_host = host.clone();
 
     let subject = format!(
         "{}.{}.{}",
         super::nsprefix(bus.ns.as_ref().map(String::as_str)),
         latticeclient::controlplane::CPLANE_PREFIX,
-        hk.public_key()
+        host_id.to_string()
     );
     let (term_s, term_r): (Sender<bool>, Receiver<bool>) = channel::unbounded();
     terminators
-        .write()
-        .unwrap()
+        .write_or_recover()
         .insert(subject.to_string(), term_s);
+    let (exited_s, exited_r) = channel::bounded(1);
+    *bus.cplane_exited.lock_or_recover() = Some(exited_r);
+
+    // Downloading and starting actors and providers can take a long time, so that work is
+    // handed off to a bounded pool of workers, keeping this loop free to acknowledge commands
+    let (work_s, work_r): (Sender<ControlCommand>, Receiver<ControlCommand>) = channel::unbounded();
+    let mut workers: Vec<_> = (0..bus.control_workers)
+        .map(|_| {
+            let work_r = work_r.clone();
+            let host = host.clone();
+            thread::spawn(move || {
+                for cmd in work_r.iter() {
+                    match cmd {
+                        ControlCommand::StartActor(cmd, _) => start_actor(&host, cmd),
+                        ControlCommand::StartProvider(cmd, _) => start_provider(&host, cmd),
+                        _ => {}
+                    }
+                }
+            })
+        })
+        .collect();
 
     thread::spawn(move || loop {
-        let key = KeyPair::from_seed(&hk.seed().unwrap()).unwrap();
         select! {
             recv(com_r) -> cmd => {
                 if let Ok(cmd) = cmd {
@@ -370,53 +1043,34 @@ pub(crate) fn spawn_controlplane(
                             // our own download process.
                             if let Err(e) = msg.respond(&serde_json::to_vec(&LaunchAck {
                                 actor_id: cmd.actor_id.to_string(),
-                                host: hk.public_key()
+                                host: host_id.to_string()
                             }).unwrap()) {
                                 error!("Failed to send schedule acknowledgement reply: {}", e);
                             } else {
                                 info!("Acknowledged actor start request.");
                             }
-                            // As of 0.14.0, the "actor_id" here is actually an OCI registry image reference
-                            match crate::inthost::fetch_actor(&cmd.actor_id) {
-                                Ok(a) => {
-                                    image_map.write().unwrap().insert(cmd.actor_id.to_string(), a.public_key());
-                                    let wg = crossbeam_utils::sync::WaitGroup::new();
-                                    if crate::authz::enforce_validation(&a.token.jwt).is_err() {
-                                        error!("Attempt to remotely schedule invalid actor.");
-                                        continue;
-                                    }
-                                    if !auth.read().unwrap().can_load(&a.token.claims) {
-                                        error!("Authorization hook denied access to remotely scheduled module.");
-                                        continue;
-                                    }
-
-                                    crate::authz::register_claims(
-                                        claims.clone(),
-                                        &a.token.claims.subject,
-                                        a.token.claims.clone(),
-                                    );
-
-                                    let _ = crate::spawns::spawn_actor(wg, a.token.claims.clone(), a.bytes,
-                                        None, actor, binding.clone(), bus.clone(), mids.clone(),
-                                        caps.clone(), bindings.clone(), claimsmap.clone(), terminators.clone(),
-                                        key, auth.clone(), image_map.clone(), Some(cmd.actor_id.to_string()));
-
-
-                                },
-                                Err(e) => {
-                                    error!("Actor download failed for {}: {}", &cmd.actor_id, e)
+                            let _ = work_s.send(ControlCommand::StartActor(cmd, msg));
+                        },
+                        ControlCommand::TerminateActor(cmd, requested_by) => {
+                            // get PK from the OCI ref, which may have been removed since the request was queued
+                            let pk = match image_map.read_or_recover().get(&cmd.actor_id) {
+                                Some(pk) => pk.to_string(),
+                                None => {
+                                    warn!("Actor {} is no longer running, nothing to terminate", cmd.actor_id);
+                                    continue;
+                                }
+                            };
+                            if let Err(e) = authz_host.authorize_termination(&pk, &requested_by) {
+                                warn!("Ignoring request to terminate actor: {}", e);
+                            } else {
+                                image_map.write_or_recover().remove(&pk);
+                                let actor_subject = bus.actor_subject(&pk);
+                                match terminators.read_or_recover().get(&actor_subject) {
+                                    Some(t) => { let _ = t.send(true); },
+                                    None => warn!("Actor {} is no longer running, nothing to terminate", pk),
                                 }
                             }
                         },
-                        ControlCommand::TerminateActor(cmd) => {
-                            let pk = image_map.read().unwrap()[&cmd.actor_id].to_string(); // get PK from the OCI ref
-                            image_map.write().unwrap().remove(&pk);
-                            let actor_subject = bus.actor_subject(&pk);
-                            terminators.read().unwrap()
-                                [&actor_subject]
-                                .send(true)
-                                .unwrap();
-                        },
                         ControlCommand::TerminateProvider(cmd) => {
                             // TODO: this command will continue to be a no-op until the "async rewrite",
                             // which should include a re-organization of how providers subscribe to the bus
@@ -424,51 +1078,29 @@ pub(crate) fn spawn_controlplane(
                         ControlCommand::StartProvider(cmd, msg) => {
                             if let Err(e) = msg.respond(&serde_json::to_vec(&ProviderLaunchAck {
                                 provider_ref: cmd.provider_ref.to_string(),
-                                host: hk.public_key()
+                                host: host_id.to_string()
                             }).unwrap()) {
                                 error!("Failed to send schedule provider acknowledgement reply: {}", e);
                             } else {
                                 info!("Acknowledged provider start request.");
                             }
-                            match crate::inthost::fetch_provider(&cmd.provider_ref, &cmd.binding_name, labels.clone()) {
-                                Ok((p, c)) => {
-                                    if caps
-                                       .read()
-                                       .unwrap()
-                                       .contains_key(&RouteKey::new(&cmd.binding_name, &p.id()))
-                                    {
-                                        error!(
-                                          "Capability provider {} cannot be bound to the same name ({}) twice, loading failed.", p.id(), &cmd.binding_name
-                                        );
-                                    }
-                                    caps.write().unwrap().insert(
-                                        RouteKey::new(&cmd.binding_name, &p.descriptor.id),
-                                        p.descriptor().clone(),
-                                    );
-                                    let wg = crossbeam_utils::sync::WaitGroup::new();
-                                    let key = KeyPair::from_seed(&hk.seed().unwrap()).unwrap();
-                                    let _ = crate::spawns::spawn_native_capability(
-                                        p,
-                                        bus.clone(),
-                                        mids.clone(),
-                                        bindings.clone(),
-                                        terminators.clone(),
-                                        plugins.clone(),
-                                        wg.clone(),
-                                        Arc::new(key),
-                                    );
-                                    wg.wait();
-                                },
-                                Err(e) => {
-                                    error!("Provider download failed to {}: {}", &cmd.provider_ref, e)
-                                }
-                            }
+                            let _ = work_s.send(ControlCommand::StartProvider(cmd, msg));
                         }
                     }
                 }
             }
             recv(term_r) -> _term => {
-                terminators.write().unwrap().remove(&subject);
+                terminators.write_or_recover().remove(&subject);
+                // Commands that were queued before the host started draining are refused
+                for cmd in com_r.try_iter() {
+                    refuse_while_draining(cmd, &host_id);
+                }
+                // Dropping the work sender stops the workers once their current job finishes
+                drop(work_s);
+                for w in workers.drain(..) {
+                    let _ = w.join();
+                }
+                let _ = exited_s.send(());
                 break;
             }
         }
@@ -476,56 +1108,251 @@ pub(crate) fn spawn_controlplane(
     Ok(())
 }
 
+fn start_actor(host: &crate::Host, cmd: LaunchCommand) {
+    let bus = host.bus.clone();
+    // As of 0.14.0, the "actor_id" here is actually an OCI registry image reference
+    match crate::inthost::fetch_actor(host.fetcher.as_ref(), &cmd.actor_id) {
+        Ok(a) => {
+            host.image_map
+                .write_or_recover()
+                .insert(cmd.actor_id.to_string(), a.public_key());
+            let wg = crossbeam_utils::sync::WaitGroup::new();
+            if let Err(e) = host.validate_actor_claims(&a.token) {
+                error!("Attempt to remotely schedule invalid actor: {}", e);
+                return;
+            }
+            if let Err(e) = host.check_imports(&a) {
+                error!("Attempt to remotely schedule unsupported actor: {}", e);
+                return;
+            }
+            let authz_ctx = crate::authz::authorization_context(
+                &host.id(),
+                &host.labels,
+                bus.ns.as_ref().map(String::as_str),
+            );
+            if !host
+                .authorizer
+                .read_or_recover()
+                .can_load_ctx(&a.token.claims, &authz_ctx)
+            {
+                error!("Authorization hook denied access to remotely scheduled module.");
+                return;
+            }
+
+            crate::authz::register_claims(
+                host.claims.clone(),
+                &a.token.claims.subject,
+                a.token.claims.clone(),
+            );
+            host.actor_origins.write_or_recover().insert(
+                a.public_key(),
+                crate::inthost::Origin::new(Some(cmd.actor_id.to_string())),
+            );
+
+            let spawned = crate::spawns::spawn_actor(
+                wg,
+                a.token.claims.clone(),
+                a.bytes,
+                None,
+                true,
+                None,
+                host.default_binding.clone(),
+                bus.clone(),
+                host.middlewares.clone(),
+                host.middleware_policy,
+                host.caps.clone(),
+                host.bindings.clone(),
+                host.claims.clone(),
+                host.terminators.clone(),
+                host.signer.clone(),
+                host.authorizer.clone(),
+                host.image_map.clone(),
+                Some(cmd.actor_id.to_string()),
+                host.labels.clone(),
+                bus.ns.clone(),
+                host.audit.clone(),
+                host.health.clone(),
+                host.in_flight.clone(),
+                host.actor_runtime.clone(),
+                host.capability_allowlist.clone(),
+                DeliveryMode::QueueGroup,
+                host.timings.clone(),
+            );
+            match spawned {
+                Ok(spawned) => {
+                    host.actor_exits
+                        .write_or_recover()
+                        .insert(a.public_key(), spawned.exited);
+                }
+                Err(e) => {
+                    error!("Failed to start remotely scheduled actor: {}", e);
+                    crate::authz::unregister_claims(host.claims.clone(), &a.public_key());
+                    host.actor_origins
+                        .write_or_recover()
+                        .remove(&a.public_key());
+                }
+            }
+        }
+        Err(e) => error!("Actor download failed for {}: {}", &cmd.actor_id, e),
+    }
+}
+
+fn start_provider(host: &crate::Host, cmd: LaunchProviderCommand) {
+    match crate::inthost::fetch_provider(
+        host.fetcher.as_ref(),
+        &host.work_dir,
+        &cmd.provider_ref,
+        &cmd.binding_name,
+        host.labels.clone(),
+    ) {
+        Ok((p, c)) => {
+            if let Err(e) = host.capability_allowlist.check(&p.id()) {
+                error!("Refusing to start provider {}: {}", cmd.provider_ref, e);
+                return;
+            }
+            if host
+                .caps
+                .read_or_recover()
+                .contains_key(&RouteKey::new(&cmd.binding_name, &p.id()))
+            {
+                error!(
+                    "Capability provider {} cannot be bound to the same name ({}) twice, loading failed.",
+                    p.id(),
+                    &cmd.binding_name
+                );
+                return;
+            }
+            host.caps.write_or_recover().insert(
+                RouteKey::new(&cmd.binding_name, &p.descriptor.id),
+                p.descriptor().clone(),
+            );
+            host.provider_origins.write_or_recover().insert(
+                RouteKey::new(&cmd.binding_name, &p.descriptor.id),
+                crate::inthost::Origin::new(Some(cmd.provider_ref.to_string())),
+            );
+            host.image_map
+                .write_or_recover()
+                .insert(cmd.provider_ref.to_string(), c.subject.to_string());
+            let wg = crossbeam_utils::sync::WaitGroup::new();
+            let _ = crate::spawns::spawn_native_capability(
+                p,
+                host.bus.clone(),
+                host.middlewares.clone(),
+                host.middleware_policy,
+                host.bindings.clone(),
+                host.claims.clone(),
+                host.caps.clone(),
+                host.terminators.clone(),
+                host.plugins.clone(),
+                wg.clone(),
+                host.signer.clone(),
+                host.in_flight.clone(),
+                host.rebinders.clone(),
+            );
+            wg.wait();
+        }
+        Err(e) => error!("Provider download failed to {}: {}", &cmd.provider_ref, e),
+    }
+}
+
 // This thread handles control plane commands or demands, e.g. "launch actor" and "launch provider"
 // It also responds to provider and actor auctions
 fn spawn_controlplane_handler(
-    nc: Arc<RwLock<Option<nats::Connection>>>,
+    nc: Arc<RwLock<Option<Connection>>>,
     host_id: String,
     claims: Arc<RwLock<HashMap<String, Claims<Actor>>>>,
-    bindings: Arc<RwLock<BindingsList>>,
+    bindings: Arc<RwLock<Bindings>>,
     caps: Arc<RwLock<HashMap<RouteKey, CapabilityDescriptor>>>,
     labels: Arc<RwLock<HashMap<String, String>>>,
     ns: Option<String>,
     cplane_s: Sender<ControlCommand>,
     authz: Arc<RwLock<Box<dyn crate::authz::Authorizer>>>,
     image_map: Arc<RwLock<HashMap<String, String>>>,
+    issuers: Vec<String>,
+    events: Arc<EventBroker>,
+    draining: Arc<AtomicBool>,
 ) -> Result<()> {
     let subject = controlplane_wildcard_subject(ns.as_ref().map(String::as_str));
+    let seen = super::controlauth::SeenCommands::default();
+    let payload = move |msg: &Message| command_payload(&issuers, &seen, msg, &events);
     let lbs = labels.clone();
 
     let _ = nc
-        .read()
-        .unwrap()
+        .read_or_recover()
         .as_ref()
         .unwrap()
         .subscribe(&subject)?
         .with_handler(move |msg| {
+            if draining.load(Ordering::SeqCst) {
+                // No new work is taken on, nor bid for, while shutting down
+                if (msg.subject.ends_with(LAUNCH_ACTOR) || msg.subject.ends_with(LAUNCH_PROVIDER))
+                    && msg.subject.contains(&host_id)
+                {
+                    refuse_msg_while_draining(&msg, &host_id);
+                }
+                return Ok(());
+            }
             if msg.subject.ends_with(LAUNCH_ACTOR) && msg.subject.contains(&host_id) {
                 // schedule the actor
-                let lc: LaunchCommand = serde_json::from_slice(&msg.data).unwrap();
-                cplane_s.send(ControlCommand::StartActor(lc, msg)).unwrap();
+                let data = match payload(&msg) {
+                    Some((_, d)) => d,
+                    None => return Ok(()),
+                };
+                let lc: LaunchCommand = match parse_command(&msg, &data, &host_id) {
+                    Some(c) => c,
+                    None => return Ok(()),
+                };
+                dispatch_command(&cplane_s, ControlCommand::StartActor(lc, msg));
             } else if msg.subject.ends_with(TERMINATE_ACTOR) && msg.subject.contains(&host_id) {
-                let tc: TerminateCommand = serde_json::from_slice(&msg.data).unwrap();
-                if !image_map.read().unwrap().contains_key(&tc.actor_id) {
+                let (requested_by, data) = match payload(&msg) {
+                    Some(p) => p,
+                    None => return Ok(()),
+                };
+                let tc: TerminateCommand = match parse_command(&msg, &data, &host_id) {
+                    Some(c) => c,
+                    None => return Ok(()),
+                };
+                if !image_map.read_or_recover().contains_key(&tc.actor_id) {
                     // actor IDs are OCI image references in the requests
                     warn!("Received request to terminate non-existent actor. Ignoring.");
                 } else {
-                    cplane_s.send(ControlCommand::TerminateActor(tc)).unwrap();
+                    dispatch_command(&cplane_s, ControlCommand::TerminateActor(tc, requested_by));
                 }
             } else if msg.subject.ends_with(LAUNCH_PROVIDER) && msg.subject.contains(&host_id) {
                 // schedule the provider
-                let lc: LaunchProviderCommand =serde_json::from_slice(&msg.data).unwrap();
-                cplane_s.send(ControlCommand::StartProvider(lc, msg)).unwrap();
+                let data = match payload(&msg) {
+                    Some((_, d)) => d,
+                    None => return Ok(()),
+                };
+                let lc: LaunchProviderCommand = match parse_command(&msg, &data, &host_id) {
+                    Some(c) => c,
+                    None => return Ok(()),
+                };
+                dispatch_command(&cplane_s, ControlCommand::StartProvider(lc, msg));
             } else if msg.subject.ends_with(TERMINATE_PROVIDER) && msg.subject.contains(&host_id) {
-                let tc: TerminateProviderCommand = serde_json::from_slice(&msg.data).unwrap();
-                if !image_map.read().unwrap().contains_key(&tc.provider_ref) {
+                let data = match payload(&msg) {
+                    Some((_, d)) => d,
+                    None => return Ok(()),
+                };
+                let tc: TerminateProviderCommand = match parse_command(&msg, &data, &host_id) {
+                    Some(c) => c,
+                    None => return Ok(()),
+                };
+                if !image_map.read_or_recover().contains_key(&tc.provider_ref) {
                     warn!("Received request to terminate non-existent provider. Ignoring.");
                 } else {
-                    cplane_s.send(ControlCommand::TerminateProvider(tc)).unwrap();
+                    dispatch_command(&cplane_s, ControlCommand::TerminateProvider(tc));
                 }
             } else if msg.subject.ends_with(PROVIDER_AUCTION_REQ) { // ** WARNING ** ORDER OF COMPARISON IS IMPORTANT HERE
-                let req: ProviderAuctionRequest = serde_json::from_slice(&msg.data)?;
-                if image_map.read().unwrap().contains_key(&req.provider_ref) {
+                let req: ProviderAuctionRequest = match decode_json(&msg.subject, &msg.data) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        // Any reply to an auction is a bid, so malformed requests go unanswered
+                        warn!("Ignoring malformed provider auction request: {}", e);
+                        return Ok(());
+                    }
+                };
+                if image_map.read_or_recover().contains_key(&req.provider_ref) {
                     trace!("Skipping provider auction response - provider is in local image map");
                 } else {
                     if !host_satifies_constraints(labels.clone(), &req.constraints) {
@@ -540,8 +1367,14 @@ fn spawn_controlplane_handler(
                     }
                 }
             } else if msg.subject.ends_with(AUCTION_REQ) {
-                let req: LaunchAuctionRequest = serde_json::from_slice(&msg.data)?;
-                if image_map.read().unwrap().contains_key(&req.actor_id) {
+                let req: LaunchAuctionRequest = match decode_json(&msg.subject, &msg.data) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        warn!("Ignoring malformed actor auction request: {}", e);
+                        return Ok(());
+                    }
+                };
+                if image_map.read_or_recover().contains_key(&req.actor_id) {
                     trace!("Skipping auction response - actor already running locally.");
                 } else {
                     if !host_satifies_constraints(labels.clone(), &req.constraints) {
@@ -560,12 +1393,44 @@ fn spawn_controlplane_handler(
     Ok(())
 }
 
+// Returns who requested the command carried by a control plane message (as passed to the
+// authorizer), and the command. If the host only accepts commands from trusted issuers, the
+// message must be a command signed by one of them that the host hasn't already received;
+// anything else is rejected (returning `None`) and reported as a host event
+fn command_payload(
+    issuers: &[String],
+    seen: &super::controlauth::SeenCommands,
+    msg: &Message,
+    events: &EventBroker,
+) -> Option<(String, Vec<u8>)> {
+    if issuers.is_empty() {
+        return Some((
+            crate::authz::REQUESTED_BY_CONTROL_PLANE.to_string(),
+            msg.data.clone(),
+        ));
+    }
+    match super::controlauth::verify(issuers, seen, &msg.subject, &msg.data) {
+        Ok((issuer, cmd)) => Some((
+            format!("{}:{}", crate::authz::REQUESTED_BY_CONTROL_PLANE, issuer),
+            cmd,
+        )),
+        Err(reason) => {
+            warn!("Rejected control command on {}: {}", msg.subject, reason);
+            events.publish(HostEvent::ControlCommandRejected {
+                subject: msg.subject.to_string(),
+                reason,
+            });
+            None
+        }
+    }
+}
+
 fn host_satifies_constraints(
     labels: Arc<RwLock<HashMap<String, String>>>,
     constraints: &HashMap<String, String>,
 ) -> bool {
     for (label, val) in constraints.iter() {
-        match labels.read().unwrap().get_key_value(label) {
+        match labels.read_or_recover().get_key_value(label) {
             Some((k, v)) => {
                 if v != val {
                     return false;
@@ -578,28 +1443,45 @@ fn host_satifies_constraints(
 }
 
 fn spawn_inventory_handler(
-    nc: Arc<RwLock<Option<nats::Connection>>>,
+    nc: Arc<RwLock<Option<Connection>>>,
     host_id: String,
     claims: Arc<RwLock<HashMap<String, Claims<Actor>>>>,
-    bindings: Arc<RwLock<BindingsList>>,
+    bindings: Arc<RwLock<Bindings>>,
     caps: Arc<RwLock<HashMap<RouteKey, CapabilityDescriptor>>>,
     started: SystemTime,
     labels: Arc<RwLock<HashMap<String, String>>>,
     ns: Option<String>,
-    image_map: Arc<RwLock<HashMap<String, String>>>,
+    actor_origins: Arc<RwLock<HashMap<String, Origin>>>,
+    provider_origins: Arc<RwLock<HashMap<RouteKey, Origin>>>,
+    actor_runtime: Arc<ActorRuntime>,
 ) -> Result<()> {
     let lbs = labels.clone();
     let subject = super::inventory_wildcard_subject(ns.as_ref().map(String::as_str));
 
     let _ = nc
-        .read()
-        .unwrap()
+        .read_or_recover()
         .as_ref()
         .unwrap()
         .subscribe(&subject)?
         .with_handler(move |msg| {
             trace!("Handling Inventory Request");
-            if msg.subject.contains(INVENTORY_HOSTS) {
+            // The extended subjects contain the standard ones, so they must be checked first
+            if msg.subject.ends_with(INVENTORY_ACTORS_EX) {
+                respond_with_actors_ex(
+                    msg,
+                    host_id.to_string(),
+                    crate::snapshot::take(&claims, &caps, &bindings, &lbs),
+                    actor_origins.clone(),
+                    actor_runtime.clone(),
+                )
+            } else if msg.subject.ends_with(INVENTORY_CAPABILITIES_EX) {
+                respond_with_caps_ex(
+                    msg,
+                    host_id.to_string(),
+                    crate::snapshot::take(&claims, &caps, &bindings, &lbs),
+                    provider_origins.clone(),
+                )
+            } else if msg.subject.contains(INVENTORY_HOSTS) {
                 respond_with_host(msg, host_id.to_string(), started, lbs.clone())
             } else if msg.subject.contains(INVENTORY_ACTORS) {
                 respond_with_actors(msg, host_id.to_string(), claims.clone())
@@ -617,8 +1499,107 @@ fn spawn_inventory_handler(
     Ok(())
 }
 
+fn parse_event(data: &[u8]) -> std::result::Result<Option<BusEvent>, serde_json::Error> {
+    let ce: CloudEvent = serde_json::from_slice(data)?;
+    if ce.event_type.starts_with(EXTENDED_EVENT_PREFIX) {
+        return Ok(None);
+    }
+    serde_json::from_str(&ce.data).map(Some)
+}
+
+fn parse_extended_event(
+    data: &[u8],
+) -> std::result::Result<Option<LatticeEvent>, serde_json::Error> {
+    let ce: CloudEvent = serde_json::from_slice(data)?;
+    if !ce.event_type.starts_with(EXTENDED_EVENT_PREFIX) {
+        return Ok(None);
+    }
+    serde_json::from_str(&ce.data).map(Some)
+}
+
+// Flushes published events, retrying with backoff since an event that never leaves the host
+// can't be published again later
+fn flush(nc: &Connection) -> std::io::Result<()> {
+    super::FLUSH_BACKOFF.retry("flush published event", |_| nc.flush())
+}
+
+// Extended events borrow the envelope (id, time, and so on) of a cloud event built by
+// latticeclient, replacing its type and data. Consumers that parse the data as a `BusEvent`
+// fail to and skip the event
+fn publish_extended_event(nc: &Connection, subject: &str, event: &LatticeEvent) -> Result<()> {
+    let data = serde_json::to_string(event).map_err(|e| {
+        errors::bus(BusError::SerializationFailure {
+            subject: subject.to_string(),
+            reason: e.to_string(),
+        })
+    })?;
+    let cloud_event = CloudEvent {
+        event_type: event.event_type().to_string(),
+        data,
+        ..CloudEvent::from(BusEvent::HostStarted(event.host().to_string()))
+    };
+    let payload = serde_json::to_vec(&cloud_event).map_err(|e| {
+        errors::bus(BusError::SerializationFailure {
+            subject: subject.to_string(),
+            reason: e.to_string(),
+        })
+    })?;
+    nc.publish(subject, &payload)
+        .map_err(|e| errors::from_bus_io(subject, e))
+}
+
+// Answers a command to start an actor or provider that arrived while the host is shutting down.
+// The answer isn't an acknowledgement, so the requester treats the launch as failed
+fn refuse_while_draining(cmd: ControlCommand, host_id: &str) {
+    let msg = match cmd {
+        ControlCommand::StartActor(_, msg) | ControlCommand::StartProvider(_, msg) => msg,
+        _ => return,
+    };
+    refuse_msg_while_draining(&msg, host_id);
+}
+
+// Parses the JSON of a control plane command, replying with an error (when the sender is waiting
+// for a reply) if it's malformed
+fn parse_command<T: serde::de::DeserializeOwned>(
+    msg: &Message,
+    data: &[u8],
+    host_id: &str,
+) -> Option<T> {
+    match decode_json(&msg.subject, data) {
+        Ok(cmd) => Some(cmd),
+        Err(e) => {
+            warn!("Ignoring malformed control command: {}", e);
+            if msg.reply.is_some() {
+                let rejection = serde_json::json!({ "host": host_id, "error": e.to_string() });
+                if let Err(e) = msg.respond(rejection.to_string()) {
+                    error!("Failed to send malformed command reply: {}", e);
+                }
+            }
+            None
+        }
+    }
+}
+
+fn refuse_msg_while_draining(msg: &Message, host_id: &str) {
+    info!(
+        "Refusing control command on {}, host is draining",
+        msg.subject
+    );
+    let refusal = serde_json::json!({ "host": host_id, "error": HOST_DRAINING });
+    if let Err(e) = msg.respond(refusal.to_string()) {
+        error!("Failed to send draining reply: {}", e);
+    }
+}
+
+// The control plane worker pool stops when the host shuts down, after which commands are dropped
+fn dispatch_command(cplane_s: &Sender<ControlCommand>, cmd: ControlCommand) {
+    if cplane_s.send(cmd).is_err() {
+        warn!("Control plane is no longer running, ignoring command");
+    }
+}
+
 fn respond_with_host(
-    msg: nats::Message,
+    msg: Message,
     host_id: String,
     started: SystemTime,
     labels: Arc<RwLock<HashMap<String, String>>>,
@@ -626,18 +1607,18 @@ fn respond_with_host(
     let hp = HostProfile {
         id: host_id.to_string(),
         uptime_ms: started.elapsed().unwrap_or(Duration::new(0, 0)).as_millis(),
-        labels: labels.read().unwrap().clone(),
+        labels: labels.read_or_recover().clone(),
     };
     msg.respond(serde_json::to_vec(&InventoryResponse::Host(hp)).unwrap())
 }
 
 fn respond_with_actors(
-    msg: nats::Message,
+    msg: Message,
     host: String,
     claims: Arc<RwLock<HashMap<String, Claims<Actor>>>>,
 ) -> std::result::Result<(), std::io::Error> {
     let actorlist = {
-        let lock = claims.read().unwrap();
+        let lock = claims.read_or_recover();
         lock.values().cloned().collect()
     };
     let ir = InventoryResponse::Actors {
@@ -649,18 +1630,27 @@ fn respond_with_actors(
 }
 
 fn respond_with_bindings(
-    msg: nats::Message,
+    msg: Message,
     host: String,
-    bindings: Arc<RwLock<BindingsList>>,
+    bindings: Arc<RwLock<Bindings>>,
 ) -> std::result::Result<(), std::io::Error> {
     let mut items = Vec::<Binding>::new();
-    let lock = bindings.read().unwrap();
+    let lock = bindings.read_or_recover();
     for (k, v) in lock.iter() {
+        // The inventory has no place for a binding's configuration name, so it travels with
+        // the configuration, as it does when the binding is delivered to a provider
+        let mut configuration = v.values.clone();
+        if let Some(ref name) = k.config_name {
+            configuration.insert(
+                crate::CONFIG_WASCC_CONFIG_NAME.to_string(),
+                name.to_string(),
+            );
+        }
         items.push(Binding {
-            actor: k.0.to_string(),
-            capability_id: k.1.to_string(),
-            binding_name: k.2.to_string(),
-            configuration: v.values.clone(),
+            actor: k.actor.to_string(),
+            capability_id: k.capid.to_string(),
+            binding_name: k.binding.to_string(),
+            configuration,
         });
     }
     let ir = InventoryResponse::Bindings {
@@ -672,12 +1662,12 @@ fn respond_with_bindings(
 }
 
 fn respond_with_caps(
-    msg: nats::Message,
+    msg: Message,
     host: String,
     caps: Arc<RwLock<HashMap<RouteKey, CapabilityDescriptor>>>,
 ) -> std::result::Result<(), std::io::Error> {
     let mut capabilities = vec![];
-    let lock = caps.read().unwrap();
+    let lock = caps.read_or_recover();
     // RouteKey - (binding, capid)
     for (k, v) in lock.iter() {
         let hc = HostedCapability {
@@ -691,73 +1681,528 @@ fn respond_with_caps(
         .map_err(|e| e.into())
 }
 
-// This function is invoked any time an invocation is _received_ by the message bus
-fn handle_invocation(
-    msg: &nats::Message,
-    sender: Sender<Invocation>,
-    receiver: Receiver<InvocationResponse>,
+/// Inventory subject suffix for the extended actor inventory, which includes provenance
+pub(crate) const INVENTORY_ACTORS_EX: &str = "inventory.actors_ex";
+/// Inventory subject suffix for the extended capability inventory, which includes provenance
+pub(crate) const INVENTORY_CAPABILITIES_EX: &str = "inventory.capabilities_ex";
+
+/// An actor running in a host, including the OCI image reference it was started from (if any),
+/// when it started, and how many invocations it has handled. Times are in milliseconds since
+/// the epoch, or 0 if there was no such event
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub(crate) struct ActorInventoryEntry {
+    pub subject: String,
+    pub image_ref: Option<String>,
+    pub started_at: u64,
+    #[serde(default)]
+    pub invocations: u64,
+    #[serde(default)]
+    pub errors: u64,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    #[serde(default)]
+    pub last_invocation_at: u64,
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+}
+
+/// A capability provider running in a host, including the OCI image reference it was
+/// started from (if any) and when it started (in milliseconds since the epoch)
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub(crate) struct CapabilityInventoryEntry {
+    pub capid: String,
+    pub binding_name: String,
+    pub descriptor: CapabilityDescriptor,
+    pub provider_ref: Option<String>,
+    pub started_at: u64,
+}
+
+fn epoch_millis(t: SystemTime) -> u64 {
+    t.duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn respond_with_actors_ex(
+    msg: Message,
+    host: String,
+    snapshot: HostSnapshot,
+    origins: Arc<RwLock<HashMap<String, Origin>>>,
+    runtime: Arc<ActorRuntime>,
+) -> std::result::Result<(), std::io::Error> {
+    let origins = origins.read_or_recover();
+    let actors: Vec<_> = snapshot
+        .actors
+        .iter()
+        .map(|(pk, _)| {
+            let origin = origins.get(pk);
+            let info = runtime.info(pk);
+            ActorInventoryEntry {
+                subject: pk.to_string(),
+                image_ref: origin.and_then(|o| o.image_ref.clone()),
+                started_at: origin.map_or(0, |o| epoch_millis(o.started_at)),
+                invocations: info.as_ref().map_or(0, |i| i.invocations),
+                errors: info.as_ref().map_or(0, |i| i.errors),
+                last_error: info.as_ref().and_then(|i| i.last_error.clone()),
+                last_invocation_at: info
+                    .as_ref()
+                    .and_then(|i| i.last_invocation_at)
+                    .map_or(0, epoch_millis),
+                annotations: info.map(|i| i.annotations).unwrap_or_default(),
+            }
+        })
+        .collect();
+    msg.respond(serde_json::to_vec(&serde_json::json!({ "host": host, "actors": actors })).unwrap())
+}
+
+fn respond_with_caps_ex(
+    msg: Message,
+    host: String,
+    snapshot: HostSnapshot,
+    origins: Arc<RwLock<HashMap<RouteKey, Origin>>>,
+) -> std::result::Result<(), std::io::Error> {
+    let origins = origins.read_or_recover();
+    let capabilities: Vec<_> = snapshot
+        .capabilities
+        .into_iter()
+        .map(|((binding_name, capid), v)| {
+            let origin = origins.get(&RouteKey::new(&binding_name, &capid));
+            CapabilityInventoryEntry {
+                capid,
+                binding_name,
+                descriptor: v,
+                provider_ref: origin.and_then(|o| o.image_ref.clone()),
+                started_at: origin.map_or(0, |o| epoch_millis(o.started_at)),
+            }
+        })
+        .collect();
+    msg.respond(
+        serde_json::to_vec(&serde_json::json!({ "host": host, "capabilities": capabilities }))
+            .unwrap(),
+    )
+}
+
+// Waits for the subscription's handler to end and, unless the subscription has since been
+// removed or replaced, re-subscribes with backoff. Each attempt is announced with a
+// `SubscriptionLost` host event
+fn spawn_resubscriber(
+    subject: String,
+    id: u64,
+    closed: Receiver<()>,
+    nc: Arc<RwLock<Option<Connection>>>,
+    subs: Arc<RwLock<HashMap<String, Subscribed>>>,
+    failed_subs: Arc<RwLock<HashSet<String>>>,
+    events: Arc<EventBroker>,
 ) {
-    let inv = invocation_from_msg(msg);
+    thread::spawn(move || {
+        let mut closed = closed;
+        loop {
+            // Nothing is ever sent, this returns once the handler has dropped its sender
+            let _ = closed.recv();
+            let mut attempt = 0;
+            closed = loop {
+                let resubscribe = match subs.read_or_recover().get(&subject) {
+                    Some(s) if s.id == id => s.resubscribe.clone(),
+                    _ => return, // unsubscribed intentionally
+                };
+                attempt += 1;
+                warn!(
+                    "Subscription to {} was lost, re-subscribing (attempt {})",
+                    subject, attempt
+                );
+                events.publish(HostEvent::SubscriptionLost {
+                    subject: subject.to_string(),
+                    attempt,
+                });
+                let delay = RESUBSCRIBE_BACKOFF_MS
+                    .saturating_mul(1 << (attempt - 1).min(16))
+                    .min(RESUBSCRIBE_BACKOFF_MAX_MS);
+                thread::sleep(Duration::from_millis(delay));
+                let conn = match nc.read_or_recover().as_ref().cloned() {
+                    Some(c) => c,
+                    None => return,
+                };
+                let (closed_s, closed_r) = channel::bounded(0);
+                match resubscribe(&conn, &subject, closed_s) {
+                    Ok(handler) => {
+                        let mut lock = subs.write_or_recover();
+                        match lock.get_mut(&subject) {
+                            Some(s) if s.id == id => s.handler = handler,
+                            _ => {
+                                let _ = handler.unsubscribe();
+                                return;
+                            }
+                        }
+                        failed_subs.write_or_recover().remove(&subject);
+                        info!("Re-subscribed to {}", subject);
+                        events.publish(HostEvent::SubscriptionRestored {
+                            subject: subject.to_string(),
+                        });
+                        break closed_r;
+                    }
+                    Err(e) => {
+                        error!("Failed to re-subscribe to {}: {}", subject, e);
+                        if attempt >= RESUBSCRIBE_MAX_FAILURES {
+                            failed_subs.write_or_recover().insert(subject.to_string());
+                        }
+                    }
+                }
+            };
+        }
+    });
+}
+
+// Checks the invocation's antiforgery claims, returning the response to send back (and
+// publishing a forgery event if connected) when they aren't valid
+fn check_antiforgery(
+    inv: &Invocation,
+    signer: &InvocationSigner,
+    nc: Option<&Connection>,
+    event_subject: &str,
+) -> Option<InvocationResponse> {
     //TODO: when we implement the issue, check that the invocation's origin host is not in the block list
-    if let Err(e) = inv.validate_antiforgery() {
-        error!("Invocation Antiforgery check failure: {}", e);
-        let inv_r = InvocationResponse::error(&inv, &format!("Antiforgery check failure: {}", e));
-        msg.respond(serialize(inv_r).unwrap()).unwrap();
-    // TODO: when we implement the issue, publish an antiforgery check event on wasmbus.events
+    let e = signer.validate(inv).err()?;
+    error!("Invocation Antiforgery check failure: {}", e);
+    let event = LatticeEvent::InvocationForgeryDetected {
+        origin: inv.origin.url(),
+        target: inv.target.url(),
+        operation: inv.operation.to_string(),
+        origin_host: inv.host_id.to_string(),
+        host: signer.host_id().to_string(),
+        reason: ReasonCode::AntiforgeryCheckFailed,
+    };
+    if let Some(nc) = nc {
+        if let Err(e) = publish_extended_event(nc, event_subject, &event) {
+            warn!("Failed to publish invocation forgery event: {}", e);
+        }
+    }
     // TODO: when we implement the issue, add the host origin of the invocation to the global lattice block list
+    Some(InvocationResponse::error(
+        inv,
+        &format!("Antiforgery check failure: {}", e),
+    ))
+}
+
+// This function is invoked any time an invocation is _received_ by the message bus
+fn handle_invocation(
+    msg: &Message,
+    local: &LocalSubscriber,
+    nc: &Connection,
+    deadletters: &DeadLetters,
+    signer: &InvocationSigner,
+    event_subject: &str,
+) {
+    let inv = match invocation_from_msg(msg) {
+        Ok(inv) => inv,
+        Err(e) => {
+            warn!("Dropping malformed invocation: {}", e);
+            if msg.reply.is_some() {
+                respond(
+                    msg,
+                    &InvocationResponse {
+                        msg: vec![],
+                        error: Some(e.to_string()),
+                        invocation_id: String::new(),
+                    },
+                );
+            }
+            return;
+        }
+    };
+    if let Some(inv_r) = check_antiforgery(&inv, signer, Some(nc), event_subject) {
+        respond(msg, &inv_r);
     } else {
-        if let Ok(()) = sender.send(inv) {
-            let inv_r = receiver.recv().unwrap();
-            msg.respond(serialize(inv_r).unwrap()).unwrap();
-        } else {
-            warn!("Received invocation but its destination thread is no longer running.");
+        // Answer right away when the destination thread has gone away, rather than leaving
+        // the caller to time out
+        let invocation_id = inv.id.to_string();
+        let no_subscriber = || InvocationResponse {
+            msg: vec![],
+            error: Some(super::no_subscriber_error(&msg.subject)),
+            invocation_id: invocation_id.to_string(),
+        };
+        match local.exchange(inv, None) {
+            Ok(inv_r) => respond(msg, &inv_r),
+            Err(ExchangeError::Undelivered(inv)) => {
+                warn!(
+                    "Invocation on {} is undeliverable, its destination thread is no longer running.",
+                    msg.subject
+                );
+                respond(msg, &no_subscriber());
+                let event = super::undeliverable_event(&msg.subject, &inv);
+                deadletters.report(nc, event, &inv.host_id);
+            }
+            Err(_) => {
+                warn!(
+                    "Invocation on {} went unanswered, its destination thread stopped running.",
+                    msg.subject
+                );
+                respond(msg, &no_subscriber());
+            }
         }
     }
 }
 
-fn invocation_from_msg(msg: &nats::Message) -> Invocation {
-    let i: Invocation = deserialize(&msg.data).unwrap();
-    i
+// The requester may have timed out or disconnected, in which case there's no one to tell
+fn respond(msg: &Message, inv_r: &InvocationResponse) {
+    match encode(&msg.subject, inv_r) {
+        Ok(buf) => {
+            if let Err(e) = msg.respond(buf) {
+                warn!("Failed to respond to invocation on {}: {}", msg.subject, e);
+            }
+        }
+        Err(e) => error!("{}", e),
+    }
 }
 
-fn get_credsfile() -> Option<String> {
-    std::env::var(LATTICE_CREDSFILE_KEY).ok()
+fn encode(subject: &str, item: &impl serde::Serialize) -> Result<Vec<u8>> {
+    serialize(item).map_err(|e| {
+        errors::bus(BusError::SerializationFailure {
+            subject: subject.to_string(),
+            reason: e.to_string(),
+        })
+    })
 }
 
-fn get_env(var: &str, default: &str) -> String {
-    match std::env::var(var) {
-        Ok(val) => {
-            if val.is_empty() {
-                default.to_string()
-            } else {
-                val.to_string()
-            }
-        }
-        Err(_) => default.to_string(),
-    }
+fn decode<T: serde::de::DeserializeOwned>(subject: &str, data: &[u8]) -> Result<T> {
+    deserialize(data).map_err(|e| malformed(subject, data, e))
 }
 
-fn get_connection() -> nats::Connection {
-    let host = get_env(LATTICE_HOST_KEY, DEFAULT_LATTICE_HOST);
-    info!("Lattice Host: {}", host);
-    let mut opts = if let Some(creds) = get_credsfile() {
+fn decode_json<T: serde::de::DeserializeOwned>(subject: &str, data: &[u8]) -> Result<T> {
+    serde_json::from_slice(data).map_err(|e| malformed(subject, data, e))
+}
+
+// Describes a payload that couldn't be parsed by its size and a hash, so that it can be matched
+// up with what the sender logged without logging (possibly sensitive) contents
+fn malformed(subject: &str, data: &[u8], e: impl std::fmt::Display) -> errors::Error {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    errors::bus(BusError::SerializationFailure {
+        subject: subject.to_string(),
+        reason: format!(
+            "{} ({} bytes, hash {:016x})",
+            e,
+            data.len(),
+            hasher.finish()
+        ),
+    })
+}
+
+fn invocation_from_msg(msg: &Message) -> Result<Invocation> {
+    decode(&msg.subject, &msg.data)
+}
+
+fn get_connection(options: &LatticeOptions) -> Connection {
+    info!("Lattice Host: {}", options.host);
+    let mut opts = if let Some(ref creds) = options.creds_file {
         nats::Options::with_credentials(creds)
     } else {
         nats::Options::new()
     };
     opts = opts.with_name("waSCC Lattice");
-    opts.connect(&host).unwrap()
+    opts.connect(&options.host).unwrap().into()
 }
 
-fn get_timeout() -> Duration {
-    match std::env::var(LATTICE_RPC_TIMEOUT_KEY) {
-        Ok(val) => {
-            if val.is_empty() {
-                Duration::from_millis(DEFAULT_LATTICE_RPC_TIMEOUT_MILLIS)
-            } else {
-                Duration::from_millis(val.parse().unwrap_or(DEFAULT_LATTICE_RPC_TIMEOUT_MILLIS))
+#[cfg(all(test, feature = "test-lattice"))]
+mod test {
+    use super::{
+        decode, decode_json, encode, handle_invocation, Connection, DeadLetters, LatticeOptions,
+        LocalSubscriber,
+    };
+    use crate::bus::memlattice::MemBroker;
+    use crate::events::{EventBroker, HostEvent};
+    use crate::signer::InvocationSigner;
+    use crate::{Invocation, WasccEntity};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use wascap::prelude::KeyPair;
+
+    #[test]
+    fn undeliverable_invocations_answered() {
+        let broker = MemBroker::new();
+        let nc: Connection = broker.connect().into();
+        let events = Arc::new(EventBroker::default());
+        let host_events = events.subscribe();
+        let deadletters = DeadLetters {
+            subject: crate::bus::deadletter_subject(None),
+            events,
+            limiter: Default::default(),
+        };
+        let letters = nc.subscribe("wasmbus.deadletter").unwrap();
+
+        // The subscription outlives the thread it delivers to
+        let subject = "wasmbus.actor.Ma";
+        let (inv_s, inv_r) = crossbeam_channel::unbounded();
+        let (_resp_s, resp_r) = crossbeam_channel::unbounded();
+        drop(inv_r);
+        let local = LocalSubscriber::new(inv_s, resp_r);
+        let conn = nc.clone();
+        let key = KeyPair::new_server();
+        let signer = InvocationSigner::new(&key.public_key(), &key.seed().unwrap(), false).unwrap();
+        let _handler = nc.subscribe(subject).unwrap().with_handler(move |msg| {
+            handle_invocation(&msg, &local, &conn, &deadletters, &signer, "wasmbus.events");
+            Ok(())
+        });
+
+        let inv = Invocation::test_builder()
+            .with_target(WasccEntity::Actor("Ma".to_string()))
+            .with_payload(b"secret".to_vec())
+            .build();
+        let resp = nc
+            .request_timeout(
+                subject,
+                encode(subject, &inv).unwrap(),
+                Duration::from_secs(5),
+            )
+            .unwrap();
+        let inv_r: crate::InvocationResponse = decode(subject, &resp.data).unwrap();
+        assert_eq!(Some(crate::bus::no_subscriber_error(subject)), inv_r.error);
+
+        let letter = letters.next_timeout(Duration::from_secs(1)).unwrap();
+        let letter: serde_json::Value = serde_json::from_slice(&letter.data).unwrap();
+        assert_eq!(letter["invocation_id"], inv.id.as_str());
+        assert_eq!(letter["target"], "wasmbus://Ma/TestOperation");
+        assert!(!letter.to_string().contains("secret"));
+
+        match host_events.recv_timeout(Duration::from_secs(1)).unwrap() {
+            HostEvent::InvocationUndeliverable { invocation_id, .. } => {
+                assert_eq!(inv.id, invocation_id)
+            }
+            e => panic!("unexpected event {:?}", e),
+        }
+    }
+
+    // Payloads of assorted lengths from a small xorshift generator, the same on every run
+    fn random_payloads(count: usize) -> Vec<Vec<u8>> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        (0..count)
+            .map(|i| {
+                (0..=i % 64)
+                    .map(|_| {
+                        state ^= state << 13;
+                        state ^= state >> 7;
+                        state ^= state << 17;
+                        state as u8
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn random_bytes_rejected() {
+        use latticeclient::controlplane::{
+            LaunchAuctionRequest, LaunchCommand, LaunchProviderCommand, ProviderAuctionRequest,
+            TerminateCommand, TerminateProviderCommand,
+        };
+
+        let subject = "wasmbus.control.Nxxx.launch";
+        for data in random_payloads(1_000) {
+            let size = format!("{} bytes", data.len());
+            let err = decode::<Invocation>("wasmbus.actor.Ma", &data).unwrap_err();
+            assert!(err.to_string().contains(&size), "{}", err);
+            let err = decode_json::<LaunchCommand>(subject, &data).unwrap_err();
+            assert!(err.to_string().contains(&size), "{}", err);
+            assert!(decode_json::<TerminateCommand>(subject, &data).is_err());
+            assert!(decode_json::<LaunchProviderCommand>(subject, &data).is_err());
+            assert!(decode_json::<TerminateProviderCommand>(subject, &data).is_err());
+            assert!(decode_json::<LaunchAuctionRequest>(subject, &data).is_err());
+            assert!(decode_json::<ProviderAuctionRequest>(subject, &data).is_err());
+        }
+    }
+
+    #[test]
+    fn malformed_invocations_answered() {
+        let broker = MemBroker::new();
+        let nc: Connection = broker.connect().into();
+        let deadletters = DeadLetters {
+            subject: crate::bus::deadletter_subject(None),
+            events: Arc::new(EventBroker::default()),
+            limiter: Default::default(),
+        };
+        let key = KeyPair::new_server();
+        let signer = InvocationSigner::new(&key.public_key(), &key.seed().unwrap(), false).unwrap();
+        let inv = signer.invocation(
+            WasccEntity::Actor("system".to_string()),
+            WasccEntity::Actor("Ma".to_string()),
+            "TestOperation",
+            vec![],
+        );
+
+        // A subscriber that answers every invocation it's handed
+        let subject = "wasmbus.actor.Ma";
+        let (inv_s, inv_r) = crossbeam_channel::unbounded::<Invocation>();
+        let (resp_s, resp_r) = crossbeam_channel::unbounded();
+        std::thread::spawn(move || {
+            for inv in inv_r.iter() {
+                let _ = resp_s.send(crate::InvocationResponse::success(&inv, b"ok".to_vec()));
             }
+        });
+        let local = LocalSubscriber::new(inv_s, resp_r);
+        let conn = nc.clone();
+        let _handler = nc.subscribe(subject).unwrap().with_handler(move |msg| {
+            handle_invocation(&msg, &local, &conn, &deadletters, &signer, "wasmbus.events");
+            Ok(())
+        });
+
+        for data in random_payloads(50) {
+            let resp = nc
+                .request_timeout(subject, &data, Duration::from_secs(5))
+                .unwrap();
+            let inv_r: crate::InvocationResponse = decode(subject, &resp.data).unwrap();
+            assert!(inv_r.error.is_some());
         }
-        Err(_) => Duration::from_millis(DEFAULT_LATTICE_RPC_TIMEOUT_MILLIS),
+        // Published without a reply subject, there's no one to answer
+        nc.publish(subject, b"\xff").unwrap();
+
+        let resp = nc
+            .request_timeout(
+                subject,
+                encode(subject, &inv).unwrap(),
+                Duration::from_secs(5),
+            )
+            .unwrap();
+        let inv_r: crate::InvocationResponse = decode(subject, &resp.data).unwrap();
+        assert_eq!(None, inv_r.error);
+        assert_eq!(b"ok".to_vec(), inv_r.msg);
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn options_from_vars() {
+        let options = LatticeOptions::from_vars(vars(&[
+            ("LATTICE_HOST", "nats.example.com:4222"),
+            ("LATTICE_CREDS_FILE", "/etc/wascc/host.creds"),
+            ("LATTICE_RPC_TIMEOUT_MILLIS", "1500"),
+            ("LATTICE_CONTROL_WORKERS", "8"),
+        ]));
+        assert_eq!(
+            LatticeOptions {
+                host: "nats.example.com:4222".to_string(),
+                creds_file: Some("/etc/wascc/host.creds".into()),
+                rpc_timeout: Duration::from_millis(1500),
+                control_workers: 8,
+            },
+            options
+        );
+    }
+
+    #[test]
+    fn options_default_when_unset_or_invalid() {
+        let options = LatticeOptions::from_vars(vars(&[
+            ("LATTICE_HOST", ""),
+            ("LATTICE_RPC_TIMEOUT_MILLIS", "soon"),
+            ("LATTICE_CONTROL_WORKERS", "0"),
+        ]));
+        assert_eq!(LatticeOptions::default(), options);
+        assert_eq!("127.0.0.1", options.host);
+        assert_eq!(Duration::from_millis(600), options.rpc_timeout);
+        assert_eq!(4, options.control_workers);
     }
 }
@@ -0,0 +1,570 @@
+//! An in-process stand-in for the NATS server behind the lattice, enabled with the
+//! `test-lattice` feature flag. A host built with `HostBuilder::with_mem_broker` connects to the
+//! given `MemBroker` instead of dialing `LATTICE_HOST`, so multi-host tests can run within a
+//! single process and without any external dependencies. Hosts built without a broker still
+//! connect to a NATS server, even when the feature is on, unless their lattice host is
+//! `SHARED_BROKER_HOST` (e.g. `LATTICE_HOST=memory`), in which case they all join one broker
+//! shared by the whole process. That lets a test suite run without a server.
+//!
+//! The broker follows the NATS semantics the host relies on: subjects are dot-separated tokens,
+//! subscriptions may use the `*` (exactly one token) and `>` (one or more trailing tokens)
+//! wildcards, a message published to a queue group is delivered to only one member of that
+//! group, and requests are answered through unique inbox subjects. Hosts sharing a broker are
+//! kept apart by lattice namespace exactly as they would be on a real server.
+
+use crate::locks::MutexExt;
+use latticeclient::{
+    Binding, HostProfile, HostedCapability, InventoryResponse, INVENTORY_ACTORS,
+    INVENTORY_BINDINGS, INVENTORY_CAPABILITIES, INVENTORY_HOSTS,
+};
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use wascap::jwt::{Actor, Claims};
+
+/// The lattice host (see `LatticeOptions`) that has a host built without a broker of its own
+/// join the broker shared by every such host in the process, instead of dialing a NATS server
+pub const SHARED_BROKER_HOST: &str = "memory";
+
+lazy_static::lazy_static! {
+    static ref SHARED_BROKER: MemBroker = MemBroker::new();
+}
+
+/// An in-memory message broker. Cloning a broker produces another handle to the same broker
+#[derive(Clone, Default)]
+pub struct MemBroker {
+    inner: Arc<BrokerState>,
+}
+
+#[derive(Default)]
+struct BrokerState {
+    next_id: AtomicU64,
+    subs: Mutex<HashMap<u64, SubEntry>>,
+    rotation: AtomicU64,
+}
+
+struct SubEntry {
+    conn: u64,
+    subject: String,
+    queue: Option<String>,
+    sender: crossbeam_channel::Sender<Message>,
+}
+
+impl std::fmt::Debug for MemBroker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemBroker")
+            .field("subscriptions", &self.inner.subs.lock_or_recover().len())
+            .finish()
+    }
+}
+
+impl MemBroker {
+    /// Creates a new broker, isolated from every other broker in the process
+    pub fn new() -> MemBroker {
+        MemBroker::default()
+    }
+
+    /// The broker joined by hosts whose lattice host is `SHARED_BROKER_HOST`
+    pub fn shared() -> MemBroker {
+        SHARED_BROKER.clone()
+    }
+
+    /// Removes every subscription to exactly this subject without the subscribers asking, as
+    /// a server does when it revokes a client's permissions, returning how many were removed.
+    /// Their handlers end as they would for a real connection
+    pub fn drop_subscriptions(&self, subject: &str) -> usize {
+        let mut subs = self.inner.subs.lock_or_recover();
+        let before = subs.len();
+        subs.retain(|_, s| s.subject != subject);
+        before - subs.len()
+    }
+
+    /// Opens a new connection to this broker
+    pub fn connect(&self) -> Connection {
+        Connection {
+            id: self.next_id(),
+            broker: self.clone(),
+            closed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn next_id(&self) -> u64 {
+        self.inner.next_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    fn add_sub(
+        &self,
+        conn: u64,
+        subject: &str,
+        queue: Option<&str>,
+    ) -> io::Result<(u64, crossbeam_channel::Receiver<Message>)> {
+        if !valid_subject(subject, true) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid subscription subject: {}", subject),
+            ));
+        }
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let sid = self.next_id();
+        self.inner.subs.lock_or_recover().insert(
+            sid,
+            SubEntry {
+                conn,
+                subject: subject.to_string(),
+                queue: queue.map(|q| q.to_string()),
+                sender,
+            },
+        );
+        Ok((sid, receiver))
+    }
+
+    // Dropping the entry drops its sender, which ends any handler thread reading from it
+    fn remove_sub(&self, sid: u64) {
+        self.inner.subs.lock_or_recover().remove(&sid);
+    }
+
+    fn remove_conn(&self, conn: u64) {
+        self.inner
+            .subs
+            .lock_or_recover()
+            .retain(|_, s| s.conn != conn);
+    }
+
+    fn publish(&self, subject: &str, reply: Option<&str>, data: &[u8]) -> io::Result<()> {
+        if !valid_subject(subject, false) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid publish subject: {}", subject),
+            ));
+        }
+        let msg = Message {
+            subject: subject.to_string(),
+            reply: reply.map(|r| r.to_string()),
+            data: data.to_vec(),
+            broker: self.clone(),
+        };
+        let subs = self.inner.subs.lock_or_recover();
+        let mut groups: HashMap<&str, Vec<&SubEntry>> = HashMap::new();
+        for sub in subs.values() {
+            if !subject_matches(&sub.subject, subject) {
+                continue;
+            }
+            match sub.queue {
+                Some(ref q) => groups.entry(q.as_str()).or_default().push(sub),
+                None => {
+                    let _ = sub.sender.send(msg.clone());
+                }
+            }
+        }
+        // Each queue group gets one copy, spread across its members in turn
+        for members in groups.values() {
+            let turn = self.inner.rotation.fetch_add(1, Ordering::SeqCst) as usize;
+            let _ = members[turn % members.len()].sender.send(msg.clone());
+        }
+        Ok(())
+    }
+}
+
+/// A connection to a `MemBroker`, mirroring the parts of `nats::Connection` used by the lattice.
+/// Clones share the connection, and closing any of them closes it for all
+#[derive(Clone, Debug)]
+pub struct Connection {
+    id: u64,
+    broker: MemBroker,
+    closed: Arc<AtomicBool>,
+}
+
+impl Connection {
+    pub fn subscribe(&self, subject: &str) -> io::Result<Subscription> {
+        self.check_open()?;
+        let (sid, receiver) = self.broker.add_sub(self.id, subject, None)?;
+        Ok(Subscription::new(sid, receiver, self.broker.clone()))
+    }
+
+    pub fn queue_subscribe(&self, subject: &str, queue: &str) -> io::Result<Subscription> {
+        self.check_open()?;
+        let (sid, receiver) = self.broker.add_sub(self.id, subject, Some(queue))?;
+        Ok(Subscription::new(sid, receiver, self.broker.clone()))
+    }
+
+    pub fn publish(&self, subject: &str, msg: impl AsRef<[u8]>) -> io::Result<()> {
+        self.check_open()?;
+        self.broker.publish(subject, None, msg.as_ref())
+    }
+
+    pub fn publish_request(
+        &self,
+        subject: &str,
+        reply: &str,
+        msg: impl AsRef<[u8]>,
+    ) -> io::Result<()> {
+        self.check_open()?;
+        self.broker.publish(subject, Some(reply), msg.as_ref())
+    }
+
+    /// Publishes a request and waits for the first reply, failing with `TimedOut` if nothing
+    /// replies in time (including when nothing is subscribed to the subject)
+    pub fn request_timeout(
+        &self,
+        subject: &str,
+        msg: impl AsRef<[u8]>,
+        timeout: Duration,
+    ) -> io::Result<Message> {
+        let inbox = self.new_inbox();
+        let sub = self.subscribe(&inbox)?;
+        self.publish_request(subject, &inbox, msg)?;
+        let res = sub.next_timeout(timeout);
+        let _ = sub.unsubscribe();
+        res
+    }
+
+    pub fn new_inbox(&self) -> String {
+        format!("_INBOX.{}.{}", self.id, self.broker.next_id())
+    }
+
+    /// Messages are delivered as soon as they're published, so there is never anything to flush
+    pub fn flush(&self) -> io::Result<()> {
+        self.check_open()
+    }
+
+    /// Closes the connection, removing all of its subscriptions
+    pub fn close(self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.broker.remove_conn(self.id);
+    }
+
+    fn check_open(&self) -> io::Result<()> {
+        if self.closed.load(Ordering::SeqCst) {
+            Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Connection to the in-memory broker is closed",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A subscription to a subject. Dropping the subscription unsubscribes it, unless it has been
+/// handed to a handler with `with_handler`
+#[derive(Debug)]
+pub struct Subscription {
+    sid: Option<u64>,
+    receiver: crossbeam_channel::Receiver<Message>,
+    broker: MemBroker,
+}
+
+impl Subscription {
+    fn new(sid: u64, receiver: crossbeam_channel::Receiver<Message>, broker: MemBroker) -> Self {
+        Subscription {
+            sid: Some(sid),
+            receiver,
+            broker,
+        }
+    }
+
+    pub fn next_timeout(&self, timeout: Duration) -> io::Result<Message> {
+        self.receiver.recv_timeout(timeout).map_err(|e| match e {
+            crossbeam_channel::RecvTimeoutError::Timeout => {
+                io::Error::new(io::ErrorKind::TimedOut, "next_timeout: timed out")
+            }
+            crossbeam_channel::RecvTimeoutError::Disconnected => io::Error::new(
+                io::ErrorKind::NotConnected,
+                "next_timeout: subscription closed",
+            ),
+        })
+    }
+
+    pub fn unsubscribe(mut self) -> io::Result<()> {
+        if let Some(sid) = self.sid.take() {
+            self.broker.remove_sub(sid);
+        }
+        Ok(())
+    }
+
+    /// Processes each message on a background thread with the given function until the
+    /// subscription is unsubscribed or its connection is closed. Dropping the returned handler
+    /// leaves the subscription in place
+    pub fn with_handler<F>(mut self, handler: F) -> Handler
+    where
+        F: Fn(Message) -> io::Result<()> + Send + 'static,
+    {
+        let sid = self.sid.take();
+        let receiver = self.receiver.clone();
+        std::thread::spawn(move || {
+            for msg in receiver.iter() {
+                let subject = msg.subject.clone();
+                if let Err(e) = handler(msg) {
+                    error!("Error in handler for message on {}: {}", subject, e);
+                }
+            }
+        });
+        Handler {
+            sid,
+            broker: self.broker.clone(),
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(sid) = self.sid.take() {
+            self.broker.remove_sub(sid);
+        }
+    }
+}
+
+/// A subscription whose messages are being processed by a handler function
+#[derive(Debug)]
+pub struct Handler {
+    sid: Option<u64>,
+    broker: MemBroker,
+}
+
+impl Handler {
+    pub fn unsubscribe(self) -> io::Result<()> {
+        if let Some(sid) = self.sid {
+            self.broker.remove_sub(sid);
+        }
+        Ok(())
+    }
+}
+
+/// A message received from a `MemBroker`
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub subject: String,
+    pub reply: Option<String>,
+    pub data: Vec<u8>,
+    broker: MemBroker,
+}
+
+impl Message {
+    /// Publishes a reply to the message's reply subject
+    pub fn respond(&self, msg: impl AsRef<[u8]>) -> io::Result<()> {
+        match self.reply {
+            Some(ref reply) => self.broker.publish(reply, None, msg.as_ref()),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No reply subject to reply to",
+            )),
+        }
+    }
+}
+
+/// Queries lattice inventory over a `MemBroker` connection, mirroring the parts of
+/// `latticeclient::Client` used by the host and its tests. Like the real client, each query
+/// collects responses from every host until the timeout elapses
+pub struct Client {
+    conn: Connection,
+    timeout: Duration,
+    ns: Option<String>,
+}
+
+type ClientResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+impl Client {
+    pub fn with_connection(conn: Connection, timeout: Duration, ns: Option<String>) -> Client {
+        Client { conn, timeout, ns }
+    }
+
+    pub fn get_hosts(&self) -> ClientResult<Vec<HostProfile>> {
+        Ok(self
+            .gather(INVENTORY_HOSTS)?
+            .into_iter()
+            .filter_map(|ir| match ir {
+                InventoryResponse::Host(h) => Some(h),
+                _ => None,
+            })
+            .collect())
+    }
+
+    pub fn get_actors(&self) -> ClientResult<HashMap<String, Vec<Claims<Actor>>>> {
+        Ok(self
+            .gather(INVENTORY_ACTORS)?
+            .into_iter()
+            .filter_map(|ir| match ir {
+                InventoryResponse::Actors { host, actors } => Some((host, actors)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    pub fn get_capabilities(&self) -> ClientResult<HashMap<String, Vec<HostedCapability>>> {
+        Ok(self
+            .gather(INVENTORY_CAPABILITIES)?
+            .into_iter()
+            .filter_map(|ir| match ir {
+                InventoryResponse::Capabilities { host, capabilities } => {
+                    Some((host, capabilities))
+                }
+                _ => None,
+            })
+            .collect())
+    }
+
+    pub fn get_bindings(&self) -> ClientResult<HashMap<String, Vec<Binding>>> {
+        Ok(self
+            .gather(INVENTORY_BINDINGS)?
+            .into_iter()
+            .filter_map(|ir| match ir {
+                InventoryResponse::Bindings { host, bindings } => Some((host, bindings)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    fn gather(&self, suffix: &str) -> ClientResult<Vec<InventoryResponse>> {
+        let subject = format!(
+            "{}.{}",
+            super::nsprefix(self.ns.as_ref().map(String::as_str)),
+            suffix
+        );
+        let inbox = self.conn.new_inbox();
+        let sub = self.conn.subscribe(&inbox)?;
+        self.conn.publish_request(&subject, &inbox, b"")?;
+
+        let deadline = Instant::now() + self.timeout;
+        let mut responses = Vec::new();
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            match sub.next_timeout(deadline - now) {
+                Ok(msg) => match serde_json::from_slice(&msg.data) {
+                    Ok(ir) => responses.push(ir),
+                    Err(e) => warn!("Ignoring malformed inventory response: {}", e),
+                },
+                Err(_) => break,
+            }
+        }
+        let _ = sub.unsubscribe();
+        Ok(responses)
+    }
+}
+
+// Publish subjects must be literal, subscription subjects may contain wildcards, with `>`
+// only allowed as the last token
+fn valid_subject(subject: &str, wildcards: bool) -> bool {
+    let tokens: Vec<_> = subject.split('.').collect();
+    !subject.is_empty()
+        && !subject.chars().any(char::is_whitespace)
+        && tokens.iter().enumerate().all(|(i, t)| match *t {
+            "" => false,
+            "*" => wildcards,
+            ">" => wildcards && i == tokens.len() - 1,
+            _ => true,
+        })
+}
+
+fn subject_matches(pattern: &str, subject: &str) -> bool {
+    let mut subject_tokens = subject.split('.');
+    for p in pattern.split('.') {
+        match (p, subject_tokens.next()) {
+            (">", Some(_)) => return true,
+            (_, None) => return false,
+            ("*", Some(_)) => {}
+            (p, Some(s)) if p == s => {}
+            _ => return false,
+        }
+    }
+    subject_tokens.next().is_none()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{subject_matches, MemBroker};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn wildcards_match_tokens() {
+        assert!(subject_matches(
+            "wasmbus.inventory.*",
+            "wasmbus.inventory.actors"
+        ));
+        assert!(!subject_matches("wasmbus.inventory.*", "wasmbus.inventory"));
+        assert!(!subject_matches(
+            "wasmbus.inventory.*",
+            "wasmbus.inventory.a.b"
+        ));
+        assert!(subject_matches(
+            "wasmbus.control.>",
+            "wasmbus.control.Nx.launch"
+        ));
+        assert!(!subject_matches("wasmbus.control.>", "wasmbus.control"));
+        assert!(subject_matches("wasmbus.events", "wasmbus.events"));
+        assert!(!subject_matches("wasmbus.events", "ns.wasmbus.events"));
+    }
+
+    #[test]
+    fn queue_group_delivers_once() {
+        let broker = MemBroker::new();
+        let nc = broker.connect();
+        let count = Arc::new(AtomicUsize::new(0));
+        let mut handlers = Vec::new();
+        for _ in 0..3 {
+            let count = count.clone();
+            let sub = nc
+                .queue_subscribe("wasmbus.actor.Ma", "wasmbus.actor.Ma")
+                .unwrap();
+            handlers.push(sub.with_handler(move |msg| {
+                count.fetch_add(1, Ordering::SeqCst);
+                msg.respond("hi")
+            }));
+        }
+        let plain = nc.subscribe("wasmbus.actor.*").unwrap();
+
+        let resp = nc
+            .request_timeout("wasmbus.actor.Ma", "hello", Duration::from_millis(500))
+            .unwrap();
+        assert_eq!(b"hi".to_vec(), resp.data);
+        assert_eq!(1, count.load(Ordering::SeqCst));
+        assert_eq!(
+            b"hello".to_vec(),
+            plain.next_timeout(Duration::from_millis(100)).unwrap().data
+        );
+    }
+
+    #[test]
+    fn requests_time_out_without_responders() {
+        let broker = MemBroker::new();
+        let nc = broker.connect();
+        let err = nc
+            .request_timeout("wasmbus.actor.Mnobody", "", Duration::from_millis(50))
+            .err()
+            .unwrap();
+        assert_eq!(std::io::ErrorKind::TimedOut, err.kind());
+    }
+
+    #[test]
+    fn close_removes_subscriptions() {
+        let broker = MemBroker::new();
+        let nc1 = broker.connect();
+        let nc2 = broker.connect();
+        let _ = nc1
+            .subscribe("wasmbus.events")
+            .unwrap()
+            .with_handler(|_| Ok(()));
+        let sub = nc2.subscribe("wasmbus.events").unwrap();
+        nc1.clone().close();
+        assert!(nc1.publish("wasmbus.events", "x").is_err());
+
+        nc2.publish("wasmbus.events", "x").unwrap();
+        assert!(sub.next_timeout(Duration::from_millis(100)).is_ok());
+        assert_eq!(1, broker.inner.subs.lock().unwrap().len());
+    }
+
+    #[test]
+    fn brokers_isolated() {
+        let b1 = MemBroker::new();
+        let b2 = MemBroker::new();
+        let sub = b1.connect().subscribe("wasmbus.events").unwrap();
+        b2.connect().publish("wasmbus.events", "x").unwrap();
+        assert!(sub.next_timeout(Duration::from_millis(50)).is_err());
+    }
+}
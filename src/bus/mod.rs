@@ -1,23 +1,106 @@
-#[cfg(feature = "lattice")]
-use crossbeam::Sender;
+use crate::locks::MutexExt;
+use crossbeam::{Receiver, Sender};
+use crossbeam_channel::RecvTimeoutError;
 
 pub const URL_SCHEME: &str = "wasmbus";
 
+// Ordering
+//
+// Each capability provider instance subscribes to a root subject, shared with the provider's
+// other instances in the lattice, on which it receives health requests and other calls that any
+// instance can answer. Its root thread also takes every message on the provider's configuration
+// subject, which is broadcast to all instances, so that each one receives the configuration
+// operations (OP_BIND_ACTOR and OP_REMOVE_ACTOR). Once an actor is bound to it, the instance also
+// subscribes to a bound subject for that actor, on which it receives the actor's calls. The
+// root and bound subjects are independent, so the bus itself doesn't order one against the
+// other. The host does, for each (actor, capid, binding):
+//
+// 1. The actor's calls are handled one at a time, in the order they're received on the bound
+//    subject.
+// 2. A configuration operation for an actor that's already bound is handed from the root
+//    subject's thread to the bound subject's, and handled there between the actor's calls, never
+//    alongside one. A call made once the operation has been answered (e.g. once
+//    `Host::update_binding_values` has returned) observes the new configuration, and no call
+//    observes a configuration partway through being applied.
+// 3. An actor's first binding is handled on the root subject, and the bound subject is only
+//    subscribed once the provider has accepted it, so no call precedes it.
+//
+// As a consequence, a provider's configuration handler mustn't wait on a call from the same
+// actor to the same provider instance, which would be queued behind the handler itself.
+
+// The error carried by the response to an invocation that arrived for a subscriber that is no
+// longer running. Callers on other hosts turn such a response into `BusError::NoResponders`
+#[cfg(feature = "lattice")]
+pub(crate) const NO_SUBSCRIBER_ERROR: &str = "No running subscriber for invocation";
+
+// The full error for an invocation on the subject. Callers compare the whole error, so that a
+// subscriber's own error can't be mistaken for it, nor can one for a different subject
+#[cfg(feature = "lattice")]
+pub(crate) fn no_subscriber_error(subject: &str) -> String {
+    format!("{} on {}", NO_SUBSCRIBER_ERROR, subject)
+}
+
+// At most this many undeliverable invocations are reported (as host events and, in a lattice,
+// on the dead-letter subject) per second. The rest are only counted, so that a flood of
+// invocations for a subscriber that has gone away can't become a flood of reports
+const DEADLETTER_MAX_PER_SEC: u32 = 10;
+
+// Re-establishing a binding when a provider loads: every provider instance in a large lattice
+// rebinds at once, so each rebind is retried with jittered backoff before it's reported as failed
+#[cfg(feature = "lattice")]
+pub(crate) const REBIND_BACKOFF: Backoff = Backoff {
+    initial: Duration::from_millis(100),
+    max: Duration::from_millis(1_000),
+    max_attempts: 4,
+};
+
+// The longest a provider instance waits before re-establishing each of its bindings, and how
+// many it re-establishes at a time
+#[cfg(feature = "lattice")]
+pub(crate) const REBIND_STAGGER: Duration = Duration::from_millis(250);
+#[cfg(feature = "lattice")]
+pub(crate) const REBIND_CONCURRENCY: usize = 4;
+
+// Flushing a published event to the lattice
+#[cfg(feature = "lattice")]
+pub(crate) const FLUSH_BACKOFF: Backoff = Backoff {
+    initial: Duration::from_millis(10),
+    max: Duration::from_millis(100),
+    max_attempts: 3,
+};
+
+use crate::events::HostEvent;
+use crate::{Invocation, InvocationResponse};
 #[cfg(feature = "lattice")]
-use crate::{BindingsList, RouteKey};
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "lattice")]
+use crate::{bindings::Bindings, RouteKey};
 #[cfg(feature = "lattice")]
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 #[cfg(feature = "lattice")]
-use std::sync::{Arc, RwLock};
+use std::sync::RwLock;
 #[cfg(feature = "lattice")]
 use wascap::jwt::{Actor, Claims};
 #[cfg(feature = "lattice")]
 use wascc_codec::capabilities::CapabilityDescriptor;
 
+#[cfg(feature = "lattice")]
+pub(crate) mod controlauth;
 #[cfg(not(feature = "lattice"))]
 pub(crate) mod inproc;
 #[cfg(feature = "lattice")]
 pub(crate) mod lattice;
+#[cfg(feature = "test-lattice")]
+pub mod memlattice;
+#[cfg(feature = "lattice")]
+mod remoteclaims;
+#[cfg(feature = "lattice")]
+pub(crate) mod transport;
 
 #[cfg(not(feature = "lattice"))]
 pub(crate) use inproc::InprocBus as MessageBus;
@@ -26,8 +109,8 @@ pub(crate) use inproc::InprocBus as MessageBus;
 pub(crate) use lattice::DistributedBus as MessageBus;
 
 #[cfg(not(feature = "lattice"))]
-pub(crate) fn new() -> MessageBus {
-    inproc::InprocBus::new()
+pub(crate) fn new(stats: Arc<crate::stats::StatsCounters>) -> MessageBus {
+    inproc::InprocBus::new(stats)
 }
 
 #[cfg(feature = "lattice")]
@@ -35,13 +118,23 @@ pub(crate) fn new(
     host_id: String,
     claims: Arc<RwLock<HashMap<String, Claims<Actor>>>>,
     caps: Arc<RwLock<HashMap<RouteKey, CapabilityDescriptor>>>,
-    bindings: Arc<RwLock<BindingsList>>,
+    bindings: Arc<RwLock<Bindings>>,
     labels: Arc<RwLock<HashMap<String, String>>>,
     terminators: Arc<RwLock<HashMap<String, Sender<bool>>>>,
     ns: Option<String>,
     cplane_s: Sender<lattice::ControlCommand>,
     authz: Arc<RwLock<Box<dyn crate::authz::Authorizer>>>,
     image_map: Arc<RwLock<HashMap<String, String>>>,
+    actor_origins: Arc<RwLock<HashMap<String, crate::inthost::Origin>>>,
+    provider_origins: Arc<RwLock<HashMap<RouteKey, crate::inthost::Origin>>>,
+    actor_runtime: Arc<crate::actorinfo::ActorRuntime>,
+    started: std::time::SystemTime,
+    signer: Arc<crate::signer::InvocationSigner>,
+    options: lattice::LatticeOptions,
+    config: lattice::LatticeConfig,
+    control_issuers: Vec<String>,
+    conn: Option<lattice::Connection>,
+    stats: Arc<crate::stats::StatsCounters>,
 ) -> MessageBus {
     lattice::DistributedBus::new(
         host_id,
@@ -54,6 +147,16 @@ pub(crate) fn new(
         cplane_s,
         authz,
         image_map,
+        actor_origins,
+        provider_origins,
+        actor_runtime,
+        started,
+        signer,
+        options,
+        config,
+        control_issuers,
+        conn,
+        stats,
     )
 }
 
@@ -63,6 +166,64 @@ pub(crate) fn get_namespace_prefix() -> Option<String> {
     ::std::env::var(LATTICE_NAMESPACE_ENV).ok()
 }
 
+/// How the invocations published on an actor's subject are shared among the instances of that
+/// actor running in a lattice, chosen with `Host::add_actor_with_delivery`. A host without a
+/// lattice only ever runs one instance of an actor, so there the mode has no effect.
+///
+/// Whatever the mode, an actor's bindings are lattice-wide. When an instance is removed, the
+/// bindings are only removed if no instance of the actor remains anywhere in the lattice (as
+/// counted by the lattice's instance count), not if none remains in its group or mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeliveryMode {
+    /// All instances share one queue group, so each invocation is handled by exactly one of
+    /// them. This suits stateless actors, and is the default
+    QueueGroup,
+    /// Every instance handles every invocation, e.g. so that an actor keeping per-host state
+    /// sees every message. The caller receives whichever response arrives first
+    Broadcast,
+    /// Instances naming the same group share its invocations, and every group (including the
+    /// default queue group) receives each invocation
+    NamedGroup(String),
+}
+
+impl Default for DeliveryMode {
+    fn default() -> Self {
+        DeliveryMode::QueueGroup
+    }
+}
+
+/// Rejects named queue groups that the message broker can't accept: empty names and names
+/// containing whitespace
+pub(crate) fn validate_delivery_mode(mode: &DeliveryMode) -> crate::Result<()> {
+    match mode {
+        DeliveryMode::NamedGroup(group)
+            if group.is_empty() || group.contains(char::is_whitespace) =>
+        {
+            Err(crate::errors::new(crate::errors::ErrorKind::MiscHost(
+                format!("Invalid queue group name '{}'", group),
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Rejects subjects that cannot be delivered: empty subjects, subjects containing whitespace,
+/// and subjects with empty tokens (e.g. `wasmbus..actor`)
+pub(crate) fn validate_subject(subject: &str) -> crate::Result<()> {
+    if subject.is_empty()
+        || subject.chars().any(char::is_whitespace)
+        || subject.split('.').any(str::is_empty)
+    {
+        Err(crate::errors::bus(
+            crate::errors::BusError::SubjectInvalid {
+                subject: subject.to_string(),
+            },
+        ))
+    } else {
+        Ok(())
+    }
+}
+
 pub(crate) fn actor_subject(ns: Option<&str>, actor: &str) -> String {
     format!("{}.actor.{}", nsprefix(ns), actor)
 }
@@ -76,6 +237,17 @@ pub(crate) fn provider_subject(ns: Option<&str>, capid: &str, binding: &str) ->
     )
 }
 
+// The subject every instance of a provider receives configuration operations on, kept apart
+// from the root subject so that only those operations are broadcast
+pub(crate) fn provider_config_subject(ns: Option<&str>, capid: &str, binding: &str) -> String {
+    format!(
+        "{}.config.{}.{}",
+        nsprefix(ns),
+        normalize_capid(capid),
+        binding
+    )
+}
+
 pub(crate) fn inventory_wildcard_subject(ns: Option<&str>) -> String {
     format!("{}.inventory.*", nsprefix(ns))
 }
@@ -84,6 +256,244 @@ pub(crate) fn event_subject(ns: Option<&str>) -> String {
     format!("{}.events", nsprefix(ns))
 }
 
+#[cfg(feature = "lattice")]
+pub(crate) fn deadletter_subject(ns: Option<&str>) -> String {
+    format!("{}.deadletter", nsprefix(ns))
+}
+
+// Describes an invocation that could not be delivered, without its payload
+pub(crate) fn undeliverable_event(subject: &str, inv: &Invocation) -> HostEvent {
+    HostEvent::InvocationUndeliverable {
+        subject: subject.to_string(),
+        invocation_id: inv.id.to_string(),
+        origin: inv.origin_url(),
+        target: inv.target_url(),
+        operation: inv.operation.to_string(),
+    }
+}
+
+/// Why an invocation handed to a `LocalSubscriber` went unanswered
+#[derive(Debug)]
+pub(crate) enum ExchangeError {
+    /// No response arrived before the timeout elapsed
+    Timeout,
+    /// The subscriber is no longer running, and never received the invocation, which is
+    /// returned
+    Undelivered(Invocation),
+    /// The subscriber received the invocation, but stopped running before answering it
+    Unanswered,
+    /// Too many invocations were already waiting on the subscriber, so this one wasn't sent
+    Saturated,
+}
+
+/// The channels of a subscriber running in this host. Invocations are handed over one at a
+/// time, however they arrived, so that each response goes to the caller whose invocation it
+/// answers. A response that arrives after its caller stopped waiting is recognized by its
+/// invocation ID and discarded by the next caller, so a slow or hung subscriber holds up each
+/// caller no longer than that caller's own timeout
+#[derive(Clone)]
+pub(crate) struct LocalSubscriber {
+    sender: Sender<Invocation>,
+    receiver: Receiver<InvocationResponse>,
+    // Holds a single token, taken for the length of an exchange
+    turn: (Sender<()>, Receiver<()>),
+    // Callers in the middle of an exchange, including any waiting for their turn
+    waiting: Arc<AtomicUsize>,
+}
+
+impl LocalSubscriber {
+    pub(crate) fn new(sender: Sender<Invocation>, receiver: Receiver<InvocationResponse>) -> Self {
+        let turn = crossbeam_channel::bounded(1);
+        let _ = turn.0.send(());
+        LocalSubscriber {
+            sender,
+            receiver,
+            turn,
+            waiting: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Hands the invocation to the subscriber and waits for its response, for at most the
+    /// timeout if there is one
+    pub(crate) fn exchange(
+        &self,
+        inv: Invocation,
+        timeout: Option<Duration>,
+    ) -> std::result::Result<InvocationResponse, ExchangeError> {
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        self.finish_exchange(inv, timeout)
+    }
+
+    /// Like `exchange`, but fails with `ExchangeError::Saturated` rather than waiting if
+    /// `max_pending` invocations are already waiting on the subscriber. Checking the count and
+    /// joining the callers waiting is a single atomic step, so no more than `max_pending`
+    /// concurrent callers can get past the check
+    pub(crate) fn try_exchange(
+        &self,
+        inv: Invocation,
+        timeout: Option<Duration>,
+        max_pending: usize,
+    ) -> std::result::Result<InvocationResponse, ExchangeError> {
+        let queued = self.sender.len();
+        if self.waiting.fetch_add(1, Ordering::SeqCst) + queued >= max_pending {
+            self.waiting.fetch_sub(1, Ordering::SeqCst);
+            return Err(ExchangeError::Saturated);
+        }
+        self.finish_exchange(inv, timeout)
+    }
+
+    // Completes an exchange for a caller already counted as waiting
+    fn finish_exchange(
+        &self,
+        inv: Invocation,
+        timeout: Option<Duration>,
+    ) -> std::result::Result<InvocationResponse, ExchangeError> {
+        let res = self.take_turn(inv, timeout);
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+        res
+    }
+
+    fn take_turn(
+        &self,
+        inv: Invocation,
+        timeout: Option<Duration>,
+    ) -> std::result::Result<InvocationResponse, ExchangeError> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        // Both ends of the turn are held here, so it can only time out
+        if recv_until(&self.turn.1, deadline).is_err() {
+            return Err(ExchangeError::Timeout);
+        }
+        let id = inv.id.to_string();
+        let res = match self.sender.send(inv) {
+            Ok(_) => loop {
+                match recv_until(&self.receiver, deadline) {
+                    Ok(inv_r) if inv_r.invocation_id != id => trace!(
+                        "Discarding late response to invocation {}",
+                        inv_r.invocation_id
+                    ),
+                    Ok(inv_r) => break Ok(inv_r),
+                    Err(RecvTimeoutError::Timeout) => break Err(ExchangeError::Timeout),
+                    Err(RecvTimeoutError::Disconnected) => break Err(ExchangeError::Unanswered),
+                }
+            },
+            Err(e) => Err(ExchangeError::Undelivered(e.into_inner())),
+        };
+        let _ = self.turn.0.send(());
+        res
+    }
+}
+
+fn recv_until<T>(
+    r: &Receiver<T>,
+    deadline: Option<Instant>,
+) -> std::result::Result<T, RecvTimeoutError> {
+    match deadline {
+        Some(d) => r.recv_timeout(d.saturating_duration_since(Instant::now())),
+        None => r.recv().map_err(|_| RecvTimeoutError::Disconnected),
+    }
+}
+
+/// Limits how often undeliverable invocations are reported
+#[derive(Default)]
+pub(crate) struct DeadLetterLimiter {
+    // The start of the current one-second window and the number of reports requested in it
+    window: Mutex<Option<(Instant, u32)>>,
+}
+
+impl DeadLetterLimiter {
+    /// Indicates whether another undeliverable invocation may be reported now
+    pub(crate) fn admit(&self) -> bool {
+        let mut window = self.window.lock_or_recover();
+        let now = Instant::now();
+        match *window {
+            Some((start, ref mut count)) if now.duration_since(start) < Duration::from_secs(1) => {
+                *count += 1;
+                *count <= DEADLETTER_MAX_PER_SEC
+            }
+            prev => {
+                if let Some((_, count)) = prev {
+                    if count > DEADLETTER_MAX_PER_SEC {
+                        warn!(
+                            "Suppressed {} reports of undeliverable invocations",
+                            count - DEADLETTER_MAX_PER_SEC
+                        );
+                    }
+                }
+                *window = Some((now, 1));
+                true
+            }
+        }
+    }
+}
+
+/// Retries bus operations that may fail transiently, waiting between attempts for a delay
+/// that doubles from `initial` up to `max`. Each delay is jittered (to between half and all of
+/// it) so that many hosts retrying at once don't stay in lockstep
+#[cfg(feature = "lattice")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    /// The number of attempts made, including the first, before giving up
+    pub max_attempts: u32,
+}
+
+#[cfg(feature = "lattice")]
+impl Backoff {
+    /// The un-jittered delay after the given (1-based) failed attempt
+    pub(crate) fn base_delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32 << attempt.saturating_sub(1).min(16);
+        self.initial
+            .checked_mul(factor)
+            .unwrap_or(self.max)
+            .min(self.max)
+    }
+
+    /// The delay to wait after the given (1-based) failed attempt
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        jitter(self.base_delay(attempt), rand::random::<f64>())
+    }
+
+    /// Calls `op` with the (1-based) attempt number until it succeeds or `max_attempts` have
+    /// failed, in which case the last error is returned. `what` names the operation in logs
+    pub(crate) fn retry<T, E, F>(&self, what: &str, mut op: F) -> std::result::Result<T, E>
+    where
+        E: Display,
+        F: FnMut(u32) -> std::result::Result<T, E>,
+    {
+        let mut attempt = 1;
+        loop {
+            match op(attempt) {
+                Ok(t) => return Ok(t),
+                Err(e) if attempt >= self.max_attempts => return Err(e),
+                Err(e) => {
+                    let delay = self.delay(attempt);
+                    warn!(
+                        "Failed to {} (attempt {} of {}), retrying in {:?}: {}",
+                        what, attempt, self.max_attempts, delay, e
+                    );
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// A random delay of up to `max`, to spread out work that many hosts would otherwise start at
+/// the same moment
+#[cfg(feature = "lattice")]
+pub(crate) fn stagger(max: Duration) -> Duration {
+    max.mul_f64(rand::random::<f64>())
+}
+
+// Scales a delay to between half and all of itself, given a random number in [0, 1)
+#[cfg(feature = "lattice")]
+fn jitter(delay: Duration, random: f64) -> Duration {
+    let half = delay / 2;
+    half + half.mul_f64(random.max(0.0).min(1.0))
+}
+
 // By convention most of the waSCC ecosystem uses a "group:item" string
 // for the capability IDs, e.g. "wascc:messaging" or "gpio:relay". To
 // accommodate message broker subjects that might not work with the ":"
@@ -113,3 +523,70 @@ pub(crate) fn nsprefix(ns: Option<&str>) -> String {
         None => "wasmbus".to_string(),
     }
 }
+
+#[cfg(test)]
+#[cfg(feature = "lattice")]
+mod test {
+    use super::{jitter, Backoff};
+    use std::time::Duration;
+
+    fn backoff(max_attempts: u32) -> Backoff {
+        Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_millis(1_000),
+            max_attempts,
+        }
+    }
+
+    #[test]
+    fn base_delay_doubles_up_to_max() {
+        let b = backoff(10);
+        assert_eq!(Duration::from_millis(100), b.base_delay(1));
+        assert_eq!(Duration::from_millis(200), b.base_delay(2));
+        assert_eq!(Duration::from_millis(400), b.base_delay(3));
+        assert_eq!(Duration::from_millis(800), b.base_delay(4));
+        assert_eq!(Duration::from_millis(1_000), b.base_delay(5));
+        assert_eq!(Duration::from_millis(1_000), b.base_delay(u32::MAX));
+    }
+
+    #[test]
+    fn jitter_stays_within_half_and_whole_delay() {
+        let delay = Duration::from_millis(400);
+        assert_eq!(Duration::from_millis(200), jitter(delay, 0.0));
+        assert_eq!(Duration::from_millis(300), jitter(delay, 0.5));
+        assert_eq!(delay, jitter(delay, 1.0));
+        for attempt in 1..8 {
+            let b = backoff(10);
+            let d = b.delay(attempt);
+            assert!(d >= b.base_delay(attempt) / 2 && d <= b.base_delay(attempt));
+        }
+    }
+
+    #[test]
+    fn retry_stops_at_success_or_max_attempts() {
+        let b = Backoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(2),
+            max_attempts: 3,
+        };
+        let mut calls = 0;
+        let r: Result<u32, String> = b.retry("succeed", |attempt| {
+            calls += 1;
+            if attempt < 2 {
+                Err("not yet".to_string())
+            } else {
+                Ok(attempt)
+            }
+        });
+        assert_eq!(Ok(2), r);
+        assert_eq!(2, calls);
+
+        calls = 0;
+        let r: Result<(), String> = b.retry("fail", |attempt| {
+            calls += 1;
+            Err(format!("failure {}", attempt))
+        });
+        assert_eq!(Err("failure 3".to_string()), r);
+        assert_eq!(3, calls);
+    }
+}
@@ -0,0 +1,164 @@
+// A bounded cache of the claims of actors discovered elsewhere in the lattice. The host's own
+// claims map only ever holds the actors it hosts; claims looked up in the lattice to authorize
+// bindings to remote actors are kept here instead, for at most the configured time, and the
+// least recently used entries are evicted once the cache is full. The host checks cached claims
+// again each time it uses them, removing any that have since expired or been revoked.
+
+use crate::locks::MutexExt;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use wascap::jwt::{Actor, Claims};
+
+pub(crate) const DEFAULT_CAPACITY: usize = 256;
+pub(crate) const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+struct Entry {
+    claims: Claims<Actor>,
+    cached_at: Instant,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<String, Entry>,
+    // incremented on every access, so the entry with the lowest value is the least recently used
+    tick: u64,
+}
+
+pub(crate) struct RemoteClaims {
+    capacity: usize,
+    ttl: Duration,
+    entries: Mutex<Entries>,
+}
+
+impl RemoteClaims {
+    pub(crate) fn new(capacity: usize, ttl: Duration) -> RemoteClaims {
+        RemoteClaims {
+            capacity,
+            ttl,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    pub(crate) fn get(&self, actor: &str) -> Option<Claims<Actor>> {
+        let mut entries = self.entries.lock_or_recover();
+        entries.tick += 1;
+        let tick = entries.tick;
+        let ttl = self.ttl;
+        let expired = match entries.map.get_mut(actor) {
+            Some(e) if e.cached_at.elapsed() < ttl => {
+                e.last_used = tick;
+                return Some(e.claims.clone());
+            }
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            entries.map.remove(actor);
+        }
+        None
+    }
+
+    pub(crate) fn insert(&self, actor: &str, claims: Claims<Actor>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock_or_recover();
+        entries.tick += 1;
+        let tick = entries.tick;
+        if !entries.map.contains_key(actor) && entries.map.len() >= self.capacity {
+            let ttl = self.ttl;
+            entries.map.retain(|_, e| e.cached_at.elapsed() < ttl);
+            if entries.map.len() >= self.capacity {
+                let lru = entries
+                    .map
+                    .iter()
+                    .min_by_key(|(_, e)| e.last_used)
+                    .map(|(k, _)| k.to_string());
+                if let Some(k) = lru {
+                    entries.map.remove(&k);
+                }
+            }
+        }
+        entries.map.insert(
+            actor.to_string(),
+            Entry {
+                claims,
+                cached_at: Instant::now(),
+                last_used: tick,
+            },
+        );
+    }
+
+    pub(crate) fn remove(&self, actor: &str) {
+        self.entries.lock_or_recover().map.remove(actor);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.lock_or_recover().map.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RemoteClaims;
+    use std::time::Duration;
+    use wascap::jwt::{Actor, Claims, ClaimsBuilder};
+    use wascap::prelude::KeyPair;
+
+    fn claims() -> (String, Claims<Actor>) {
+        let module = KeyPair::new_module();
+        let claims = ClaimsBuilder::<Actor>::new()
+            .issuer(&KeyPair::new_account().public_key())
+            .subject(&module.public_key())
+            .with_metadata(Actor::default())
+            .build();
+        (module.public_key(), claims)
+    }
+
+    #[test]
+    fn bounded_and_evicts_least_recently_used() {
+        let cache = RemoteClaims::new(3, Duration::from_secs(60));
+        let actors: Vec<_> = (0..3).map(|_| claims()).collect();
+        for (pk, c) in actors.iter() {
+            cache.insert(pk, c.clone());
+        }
+        // Using the first entry makes the second the least recently used
+        assert!(cache.get(&actors[0].0).is_some());
+        for _ in 0..100 {
+            let (pk, c) = claims();
+            cache.insert(&pk, c);
+            assert!(cache.len() <= 3);
+        }
+        assert_eq!(3, cache.len());
+
+        let cache = RemoteClaims::new(2, Duration::from_secs(60));
+        cache.insert(&actors[0].0, actors[0].1.clone());
+        cache.insert(&actors[1].0, actors[1].1.clone());
+        assert!(cache.get(&actors[0].0).is_some());
+        cache.insert(&actors[2].0, actors[2].1.clone());
+        assert!(cache.get(&actors[0].0).is_some());
+        assert!(cache.get(&actors[1].0).is_none());
+        assert!(cache.get(&actors[2].0).is_some());
+    }
+
+    #[test]
+    fn entries_expire() {
+        let cache = RemoteClaims::new(10, Duration::from_millis(50));
+        let (pk, c) = claims();
+        cache.insert(&pk, c.clone());
+        assert!(cache.get(&pk).is_some());
+        cache.remove(&pk);
+        assert!(cache.get(&pk).is_none());
+        cache.insert(&pk, c);
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(cache.get(&pk).is_none());
+        assert_eq!(0, cache.len());
+
+        let disabled = RemoteClaims::new(0, Duration::from_secs(60));
+        let (pk, c) = claims();
+        disabled.insert(&pk, c);
+        assert!(disabled.get(&pk).is_none());
+    }
+}
@@ -0,0 +1,289 @@
+//! The connection between a lattice host and the broker carrying the lattice's messages. This
+//! is a NATS server unless the host was built with `HostBuilder::with_mem_broker`, which is
+//! available with the `test-lattice` feature. The broker is chosen for each host when it's
+//! built, so a single test binary can run some hosts against a NATS server and others against
+//! in-memory brokers.
+//!
+//! The types here mirror the parts of the `nats` crate's API that the lattice uses.
+
+#[cfg(feature = "test-lattice")]
+use super::memlattice;
+use std::collections::HashMap;
+use std::io;
+use std::time::Duration;
+use wascap::jwt::{Actor, Claims};
+
+/// A connection to the lattice's broker. Clones share the connection
+#[derive(Clone)]
+pub(crate) enum Connection {
+    Nats(nats::Connection),
+    #[cfg(feature = "test-lattice")]
+    Mem(memlattice::Connection),
+}
+
+impl From<nats::Connection> for Connection {
+    fn from(nc: nats::Connection) -> Self {
+        Connection::Nats(nc)
+    }
+}
+
+#[cfg(feature = "test-lattice")]
+impl From<memlattice::Connection> for Connection {
+    fn from(nc: memlattice::Connection) -> Self {
+        Connection::Mem(nc)
+    }
+}
+
+impl Connection {
+    pub fn subscribe(&self, subject: &str) -> io::Result<Subscription> {
+        let inner = match self {
+            Connection::Nats(nc) => SubscriptionInner::Nats(nc.subscribe(subject)?),
+            #[cfg(feature = "test-lattice")]
+            Connection::Mem(nc) => SubscriptionInner::Mem(nc.subscribe(subject)?),
+        };
+        Ok(self.subscription(inner))
+    }
+
+    pub fn queue_subscribe(&self, subject: &str, queue: &str) -> io::Result<Subscription> {
+        let inner = match self {
+            Connection::Nats(nc) => SubscriptionInner::Nats(nc.queue_subscribe(subject, queue)?),
+            #[cfg(feature = "test-lattice")]
+            Connection::Mem(nc) => SubscriptionInner::Mem(nc.queue_subscribe(subject, queue)?),
+        };
+        Ok(self.subscription(inner))
+    }
+
+    pub fn publish(&self, subject: &str, msg: impl AsRef<[u8]>) -> io::Result<()> {
+        match self {
+            Connection::Nats(nc) => nc.publish(subject, msg),
+            #[cfg(feature = "test-lattice")]
+            Connection::Mem(nc) => nc.publish(subject, msg),
+        }
+    }
+
+    pub fn publish_request(
+        &self,
+        subject: &str,
+        reply: &str,
+        msg: impl AsRef<[u8]>,
+    ) -> io::Result<()> {
+        match self {
+            Connection::Nats(nc) => nc.publish_request(subject, reply, msg),
+            #[cfg(feature = "test-lattice")]
+            Connection::Mem(nc) => nc.publish_request(subject, reply, msg),
+        }
+    }
+
+    pub fn request_timeout(
+        &self,
+        subject: &str,
+        msg: impl AsRef<[u8]>,
+        timeout: Duration,
+    ) -> io::Result<Message> {
+        Ok(match self {
+            Connection::Nats(nc) => {
+                let m = nc.request_timeout(subject, msg, timeout)?;
+                self.message(m.subject, m.reply, m.data)
+            }
+            #[cfg(feature = "test-lattice")]
+            Connection::Mem(nc) => {
+                let m = nc.request_timeout(subject, msg, timeout)?;
+                self.message(m.subject, m.reply, m.data)
+            }
+        })
+    }
+
+    pub fn new_inbox(&self) -> String {
+        match self {
+            Connection::Nats(nc) => nc.new_inbox(),
+            #[cfg(feature = "test-lattice")]
+            Connection::Mem(nc) => nc.new_inbox(),
+        }
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        match self {
+            Connection::Nats(nc) => nc.flush(),
+            #[cfg(feature = "test-lattice")]
+            Connection::Mem(nc) => nc.flush(),
+        }
+    }
+
+    pub fn close(self) {
+        match self {
+            Connection::Nats(nc) => nc.close(),
+            #[cfg(feature = "test-lattice")]
+            Connection::Mem(nc) => nc.close(),
+        }
+    }
+
+    fn subscription(&self, inner: SubscriptionInner) -> Subscription {
+        Subscription {
+            inner,
+            conn: self.clone(),
+        }
+    }
+
+    fn message(&self, subject: String, reply: Option<String>, data: Vec<u8>) -> Message {
+        Message {
+            subject,
+            reply,
+            data,
+            conn: self.clone(),
+        }
+    }
+}
+
+enum SubscriptionInner {
+    Nats(nats::Subscription),
+    #[cfg(feature = "test-lattice")]
+    Mem(memlattice::Subscription),
+}
+
+/// A subscription to a subject. Dropping the subscription unsubscribes it, unless it has been
+/// handed to a handler with `with_handler`
+pub(crate) struct Subscription {
+    inner: SubscriptionInner,
+    conn: Connection,
+}
+
+impl Subscription {
+    pub fn next_timeout(&self, timeout: Duration) -> io::Result<Message> {
+        Ok(match self.inner {
+            SubscriptionInner::Nats(ref sub) => {
+                let m = sub.next_timeout(timeout)?;
+                self.conn.message(m.subject, m.reply, m.data)
+            }
+            #[cfg(feature = "test-lattice")]
+            SubscriptionInner::Mem(ref sub) => {
+                let m = sub.next_timeout(timeout)?;
+                self.conn.message(m.subject, m.reply, m.data)
+            }
+        })
+    }
+
+    pub fn unsubscribe(self) -> io::Result<()> {
+        match self.inner {
+            SubscriptionInner::Nats(sub) => sub.unsubscribe(),
+            #[cfg(feature = "test-lattice")]
+            SubscriptionInner::Mem(sub) => sub.unsubscribe(),
+        }
+    }
+
+    /// Processes each message on a background thread with the given function until the
+    /// subscription is unsubscribed or its connection is closed
+    pub fn with_handler<F>(self, handler: F) -> Handler
+    where
+        F: Fn(Message) -> io::Result<()> + Send + 'static,
+    {
+        let conn = self.conn;
+        match self.inner {
+            SubscriptionInner::Nats(sub) => Handler::Nats(
+                sub.with_handler(move |m| handler(conn.message(m.subject, m.reply, m.data))),
+            ),
+            #[cfg(feature = "test-lattice")]
+            SubscriptionInner::Mem(sub) => Handler::Mem(
+                sub.with_handler(move |m| handler(conn.message(m.subject, m.reply, m.data))),
+            ),
+        }
+    }
+}
+
+/// A subscription whose messages are being processed by a handler function
+pub(crate) enum Handler {
+    Nats(nats::subscription::Handler),
+    #[cfg(feature = "test-lattice")]
+    Mem(memlattice::Handler),
+}
+
+impl Handler {
+    pub fn unsubscribe(self) -> io::Result<()> {
+        match self {
+            Handler::Nats(h) => h.unsubscribe(),
+            #[cfg(feature = "test-lattice")]
+            Handler::Mem(h) => h.unsubscribe(),
+        }
+    }
+}
+
+/// A message received from the broker
+#[derive(Clone)]
+pub(crate) struct Message {
+    pub subject: String,
+    pub reply: Option<String>,
+    pub data: Vec<u8>,
+    // The connection the message arrived on, through which it's answered
+    conn: Connection,
+}
+
+impl Message {
+    /// Publishes a reply to the message's reply subject
+    pub fn respond(&self, msg: impl AsRef<[u8]>) -> io::Result<()> {
+        match self.reply {
+            Some(ref reply) => self.conn.publish(reply, msg),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No reply subject to reply to",
+            )),
+        }
+    }
+}
+
+impl std::fmt::Debug for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Message")
+            .field("subject", &self.subject)
+            .field("reply", &self.reply)
+            .field("data", &self.data.len())
+            .finish()
+    }
+}
+
+type ClientResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Queries lattice inventory over a connection to the lattice's broker
+pub(crate) enum LatticeClient {
+    Nats(latticeclient::Client),
+    #[cfg(feature = "test-lattice")]
+    Mem(memlattice::Client),
+}
+
+impl LatticeClient {
+    pub fn with_connection(conn: Connection, timeout: Duration, ns: Option<String>) -> Self {
+        match conn {
+            Connection::Nats(nc) => {
+                LatticeClient::Nats(latticeclient::Client::with_connection(nc, timeout, ns))
+            }
+            #[cfg(feature = "test-lattice")]
+            Connection::Mem(nc) => {
+                LatticeClient::Mem(memlattice::Client::with_connection(nc, timeout, ns))
+            }
+        }
+    }
+
+    pub fn get_actors(&self) -> ClientResult<HashMap<String, Vec<Claims<Actor>>>> {
+        match self {
+            LatticeClient::Nats(lc) => lc.get_actors(),
+            #[cfg(feature = "test-lattice")]
+            LatticeClient::Mem(lc) => lc.get_actors(),
+        }
+    }
+
+    pub fn get_capabilities(
+        &self,
+    ) -> ClientResult<HashMap<String, Vec<latticeclient::HostedCapability>>> {
+        match self {
+            LatticeClient::Nats(lc) => lc.get_capabilities(),
+            #[cfg(feature = "test-lattice")]
+            LatticeClient::Mem(lc) => lc.get_capabilities(),
+        }
+    }
+
+    pub fn get_bindings(&self) -> ClientResult<HashMap<String, Vec<latticeclient::Binding>>> {
+        match self {
+            LatticeClient::Nats(lc) => lc.get_bindings(),
+            #[cfg(feature = "test-lattice")]
+            LatticeClient::Mem(lc) => lc.get_bindings(),
+        }
+    }
+}
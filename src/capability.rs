@@ -1,19 +1,38 @@
+use crate::errors::{self, ErrorKind};
 use crate::Result;
 use libloading::Library;
 use libloading::Symbol;
-use std::ffi::OsStr;
+use std::ffi::{CStr, OsStr};
+use std::os::raw::c_char;
+use std::time::{Duration, Instant};
 use wascc_codec::{
     capabilities::{CapabilityDescriptor, CapabilityProvider, OP_GET_CAPABILITY_DESCRIPTOR},
     deserialize, SYSTEM_ACTOR,
 };
 
+/// The version of wascc-codec this host was built against. Native capability providers are
+/// passed codec types across the library boundary, so they must have been built against a
+/// compatible version
+pub const CODEC_VERSION: &str = wascc_codec::VERSION;
+
+// The binding name used when none is given, unless the host is configured with another
+pub(crate) const DEFAULT_BINDING: &str = "default";
+
+// The symbol a native capability provider exports to declare the wascc-codec version it was
+// built against. See `NativeCapability::from_file`
+pub(crate) const CODEC_VERSION_SYMBOL: &str = "__capability_provider_codec_version";
+
 /// Represents a native capability provider compiled as a shared object library.
 /// These plugins are OS- and architecture-specific, so they will be `.so` files on Linux, `.dylib`
 /// files on macOS, etc.
 pub struct NativeCapability {
     pub(crate) plugin: Box<dyn CapabilityProvider>,
     pub(crate) binding_name: String,
+    // Set when no binding name was given, so that the host's default binding name is used
+    pub(crate) implicit_binding: bool,
     pub(crate) descriptor: CapabilityDescriptor,
+    // How long the phases of loading the provider took, recorded by the host it's added to
+    pub(crate) load_timings: Vec<(&'static str, Duration)>,
     // This field is solely used to keep the FFI library instance allocated for the same
     // lifetime as the boxed plugin
     #[allow(dead_code)]
@@ -23,23 +42,63 @@ pub struct NativeCapability {
 impl NativeCapability {
     /// Reads a capability provider from a file. The capability provider must implement the
     /// correct FFI interface to support waSCC plugins. See [wascc.dev](https://wascc.dev) for
-    /// documentation and tutorials on how to create a native capability provider
+    /// documentation and tutorials on how to create a native capability provider.
+    ///
+    /// A provider can declare the version of wascc-codec it was built against by exporting
+    /// a function named `__capability_provider_codec_version`, taking no arguments and
+    /// returning a pointer to a static, nul-terminated version string such as `"0.8.1"`:
+    ///
+    /// ```ignore
+    /// #[no_mangle]
+    /// pub extern "C" fn __capability_provider_codec_version() -> *const std::os::raw::c_char {
+    ///     b"0.8.1\0".as_ptr() as _
+    /// }
+    /// ```
+    ///
+    /// A declared version is checked against `CODEC_VERSION` before the provider is
+    /// instantiated, and the provider is refused if the two aren't compatible:
+    ///
+    /// | Host codec | Provider codec | Result |
+    /// |------------|----------------|--------|
+    /// | 0.8.x      | 0.8.y          | Loaded |
+    /// | 0.8.x      | 0.7.y, 0.9.y   | Refused |
+    /// | x.y (x ≥ 1)| x.z            | Loaded |
+    /// | x.y (x ≥ 1)| w.z (w ≠ x)    | Refused |
+    /// | any        | not declared   | Loaded, with a warning |
+    ///
+    /// As with Cargo, versions before 1.0 are only compatible if their minor versions match
     pub fn from_file<P: AsRef<OsStr>>(
         filename: P,
         binding_target_name: Option<String>,
     ) -> Result<Self> {
         type PluginCreate = unsafe fn() -> *mut dyn CapabilityProvider;
+        type CodecVersion = unsafe extern "C" fn() -> *const c_char;
 
+        let start = Instant::now();
         let library = Library::new(filename.as_ref())?;
 
+        let declared = unsafe {
+            library
+                .get::<CodecVersion>(CODEC_VERSION_SYMBOL.as_bytes())
+                .ok()
+                .map(|f| f())
+                .filter(|p| !p.is_null())
+                .map(|p| CStr::from_ptr(p).to_string_lossy().to_string())
+        };
+        check_declared_version(&filename.as_ref().to_string_lossy(), declared)?;
+
         let plugin = unsafe {
             let constructor: Symbol<PluginCreate> = library.get(b"__capability_provider_create")?;
             let boxed_raw = constructor();
 
             Box::from_raw(boxed_raw)
         };
+        let mut load_timings = vec![("dlopen", start.elapsed())];
+        let start = Instant::now();
         let descriptor = get_descriptor(&plugin)?;
-        let binding = binding_target_name.unwrap_or("default".to_string());
+        load_timings.push(("descriptor", start.elapsed()));
+        let implicit_binding = binding_target_name.is_none();
+        let binding = binding_target_name.unwrap_or(DEFAULT_BINDING.to_string());
         info!(
             "Loaded native capability provider '{}' v{} ({}) for {}/{}",
             descriptor.name, descriptor.version, descriptor.revision, descriptor.id, binding
@@ -49,6 +108,8 @@ impl NativeCapability {
             plugin,
             descriptor,
             binding_name: binding,
+            implicit_binding,
+            load_timings,
             library: Some(library),
         })
     }
@@ -63,8 +124,11 @@ impl NativeCapability {
         binding_target_name: Option<String>,
     ) -> Result<Self> {
         let b: Box<dyn CapabilityProvider> = Box::new(instance);
+        let start = Instant::now();
         let descriptor = get_descriptor(&b)?;
-        let binding = binding_target_name.unwrap_or("default".to_string());
+        let load_timings = vec![("descriptor", start.elapsed())];
+        let implicit_binding = binding_target_name.is_none();
+        let binding = binding_target_name.unwrap_or(DEFAULT_BINDING.to_string());
 
         info!(
             "Loaded native capability provider '{}' v{} ({}) for {}/{}",
@@ -74,6 +138,8 @@ impl NativeCapability {
             descriptor,
             plugin: b,
             binding_name: binding,
+            implicit_binding,
+            load_timings,
             library: None,
         })
     }
@@ -94,8 +160,90 @@ impl NativeCapability {
     }
 }
 
+// Refuses the provider in the named file if the codec version it declared isn't compatible with
+// the host's
+fn check_declared_version(filename: &str, declared: Option<String>) -> Result<()> {
+    match declared {
+        Some(v) => check_codec_version(&v, CODEC_VERSION).map_err(|e| {
+            errors::new(ErrorKind::CapabilityProvider(format!(
+                "{}: {}",
+                filename, e
+            )))
+        }),
+        None => {
+            warn!(
+                "Capability provider {} doesn't declare its wascc-codec version, assuming it is compatible with {}",
+                filename,
+                CODEC_VERSION
+            );
+            Ok(())
+        }
+    }
+}
+
+// Fails if a provider built against the given codec version can't be used by a host built
+// against the host version
+fn check_codec_version(provider: &str, host: &str) -> std::result::Result<(), String> {
+    // The significant part of a version: the major version, or the minor version before 1.0
+    fn significant(v: &str) -> Option<(u64, u64)> {
+        let mut parts = v.trim().split('.').map(|p| p.parse::<u64>().ok());
+        let major = parts.next()??;
+        let minor = parts.next().unwrap_or(Some(0))?;
+        Some(if major == 0 { (0, minor) } else { (major, 0) })
+    }
+    match (significant(provider), significant(host)) {
+        (Some(p), Some(h)) if p == h => Ok(()),
+        (Some(_), Some(_)) => Err(format!(
+            "provider was built against wascc-codec {}, which is incompatible with this host's wascc-codec {}",
+            provider, host
+        )),
+        _ => Err(format!(
+            "provider declares an invalid wascc-codec version '{}'",
+            provider
+        )),
+    }
+}
+
 fn get_descriptor(plugin: &Box<dyn CapabilityProvider>) -> Result<CapabilityDescriptor> {
     let res = plugin.handle_call(SYSTEM_ACTOR, OP_GET_CAPABILITY_DESCRIPTOR, &[])?;
     let descriptor: CapabilityDescriptor = deserialize(&res)?;
     Ok(descriptor)
 }
+
+#[cfg(test)]
+mod test {
+    use super::{check_codec_version, check_declared_version, CODEC_VERSION};
+    use crate::errors::ErrorKind;
+
+    #[test]
+    fn codec_versions_checked() {
+        assert!(check_codec_version("0.8.1", "0.8").is_ok());
+        assert!(check_codec_version("0.8", "0.8.3").is_ok());
+        assert!(check_codec_version("1.2.0", "1.0").is_ok());
+
+        let err = check_codec_version("0.7.2", "0.8").unwrap_err();
+        assert!(err.contains("0.7.2"));
+        assert!(err.contains("0.8"));
+        assert!(check_codec_version("0.9.0", "0.8").is_err());
+        assert!(check_codec_version("1.0.0", "2.1").is_err());
+        assert!(check_codec_version("eight", "0.8").is_err());
+    }
+
+    #[test]
+    fn declared_versions_checked() {
+        assert_eq!(wascc_codec::VERSION, CODEC_VERSION);
+        assert!(check_declared_version("libnew.so", Some(CODEC_VERSION.to_string())).is_ok());
+        // Providers that don't declare a version are still loaded
+        assert!(check_declared_version("libunknown.so", None).is_ok());
+
+        let err = check_declared_version("libold.so", Some("0.4.2".to_string())).unwrap_err();
+        match err.kind() {
+            ErrorKind::CapabilityProvider(msg) => {
+                assert!(msg.starts_with("libold.so"));
+                assert!(msg.contains("0.4.2"));
+                assert!(msg.contains(CODEC_VERSION));
+            }
+            _ => panic!("unexpected error: {}", err),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Configuring a host entirely from environment variables with `HostBuilder::from_env`, for
+//! deployments (such as containers) where writing Rust glue code isn't practical. The following
+//! variables are recognized, and any that are unset leave the builder's default in place:
+//!
+//! | Variable | Builder equivalent |
+//! |---|---|
+//! | `WASCC_LABEL_<NAME>` | `with_label`, with the label name lowercased (e.g. `WASCC_LABEL_REGION=us-east`) |
+//! | `WASCC_BINDING_METADATA` | `with_binding_metadata`, as a comma-separated list of label names |
+//! | `WASCC_WORK_DIR` | `with_work_dir` |
+//! | `WASCC_AUDIT_CAPACITY` | `with_audit_capacity` |
+//! | `WASCC_LATTICE_NAMESPACE` | `with_lattice_namespace` (lattice mode only) |
+//! | `WASCC_LATTICE_RPC_TIMEOUT_MILLIS` | `with_lattice_rpc_timeout` (lattice mode only) |
+//!
+//! Credentials for OCI registries continue to be read from `OCI_REGISTRY_USER` and
+//! `OCI_REGISTRY_PASSWORD` whenever a registry is contacted.
+
+use crate::errors::{self, ErrorKind};
+use crate::{HostBuilder, Result};
+use std::collections::HashMap;
+
+pub const ENV_LABEL_PREFIX: &str = "WASCC_LABEL_";
+pub const ENV_BINDING_METADATA: &str = "WASCC_BINDING_METADATA";
+pub const ENV_WORK_DIR: &str = "WASCC_WORK_DIR";
+pub const ENV_AUDIT_CAPACITY: &str = "WASCC_AUDIT_CAPACITY";
+pub const ENV_LATTICE_NAMESPACE: &str = "WASCC_LATTICE_NAMESPACE";
+pub const ENV_LATTICE_RPC_TIMEOUT_MILLIS: &str = "WASCC_LATTICE_RPC_TIMEOUT_MILLIS";
+
+impl HostBuilder {
+    /// Creates a new host builder configured from `WASCC_*` environment variables, as described
+    /// in the [config](config/index.html) module. Builder functions called on the result take
+    /// precedence over the environment, including labels. If any variable holds an invalid
+    /// value, the returned error lists every offending variable
+    pub fn from_env() -> Result<HostBuilder> {
+        Self::from_vars(std::env::vars())
+    }
+
+    pub(crate) fn from_vars(vars: impl Iterator<Item = (String, String)>) -> Result<HostBuilder> {
+        let vars: HashMap<String, String> = vars.collect();
+        let mut problems = Vec::new();
+        let mut b = HostBuilder::new();
+
+        for (key, value) in vars.iter() {
+            if key.starts_with(ENV_LABEL_PREFIX) {
+                let label = key[ENV_LABEL_PREFIX.len()..].to_lowercase();
+                if label.is_empty() {
+                    problems.push(format!("{}: label name is empty", key));
+                } else {
+                    b.env_labels.insert(label, value.to_string());
+                }
+            }
+        }
+        if let Some(md) = vars.get(ENV_BINDING_METADATA) {
+            b.binding_metadata = md
+                .split(',')
+                .map(|l| l.trim().to_string())
+                .filter(|l| !l.is_empty())
+                .collect();
+        }
+        if let Some(dir) = vars.get(ENV_WORK_DIR).filter(|d| !d.is_empty()) {
+            b.work_dir = dir.into();
+        }
+        if let Some(cap) = vars.get(ENV_AUDIT_CAPACITY) {
+            match cap.parse() {
+                Ok(cap) => b.audit_capacity = cap,
+                Err(e) => problems.push(format!("{}: {}", ENV_AUDIT_CAPACITY, e)),
+            }
+        }
+        #[cfg(feature = "lattice")]
+        {
+            if let Some(ns) = vars.get(ENV_LATTICE_NAMESPACE) {
+                if ns.is_empty() || !ns.chars().all(char::is_alphanumeric) {
+                    problems.push(format!(
+                        "{}: namespace must be non-empty and alphanumeric",
+                        ENV_LATTICE_NAMESPACE
+                    ));
+                } else {
+                    b.ns = Some(ns.to_lowercase());
+                }
+            }
+            if let Some(ms) = vars.get(ENV_LATTICE_RPC_TIMEOUT_MILLIS) {
+                match ms.parse() {
+                    Ok(ms) => b.lattice_options.rpc_timeout = std::time::Duration::from_millis(ms),
+                    Err(e) => problems.push(format!("{}: {}", ENV_LATTICE_RPC_TIMEOUT_MILLIS, e)),
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(b)
+        } else {
+            problems.sort();
+            Err(errors::new(ErrorKind::MiscHost(format!(
+                "Invalid host configuration in environment: {}",
+                problems.join("; ")
+            ))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::HostBuilder;
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn invalid_values_listed() {
+        let err = HostBuilder::from_vars(vars(&[
+            ("WASCC_AUDIT_CAPACITY", "lots"),
+            ("WASCC_LABEL_", "nameless"),
+            ("WASCC_LABEL_REGION", "us-east"),
+        ]))
+        .err()
+        .unwrap();
+        let msg = err.to_string();
+        assert!(msg.contains("WASCC_AUDIT_CAPACITY"));
+        assert!(msg.contains("WASCC_LABEL_: label name is empty"));
+        assert!(!msg.contains("REGION"));
+    }
+
+    #[test]
+    fn unrelated_vars_ignored() {
+        let b =
+            HostBuilder::from_vars(vars(&[("PATH", "/bin"), ("WASCC_LABEL_ZONE", "b")])).unwrap();
+        assert_eq!(1, b.env_labels.len());
+        assert_eq!("b", b.env_labels["zone"]);
+    }
+}
@@ -1,10 +1,30 @@
 use crate::bus::MessageBus;
-use crate::inthost::{Invocation, WasccEntity};
+use crate::inthost::{Invocation, InvocationResponse, WasccEntity};
+use crate::signer::InvocationSigner;
 use std::{error::Error, sync::Arc};
 
-use wascap::prelude::KeyPair;
 use wascc_codec::capabilities::Dispatcher;
 
+/// The number of invocations that may be waiting on an actor before `try_dispatch` considers
+/// it saturated
+pub const TRY_DISPATCH_MAX_PENDING: usize = 16;
+
+/// An extension of the codec's `Dispatcher` for capability providers that would rather fail
+/// than wait on an actor that can't keep up (e.g. an HTTP server returning a 503). Failures are
+/// boxed `wascc_host::errors::Error` values, and those of kind `ErrorKind::Bus` identify why the
+/// invocation was rejected
+pub trait TryDispatcher: Dispatcher {
+    /// Invokes a function on an actor, failing immediately if the actor isn't running in this
+    /// host or already has `TRY_DISPATCH_MAX_PENDING` invocations waiting. In lattice mode the
+    /// queue depth of remote actors can't be observed, so this behaves like `dispatch`
+    fn try_dispatch(
+        &self,
+        actor: &str,
+        op: &str,
+        msg: &[u8],
+    ) -> Result<Vec<u8>, Box<dyn Error + Sync + Send>>;
+}
+
 /// A dispatcher is given to each capability provider, allowing it to send
 /// commands in to the guest module and await replies. This dispatch
 /// is one way, and is _not_ used for the guest module to send commands to capabilities
@@ -13,35 +33,37 @@ pub(crate) struct WasccNativeDispatcher {
     bus: Arc<MessageBus>,
     capid: String,
     binding: String,
-    hk: Arc<KeyPair>,
+    signer: Arc<InvocationSigner>,
 }
 
 impl WasccNativeDispatcher {
-    pub fn new(hk: Arc<KeyPair>, bus: Arc<MessageBus>, capid: &str, binding: &str) -> Self {
+    pub fn new(
+        signer: Arc<InvocationSigner>,
+        bus: Arc<MessageBus>,
+        capid: &str,
+        binding: &str,
+    ) -> Self {
         WasccNativeDispatcher {
             bus,
             capid: capid.to_string(),
             binding: binding.to_string(),
-            hk,
+            signer,
         }
     }
-}
 
-impl Dispatcher for WasccNativeDispatcher {
-    /// Called by a capability provider to invoke a function on an actor
-    fn dispatch(
+    fn invoke_with(
         &self,
         actor: &str,
         op: &str,
         msg: &[u8],
+        invoke: impl FnOnce(&str, Invocation) -> crate::Result<InvocationResponse>,
     ) -> Result<Vec<u8>, Box<dyn Error + Sync + Send>> {
         trace!(
             "Dispatching operation '{}' ({} bytes) to actor",
             op,
             msg.len()
         );
-        let inv = Invocation::new(
-            &self.hk,
+        let inv = self.signer.invocation(
             WasccEntity::Capability {
                 capid: self.capid.to_string(),
                 binding: self.binding.to_string(),
@@ -51,7 +73,7 @@ impl Dispatcher for WasccNativeDispatcher {
             msg.to_vec(),
         );
         let tgt_sub = self.bus.actor_subject(actor);
-        let resp = self.bus.invoke(&tgt_sub, inv);
+        let resp = invoke(&tgt_sub, inv);
 
         match resp {
             Ok(r) => match r.error {
@@ -62,3 +84,136 @@ impl Dispatcher for WasccNativeDispatcher {
         }
     }
 }
+
+impl Dispatcher for WasccNativeDispatcher {
+    /// Called by a capability provider to invoke a function on an actor
+    fn dispatch(
+        &self,
+        actor: &str,
+        op: &str,
+        msg: &[u8],
+    ) -> Result<Vec<u8>, Box<dyn Error + Sync + Send>> {
+        self.invoke_with(actor, op, msg, |subject, inv| self.bus.invoke(subject, inv))
+    }
+}
+
+impl TryDispatcher for WasccNativeDispatcher {
+    fn try_dispatch(
+        &self,
+        actor: &str,
+        op: &str,
+        msg: &[u8],
+    ) -> Result<Vec<u8>, Box<dyn Error + Sync + Send>> {
+        self.invoke_with(actor, op, msg, |subject, inv| {
+            self.bus.try_invoke(subject, inv, TRY_DISPATCH_MAX_PENDING)
+        })
+    }
+}
+
+#[cfg(all(test, not(feature = "lattice")))]
+mod test {
+    use super::{TryDispatcher, WasccNativeDispatcher, TRY_DISPATCH_MAX_PENDING};
+    use crate::errors::{BusError, Error, ErrorKind};
+    use crate::signer::InvocationSigner;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use wascap::prelude::KeyPair;
+
+    fn bus_error(e: Box<dyn std::error::Error + Sync + Send>) -> BusError {
+        match e.downcast::<Error>().unwrap().into_kind() {
+            ErrorKind::Bus(b) => b,
+            k => panic!("expected a bus error, got {:?}", k),
+        }
+    }
+
+    #[test]
+    fn try_dispatch_fails_fast() {
+        let bus = Arc::new(crate::bus::new(Default::default()));
+        let key = KeyPair::new_server();
+        let signer = InvocationSigner::new(&key.public_key(), &key.seed().unwrap(), false).unwrap();
+        let d = WasccNativeDispatcher::new(
+            Arc::new(signer),
+            bus.clone(),
+            "wascc:http_server",
+            "default",
+        );
+
+        let start = Instant::now();
+        match bus_error(d.try_dispatch("Mnosuch", "HandleRequest", &[]).unwrap_err()) {
+            BusError::NoResponders { .. } => {}
+            e => panic!("unexpected error {:?}", e),
+        }
+
+        // An actor that has stopped taking invocations off its queue
+        let subject = bus.actor_subject("Mbusy");
+        let (inv_s, _inv_r) = crossbeam_channel::unbounded();
+        let (_resp_s, resp_r) = crossbeam_channel::unbounded();
+        for _ in 0..TRY_DISPATCH_MAX_PENDING {
+            inv_s.send(stalled_invocation()).unwrap();
+        }
+        bus.subscribe(&subject, inv_s, resp_r).unwrap();
+        match bus_error(d.try_dispatch("Mbusy", "HandleRequest", &[]).unwrap_err()) {
+            BusError::Saturated { subject: s } => assert_eq!(s, subject),
+            e => panic!("unexpected error {:?}", e),
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn try_dispatch_bounded_under_contention() {
+        let bus = Arc::new(crate::bus::new(Default::default()));
+        let key = KeyPair::new_server();
+        let signer = InvocationSigner::new(&key.public_key(), &key.seed().unwrap(), false).unwrap();
+        let d = Arc::new(WasccNativeDispatcher::new(
+            Arc::new(signer),
+            bus.clone(),
+            "wascc:http_server",
+            "default",
+        ));
+
+        // A slow actor, which answers nothing until every caller has tried
+        let subject = bus.actor_subject("Mslow");
+        let (inv_s, inv_r) = crossbeam_channel::unbounded::<crate::Invocation>();
+        let (resp_s, resp_r) = crossbeam_channel::unbounded();
+        bus.subscribe(&subject, inv_s, resp_r).unwrap();
+        std::thread::spawn(move || {
+            for inv in inv_r.iter() {
+                std::thread::sleep(Duration::from_millis(50));
+                let _ = resp_s.send(crate::InvocationResponse::success(&inv, vec![]));
+            }
+        });
+
+        let callers = 4 * TRY_DISPATCH_MAX_PENDING;
+        let start = Arc::new(std::sync::Barrier::new(callers));
+        let handles: Vec<_> = (0..callers)
+            .map(|_| {
+                let d = d.clone();
+                let start = start.clone();
+                std::thread::spawn(move || {
+                    start.wait();
+                    d.try_dispatch("Mslow", "HandleRequest", &[])
+                        .map_err(bus_error)
+                })
+            })
+            .collect();
+        let mut admitted = 0;
+        for h in handles {
+            match h.join().unwrap() {
+                Ok(_) => admitted += 1,
+                Err(BusError::Saturated { .. }) => {}
+                Err(e) => panic!("unexpected error {:?}", e),
+            }
+        }
+        assert_eq!(TRY_DISPATCH_MAX_PENDING, admitted);
+    }
+
+    fn stalled_invocation() -> crate::Invocation {
+        crate::Invocation::new(
+            &KeyPair::new_server(),
+            crate::WasccEntity::Actor("system".to_string()),
+            crate::WasccEntity::Actor("Mbusy".to_string()),
+            "HandleRequest",
+            vec![],
+        )
+    }
+}
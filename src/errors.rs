@@ -21,6 +21,105 @@ pub enum ErrorKind {
     Plugin(libloading::Error),
     Middleware(String),
     Serialization(String),
+    Bus(BusError),
+    /// The capability provider has failed its health checks, see the `health` module
+    ProviderUnhealthy {
+        capid: String,
+        binding: String,
+    },
+    /// Invocations of the target (an actor or capability URL) are being rejected by a
+    /// circuit breaker, see the `middleware::circuitbreaker` module
+    CircuitOpen {
+        target: String,
+    },
+}
+
+/// A failure to deliver a message over the message bus (in-process or lattice), classified so
+/// that callers can decide whether it is worth retrying. Each variant carries the subject of the
+/// message involved
+#[derive(Debug, Clone, PartialEq)]
+pub enum BusError {
+    /// No response arrived before the request timeout elapsed
+    Timeout { subject: String },
+    /// There is no live connection to the bus
+    Disconnected { subject: String },
+    /// Nothing is subscribed to the subject, e.g. the target actor isn't running
+    NoResponders { subject: String },
+    /// The subscriber already has as many requests waiting as the caller allowed
+    Saturated { subject: String },
+    /// The message or its response could not be (de)serialized
+    SerializationFailure { subject: String, reason: String },
+    /// The subject is not valid for the bus
+    SubjectInvalid { subject: String },
+}
+
+impl BusError {
+    /// The subject of the message that could not be delivered
+    pub fn subject(&self) -> &str {
+        match self {
+            BusError::Timeout { subject }
+            | BusError::Disconnected { subject }
+            | BusError::NoResponders { subject }
+            | BusError::Saturated { subject }
+            | BusError::SerializationFailure { subject, .. }
+            | BusError::SubjectInvalid { subject } => subject,
+        }
+    }
+
+    /// Indicates whether the same request might succeed if sent again later. Timeouts,
+    /// disconnections, a lack of responders, and saturated subscribers are transient; malformed
+    /// messages are not
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            BusError::Timeout { .. }
+            | BusError::Disconnected { .. }
+            | BusError::NoResponders { .. }
+            | BusError::Saturated { .. } => true,
+            BusError::SerializationFailure { .. } | BusError::SubjectInvalid { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BusError::Timeout { subject } => write!(f, "request to {} timed out", subject),
+            BusError::Disconnected { subject } => {
+                write!(f, "no bus connection for message to {}", subject)
+            }
+            BusError::NoResponders { subject } => write!(f, "no responders for {}", subject),
+            BusError::Saturated { subject } => {
+                write!(f, "too many requests waiting on {}", subject)
+            }
+            BusError::SerializationFailure { subject, reason } => {
+                write!(f, "failed to serialize message for {}: {}", subject, reason)
+            }
+            BusError::SubjectInvalid { subject } => write!(f, "invalid subject '{}'", subject),
+        }
+    }
+}
+
+/// Creates a bus error
+pub(crate) fn bus(e: BusError) -> Error {
+    new(ErrorKind::Bus(e))
+}
+
+/// Classifies an I/O error returned by the NATS client for a message on the given subject.
+/// Errors that don't correspond to a bus failure mode are kept as I/O errors
+pub(crate) fn from_bus_io(subject: &str, e: std::io::Error) -> Error {
+    use std::io::ErrorKind as IoKind;
+    let subject = subject.to_string();
+    match e.kind() {
+        IoKind::TimedOut | IoKind::WouldBlock => bus(BusError::Timeout { subject }),
+        IoKind::NotConnected
+        | IoKind::ConnectionRefused
+        | IoKind::ConnectionReset
+        | IoKind::ConnectionAborted
+        | IoKind::BrokenPipe
+        | IoKind::UnexpectedEof => bus(BusError::Disconnected { subject }),
+        IoKind::InvalidInput => bus(BusError::SubjectInvalid { subject }),
+        _ => new(ErrorKind::IO(e)),
+    }
 }
 
 impl Error {
@@ -31,6 +130,15 @@ impl Error {
     pub fn into_kind(self) -> ErrorKind {
         *self.0
     }
+
+    /// Indicates whether the operation that produced this error might succeed if attempted
+    /// again, which is only the case for transient bus failures such as timeouts
+    pub fn is_retryable(&self) -> bool {
+        match *self.0 {
+            ErrorKind::Bus(ref e) => e.is_retryable(),
+            _ => false,
+        }
+    }
 }
 
 impl StdError for Error {
@@ -46,6 +154,9 @@ impl StdError for Error {
             ErrorKind::Plugin(_) => "Plugin error",
             ErrorKind::Middleware(_) => "Middleware error",
             ErrorKind::Serialization(_) => "Serialization failure",
+            ErrorKind::Bus(_) => "Message bus failure",
+            ErrorKind::ProviderUnhealthy { .. } => "Capability provider unhealthy",
+            ErrorKind::CircuitOpen { .. } => "Circuit open",
         }
     }
 
@@ -61,6 +172,9 @@ impl StdError for Error {
             ErrorKind::Plugin(ref err) => Some(err),
             ErrorKind::Middleware(_) => None,
             ErrorKind::Serialization(_) => None,
+            ErrorKind::Bus(_) => None,
+            ErrorKind::ProviderUnhealthy { .. } => None,
+            ErrorKind::CircuitOpen { .. } => None,
         }
     }
 }
@@ -84,6 +198,20 @@ impl fmt::Display for Error {
             ErrorKind::Plugin(ref err) => write!(f, "Plugin error: {}", err),
             ErrorKind::Middleware(ref err) => write!(f, "Middleware error: {}", err),
             ErrorKind::Serialization(ref err) => write!(f, "Serialization failure: {}", err),
+            ErrorKind::Bus(ref err) => write!(f, "Message bus failure: {}", err),
+            ErrorKind::ProviderUnhealthy {
+                ref capid,
+                ref binding,
+            } => write!(
+                f,
+                "Capability provider {},{} is unhealthy and not accepting invocations",
+                binding, capid
+            ),
+            ErrorKind::CircuitOpen { ref target } => write!(
+                f,
+                "Circuit open for {}: invocations are being rejected after repeated failures",
+                target
+            ),
         }
     }
 }
@@ -128,4 +256,58 @@ mod tests {
     #[allow(dead_code)]
     fn assert_sync_send<T: Send + Sync>() {}
     const _: fn() = || assert_sync_send::<super::Error>();
+
+    use super::{BusError, ErrorKind};
+    use std::io;
+
+    #[test]
+    fn nats_failures_classified() {
+        let classify = |kind| super::from_bus_io("wasmbus.actor.Ma", io::Error::new(kind, "boom"));
+
+        let e = classify(io::ErrorKind::TimedOut);
+        assert!(e.is_retryable());
+        match e.kind() {
+            ErrorKind::Bus(BusError::Timeout { subject }) => {
+                assert_eq!(subject, "wasmbus.actor.Ma")
+            }
+            k => panic!("unexpected kind {:?}", k),
+        }
+        for kind in &[io::ErrorKind::NotConnected, io::ErrorKind::BrokenPipe] {
+            match classify(*kind).kind() {
+                ErrorKind::Bus(BusError::Disconnected { .. }) => {}
+                k => panic!("unexpected kind {:?}", k),
+            }
+        }
+        let e = classify(io::ErrorKind::InvalidInput);
+        assert!(!e.is_retryable());
+        match e.kind() {
+            ErrorKind::Bus(BusError::SubjectInvalid { .. }) => {}
+            k => panic!("unexpected kind {:?}", k),
+        }
+        let e = classify(io::ErrorKind::PermissionDenied);
+        assert!(!e.is_retryable());
+        match e.kind() {
+            ErrorKind::IO(_) => {}
+            k => panic!("unexpected kind {:?}", k),
+        }
+    }
+
+    #[test]
+    fn only_transient_bus_errors_retryable() {
+        let subject = "wasmbus.provider.wascc.keyvalue.default".to_string();
+        assert!(super::bus(BusError::NoResponders {
+            subject: subject.clone()
+        })
+        .is_retryable());
+        assert!(!super::bus(BusError::SerializationFailure {
+            subject: subject.clone(),
+            reason: "bad".to_string()
+        })
+        .is_retryable());
+        assert!(!super::new(ErrorKind::MiscHost("No such actor".to_string())).is_retryable());
+        assert_eq!(
+            "Message bus failure: no responders for wasmbus.provider.wascc.keyvalue.default",
+            super::bus(BusError::NoResponders { subject }).to_string()
+        );
+    }
 }
@@ -0,0 +1,328 @@
+//! # Host Events
+//!
+//! Events emitted by a host as it operates. Consumers obtain a channel of these events
+//! with `Host::events`. Host events are delivered locally, regardless of whether the
+//! host is running in lattice mode.
+
+use crate::locks::RwLockExt;
+use crossbeam::{Receiver, Sender};
+use crossbeam_channel as channel;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// An event that occurred within a host
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostEvent {
+    /// An actor is ready for invocations, having taken `start_duration` to start (mostly
+    /// compiling its module)
+    ActorStarted {
+        actor: String,
+        start_duration: Duration,
+        /// The actor's annotations, if it has any (see `Host::add_actor_with_annotations`)
+        annotations: Option<HashMap<String, String>>,
+    },
+    /// An actor was stopped, having been removed from the host
+    ActorStopped {
+        actor: String,
+        /// The actor's annotations at the time it stopped, if it had any
+        annotations: Option<HashMap<String, String>>,
+    },
+    /// A native capability provider is ready for invocations, having taken `load_duration` to
+    /// be registered and subscribed
+    ProviderStarted {
+        capid: String,
+        binding: String,
+        load_duration: Duration,
+    },
+    /// An actor attempted an operation on a target that it is not authorized to invoke, either
+    /// because it lacks the capability attestation or because the authorizer denied it
+    AuthorizationDenied {
+        actor: String,
+        capid: String,
+        binding: String,
+        operation: String,
+        reason: String,
+    },
+    /// A watched actor was replaced with a new version of its module file
+    ActorUpdated { actor: String },
+    /// A new version of a watched actor's module file could not be loaded or swapped in
+    ActorUpdateFailed { actor: String, reason: String },
+    /// A capability provider failed enough consecutive health probes to be considered unhealthy
+    ProviderUnhealthy {
+        capid: String,
+        binding: String,
+        reason: String,
+    },
+    /// A capability provider that was unhealthy answered a health probe
+    ProviderRecovered { capid: String, binding: String },
+    /// A lattice subscription, such as an actor's or provider's invocation subject, went away
+    /// without the host unsubscribing and is being re-established. Published for each attempt
+    SubscriptionLost { subject: String, attempt: u32 },
+    /// A lost lattice subscription was re-established
+    SubscriptionRestored { subject: String },
+    /// A capability provider reported that it can no longer serve an actor's binding, so the
+    /// binding was removed
+    BindingRemoved {
+        actor: String,
+        capid: String,
+        binding: String,
+        reason: String,
+    },
+    /// The configuration of an existing binding was changed with `Host::update_binding_values`
+    BindingUpdated {
+        actor: String,
+        capid: String,
+        binding: String,
+    },
+    /// An invocation was sent to a subject whose subscriber is no longer running (or, in a
+    /// single host, to a subject nobody is subscribed to) and was answered with an error.
+    /// These events are rate-limited, so not every undeliverable invocation is reported
+    InvocationUndeliverable {
+        subject: String,
+        invocation_id: String,
+        origin: String,
+        target: String,
+        operation: String,
+    },
+    /// A lattice control plane command was ignored because this host only accepts commands
+    /// signed by trusted issuers and the command wasn't signed, was signed by an untrusted
+    /// issuer, had expired, or was signed for a different subject
+    ControlCommandRejected { subject: String, reason: String },
+    /// When a capability provider was loaded into a lattice host, the bindings to it that
+    /// already existed in the lattice could not all be re-established, even after retrying.
+    /// `failures` maps the public key of each actor left unbound to the last error its
+    /// rebind attempt produced
+    BindingsNotReestablished {
+        capid: String,
+        binding: String,
+        failures: HashMap<String, String>,
+    },
+    /// The authorizer refused to let a running actor be replaced with a new module
+    ActorReplaceDenied { actor: String },
+    /// The authorizer refused to let an actor be removed. `requested_by` says who asked, as
+    /// passed to `Authorizer::can_terminate`
+    ActorTerminationDenied { actor: String, requested_by: String },
+}
+
+/// A lattice event with no counterpart in `latticeclient::BusEvent`. These are published on the
+/// lattice event subject as cloud events with types of their own (see `LatticeEvent::event_type`),
+/// which consumers that only understand `BusEvent`s skip. Obtain a channel of them with
+/// `Host::lattice_extended_events`
+#[cfg(feature = "lattice")]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum LatticeEvent {
+    /// A binding between an actor and a capability provider was removed
+    ActorBindingRemoved {
+        actor: String,
+        capid: String,
+        instance_name: String,
+        host: String,
+        reason: ReasonCode,
+    },
+    /// A capability provider failed enough consecutive health probes to be considered unhealthy
+    ProviderUnhealthy {
+        capid: String,
+        instance_name: String,
+        host: String,
+        reason: ReasonCode,
+    },
+    /// A capability provider that was unhealthy answered a health probe
+    ProviderHealthy {
+        capid: String,
+        instance_name: String,
+        host: String,
+        reason: ReasonCode,
+    },
+    /// A running actor was replaced with a new module
+    ActorReplaced {
+        actor: String,
+        host: String,
+        reason: ReasonCode,
+    },
+    /// A running actor could not be replaced with a new module
+    ActorReplaceFailed {
+        actor: String,
+        host: String,
+        reason: ReasonCode,
+    },
+    /// An invocation received from the lattice failed its anti-forgery check and was rejected.
+    /// `origin_host` is the host the invocation claims to have come from
+    InvocationForgeryDetected {
+        origin: String,
+        target: String,
+        operation: String,
+        origin_host: String,
+        host: String,
+        reason: ReasonCode,
+    },
+    /// A capability provider instance could not re-establish the lattice's existing bindings
+    /// for the listed actors when it was loaded
+    BindingsNotReestablished {
+        capid: String,
+        instance_name: String,
+        actors: Vec<String>,
+        host: String,
+        reason: ReasonCode,
+    },
+}
+
+/// The machine-readable cause of a `LatticeEvent`
+#[cfg(feature = "lattice")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasonCode {
+    /// The binding was removed with `Host::remove_binding`
+    Requested,
+    /// The capability provider reported that it can no longer serve the binding
+    ProviderReleased,
+    /// The capability provider failed its health probes
+    HealthProbeFailed,
+    /// The capability provider answered a health probe
+    HealthProbeAnswered,
+    /// The actor accepted the new module
+    LiveUpdateApplied,
+    /// The actor rejected the new module, e.g. because the WebAssembly driver couldn't swap it
+    LiveUpdateRejected,
+    /// The live update couldn't be delivered to the actor
+    ActorUnreachable,
+    /// The invocation's signature, hash, or claims didn't check out
+    AntiforgeryCheckFailed,
+    /// The capability provider failed to bind the actors, even after retrying
+    RebindFailed,
+    /// The host's authorizer refused the change
+    AuthorizerDenied,
+}
+
+#[cfg(feature = "lattice")]
+impl LatticeEvent {
+    /// The cloud event type under which this event is published
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            LatticeEvent::ActorBindingRemoved { .. } => {
+                "com.wascc.lattice.ext.actor_binding_removed"
+            }
+            LatticeEvent::ProviderUnhealthy { .. } => "com.wascc.lattice.ext.provider_unhealthy",
+            LatticeEvent::ProviderHealthy { .. } => "com.wascc.lattice.ext.provider_healthy",
+            LatticeEvent::ActorReplaced { .. } => "com.wascc.lattice.ext.actor_replaced",
+            LatticeEvent::ActorReplaceFailed { .. } => "com.wascc.lattice.ext.actor_replace_failed",
+            LatticeEvent::InvocationForgeryDetected { .. } => {
+                "com.wascc.lattice.ext.invocation_forgery_detected"
+            }
+            LatticeEvent::BindingsNotReestablished { .. } => {
+                "com.wascc.lattice.ext.bindings_not_reestablished"
+            }
+        }
+    }
+
+    /// The host that published this event
+    pub fn host(&self) -> &str {
+        match self {
+            LatticeEvent::ActorBindingRemoved { host, .. }
+            | LatticeEvent::ProviderUnhealthy { host, .. }
+            | LatticeEvent::ProviderHealthy { host, .. }
+            | LatticeEvent::ActorReplaced { host, .. }
+            | LatticeEvent::ActorReplaceFailed { host, .. }
+            | LatticeEvent::InvocationForgeryDetected { host, .. }
+            | LatticeEvent::BindingsNotReestablished { host, .. } => host,
+        }
+    }
+
+    /// The reason this event was published
+    pub fn reason(&self) -> ReasonCode {
+        match self {
+            LatticeEvent::ActorBindingRemoved { reason, .. }
+            | LatticeEvent::ProviderUnhealthy { reason, .. }
+            | LatticeEvent::ProviderHealthy { reason, .. }
+            | LatticeEvent::ActorReplaced { reason, .. }
+            | LatticeEvent::ActorReplaceFailed { reason, .. }
+            | LatticeEvent::InvocationForgeryDetected { reason, .. }
+            | LatticeEvent::BindingsNotReestablished { reason, .. } => *reason,
+        }
+    }
+
+    // Health transitions are published as host events first, then forwarded to the lattice
+    pub(crate) fn from_health(event: &HostEvent, host: &str) -> Option<LatticeEvent> {
+        match event {
+            HostEvent::ProviderUnhealthy { capid, binding, .. } => {
+                Some(LatticeEvent::ProviderUnhealthy {
+                    capid: capid.to_string(),
+                    instance_name: binding.to_string(),
+                    host: host.to_string(),
+                    reason: ReasonCode::HealthProbeFailed,
+                })
+            }
+            HostEvent::ProviderRecovered { capid, binding } => {
+                Some(LatticeEvent::ProviderHealthy {
+                    capid: capid.to_string(),
+                    instance_name: binding.to_string(),
+                    host: host.to_string(),
+                    reason: ReasonCode::HealthProbeAnswered,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A subscription to the lattice event subject, from `Host::lattice_events` or
+/// `Host::lattice_extended_events`. It dereferences to the channel on which the events arrive.
+/// Dropping the stream unsubscribes it before the drop returns, so no events are delivered
+/// after that
+#[cfg(feature = "lattice")]
+pub struct LatticeEventStream<T> {
+    receiver: Receiver<T>,
+    handler: Option<crate::bus::lattice::Handler>,
+}
+
+#[cfg(feature = "lattice")]
+impl<T> LatticeEventStream<T> {
+    pub(crate) fn new(receiver: Receiver<T>, handler: crate::bus::lattice::Handler) -> Self {
+        LatticeEventStream {
+            receiver,
+            handler: Some(handler),
+        }
+    }
+}
+
+#[cfg(feature = "lattice")]
+impl<T> std::ops::Deref for LatticeEventStream<T> {
+    type Target = Receiver<T>;
+
+    fn deref(&self) -> &Receiver<T> {
+        &self.receiver
+    }
+}
+
+#[cfg(feature = "lattice")]
+impl<T> Drop for LatticeEventStream<T> {
+    fn drop(&mut self) {
+        if let Some(h) = self.handler.take() {
+            if let Err(e) = h.unsubscribe() {
+                warn!("Failed to unsubscribe from lattice events: {}", e);
+            }
+        }
+    }
+}
+
+/// Fans out host events to all subscribers. Subscribers whose receivers have been
+/// dropped are pruned on the next publish
+#[derive(Default)]
+pub(crate) struct EventBroker {
+    subscribers: RwLock<Vec<Sender<HostEvent>>>,
+}
+
+impl EventBroker {
+    pub(crate) fn subscribe(&self) -> Receiver<HostEvent> {
+        let (s, r) = channel::unbounded();
+        self.subscribers.write_or_recover().push(s);
+        r
+    }
+
+    pub(crate) fn publish(&self, event: HostEvent) {
+        trace!("Host event: {:?}", event);
+        self.subscribers
+            .write_or_recover()
+            .retain(|s| s.send(event.clone()).is_ok());
+    }
+}
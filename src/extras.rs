@@ -1,8 +1,17 @@
-// A default implementation of the "wascc:extras" provider that is always included
-// with the host runtime. This provides functionality for generating random numbers,
-// generating a guid, and generating a sequence number... things that a standalone
-// WASM module cannot do.
+//! # Extras Provider
+//!
+//! A default implementation of the "wascc:extras" provider that is always included
+//! with the host runtime unless disabled on the host builder. This provides functionality
+//! for generating random numbers, generating a guid, and generating a sequence number...
+//! things that a standalone WASM module cannot do.
+//!
+//! Each actor has its own sequence for each binding name it's bound to the provider with, so
+//! an actor bound as `orders` and as `invoices` has two independent sequences. The provider is
+//! served on any binding name an actor is bound to it with, until no actor is bound to it with
+//! that name any longer. An actor can start one of its sequences over with `OP_RESET_SEQUENCE`;
+//! it can't reset another actor's sequences.
 
+use crate::locks::RwLockExt;
 use crate::{REVISION, VERSION};
 use std::error::Error;
 use std::sync::{Arc, RwLock};
@@ -19,23 +28,33 @@ use wascc_codec::core::OP_BIND_ACTOR;
 use wascc_codec::extras::*;
 use wascc_codec::{deserialize, serialize, SYSTEM_ACTOR};
 
+/// The capability ID of the extras provider
+pub const CAPABILITY_ID: &str = "wascc:extras";
+
+/// Starts the calling actor's sequence for the binding it calls through over from 0. The
+/// request carries no payload and the response is empty
+pub const OP_RESET_SEQUENCE: &str = "ResetSequence";
+
+/// The sequences of every actor, keyed by actor and binding name, shared by the instances of the
+/// provider serving the different binding names
+pub(crate) type Sequences = Arc<RwLock<HashMap<(String, String), AtomicU64>>>;
+
 pub(crate) struct ExtrasCapabilityProvider {
     dispatcher: Arc<RwLock<Box<dyn Dispatcher>>>,
-    sequences: Arc<RwLock<HashMap<String, AtomicU64>>>,
+    // the binding name this instance serves
+    binding: String,
+    sequences: Sequences,
 }
 
-impl Default for ExtrasCapabilityProvider {
-    fn default() -> Self {
+impl ExtrasCapabilityProvider {
+    pub fn new(binding: &str, sequences: Sequences) -> Self {
         ExtrasCapabilityProvider {
             dispatcher: Arc::new(RwLock::new(Box::new(NullDispatcher::new()))),
-            sequences: Arc::new(RwLock::new(HashMap::new())),
+            binding: binding.to_string(),
+            sequences,
         }
     }
-}
-
-pub(crate) const CAPABILITY_ID: &str = "wascc:extras";
 
-impl ExtrasCapabilityProvider {
     fn generate_guid(
         &self,
         _actor: &str,
@@ -83,9 +102,9 @@ impl ExtrasCapabilityProvider {
         actor: &str,
         _msg: GeneratorRequest,
     ) -> Result<Vec<u8>, Box<dyn Error + Sync + Send>> {
-        let mut lock = self.sequences.write().unwrap();
+        let mut lock = self.sequences.write_or_recover();
         let seq = lock
-            .entry(actor.to_string())
+            .entry((actor.to_string(), self.binding.clone()))
             .or_insert(AtomicU64::new(0))
             .fetch_add(1, Ordering::SeqCst);
         let result = GeneratorResult {
@@ -96,6 +115,14 @@ impl ExtrasCapabilityProvider {
         Ok(serialize(&result)?)
     }
 
+    // Only ever resets the calling actor's own sequence
+    fn reset_sequence(&self, actor: &str) -> Result<Vec<u8>, Box<dyn Error + Sync + Send>> {
+        self.sequences
+            .write_or_recover()
+            .remove(&(actor.to_string(), self.binding.clone()));
+        Ok(vec![])
+    }
+
     fn get_descriptor(&self) -> Result<Vec<u8>, Box<dyn Error + Sync + Send>> {
         Ok(serialize(
             CapabilityDescriptor::builder()
@@ -119,7 +146,12 @@ impl ExtrasCapabilityProvider {
                 .with_operation(
                     OP_REQUEST_SEQUENCE,
                     OperationDirection::ToProvider,
-                    "Requests the next number in a process-wide global sequence number",
+                    "Requests the next number in the calling actor's sequence for the binding",
+                )
+                .with_operation(
+                    OP_RESET_SEQUENCE,
+                    OperationDirection::ToProvider,
+                    "Starts the calling actor's sequence for the binding over from 0",
                 )
                 .build(),
         )?)
@@ -132,7 +164,7 @@ impl CapabilityProvider for ExtrasCapabilityProvider {
         dispatcher: Box<dyn wascc_codec::capabilities::Dispatcher>,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         trace!("Dispatcher received.");
-        let mut lock = self.dispatcher.write().unwrap();
+        let mut lock = self.dispatcher.write_or_recover();
         *lock = dispatcher;
 
         Ok(())
@@ -151,8 +183,73 @@ impl CapabilityProvider for ExtrasCapabilityProvider {
             OP_REQUEST_GUID => self.generate_guid(actor, deserialize(msg)?),
             OP_REQUEST_RANDOM => self.generate_random(actor, deserialize(msg)?),
             OP_REQUEST_SEQUENCE => self.generate_sequence(actor, deserialize(msg)?),
+            OP_RESET_SEQUENCE if actor != SYSTEM_ACTOR => self.reset_sequence(actor),
             OP_BIND_ACTOR => Ok(vec![]),
             _ => Err("bad dispatch".into()),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{ExtrasCapabilityProvider, Sequences, OP_RESET_SEQUENCE};
+    use wascc_codec::capabilities::CapabilityProvider;
+    use wascc_codec::extras::{GeneratorRequest, GeneratorResult, OP_REQUEST_SEQUENCE};
+    use wascc_codec::{deserialize, serialize};
+
+    fn next(provider: &ExtrasCapabilityProvider, actor: &str) -> u64 {
+        let req = serialize(GeneratorRequest {
+            guid: false,
+            sequence: true,
+            random: false,
+            min: 0,
+            max: 0,
+        })
+        .unwrap();
+        let res = provider
+            .handle_call(actor, OP_REQUEST_SEQUENCE, &req)
+            .unwrap();
+        let res: GeneratorResult = deserialize(&res).unwrap();
+        res.sequence_number
+    }
+
+    #[test]
+    fn sequences_kept_per_actor_and_binding() {
+        let sequences = Sequences::default();
+        let orders = ExtrasCapabilityProvider::new("orders", sequences.clone());
+        let invoices = ExtrasCapabilityProvider::new("invoices", sequences.clone());
+
+        assert_eq!(0, next(&orders, "Mxxxx"));
+        assert_eq!(1, next(&orders, "Mxxxx"));
+        assert_eq!(2, next(&orders, "Mxxxx"));
+        assert_eq!(0, next(&invoices, "Mxxxx"));
+        assert_eq!(3, next(&orders, "Mxxxx"));
+        assert_eq!(1, next(&invoices, "Mxxxx"));
+        assert_eq!(0, next(&orders, "Myyyy"));
+    }
+
+    #[test]
+    fn reset_only_affects_callers_sequence() {
+        let sequences = Sequences::default();
+        let orders = ExtrasCapabilityProvider::new("orders", sequences.clone());
+        let invoices = ExtrasCapabilityProvider::new("invoices", sequences.clone());
+        for _ in 0..3 {
+            next(&orders, "Mxxxx");
+            next(&orders, "Myyyy");
+            next(&invoices, "Mxxxx");
+        }
+
+        assert!(orders
+            .handle_call("Mxxxx", OP_RESET_SEQUENCE, &[])
+            .unwrap()
+            .is_empty());
+        assert_eq!(0, next(&orders, "Mxxxx"));
+        assert_eq!(3, next(&orders, "Myyyy"));
+        assert_eq!(3, next(&invoices, "Mxxxx"));
+
+        // The host itself has no sequence to reset
+        assert!(orders
+            .handle_call(wascc_codec::SYSTEM_ACTOR, OP_RESET_SEQUENCE, &[])
+            .is_err());
+    }
+}
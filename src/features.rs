@@ -0,0 +1,62 @@
+// The feature flags this crate was compiled with, checked in this one place so that a new
+// feature only needs to be added here to show up in `Host::features`, `Host::info`, and the
+// startup banner
+
+// Every optional feature of the crate, in the order they're reported
+const FEATURES: &[(&str, bool)] = &[
+    ("wasmtime", cfg!(feature = "wasmtime")),
+    ("wasm3", cfg!(feature = "wasm3")),
+    ("manifest", cfg!(feature = "manifest")),
+    ("lattice", cfg!(feature = "lattice")),
+    ("test-lattice", cfg!(feature = "test-lattice")),
+    (
+        "prometheus_middleware",
+        cfg!(feature = "prometheus_middleware"),
+    ),
+    ("admin_api", cfg!(feature = "admin_api")),
+    ("watch", cfg!(feature = "watch")),
+    ("signals", cfg!(feature = "signals")),
+    ("testing", cfg!(feature = "testing")),
+    ("chaos", cfg!(feature = "chaos")),
+    ("bin", cfg!(feature = "bin")),
+];
+
+/// The WebAssembly engine actors are run with. When both engines are compiled in, wasmtime is
+/// the one used
+#[cfg(feature = "wasmtime")]
+pub(crate) const ENGINE: &str = "wasmtime";
+#[cfg(all(feature = "wasm3", not(feature = "wasmtime")))]
+pub(crate) const ENGINE: &str = "wasm3";
+#[cfg(not(any(feature = "wasmtime", feature = "wasm3")))]
+pub(crate) const ENGINE: &str = "none";
+
+pub(crate) const LATTICE_ENABLED: bool = cfg!(feature = "lattice");
+
+/// The features this build was compiled with
+pub(crate) fn enabled() -> Vec<&'static str> {
+    FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(f, _)| *f)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::enabled;
+
+    #[test]
+    fn reports_compiled_features() {
+        let features = enabled();
+        assert_eq!(cfg!(feature = "wasmtime"), features.contains(&"wasmtime"));
+        assert_eq!(cfg!(feature = "wasm3"), features.contains(&"wasm3"));
+        assert_eq!(cfg!(feature = "manifest"), features.contains(&"manifest"));
+        assert_eq!(cfg!(feature = "lattice"), features.contains(&"lattice"));
+        assert_eq!(
+            cfg!(feature = "prometheus_middleware"),
+            features.contains(&"prometheus_middleware")
+        );
+        assert_eq!(cfg!(feature = "testing"), features.contains(&"testing"));
+        assert!(!features.contains(&"default"));
+    }
+}
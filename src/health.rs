@@ -0,0 +1,393 @@
+//! # Provider Health Checks
+//!
+//! A native capability provider can stop responding (for example, by deadlocking internally)
+//! while its bus subscription remains in place, which would otherwise go unnoticed until actors
+//! start timing out. When enabled with `HostBuilder::with_health_checks`, the host periodically
+//! sends each of its capability providers an `OP_HEALTH_REQUEST` over the message bus, falling
+//! back to `OP_GET_CAPABILITY_DESCRIPTOR` for providers that don't support health requests.
+//!
+//! Each host probes its own instance of a provider, even where instances in other hosts of a
+//! lattice share its subject. A provider that fails its first probe, or later fails
+//! `unhealthy_threshold` consecutive probes, is marked unhealthy, which emits a
+//! `HostEvent::ProviderUnhealthy`, and the first successful probe afterwards emits a
+//! `HostEvent::ProviderRecovered`. The current state of every provider can be retrieved with
+//! `Host::capability_health`. If `fail_fast` is set, actor invocations of an unhealthy provider
+//! fail immediately with `ErrorKind::ProviderUnhealthy` rather than waiting on the provider.
+
+use crate::bus::MessageBus;
+use crate::events::HostEvent;
+#[cfg(feature = "lattice")]
+use crate::events::LatticeEvent;
+use crate::locks::{MutexExt, RwLockExt};
+use crate::signer::InvocationSigner;
+use crate::{RouteKey, WasccEntity};
+use crossbeam::{Receiver, Sender};
+use crossbeam_channel as channel;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use wascc_codec::capabilities::OP_GET_CAPABILITY_DESCRIPTOR;
+use wascc_codec::core::{HealthRequest, HealthResponse, OP_HEALTH_REQUEST};
+use wascc_codec::{deserialize, serialize, SYSTEM_ACTOR};
+
+/// How often and how strictly capability providers are probed
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthCheckConfig {
+    /// The time between rounds of probes
+    pub interval: Duration,
+    /// How long a provider has to answer a probe before the probe counts as failed
+    pub timeout: Duration,
+    /// The number of consecutive failed probes after which a provider is marked unhealthy
+    pub unhealthy_threshold: u32,
+    /// Whether actor invocations of an unhealthy provider should fail immediately
+    pub fail_fast: bool,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        HealthCheckConfig {
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(2),
+            unhealthy_threshold: 3,
+            fail_fast: false,
+        }
+    }
+}
+
+/// The health of a capability provider as determined by its most recent probes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HealthStatus {
+    /// The provider hasn't been probed yet
+    Unknown,
+    /// The provider answered its most recent probe, or has since failed fewer than the threshold
+    Healthy,
+    /// The provider failed its first probe, or has failed at least the threshold number of
+    /// consecutive probes
+    Unhealthy,
+}
+
+/// The health of a single capability provider (capability ID and binding name)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProviderHealth {
+    pub status: HealthStatus,
+    /// The number of probes that have failed since the last successful probe
+    pub consecutive_failures: u32,
+    /// When the provider was last probed, if ever
+    pub last_probe_at: Option<SystemTime>,
+    /// The reason the most recent failed probe failed, cleared by a successful probe
+    pub last_error: Option<String>,
+}
+
+impl Default for ProviderHealth {
+    fn default() -> Self {
+        ProviderHealth {
+            status: HealthStatus::Unknown,
+            consecutive_failures: 0,
+            last_probe_at: None,
+            last_error: None,
+        }
+    }
+}
+
+pub(crate) struct HealthMonitor {
+    config: Option<HealthCheckConfig>,
+    providers: RwLock<HashMap<RouteKey, ProviderHealth>>,
+    // Probes that haven't answered yet, which aren't sent again until they do
+    in_flight: Arc<Mutex<HashSet<RouteKey>>>,
+    // Dropped to stop the prober thread
+    stop: Mutex<Option<Sender<()>>>,
+}
+
+impl HealthMonitor {
+    pub fn new(config: Option<HealthCheckConfig>) -> HealthMonitor {
+        HealthMonitor {
+            config,
+            providers: RwLock::new(HashMap::new()),
+            in_flight: Arc::new(Mutex::new(HashSet::new())),
+            stop: Mutex::new(None),
+        }
+    }
+
+    /// Whether invocations of the provider should be rejected without being delivered
+    pub fn rejects(&self, capid: &str, binding: &str) -> bool {
+        self.config.as_ref().map_or(false, |c| c.fail_fast)
+            && self
+                .providers
+                .read_or_recover()
+                .get(&RouteKey::new(binding, capid))
+                .map_or(false, |h| h.status == HealthStatus::Unhealthy)
+    }
+
+    pub fn snapshot(&self) -> HashMap<(String, String), ProviderHealth> {
+        self.providers
+            .read_or_recover()
+            .iter()
+            .map(|(k, v)| ((k.binding_name.to_string(), k.capid.to_string()), v.clone()))
+            .collect()
+    }
+
+    /// Records the outcome of a probe, returning the event to publish if the provider's
+    /// health changed as a result
+    pub fn record(
+        &self,
+        key: &RouteKey,
+        result: std::result::Result<(), String>,
+    ) -> Option<HostEvent> {
+        let threshold = self
+            .config
+            .as_ref()
+            .map_or(1, |c| c.unhealthy_threshold.max(1));
+        let mut providers = self.providers.write_or_recover();
+        let health = providers.entry(key.clone()).or_default();
+        let was_unhealthy = health.status == HealthStatus::Unhealthy;
+        health.last_probe_at = Some(SystemTime::now());
+        match result {
+            Ok(()) => {
+                health.status = HealthStatus::Healthy;
+                health.consecutive_failures = 0;
+                health.last_error = None;
+                if was_unhealthy {
+                    info!(
+                        "Capability provider {},{} recovered",
+                        key.binding_name, key.capid
+                    );
+                    return Some(HostEvent::ProviderRecovered {
+                        capid: key.capid.to_string(),
+                        binding: key.binding_name.to_string(),
+                    });
+                }
+            }
+            Err(reason) => {
+                health.consecutive_failures += 1;
+                health.last_error = Some(reason.to_string());
+                // A provider that has never answered a probe gets no benefit of the doubt
+                if health.consecutive_failures >= threshold
+                    || health.status == HealthStatus::Unknown
+                {
+                    health.status = HealthStatus::Unhealthy;
+                    if !was_unhealthy {
+                        error!(
+                            "Capability provider {},{} is unhealthy: {}",
+                            key.binding_name, key.capid, reason
+                        );
+                        return Some(HostEvent::ProviderUnhealthy {
+                            capid: key.capid.to_string(),
+                            binding: key.binding_name.to_string(),
+                            reason,
+                        });
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    // Providers that have been removed from the host are no longer tracked
+    fn retain(&self, keys: &[RouteKey]) {
+        self.providers
+            .write_or_recover()
+            .retain(|k, _| keys.contains(k));
+    }
+
+    /// Stops probing, e.g. because the host is shutting down
+    pub fn stop(&self) {
+        self.stop.lock_or_recover().take();
+    }
+}
+
+/// Starts the thread that probes the host's capability providers, if health checks are enabled
+pub(crate) fn spawn_prober(host: &crate::Host) {
+    let config = match host.health.config.clone() {
+        Some(c) => c,
+        None => return,
+    };
+    let (stop_s, stop_r): (Sender<()>, Receiver<()>) = channel::bounded(1);
+    *host.health.stop.lock_or_recover() = Some(stop_s);
+    let health = host.health.clone();
+    let bus = host.bus.clone();
+    let caps = host.caps.clone();
+    let signer = host.signer.clone();
+
+    std::thread::spawn(move || loop {
+        match stop_r.recv_timeout(config.interval) {
+            Err(channel::RecvTimeoutError::Timeout) => {}
+            _ => break,
+        }
+        let keys: Vec<RouteKey> = caps.read_or_recover().keys().cloned().collect();
+        health.retain(&keys);
+        for key in keys {
+            // A probe that still hasn't answered counts against the provider again
+            let result = if health.in_flight.lock_or_recover().contains(&key) {
+                Err("Previous health probe has not been answered".to_string())
+            } else {
+                probe(&bus, &signer, &key, config.timeout, &health.in_flight)
+            };
+            if let Some(evt) = health.record(&key, result) {
+                #[cfg(feature = "lattice")]
+                {
+                    if let Some(le) = LatticeEvent::from_health(&evt, signer.host_id()) {
+                        let _ = bus.publish_lattice_event(le);
+                    }
+                }
+                bus.publish_host_event(evt);
+            }
+        }
+    });
+}
+
+// Sends a health request to the provider, falling back to a descriptor query for providers
+// that don't support health requests
+fn probe(
+    bus: &Arc<MessageBus>,
+    signer: &InvocationSigner,
+    key: &RouteKey,
+    timeout: Duration,
+    in_flight: &Arc<Mutex<HashSet<RouteKey>>>,
+) -> std::result::Result<(), String> {
+    let req = serialize(HealthRequest { placeholder: false }).map_err(|e| e.to_string())?;
+    let resp = invoke_with_timeout(bus, signer, key, OP_HEALTH_REQUEST, req, timeout, in_flight)?;
+    match resp {
+        Ok(msg) => match deserialize::<HealthResponse>(&msg) {
+            Ok(HealthResponse {
+                healthy: false,
+                message,
+            }) => Err(format!("Provider reported itself unhealthy: {}", message)),
+            _ => Ok(()),
+        },
+        Err(_) => invoke_with_timeout(
+            bus,
+            signer,
+            key,
+            OP_GET_CAPABILITY_DESCRIPTOR,
+            vec![],
+            timeout,
+            in_flight,
+        )?
+        .map(|_| ())
+        .map_err(|e| format!("Provider failed descriptor query: {}", e)),
+    }
+}
+
+// The outer result is a failure to get any answer from the provider, the inner result is
+// the provider's own answer
+fn invoke_with_timeout(
+    bus: &Arc<MessageBus>,
+    signer: &InvocationSigner,
+    key: &RouteKey,
+    operation: &str,
+    msg: Vec<u8>,
+    timeout: Duration,
+    in_flight: &Arc<Mutex<HashSet<RouteKey>>>,
+) -> std::result::Result<std::result::Result<Vec<u8>, String>, String> {
+    let inv = signer.invocation(
+        WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
+        WasccEntity::Capability {
+            capid: key.capid.to_string(),
+            binding: key.binding_name.to_string(),
+        },
+        operation,
+        msg,
+    );
+    let subject = bus.provider_subject(&key.capid, &key.binding_name);
+    let (s, r) = channel::bounded(1);
+    in_flight.lock_or_recover().insert(key.clone());
+    {
+        let bus = bus.clone();
+        let key = key.clone();
+        let in_flight = in_flight.clone();
+        // The probe goes to this host's own instance of the provider, which mustn't be masked
+        // by an instance in another host of the lattice answering in its place. Waiting for a
+        // turn with a stuck provider can outlast the timeout, so the probe can't be sent from
+        // the prober thread itself
+        std::thread::spawn(move || {
+            let res = bus.invoke_own(&subject, inv, timeout);
+            in_flight.lock_or_recover().remove(&key);
+            let _ = s.send(res);
+        });
+    }
+    match r.recv_timeout(timeout) {
+        Ok(Ok(inv_r)) => Ok(match inv_r.error {
+            Some(e) => Err(e),
+            None => Ok(inv_r.msg),
+        }),
+        Ok(Err(e)) => Err(format!("Health probe failed: {}", e)),
+        Err(_) => Err(format!(
+            "Health probe was not answered within {}ms",
+            timeout.as_millis()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HealthCheckConfig, HealthMonitor, HealthStatus};
+    use crate::events::HostEvent;
+    use crate::RouteKey;
+
+    #[test]
+    fn transitions_at_threshold() {
+        let hm = HealthMonitor::new(Some(HealthCheckConfig {
+            unhealthy_threshold: 2,
+            fail_fast: true,
+            ..Default::default()
+        }));
+        let key = RouteKey::new("default", "wascc:keyvalue");
+
+        assert!(hm.record(&key, Ok(())).is_none());
+        assert!(hm.record(&key, Err("stuck".into())).is_none());
+        assert!(!hm.rejects("wascc:keyvalue", "default"));
+        assert_eq!(
+            Some(HostEvent::ProviderUnhealthy {
+                capid: "wascc:keyvalue".into(),
+                binding: "default".into(),
+                reason: "stuck".into(),
+            }),
+            hm.record(&key, Err("stuck".into()))
+        );
+        assert!(hm.record(&key, Err("stuck".into())).is_none());
+        assert!(hm.rejects("wascc:keyvalue", "default"));
+        let h = &hm.snapshot()[&("default".to_string(), "wascc:keyvalue".to_string())];
+        assert_eq!(HealthStatus::Unhealthy, h.status);
+        assert_eq!(3, h.consecutive_failures);
+
+        assert_eq!(
+            Some(HostEvent::ProviderRecovered {
+                capid: "wascc:keyvalue".into(),
+                binding: "default".into(),
+            }),
+            hm.record(&key, Ok(()))
+        );
+        assert!(!hm.rejects("wascc:keyvalue", "default"));
+    }
+
+    #[test]
+    fn first_failure_unhealthy() {
+        let hm = HealthMonitor::new(Some(HealthCheckConfig {
+            unhealthy_threshold: 3,
+            ..Default::default()
+        }));
+        let key = RouteKey::new("default", "wascc:keyvalue");
+        assert_eq!(
+            Some(HostEvent::ProviderUnhealthy {
+                capid: "wascc:keyvalue".into(),
+                binding: "default".into(),
+                reason: "stuck".into(),
+            }),
+            hm.record(&key, Err("stuck".into()))
+        );
+        let h = &hm.snapshot()[&("default".to_string(), "wascc:keyvalue".to_string())];
+        assert_eq!(HealthStatus::Unhealthy, h.status);
+        assert_eq!(1, h.consecutive_failures);
+    }
+
+    #[test]
+    fn fail_fast_is_opt_in() {
+        let hm = HealthMonitor::new(Some(HealthCheckConfig {
+            unhealthy_threshold: 1,
+            ..Default::default()
+        }));
+        let key = RouteKey::new("default", "wascc:keyvalue");
+        hm.record(&key, Err("stuck".into()));
+        assert!(!hm.rejects("wascc:keyvalue", "default"));
+    }
+}
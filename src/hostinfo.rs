@@ -0,0 +1,110 @@
+// Introspection of a host's identity, uptime, and build, for fingerprinting hosts from tooling
+// and support logs
+
+use crate::features;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+/// Identifies a running host and the build of this crate it is running, as returned by
+/// `Host::info`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    any(feature = "lattice", feature = "manifest", feature = "admin_api"),
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct HostInfo {
+    /// The host's public key
+    pub id: String,
+    /// The version of this crate (`VERSION`)
+    pub version: String,
+    /// The revision of this crate (`REVISION`)
+    pub revision: u32,
+    /// How long ago the host was created
+    pub uptime: Duration,
+    /// The lattice namespace of the host, if any
+    pub namespace: Option<String>,
+    /// Whether this build supports lattice mode
+    pub lattice_enabled: bool,
+    /// The WebAssembly engine actors are run with (`wasmtime` or `wasm3`)
+    pub engine: String,
+    /// The optional features this build was compiled with, such as `manifest` or
+    /// `prometheus_middleware` (see `Host::features`)
+    pub features: Vec<String>,
+    pub labels: HashMap<String, String>,
+    /// The capability IDs of the providers the host may load, if restricted with
+    /// `HostBuilder::with_capability_allowlist`. Empty if the host is actor-only (see
+    /// `HostBuilder::actor_only`)
+    #[cfg_attr(
+        any(feature = "lattice", feature = "manifest", feature = "admin_api"),
+        serde(default)
+    )]
+    pub capability_allowlist: Option<Vec<String>>,
+}
+
+impl HostInfo {
+    pub(crate) fn new(
+        id: &str,
+        started: SystemTime,
+        namespace: Option<String>,
+        labels: HashMap<String, String>,
+        capability_allowlist: Option<Vec<String>>,
+    ) -> HostInfo {
+        HostInfo {
+            id: id.to_string(),
+            version: crate::VERSION.to_string(),
+            revision: crate::REVISION,
+            uptime: started.elapsed().unwrap_or_default(),
+            namespace,
+            lattice_enabled: features::LATTICE_ENABLED,
+            engine: features::ENGINE.to_string(),
+            features: features::enabled().into_iter().map(String::from).collect(),
+            labels,
+            capability_allowlist,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HostInfo;
+    use std::collections::HashMap;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn info_describes_build() {
+        let started = SystemTime::now() - Duration::from_secs(5);
+        let info = HostInfo::new(
+            "Nxxx",
+            started,
+            Some("prod".to_string()),
+            HashMap::new(),
+            None,
+        );
+        assert_eq!("Nxxx", info.id);
+        assert_eq!(crate::VERSION, info.version);
+        assert_eq!(crate::REVISION, info.revision);
+        assert!(info.uptime >= Duration::from_secs(5));
+        assert_eq!(Some("prod".to_string()), info.namespace);
+        assert_eq!(cfg!(feature = "lattice"), info.lattice_enabled);
+        if cfg!(feature = "wasmtime") {
+            assert_eq!("wasmtime", info.engine);
+        }
+        assert_eq!(crate::features::enabled(), info.features);
+    }
+
+    #[test]
+    #[cfg(feature = "lattice")]
+    fn info_round_trips() {
+        let mut labels = HashMap::new();
+        labels.insert("region".to_string(), "west".to_string());
+        let info = HostInfo::new(
+            "Nxxx",
+            SystemTime::now(),
+            None,
+            labels,
+            Some(vec!["wascc:keyvalue".to_string()]),
+        );
+        let json = serde_json::to_string(&info).unwrap();
+        assert_eq!(info, serde_json::from_str(&json).unwrap());
+    }
+}
@@ -0,0 +1,170 @@
+//! # Host Metadata Provider
+//!
+//! A built-in "wascc:hostmeta" provider that is included with the host runtime unless disabled
+//! on the host builder. It gives actors attested for the capability read-only access to the
+//! identity of the host they're running in, e.g. for inclusion in telemetry. In a lattice, an
+//! actor's calls are always answered by the provider in its own host. Responses are
+//! msgpack-encoded with `wascc_codec::serialize`:
+//!
+//! * `OP_GET_HOST_ID` - the host's public key, as a `String`
+//! * `OP_GET_LABELS` - the host's labels, as a `HashMap<String, String>`
+//! * `OP_GET_NAMESPACE` - the host's lattice namespace, as an `Option<String>`
+//!
+//! Requests carry no payload. Labels are read at the time of each call, so labels changed
+//! while the host is running (e.g. by applying a manifest) are visible to actors.
+
+use crate::locks::RwLockExt;
+use crate::{REVISION, VERSION};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use wascc_codec::capabilities::{
+    CapabilityDescriptor, CapabilityProvider, Dispatcher, NullDispatcher, OperationDirection,
+    OP_GET_CAPABILITY_DESCRIPTOR,
+};
+use wascc_codec::core::{OP_BIND_ACTOR, OP_REMOVE_ACTOR};
+use wascc_codec::{serialize, SYSTEM_ACTOR};
+
+/// The capability ID of the host metadata provider
+pub const CAPABILITY_ID: &str = "wascc:hostmeta";
+
+/// Requests the public key of the host running the calling actor
+pub const OP_GET_HOST_ID: &str = "GetHostId";
+/// Requests the labels of the host running the calling actor
+pub const OP_GET_LABELS: &str = "GetLabels";
+/// Requests the lattice namespace of the host running the calling actor, if it has one
+pub const OP_GET_NAMESPACE: &str = "GetNamespace";
+
+pub(crate) struct HostMetaCapabilityProvider {
+    dispatcher: Arc<RwLock<Box<dyn Dispatcher>>>,
+    host_id: String,
+    labels: Arc<RwLock<HashMap<String, String>>>,
+    ns: Option<String>,
+}
+
+impl HostMetaCapabilityProvider {
+    pub fn new(
+        host_id: &str,
+        labels: Arc<RwLock<HashMap<String, String>>>,
+        ns: Option<String>,
+    ) -> Self {
+        HostMetaCapabilityProvider {
+            dispatcher: Arc::new(RwLock::new(Box::new(NullDispatcher::new()))),
+            host_id: host_id.to_string(),
+            labels,
+            ns,
+        }
+    }
+
+    fn get_descriptor(&self) -> Result<Vec<u8>, Box<dyn Error + Sync + Send>> {
+        Ok(serialize(
+            CapabilityDescriptor::builder()
+                .id(CAPABILITY_ID)
+                .name("waSCC Host Metadata (Internal)")
+                .long_description(
+                    "A capability provider exposing the identity of the host to the actors it runs",
+                )
+                .version(VERSION)
+                .revision(REVISION)
+                .with_operation(
+                    OP_GET_HOST_ID,
+                    OperationDirection::ToProvider,
+                    "Requests the public key of the host",
+                )
+                .with_operation(
+                    OP_GET_LABELS,
+                    OperationDirection::ToProvider,
+                    "Requests the current labels of the host",
+                )
+                .with_operation(
+                    OP_GET_NAMESPACE,
+                    OperationDirection::ToProvider,
+                    "Requests the lattice namespace of the host, if any",
+                )
+                .build(),
+        )?)
+    }
+}
+
+impl CapabilityProvider for HostMetaCapabilityProvider {
+    fn configure_dispatch(
+        &self,
+        dispatcher: Box<dyn Dispatcher>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        trace!("Dispatcher received.");
+        let mut lock = self.dispatcher.write_or_recover();
+        *lock = dispatcher;
+
+        Ok(())
+    }
+
+    fn handle_call(
+        &self,
+        actor: &str,
+        op: &str,
+        _msg: &[u8],
+    ) -> Result<Vec<u8>, Box<dyn Error + Sync + Send>> {
+        trace!("Received host call from {}, operation - {}", actor, op);
+
+        match op {
+            OP_GET_CAPABILITY_DESCRIPTOR if actor == SYSTEM_ACTOR => self.get_descriptor(),
+            OP_GET_HOST_ID => Ok(serialize(&self.host_id)?),
+            OP_GET_LABELS => Ok(serialize(&*self.labels.read_or_recover())?),
+            OP_GET_NAMESPACE => Ok(serialize(&self.ns)?),
+            OP_BIND_ACTOR | OP_REMOVE_ACTOR => Ok(vec![]),
+            _ => Err("bad dispatch".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CAPABILITY_ID, OP_GET_HOST_ID, OP_GET_LABELS, OP_GET_NAMESPACE};
+    use crate::{HostBuilder, Invocation, WasccEntity};
+    use std::collections::HashMap;
+    use wascc_codec::deserialize;
+
+    #[test]
+    fn actors_can_read_host_metadata() {
+        let host = HostBuilder::new().with_label("region", "east").build();
+        let subject = host.bus.provider_subject(CAPABILITY_ID, "default");
+        let call = |op: &str| {
+            let inv = Invocation::test_builder()
+                .with_origin(WasccEntity::Actor("Mxxxx".to_string()))
+                .with_target(WasccEntity::Capability {
+                    capid: CAPABILITY_ID.to_string(),
+                    binding: "default".to_string(),
+                })
+                .with_operation(op)
+                .build();
+            let resp = host.bus.invoke(&subject, inv).unwrap();
+            assert!(resp.error.is_none());
+            resp.msg
+        };
+
+        let id: String = deserialize(&call(OP_GET_HOST_ID)).unwrap();
+        assert_eq!(host.id(), id);
+        let ns: Option<String> = deserialize(&call(OP_GET_NAMESPACE)).unwrap();
+        assert_eq!(host.ns, ns);
+        let labels: HashMap<String, String> = deserialize(&call(OP_GET_LABELS)).unwrap();
+        assert_eq!("east", labels["region"]);
+
+        // Labels changed after the provider started are visible
+        host.labels
+            .write()
+            .unwrap()
+            .insert("region".to_string(), "west".to_string());
+        let labels: HashMap<String, String> = deserialize(&call(OP_GET_LABELS)).unwrap();
+        assert_eq!("west", labels["region"]);
+        host.shutdown().unwrap();
+    }
+
+    #[test]
+    fn provider_can_be_disabled() {
+        let key = ("default".to_string(), CAPABILITY_ID.to_string());
+        assert!(HostBuilder::new().build().capabilities().contains_key(&key));
+        let host = HostBuilder::new().with_hostmeta_provider(false).build();
+        assert!(!host.capabilities().contains_key(&key));
+        host.shutdown().unwrap();
+    }
+}
@@ -0,0 +1,220 @@
+// A lightweight scan of the import section of an actor's WebAssembly module, so that an actor
+// importing functions the host doesn't provide is caught when it's added rather than failing
+// on its first invocation. Only the section headers and the import section are read
+
+use crate::{errors, Actor, Host, Result};
+
+const WAPC_MODULE: &str = "wapc";
+const WASI_MODULES: &[&str] = &["wasi_snapshot_preview1", "wasi_unstable"];
+
+const WASM_MAGIC: &[u8] = b"\0asm";
+const IMPORT_SECTION: u8 = 2;
+
+/// The imports of an actor's WebAssembly module, as returned by `Actor::imports` and reported
+/// in `ActorRuntimeInfo`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportReport {
+    /// Whether the module imports the waPC host functions, through which actors call the host
+    pub wascc_host_calls: bool,
+    /// The WASI modules imported from, e.g. `wasi_snapshot_preview1`
+    pub wasi_modules: Vec<String>,
+    /// Imports from any other module, as `module::name`
+    pub unknown_imports: Vec<String>,
+}
+
+impl ImportReport {
+    /// Scans the import section of a WebAssembly module
+    pub(crate) fn from_module(buf: &[u8]) -> Result<ImportReport> {
+        let mut report = ImportReport::default();
+        let mut r = Reader { buf, pos: 0 };
+        if r.bytes(4)? != WASM_MAGIC {
+            return Err(malformed("missing WebAssembly magic number"));
+        }
+        r.bytes(4)?; // version
+        while !r.done() {
+            let id = r.byte()?;
+            let len = r.leb()? as usize;
+            let section = r.bytes(len)?;
+            if id == IMPORT_SECTION {
+                report.scan(&mut Reader {
+                    buf: section,
+                    pos: 0,
+                })?;
+            }
+        }
+        Ok(report)
+    }
+
+    fn scan(&mut self, r: &mut Reader) -> Result<()> {
+        for _ in 0..r.leb()? {
+            let module = r.name()?;
+            let name = r.name()?;
+            r.skip_import_desc()?;
+            if module == WAPC_MODULE {
+                self.wascc_host_calls = true;
+            } else if WASI_MODULES.contains(&module.as_str()) {
+                if !self.wasi_modules.contains(&module) {
+                    self.wasi_modules.push(module);
+                }
+            } else {
+                self.unknown_imports.push(format!("{}::{}", module, name));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Host {
+    // Actors are run without WASI, and are only given the waPC host functions
+    pub(crate) fn check_imports(&self, actor: &Actor) -> Result<()> {
+        let report = actor.imports();
+        let mut problems = Vec::new();
+        if !report.wasi_modules.is_empty() {
+            problems.push(format!(
+                "imports WASI ({}), which actors aren't given",
+                report.wasi_modules.join(", ")
+            ));
+        }
+        if !report.unknown_imports.is_empty() {
+            problems.push(format!(
+                "imports functions the host doesn't provide ({})",
+                report.unknown_imports.join(", ")
+            ));
+        }
+        if problems.is_empty() {
+            return Ok(());
+        }
+        let msg = format!("Actor {} {}", actor.public_key(), problems.join(" and "));
+        if self.strict_imports {
+            Err(errors::new(errors::ErrorKind::MiscHost(msg)))
+        } else {
+            warn!("{}", msg);
+            Ok(())
+        }
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn done(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let b = *self
+            .buf
+            .get(self.pos)
+            .ok_or_else(|| malformed("unexpected end of module"))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.buf.len() - self.pos < len {
+            return Err(malformed("unexpected end of module"));
+        }
+        let b = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(b)
+    }
+
+    // An unsigned LEB128 integer of at most 32 bits
+    fn leb(&mut self) -> Result<u32> {
+        let mut result = 0_u32;
+        for shift in (0..35).step_by(7) {
+            let b = self.byte()?;
+            result |= ((b & 0x7f) as u32) << shift;
+            if b & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+        Err(malformed("integer too long"))
+    }
+
+    fn name(&mut self) -> Result<String> {
+        let len = self.leb()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| malformed("invalid import name"))
+    }
+
+    fn limits(&mut self) -> Result<()> {
+        let flags = self.byte()?;
+        self.leb()?;
+        if flags & 0x01 != 0 {
+            self.leb()?;
+        }
+        Ok(())
+    }
+
+    fn skip_import_desc(&mut self) -> Result<()> {
+        match self.byte()? {
+            0x00 => self.leb().map(|_| ()), // function, by type index
+            0x01 => {
+                self.byte()?; // table element type
+                self.limits()
+            }
+            0x02 => self.limits(),             // memory
+            0x03 => self.bytes(2).map(|_| ()), // global value type and mutability
+            kind => Err(malformed(&format!("unknown import kind {}", kind))),
+        }
+    }
+}
+
+fn malformed(reason: &str) -> errors::Error {
+    errors::new(errors::ErrorKind::MiscHost(format!(
+        "Failed to read the module's imports: {}",
+        reason
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::ImportReport;
+
+    // A module with an import section holding the given (module, name) function imports
+    fn module(imports: &[(&str, &str)]) -> Vec<u8> {
+        let mut section = vec![imports.len() as u8];
+        for (module, name) in imports {
+            section.push(module.len() as u8);
+            section.extend_from_slice(module.as_bytes());
+            section.push(name.len() as u8);
+            section.extend_from_slice(name.as_bytes());
+            section.extend_from_slice(&[0x00, 0x00]);
+        }
+        let mut buf = b"\0asm\x01\0\0\0".to_vec();
+        buf.extend_from_slice(&[0x01, 0x04, 0x01, 0x60, 0x00, 0x00]); // type section
+        buf.push(0x02);
+        buf.push(section.len() as u8);
+        buf.extend_from_slice(&section);
+        buf
+    }
+
+    #[test]
+    fn imports_classified() {
+        let report = ImportReport::from_module(&module(&[
+            ("wapc", "__host_call"),
+            ("wasi_snapshot_preview1", "fd_write"),
+            ("wasi_snapshot_preview1", "proc_exit"),
+            ("env", "abort"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            ImportReport {
+                wascc_host_calls: true,
+                wasi_modules: vec!["wasi_snapshot_preview1".to_string()],
+                unknown_imports: vec!["env::abort".to_string()],
+            },
+            report
+        );
+    }
+
+    #[test]
+    fn truncated_module_rejected() {
+        let buf = module(&[("wapc", "__host_call")]);
+        assert!(ImportReport::from_module(&buf[..buf.len() - 3]).is_err());
+        assert!(ImportReport::from_module(b"not wasm").is_err());
+    }
+}
@@ -0,0 +1,117 @@
+//! Tracking of the invocations currently being handled by each actor and capability provider
+//! in this host, for `Host::in_flight` and `Host::wait_for_idle`
+
+use crate::locks::MutexExt;
+use crate::WasccEntity;
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+pub(crate) struct InFlight {
+    // Entity URL -> number of invocations being handled. Entities with none are removed
+    counts: Mutex<HashMap<String, usize>>,
+    idle: Condvar,
+}
+
+/// Marks an invocation as in flight until dropped
+pub(crate) struct InFlightGuard<'a> {
+    tracker: &'a InFlight,
+    url: String,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        let mut counts = self.tracker.counts.lock_or_recover();
+        if let Some(n) = counts.get_mut(&self.url) {
+            *n -= 1;
+            if *n == 0 {
+                counts.remove(&self.url);
+            }
+        }
+        self.tracker.idle.notify_all();
+    }
+}
+
+impl InFlight {
+    pub fn new() -> InFlight {
+        InFlight {
+            counts: Mutex::new(HashMap::new()),
+            idle: Condvar::new(),
+        }
+    }
+
+    /// Records the start of an invocation handled by the entity, which ends when the returned
+    /// guard is dropped
+    pub fn begin(&self, entity: &WasccEntity) -> InFlightGuard {
+        let url = entity.url();
+        *self
+            .counts
+            .lock_or_recover()
+            .entry(url.to_string())
+            .or_insert(0) += 1;
+        InFlightGuard { tracker: self, url }
+    }
+
+    pub fn counts(&self) -> HashMap<String, usize> {
+        self.counts.lock_or_recover().clone()
+    }
+
+    /// Blocks until none of the entities whose URLs satisfy the filter have invocations in
+    /// flight, returning the number still in flight if the timeout elapses first
+    pub fn wait_idle(
+        &self,
+        timeout: Duration,
+        filter: impl Fn(&str) -> bool,
+    ) -> std::result::Result<(), usize> {
+        let deadline = Instant::now() + timeout;
+        let mut counts = self.counts.lock_or_recover();
+        loop {
+            let busy: usize = counts
+                .iter()
+                .filter(|(url, _)| filter(url))
+                .map(|(_, n)| n)
+                .sum();
+            if busy == 0 {
+                return Ok(());
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(busy);
+            }
+            counts = self.idle.wait_timeout(counts, deadline - now).unwrap().0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::InFlight;
+    use crate::WasccEntity;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn waits_for_guards() {
+        let tracker = Arc::new(InFlight::new());
+        let actor = WasccEntity::Actor("Mxxxx".to_string());
+        let a = tracker.begin(&actor);
+        let b = tracker.begin(&actor);
+        assert_eq!(2, tracker.counts()["wasmbus://Mxxxx"]);
+        drop(a);
+        assert_eq!(
+            Err(1),
+            tracker.wait_idle(Duration::from_millis(10), |_| true)
+        );
+        // Entities excluded by the filter don't count
+        assert!(tracker
+            .wait_idle(Duration::from_millis(10), |u| u != "wasmbus://Mxxxx")
+            .is_ok());
+
+        let t = tracker.clone();
+        let waiter = std::thread::spawn(move || t.wait_idle(Duration::from_secs(5), |_| true));
+        std::thread::sleep(Duration::from_millis(50));
+        drop(b);
+        assert!(waiter.join().unwrap().is_ok());
+        assert!(tracker.counts().is_empty());
+    }
+}
@@ -1,20 +1,25 @@
 // Implementations of support functions for the `Host` struct
 
 use super::Host;
+use crate::locks::RwLockExt;
 use crate::Result;
 use data_encoding::HEXUPPER;
 use ring::digest::{Context, Digest, SHA256};
 
+use crate::antiforgery::AntiforgeryCache;
+use crate::bindings::{BindingKey, Bindings};
 use crate::bus;
 use crate::bus::MessageBus;
-use crate::BindingsList;
-use crate::{authz, errors, Actor, Authorizer, NativeCapability, RouteKey};
+use crate::signer::InvocationSigner;
+use crate::stats::{REASON_AUTHORIZER_DENIED, REASON_MISSING_ATTESTATION};
+use crate::{authz, errors, Actor, Authorizer, HostEvent, NativeCapability, RouteKey};
 use errors::ErrorKind;
 use provider_archive::ProviderArchive;
 use std::str::FromStr;
 use std::{
     collections::HashMap,
     io::Read,
+    path::{Path, PathBuf},
     sync::{Arc, RwLock},
 };
 use uuid::Uuid;
@@ -22,7 +27,7 @@ use wapc::WapcHost;
 use wascap::{jwt::Claims, prelude::KeyPair};
 use wascc_codec::{
     capabilities::{CapabilityDescriptor, OP_GET_CAPABILITY_DESCRIPTOR},
-    core::{CapabilityConfiguration, OP_PERFORM_LIVE_UPDATE, OP_REMOVE_ACTOR},
+    core::{CapabilityConfiguration, OP_BIND_ACTOR, OP_PERFORM_LIVE_UPDATE, OP_REMOVE_ACTOR},
     deserialize, serialize, SYSTEM_ACTOR,
 };
 
@@ -33,22 +38,110 @@ pub(crate) const CORELABEL_OSFAMILY: &str = "hostcore.osfamily";
 pub(crate) const OCI_VAR_USER: &str = "OCI_REGISTRY_USER";
 pub(crate) const OCI_VAR_PASSWORD: &str = "OCI_REGISTRY_PASSWORD";
 
+// How long an actor may take to handle the notice that one of its bindings was removed
+const NOTIFY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+// Lock ordering
+//
+// The host's state is kept behind `RwLock`s shared with the threads serving actors and
+// providers. An invocation over the bus blocks until another thread answers it, and that thread
+// may itself need the host's state, e.g. when a provider's OP_BIND_ACTOR handler dispatches to
+// the actor being bound and the actor calls out to another provider. Since a waiting writer
+// blocks new readers, even a read lock held across an invocation can deadlock, so:
+//
+// 1. No lock is held across a bus invocation, a dispatch, or a call into a provider or actor,
+//    with the exceptions below. Copy what's needed out of the guard and let it go first, as
+//    `deconfigure_actor` does with the actor's bindings and `PluginManager::call` does with the
+//    provider.
+// 2. Locks are otherwise held one at a time where possible. Where several are held together,
+//    they're taken in the order claims, capabilities, bindings, labels (as `Host::snapshot`
+//    takes all four), and the authorizer is never held while another lock is taken (so the
+//    authorization context, which reads the labels, is gathered beforehand).
+// 3. The middleware pipeline is an exception to the first rule: it's read for the length of an
+//    invocation, so `Host::add_middleware` waits for invocations in flight and mustn't be called
+//    from within an actor, provider, or middleware.
+// 4. The binding locks (`Host::binding_changes`) are the other exception. A binding's lock is
+//    held while the binding is delivered to the provider's instances and recorded, so that
+//    changes to the same binding are applied one after another, and an extras provider's lock is
+//    held while `release_extras_binding` removes the provider. Each lock covers a single binding
+//    or provider, is taken before any of the host's other locks, and is never taken by an actor,
+//    provider, or middleware, so the threads answering the invocation can't end up waiting on it.
+
+/// The most entities an invocation's origin chain may hold. A call that would exceed it, such as
+/// one made within a loop of actors calling each other, is refused
+pub const MAX_ORIGIN_CHAIN: usize = 16;
+
+/// Prefixes an operation name in an actor's host call to give the payload's content type, as
+/// the waPC host callback has no other room for it: the operation
+/// `__wascc_content_type=application/json|Store` calls `Store` with a payload of type
+/// `application/json`. The content type ends at the first `|`
+pub const CONTENT_TYPE_OPERATION_PREFIX: &str = "__wascc_content_type=";
+
+thread_local! {
+    // The origin chain of the invocation being handled by the module running on this thread,
+    // read when the module calls out through `wapc_host_callback`
+    static ACTIVE_CHAIN: std::cell::RefCell<Vec<String>> = std::cell::RefCell::new(Vec::new());
+}
+
+// Runs the function (which invokes a module) with the given origin chain as the chain of the
+// invocation being handled on this thread. The previous chain is restored even if the function
+// panics, so a guest panic caught further up doesn't leave its chain on the thread
+pub(crate) fn with_origin_chain<T>(chain: &[String], f: impl FnOnce() -> T) -> T {
+    struct Restore(Option<Vec<String>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            if let Some(previous) = self.0.take() {
+                ACTIVE_CHAIN.with(|c| c.replace(previous));
+            }
+        }
+    }
+    let _restore = Restore(Some(ACTIVE_CHAIN.with(|c| c.replace(chain.to_vec()))));
+    f()
+}
+
+/// A function registered by an embedder to be run at a point in the host's lifecycle
+pub(crate) type Hook = Box<dyn FnOnce() + Send>;
+
+pub(crate) fn run_hooks(kind: &str, hooks: Vec<Hook>) {
+    for hook in hooks {
+        if std::panic::catch_unwind(std::panic::AssertUnwindSafe(hook)).is_err() {
+            error!("A host {} hook panicked", kind);
+        }
+    }
+}
+
+/// Records where a running actor or capability provider came from and when it started
+#[derive(Debug, Clone)]
+pub(crate) struct Origin {
+    pub image_ref: Option<String>,
+    pub started_at: std::time::SystemTime,
+}
+
+impl Origin {
+    pub fn new(image_ref: Option<String>) -> Origin {
+        Origin {
+            image_ref,
+            started_at: std::time::SystemTime::now(),
+        }
+    }
+}
+
 #[allow(dead_code)]
 pub(crate) const RESTRICTED_LABELS: [&str; 3] = [CORELABEL_OSFAMILY, CORELABEL_ARCH, CORELABEL_OS];
 
 // Unsubscribes all of the private actor-provider comms subjects
 pub(crate) fn unsub_all_bindings(
-    bindings: Arc<RwLock<BindingsList>>,
+    bindings: Arc<RwLock<Bindings>>,
     bus: Arc<MessageBus>,
     capid: &str,
+    binding: &str,
 ) {
     bindings
-        .read()
-        .unwrap()
-        .keys()
-        .filter(|(_a, c, _b)| c == capid)
-        .for_each(|(a, c, b)| {
-            let _ = bus.unsubscribe(&bus.provider_subject_bound_actor(c, b, a));
+        .read_or_recover()
+        .for_provider(capid, binding)
+        .for_each(|(k, _)| {
+            let _ =
+                bus.unsubscribe(&bus.provider_subject_bound_actor(&k.capid, &k.binding, &k.actor));
         });
 }
 
@@ -58,11 +151,14 @@ impl Host {
         actor: &str,
         capid: &str,
         binding: &str,
+        config_name: Option<&str>,
         config: &CapabilityConfiguration,
     ) -> Result<()> {
-        let mut lock = self.bindings.write().unwrap();
-        lock.insert(
-            (actor.to_string(), capid.to_string(), binding.to_string()),
+        self.bindings.write_or_recover().insert_named(
+            actor,
+            capid,
+            binding,
+            config_name,
             config.clone(),
         );
         trace!(
@@ -74,20 +170,176 @@ impl Host {
         Ok(())
     }
 
+    // The subset of this host's labels that have been selected for forwarding to providers
+    pub(crate) fn forwarded_labels(&self) -> HashMap<String, String> {
+        let labels = self.labels.read_or_recover();
+        self.binding_metadata
+            .iter()
+            .filter_map(|l| labels.get(l).map(|v| (l.to_string(), v.to_string())))
+            .collect()
+    }
+
     pub(crate) fn ensure_extras(&self) -> Result<()> {
+        if let Some(ref sequences) = self.extras_sequences {
+            self.add_native_capability(NativeCapability::from_instance(
+                crate::extras::ExtrasCapabilityProvider::new(
+                    &self.default_binding,
+                    sequences.clone(),
+                ),
+                None,
+            )?)?;
+        }
+        Ok(())
+    }
+
+    // The extras provider is served on any binding name an actor is bound to it with, so that
+    // each binding has its own sequences
+    pub(crate) fn ensure_extras_binding(&self, binding: &str) -> Result<()> {
+        let sequences = match self.extras_sequences {
+            Some(ref s) => s.clone(),
+            None => return Ok(()),
+        };
+        let route_key = RouteKey::new(binding, crate::extras::CAPABILITY_ID);
+        if self.caps.read_or_recover().contains_key(&route_key) {
+            return Ok(());
+        }
+        match self.add_native_capability(NativeCapability::from_instance(
+            crate::extras::ExtrasCapabilityProvider::new(binding, sequences),
+            Some(binding.to_string()),
+        )?) {
+            // Another binding may have added it in the meantime
+            Err(_) if self.caps.read_or_recover().contains_key(&route_key) => Ok(()),
+            res => res,
+        }
+    }
+
+    // Removes the extras provider served on a binding name other than the default once no
+    // actor is bound to it with that name, so names that fall out of use don't each leave a
+    // provider behind. Takes the provider's binding lock, so it can't remove a provider that a
+    // binding being applied has just ensured
+    pub(crate) fn release_extras_binding(&self, binding: &str) {
+        if self.extras_sequences.is_none() || binding == self.default_binding {
+            return;
+        }
+        let _changes = self.binding_changes.lock(extras_lock_key(binding));
+        let route_key = RouteKey::new(binding, crate::extras::CAPABILITY_ID);
+        if !self.caps.read_or_recover().contains_key(&route_key)
+            || self
+                .bindings
+                .read_or_recover()
+                .for_provider(crate::extras::CAPABILITY_ID, binding)
+                .next()
+                .is_some()
+        {
+            return;
+        }
+        if let Err(e) =
+            self.remove_native_capability(crate::extras::CAPABILITY_ID, Some(binding.to_string()))
+        {
+            warn!(
+                "Failed to remove the unused extras provider for binding {}: {}",
+                binding, e
+            );
+        }
+    }
+
+    // Forgets the sequences of every binding the actor had to the extras provider
+    pub(crate) fn forget_extras_sequences(&self, actor: &str) {
+        if let Some(ref sequences) = self.extras_sequences {
+            sequences
+                .write_or_recover()
+                .retain(|(seq_actor, _), _| seq_actor != actor);
+        }
+    }
+
+    // Whether a built-in provider may be added, warning if the capability allowlist rules it out
+    pub(crate) fn builtin_allowed(&self, capid: &str) -> bool {
+        let allowed = self.capability_allowlist.permits(capid);
+        if !allowed {
+            warn!(
+                "Not adding the built-in {} provider, which isn't on the host's capability allowlist",
+                capid
+            );
+        }
+        allowed
+    }
+
+    pub(crate) fn ensure_hostmeta(&self) -> Result<()> {
         self.add_native_capability(NativeCapability::from_instance(
-            crate::extras::ExtrasCapabilityProvider::default(),
+            crate::hostmeta::HostMetaCapabilityProvider::new(
+                &self.id(),
+                self.labels.clone(),
+                self.ns.clone(),
+            ),
             None,
         )?)?;
         Ok(())
     }
 }
 
-/// In the case of a portable capability provider, obtain its capability descriptor
-pub(crate) fn get_descriptor(host: &mut WapcHost) -> Result<CapabilityDescriptor> {
-    let msg = wascc_codec::core::HealthRequest { placeholder: false }; // TODO: eventually support sending an empty slice for this
-    let res = host.call(OP_GET_CAPABILITY_DESCRIPTOR, &serialize(&msg)?)?;
-    deserialize(&res).map_err(|e| e.into())
+/// The payload a portable capability provider expects with a request for its descriptor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum DescriptorDialect {
+    /// An empty payload, as current providers expect
+    Empty,
+    /// A serialized `HealthRequest`, as providers built against older codec conventions expect
+    Legacy,
+}
+
+impl DescriptorDialect {
+    pub(crate) fn payload(self) -> Vec<u8> {
+        match self {
+            DescriptorDialect::Empty => Vec::new(),
+            DescriptorDialect::Legacy => {
+                serialize(&wascc_codec::core::HealthRequest { placeholder: false }).unwrap()
+            }
+        }
+    }
+}
+
+/// Queries a portable capability provider for its descriptor, returning it along with the
+/// dialect of descriptor request the provider answered, so that later requests can be sent in
+/// the same form
+pub(crate) fn get_descriptor(
+    host: &mut WapcHost,
+) -> Result<(CapabilityDescriptor, DescriptorDialect)> {
+    negotiate_descriptor(|op, msg| host.call(op, msg).map_err(|e| e.into()))
+}
+
+// Asks for the descriptor with an empty payload, and if the provider fails to answer, once more
+// with the legacy payload
+fn negotiate_descriptor(
+    mut call: impl FnMut(&str, &[u8]) -> Result<Vec<u8>>,
+) -> Result<(CapabilityDescriptor, DescriptorDialect)> {
+    let mut query = |dialect: DescriptorDialect| -> Result<CapabilityDescriptor> {
+        let res = call(OP_GET_CAPABILITY_DESCRIPTOR, &dialect.payload())?;
+        deserialize(&res).map_err(|e| e.into())
+    };
+    match query(DescriptorDialect::Empty) {
+        Ok(d) => {
+            debug!(
+                "Capability provider {} answered an empty descriptor request",
+                d.id
+            );
+            Ok((d, DescriptorDialect::Empty))
+        }
+        Err(e) => match query(DescriptorDialect::Legacy) {
+            Ok(d) => {
+                info!(
+                    "Capability provider {} rejected an empty descriptor request ({}) but answered the legacy request, which will be used from now on",
+                    d.id, e
+                );
+                Ok((d, DescriptorDialect::Legacy))
+            }
+            Err(legacy) => {
+                warn!(
+                    "Capability provider rejected both an empty descriptor request ({}) and the legacy request ({})",
+                    e, legacy
+                );
+                Err(legacy)
+            }
+        },
+    }
 }
 
 pub(crate) fn remove_cap(
@@ -95,26 +347,78 @@ pub(crate) fn remove_cap(
     capid: &str,
     binding: &str,
 ) {
-    caps.write().unwrap().remove(&RouteKey::new(binding, capid));
+    caps.write_or_recover()
+        .remove(&RouteKey::new(binding, capid));
 }
 
 /// Puts a "live update" message into the dispatch queue, which will be handled
 /// as soon as it is pulled off the channel for the target actor
 pub(crate) fn replace_actor(
-    hostkey: &KeyPair,
+    signer: &InvocationSigner,
     bus: Arc<MessageBus>,
     new_actor: Actor,
 ) -> Result<()> {
     let public_key = new_actor.token.claims.subject;
     let tgt_subject = bus.actor_subject(&public_key);
-    let inv = gen_liveupdate_invocation(hostkey, &public_key, new_actor.bytes);
+    let inv = gen_liveupdate_invocation(signer, &public_key, new_actor.bytes);
 
     match bus.invoke(&tgt_subject, inv) {
-        Ok(_) => {
-            info!("Actor {} replaced", public_key);
+        Ok(inv_r) => {
+            match inv_r.error {
+                None => info!("Actor {} replaced", public_key),
+                Some(ref e) => warn!("Actor {} could not be replaced: {}", public_key, e),
+            }
+            #[cfg(feature = "lattice")]
+            let _ = bus.publish_lattice_event(match inv_r.error {
+                None => crate::LatticeEvent::ActorReplaced {
+                    actor: public_key.to_string(),
+                    host: signer.host_id().to_string(),
+                    reason: crate::ReasonCode::LiveUpdateApplied,
+                },
+                Some(_) => crate::LatticeEvent::ActorReplaceFailed {
+                    actor: public_key.to_string(),
+                    host: signer.host_id().to_string(),
+                    reason: crate::ReasonCode::LiveUpdateRejected,
+                },
+            });
             Ok(())
         }
-        Err(e) => Err(e),
+        Err(e) => {
+            #[cfg(feature = "lattice")]
+            let _ = bus.publish_lattice_event(crate::LatticeEvent::ActorReplaceFailed {
+                actor: public_key.to_string(),
+                host: signer.host_id().to_string(),
+                reason: crate::ReasonCode::ActorUnreachable,
+            });
+            Err(e)
+        }
+    }
+}
+
+/// Logs a response that couldn't be delivered because the requester has gone away, e.g. because the
+/// host shut down mid-invocation. The bus holds the receiving end of a subscription's response
+/// channel until the subscription is removed, so a failure while the subject is still
+/// subscribed usually means it was subscribed again (e.g. by a hot swap) in the meantime
+pub(crate) fn response_undeliverable(bus: &MessageBus, subject: &str) {
+    if bus.is_subscribed(subject) {
+        warn!(
+            "Response on {} could not be delivered, its subscription has been replaced",
+            subject
+        );
+    } else {
+        warn!(
+            "Response on {} could not be delivered, its subscription is gone",
+            subject
+        );
+    }
+}
+
+/// Asks a bus subscriber's thread to run its termination cleanup, for when its subscription has
+/// gone away without it being told to terminate. Only the first request is sent
+pub(crate) fn stop_once(terminator: &crossbeam::Sender<bool>, stopping: &mut bool) {
+    if !*stopping {
+        *stopping = true;
+        let _ = terminator.send(true);
     }
 }
 
@@ -128,9 +432,12 @@ pub(crate) fn live_update(guest: &mut WapcHost, inv: &Invocation) -> InvocationR
     }
 }
 
-fn gen_liveupdate_invocation(hostkey: &KeyPair, target: &str, bytes: Vec<u8>) -> Invocation {
-    Invocation::new(
-        hostkey,
+fn gen_liveupdate_invocation(
+    signer: &InvocationSigner,
+    target: &str,
+    bytes: Vec<u8>,
+) -> Invocation {
+    signer.invocation(
         WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
         WasccEntity::Actor(target.to_string()),
         OP_PERFORM_LIVE_UPDATE,
@@ -141,9 +448,9 @@ fn gen_liveupdate_invocation(hostkey: &KeyPair, target: &str, bytes: Vec<u8>) ->
 /// Removes all bindings for a given actor by sending the "remove actor" message
 /// to each of the capabilities
 pub(crate) fn deconfigure_actor(
-    hostkey: KeyPair,
+    signer: &InvocationSigner,
     bus: Arc<MessageBus>,
-    bindings: Arc<RwLock<BindingsList>>,
+    bindings: Arc<RwLock<Bindings>>,
     key: &str,
 ) {
     #[cfg(feature = "lattice")]
@@ -157,56 +464,175 @@ pub(crate) fn deconfigure_actor(
             }
         }
     }
-    let cfg = CapabilityConfiguration {
-        module: key.to_string(),
-        values: HashMap::new(),
-    };
-    let buf = serialize(&cfg).unwrap();
-    let nbindings: Vec<_> = {
-        let lock = bindings.read().unwrap();
-        lock.keys()
-            .filter(|(a, _cap, _bind)| a == key)
-            .cloned()
-            .collect()
+    let mut nbindings: Vec<_> = {
+        let lock = bindings.read_or_recover();
+        lock.for_actor(key).map(|(k, _)| k.clone()).collect()
     };
+    // Named bindings go first, as removing the unnamed one releases all of the actor's
+    // bindings to the provider
+    nbindings.sort_by_key(|k| k.config_name.is_none());
 
-    // (actor, capid, binding)
-    for (actor, capid, binding) in nbindings {
+    for BindingKey {
+        actor,
+        capid,
+        binding,
+        config_name,
+    } in nbindings
+    {
         info!("Unbinding actor {} from {},{}", actor, binding, capid);
         let _inv_r = bus.invoke(
-            &bus.provider_subject(&capid, &binding), // The OP_REMOVE_ACTOR invocation should go to _all_ instances of the provider being unbound
-            gen_remove_actor(&hostkey, buf.clone(), &binding, &capid),
+            &bus.provider_config_subject(&capid, &binding), // The OP_REMOVE_ACTOR invocation should go to _all_ instances of the provider being unbound
+            gen_remove_actor(
+                signer,
+                removal_payload(key, config_name.as_deref()),
+                &binding,
+                &capid,
+            ),
         );
-        remove_binding(bindings.clone(), key, &binding, &capid);
+        bindings
+            .write_or_recover()
+            .remove_named(key, &capid, &binding, config_name.as_deref());
     }
 }
 
 /// Removes all bindings from a capability without notifying anyone
-pub(crate) fn unbind_all_from_cap(bindings: Arc<RwLock<BindingsList>>, capid: &str, binding: &str) {
-    let mut lock = bindings.write().unwrap();
-    // (actor, capid, binding name)
-    lock.retain(|k, _| !(k.1 == capid) && (k.2 == binding));
+pub(crate) fn unbind_all_from_cap(bindings: Arc<RwLock<Bindings>>, capid: &str, binding: &str) {
+    bindings.write_or_recover().remove_provider(capid, binding);
 }
 
+// Forgets the actor's bindings to the provider instance, including any named ones
 pub(crate) fn remove_binding(
-    bindings: Arc<RwLock<BindingsList>>,
+    bindings: Arc<RwLock<Bindings>>,
     actor: &str,
     binding: &str,
     capid: &str,
 ) {
-    // binding: (actor,  capid, binding)
-    let mut lock = bindings.write().unwrap();
-    lock.remove(&(actor.to_string(), capid.to_string(), binding.to_string()));
+    bindings
+        .write_or_recover()
+        .remove_all_named(actor, capid, binding);
+}
+
+// The configuration delivered with OP_REMOVE_ACTOR, which names the binding being removed if it
+// was set with a configuration name
+pub(crate) fn removal_payload(actor: &str, config_name: Option<&str>) -> Vec<u8> {
+    let mut values = HashMap::new();
+    if let Some(name) = config_name {
+        values.insert(
+            crate::CONFIG_WASCC_CONFIG_NAME.to_string(),
+            name.to_string(),
+        );
+    }
+    serialize(&CapabilityConfiguration {
+        module: actor.to_string(),
+        values,
+    })
+    .unwrap()
+}
+
+/// Removes a binding that its capability provider can no longer serve: the binding is
+/// forgotten, and this host's instance of the actor is told about it with the given operation,
+/// if there is one. Every host in the lattice does so for its own copy of the binding, but only
+/// the one that `releases` it asks every instance of the provider to release the binding's
+/// resources and announces the removal to the lattice
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn remove_failed_binding(
+    signer: &InvocationSigner,
+    bus: &MessageBus,
+    bindings: &Arc<RwLock<Bindings>>,
+    actor: &str,
+    capid: &str,
+    binding: &str,
+    reason: &str,
+    notify_op: Option<&str>,
+    releases: bool,
+) {
+    warn!(
+        "Capability provider {},{} reported a failed binding for actor {}: {}",
+        capid, binding, actor, reason
+    );
+    if releases {
+        let cfg = CapabilityConfiguration {
+            module: actor.to_string(),
+            values: HashMap::new(),
+        };
+        match bus.invoke_all(
+            &bus.provider_config_subject(capid, binding),
+            gen_remove_actor(signer, serialize(&cfg).unwrap(), binding, capid),
+        ) {
+            Ok(responses) => {
+                for e in responses.iter().filter_map(|r| r.error.as_ref()) {
+                    warn!(
+                        "A {},{} instance failed to release actor {}: {}",
+                        binding, capid, actor, e
+                    );
+                }
+            }
+            Err(e) => warn!(
+                "Failed to ask {},{} to release actor {}: {}",
+                binding, capid, actor, e
+            ),
+        }
+        #[cfg(feature = "lattice")]
+        let _ = bus.publish_lattice_event(crate::LatticeEvent::ActorBindingRemoved {
+            actor: actor.to_string(),
+            capid: capid.to_string(),
+            instance_name: binding.to_string(),
+            host: signer.host_id().to_string(),
+            reason: crate::ReasonCode::ProviderReleased,
+        });
+    }
+    remove_binding(bindings.clone(), actor, binding, capid);
+    bus.publish_host_event(HostEvent::BindingRemoved {
+        actor: actor.to_string(),
+        capid: capid.to_string(),
+        binding: binding.to_string(),
+        reason: reason.to_string(),
+    });
+
+    let notify_op = match notify_op {
+        Some(op) => op,
+        None => return,
+    };
+    let mut values = HashMap::new();
+    values.insert("capid".to_string(), capid.to_string());
+    values.insert("binding".to_string(), binding.to_string());
+    values.insert("reason".to_string(), reason.to_string());
+    let notice = CapabilityConfiguration {
+        module: actor.to_string(),
+        values,
+    };
+    let inv = signer.invocation(
+        WasccEntity::Capability {
+            capid: capid.to_string(),
+            binding: binding.to_string(),
+        },
+        WasccEntity::Actor(actor.to_string()),
+        notify_op,
+        serialize(&notice).unwrap(),
+    );
+    // Actors aren't required to handle the notification
+    match bus.invoke_own(&bus.actor_subject(actor), inv, NOTIFY_TIMEOUT) {
+        Ok(InvocationResponse { error: Some(e), .. }) => {
+            debug!("Actor {} did not handle {}: {}", actor, notify_op, e)
+        }
+        Err(e) => debug!("Could not notify actor {} of removed binding: {}", actor, e),
+        Ok(_) => {}
+    }
+}
+
+// The binding lock held for an extras provider served on the binding name, which a binding to
+// the provider holds along with the lock for the binding itself
+pub(crate) fn extras_lock_key(binding: &str) -> String {
+    format!("{}/{}", crate::extras::CAPABILITY_ID, binding)
 }
 
 pub(crate) fn gen_remove_actor(
-    hostkey: &KeyPair,
+    signer: &InvocationSigner,
     msg: Vec<u8>,
     binding: &str,
     capid: &str,
 ) -> Invocation {
-    Invocation::new(
-        hostkey,
+    signer.invocation(
         WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
         WasccEntity::Capability {
             capid: capid.to_string(),
@@ -228,6 +654,20 @@ pub struct Invocation {
     pub id: String,
     pub encoded_claims: String,
     pub host_id: String,
+    /// The public keys of the actors (or portable capability providers) whose calls led to this
+    /// invocation, outermost first and ending with the origin. Empty for invocations that
+    /// weren't made by a module, e.g. those sent by the host or by native capability providers.
+    /// The chain is advisory only: it isn't covered by the invocation's signature, so any host
+    /// or transport the invocation passes through can alter it, and it mustn't be relied on
+    /// for authorization
+    #[cfg_attr(feature = "lattice", serde(default))]
+    pub origin_chain: Vec<String>,
+    /// The media type of the payload (e.g. `application/json` or `application/msgpack`), if the
+    /// caller gave one, so middleware can tell payloads apart. It's only a hint: it isn't covered
+    /// by the invocation's signature, and actors and providers may ignore it (native capability
+    /// providers are only given the operation and payload)
+    #[cfg_attr(feature = "lattice", serde(default))]
+    pub content_type: Option<String>,
 }
 
 /// Represents an invocation target - either an actor or a bound capability provider
@@ -250,6 +690,68 @@ impl WasccEntity {
             ),
         }
     }
+
+    /// Parses a URL produced by `url`, such as `wasmbus://Mxxxx` for an actor or
+    /// `wasmbus://wascc/keyvalue/default` for a capability provider. The same normalization
+    /// applied by `url` is applied here, so the capability ID and binding name of the result
+    /// are lowercase and contain no spaces. Because that normalization is lossy, only
+    /// entities that are already in normal form survive a round trip unchanged
+    pub fn from_url(url: &str) -> Result<WasccEntity> {
+        let malformed = || {
+            errors::new(ErrorKind::MiscHost(format!(
+                "Malformed {} URL: {}",
+                bus::URL_SCHEME,
+                url
+            )))
+        };
+        let prefix = format!("{}://", bus::URL_SCHEME);
+        if !url.starts_with(&prefix) {
+            return Err(malformed());
+        }
+        let segments: Vec<_> = url[prefix.len()..].split('/').collect();
+        if segments.iter().any(|s| s.is_empty()) {
+            return Err(malformed());
+        }
+        let normalize = |s: &str| s.replace(" ", "_").to_lowercase();
+        match segments.split_last() {
+            Some((pk, [])) => Ok(WasccEntity::Actor(pk.to_string())),
+            Some((binding, capid)) => Ok(WasccEntity::Capability {
+                capid: capid
+                    .iter()
+                    .map(|s| normalize(s))
+                    .collect::<Vec<_>>()
+                    .join(":"),
+                binding: normalize(binding),
+            }),
+            None => Err(malformed()),
+        }
+    }
+}
+
+impl std::fmt::Display for WasccEntity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.url())
+    }
+}
+
+// Describes the entity (and, for target URLs, the operation) a signed claims URL refers to,
+// for reporting which part of a tampered invocation no longer matches its claims
+fn describe_url(url: &str, has_operation: bool) -> String {
+    let (entity, op) = match url.rfind('/') {
+        Some(i) if has_operation => (&url[..i], Some(&url[i + 1..])),
+        _ => (url, None),
+    };
+    let entity = match WasccEntity::from_url(entity) {
+        Ok(WasccEntity::Actor(pk)) => format!("actor {}", pk),
+        Ok(WasccEntity::Capability { capid, binding }) => {
+            format!("capability {} ({})", capid, binding)
+        }
+        Err(_) => return format!("malformed URL {}", url),
+    };
+    match op {
+        Some(op) => format!("{} on {}", op, entity),
+        None => entity,
+    }
 }
 
 impl Invocation {
@@ -278,6 +780,8 @@ impl Invocation {
             id: subject,
             encoded_claims: claims.encode(&hostkey).unwrap(),
             host_id: issuer.to_string(),
+            origin_chain: Vec::new(),
+            content_type: None,
         }
     }
 
@@ -293,38 +797,94 @@ impl Invocation {
         invocation_hash(&self.target_url(), &self.origin_url(), &self.msg)
     }
 
+    /// Starts building a signed invocation with defaults suitable for unit tests of middleware,
+    /// authorizers, and custom transports. See `InvocationBuilder`
+    pub fn test_builder() -> InvocationBuilder {
+        InvocationBuilder::new()
+    }
+
+    /// Checks that the invocation's claims are intact and were signed by the host the
+    /// invocation names. This rejects invocations signed with a key other than the host's own,
+    /// such as those sent by hosts using `HostBuilder::with_invocation_signer` or whose key has
+    /// been rotated
+    #[deprecated(
+        since = "0.14.0",
+        note = "checks only the invocation's `host_id`; use `validate_antiforgery_with_signers` with the keys to accept"
+    )]
     pub fn validate_antiforgery(&self) -> Result<()> {
-        let vr = wascap::jwt::validate_token::<wascap::prelude::Invocation>(&self.encoded_claims)?;
-        let claims = Claims::<wascap::prelude::Invocation>::decode(&self.encoded_claims)?;
-        if vr.expired {
-            return Err(errors::new(ErrorKind::Authorization(
-                "Invocation claims token expired".into(),
-            )));
-        }
-        if !vr.signature_valid {
-            return Err(errors::new(ErrorKind::Authorization(
-                "Invocation claims signature invalid".into(),
-            )));
-        }
-        if vr.cannot_use_yet {
-            return Err(errors::new(ErrorKind::Authorization(
-                "Attempt to use invocation before claims token allows".into(),
-            )));
-        }
-        let inv_claims = claims.metadata.unwrap();
+        self.check_antiforgery(&[self.host_id.as_str()], None)
+    }
+
+    /// Checks that the invocation's claims are intact and were signed by the host the
+    /// invocation names, skipping the verification of the claims' signature if the cache has
+    /// already verified them. Everything specific to the invocation is still checked every time
+    pub fn validate_antiforgery_cached(&self, cache: &AntiforgeryCache) -> Result<()> {
+        self.check_antiforgery(&[self.host_id.as_str()], Some(cache))
+    }
+
+    /// Checks that the invocation's claims are intact and were signed by one of the given
+    /// keys, such as the invocation signing keys shared by the hosts in a lattice (see
+    /// `HostBuilder::with_invocation_signer`). The invocation's `host_id` isn't checked
+    pub fn validate_antiforgery_with_signers(&self, signers: &[String]) -> Result<()> {
+        let signers: Vec<&str> = signers.iter().map(String::as_str).collect();
+        self.check_antiforgery(&signers, None)
+    }
+
+    pub(crate) fn check_antiforgery(
+        &self,
+        signers: &[&str],
+        cache: Option<&AntiforgeryCache>,
+    ) -> Result<()> {
+        let claims = match cache {
+            Some(cache) => cache.verify(&self.encoded_claims)?,
+            None => verify_invocation_token(&self.encoded_claims)?,
+        };
+        self.check_claims(&claims, signers)
+    }
+
+    // The checks that tie verified claims to this particular invocation
+    fn check_claims(
+        &self,
+        claims: &Claims<wascap::prelude::Invocation>,
+        signers: &[&str],
+    ) -> Result<()> {
+        let inv_claims = claims.metadata.as_ref().unwrap();
         if inv_claims.invocation_hash != self.hash() {
-            return Err(errors::new(ErrorKind::Authorization(
-                "Invocation hash does not match signed claims hash".into(),
-            )));
+            let detail = if inv_claims.target_url != self.target_url() {
+                format!(
+                    "claims target {}, invocation targets {}",
+                    describe_url(&inv_claims.target_url, true),
+                    describe_url(&self.target_url(), true)
+                )
+            } else if inv_claims.origin_url != self.origin_url() {
+                format!(
+                    "claims origin {}, invocation origin {}",
+                    describe_url(&inv_claims.origin_url, false),
+                    describe_url(&self.origin_url(), false)
+                )
+            } else {
+                "payload altered".to_string()
+            };
+            return Err(errors::new(ErrorKind::Authorization(format!(
+                "Invocation hash does not match signed claims hash ({})",
+                detail
+            ))));
         }
         if claims.subject != self.id {
             return Err(errors::new(ErrorKind::Authorization(
                 "Subject of invocation claims token does not match invocation ID".into(),
             )));
         }
-        if claims.issuer != self.host_id {
+        if !signers.contains(&claims.issuer.as_str()) {
             return Err(errors::new(ErrorKind::Authorization(
-                "Invocation claims issuer does not match invocation host".into(),
+                if signers == [self.host_id.as_str()] {
+                    "Invocation claims issuer does not match invocation host".to_string()
+                } else {
+                    format!(
+                        "Invocation claims issuer {} is not an accepted signer",
+                        claims.issuer
+                    )
+                },
             )));
         }
         if inv_claims.target_url != self.target_url() {
@@ -342,6 +902,48 @@ impl Invocation {
     }
 }
 
+// Verifies the signature and validity period of an invocation's claims token, returning the
+// claims. This is the part of the antiforgery checks that doesn't depend on the invocation
+// carrying the token, and so the part an `AntiforgeryCache` can skip
+pub(crate) fn verify_invocation_token(
+    encoded: &str,
+) -> Result<Claims<wascap::prelude::Invocation>> {
+    let vr = wascap::jwt::validate_token::<wascap::prelude::Invocation>(encoded)?;
+    let claims = Claims::<wascap::prelude::Invocation>::decode(encoded)?;
+    check_invocation_token(claims, vr.signature_valid, vr.expired, vr.cannot_use_yet)
+}
+
+// Turns the outcome of validating an invocation's claims token into the claims, or the first
+// reason they can't be used
+pub(crate) fn check_invocation_token(
+    claims: Claims<wascap::prelude::Invocation>,
+    signature_valid: bool,
+    expired: bool,
+    cannot_use_yet: bool,
+) -> Result<Claims<wascap::prelude::Invocation>> {
+    if expired {
+        return Err(errors::new(ErrorKind::Authorization(
+            "Invocation claims token expired".into(),
+        )));
+    }
+    if !signature_valid {
+        return Err(errors::new(ErrorKind::Authorization(
+            "Invocation claims signature invalid".into(),
+        )));
+    }
+    if cannot_use_yet {
+        return Err(errors::new(ErrorKind::Authorization(
+            "Attempt to use invocation before claims token allows".into(),
+        )));
+    }
+    if claims.metadata.is_none() {
+        return Err(errors::new(ErrorKind::Authorization(
+            "Invocation claims token has no invocation metadata".into(),
+        )));
+    }
+    Ok(claims)
+}
+
 /// The response to an invocation
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "lattice", derive(serde::Serialize, serde::Deserialize))]
@@ -367,17 +969,176 @@ impl InvocationResponse {
             invocation_id: inv.id.to_string(),
         }
     }
+
+    /// A successful response to the invocation that echoes its payload, for use in tests
+    pub fn test_success(inv: &Invocation) -> InvocationResponse {
+        InvocationResponse::success(inv, inv.msg.clone())
+    }
+
+    /// A failed response to the invocation, for use in tests
+    pub fn test_error(inv: &Invocation, err: &str) -> InvocationResponse {
+        InvocationResponse::error(inv, err)
+    }
+}
+
+/// Builds invocations outside of a running host, for unit testing middleware, authorizers, and
+/// custom transports. Unless overridden, the origin is a freshly generated actor key, the target
+/// is the `default` binding of the `wascc:testing` capability, the operation is `TestOperation`,
+/// the payload is empty, and the invocation is signed with a freshly generated host key.
+///
+/// The result is a genuinely signed invocation, so it passes `validate_antiforgery_with_signers`
+/// given its `host_id`. That only proves the invocation hasn't been altered since it was signed
+/// by the holder of the key named in `host_id`; it doesn't prove that key belongs to a trusted
+/// host. Anyone can sign an
+/// invocation this way, so code that accepts invocations from outside the host should also
+/// check `host_id` against the hosts it trusts.
+///
+/// ```
+/// use wascc_host::middleware::{InvocationHandler, MiddlewareResponse};
+/// use wascc_host::{Invocation, InvocationResponse, Middleware, Result};
+///
+/// // Rejects actor invocations with empty payloads without invoking the actor
+/// struct RejectEmpty;
+///
+/// impl Middleware for RejectEmpty {
+///     fn actor_pre_invoke(&self, inv: Invocation) -> Result<Invocation> {
+///         Ok(inv)
+///     }
+///     fn actor_invoke(&self, inv: Invocation, handler: InvocationHandler) -> Result<MiddlewareResponse> {
+///         if inv.msg.is_empty() {
+///             Ok(MiddlewareResponse::Halt(InvocationResponse::test_error(&inv, "empty payload")))
+///         } else {
+///             Ok(MiddlewareResponse::Continue(handler.invoke(inv)))
+///         }
+///     }
+///     fn actor_post_invoke(&self, response: InvocationResponse) -> Result<InvocationResponse> {
+///         Ok(response)
+///     }
+///     fn capability_pre_invoke(&self, inv: Invocation) -> Result<Invocation> {
+///         Ok(inv)
+///     }
+///     fn capability_invoke(&self, inv: Invocation, handler: InvocationHandler) -> Result<MiddlewareResponse> {
+///         Ok(MiddlewareResponse::Continue(handler.invoke(inv)))
+///     }
+///     fn capability_post_invoke(&self, response: InvocationResponse) -> Result<InvocationResponse> {
+///         Ok(response)
+///     }
+/// }
+///
+/// let target = |inv: Invocation| InvocationResponse::test_success(&inv);
+///
+/// let inv = Invocation::test_builder().with_operation("HandleRequest").build();
+/// assert!(inv
+///     .validate_antiforgery_with_signers(&[inv.host_id.clone()])
+///     .is_ok());
+/// match RejectEmpty.actor_invoke(inv, InvocationHandler::new(&target)).unwrap() {
+///     MiddlewareResponse::Halt(r) => assert_eq!(Some("empty payload".to_string()), r.error),
+///     MiddlewareResponse::Continue(_) => panic!("empty payload was not rejected"),
+/// }
+///
+/// let inv = Invocation::test_builder().with_payload(b"hello".to_vec()).build();
+/// match RejectEmpty.actor_invoke(inv, InvocationHandler::new(&target)).unwrap() {
+///     MiddlewareResponse::Continue(r) => assert_eq!(b"hello".to_vec(), r.msg),
+///     MiddlewareResponse::Halt(_) => panic!("valid invocation was halted"),
+/// }
+/// ```
+pub struct InvocationBuilder {
+    origin: WasccEntity,
+    target: WasccEntity,
+    operation: String,
+    msg: Vec<u8>,
+    host_key: KeyPair,
+    origin_chain: Vec<String>,
+    content_type: Option<String>,
+}
+
+impl InvocationBuilder {
+    fn new() -> InvocationBuilder {
+        InvocationBuilder {
+            origin: WasccEntity::Actor(KeyPair::new_module().public_key()),
+            target: WasccEntity::Capability {
+                capid: "wascc:testing".to_string(),
+                binding: "default".to_string(),
+            },
+            operation: "TestOperation".to_string(),
+            msg: Vec::new(),
+            host_key: KeyPair::new_server(),
+            origin_chain: Vec::new(),
+            content_type: None,
+        }
+    }
+
+    /// Sets the actor or capability provider the invocation comes from
+    pub fn with_origin(self, origin: WasccEntity) -> InvocationBuilder {
+        InvocationBuilder { origin, ..self }
+    }
+
+    /// Sets the actor or capability provider the invocation is sent to
+    pub fn with_target(self, target: WasccEntity) -> InvocationBuilder {
+        InvocationBuilder { target, ..self }
+    }
+
+    /// Sets the operation name. Any string is accepted, including empty ones
+    pub fn with_operation(self, operation: &str) -> InvocationBuilder {
+        InvocationBuilder {
+            operation: operation.to_string(),
+            ..self
+        }
+    }
+
+    pub fn with_payload(self, msg: Vec<u8>) -> InvocationBuilder {
+        InvocationBuilder { msg, ..self }
+    }
+
+    /// Sets the key the invocation is signed with, which becomes its `host_id`
+    pub fn with_host_key(self, host_key: KeyPair) -> InvocationBuilder {
+        InvocationBuilder { host_key, ..self }
+    }
+
+    /// Sets the chain of entities whose calls led to the invocation
+    pub fn with_origin_chain(self, chain: &[&str]) -> InvocationBuilder {
+        InvocationBuilder {
+            origin_chain: chain.iter().map(|s| s.to_string()).collect(),
+            ..self
+        }
+    }
+
+    /// Sets the media type of the payload
+    pub fn with_content_type(self, content_type: &str) -> InvocationBuilder {
+        InvocationBuilder {
+            content_type: Some(content_type.to_string()),
+            ..self
+        }
+    }
+
+    /// Signs and returns the invocation
+    pub fn build(self) -> Invocation {
+        let mut inv = Invocation::new(
+            &self.host_key,
+            self.origin,
+            self.target,
+            &self.operation,
+            self.msg,
+        );
+        inv.origin_chain = self.origin_chain;
+        inv.content_type = self.content_type;
+        inv
+    }
 }
 
 pub(crate) fn wapc_host_callback(
-    hostkey: KeyPair,
+    signer: &InvocationSigner,
     claims: Claims<wascap::jwt::Actor>,
     bus: Arc<MessageBus>,
     binding: &str,
+    default_binding: &str,
     namespace: &str,
     operation: &str,
     payload: &[u8],
     authorizer: Arc<RwLock<Box<dyn Authorizer>>>,
+    authz_ctx: &authz::AuthorizationContext,
+    health: &crate::health::HealthMonitor,
+    allowlist: &authz::CapabilityAllowlist,
 ) -> std::result::Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
     trace!(
         "Guest {} invoking {}:{}",
@@ -387,19 +1148,66 @@ pub(crate) fn wapc_host_callback(
     );
 
     let capability_id = namespace;
-    let inv = invocation_from_callback(
-        &hostkey,
+    let (content_type, operation) = split_content_type(operation);
+    let mut inv = invocation_from_callback(
+        signer,
         &claims.subject,
         binding,
+        default_binding,
         namespace,
         operation,
         payload,
     );
+    inv.origin_chain = ACTIVE_CHAIN.with(|c| c.borrow().clone());
+    inv.origin_chain.push(claims.subject.to_string());
+    inv.content_type = content_type;
+    if inv.origin_chain.len() > MAX_ORIGIN_CHAIN {
+        return Err(Box::new(errors::new(errors::ErrorKind::HostCallFailure(
+            format!(
+                "{} attempted to call {} on {} beyond the maximum origin chain length of {}",
+                claims.subject, operation, inv.target, MAX_ORIGIN_CHAIN
+            )
+            .into(),
+        ))));
+    }
+
+    // Checked ahead of the actor's claims, so every call to a provider fails in the same way
+    if allowlist.is_actor_only() {
+        if let WasccEntity::Capability { capid, binding } = &inv.target {
+            bus.publish_host_event(HostEvent::AuthorizationDenied {
+                actor: claims.subject.to_string(),
+                capid: capid.to_string(),
+                binding: binding.to_string(),
+                operation: operation.to_string(),
+                reason: authz::CAPABILITIES_DISABLED.to_string(),
+            });
+            return Err(Box::new(errors::new(errors::ErrorKind::Authorization(
+                format!(
+                    "Actor {} attempted to call {} on {},{} - {}",
+                    claims.subject,
+                    operation,
+                    capid,
+                    binding,
+                    authz::CAPABILITIES_DISABLED
+                ),
+            ))));
+        }
+    }
 
     if !authz::can_invoke(&claims, capability_id, operation) {
+        let reason = authz::attestation_denial(&claims, capability_id, binding, operation);
+        bus.stats()
+            .denied_in_pipeline(&claims.subject, &inv.target, REASON_MISSING_ATTESTATION);
+        bus.publish_host_event(HostEvent::AuthorizationDenied {
+            actor: claims.subject.to_string(),
+            capid: capability_id.to_string(),
+            binding: binding.to_string(),
+            operation: operation.to_string(),
+            reason: reason.to_string(),
+        });
         return Err(Box::new(errors::new(errors::ErrorKind::Authorization(
             format!(
-                "{} {} attempted to call {} on {},{} - PERMISSION DENIED.",
+                "{} {} attempted to call {} on {},{} - PERMISSION DENIED: {}.",
                 if claims.metadata.unwrap().provider {
                     "Provider"
                 } else {
@@ -408,15 +1216,24 @@ pub(crate) fn wapc_host_callback(
                 claims.subject,
                 operation,
                 capability_id,
-                binding
+                binding,
+                reason
             ),
         ))));
     } else {
         if !authorizer
-            .read()
-            .unwrap()
-            .can_invoke(&claims, &inv.target, operation)
+            .read_or_recover()
+            .can_invoke_ctx(&claims, &inv.target, operation, authz_ctx)
         {
+            bus.stats()
+                .denied_in_pipeline(&claims.subject, &inv.target, REASON_AUTHORIZER_DENIED);
+            bus.publish_host_event(HostEvent::AuthorizationDenied {
+                actor: claims.subject.to_string(),
+                capid: capability_id.to_string(),
+                binding: binding.to_string(),
+                operation: operation.to_string(),
+                reason: "Authorizer denied access".to_string(),
+            });
             return Err(Box::new(errors::new(errors::ErrorKind::Authorization(
                 format!(
                     "{} {} attempted to call {:?} - Authorizer denied access",
@@ -431,6 +1248,14 @@ pub(crate) fn wapc_host_callback(
             ))));
         }
     }
+    if health.rejects(capability_id, binding) {
+        return Err(Box::new(errors::new(
+            errors::ErrorKind::ProviderUnhealthy {
+                capid: capability_id.to_string(),
+                binding: binding.to_string(),
+            },
+        )));
+    }
     // Make a request on either `wasmbus.Mxxxxx` for an actor or `wasmbus.{capid}.{binding}.{calling-actor}` for
     // a bound capability provider
     let invoke_subject = match &inv.target {
@@ -487,23 +1312,61 @@ pub(crate) fn fetch_oci_bytes(img: &str) -> Result<Vec<u8>> {
     }
 }
 
-pub(crate) fn fetch_provider_archive(img: &str) -> Result<ProviderArchive> {
-    let bytes = fetch_oci_bytes(img)?;
+/// A source of actor modules and provider archives identified by OCI image references. The
+/// default fetcher pulls from OCI registries, using the credentials in the `OCI_REGISTRY_USER`
+/// and `OCI_REGISTRY_PASSWORD` environment variables if present. A custom fetcher can be supplied
+/// via `HostBuilder::with_image_fetcher`, e.g. to serve images from a local cache or in tests
+pub trait ImageFetcher: Sync + Send {
+    /// Retrieves the raw bytes of the image with the given reference
+    fn fetch(&self, image_ref: &str) -> Result<Vec<u8>>;
+}
+
+pub(crate) struct OciFetcher {}
+
+impl ImageFetcher for OciFetcher {
+    fn fetch(&self, image_ref: &str) -> Result<Vec<u8>> {
+        fetch_oci_bytes(image_ref)
+    }
+}
+
+pub(crate) fn fetch_provider_archive(
+    fetcher: &dyn ImageFetcher,
+    img: &str,
+) -> Result<ProviderArchive> {
+    let bytes = fetcher.fetch(img)?;
     ProviderArchive::try_load(&bytes)
         .map_err(|e| format!("Failed to load provider archive: {}", e).into())
 }
 
+// Separates the content type given by `CONTENT_TYPE_OPERATION_PREFIX`, if any, from the operation
+fn split_content_type(operation: &str) -> (Option<String>, &str) {
+    if operation.starts_with(CONTENT_TYPE_OPERATION_PREFIX) {
+        let rest = &operation[CONTENT_TYPE_OPERATION_PREFIX.len()..];
+        if let Some(i) = rest.find('|') {
+            let content_type = &rest[..i];
+            let content_type = if content_type.is_empty() {
+                None
+            } else {
+                Some(content_type.to_string())
+            };
+            return (content_type, &rest[i + 1..]);
+        }
+    }
+    (None, operation)
+}
+
 fn invocation_from_callback(
-    hostkey: &KeyPair,
+    signer: &InvocationSigner,
     origin: &str,
     bd: &str,
+    default_binding: &str,
     ns: &str,
     op: &str,
     payload: &[u8],
 ) -> Invocation {
     let binding = if bd.trim().is_empty() {
         // Some actor SDKs may not specify a binding field by default
-        "default".to_string()
+        default_binding.to_string()
     } else {
         bd.to_string()
     };
@@ -515,8 +1378,7 @@ fn invocation_from_callback(
             capid: ns.to_string(),
         }
     };
-    Invocation::new(
-        hostkey,
+    signer.invocation(
         WasccEntity::Actor(origin.to_string()),
         target,
         op,
@@ -525,15 +1387,69 @@ fn invocation_from_callback(
 }
 
 pub(crate) fn gen_config_invocation(
-    hostkey: &KeyPair,
+    signer: &InvocationSigner,
     actor: &str,
     capid: &str,
     claims: Claims<wascap::jwt::Actor>,
     binding: String,
     values: HashMap<String, String>,
+    host_labels: &HashMap<String, String>,
+) -> Invocation {
+    let payload = config_payload(signer, actor, claims, &binding, values, host_labels);
+    signer.invocation(
+        WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
+        WasccEntity::Capability {
+            capid: capid.to_string(),
+            binding,
+        },
+        OP_BIND_ACTOR,
+        payload,
+    )
+}
+
+// Configuration delivered straight to an actor rather than to a capability provider on the
+// actor's behalf, see `Host::configure_actor`
+pub(crate) fn gen_actor_config_invocation(
+    signer: &InvocationSigner,
+    actor: &str,
+    claims: Claims<wascap::jwt::Actor>,
+    binding: &str,
+    values: HashMap<String, String>,
+    host_labels: &HashMap<String, String>,
 ) -> Invocation {
+    let payload = config_payload(signer, actor, claims, binding, values, host_labels);
+    signer.invocation(
+        WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
+        WasccEntity::Actor(actor.to_string()),
+        OP_BIND_ACTOR,
+        payload,
+    )
+}
+
+fn config_payload(
+    signer: &InvocationSigner,
+    actor: &str,
+    claims: Claims<wascap::jwt::Actor>,
+    binding: &str,
+    values: HashMap<String, String>,
+    host_labels: &HashMap<String, String>,
+) -> Vec<u8> {
     use wascc_codec::core::*;
     let mut values = values.clone();
+    values.insert(
+        crate::CONFIG_WASCC_BINDING_NAME.to_string(),
+        binding.to_string(),
+    );
+    values.insert(
+        crate::CONFIG_WASCC_HOST_ID.to_string(),
+        signer.host_id().to_string(),
+    );
+    for (k, v) in host_labels {
+        values.insert(
+            format!("{}{}", crate::CONFIG_WASCC_HOST_LABEL_PREFIX, k),
+            v.to_string(),
+        );
+    }
     values.insert(
         CONFIG_WASCC_CLAIMS_ISSUER.to_string(),
         claims.issuer.to_string(),
@@ -569,17 +1485,7 @@ pub(crate) fn gen_config_invocation(
         module: actor.to_string(),
         values,
     };
-    let payload = serialize(&cfgvals).unwrap();
-    Invocation::new(
-        hostkey,
-        WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
-        WasccEntity::Capability {
-            capid: capid.to_string(),
-            binding,
-        },
-        OP_BIND_ACTOR,
-        payload,
-    )
+    serialize(&cfgvals).unwrap()
 }
 
 fn sha256_digest<R: Read>(mut reader: R) -> Result<Digest> {
@@ -597,6 +1503,9 @@ fn sha256_digest<R: Read>(mut reader: R) -> Result<Digest> {
     Ok(context.finish())
 }
 
+/// Computes the hash embedded in an invocation's signed claims from its target URL (including
+/// the operation), origin URL, and payload. Transports that construct or forward invocations
+/// can use it to check a payload against the claims before handing the invocation to a host
 pub fn invocation_hash(target_url: &str, origin_url: &str, msg: &[u8]) -> String {
     use std::io::Write;
     let mut cleanbytes: Vec<u8> = Vec::new();
@@ -626,13 +1535,134 @@ pub(crate) fn detect_core_host_labels() -> HashMap<String, String> {
     hm
 }
 
-pub(crate) fn fetch_actor(actor_id: &str) -> Result<crate::actor::Actor> {
-    let vec = crate::inthost::fetch_oci_bytes(actor_id)?;
+impl Host {
+    // Waits for the processing loops of all actors and capability providers to exit,
+    // returning false if any are still running after the timeout
+    pub(crate) fn await_terminations(&self, timeout: std::time::Duration) -> bool {
+        let start = std::time::Instant::now();
+        while !self.terminators.read_or_recover().is_empty() {
+            if start.elapsed() > timeout {
+                return false;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        true
+    }
+
+    // Waits for the processing loop subscribed to the given subject to exit, returning
+    // false if it's still running after the timeout
+    pub(crate) fn await_termination(&self, subject: &str, timeout: std::time::Duration) -> bool {
+        let start = std::time::Instant::now();
+        while self.terminators.read_or_recover().contains_key(subject) {
+            if start.elapsed() > timeout {
+                return false;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        true
+    }
+
+    // Removes every trace of a capability provider from the host. The provider's own
+    // thread does most of this when it terminates, but if that thread has died (or doesn't finish
+    // in time) the provider could otherwise never be added again. Every step is idempotent
+    pub(crate) fn purge_capability(&self, capid: &str, binding: &str) {
+        let bound_subjects: Vec<_> = self
+            .bindings
+            .read_or_recover()
+            .for_provider(capid, binding)
+            .map(|(k, _)| {
+                self.bus
+                    .provider_subject_bound_actor(capid, binding, &k.actor)
+            })
+            .collect();
+        for subject in bound_subjects {
+            if let Some(t) = self.terminators.write_or_recover().remove(&subject) {
+                let _ = t.send(true);
+            }
+            let _ = self.bus.unsubscribe(&subject);
+        }
+        unbind_all_from_cap(self.bindings.clone(), capid, binding);
+
+        let subject = self.bus.provider_subject(capid, binding);
+        let _ = self.bus.unsubscribe(&subject);
+        let _ = self
+            .bus
+            .unsubscribe(&self.bus.provider_config_subject(capid, binding));
+        self.terminators.write_or_recover().remove(&subject);
+        self.rebinders
+            .write_or_recover()
+            .remove(&RouteKey::new(binding, capid));
+        remove_cap(self.caps.clone(), capid, binding);
+        let _ = self
+            .plugins
+            .write_or_recover()
+            .remove_plugin(binding, capid);
+        self.provider_origins
+            .write_or_recover()
+            .remove(&RouteKey::new(binding, capid));
+    }
+}
+
+// Public keys of the actors selected by the filter, sorted
+pub(crate) fn actors_matching_filter<'a>(
+    claims: impl Iterator<Item = &'a Claims<wascap::jwt::Actor>>,
+    filter: &crate::ActorFilter,
+) -> Vec<String> {
+    let mut actors: Vec<_> = match filter {
+        crate::ActorFilter::All => claims.map(|c| c.subject.to_string()).collect(),
+        crate::ActorFilter::Tags(tags, mode) => {
+            let tags: Vec<_> = tags.iter().map(String::as_str).collect();
+            return actors_matching_tags(claims, &tags, *mode);
+        }
+        crate::ActorFilter::Capability(capid) => claims
+            .filter(|c| c.subject != *capid && authz::can_invoke(c, capid, ""))
+            .map(|c| c.subject.to_string())
+            .collect(),
+    };
+    actors.sort();
+    actors
+}
+
+// Public keys of the actors whose tags match the query, sorted and without duplicates
+pub(crate) fn actors_matching_tags<'a>(
+    claims: impl Iterator<Item = &'a Claims<wascap::jwt::Actor>>,
+    tags: &[&str],
+    mode: crate::TagMatch,
+) -> Vec<String> {
+    let normalize = |t: &str| t.trim().to_lowercase();
+    let wanted: Vec<_> = tags.iter().map(|t| normalize(t)).collect();
+    let mut actors: Vec<_> = claims
+        .filter(|c| {
+            let actor_tags: Vec<_> = c
+                .metadata
+                .as_ref()
+                .and_then(|m| m.tags.as_ref())
+                .map(|t| t.iter().map(|t| normalize(t)).collect())
+                .unwrap_or_default();
+            match mode {
+                crate::TagMatch::All => wanted.iter().all(|t| actor_tags.contains(t)),
+                crate::TagMatch::Any => wanted.iter().any(|t| actor_tags.contains(t)),
+            }
+        })
+        .map(|c| c.subject.to_string())
+        .collect();
+    actors.sort();
+    actors.dedup();
+    actors
+}
+
+pub(crate) fn fetch_actor(
+    fetcher: &dyn ImageFetcher,
+    actor_id: &str,
+) -> Result<crate::actor::Actor> {
+    let vec = fetcher.fetch(actor_id)?;
 
     crate::actor::Actor::from_slice(&vec)
 }
 
 pub(crate) fn fetch_provider(
+    fetcher: &dyn ImageFetcher,
+    work_dir: &Path,
     provider_ref: &str,
     binding_name: &str,
     labels: Arc<RwLock<HashMap<String, String>>>,
@@ -643,18 +1673,20 @@ pub(crate) fn fetch_provider(
     use std::fs::File;
     use std::io::Write;
 
-    let par = crate::inthost::fetch_provider_archive(provider_ref)?;
-    let lock = labels.read().unwrap();
+    let par = crate::inthost::fetch_provider_archive(fetcher, provider_ref)?;
+    let lock = labels.read_or_recover();
     let target = format!("{}-{}", lock[CORELABEL_ARCH], lock[CORELABEL_OS]);
     let v = par.target_bytes(&target);
     if let Some(v) = v {
-        let path = std::env::temp_dir();
-        let path = path.join(target);
+        // Each fetch gets its own directory so concurrent fetches of the same provider can't
+        // overwrite a library that another fetch is loading
+        let path = new_scratch_dir(work_dir)?.join(target);
         {
             let mut tf = File::create(&path)?;
             tf.write_all(&v)?;
         }
-        let nc = NativeCapability::from_file(path, Some(binding_name.to_string()))?;
+        let nc = NativeCapability::from_file(&path, Some(binding_name.to_string()))
+            .map_err(|e| explain_load_failure(e, &path))?;
         if let Some(c) = par.claims() {
             Ok((nc, c))
         } else {
@@ -669,13 +1701,155 @@ pub(crate) fn fetch_provider(
     }
 }
 
+/// Creates a new, uniquely named directory beneath the host's work directory, creating the
+/// work directory first if needed. On Unix both are only accessible by the current user
+pub(crate) fn new_scratch_dir(work_dir: &Path) -> Result<PathBuf> {
+    create_private_dir(work_dir)?;
+    let dir = work_dir.join(Uuid::new_v4().to_string());
+    create_private_dir(&dir)?;
+    Ok(dir)
+}
+
+fn create_private_dir(path: &Path) -> Result<()> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(path)?;
+    Ok(())
+}
+
+/// Removes the host's work directory and everything in it
+pub(crate) fn clean_work_dir(work_dir: &Path) -> Result<()> {
+    if work_dir.exists() {
+        std::fs::remove_dir_all(work_dir)?;
+    }
+    Ok(())
+}
+
+// The dynamic loader refuses to map libraries from filesystems mounted noexec (commonly /tmp on
+// hardened systems), which otherwise surfaces as a fairly cryptic dlopen error
+fn is_noexec_failure(msg: &str) -> bool {
+    msg.contains("failed to map segment from shared object")
+}
+
+fn explain_load_failure(e: errors::Error, path: &Path) -> errors::Error {
+    match e.kind() {
+        ErrorKind::Plugin(ref le) if is_noexec_failure(&le.to_string()) => {
+            errors::new(ErrorKind::MiscHost(format!(
+                "Failed to load capability provider from {} ({}). The work directory may be on a \
+                 filesystem mounted noexec, use HostBuilder::with_work_dir to choose a directory \
+                 that allows executables",
+                path.display(),
+                le
+            )))
+        }
+        _ => e,
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::Invocation;
+    use super::{Invocation, InvocationResponse};
     use crate::WasccEntity;
     use wascap::prelude::KeyPair;
 
     #[test]
+    fn test_builder_signs_invocations() {
+        let hostkey = KeyPair::new_server();
+        let host_id = hostkey.public_key();
+        let inv = Invocation::test_builder()
+            .with_origin(WasccEntity::Actor("Mxxxx".into()))
+            .with_operation("")
+            .with_host_key(hostkey)
+            .build();
+        assert!(inv
+            .validate_antiforgery_with_signers(&[host_id.clone()])
+            .is_ok());
+        assert_eq!(host_id, inv.host_id);
+        assert_eq!("wasmbus://wascc/testing/default/", inv.target_url());
+        assert!(inv.msg.is_empty());
+
+        let resp = InvocationResponse::test_success(&inv);
+        assert_eq!(inv.id, resp.invocation_id);
+        assert!(resp.error.is_none());
+    }
+
+    #[test]
+    #[cfg(not(feature = "lattice"))]
+    fn actor_only_hosts_reject_capability_calls() {
+        use crate::authz::{self, Authorizer, CapabilityAllowlist, DefaultAuthorizer};
+        use crate::health::HealthMonitor;
+        use crate::signer::InvocationSigner;
+        use crate::stats::StatsCounters;
+        use std::collections::HashMap;
+        use std::sync::{Arc, RwLock};
+        use wascap::prelude::{Actor, ClaimsBuilder};
+
+        let hostkey = KeyPair::new_server();
+        let signer =
+            InvocationSigner::new(&hostkey.public_key(), &hostkey.seed().unwrap(), false).unwrap();
+        let bus = Arc::new(crate::bus::new(Arc::new(StatsCounters::new(Arc::new(
+            RwLock::new(vec![]),
+        )))));
+        let target = KeyPair::new_module().public_key();
+        let claims = ClaimsBuilder::<Actor>::new()
+            .issuer(&KeyPair::new_account().public_key())
+            .subject(&KeyPair::new_module().public_key())
+            .with_metadata(Actor {
+                name: Some("caller".to_string()),
+                caps: Some(vec![target.to_string(), "wascc:keyvalue".to_string()]),
+                ..Default::default()
+            })
+            .build();
+        let (inv_s, inv_r) = crossbeam_channel::unbounded::<Invocation>();
+        let (resp_s, resp_r) = crossbeam_channel::unbounded();
+        bus.subscribe(&bus.actor_subject(&target), inv_s, resp_r)
+            .unwrap();
+        std::thread::spawn(move || {
+            for inv in inv_r {
+                let _ = resp_s.send(InvocationResponse::success(&inv, inv.msg.clone()));
+            }
+        });
+        let authorizer: Arc<RwLock<Box<dyn Authorizer>>> =
+            Arc::new(RwLock::new(Box::new(DefaultAuthorizer::new())));
+        let ctx = authz::authorization_context(
+            signer.host_id(),
+            &Arc::new(RwLock::new(HashMap::new())),
+            None,
+        );
+        let health = HealthMonitor::new(None);
+        let allowlist = CapabilityAllowlist::actor_only();
+        let call = |ns: &str| {
+            super::wapc_host_callback(
+                &signer,
+                claims.clone(),
+                bus.clone(),
+                "",
+                "default",
+                ns,
+                "Echo",
+                b"hello",
+                authorizer.clone(),
+                &ctx,
+                &health,
+                &allowlist,
+            )
+        };
+
+        assert_eq!(b"hello".to_vec(), call(&target).unwrap());
+        let err = call("wascc:keyvalue").unwrap_err();
+        assert!(err.to_string().contains(authz::CAPABILITIES_DISABLED));
+        // even for capabilities the actor isn't attested for
+        let err = call("wascc:messaging").unwrap_err();
+        assert!(err.to_string().contains(authz::CAPABILITIES_DISABLED));
+    }
+
+    #[test]
+    #[allow(deprecated)]
     fn invocation_antiforgery() {
         let hostkey = KeyPair::new_server();
         // As soon as we create the invocation, the claims are baked and signed with the hash embedded.
@@ -697,7 +1871,9 @@ mod test {
         // Let's tamper with the invocation and we should hit the hash check first
         let mut bad_inv = inv.clone();
         bad_inv.target = WasccEntity::Actor("BADACTOR-EXFILTRATOR".into());
-        assert!(bad_inv.validate_antiforgery().is_err());
+        let err = bad_inv.validate_antiforgery().unwrap_err().to_string();
+        assert!(err.contains("claims target OP_TESTING on capability wascc:messaging (default)"));
+        assert!(err.contains("invocation targets OP_TESTING on actor BADACTOR-EXFILTRATOR"));
 
         // Alter the payload and we should also hit the hash check
         let mut really_bad_inv = inv.clone();
@@ -710,4 +1886,406 @@ mod test {
             "wasmbus://wascc/messaging/default/OP_TESTING"
         );
     }
+
+    #[test]
+    fn entity_urls_round_trip() {
+        let capids = &[
+            "wascc:keyvalue",
+            "wascc:http_server",
+            "mycap",
+            "acme:blob-store:v2",
+            "Wascc:Key Value",
+        ];
+        let bindings = &["default", "Backup One", "x1"];
+        let mut entities = vec![
+            WasccEntity::Actor("MB4OLDIC3TCZ4Q4TGGOVAZC43VXFE2JQVRAXQMQFXUCREOOFEKOKZTY2".into()),
+            WasccEntity::Actor("testing".into()),
+        ];
+        for capid in capids {
+            for binding in bindings {
+                entities.push(WasccEntity::Capability {
+                    capid: capid.to_string(),
+                    binding: binding.to_string(),
+                });
+            }
+        }
+        for entity in entities {
+            let url = entity.url();
+            let parsed = WasccEntity::from_url(&url).unwrap();
+            assert_eq!(url, parsed.url());
+            assert_eq!(url, parsed.to_string());
+            // Already-normalized entities come back unchanged
+            assert_eq!(parsed, WasccEntity::from_url(&parsed.url()).unwrap());
+            let normalized = match &entity {
+                WasccEntity::Capability { capid, binding } => {
+                    capid == &capid.to_lowercase()
+                        && !capid.contains(' ')
+                        && binding == &binding.to_lowercase()
+                        && !binding.contains(' ')
+                }
+                WasccEntity::Actor(_) => true,
+            };
+            if normalized {
+                assert_eq!(entity, parsed);
+            }
+        }
+        assert_eq!(
+            WasccEntity::Capability {
+                capid: "wascc:key_value".into(),
+                binding: "backup_one".into()
+            },
+            WasccEntity::from_url("wasmbus://Wascc/Key Value/Backup One").unwrap()
+        );
+        for bad in &[
+            "wasmbus://",
+            "wasmbus://wascc//default",
+            "nats://Mxxx",
+            "Mxxx",
+        ] {
+            assert!(WasccEntity::from_url(bad).is_err());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "lattice")]
+    fn entity_serde_round_trip() {
+        for entity in vec![
+            WasccEntity::Actor("Mxxxx".into()),
+            WasccEntity::Capability {
+                capid: "wascc:keyvalue".into(),
+                binding: "default".into(),
+            },
+        ] {
+            let json = serde_json::to_string(&entity).unwrap();
+            assert_eq!(entity, serde_json::from_str(&json).unwrap());
+        }
+    }
+
+    #[test]
+    fn content_type_split_from_operation() {
+        use super::split_content_type;
+
+        assert_eq!(
+            (Some("application/json".to_string()), "Store"),
+            split_content_type("__wascc_content_type=application/json|Store")
+        );
+        assert_eq!(
+            (None, "Store"),
+            split_content_type("__wascc_content_type=|Store")
+        );
+        assert_eq!((None, "Store"), split_content_type("Store"));
+        // Without the terminator the operation is left alone
+        assert_eq!(
+            (None, "__wascc_content_type=application/json"),
+            split_content_type("__wascc_content_type=application/json")
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "lattice")]
+    fn content_type_serde_round_trip() {
+        use wascc_codec::{deserialize, serialize};
+
+        let inv = Invocation::test_builder()
+            .with_content_type("application/json")
+            .with_payload(b"{}".to_vec())
+            .build();
+        let copy: Invocation = deserialize(&serialize(&inv).unwrap()).unwrap();
+        assert_eq!(Some("application/json".to_string()), copy.content_type);
+        assert!(copy
+            .validate_antiforgery_with_signers(&[copy.host_id.clone()])
+            .is_ok());
+
+        // Invocations from hosts that predate the field have no content type
+        #[derive(serde::Serialize)]
+        struct OldInvocation {
+            origin: WasccEntity,
+            target: WasccEntity,
+            operation: String,
+            msg: Vec<u8>,
+            id: String,
+            encoded_claims: String,
+            host_id: String,
+            origin_chain: Vec<String>,
+        }
+        let old = OldInvocation {
+            origin: inv.origin.clone(),
+            target: inv.target.clone(),
+            operation: inv.operation.clone(),
+            msg: inv.msg.clone(),
+            id: inv.id.clone(),
+            encoded_claims: inv.encoded_claims.clone(),
+            host_id: inv.host_id.clone(),
+            origin_chain: vec![],
+        };
+        let copy: Invocation = deserialize(&serialize(&old).unwrap()).unwrap();
+        assert!(copy.content_type.is_none());
+        assert_eq!(inv.id, copy.id);
+    }
+
+    #[test]
+    fn config_invocation_includes_binding_metadata() {
+        use super::gen_config_invocation;
+        use std::collections::HashMap;
+        use wascap::prelude::{Actor, ClaimsBuilder};
+        use wascc_codec::{core::CapabilityConfiguration, deserialize};
+
+        let hostkey = KeyPair::new_server();
+        let module = KeyPair::new_module();
+        let claims = ClaimsBuilder::<Actor>::new()
+            .issuer(&KeyPair::new_account().public_key())
+            .subject(&module.public_key())
+            .with_metadata(Actor::default())
+            .build();
+        let mut values = HashMap::new();
+        values.insert("PORT".to_string(), "8080".to_string());
+        let mut labels = HashMap::new();
+        labels.insert("region".to_string(), "us-east".to_string());
+
+        let inv = gen_config_invocation(
+            &hostkey,
+            &module.public_key(),
+            "wascc:messaging",
+            claims,
+            "backend".to_string(),
+            values,
+            &labels,
+        );
+        let cfg: CapabilityConfiguration = deserialize(&inv.msg).unwrap();
+        assert_eq!(cfg.values["PORT"], "8080");
+        assert_eq!(cfg.values[crate::CONFIG_WASCC_BINDING_NAME], "backend");
+        assert_eq!(
+            cfg.values[crate::CONFIG_WASCC_HOST_ID],
+            hostkey.public_key()
+        );
+        assert_eq!(cfg.values["__wascc_host_label_region"], "us-east");
+    }
+
+    #[test]
+    fn scratch_dirs_are_unique_and_cleaned() {
+        let work_dir = std::env::temp_dir().join(format!("wascc-test-{}", uuid::Uuid::new_v4()));
+        let a = super::new_scratch_dir(&work_dir).unwrap();
+        let b = super::new_scratch_dir(&work_dir).unwrap();
+        assert_ne!(a, b);
+        assert!(a.starts_with(&work_dir) && a.is_dir());
+        assert!(b.starts_with(&work_dir) && b.is_dir());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&work_dir).unwrap().permissions().mode();
+            assert_eq!(0o700, mode & 0o777);
+            let mode = std::fs::metadata(&a).unwrap().permissions().mode();
+            assert_eq!(0o700, mode & 0o777);
+        }
+
+        std::fs::write(a.join("x86_64-linux"), b"not really a library").unwrap();
+        super::clean_work_dir(&work_dir).unwrap();
+        assert!(!work_dir.exists());
+        // Cleaning a work directory that was never created is fine
+        super::clean_work_dir(&work_dir).unwrap();
+    }
+
+    #[test]
+    fn noexec_load_failure_detected() {
+        assert!(super::is_noexec_failure(
+            "/tmp/x/x86_64-linux: failed to map segment from shared object"
+        ));
+        assert!(!super::is_noexec_failure(
+            "/tmp/x/x86_64-linux: cannot open shared object file: No such file or directory"
+        ));
+    }
+
+    #[test]
+    fn dead_provider_can_be_removed_and_readded() {
+        use crate::{Host, NativeCapability};
+
+        let host = Host::new();
+        let load =
+            || NativeCapability::from_file("./examples/.assets/libkeyvalue.so", None).unwrap();
+        host.add_native_capability(load()).unwrap();
+
+        // Simulate a provider thread that has died by replacing its terminator with one
+        // nobody is listening to (the original is kept so the real thread stays idle)
+        let subject = host.bus.provider_subject("wascc:keyvalue", "default");
+        let (s, r) = crossbeam_channel::unbounded();
+        drop(r);
+        let _original = host.terminators.write().unwrap().insert(subject.clone(), s);
+
+        host.remove_native_capability("wascc:keyvalue", None)
+            .unwrap();
+        assert!(!host
+            .capabilities()
+            .contains_key(&("default".to_string(), "wascc:keyvalue".to_string())));
+        assert!(!host.terminators.read().unwrap().contains_key(&subject));
+        assert!(host
+            .remove_native_capability("wascc:keyvalue", None)
+            .is_err());
+
+        host.add_native_capability(load()).unwrap();
+        assert!(host
+            .capabilities()
+            .contains_key(&("default".to_string(), "wascc:keyvalue".to_string())));
+        host.shutdown().unwrap();
+    }
+
+    #[test]
+    fn host_survives_poisoned_locks() {
+        use crate::{Actor, Host, NativeCapability};
+        use std::collections::HashMap;
+        use std::sync::{Arc, RwLock};
+        use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+        use wascc_codec::serialize;
+
+        fn poison<T: Send + Sync + 'static>(lock: Arc<RwLock<T>>) {
+            let l = lock.clone();
+            let _ = std::thread::spawn(move || {
+                let _guard = l.write().unwrap();
+                panic!("deliberately poisoning a host lock");
+            })
+            .join();
+            assert!(lock.is_poisoned());
+        }
+
+        let host = Host::new();
+        poison(host.claims.clone());
+        poison(host.bindings.clone());
+        poison(host.caps.clone());
+        poison(host.terminators.clone());
+        poison(host.middlewares.clone());
+
+        let actor = Actor::from_file("./examples/.assets/echo.wasm").unwrap();
+        let pk = actor.public_key();
+        host.add_actor(actor).unwrap();
+        host.add_native_capability(
+            NativeCapability::from_file("./examples/.assets/libkeyvalue.so", None).unwrap(),
+        )
+        .unwrap();
+        assert!(host
+            .capabilities()
+            .contains_key(&("default".to_string(), "wascc:keyvalue".to_string())));
+
+        let req = serialize(Request {
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            query_string: "".to_string(),
+            header: HashMap::new(),
+            body: vec![],
+        })
+        .unwrap();
+        assert!(host.call_actor(&pk, OP_HANDLE_REQUEST, &req).is_ok());
+        assert!(host.actors().iter().any(|(k, _)| k == &pk));
+
+        host.remove_actor(&pk).unwrap();
+        host.shutdown().unwrap();
+    }
+
+    #[test]
+    fn tag_matching_dedups_instances() {
+        use wascap::prelude::{Actor, ClaimsBuilder, KeyPair};
+
+        let tagged = |tags: &[&str]| {
+            ClaimsBuilder::<Actor>::new()
+                .issuer(&KeyPair::new_account().public_key())
+                .subject(&KeyPair::new_module().public_key())
+                .with_metadata(Actor {
+                    tags: Some(tags.iter().map(|t| t.to_string()).collect()),
+                    ..Default::default()
+                })
+                .build()
+        };
+        let a = tagged(&["payment"]);
+        let b = tagged(&["Payment ", "billing"]);
+        // The same actor running in two hosts
+        let claims = vec![a.clone(), b.clone(), a.clone()];
+
+        let mut expected = vec![a.subject.to_string(), b.subject.to_string()];
+        expected.sort();
+        assert_eq!(
+            expected,
+            super::actors_matching_tags(claims.iter(), &["payment"], crate::TagMatch::All)
+        );
+        assert_eq!(
+            vec![b.subject.to_string()],
+            super::actors_matching_tags(
+                claims.iter(),
+                &["payment", "BILLING"],
+                crate::TagMatch::All
+            )
+        );
+        assert_eq!(
+            expected,
+            super::actors_matching_tags(
+                claims.iter(),
+                &["billing", "payment"],
+                crate::TagMatch::Any
+            )
+        );
+    }
+
+    // A provider that only answers descriptor requests carrying the payload of its dialect
+    fn provider(
+        dialect: super::DescriptorDialect,
+        calls: &mut Vec<Vec<u8>>,
+    ) -> impl FnMut(&str, &[u8]) -> crate::Result<Vec<u8>> + '_ {
+        move |op, msg| {
+            assert_eq!(wascc_codec::capabilities::OP_GET_CAPABILITY_DESCRIPTOR, op);
+            calls.push(msg.to_vec());
+            if msg == dialect.payload().as_slice() {
+                let d = wascc_codec::capabilities::CapabilityDescriptor::builder()
+                    .id("testing:dialect")
+                    .name("Dialect")
+                    .build();
+                Ok(wascc_codec::serialize(&d).unwrap())
+            } else {
+                Err(crate::errors::new(crate::errors::ErrorKind::MiscHost(
+                    "unexpected payload".to_string(),
+                )))
+            }
+        }
+    }
+
+    #[test]
+    fn descriptor_dialects_negotiated() {
+        use super::{negotiate_descriptor, DescriptorDialect};
+
+        let mut calls = Vec::new();
+        let (d, dialect) =
+            negotiate_descriptor(provider(DescriptorDialect::Empty, &mut calls)).unwrap();
+        assert_eq!("testing:dialect", d.id);
+        assert_eq!(DescriptorDialect::Empty, dialect);
+        assert_eq!(vec![Vec::<u8>::new()], calls);
+
+        let mut calls = Vec::new();
+        let (d, dialect) =
+            negotiate_descriptor(provider(DescriptorDialect::Legacy, &mut calls)).unwrap();
+        assert_eq!("testing:dialect", d.id);
+        assert_eq!(DescriptorDialect::Legacy, dialect);
+        assert_eq!(vec![Vec::new(), DescriptorDialect::Legacy.payload()], calls);
+
+        // A provider that answers neither fails to load
+        let mut calls = 0;
+        assert!(negotiate_descriptor(|_, _| {
+            calls += 1;
+            Err(crate::errors::new(crate::errors::ErrorKind::MiscHost(
+                "broken".to_string(),
+            )))
+        })
+        .is_err());
+        assert_eq!(2, calls);
+    }
+
+    #[test]
+    fn origin_chain_restored_after_panic() {
+        let outer = vec!["Mouter".to_string()];
+        let inner = vec!["Mouter".to_string(), "Minner".to_string()];
+        super::with_origin_chain(&outer, || {
+            let res = std::panic::catch_unwind(|| {
+                super::with_origin_chain(&inner, || panic!("guest panicked"))
+            });
+            assert!(res.is_err());
+            assert_eq!(outer, super::ACTIVE_CHAIN.with(|c| c.borrow().clone()));
+        });
+        assert!(super::ACTIVE_CHAIN.with(|c| c.borrow().is_empty()));
+    }
 }
@@ -68,47 +68,178 @@ extern crate log;
 extern crate crossbeam;
 
 mod actor;
-mod authz;
+mod actorinfo;
+#[cfg(feature = "admin_api")]
+mod admin;
+mod antiforgery;
+pub mod audit;
+pub mod authz;
+mod bindings;
 mod bus;
 mod capability;
+pub mod config;
 mod dispatch;
 pub mod errors;
-mod extras;
+pub mod events;
+pub mod extras;
+mod features;
+pub mod health;
+mod hostinfo;
+pub mod hostmeta;
+mod imports;
+mod inflight;
 mod inthost;
+mod locks;
+pub mod logging;
 #[cfg(feature = "manifest")]
 mod manifest;
 pub mod middleware;
 mod plugins;
+pub mod quick;
+#[cfg(feature = "signals")]
+mod signals;
+mod signer;
+mod snapshot;
 mod spawns;
+mod stats;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod timings;
+#[cfg(feature = "watch")]
+mod watch;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const REVISION: u32 = 2;
 
+// How long shutdown waits for actors and capability providers to terminate
+const SHUTDOWN_TIMEOUT_MS: u64 = 5_000;
+// How long removing a capability provider waits for it to terminate before cleaning up after it
+const PROVIDER_TERMINATION_TIMEOUT_MS: u64 = 1_000;
+// How long replace_actor waits for the actor's in-flight invocations to finish
+const REPLACE_IDLE_TIMEOUT_MS: u64 = 2_000;
+// How long applying a manifest waits for each actor and capability provider it adds to be
+// reachable over the bus, and how often readiness is re-checked in the meantime
+const MANIFEST_READY_TIMEOUT_MS: u64 = 5_000;
+const READY_POLL_INTERVAL_MS: u64 = 10;
+// How long claims registered ahead of their actor are kept by default
+const CLAIMS_REGISTRATION_TTL_MS: u64 = 600_000;
+
+/// Prefix reserved for configuration values injected by the host when binding an actor to a
+/// capability provider. Bindings supplying configuration keys with this prefix are rejected
+pub const CONFIG_WASCC_RESERVED_PREFIX: &str = "__wascc_";
+/// Configuration key containing the name of the binding (e.g. `default`) being configured
+pub const CONFIG_WASCC_BINDING_NAME: &str = "__wascc_binding_name";
+/// Configuration key containing the public key of the host performing the binding
+pub const CONFIG_WASCC_HOST_ID: &str = "__wascc_host_id";
+/// Prefix for configuration keys containing host labels. Only labels explicitly selected with
+/// `HostBuilder::with_binding_metadata` are forwarded, e.g. `__wascc_host_label_region`
+pub const CONFIG_WASCC_HOST_LABEL_PREFIX: &str = "__wascc_host_label_";
+/// Configuration key containing the configuration name of a binding set with
+/// `Host::set_binding_named`, so that a provider can keep separate resources for each of an
+/// actor's named bindings to it. Absent for bindings set with `set_binding`
+pub const CONFIG_WASCC_CONFIG_NAME: &str = "__wascc_config_name";
+/// Configuration key set to `true` when a binding's configuration is delivered by
+/// `Host::update_binding_values` rather than `set_binding`. Providers that recognize it can apply
+/// the new values to the resources they already hold for the actor; others treat the
+/// configuration as a re-bind
+pub const CONFIG_WASCC_UPDATE: &str = "__wascc_update";
+
+/// The operation a capability provider dispatches to the system actor (`wascc_codec::SYSTEM_ACTOR`)
+/// to report that it can no longer serve one of its bindings, e.g. because the credentials in
+/// the binding's configuration were revoked. The payload is a `CapabilityConfiguration` whose
+/// module is the bound actor, optionally with a `reason` configuration value. The host removes
+/// the binding and notifies the actor with `OP_BINDING_REMOVED` (or the operation set with
+/// `HostBuilder::with_binding_removed_operation`)
+pub const OP_BINDING_FAILED: &str = "BindingFailed";
+/// The operation invoked on an actor, by default, when one of its bindings is removed at the
+/// request of the provider. The payload is a `CapabilityConfiguration` for the actor whose
+/// values hold the `capid`, `binding`, and `reason` of the removed binding
+pub const OP_BINDING_REMOVED: &str = "BindingRemoved";
+
 pub type Result<T> = std::result::Result<T, errors::Error>;
 
+/// Determines how an actor's tags must match the tags in a query
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TagMatch {
+    /// The actor must have every tag in the query
+    All,
+    /// The actor must have at least one of the tags in the query
+    Any,
+}
+
+/// Selects the actors in a host that an operation is broadcast to with
+/// `Host::broadcast_to_actors`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ActorFilter {
+    /// Every actor in the host
+    All,
+    /// Actors whose tags match the given tags, compared as in `Host::actors_by_tag`
+    Tags(Vec<String>, TagMatch),
+    /// Actors attested for the given capability ID
+    Capability(String),
+}
+
 pub use actor::Actor;
-pub use capability::NativeCapability;
-pub use inthost::{Invocation, InvocationResponse, WasccEntity};
+pub use actorinfo::ActorRuntimeInfo;
+pub use antiforgery::{AntiforgeryCache, DEFAULT_ANTIFORGERY_CACHE_CAPACITY};
+pub use audit::InvocationAuditEntry;
+pub use capability::{NativeCapability, CODEC_VERSION};
+pub use dispatch::{TryDispatcher, TRY_DISPATCH_MAX_PENDING};
+pub use health::{HealthCheckConfig, HealthStatus, ProviderHealth};
+pub use hostinfo::HostInfo;
+pub use imports::ImportReport;
+pub use inthost::{
+    invocation_hash, ImageFetcher, Invocation, InvocationBuilder, InvocationResponse, WasccEntity,
+    CONTENT_TYPE_OPERATION_PREFIX, MAX_ORIGIN_CHAIN,
+};
+pub use logging::{LogRecord, LoggingConfig};
+pub use plugins::ProviderStats;
+pub use quick::QuickHost;
+pub use snapshot::{BindingSnapshot, HostSnapshot};
+pub use stats::{HostStats, REASON_AUTHORIZER_DENIED, REASON_MISSING_ATTESTATION};
+pub use timings::{HostTimings, TimingEntry};
 
 #[cfg(feature = "manifest")]
-pub use manifest::{BindingEntry, HostManifest};
+pub use manifest::{
+    BindingEntry, BindingTemplate, EntryResult, HostManifest, ManifestReport, PreflightReport,
+    TemplateBinding, MAX_MANIFEST_VERSION, TEMPLATE_PLACEHOLDERS,
+};
 
 #[cfg(feature = "prometheus_middleware")]
 pub use middleware::prometheus;
 
+#[cfg(feature = "watch")]
+pub use watch::WatchHandle;
+
+#[cfg(feature = "test-lattice")]
+pub use bus::memlattice;
+
 #[cfg(feature = "lattice")]
 use latticeclient::BusEvent;
 
 #[cfg(feature = "lattice")]
 use bus::lattice::ControlCommand;
 
-pub use authz::Authorizer;
-pub use middleware::Middleware;
+#[cfg(feature = "lattice")]
+pub use bus::lattice::{LatticeConfig, LatticeOptions};
+
+#[cfg(feature = "lattice")]
+pub use bus::controlauth::sign_control_command;
+
+#[cfg(feature = "lattice")]
+pub use events::{LatticeEvent, LatticeEventStream, ReasonCode};
+
+pub use authz::{
+    AuthorizationContext, Authorizer, QuarantinedActor, REQUESTED_BY_CONTROL_PLANE,
+    REQUESTED_BY_HOST,
+};
+pub use bus::DeliveryMode;
+pub use events::HostEvent;
+pub use middleware::{Middleware, MiddlewareScope, ScopedMiddleware};
 pub use wapc::WasiParams;
 
 pub type SubjectClaimsPair = (String, Claims<wascap::jwt::Actor>);
 
-use crate::inthost::fetch_oci_bytes;
 use bus::{get_namespace_prefix, MessageBus};
 use crossbeam::Sender;
 #[cfg(feature = "lattice")]
@@ -116,24 +247,26 @@ use crossbeam_channel as channel;
 use crossbeam_channel::Receiver;
 #[cfg(any(feature = "lattice", feature = "manifest"))]
 use inthost::RESTRICTED_LABELS;
+use locks::{MutexExt, RwLockExt};
+#[cfg(feature = "manifest")]
+use manifest::run_entries;
 use plugins::PluginManager;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
 use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock},
+    collections::{hash_map::Entry, HashMap},
+    sync::{Arc, Mutex, RwLock},
 };
 use wascap::jwt::Claims;
 use wascap::prelude::KeyPair;
 use wascc_codec::{
     capabilities::CapabilityDescriptor,
+    capabilities::OP_GET_CAPABILITY_DESCRIPTOR,
     core::{CapabilityConfiguration, OP_BIND_ACTOR},
     serialize, SYSTEM_ACTOR,
 };
 
-type BindingsList = HashMap<BindingTuple, CapabilityConfiguration>;
-type BindingTuple = (String, String, String); // (from-actor, to-capid, to-binding-name)
-
 /// A routing key is a combination of a capability ID and the binding name used for
 /// that capability. Think of it as a unique or primary key for a capid+binding.
 #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone)]
@@ -156,6 +289,42 @@ pub struct HostBuilder {
     labels: HashMap<String, String>,
     ns: Option<String>,
     authorizer: Box<dyn Authorizer + 'static>,
+    binding_metadata: Vec<String>,
+    fetcher: Box<dyn ImageFetcher + 'static>,
+    work_dir: PathBuf,
+    started_hooks: Vec<inthost::Hook>,
+    audit_capacity: usize,
+    binding_removed_op: String,
+    env_labels: HashMap<String, String>,
+    health_checks: Option<HealthCheckConfig>,
+    hostmeta: bool,
+    extras: bool,
+    capability_allowlist: Option<Vec<String>>,
+    actor_only: bool,
+    logging: Option<LoggingConfig>,
+    default_binding: String,
+    invocation_signer: Option<String>,
+    antiforgery_cache: Option<usize>,
+    claims_skew: Duration,
+    claims_registration_ttl: Duration,
+    quarantine: bool,
+    strict_imports: bool,
+    middleware_policy: middleware::ErrorPolicy,
+    timing_report: bool,
+    #[cfg(feature = "lattice")]
+    lattice_options: LatticeOptions,
+    #[cfg(feature = "lattice")]
+    lattice_config: LatticeConfig,
+    #[cfg(feature = "lattice")]
+    control_issuers: Vec<String>,
+    #[cfg(feature = "lattice")]
+    binding_sync: bool,
+    #[cfg(feature = "test-lattice")]
+    mem_broker: Option<bus::memlattice::MemBroker>,
+    #[cfg(feature = "admin_api")]
+    admin_socket: Option<String>,
+    #[cfg(feature = "admin_api")]
+    admin_token: Option<String>,
 }
 
 impl HostBuilder {
@@ -168,6 +337,42 @@ impl HostBuilder {
             labels: inthost::detect_core_host_labels(),
             ns: get_namespace_prefix(),
             authorizer: Box::new(authz::DefaultAuthorizer::new()),
+            binding_metadata: Vec::new(),
+            fetcher: Box::new(inthost::OciFetcher {}),
+            work_dir: std::env::temp_dir(),
+            started_hooks: Vec::new(),
+            audit_capacity: audit::DEFAULT_AUDIT_CAPACITY,
+            binding_removed_op: OP_BINDING_REMOVED.to_string(),
+            env_labels: HashMap::new(),
+            health_checks: None,
+            hostmeta: true,
+            extras: true,
+            capability_allowlist: None,
+            actor_only: false,
+            logging: None,
+            default_binding: capability::DEFAULT_BINDING.to_string(),
+            invocation_signer: None,
+            antiforgery_cache: None,
+            claims_skew: Duration::from_secs(0),
+            claims_registration_ttl: Duration::from_millis(CLAIMS_REGISTRATION_TTL_MS),
+            quarantine: false,
+            strict_imports: false,
+            middleware_policy: middleware::ErrorPolicy::default(),
+            timing_report: false,
+            #[cfg(feature = "lattice")]
+            lattice_options: LatticeOptions::from_env(),
+            #[cfg(feature = "lattice")]
+            lattice_config: LatticeConfig::default(),
+            #[cfg(feature = "lattice")]
+            control_issuers: Vec::new(),
+            #[cfg(feature = "lattice")]
+            binding_sync: false,
+            #[cfg(feature = "test-lattice")]
+            mem_broker: None,
+            #[cfg(feature = "admin_api")]
+            admin_socket: None,
+            #[cfg(feature = "admin_api")]
+            admin_token: None,
         };
 
         b
@@ -187,6 +392,88 @@ impl HostBuilder {
         }
     }
 
+    /// Sets how long this host waits for a response to a request sent over the lattice. If not
+    /// set, the `LATTICE_RPC_TIMEOUT_MILLIS` environment variable is used, defaulting to 600ms
+    #[cfg(feature = "lattice")]
+    pub fn with_lattice_rpc_timeout(self, timeout: Duration) -> HostBuilder {
+        HostBuilder {
+            lattice_options: LatticeOptions {
+                rpc_timeout: timeout,
+                ..self.lattice_options
+            },
+            ..self
+        }
+    }
+
+    /// Hands invocations of actors and capability providers running in this host directly to
+    /// them, rather than sending them through the NATS server and back (see
+    /// `LatticeConfig::prefer_local`). Invocations of anything running only on other hosts are
+    /// unaffected
+    #[cfg(feature = "lattice")]
+    pub fn with_lattice_prefer_local(self, prefer_local: bool) -> HostBuilder {
+        HostBuilder {
+            lattice_config: LatticeConfig {
+                prefer_local,
+                ..self.lattice_config
+            },
+            ..self
+        }
+    }
+
+    /// Sets how this host connects to the lattice, in place of the options read from the
+    /// `LATTICE_*` environment variables when the builder was created
+    #[cfg(feature = "lattice")]
+    pub fn with_lattice_options(self, lattice_options: LatticeOptions) -> HostBuilder {
+        HostBuilder {
+            lattice_options,
+            ..self
+        }
+    }
+
+    /// Sets separate timeouts for invocations, binding operations, and inventory queries sent
+    /// over the lattice. Timeouts left unset in the configuration use the RPC timeout
+    #[cfg(feature = "lattice")]
+    pub fn with_lattice_config(self, lattice_config: LatticeConfig) -> HostBuilder {
+        HostBuilder {
+            lattice_config,
+            ..self
+        }
+    }
+
+    /// Only accepts control plane commands (launching and terminating actors and providers)
+    /// signed by one of the given issuers, identified by their public keys. Commands are signed
+    /// with `sign_control_command`; unsigned or forged commands are rejected and reported with a
+    /// `ControlCommandRejected` host event. If no issuers are set, unsigned commands are accepted
+    #[cfg(feature = "lattice")]
+    pub fn with_control_issuers(self, issuers: Vec<String>) -> HostBuilder {
+        HostBuilder {
+            control_issuers: issuers,
+            ..self
+        }
+    }
+
+    /// Sets whether the host merges the lattice's bindings into its own (see
+    /// `Host::sync_bindings_from_lattice`) after each actor or native capability provider is
+    /// added to it. Since this queries every host in the lattice, it makes adding actors and
+    /// providers slower. Disabled by default
+    #[cfg(feature = "lattice")]
+    pub fn with_lattice_binding_sync(self, enabled: bool) -> HostBuilder {
+        HostBuilder {
+            binding_sync: enabled,
+            ..self
+        }
+    }
+
+    /// Connects this host to the given in-memory broker instead of the NATS server named by its
+    /// lattice options, so that groups of hosts can run within a single test process
+    #[cfg(feature = "test-lattice")]
+    pub fn with_mem_broker(self, broker: memlattice::MemBroker) -> HostBuilder {
+        HostBuilder {
+            mem_broker: Some(broker),
+            ..self
+        }
+    }
+
     /// Sets a custom authorizer to be used for authorizing actors, capability providers,
     /// and invocation requests. Note that the authorizer cannot be used to implement _less_
     /// strict measures than the default authorizer, it can only be used to implement
@@ -198,6 +485,12 @@ impl HostBuilder {
         }
     }
 
+    /// Sets a chain of authorizers, all of which must allow an action for it to proceed. This is
+    /// shorthand for `with_authorizer(AuthorizerChain::from(authorizers))`
+    pub fn with_authorizers(self, authorizers: Vec<Box<dyn Authorizer>>) -> HostBuilder {
+        self.with_authorizer(authz::AuthorizerChain::from(authorizers))
+    }
+
     /// Adds an arbitrary label->value pair of metadata to the host. Cannot override
     /// reserved labels such as those that begin with `hostcore.` Calling this twice
     /// on the same label will have no effect after the first call.
@@ -209,66 +502,449 @@ impl HostBuilder {
         HostBuilder { labels: hm, ..self }
     }
 
+    /// Selects the host labels that will be forwarded to capability providers in the configuration
+    /// values of every binding, as `__wascc_host_label_{label}` keys. No labels are forwarded by
+    /// default. The binding name (`__wascc_binding_name`) and host ID (`__wascc_host_id`) are
+    /// always included
+    pub fn with_binding_metadata(self, labels: &[&str]) -> HostBuilder {
+        HostBuilder {
+            binding_metadata: labels.iter().map(|l| l.to_string()).collect(),
+            ..self
+        }
+    }
+
+    /// Sets the image fetcher used to retrieve actors and capability providers by OCI image
+    /// reference, replacing the default OCI registry client
+    pub fn with_image_fetcher(self, fetcher: impl ImageFetcher + 'static) -> HostBuilder {
+        HostBuilder {
+            fetcher: Box::new(fetcher),
+            ..self
+        }
+    }
+
+    /// Sets the directory in which the host keeps scratch files, such as the capability provider
+    /// libraries extracted from provider archives. The host works within its own subdirectory of
+    /// this directory, created on first use with permissions restricting it to the current user
+    /// and removed when the host is shut down. Defaults to the operating system's temporary
+    /// directory, which cannot be used on systems where it is mounted `noexec`
+    pub fn with_work_dir(self, path: impl AsRef<Path>) -> HostBuilder {
+        HostBuilder {
+            work_dir: path.as_ref().to_path_buf(),
+            ..self
+        }
+    }
+
+    /// Sets the number of recent invocations recorded for each actor and made available through
+    /// `Host::actor_recent_invocations`. Defaults to 256. Setting this to 0 disables the audit
+    pub fn with_audit_capacity(self, capacity: usize) -> HostBuilder {
+        HostBuilder {
+            audit_capacity: capacity,
+            ..self
+        }
+    }
+
+    /// Sets the operation invoked on an actor when a capability provider reports that it can
+    /// no longer serve the actor's binding (see `OP_BINDING_FAILED`). Defaults to
+    /// `OP_BINDING_REMOVED`
+    pub fn with_binding_removed_operation(self, operation: &str) -> HostBuilder {
+        HostBuilder {
+            binding_removed_op: operation.to_string(),
+            ..self
+        }
+    }
+
+    /// Enables periodic health probes of this host's capability providers, as described in the
+    /// [health](health/index.html) module. Health checks are disabled by default
+    pub fn with_health_checks(self, config: HealthCheckConfig) -> HostBuilder {
+        HostBuilder {
+            health_checks: Some(config),
+            ..self
+        }
+    }
+
+    /// Sets whether the built-in `wascc:hostmeta` provider, which lets actors attested for it
+    /// read the host's ID, labels, and namespace, is added to the host. See the
+    /// [hostmeta](hostmeta/index.html) module. Enabled by default
+    pub fn with_hostmeta_provider(self, enabled: bool) -> HostBuilder {
+        HostBuilder {
+            hostmeta: enabled,
+            ..self
+        }
+    }
+
+    /// Sets whether the built-in `wascc:extras` provider, which gives actors attested for it
+    /// random numbers, GUIDs, and sequences, is added to the host. Enabled by default
+    pub fn with_extras_provider(self, enabled: bool) -> HostBuilder {
+        HostBuilder {
+            extras: enabled,
+            ..self
+        }
+    }
+
+    /// Restricts the capability providers this host may load, whether native or portable,
+    /// added directly or started over the lattice, to those whose capability IDs are in the
+    /// list (compared ignoring case and surrounding whitespace), independent of the
+    /// capabilities actors are attested for. Adding any other provider fails with an
+    /// `Authorization` error. The extras provider is always allowed unless it was disabled with
+    /// `with_extras_provider`, while the other built-in providers are only added if allowed.
+    /// The effective allowlist is reported by `Host::info`
+    pub fn with_capability_allowlist(self, capids: Vec<String>) -> HostBuilder {
+        HostBuilder {
+            capability_allowlist: Some(capids),
+            ..self
+        }
+    }
+
+    /// Runs the host in actor-only mode, meant for pure compute meshes in which actors only call
+    /// one another. No capability providers are added to the host, including the built-in
+    /// ones, so actors attested for `wascc:extras` or `wascc:hostmeta` aren't bound to them
+    /// either. Adding a native or portable provider, directly or over the lattice, fails, and
+    /// an actor's call to any capability is rejected with a "capabilities disabled on this
+    /// host" error, while calls between actors work as usual. Takes precedence over
+    /// `with_capability_allowlist` and the settings of the built-in providers
+    pub fn actor_only(self) -> HostBuilder {
+        HostBuilder {
+            actor_only: true,
+            ..self
+        }
+    }
+
+    /// Adds the built-in `wascc:logging` provider to the host, which forwards log records from
+    /// the actors bound to it to the host's logger. See the [logging](logging/index.html)
+    /// module. The provider isn't added by default, since it can't be used alongside a
+    /// separately loaded `wascc:logging` provider
+    pub fn with_logging_provider(self, config: LoggingConfig) -> HostBuilder {
+        HostBuilder {
+            logging: Some(config),
+            ..self
+        }
+    }
+
+    /// Sets the binding name used whenever a binding name isn't given, e.g. when calling
+    /// `set_binding` with `None` or loading a provider without a binding name. Defaults to
+    /// `default`. Setting it to something specific to a deployment, like the name of its
+    /// environment, makes bindings that don't name the environment they were meant for fail
+    /// rather than silently landing on another. The name must be usable in a message bus
+    /// subject, so it can't be empty or contain whitespace or any of `.`, `*`, and `>`
+    pub fn with_default_binding_name(self, name: &str) -> HostBuilder {
+        if name.is_empty()
+            || name
+                .chars()
+                .any(|c| c.is_whitespace() || c == '.' || c == '*' || c == '>')
+        {
+            panic!("Cannot use '{}' as the default binding name", name);
+        }
+        HostBuilder {
+            default_binding: name.to_string(),
+            ..self
+        }
+    }
+
+    /// Signs the invocations this host sends with the given key (an encoded seed) rather than
+    /// with the key that identifies the host, so that the signing key can be rotated (see
+    /// `Host::rotate_invocation_signer`) without changing the host's identity. Once set, the
+    /// host only accepts invocations signed with its signing key, so in lattice mode every host
+    /// should be given the same key. Without one, each invocation must be signed by the host
+    /// that sent it
+    pub fn with_invocation_signer(self, seed: &str) -> HostBuilder {
+        if KeyPair::from_seed(seed).is_err() {
+            panic!("Cannot use an invalid seed as the invocation signing key");
+        }
+        HostBuilder {
+            invocation_signer: Some(seed.to_string()),
+            ..self
+        }
+    }
+
+    /// Caches the claims of the invocations this host receives over the lattice once their
+    /// signatures have been verified, holding at most `capacity` claims tokens, so that claims
+    /// checked again (e.g. when an invocation is redelivered) skip the signature verification.
+    /// The rest of the antiforgery checks are still made for every invocation. Disabled by
+    /// default
+    pub fn with_antiforgery_cache(self, capacity: usize) -> HostBuilder {
+        HostBuilder {
+            antiforgery_cache: Some(capacity),
+            ..self
+        }
+    }
+
+    /// Sets how far the clock of the host may differ from the clocks of the issuers of actors'
+    /// claims. Claims are accepted when they expired or become valid no more than this long
+    /// ago or from now, respectively, whether the actor is added directly, from a manifest, or
+    /// by a lattice control command. Tolerances are in whole seconds. Defaults to none
+    pub fn with_claims_skew_tolerance(self, tolerance: Duration) -> HostBuilder {
+        HostBuilder {
+            claims_skew: tolerance,
+            ..self
+        }
+    }
+
+    /// Sets how long claims registered with `Host::register_actor_claims` are kept before
+    /// their actor is added. A registration lapses after this long, or when the claims expire
+    /// if that's sooner. Defaults to 10 minutes
+    pub fn with_claims_registration_ttl(self, ttl: Duration) -> HostBuilder {
+        HostBuilder {
+            claims_registration_ttl: ttl,
+            ..self
+        }
+    }
+
+    /// Sets whether actors whose claims fail validation when added are recorded, along with
+    /// the reason and the claims' validity period, so that they can be listed with
+    /// `Host::quarantined_actors`. Adding such an actor still fails. Disabled by default
+    pub fn with_actor_quarantine(self, enabled: bool) -> HostBuilder {
+        HostBuilder {
+            quarantine: enabled,
+            ..self
+        }
+    }
+
+    /// Sets whether adding an actor whose module imports WASI or anything else the host doesn't
+    /// provide to actors (anything but the waPC host functions) fails. Otherwise, which is the
+    /// default, a warning is logged and the actor is added regardless. See `Actor::imports`
+    pub fn with_strict_imports(self, strict: bool) -> HostBuilder {
+        HostBuilder {
+            strict_imports: strict,
+            ..self
+        }
+    }
+
+    /// Sets how errors returned by middleware's pre- and post-invoke hooks are treated, unless a
+    /// middleware chooses for itself with `Middleware::error_policy`. The default,
+    /// `ErrorPolicy::FailOpen`, logs the error and carries on with the invocation as though the
+    /// failing hook hadn't run. `ErrorPolicy::FailClosed` rejects the invocation with an error
+    /// response instead. Errors returned by invoke hooks always reject the invocation
+    pub fn with_middleware_error_policy(self, policy: middleware::ErrorPolicy) -> HostBuilder {
+        HostBuilder {
+            middleware_policy: policy,
+            ..self
+        }
+    }
+
+    /// Sets whether the timings recorded by the host (see `Host::timings`) are logged at info
+    /// level, one line per phase, once the host has shut down. Disabled by default
+    pub fn with_timing_report(self, enabled: bool) -> HostBuilder {
+        HostBuilder {
+            timing_report: enabled,
+            ..self
+        }
+    }
+
+    /// Serves a local, read-only admin API for this host on the given address, which is either
+    /// a TCP socket address (e.g. `127.0.0.1:9900`) or the path of a Unix domain socket. The
+    /// `/info`, `/actors`, `/capabilities`, `/bindings`, and `/stats` endpoints return JSON
+    /// backed by the corresponding host accessors (binding configuration values are omitted,
+    /// only their names are listed), `/snapshot` returns the actors, providers, bindings, and
+    /// labels together as read by `Host::snapshot`, and `POST /shutdown` shuts the host down if it carries the
+    /// token set with `with_admin_token`. The server is stopped when the host shuts down
+    #[cfg(feature = "admin_api")]
+    pub fn with_admin_socket(self, addr: &str) -> HostBuilder {
+        HostBuilder {
+            admin_socket: Some(addr.to_string()),
+            ..self
+        }
+    }
+
+    /// Sets the bearer token required by the admin API's `POST /shutdown` endpoint, which is
+    /// refused if no token is set
+    #[cfg(feature = "admin_api")]
+    pub fn with_admin_token(self, token: &str) -> HostBuilder {
+        HostBuilder {
+            admin_token: Some(token.to_string()),
+            ..self
+        }
+    }
+
+    /// Registers a function to be called once the host has been built and is ready to accept
+    /// actors and capability providers. Hooks are called in the order in which they were
+    /// registered, and a panic within a hook is logged rather than propagated
+    pub fn on_started(self, hook: impl FnOnce() + Send + 'static) -> HostBuilder {
+        let mut started_hooks = self.started_hooks;
+        started_hooks.push(Box::new(hook));
+        HostBuilder {
+            started_hooks,
+            ..self
+        }
+    }
+
     /// Converts the transient builder instance into a realized host runtime instance
     pub fn build(self) -> Host {
-        #[cfg(not(feature = "lattice"))]
-        let h = Host::generate(self.authorizer, self.labels, self.ns.clone());
-        #[cfg(feature = "lattice")]
-        let h = Host::generate(self.authorizer, self.labels, self.ns.clone());
-        h
+        Host::generate(self)
     }
 }
 
-/// Represents an instance of a waSCC host runtime
+/// Represents an instance of a waSCC host runtime. Unless lattice mode is enabled, every host
+/// has its own message bus and keeps no state outside of itself, so any number of hosts can run
+/// in the same process, even with the same actors and capability providers, without one seeing
+/// another's invocations, bindings, or events. Clones of a host refer to the same runtime
 #[derive(Clone)]
 pub struct Host {
     bus: Arc<MessageBus>,
     claims: Arc<RwLock<HashMap<String, Claims<wascap::jwt::Actor>>>>,
+    // claims of actors that have been registered ahead of being added to this host
+    registered_claims: Arc<RwLock<HashMap<String, authz::Registration>>>,
     plugins: Arc<RwLock<PluginManager>>,
-    bindings: Arc<RwLock<BindingsList>>,
+    bindings: Arc<RwLock<bindings::Bindings>>,
     caps: Arc<RwLock<HashMap<RouteKey, CapabilityDescriptor>>>,
     middlewares: Arc<RwLock<Vec<Box<dyn Middleware>>>>,
     // the key to this field is the subscription subject, and not either a pk or a capid
     terminators: Arc<RwLock<HashMap<String, Sender<bool>>>>,
     pk: String,
-    sk: String,
     authorizer: Arc<RwLock<Box<dyn Authorizer>>>,
     labels: Arc<RwLock<HashMap<String, String>>>,
     // mapping between OCI registry image references and the associated unique identity (e.g. "Mxxx" and "Vxxx")
     image_map: Arc<RwLock<HashMap<String, String>>>,
     ns: Option<String>,
+    // labels forwarded to capability providers in binding configuration
+    binding_metadata: Arc<Vec<String>>,
+    actor_origins: Arc<RwLock<HashMap<String, inthost::Origin>>>,
+    provider_origins: Arc<RwLock<HashMap<RouteKey, inthost::Origin>>>,
+    fetcher: Arc<dyn ImageFetcher>,
+    // this host's own subdirectory of the configured work directory
+    work_dir: PathBuf,
+    shutdown_hooks: Arc<Mutex<Vec<inthost::Hook>>>,
+    // held for an (actor, capid, binding) while a binding is delivered, so that an update merges
+    // its values into the configuration left by the binding changes before it
+    binding_changes: Arc<locks::KeyedLocks>,
+    audit: Arc<audit::AuditLog>,
+    health: Arc<health::HealthMonitor>,
+    in_flight: Arc<inflight::InFlight>,
+    // the binding name used when an operation doesn't name one
+    default_binding: String,
+    // signs the invocations sent by this host
+    signer: Arc<signer::InvocationSigner>,
+    claims_skew: Duration,
+    // how long claims registered ahead of their actor are kept
+    claims_registration_ttl: Duration,
+    // actors that failed claims validation, if quarantine is enabled
+    quarantine: Option<Arc<RwLock<Vec<QuarantinedActor>>>>,
+    strict_imports: bool,
+    middleware_policy: middleware::ErrorPolicy,
+    #[cfg(feature = "lattice")]
+    binding_sync: bool,
+    // signalled by each actor's thread once it has finished cleaning up after being removed
+    actor_exits: Arc<RwLock<HashMap<String, Receiver<()>>>>,
+    actor_runtime: Arc<actorinfo::ActorRuntime>,
+    started: SystemTime,
+    capability_allowlist: Arc<authz::CapabilityAllowlist>,
+    // the sequences of the extras provider, if it's enabled
+    extras_sequences: Option<extras::Sequences>,
+    timings: Arc<timings::TimingRegistry>,
+    // whether the timings are logged on shutdown
+    timing_report: bool,
+    // re-establish the lattice's bindings to each native provider in this host
+    rebinders: Arc<RwLock<HashMap<RouteKey, spawns::Rebinder>>>,
 }
 
 impl Host {
     /// Creates a new runtime host using all of the default values. Use the host builder
     /// if you want to provide more customization options
     pub fn new() -> Self {
-        let h = Self::generate(
-            Box::new(authz::DefaultAuthorizer::new()),
-            inthost::detect_core_host_labels(),
-            get_namespace_prefix(),
-        );
-        h
+        HostBuilder::new().build()
+    }
+
+    /// Starts a quick setup of a new host with default values, chaining the actor and
+    /// capability provider files to add and the bindings between them. See the `quick` module
+    pub fn quick() -> QuickHost {
+        QuickHost::new(Host::new())
     }
 
-    pub(crate) fn generate(
-        authz: Box<dyn Authorizer + 'static>,
-        labels: HashMap<String, String>,
-        ns: Option<String>,
-    ) -> Self {
+    pub(crate) fn generate(builder: HostBuilder) -> Self {
+        let HostBuilder {
+            labels,
+            ns,
+            authorizer: authz,
+            binding_metadata,
+            fetcher,
+            work_dir,
+            started_hooks,
+            audit_capacity,
+            binding_removed_op,
+            env_labels,
+            health_checks,
+            hostmeta,
+            extras,
+            capability_allowlist,
+            actor_only,
+            logging,
+            default_binding,
+            invocation_signer,
+            antiforgery_cache,
+            claims_skew,
+            claims_registration_ttl,
+            quarantine,
+            strict_imports,
+            middleware_policy,
+            timing_report,
+            #[cfg(feature = "lattice")]
+            lattice_options,
+            #[cfg(feature = "lattice")]
+            lattice_config,
+            #[cfg(feature = "lattice")]
+            control_issuers,
+            #[cfg(feature = "lattice")]
+            binding_sync,
+            #[cfg(feature = "test-lattice")]
+            mem_broker,
+            #[cfg(feature = "admin_api")]
+            admin_socket,
+            #[cfg(feature = "admin_api")]
+            admin_token,
+        } = builder;
+        let generate_start = Instant::now();
+        let timings = Arc::new(timings::TimingRegistry::default());
+        // None of the built-in providers may be added to an actor-only host
+        let extras = extras && !actor_only;
+        let hostmeta = hostmeta && !actor_only;
+        let logging = logging.filter(|_| !actor_only);
+        let mut labels = labels;
+        for (label, value) in env_labels {
+            labels.entry(label).or_insert(value);
+        }
         let key = KeyPair::new_server();
+        let signer = match invocation_signer {
+            Some(ref seed) => signer::InvocationSigner::new(&key.public_key(), seed, true),
+            None => signer::InvocationSigner::new(&key.public_key(), &key.seed().unwrap(), false),
+        }
+        .unwrap();
+        let signer = Arc::new(match antiforgery_cache {
+            Some(capacity) => signer.with_cache(AntiforgeryCache::new(capacity)),
+            None => signer,
+        });
+        timings.since("host.generate.keys", generate_start);
         let claims = Arc::new(RwLock::new(HashMap::new()));
         let caps = Arc::new(RwLock::new(HashMap::new()));
-        let bindings = Arc::new(RwLock::new(HashMap::new()));
+        let bindings = Arc::new(RwLock::new(bindings::Bindings::default()));
         let labels = Arc::new(RwLock::new(labels));
         let terminators = Arc::new(RwLock::new(HashMap::new()));
         let authz = Arc::new(RwLock::new(authz));
         let image_map = Arc::new(RwLock::new(HashMap::new()));
+        let actor_origins = Arc::new(RwLock::new(HashMap::new()));
+        let provider_origins = Arc::new(RwLock::new(HashMap::new()));
+        let actor_runtime = Arc::new(actorinfo::ActorRuntime::default());
+        let started = SystemTime::now();
+        let middlewares = Arc::new(RwLock::new(vec![]));
+        let stats = Arc::new(stats::StatsCounters::new(middlewares.clone()));
 
         #[cfg(feature = "lattice")]
         let (com_s, com_r): (Sender<ControlCommand>, Receiver<ControlCommand>) =
             channel::unbounded();
 
+        // Hosts dial the NATS server named by their lattice options unless given a broker, or
+        // told to join the one shared by the process
+        #[cfg(feature = "test-lattice")]
+        let conn = mem_broker
+            .or_else(|| {
+                if lattice_options.host == memlattice::SHARED_BROKER_HOST {
+                    Some(memlattice::MemBroker::shared())
+                } else {
+                    None
+                }
+            })
+            .map(|b| b.connect().into());
+        #[cfg(all(feature = "lattice", not(feature = "test-lattice")))]
+        let conn = None;
+
         #[cfg(feature = "lattice")]
         let bus = Arc::new(bus::new(
             key.public_key(),
@@ -281,11 +957,35 @@ impl Host {
             com_s,
             authz.clone(),
             image_map.clone(),
+            actor_origins.clone(),
+            provider_origins.clone(),
+            actor_runtime.clone(),
+            started,
+            signer.clone(),
+            lattice_options,
+            lattice_config,
+            control_issuers,
+            conn,
+            stats,
         ));
 
         #[cfg(not(feature = "lattice"))]
-        let bus = Arc::new(bus::new());
+        let bus = Arc::new(bus::new(stats));
 
+        // The host started event can't carry anything beyond the host's ID, so the details
+        // support needs to tell which build a host is running are logged alongside it
+        let capability_allowlist = Arc::new(if actor_only {
+            authz::CapabilityAllowlist::actor_only()
+        } else {
+            authz::CapabilityAllowlist::new(capability_allowlist, extras)
+        });
+        let info = HostInfo::new(
+            &key.public_key(),
+            started,
+            ns.clone(),
+            labels.read_or_recover().clone(),
+            capability_allowlist.ids(),
+        );
         #[cfg(feature = "lattice")]
         let _ = bus.publish_event(BusEvent::HostStarted(key.public_key()));
 
@@ -293,77 +993,214 @@ impl Host {
             terminators: terminators.clone(),
             bus: bus.clone(),
             claims: claims.clone(),
+            registered_claims: Arc::new(RwLock::new(HashMap::new())),
             plugins: Arc::new(RwLock::new(PluginManager::default())),
             bindings,
             caps,
-            middlewares: Arc::new(RwLock::new(vec![])),
+            middlewares,
             pk: key.public_key(),
-            sk: key.seed().unwrap(),
             authorizer: authz,
             labels,
             ns,
             image_map,
+            binding_metadata: Arc::new(binding_metadata),
+            actor_origins,
+            provider_origins,
+            fetcher: Arc::from(fetcher),
+            work_dir: work_dir.join(format!("wascc-{}", key.public_key())),
+            shutdown_hooks: Arc::new(Mutex::new(Vec::new())),
+            binding_changes: Arc::new(locks::KeyedLocks::default()),
+            audit: Arc::new(audit::AuditLog::new(audit_capacity)),
+            health: Arc::new(health::HealthMonitor::new(health_checks)),
+            in_flight: Arc::new(inflight::InFlight::new()),
+            default_binding,
+            signer,
+            claims_skew,
+            claims_registration_ttl,
+            quarantine: if quarantine {
+                Some(Arc::new(RwLock::new(Vec::new())))
+            } else {
+                None
+            },
+            strict_imports,
+            middleware_policy,
+            // enabled once the built-in providers have been added
+            #[cfg(feature = "lattice")]
+            binding_sync: false,
+            actor_exits: Arc::new(RwLock::new(HashMap::new())),
+            actor_runtime,
+            started,
+            capability_allowlist,
+            extras_sequences: if extras {
+                Some(extras::Sequences::default())
+            } else {
+                None
+            },
+            timings,
+            timing_report,
+            rebinders: Arc::new(RwLock::new(HashMap::new())),
         };
 
-        info!("Host ID is {} (v{})", key.public_key(), VERSION);
+        // A single line of key=value pairs, so support tooling can pick the build apart
+        info!(
+            "host_started id={} version={} revision={} engine={} namespace={} features={}",
+            info.id,
+            info.version,
+            info.revision,
+            info.engine,
+            info.namespace.as_deref().unwrap_or("-"),
+            info.features.join(","),
+        );
 
-        host.ensure_extras().unwrap();
+        let builtins_start = Instant::now();
+        if extras {
+            host.ensure_extras().unwrap();
+        }
+        if hostmeta && host.builtin_allowed(hostmeta::CAPABILITY_ID) {
+            host.ensure_hostmeta().unwrap();
+        }
+        if let Some(config) = logging.filter(|_| host.builtin_allowed(logging::CAPABILITY_ID)) {
+            host.add_native_capability(
+                NativeCapability::from_instance(
+                    logging::LoggingCapabilityProvider::new(config),
+                    None,
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        }
+        host.timings.since("host.generate.builtins", builtins_start);
+        #[cfg(feature = "lattice")]
+        let host = Host {
+            binding_sync,
+            ..host
+        };
+        health::spawn_prober(&host);
+        spawns::spawn_system_subscriber(&host, binding_removed_op);
 
         #[cfg(feature = "lattice")]
         let _ = bus::lattice::spawn_controlplane(&host, com_r);
 
+        #[cfg(feature = "admin_api")]
+        if let Some(addr) = admin_socket {
+            if let Err(e) = admin::start(&host, &addr, admin_token) {
+                error!("{}", e);
+            }
+        }
+
+        host.timings.since("host.generate", generate_start);
+        inthost::run_hooks("started", started_hooks);
+
         host
     }
 
-    fn add_actor_imgref(&self, actor: Actor, imgref: Option<String>) -> Result<()> {
+    fn add_actor_imgref(
+        &self,
+        actor: Actor,
+        imgref: Option<String>,
+        delivery: DeliveryMode,
+        annotations: HashMap<String, String>,
+    ) -> Result<()> {
+        let start = Instant::now();
+        let already_hosted = || {
+            errors::new(errors::ErrorKind::MiscHost(
+                format!("Actor {} is already in this host. Cannot host multiple instances of the same actor in the same host", actor.public_key())
+            ))
+        };
         if self
             .claims
-            .read()
-            .unwrap()
+            .read_or_recover()
             .contains_key(&actor.public_key())
         {
-            return Err(errors::new(errors::ErrorKind::MiscHost(
-                format!("Actor {} is already in this host. Cannot host multiple instances of the same actor in the same host", actor.public_key())
-            )));
+            return Err(already_hosted());
         }
-        authz::enforce_validation(&actor.token.jwt)?; // returns an `Err` if validation fails
+        self.validate_actor_claims(&actor.token)?; // returns an `Err` if validation fails
+        self.check_imports(&actor)?;
         if !self.check_auth(&actor.token) {
             // invoke the auth hook, if there is one
             return Err(errors::new(errors::ErrorKind::Authorization(
                 "Authorization hook denied access to module".into(),
             )));
         }
+        self.timings
+            .since(&format!("actor.{}.validate", actor.public_key()), start);
+        self.check_registered_claims(&actor.token)?;
+        let claims = actor.token.claims.clone();
 
         let c = self.claims.clone();
 
-        c.write().unwrap().insert(
-            actor.token.claims.subject.to_string(),
-            actor.token.claims.clone(),
-        );
+        // Another thread may have added the same actor since the check above, so the claims
+        // are only inserted (reserving the actor for this thread) if they're still absent
+        match c
+            .write_or_recover()
+            .entry(actor.token.claims.subject.to_string())
+        {
+            Entry::Occupied(_) => return Err(already_hosted()),
+            Entry::Vacant(e) => {
+                e.insert(claims.clone());
+            }
+        }
+        // Set before the actor starts so that its started event carries them
+        self.actor_runtime
+            .annotate(&actor.public_key(), annotations);
 
-        let key = KeyPair::from_seed(&self.sk).unwrap();
         let wg = crossbeam_utils::sync::WaitGroup::new();
         // Spin up a new thread that listens to "wasmbus.Mxxxx" calls on the message bus
-        spawns::spawn_actor(
+        let spawned = spawns::spawn_actor(
             wg.clone(),
-            actor.token.claims.clone(),
+            claims,
             actor.bytes.clone(),
             None,
             true,
             None,
+            self.default_binding.clone(),
             self.bus.clone(),
             self.middlewares.clone(),
+            self.middleware_policy,
             self.caps.clone(),
             self.bindings.clone(),
             c.clone(),
             self.terminators.clone(),
-            key,
+            self.signer.clone(),
             self.authorizer.clone(),
             self.image_map.clone(),
-            imgref,
-        )?;
+            imgref.clone(),
+            self.labels.clone(),
+            self.ns.clone(),
+            self.audit.clone(),
+            self.health.clone(),
+            self.in_flight.clone(),
+            self.actor_runtime.clone(),
+            self.capability_allowlist.clone(),
+            delivery,
+            self.timings.clone(),
+        )
+        .map_err(|e| {
+            authz::unregister_claims(c.clone(), &actor.public_key());
+            self.actor_runtime.forget_annotations(&actor.public_key());
+            e
+        })?;
         wg.wait();
-        if actor.capabilities().contains(&extras::CAPABILITY_ID.into()) {
+        self.registered_claims
+            .write_or_recover()
+            .remove(&actor.public_key());
+        self.actor_exits
+            .write_or_recover()
+            .insert(actor.public_key(), spawned.exited);
+        if let Some(ref imgref) = imgref {
+            self.image_map
+                .write_or_recover()
+                .insert(imgref.to_string(), actor.public_key());
+        }
+        self.actor_origins
+            .write_or_recover()
+            .insert(actor.public_key(), inthost::Origin::new(imgref));
+        if actor.capabilities().contains(&extras::CAPABILITY_ID.into())
+            && self
+                .caps
+                .read_or_recover()
+                .contains_key(&RouteKey::new(&self.default_binding, extras::CAPABILITY_ID))
+        {
             // force a binding so that there's a private actor subject on the bus for the
             // actor to communicate with the extras provider
             self.set_binding(
@@ -373,7 +1210,26 @@ impl Host {
                 HashMap::new(),
             )?;
         }
+        if actor
+            .capabilities()
+            .contains(&hostmeta::CAPABILITY_ID.into())
+            && self.caps.read_or_recover().contains_key(&RouteKey::new(
+                &self.default_binding,
+                hostmeta::CAPABILITY_ID,
+            ))
+        {
+            self.set_binding(
+                &actor.public_key(),
+                hostmeta::CAPABILITY_ID,
+                None,
+                HashMap::new(),
+            )?;
+        }
+        #[cfg(feature = "lattice")]
+        self.sync_bindings_after_add();
 
+        self.timings
+            .since(&format!("actor.{}.add", actor.public_key()), start);
         Ok(())
     }
 
@@ -381,66 +1237,256 @@ impl Host {
     /// will not be able to make use of capability providers unless bindings are added (or existed prior to the actor
     /// being added to a host, which is possible in `lattice` mode)
     pub fn add_actor(&self, actor: Actor) -> Result<()> {
-        self.add_actor_imgref(actor, None)
+        self.add_actor_imgref(actor, None, DeliveryMode::default(), HashMap::new())
     }
 
-    /// Adds an actor to the host by attempting to retrieve it from an OCI
-    /// registry. This function takes an image reference as an argument, e.g.
-    /// myregistry.mycloud.io/actor:v1
-    /// If OCI credentials are supplied in environment variables, those will be used.
-    pub fn add_actor_from_registry(&self, image: &str) -> Result<()> {
-        let bytes = inthost::fetch_oci_bytes(image)?;
+    /// Adds an actor to the host as `add_actor` does, attaching annotations to it: arbitrary
+    /// key/value metadata such as a deployment ID or a canary flag, which unlike the actor's
+    /// claims don't require re-signing its module. Annotations are local to this host and last
+    /// until the actor is removed, surviving `replace_actor`. They're included in the actor's
+    /// runtime info, its `ActorStarted` and `ActorStopped` events, and (in lattice mode) the
+    /// extended actor inventory
+    pub fn add_actor_with_annotations(
+        &self,
+        actor: Actor,
+        annotations: HashMap<String, String>,
+    ) -> Result<()> {
+        self.add_actor_imgref(actor, None, DeliveryMode::default(), annotations)
+    }
+
+    /// Adds an actor to the host as `add_actor` does, choosing how invocations of the actor are
+    /// shared with other instances of it in the lattice (see `DeliveryMode`). Actors added any
+    /// other way, including those scheduled on this host by the lattice control plane, use
+    /// `DeliveryMode::QueueGroup`
+    pub fn add_actor_with_delivery(&self, actor: Actor, delivery: DeliveryMode) -> Result<()> {
+        bus::validate_delivery_mode(&delivery)?;
+        self.add_actor_imgref(actor, None, delivery, HashMap::new())
+    }
 
-        self.add_actor_imgref(Actor::from_slice(&bytes)?, Some(image.to_string()))?;
+    /// Registers the claims of an actor ahead of the actor itself, so that it can be bound to
+    /// capability providers before it's added to this host (e.g. when bindings are configured
+    /// before the actor is scheduled). Nothing is started: `set_binding` records the binding and
+    /// configures the provider as usual, and the actor uses the binding once it's added. The
+    /// claims are validated as they are when an actor is added, except that there's no signed
+    /// token to verify, so the claims in the actor's module must have the same issuer and
+    /// capabilities as the registered ones when it is added, and from then on the signed claims
+    /// are the ones the host goes by. Only the subject, issuer, capabilities, and validity
+    /// period of the registered claims are kept. The registration lapses after the host's
+    /// registration TTL (see `HostBuilder::with_claims_registration_ttl`), or when the claims
+    /// expire if that's sooner, unless the actor has been added by then
+    pub fn register_actor_claims(&self, claims: Claims<wascap::jwt::Actor>) -> Result<()> {
+        if self.claims.read_or_recover().contains_key(&claims.subject) {
+            return Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                "Actor {} is already in this host",
+                claims.subject
+            ))));
+        }
+        let subject = claims.subject.to_string();
+        let registration = self.registration(claims)?;
+        self.registered_claims
+            .write_or_recover()
+            .insert(subject, registration);
         Ok(())
     }
 
-    /// Adds a portable capability provider (e.g. a WASI actor) to the waSCC host. Portable capability providers adhere
-    /// to the same contract as native capability providers, but they are implemented as "high-privilege WASM" modules
+    /// Withdraws the claims registered for an actor with `register_actor_claims`, so that it
+    /// can no longer be bound before it's added. Bindings already made for the actor are kept
+    /// until they're removed with `remove_binding`
+    pub fn unregister_actor_claims(&self, actor: &str) -> Result<()> {
+        match self.registered_claims.write_or_recover().remove(actor) {
+            Some(_) => Ok(()),
+            None => Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                "No claims are registered for actor {}",
+                actor
+            )))),
+        }
+    }
+
+    /// Adds an actor to the host by attempting to retrieve it from an OCI
+    /// registry. This function takes an image reference as an argument, e.g.
+    /// myregistry.mycloud.io/actor:v1
+    /// If OCI credentials are supplied in environment variables, those will be used.
+    /// Returns the public key of the actor that was added.
+    pub fn add_actor_from_registry(&self, image: &str) -> Result<String> {
+        let start = Instant::now();
+        let actor = inthost::fetch_actor(self.fetcher.as_ref(), image)?;
+        let pk = actor.public_key();
+        self.timings.since(&format!("actor.{}.fetch", pk), start);
+
+        self.add_actor_imgref(
+            actor,
+            Some(image.to_string()),
+            DeliveryMode::default(),
+            HashMap::new(),
+        )?;
+        Ok(pk)
+    }
+
+    /// Adds a portable capability provider (e.g. a WASI actor) to the waSCC host. Portable capability providers adhere
+    /// to the same contract as native capability providers, but they are implemented as "high-privilege WASM" modules
     /// via WASI. Today, there is very little a WASI-based capability provider can do, but in the near future when
     /// WASI gets a standardized networking stack, more providers can be written as portable modules.
+    /// As with native providers, loading a second provider with the same capability ID under the same
+    /// binding name fails.
     pub fn add_capability(
         &self,
         actor: Actor,
         binding: Option<&str>,
         wasi: WasiParams,
     ) -> Result<()> {
-        let binding = binding.unwrap_or("default");
+        self.capability_allowlist
+            .check_enabled(&actor.public_key())?;
+        let binding = binding.unwrap_or(&self.default_binding);
 
         let wg = crossbeam_utils::sync::WaitGroup::new();
-        let key = KeyPair::from_seed(&self.sk).unwrap();
         // Spins up a new thread subscribed to the "wasmbus.{capid}.{binding}" subject
-        spawns::spawn_actor(
+        let spawned = spawns::spawn_actor(
             wg.clone(),
             actor.token.claims,
             actor.bytes.clone(),
             Some(wasi),
             false,
             Some(binding.to_string()),
+            self.default_binding.clone(),
             self.bus.clone(),
             self.middlewares.clone(),
+            self.middleware_policy,
             self.caps.clone(),
             self.bindings.clone(),
             self.claims.clone(),
             self.terminators.clone(),
-            key,
+            self.signer.clone(),
             self.authorizer.clone(),
             self.image_map.clone(),
             None,
+            self.labels.clone(),
+            self.ns.clone(),
+            self.audit.clone(),
+            self.health.clone(),
+            self.in_flight.clone(),
+            self.actor_runtime.clone(),
+            self.capability_allowlist.clone(),
+            DeliveryMode::Broadcast,
+            self.timings.clone(),
         )?;
         wg.wait();
-        Ok(())
+        match spawned.failed.try_recv() {
+            Ok(e) => Err(e),
+            Err(_) => Ok(()),
+        }
     }
 
     /// Removes an actor from the host. Notifies the actor's processing thread to terminate,
     /// which will in turn attempt to unbind that actor from all previously bound capability providers
     /// (in lattice mode, this unbinding only takes place if the actor is the last instance of its
-    /// kind in the lattice)
+    /// kind in the lattice). Fails if the authorizer doesn't allow the actor to be terminated
     pub fn remove_actor(&self, pk: &str) -> Result<()> {
-        self.terminators.read().unwrap()
+        self.authorize_termination(pk, authz::REQUESTED_BY_HOST)?;
+        self.stop_actor(pk)
+    }
+
+    // Checks with the authorizer that the actor may be terminated at the requester's behest,
+    // publishing an event if it may not
+    pub(crate) fn authorize_termination(&self, pk: &str, requested_by: &str) -> Result<()> {
+        // Copied out so the claims aren't locked while the authorizer runs
+        let claims = self.claims.read_or_recover().get(pk).cloned();
+        let claims = match claims {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+        if self
+            .authorizer
+            .read_or_recover()
+            .can_terminate(&claims, requested_by)
+        {
+            return Ok(());
+        }
+        self.bus
+            .publish_host_event(HostEvent::ActorTerminationDenied {
+                actor: pk.to_string(),
+                requested_by: requested_by.to_string(),
+            });
+        Err(errors::new(errors::ErrorKind::Authorization(format!(
+            "Termination of actor {} requested by {} was denied by the authorizer",
+            pk, requested_by
+        ))))
+    }
+
+    // Removes the actor without consulting the authorizer, as when the host shuts down
+    fn stop_actor(&self, pk: &str) -> Result<()> {
+        // The actor's thread unbinds it as it exits, after which the extras providers served
+        // on the other binding names it used may no longer be needed. They're noted before the
+        // thread is told to terminate, since it may have unbound the actor by the time this
+        // would otherwise look
+        let mut extras_bindings: Vec<_> = self
+            .bindings
+            .read_or_recover()
+            .for_actor(pk)
+            .filter(|(k, _)| k.capid == extras::CAPABILITY_ID && k.binding != self.default_binding)
+            .map(|(k, _)| k.binding.to_string())
+            .collect();
+        extras_bindings.sort();
+        extras_bindings.dedup();
+        self.terminators.read_or_recover()
             [&bus::actor_subject(self.ns.as_ref().map(String::as_str), pk)]
             .send(true)
             .unwrap();
+        self.actor_origins.write_or_recover().remove(pk);
+        let exited = self.actor_exits.write_or_recover().remove(pk);
+        self.audit.forget(pk);
+        // The actor's sequences are forgotten once it can no longer request them
+        let host = self.clone();
+        let actor = pk.to_string();
+        let release = move || {
+            host.forget_extras_sequences(&actor);
+            for binding in extras_bindings {
+                host.release_extras_binding(&binding);
+            }
+        };
+        match exited {
+            Some(exited) => {
+                std::thread::spawn(move || {
+                    let _ = exited.recv();
+                    release();
+                });
+            }
+            None => release(),
+        }
+        Ok(())
+    }
+
+    /// Removes an actor from the host as with `remove_actor`, but waits until the actor has
+    /// been unbound from its capability providers and is no longer in the host before returning,
+    /// failing if that takes longer than the timeout. Use this when the same actor is added again
+    /// right after being removed, so that the old instance's unbinding can't undo the new
+    /// instance's bindings
+    pub fn remove_actor_sync(&self, pk: &str, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let exited = self.actor_exits.read_or_recover().get(pk).cloned();
+        self.remove_actor(pk)?;
+        let timed_out = || {
+            errors::new(errors::ErrorKind::MiscHost(format!(
+                "Actor {} was not removed within {:?}",
+                pk, timeout
+            )))
+        };
+        if let Some(exited) = exited {
+            // The actor's thread also disconnects the channel if it exits some other way
+            if let Err(crossbeam_channel::RecvTimeoutError::Timeout) =
+                exited.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                return Err(timed_out());
+            }
+        }
+        let subject = bus::actor_subject(self.ns.as_ref().map(String::as_str), pk);
+        while self.terminators.read_or_recover().contains_key(&subject)
+            || self.claims.read_or_recover().contains_key(pk)
+        {
+            if Instant::now() >= deadline {
+                return Err(timed_out());
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
         Ok(())
     }
 
@@ -449,14 +1495,57 @@ impl Host {
     /// providers (e.g. messages from subscriptions or HTTP requests) to build up in a backlog,
     /// so make sure the new actor can handle this stream of these delayed messages. Also ensure that
     /// the underlying WebAssembly driver (chosen via feature flag) supports hot-swapping module bytes.
+    /// Invocations the actor is already handling are given a couple of seconds to finish first.
+    /// Fails, leaving the running actor in place, if the authorizer doesn't allow the replacement.
     pub fn replace_actor(&self, new_actor: Actor) -> Result<()> {
-        let key = KeyPair::from_seed(&self.sk).unwrap();
-        crate::inthost::replace_actor(&key, self.bus.clone(), new_actor)
+        let pk = new_actor.public_key();
+        let old_claims = self.claims.read_or_recover().get(&pk).cloned();
+        if let Some(old_claims) = old_claims {
+            if !self
+                .authorizer
+                .read_or_recover()
+                .can_replace(&old_claims, &new_actor.token.claims)
+            {
+                self.bus.publish_host_event(HostEvent::ActorReplaceDenied {
+                    actor: pk.to_string(),
+                });
+                #[cfg(feature = "lattice")]
+                let _ = self
+                    .bus
+                    .publish_lattice_event(LatticeEvent::ActorReplaceFailed {
+                        actor: pk.to_string(),
+                        host: self.id(),
+                        reason: ReasonCode::AuthorizerDenied,
+                    });
+                return Err(errors::new(errors::ErrorKind::Authorization(format!(
+                    "Replacement of actor {} was denied by the authorizer",
+                    pk
+                ))));
+            }
+        }
+        let url = WasccEntity::Actor(pk).url();
+        if let Err(busy) = self
+            .in_flight
+            .wait_idle(Duration::from_millis(REPLACE_IDLE_TIMEOUT_MS), |u| u == url)
+        {
+            warn!(
+                "Replacing actor {} with {} invocations still in flight",
+                new_actor.public_key(),
+                busy
+            );
+        }
+        crate::inthost::replace_actor(&self.signer, self.bus.clone(), new_actor)
     }
 
     /// Adds a middleware item to the middleware processing pipeline
     pub fn add_middleware(&self, mid: impl Middleware) {
-        self.middlewares.write().unwrap().push(Box::new(mid));
+        self.middlewares.write_or_recover().push(Box::new(mid));
+    }
+
+    /// Adds a middleware item to the middleware processing pipeline that only applies to
+    /// invocations within the given scope, passing all other invocations through untouched
+    pub fn add_scoped_middleware(&self, mid: impl Middleware, scope: MiddlewareScope) {
+        self.add_middleware(ScopedMiddleware::new(mid, scope));
     }
 
     /// Adds a native capability provider plugin to the host runtime. If running in lattice mode,
@@ -465,55 +1554,113 @@ impl Host {
     /// the binding configuration. Note that because these capabilities are native,
     /// cross-platform support is not always guaranteed.
     pub fn add_native_capability(&self, capability: NativeCapability) -> Result<()> {
+        self.add_native_capability_imgref(capability, None)
+    }
+
+    fn add_native_capability_imgref(
+        &self,
+        mut capability: NativeCapability,
+        imgref: Option<String>,
+    ) -> Result<()> {
+        let start = Instant::now();
+        if capability.implicit_binding {
+            capability.binding_name = self.default_binding.clone();
+        }
+        let prefix = format!("provider.{}.{}", capability.id(), capability.binding_name);
+        let load_timings = std::mem::take(&mut capability.load_timings);
         let capid = capability.id();
-        if self
-            .caps
-            .read()
-            .unwrap()
-            .contains_key(&RouteKey::new(&capability.binding_name, &capability.id()))
-        {
-            return Err(errors::new(errors::ErrorKind::CapabilityProvider(format!(
-                "Capability provider {} cannot be bound to the same name ({}) twice, loading failed.", capid, capability.binding_name
-            ))));
+        self.capability_allowlist.check(&capid)?;
+        let route_key = RouteKey::new(&capability.binding_name, &capability.id());
+        // Checked and inserted under one lock, so that of several threads adding the same
+        // provider concurrently only one spawns it
+        match self.caps.write_or_recover().entry(route_key.clone()) {
+            Entry::Occupied(_) => {
+                return Err(errors::new(errors::ErrorKind::CapabilityProvider(format!(
+                    "Capability provider {} cannot be bound to the same name ({}) twice, loading failed.", capid, capability.binding_name
+                ))));
+            }
+            Entry::Vacant(e) => {
+                e.insert(capability.descriptor().clone());
+            }
         }
-        self.caps.write().unwrap().insert(
-            RouteKey::new(&capability.binding_name, &capability.descriptor.id),
-            capability.descriptor().clone(),
-        );
         let wg = crossbeam_utils::sync::WaitGroup::new();
-        let key = KeyPair::from_seed(&self.sk).unwrap();
         spawns::spawn_native_capability(
             capability,
             self.bus.clone(),
             self.middlewares.clone(),
+            self.middleware_policy,
             self.bindings.clone(),
+            self.claims.clone(),
+            self.caps.clone(),
             self.terminators.clone(),
             self.plugins.clone(),
             wg.clone(),
-            Arc::new(key),
-        )?;
+            self.signer.clone(),
+            self.in_flight.clone(),
+            self.rebinders.clone(),
+        )
+        .map_err(|e| {
+            self.caps.write_or_recover().remove(&route_key);
+            e
+        })?;
         wg.wait();
+        self.provider_origins
+            .write_or_recover()
+            .insert(route_key, inthost::Origin::new(imgref));
+        for (phase, duration) in load_timings {
+            self.timings
+                .record(&format!("{}.{}", prefix, phase), duration);
+        }
+        self.timings.since(&format!("{}.add", prefix), start);
+        #[cfg(feature = "lattice")]
+        self.sync_bindings_after_add();
         Ok(())
     }
 
     /// Adds a native capability provider plugin to the host runtime by pulling the library from a provider archive
-    /// stored in an OCI-compliant registry. This file will be stored in the operating system's designated temporary
-    /// directory after being downloaded.
+    /// stored in an OCI-compliant registry. This file will be stored in the host's work directory (see
+    /// `HostBuilder::with_work_dir`) after being downloaded.
     pub fn add_native_capability_from_registry(
         &self,
         image_ref: &str,
         binding_name: Option<String>,
     ) -> Result<()> {
-        let b = binding_name.unwrap_or("default".to_string());
-        match crate::inthost::fetch_provider(image_ref, &b, self.labels.clone()) {
-            Ok((prov, claims)) => {
-                self.add_native_capability(prov)?;
+        self.native_capability_from_registry(image_ref, binding_name)
+            .map(|_| ())
+    }
+
+    // Adds a provider from a registry, returning the entity it can be invoked as
+    fn native_capability_from_registry(
+        &self,
+        image_ref: &str,
+        binding_name: Option<String>,
+    ) -> Result<WasccEntity> {
+        let b = binding_name.unwrap_or_else(|| self.default_binding.clone());
+        let start = Instant::now();
+        match crate::inthost::fetch_provider(
+            self.fetcher.as_ref(),
+            &self.work_dir,
+            image_ref,
+            &b,
+            self.labels.clone(),
+        ) {
+            Ok((mut prov, claims)) => {
+                // Loading the library is part of the fetch, so the time downloading it is the rest
+                let loaded: Duration = prov.load_timings.iter().map(|(_, d)| *d).sum();
+                prov.load_timings.push((
+                    "fetch",
+                    start.elapsed().checked_sub(loaded).unwrap_or_default(),
+                ));
+                let entity = WasccEntity::Capability {
+                    capid: prov.id(),
+                    binding: b,
+                };
+                self.add_native_capability_imgref(prov, Some(image_ref.to_string()))?;
                 // Only write to the image map if the above add function succeeds
                 self.image_map
-                    .write()
-                    .unwrap()
+                    .write_or_recover()
                     .insert(image_ref.to_string(), claims.subject.to_string());
-                Ok(())
+                Ok(entity)
             }
             Err(e) => Err(e),
         }
@@ -525,20 +1672,87 @@ impl Host {
         capability_id: &str,
         binding_name: Option<String>,
     ) -> Result<()> {
-        let b = binding_name.unwrap_or("default".to_string());
+        let b = binding_name.unwrap_or_else(|| self.default_binding.clone());
         let subject =
             bus::provider_subject(self.ns.as_ref().map(String::as_str), capability_id, &b);
-        if let Some(terminator) = self.terminators.read().unwrap().get(&subject) {
-            terminator.send(true).unwrap();
+        let terminator = self.terminators.read_or_recover().get(&subject).cloned();
+        if terminator.is_none()
+            && !self
+                .caps
+                .read_or_recover()
+                .contains_key(&RouteKey::new(&b, capability_id))
+        {
+            return Err(errors::new(errors::ErrorKind::MiscHost(
+                "No such capability".into(),
+            )));
+        }
+        if terminator.map(|t| t.send(true).is_ok()).unwrap_or(false) {
+            // Let the provider clean up after itself first, since it knows which of its
+            // bound actor subscriptions are still live
+            let _ = self.await_termination(
+                &subject,
+                Duration::from_millis(PROVIDER_TERMINATION_TIMEOUT_MS),
+            );
+        } else {
+            warn!(
+                "Capability provider {},{} is no longer running, cleaning up its resources",
+                b, capability_id
+            );
+        }
+        self.purge_capability(capability_id, &b);
+        Ok(())
+    }
+
+    /// Removes a portable capability provider that was added with `add_capability`. The
+    /// provider's thread unsubscribes, forgets the provider's bindings, and removes it from
+    /// the host's capabilities as it terminates; if it doesn't terminate in time, the host does
+    /// so itself. Native providers are removed with `remove_native_capability` instead
+    pub fn remove_capability(&self, capid: &str, binding: Option<String>) -> Result<()> {
+        let b = binding.unwrap_or_else(|| self.default_binding.clone());
+        if self.plugins.read_or_recover().has_plugin(&b, capid) {
+            return Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                "Capability provider {},{} is a native provider, remove it with remove_native_capability",
+                b, capid
+            ))));
+        }
+        let subject = bus::provider_subject(self.ns.as_ref().map(String::as_str), capid, &b);
+        let terminator = self.terminators.read_or_recover().get(&subject).cloned();
+        if terminator.map(|t| t.send(true).is_ok()).unwrap_or(false) {
+            // If the provider doesn't finish in time, it's cleaned up after as the native path
+            // does, so that it can be added again straight away
+            if !self.await_termination(
+                &subject,
+                Duration::from_millis(PROVIDER_TERMINATION_TIMEOUT_MS),
+            ) {
+                warn!(
+                    "Capability provider {},{} did not terminate in time, cleaning up its resources",
+                    b, capid
+                );
+                self.purge_capability(capid, &b);
+            }
+            Ok(())
+        } else if self
+            .caps
+            .read_or_recover()
+            .contains_key(&RouteKey::new(&b, capid))
+        {
+            warn!(
+                "Capability provider {},{} is no longer running, cleaning up its resources",
+                b, capid
+            );
+            inthost::unbind_all_from_cap(self.bindings.clone(), capid, &b);
+            inthost::remove_cap(self.caps.clone(), capid, &b);
             Ok(())
         } else {
-            Err(errors::new(errors::ErrorKind::MiscHost(
-                "No such capability".into(),
-            )))
+            Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                "No such capability provider {},{}",
+                b, capid
+            ))))
         }
     }
 
-    /// Removes a binding between an actor and the indicated capability provider. In lattice mode,
+    /// Removes a binding between an actor and the indicated capability provider, along with any
+    /// named bindings the actor has to it (see `set_binding_named`). In lattice mode,
     /// this operation has a _lattice global_ scope, and so all running instances of the indicated
     /// capability provider will be asked to dispose of any resources provisioned for the given
     /// actor.
@@ -548,31 +1762,93 @@ impl Host {
         capid: &str,
         binding_name: Option<String>,
     ) -> Result<()> {
-        let cfg = CapabilityConfiguration {
-            module: actor.to_string(),
-            values: HashMap::new(),
-        };
-        let buf = serialize(&cfg).unwrap();
-        let binding = binding_name.unwrap_or("default".to_string());
-        let key = KeyPair::from_seed(&self.sk).unwrap();
+        let binding = binding_name.unwrap_or_else(|| self.default_binding.clone());
+        let named: Vec<_> = self
+            .bindings
+            .read_or_recover()
+            .config_names(actor, capid, &binding)
+            .into_iter()
+            .flatten()
+            .collect();
+        for name in named {
+            self.remove_binding_named(actor, capid, Some(binding.clone()), &name)?;
+        }
+        let buf = crate::inthost::removal_payload(actor, None);
         let inv_r = self.bus.invoke(
-            &self.bus.provider_subject(&capid, &binding), // The OP_REMOVE_ACTOR invocation should go to _all_ instances of the provider being unbound
-            crate::inthost::gen_remove_actor(&key, buf.clone(), &binding, &capid),
+            &self.bus.provider_config_subject(&capid, &binding), // The OP_REMOVE_ACTOR invocation should go to _all_ instances of the provider being unbound
+            crate::inthost::gen_remove_actor(&self.signer, buf.clone(), &binding, &capid),
         )?;
         if let Some(s) = inv_r.error {
             Err(format!("Failed to remove binding: {}", s).into())
         } else {
+            if capid == extras::CAPABILITY_ID {
+                // Forgotten here rather than once the provider's thread for the actor has
+                // stopped, so it's known whether the provider is still in use
+                self.bindings
+                    .write_or_recover()
+                    .remove_all_named(actor, capid, &binding);
+                self.release_extras_binding(&binding);
+            }
+            #[cfg(feature = "lattice")]
+            let _ = self
+                .bus
+                .publish_lattice_event(LatticeEvent::ActorBindingRemoved {
+                    actor: actor.to_string(),
+                    capid: capid.to_string(),
+                    instance_name: binding,
+                    host: self.id(),
+                    reason: ReasonCode::Requested,
+                });
+            Ok(())
+        }
+    }
+
+    /// Removes one of an actor's named bindings to the indicated capability provider, set with
+    /// `set_binding_named`. The provider is asked to dispose of the resources provisioned for
+    /// that binding only, and the actor's other bindings to it are kept
+    pub fn remove_binding_named(
+        &self,
+        actor: &str,
+        capid: &str,
+        binding_name: Option<String>,
+        config_name: &str,
+    ) -> Result<()> {
+        let binding = binding_name.unwrap_or_else(|| self.default_binding.clone());
+        let inv_r = self.bus.invoke(
+            &self.bus.provider_config_subject(&capid, &binding),
+            crate::inthost::gen_remove_actor(
+                &self.signer,
+                crate::inthost::removal_payload(actor, Some(config_name)),
+                &binding,
+                &capid,
+            ),
+        )?;
+        if let Some(s) = inv_r.error {
+            Err(format!("Failed to remove binding: {}", s).into())
+        } else {
+            self.bindings.write_or_recover().remove_named(
+                actor,
+                capid,
+                &binding,
+                Some(config_name),
+            );
+            if capid == extras::CAPABILITY_ID {
+                self.release_extras_binding(&binding);
+            }
             Ok(())
         }
     }
 
     /// Binds an actor to a capability provider with a given configuration. If the binding name
-    /// is `None` then the default binding name will be used (`default`). An actor can only have one named
-    /// binding per capability provider. In lattice mode, the call to this function has a _lattice global_
+    /// is `None` then the default binding name will be used (`default`, unless set
+    /// with `HostBuilder::with_default_binding_name`). An actor can only have one binding
+    /// set this way per capability provider (see `set_binding_named` for more). In lattice mode, the call to this function has a _lattice global_
     /// scope, and so all running instances of the indicated provider will be notified and provision
     /// resources accordingly. For example, if you create a binding between an actor and an HTTP server
     /// provider, and there are four instances of that provider running in the lattice, each of those
-    /// four hosts will start an HTTP server on the indicated port.
+    /// four hosts will start an HTTP server on the indicated port. If some instances fail to apply the
+    /// binding or don't respond, the binding is still recorded (and used by the instances that accepted it),
+    /// but an error describing the failed instances is returned.
     pub fn set_binding(
         &self,
         actor: &str,
@@ -580,92 +1856,462 @@ impl Host {
         binding_name: Option<String>,
         config: HashMap<String, String>,
     ) -> Result<()> {
+        let ns = self.ns.clone();
+        self.bind_in_namespace(
+            ns.as_ref().map(String::as_str),
+            actor,
+            capid,
+            binding_name,
+            config,
+        )
+    }
+
+    /// Binds each of a group of actors to the same capability provider with the same
+    /// configuration, returning the outcome for each actor. Every actor is validated (it must
+    /// exist and be attested and authorized for the capability) before any binding is delivered,
+    /// and only the actors that pass are bound. As with `set_binding`, an actor's binding is only
+    /// recorded once the provider has accepted it, so a failure for one actor never leaves a
+    /// binding recorded that the provider rejected, nor undoes the bindings of the others
+    pub fn set_group_binding(
+        &self,
+        actor_pks: &[&str],
+        capid: &str,
+        binding_name: Option<String>,
+        config: HashMap<String, String>,
+    ) -> HashMap<String, Result<()>> {
+        let ns = self.ns.clone();
+        let ns = ns.as_ref().map(String::as_str);
+        let binding = binding_name.unwrap_or_else(|| self.default_binding.clone());
+        let mut results = HashMap::new();
+        let mut valid = Vec::new();
+        for actor in actor_pks {
+            if results.contains_key(*actor) || valid.iter().any(|(a, _)| a == actor) {
+                continue;
+            }
+            match self.validate_binding(ns, actor, capid, &binding, &config) {
+                Ok(c) => valid.push((*actor, c)),
+                Err(e) => {
+                    results.insert(actor.to_string(), Err(e));
+                }
+            }
+        }
+        for (actor, c) in valid {
+            let res = self.apply_binding(
+                ns,
+                actor,
+                capid,
+                binding.clone(),
+                None,
+                c,
+                config.clone(),
+                false,
+            );
+            results.insert(actor.to_string(), res);
+        }
+        results
+    }
+
+    /// Binds an actor to a capability provider in a lattice namespace other than the one this
+    /// host belongs to, allowing a single process to administer several namespaces. Authorization
+    /// is performed exactly as it is for `set_binding`, using the actor's claims as discovered in
+    /// the target namespace. Note that the binding is not recorded by this host, since its
+    /// binding table belongs to its own namespace
+    #[cfg(feature = "lattice")]
+    pub fn set_binding_in_namespace(
+        &self,
+        ns: &str,
+        actor: &str,
+        capid: &str,
+        binding_name: Option<String>,
+        config: HashMap<String, String>,
+    ) -> Result<()> {
+        self.bind_in_namespace(Some(ns), actor, capid, binding_name, config)
+    }
+
+    fn bind_in_namespace(
+        &self,
+        ns: Option<&str>,
+        actor: &str,
+        capid: &str,
+        binding_name: Option<String>,
+        config: HashMap<String, String>,
+    ) -> Result<()> {
+        let binding = binding_name.unwrap_or_else(|| self.default_binding.clone());
+        let c = self.validate_binding(ns, actor, capid, &binding, &config)?;
+        self.apply_binding(ns, actor, capid, binding, None, c, config, false)
+    }
+
+    /// Binds an actor to a capability provider under a configuration name, which lets an actor
+    /// hold several independent bindings to the same provider instance, e.g. two subscriptions
+    /// with different topics against one messaging provider. Each named binding is recorded and
+    /// delivered separately, with its name in the `CONFIG_WASCC_CONFIG_NAME` configuration
+    /// value so the provider can provision separate resources for it. Named bindings sit
+    /// alongside the one set by `set_binding`, which has no name. The provider must support
+    /// named bindings; one that doesn't will treat each as a re-bind of the actor
+    pub fn set_binding_named(
+        &self,
+        actor: &str,
+        capid: &str,
+        binding_name: Option<String>,
+        config_name: &str,
+        config: HashMap<String, String>,
+    ) -> Result<()> {
+        if config_name.is_empty() {
+            return Err(errors::new(errors::ErrorKind::MiscHost(
+                "Binding configuration names can't be empty".to_string(),
+            )));
+        }
+        let ns = self.ns.clone();
+        let ns = ns.as_ref().map(String::as_str);
+        let binding = binding_name.unwrap_or_else(|| self.default_binding.clone());
+        let c = self.validate_binding(ns, actor, capid, &binding, &config)?;
+        self.apply_binding(
+            ns,
+            actor,
+            capid,
+            binding,
+            Some(config_name),
+            c,
+            config,
+            false,
+        )
+    }
+
+    /// Changes some of the configuration values of an existing binding without tearing it down,
+    /// e.g. to rotate a credential. The supplied values are merged into the binding's current
+    /// configuration (replacing values with the same keys) and the result is delivered to the
+    /// provider as a binding configuration carrying the `CONFIG_WASCC_UPDATE` value, so the
+    /// provider can keep the resources it holds for the actor. The binding's recorded
+    /// configuration is only changed once the provider has accepted the update, and a
+    /// `HostEvent::BindingUpdated` event is published when it is. Concurrent updates of the
+    /// same binding are applied one after the other, each to the configuration the one before
+    /// it left, so no update's values are lost
+    pub fn update_binding_values(
+        &self,
+        actor: &str,
+        capid: &str,
+        binding_name: Option<String>,
+        delta: HashMap<String, String>,
+    ) -> Result<()> {
+        let ns = self.ns.clone();
+        let ns = ns.as_ref().map(String::as_str);
+        let binding = binding_name.unwrap_or_else(|| self.default_binding.clone());
+        let c = self.validate_binding(ns, actor, capid, &binding, &delta)?;
+        self.apply_binding(ns, actor, capid, binding, None, c, delta, true)
+    }
+
+    /// Delivers configuration values straight to an actor, as an `OP_BIND_ACTOR` invocation
+    /// from the system actor carrying a `CapabilityConfiguration` for the actor itself, e.g. for
+    /// admin tooling to change an actor's own settings. Since no capability is involved, the
+    /// actor needs no capability attestation, but the authorizer must allow the invocation. In
+    /// lattice mode, the configuration is delivered to one running instance of the actor
+    pub fn configure_actor(&self, actor: &str, values: HashMap<String, String>) -> Result<()> {
         #[cfg(feature = "lattice")]
         let claims = self.bus.discover_claims(actor);
         #[cfg(not(feature = "lattice"))]
-        let claims = self.claims.read().unwrap().get(actor).cloned();
+        let claims = self.claims.read_or_recover().get(actor).cloned();
+        let claims = claims.ok_or_else(|| {
+            errors::new(errors::ErrorKind::MiscHost(format!(
+                "Attempted to configure non-existent actor {}",
+                actor
+            )))
+        })?;
+        #[cfg(feature = "lattice")]
+        self.check_discovered_claims(&claims)?;
+        if let Some(k) = values
+            .keys()
+            .find(|k| k.starts_with(CONFIG_WASCC_RESERVED_PREFIX))
+        {
+            return Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                "Configuration key {} uses the prefix {}, which is reserved for the host",
+                k, CONFIG_WASCC_RESERVED_PREFIX
+            ))));
+        }
+        let target = WasccEntity::Actor(actor.to_string());
+        let ctx = self.authorization_context();
+        if !self
+            .authorizer
+            .read_or_recover()
+            .can_invoke_ctx(&claims, &target, OP_BIND_ACTOR, &ctx)
+        {
+            self.bus
+                .stats()
+                .denied(actor, &target, REASON_AUTHORIZER_DENIED);
+            self.bus.publish_host_event(HostEvent::AuthorizationDenied {
+                actor: actor.to_string(),
+                capid: actor.to_string(),
+                binding: "".to_string(),
+                operation: OP_BIND_ACTOR.to_string(),
+                reason: "Authorizer denied access".to_string(),
+            });
+            return Err(errors::new(errors::ErrorKind::Authorization(format!(
+                "Unauthorized configuration of actor {}",
+                actor
+            ))));
+        }
+        let inv = inthost::gen_actor_config_invocation(
+            &self.signer,
+            actor,
+            claims,
+            &self.default_binding,
+            values,
+            &self.forwarded_labels(),
+        );
+        let subject = bus::actor_subject(self.ns.as_ref().map(String::as_str), actor);
+        let inv_r = self.bus.invoke(&subject, inv)?;
+        match inv_r.error {
+            Some(e) => Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                "Failed to configure actor {}: {}",
+                actor, e
+            )))),
+            None => Ok(()),
+        }
+    }
 
-        let key = KeyPair::from_seed(&self.sk).unwrap();
+    // Checks that the actor exists, that the configuration doesn't use reserved keys, and that
+    // the actor is attested and authorized for the capability, returning the actor's claims
+    fn validate_binding(
+        &self,
+        ns: Option<&str>,
+        actor: &str,
+        capid: &str,
+        binding: &str,
+        config: &HashMap<String, String>,
+    ) -> Result<Claims<wascap::jwt::Actor>> {
+        #[cfg(feature = "lattice")]
+        let claims = match self.bus.discover_claims_in(ns, actor) {
+            Some(c) => {
+                self.check_discovered_claims(&c)?;
+                Some(c)
+            }
+            None => None,
+        };
+        #[cfg(not(feature = "lattice"))]
+        let claims = {
+            let _ = ns;
+            self.claims.read_or_recover().get(actor).cloned()
+        };
+        // The actor may not be running yet if its claims were registered ahead of it
+        let claims = claims.or_else(|| self.registered_claims(actor));
 
         if claims.is_none() {
             return Err(errors::new(errors::ErrorKind::MiscHost(
                 "Attempted to bind non-existent actor".to_string(),
             )));
         }
+        if let Some(k) = config
+            .keys()
+            .find(|k| k.starts_with(CONFIG_WASCC_RESERVED_PREFIX))
+        {
+            return Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                "Configuration key {} uses the prefix {}, which is reserved for the host",
+                k, CONFIG_WASCC_RESERVED_PREFIX
+            ))));
+        }
         let c = claims.unwrap().clone();
-        let binding = binding_name.unwrap_or("default".to_string());
+        let target = WasccEntity::Capability {
+            capid: capid.to_string(),
+            binding: binding.to_string(),
+        };
         if !authz::can_invoke(&c, capid, OP_BIND_ACTOR) {
+            let reason = authz::attestation_denial(&c, capid, binding, OP_BIND_ACTOR);
+            self.bus
+                .stats()
+                .denied(actor, &target, REASON_MISSING_ATTESTATION);
+            self.bus.publish_host_event(HostEvent::AuthorizationDenied {
+                actor: actor.to_string(),
+                capid: capid.to_string(),
+                binding: binding.to_string(),
+                operation: OP_BIND_ACTOR.to_string(),
+                reason: reason.to_string(),
+            });
             return Err(errors::new(errors::ErrorKind::Authorization(format!(
-                "Unauthorized binding: actor {} is not authorized to use capability {}.",
-                actor, capid
+                "Unauthorized binding: {}.",
+                reason
             ))));
         } else {
-            if !self.authorizer.read().unwrap().can_invoke(
-                &c,
-                &WasccEntity::Capability {
+            // Gathered before the authorizer is locked, as it reads the host's labels
+            let ctx = self.authorization_context();
+            if !self
+                .authorizer
+                .read_or_recover()
+                .can_invoke_ctx(&c, &target, OP_BIND_ACTOR, &ctx)
+            {
+                self.bus
+                    .stats()
+                    .denied(actor, &target, REASON_AUTHORIZER_DENIED);
+                self.bus.publish_host_event(HostEvent::AuthorizationDenied {
+                    actor: actor.to_string(),
                     capid: capid.to_string(),
                     binding: binding.to_string(),
-                },
-                OP_BIND_ACTOR,
-            ) {
+                    operation: OP_BIND_ACTOR.to_string(),
+                    reason: "Authorizer denied access".to_string(),
+                });
                 return Err(errors::new(errors::ErrorKind::Authorization(format!(
                     "Unauthorized binding: actor {} is not authorized to use capability {}.",
                     actor, capid
                 ))));
             }
         }
+        Ok(c)
+    }
+
+    // Delivers the binding to every instance of the provider, recording it in this host's
+    // binding table only if at least one instance accepted it. For updates, the configuration
+    // holds the changed values, which are merged into the recorded binding under the same lock
+    // the result is delivered and recorded under. Updates are marked as such in the delivered
+    // configuration (but not in the recorded one)
+    #[allow(clippy::too_many_arguments)]
+    fn apply_binding(
+        &self,
+        ns: Option<&str>,
+        actor: &str,
+        capid: &str,
+        binding: String,
+        config_name: Option<&str>,
+        c: Claims<wascap::jwt::Actor>,
+        config: HashMap<String, String>,
+        update: bool,
+    ) -> Result<()> {
+        // Only bindings within this host's own namespace are recorded in its binding table
+        let local = ns == self.ns.as_ref().map(String::as_str);
+        // A binding to an extras provider also holds the provider, which is released once
+        // nothing is bound to it, until the binding is recorded
+        let _provider = if capid == extras::CAPABILITY_ID {
+            Some(
+                self.binding_changes
+                    .lock(inthost::extras_lock_key(&binding)),
+            )
+        } else {
+            None
+        };
+        let _changes = self
+            .binding_changes
+            .lock(format!("{}/{}/{}", actor, capid, binding));
+        let config = if update {
+            match self.bindings.read_or_recover().get(actor, capid, &binding) {
+                Some(cfg) => {
+                    let mut values = cfg.values.clone();
+                    values.extend(config);
+                    values
+                }
+                None => {
+                    return Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                        "Actor {} has no binding to {},{} to update",
+                        actor, binding, capid
+                    ))))
+                }
+            }
+        } else {
+            config
+        };
+        if capid == extras::CAPABILITY_ID {
+            self.ensure_extras_binding(&binding)?;
+        }
 
         info!(
             "Attempting to bind actor {} to {},{}",
             actor, &binding, capid
         );
 
-        let tgt_subject = if (actor == capid || actor == SYSTEM_ACTOR) && capid.starts_with("M") {
+        let configures_actor = (actor == capid || actor == SYSTEM_ACTOR) && capid.starts_with("M");
+        let tgt_subject = if configures_actor {
             // manually injected actor configuration
-            bus::actor_subject(self.ns.as_ref().map(String::as_str), actor)
+            warn!(
+                "Configuring actor {} with set_binding is deprecated, use Host::configure_actor instead",
+                capid
+            );
+            bus::actor_subject(ns, actor)
         } else {
-            bus::provider_subject(self.ns.as_ref().map(String::as_str), capid, &binding)
+            bus::provider_config_subject(ns, capid, &binding)
         };
         trace!("Binding subject: {}", tgt_subject);
+        let mut values = config.clone();
+        if update {
+            values.insert(CONFIG_WASCC_UPDATE.to_string(), "true".to_string());
+        }
+        if let Some(name) = config_name {
+            values.insert(CONFIG_WASCC_CONFIG_NAME.to_string(), name.to_string());
+        }
         let inv = inthost::gen_config_invocation(
-            &key,
+            &self.signer,
             actor,
             capid,
             c.clone(),
             binding.clone(),
-            config.clone(),
+            values,
+            &self.forwarded_labels(),
         );
-        match self.bus.invoke(&tgt_subject, inv) {
-            Ok(inv_r) => {
-                if let Some(e) = inv_r.error {
+        // Every instance of the provider in the lattice that answers should accept the binding
+        let delivered = if configures_actor {
+            self.bus.invoke(&tgt_subject, inv).map(|r| vec![r])
+        } else {
+            self.bus.invoke_all(&tgt_subject, inv)
+        };
+        match delivered {
+            Ok(responses) => {
+                let failures: Vec<_> = responses.iter().filter_map(|r| r.error.clone()).collect();
+                if failures.len() == responses.len() {
+                    // The bus reports an error if no instances responded at all
                     Err(errors::new(errors::ErrorKind::CapabilityProvider(format!(
                         "Failed to configure {},{} - {}",
-                        binding, capid, e
+                        binding,
+                        capid,
+                        failures.join("; ")
                     ))))
                 } else {
-                    self.record_binding(
-                        actor,
-                        capid,
-                        &binding,
-                        &CapabilityConfiguration {
-                            module: actor.to_string(),
-                            values: config,
-                        },
-                    )?;
-                    #[cfg(feature = "lattice")]
-                    let _ = self.bus.publish_event(BusEvent::ActorBindingCreated {
-                        actor: actor.to_string(),
-                        capid: capid.to_string(),
-                        instance_name: binding.to_string(),
-                        host: self.id(),
-                    });
-                    Ok(())
+                    if local {
+                        self.record_binding(
+                            actor,
+                            capid,
+                            &binding,
+                            config_name,
+                            &CapabilityConfiguration {
+                                module: actor.to_string(),
+                                values: config,
+                            },
+                        )?;
+                        #[cfg(feature = "lattice")]
+                        let _ = self.bus.publish_event(BusEvent::ActorBindingCreated {
+                            actor: actor.to_string(),
+                            capid: capid.to_string(),
+                            instance_name: binding.to_string(),
+                            host: self.id(),
+                        });
+                        if update {
+                            self.bus.publish_host_event(HostEvent::BindingUpdated {
+                                actor: actor.to_string(),
+                                capid: capid.to_string(),
+                                binding: binding.to_string(),
+                            });
+                        }
+                    }
+                    if failures.is_empty() {
+                        Ok(())
+                    } else {
+                        Err(errors::new(errors::ErrorKind::CapabilityProvider(format!(
+                            "Binding {},{} applied by {} of {} provider instances ({} failed: {})",
+                            binding,
+                            capid,
+                            responses.len() - failures.len(),
+                            responses.len(),
+                            failures.len(),
+                            failures.join("; ")
+                        ))))
+                    }
                 }
             }
-            Err(e) => Err(errors::new(errors::ErrorKind::CapabilityProvider(format!(
-                "Failed to configure {},{} - {}",
-                binding, capid, e
-            )))),
+            // Bus failures are returned as-is so callers can tell whether to retry
+            Err(e) => match e.kind() {
+                errors::ErrorKind::Bus(_) => {
+                    error!("Failed to configure {},{} - {}", binding, capid, e);
+                    Err(e)
+                }
+                _ => Err(errors::new(errors::ErrorKind::CapabilityProvider(format!(
+                    "Failed to configure {},{} - {}",
+                    binding, capid, e
+                )))),
+            },
         }
     }
 
@@ -673,23 +2319,222 @@ impl Host {
     /// knowing ahead of time if the given actor supports the specified operation. In lattice
     /// mode, this call will still only attempt a _local_ invocation on the host and will not
     /// make a lattice-wide call. If you want to make lattice-wide invocations, please use
-    /// the lattice client library.
+    /// `call_actor_anywhere`.
     pub fn call_actor(&self, actor: &str, operation: &str, msg: &[u8]) -> Result<Vec<u8>> {
-        let key = KeyPair::from_seed(&self.sk).unwrap();
-        if !self.claims.read().unwrap().contains_key(actor) {
+        if !self.claims.read_or_recover().contains_key(actor) {
             return Err(errors::new(errors::ErrorKind::MiscHost(
                 "No such actor".into(),
             )));
         }
-        let inv = Invocation::new(
-            &key,
+        self.invoke_actor_in(
+            self.ns.as_ref().map(String::as_str),
+            actor,
+            operation,
+            msg,
+            None,
+            None,
+        )
+    }
+
+    /// Invokes an operation on an actor (as with `call_actor`), marking the payload with its
+    /// media type, e.g. `application/json`. Middleware sees it as the invocation's
+    /// `content_type`; the actor itself is only given the payload
+    pub fn call_actor_typed(
+        &self,
+        actor: &str,
+        operation: &str,
+        msg: &[u8],
+        content_type: &str,
+    ) -> Result<Vec<u8>> {
+        if !self.claims.read_or_recover().contains_key(actor) {
+            return Err(errors::new(errors::ErrorKind::MiscHost(
+                "No such actor".into(),
+            )));
+        }
+        self.invoke_actor_in(
+            self.ns.as_ref().map(String::as_str),
+            actor,
+            operation,
+            msg,
+            Some(content_type),
+            None,
+        )
+    }
+
+    /// Invokes an operation on every actor in the host selected by the filter, with at most
+    /// `concurrency` invocations in flight at once. Each actor is invoked as with `call_actor`
+    /// and its result, including errors and timeouts, is collected independently of the
+    /// others, so one failing actor doesn't stop the broadcast. Results are returned in order of
+    /// the actors' public keys. As with `call_actor`, only actors running in this host are
+    /// invoked, even in lattice mode
+    pub fn broadcast_to_actors(
+        &self,
+        filter: ActorFilter,
+        operation: &str,
+        msg: &[u8],
+        concurrency: usize,
+    ) -> Vec<(String, Result<Vec<u8>>)> {
+        let actors =
+            inthost::actors_matching_filter(self.claims.read_or_recover().values(), &filter);
+        let (job_s, job_r) = crossbeam_channel::unbounded();
+        for (i, pk) in actors.iter().enumerate() {
+            job_s.send((i, pk)).unwrap();
+        }
+        drop(job_s);
+
+        let (res_s, res_r) = crossbeam_channel::unbounded();
+        crossbeam_utils::thread::scope(|s| {
+            for _ in 0..concurrency.max(1).min(actors.len()) {
+                let (job_r, res_s) = (job_r.clone(), res_s.clone());
+                s.spawn(move |_| {
+                    for (i, pk) in job_r.iter() {
+                        let _ = res_s.send((i, self.call_actor(pk, operation, msg)));
+                    }
+                });
+            }
+        })
+        .unwrap();
+        drop(res_s);
+
+        let mut results: Vec<_> = res_r.iter().collect();
+        results.sort_by_key(|(i, _)| *i);
+        results
+            .into_iter()
+            .map(|(i, res)| (actors[i].to_string(), res))
+            .collect()
+    }
+
+    /// Invokes an operation on an actor (as with `call_actor`), failing with a bus timeout error
+    /// if the actor hasn't answered within the given time. This takes precedence over the
+    /// host's configured timeouts, including in lattice mode
+    pub fn call_actor_with_timeout(
+        &self,
+        actor: &str,
+        operation: &str,
+        msg: &[u8],
+        timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        if !self.claims.read_or_recover().contains_key(actor) {
+            return Err(errors::new(errors::ErrorKind::MiscHost(
+                "No such actor".into(),
+            )));
+        }
+        self.invoke_actor_in(
+            self.ns.as_ref().map(String::as_str),
+            actor,
+            operation,
+            msg,
+            None,
+            Some(timeout),
+        )
+    }
+
+    /// Invokes an operation on an actor (as with `call_actor`), serializing the payload and
+    /// deserializing the reply with the waSCC codec. This is the same MessagePack encoding actors
+    /// and capability providers use for one another, so this should be used with any operation
+    /// that accepts one of the codec's types, e.g. `wascc_codec::http::Request`
+    #[cfg(feature = "serde")]
+    pub fn call_actor_serialized<T, R>(&self, actor: &str, operation: &str, msg: &T) -> Result<R>
+    where
+        T: serde::Serialize,
+        R: serde::de::DeserializeOwned,
+    {
+        let payload = serialize(msg)?;
+        let resp = self.call_actor_typed(actor, operation, &payload, "application/msgpack")?;
+        wascc_codec::deserialize(&resp).map_err(|e| {
+            errors::new(errors::ErrorKind::Serialization(format!(
+                "Failed to deserialize {}-byte reply to {}: {}",
+                resp.len(),
+                operation,
+                e
+            )))
+        })
+    }
+
+    /// Invokes an operation on an actor (as with `call_actor`) with a JSON payload, parsing the
+    /// actor's reply as JSON. This is only useful for actors whose operations use JSON payloads
+    /// directly; operations taking waSCC codec types should be called with `call_actor_serialized`
+    #[cfg(feature = "serde_json")]
+    pub fn call_actor_json(
+        &self,
+        actor: &str,
+        operation: &str,
+        msg: &serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        let payload = serde_json::to_vec(msg)
+            .map_err(|e| errors::new(errors::ErrorKind::Serialization(e.to_string())))?;
+        let resp = self.call_actor_typed(actor, operation, &payload, "application/json")?;
+        serde_json::from_slice(&resp).map_err(|e| {
+            errors::new(errors::ErrorKind::Serialization(format!(
+                "Failed to parse {}-byte reply to {} as JSON: {}",
+                resp.len(),
+                operation,
+                e
+            )))
+        })
+    }
+
+    /// Invoke an operation handler on an actor running in a lattice namespace other than the
+    /// one this host belongs to. Unlike `call_actor`, the actor does not need to be running in
+    /// this host; the invocation is delivered to any instance of the actor in the target namespace
+    #[cfg(feature = "lattice")]
+    pub fn call_actor_in_namespace(
+        &self,
+        ns: &str,
+        actor: &str,
+        operation: &str,
+        msg: &[u8],
+    ) -> Result<Vec<u8>> {
+        self.invoke_actor_in(Some(ns), actor, operation, msg, None, None)
+    }
+
+    /// Invoke an operation handler on an actor running anywhere in this host's lattice
+    /// namespace. Unlike `call_actor`, the actor doesn't need to be known to this host; the
+    /// invocation is signed as usual and delivered to whichever host has the actor subscribed,
+    /// within the configured RPC timeout. If no host in the lattice is running the actor, the
+    /// error is a `BusError::NoResponders`
+    #[cfg(feature = "lattice")]
+    pub fn call_actor_anywhere(&self, actor: &str, operation: &str, msg: &[u8]) -> Result<Vec<u8>> {
+        let ns = self.ns.as_ref().map(String::as_str);
+        match self.invoke_actor_in(ns, actor, operation, msg, None, None) {
+            // This version of NATS can't report that nothing is subscribed to a request, so a
+            // timeout is checked against the lattice's inventory
+            Err(e) => match e.kind() {
+                errors::ErrorKind::Bus(errors::BusError::Timeout { subject })
+                    if self.bus.instance_count(actor).ok() == Some(0) =>
+                {
+                    Err(errors::bus(errors::BusError::NoResponders {
+                        subject: subject.to_string(),
+                    }))
+                }
+                _ => Err(e),
+            },
+            res => res,
+        }
+    }
+
+    fn invoke_actor_in(
+        &self,
+        ns: Option<&str>,
+        actor: &str,
+        operation: &str,
+        msg: &[u8],
+        content_type: Option<&str>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>> {
+        let mut inv = self.signer.invocation(
             WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
             WasccEntity::Actor(actor.to_string()),
             operation,
             msg.to_vec(),
         );
-        let tgt_subject = bus::actor_subject(self.ns.as_ref().map(String::as_str), actor);
-        match self.bus.invoke(&tgt_subject, inv) {
+        inv.content_type = content_type.map(str::to_string);
+        let tgt_subject = bus::actor_subject(ns, actor);
+        let res = match timeout {
+            Some(t) => self.bus.invoke_with_timeout(&tgt_subject, inv, t),
+            None => self.bus.invoke(&tgt_subject, inv),
+        };
+        match res {
             Ok(resp) => match resp.error {
                 Some(e) => Err(format!("Invocation failure: {}", e).into()),
                 None => Ok(resp.msg),
@@ -698,68 +2543,567 @@ impl Host {
         }
     }
 
+    /// Returns operational data about an actor (when it started, how many invocations it has
+    /// handled, and its most recent error), if that actor is running in the host. The data is
+    /// reset when the actor is removed. In lattice mode the same data is included in the
+    /// extended actor inventory of each host
+    pub fn actor_runtime_info(&self, pk: &str) -> Option<ActorRuntimeInfo> {
+        self.actor_runtime.info(pk)
+    }
+
     /// Returns the full set of JWT claims for a given actor, if that actor is running in the host. This
     /// call will not query other hosts in the lattice if lattice mode is enabled.
     pub fn claims_for_actor(&self, pk: &str) -> Option<Claims<wascap::jwt::Actor>> {
-        let c = self.claims.read().unwrap().get(pk).cloned();
+        let c = self.claims.read_or_recover().get(pk).cloned();
 
         c
     }
 
+    /// Delivers the lattice's bindings to the given native capability provider in this host
+    /// again, as happens when the provider is added, returning the number of bindings delivered.
+    /// Bindings the provider has already accepted with the same configuration are skipped, so
+    /// that it isn't asked to provision their resources twice, unless `force` is set. If the
+    /// binding name is `None`, the default binding name is used
+    #[cfg(feature = "lattice")]
+    pub fn reestablish_bindings(
+        &self,
+        capid: &str,
+        binding_name: Option<String>,
+        force: bool,
+    ) -> Result<usize> {
+        let b = binding_name.unwrap_or_else(|| self.default_binding.clone());
+        let rebind = self
+            .rebinders
+            .read_or_recover()
+            .get(&RouteKey::new(&b, capid))
+            .cloned();
+        match rebind {
+            Some(rebind) => Ok(rebind(force)),
+            None => Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                "No such native capability provider {},{}",
+                b, capid
+            )))),
+        }
+    }
+
+    /// Merges the bindings known to the other hosts in the lattice into this host's own view of
+    /// its bindings, returning the number of bindings added or changed. Only bindings involving
+    /// an actor or a capability provider running in this host are merged. The providers aren't
+    /// asked to bind the actors again, since every instance of a provider is bound when the
+    /// binding is set. Where the other hosts agree on a binding's configuration and it differs
+    /// from this host's, the lattice's is used and a warning is logged. Where they disagree,
+    /// this host's view of the binding is left as it is and a warning is logged, so the outcome
+    /// doesn't depend on the order in which the hosts answer
+    #[cfg(feature = "lattice")]
+    pub fn sync_bindings_from_lattice(&self) -> Result<usize> {
+        let remote = self.bus.query_remote_bindings()?;
+        let actors: Vec<_> = self.claims.read_or_recover().keys().cloned().collect();
+        let providers: Vec<_> = self.caps.read_or_recover().keys().cloned().collect();
+        // The distinct configurations reported for each binding
+        let mut reported = std::collections::BTreeMap::<_, Vec<HashMap<String, String>>>::new();
+        for mut b in remote {
+            if !actors.contains(&b.actor)
+                && !providers.contains(&RouteKey::new(&b.binding_name, &b.capability_id))
+            {
+                continue;
+            }
+            let config_name = b.configuration.remove(CONFIG_WASCC_CONFIG_NAME);
+            let configs = reported
+                .entry((b.actor, b.capability_id, b.binding_name, config_name))
+                .or_default();
+            if !configs.contains(&b.configuration) {
+                configs.push(b.configuration);
+            }
+        }
+        let mut bindings = self.bindings.write_or_recover();
+        let mut merged = 0;
+        for ((actor, capid, binding, config_name), mut configs) in reported {
+            if configs.len() > 1 {
+                warn!(
+                    "Hosts in the lattice disagree on the configuration of the binding between {} and {},{}, leaving it unchanged",
+                    actor, binding, capid
+                );
+                continue;
+            }
+            let values = configs.remove(0);
+            let config_name = config_name.as_deref();
+            match bindings.get_named(&actor, &capid, &binding, config_name) {
+                Some(existing) if existing.values == values => continue,
+                Some(_) => warn!(
+                    "Configuration of the binding between {} and {},{} differs from the lattice's, using the lattice's",
+                    actor, binding, capid
+                ),
+                None => {}
+            }
+            bindings.insert_named(
+                &actor,
+                &capid,
+                &binding,
+                config_name,
+                CapabilityConfiguration {
+                    module: actor.to_string(),
+                    values,
+                },
+            );
+            merged += 1;
+        }
+        Ok(merged)
+    }
+
+    #[cfg(feature = "lattice")]
+    fn sync_bindings_after_add(&self) {
+        if self.binding_sync {
+            if let Err(e) = self.sync_bindings_from_lattice() {
+                warn!("Failed to merge bindings from the lattice: {}", e);
+            }
+        }
+    }
+
+    /// Returns the actors whose claims failed validation when they were added to this host,
+    /// in the order in which they failed. Always empty unless quarantine was enabled with
+    /// `HostBuilder::with_actor_quarantine`
+    pub fn quarantined_actors(&self) -> Vec<QuarantinedActor> {
+        self.quarantine
+            .as_ref()
+            .map(|q| q.read_or_recover().clone())
+            .unwrap_or_default()
+    }
+
     /// Applies a manifest JSON or YAML file to set up a host's actors, capability providers,
-    /// and actor bindings
+    /// and actor bindings. The first entry that fails to apply aborts the operation; use
+    /// `apply_manifest_with_report` to apply the remaining entries regardless
     #[cfg(feature = "manifest")]
     pub fn apply_manifest(&self, manifest: HostManifest) -> Result<()> {
+        self.apply_manifest_with_report(manifest, false).map(|_| ())
+    }
+
+    /// Applies a manifest in the same order as `apply_manifest` (actors, then capability
+    /// providers, then bindings), returning a report of the outcome of each entry. If
+    /// `continue_on_error` is true, entries that fail are recorded in the report and the
+    /// remaining entries are still applied, otherwise the first failure is returned as an error.
+    /// Bindings created from templates are expanded before anything is applied, so a template
+    /// that can't be expanded fails the manifest up front unless continuing on errors
+    #[cfg(feature = "manifest")]
+    pub fn apply_manifest_with_report(
+        &self,
+        manifest: HostManifest,
+        continue_on_error: bool,
+    ) -> Result<ManifestReport> {
+        let start = Instant::now();
+        let report = self.apply_manifest_entries(manifest, continue_on_error, 1);
+        self.timings.since("manifest.apply", start);
+        report
+    }
+
+    /// Applies a manifest like `apply_manifest_with_report` with `continue_on_error` set, but
+    /// adds up to `max_concurrency` actors at a time, and then up to `max_concurrency` capability
+    /// providers at a time, which considerably shortens startup for manifests with many actors.
+    /// Bindings are still applied one at a time once every actor and provider has been added.
+    /// Within each phase entries may be applied in any order, but the report lists them in the
+    /// order of the manifest
+    #[cfg(feature = "manifest")]
+    pub fn apply_manifest_parallel(
+        &self,
+        manifest: HostManifest,
+        max_concurrency: usize,
+    ) -> Result<ManifestReport> {
+        let start = Instant::now();
+        let report = self.apply_manifest_entries(manifest, true, max_concurrency.max(1));
+        self.timings.since("manifest.apply", start);
+        report
+    }
+
+    #[cfg(feature = "manifest")]
+    fn apply_manifest_entries(
+        &self,
+        manifest: HostManifest,
+        continue_on_error: bool,
+        concurrency: usize,
+    ) -> Result<ManifestReport> {
+        manifest
+            .check_version()
+            .map_err(|e| errors::new(errors::ErrorKind::MiscHost(e)))?;
+        let mut report = ManifestReport {
+            warnings: manifest.warnings.clone(),
+            ..Default::default()
+        };
+        let (bindings, failures) = manifest.expanded_bindings();
+        for (entry, e) in failures {
+            ManifestReport::record::<()>(
+                &mut report.bindings,
+                entry,
+                Err(errors::new(errors::ErrorKind::MiscHost(e))),
+                continue_on_error,
+            )?;
+        }
         {
-            let mut labels = self.labels.write().unwrap();
+            let mut labels = self.labels.write_or_recover();
             for (label, label_value) in manifest.labels {
                 if !RESTRICTED_LABELS.contains(&label.as_ref()) {
                     labels.insert(label.to_string(), label_value.to_string());
                 }
             }
         }
-        for actor in manifest.actors {
-            self.add_actor_file_first(&actor)?; // If file, add .wasm, otherwise assume it's an OCI ref
+        // Bindings are only applied once everything they refer to is reachable, since an actor
+        // or provider that's still starting up can't receive its configuration
+        let ready = Duration::from_millis(MANIFEST_READY_TIMEOUT_MS);
+        let results = run_entries(&manifest.actors, concurrency, continue_on_error, |actor| {
+            self.add_actor_file_first(actor) // If file, add .wasm, otherwise assume it's an OCI ref
+                .and_then(|pk| self.await_ready(&WasccEntity::Actor(pk), ready))
+        });
+        for (actor, res) in manifest.actors.iter().zip(results) {
+            ManifestReport::record(
+                &mut report.actors,
+                actor.to_string(),
+                res,
+                continue_on_error,
+            )?;
+        }
+        let results = run_entries(
+            &manifest.capabilities,
+            concurrency,
+            continue_on_error,
+            |cap| {
+                // for now, supports only file paths
+                if Path::new(&cap.path).exists() {
+                    NativeCapability::from_file(&cap.path, cap.binding_name.clone()).and_then(|c| {
+                        let entity = WasccEntity::Capability {
+                            capid: c.id(),
+                            binding: c.binding_name.to_string(),
+                        };
+                        self.add_native_capability(c).map(|_| entity)
+                    })
+                } else {
+                    self.native_capability_from_registry(&cap.path, cap.binding_name.clone())
+                }
+                .and_then(|entity| self.await_ready(&entity, ready))
+            },
+        );
+        for (cap, res) in manifest.capabilities.iter().zip(results) {
+            let entry = match cap.binding_name {
+                Some(ref b) => format!("{} ({})", cap.path, b),
+                None => cap.path.to_string(),
+            };
+            ManifestReport::record(&mut report.capabilities, entry, res, continue_on_error)?;
+        }
+        for config in bindings {
+            let binding = config
+                .binding
+                .clone()
+                .unwrap_or_else(|| self.default_binding.clone());
+            let entry = |actor: &str| format!("{} -> {} ({})", actor, config.capability, binding);
+            let mut actors: Vec<&str> = Vec::new();
+            if !config.actor.is_empty() {
+                actors.push(&config.actor);
+            }
+            for a in config.actors.iter() {
+                if !actors.contains(&a.as_str()) {
+                    actors.push(a);
+                }
+            }
+            let values = config.values.clone().unwrap_or(HashMap::new());
+            match actors.len() {
+                0 => {
+                    let res = Err(errors::new(errors::ErrorKind::MiscHost(
+                        "Binding entry must specify an actor or actors".to_string(),
+                    )));
+                    ManifestReport::record(
+                        &mut report.bindings,
+                        entry(""),
+                        res,
+                        continue_on_error,
+                    )?;
+                }
+                1 => {
+                    let res = self.set_binding(
+                        actors[0],
+                        &config.capability,
+                        config.binding.clone(),
+                        values,
+                    );
+                    ManifestReport::record(
+                        &mut report.bindings,
+                        entry(actors[0]),
+                        res,
+                        continue_on_error,
+                    )?;
+                }
+                _ => {
+                    let mut results = self.set_group_binding(
+                        &actors,
+                        &config.capability,
+                        config.binding.clone(),
+                        values,
+                    );
+                    for actor in actors {
+                        let res = results.remove(actor).unwrap_or(Ok(()));
+                        ManifestReport::record(
+                            &mut report.bindings,
+                            entry(actor),
+                            res,
+                            continue_on_error,
+                        )?;
+                    }
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Validates a manifest without applying it, reporting every problem that would prevent
+    /// an entry from being applied: actors that can't be fetched or whose claims are invalid,
+    /// expired or denied by the authorizer, capability providers that can't be loaded, bindings
+    /// to actors or capabilities that aren't in the manifest or that the actor isn't attested
+    /// for, and labels reserved for the host. Capability providers are loaded to read their
+    /// descriptors and then dropped; nothing is added to or changed in the host. Providers
+    /// fetched from a registry are unpacked into a temporary directory that's removed
+    /// afterward, rather than into the host's work directory
+    #[cfg(feature = "manifest")]
+    pub fn preflight(&self, manifest: &HostManifest) -> PreflightReport {
+        let mut report = PreflightReport {
+            version: manifest.check_version().err(),
+            warnings: manifest.warnings.clone(),
+            ..Default::default()
+        };
+        let mut labels: Vec<_> = manifest.labels.keys().collect();
+        labels.sort();
+        for label in labels {
+            let error = if RESTRICTED_LABELS.contains(&label.as_ref()) {
+                Some(format!("label {} is reserved for the host", label))
+            } else {
+                None
+            };
+            report.labels.push(EntryResult {
+                entry: label.to_string(),
+                error,
+            });
+        }
+
+        let mut claims: HashMap<String, Claims<wascap::jwt::Actor>> = HashMap::new();
+        for actor in manifest.actors.iter() {
+            let res = if Path::new(actor).exists() {
+                Actor::from_file(actor)
+            } else {
+                inthost::fetch_actor(self.fetcher.as_ref(), actor)
+            }
+            .and_then(|a| {
+                authz::enforce_validation(&a.token, self.claims_skew)?;
+                if !self.check_auth(&a.token) {
+                    return Err(errors::new(errors::ErrorKind::Authorization(
+                        "Authorization hook denied access to module".into(),
+                    )));
+                }
+                Ok(a)
+            });
+            let error = match res {
+                Ok(a) => {
+                    claims.insert(a.public_key(), a.token.claims);
+                    None
+                }
+                Err(e) => Some(e.to_string()),
+            };
+            report.actors.push(EntryResult {
+                entry: actor.to_string(),
+                error,
+            });
         }
-        for cap in manifest.capabilities {
-            // for now, supports only file paths
-            if Path::new(&cap.path).exists() {
-                self.add_native_capability(NativeCapability::from_file(
-                    cap.path,
-                    cap.binding_name,
-                )?)?;
+
+        let scratch =
+            std::env::temp_dir().join(format!("wascc-preflight-{}", uuid::Uuid::new_v4()));
+        // The built-in providers are already running
+        let mut capabilities: Vec<(String, String)> = self
+            .caps
+            .read_or_recover()
+            .keys()
+            .filter(|k| k.capid == extras::CAPABILITY_ID || k.capid == hostmeta::CAPABILITY_ID)
+            .map(|k| (k.capid.to_string(), k.binding_name.to_string()))
+            .collect();
+        for cap in manifest.capabilities.iter() {
+            let entry = match cap.binding_name {
+                Some(ref b) => format!("{} ({})", cap.path, b),
+                None => cap.path.to_string(),
+            };
+            let res = if Path::new(&cap.path).exists() {
+                NativeCapability::from_file(&cap.path, cap.binding_name.clone())
             } else {
-                self.add_native_capability_from_registry(&cap.path, cap.binding_name)?;
+                inthost::fetch_provider(
+                    self.fetcher.as_ref(),
+                    &scratch,
+                    &cap.path,
+                    cap.binding_name
+                        .as_ref()
+                        .map_or(self.default_binding.as_str(), String::as_str),
+                    self.labels.clone(),
+                )
+                .map(|(c, _)| c)
+            };
+            let error = match res {
+                Ok(c) => {
+                    let binding = if c.implicit_binding {
+                        self.default_binding.to_string()
+                    } else {
+                        c.binding_name.to_string()
+                    };
+                    capabilities.push((c.id(), binding));
+                    None
+                }
+                Err(e) => Some(e.to_string()),
+            };
+            report.capabilities.push(EntryResult { entry, error });
+        }
+        if scratch.exists() {
+            if let Err(e) = std::fs::remove_dir_all(&scratch) {
+                warn!("Failed to remove {}: {}", scratch.display(), e);
             }
         }
-        for config in manifest.bindings {
-            self.set_binding(
-                &config.actor,
-                &config.capability,
-                config.binding,
-                config.values.unwrap_or(HashMap::new()),
-            )?;
+
+        let (bindings, failures) = manifest.expanded_bindings();
+        for (entry, e) in failures {
+            report.bindings.push(EntryResult {
+                entry,
+                error: Some(e),
+            });
         }
-        Ok(())
+        for config in bindings.iter() {
+            let binding = config
+                .binding
+                .clone()
+                .unwrap_or_else(|| self.default_binding.clone());
+            let mut actors: Vec<&str> = Vec::new();
+            if !config.actor.is_empty() {
+                actors.push(&config.actor);
+            }
+            for a in config.actors.iter() {
+                if !actors.contains(&a.as_str()) {
+                    actors.push(a);
+                }
+            }
+            if actors.is_empty() {
+                actors.push("");
+            }
+            let cap_present = capabilities
+                .iter()
+                .any(|(id, b)| *id == config.capability && *b == binding);
+            let reserved = config.values.as_ref().and_then(|v| {
+                v.keys()
+                    .find(|k| k.starts_with(CONFIG_WASCC_RESERVED_PREFIX))
+                    .cloned()
+            });
+            for actor in actors {
+                let error = if actor.is_empty() {
+                    Some("Binding entry must specify an actor or actors".to_string())
+                } else if !claims.contains_key(actor) {
+                    Some(format!("actor {} is not in the manifest", actor))
+                } else if !cap_present {
+                    Some(format!(
+                        "capability {} ({}) is not in the manifest",
+                        config.capability, binding
+                    ))
+                } else if !authz::can_invoke(&claims[actor], &config.capability, OP_BIND_ACTOR) {
+                    Some(authz::attestation_denial(
+                        &claims[actor],
+                        &config.capability,
+                        &binding,
+                        OP_BIND_ACTOR,
+                    ))
+                } else {
+                    reserved.as_ref().map(|k| {
+                        format!(
+                            "Configuration key {} uses the prefix {}, which is reserved for the host",
+                            k, CONFIG_WASCC_RESERVED_PREFIX
+                        )
+                    })
+                };
+                report.bindings.push(EntryResult {
+                    entry: format!("{} -> {} ({})", actor, config.capability, binding),
+                    error,
+                });
+            }
+        }
+        report
     }
 
-    fn add_actor_file_first(&self, actor: &str) -> Result<()> {
+    fn add_actor_file_first(&self, actor: &str) -> Result<String> {
         if std::path::Path::new(actor).exists() {
-            self.add_actor(Actor::from_file(&actor)?)
+            let actor = Actor::from_file(&actor)?;
+            let pk = actor.public_key();
+            self.add_actor(actor).map(|_| pk)
         } else {
             self.add_actor_from_registry(actor)
         }
     }
 
+    /// Waits until an actor or capability provider running in this host can be reached over
+    /// the message bus: for an actor, until its subscription is in place, and for a provider,
+    /// until it answers a request for its capability descriptor. Fails if that doesn't happen
+    /// within the timeout. Applying a manifest does this for everything it adds before applying
+    /// any bindings
+    pub fn await_ready(&self, entity: &WasccEntity, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let ns = self.ns.as_ref().map(String::as_str);
+        loop {
+            let ready = match entity {
+                WasccEntity::Actor(pk) => {
+                    let subject = bus::actor_subject(ns, pk);
+                    self.bus.is_subscribed(&subject)
+                        && !self.bus.failed_subscriptions().contains(&subject)
+                }
+                WasccEntity::Capability { capid, binding } => {
+                    let inv = self.signer.invocation(
+                        WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
+                        entity.clone(),
+                        OP_GET_CAPABILITY_DESCRIPTOR,
+                        vec![],
+                    );
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    match self.bus.invoke_with_timeout(
+                        &bus::provider_subject(ns, capid, binding),
+                        inv,
+                        remaining,
+                    ) {
+                        Ok(inv_r) => inv_r.error.is_none(),
+                        Err(_) => false,
+                    }
+                }
+            };
+            if ready {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                    "{} was not ready within {}ms",
+                    entity,
+                    timeout.as_millis()
+                ))));
+            }
+            std::thread::sleep(Duration::from_millis(READY_POLL_INTERVAL_MS));
+        }
+    }
+
     /// Returns the list of actors registered in the host. Even if lattice mode is enabled, this function
     /// will only return the list of actors in this specific host
     pub fn actors(&self) -> Vec<SubjectClaimsPair> {
         authz::get_all_claims(self.claims.clone())
     }
 
+    /// Calls the function with the public key and claims of each actor registered in this host.
+    /// The actors are those in the host when the call is made; the function may call back into
+    /// the host, including to add or remove actors, without affecting which ones it's called with
+    pub fn for_each_actor(&self, mut f: impl FnMut(&str, &Claims<wascap::jwt::Actor>)) {
+        let actors: Vec<_> = self
+            .claims
+            .read_or_recover()
+            .iter()
+            .map(|(pk, claims)| (pk.clone(), claims.clone()))
+            .collect();
+        for (pk, claims) in actors.iter() {
+            f(pk, claims);
+        }
+    }
+
     /// Returns the list of capability providers registered in the host. The key is a tuple of (binding, capability ID)
     pub fn capabilities(&self) -> HashMap<(String, String), CapabilityDescriptor> {
-        let lock = self.caps.read().unwrap();
+        let lock = self.caps.read_or_recover();
         let mut res = HashMap::new();
         for (rk, descriptor) in lock.iter() {
             res.insert(
@@ -770,43 +3114,329 @@ impl Host {
         res
     }
 
+    /// Returns the actors, capability providers, bindings, and labels of the host as they were
+    /// at a single moment. The individual accessors such as `actors` and `capabilities` are
+    /// cheaper, but calling several of them in turn can observe the host partway through a
+    /// change, e.g. a binding to a provider that has since been removed
+    pub fn snapshot(&self) -> HostSnapshot {
+        snapshot::take(&self.claims, &self.caps, &self.bindings, &self.labels)
+    }
+
+    /// Returns the most recent invocations handled by the given actor, oldest first. The number
+    /// of invocations kept is set with `HostBuilder::with_audit_capacity`
+    pub fn actor_recent_invocations(&self, pk: &str) -> Vec<InvocationAuditEntry> {
+        self.audit.entries(pk)
+    }
+
+    /// Returns the number of invocations currently being handled by each actor and capability
+    /// provider in this host, keyed by entity URL (see `WasccEntity::url`). Entities that are
+    /// idle are omitted
+    pub fn in_flight(&self) -> HashMap<String, usize> {
+        self.in_flight.counts()
+    }
+
+    /// Blocks until no actor or capability provider in this host is handling an invocation, or
+    /// until the timeout elapses, in which case an error is returned. Invocations that arrive
+    /// while waiting are waited for as well. Calling this from within an invocation (e.g. from
+    /// a provider's dispatch thread) waits for that invocation too, and therefore times out
+    pub fn wait_for_idle(&self, timeout: Duration) -> Result<()> {
+        self.in_flight.wait_idle(timeout, |_| true).map_err(|busy| {
+            errors::new(errors::ErrorKind::MiscHost(format!(
+                "Timed out waiting for {} in-flight invocations to finish",
+                busy
+            )))
+        })
+    }
+
+    /// Returns the public keys of the actors currently bound to the given capability provider
+    pub fn provider_bindings(&self, capid: &str, binding: &str) -> Vec<String> {
+        let mut actors: Vec<_> = self
+            .bindings
+            .read_or_recover()
+            .for_provider(capid, binding)
+            .map(|(k, _)| k.actor.to_string())
+            .collect();
+        // An actor with several named bindings to the provider is listed once
+        actors.sort();
+        actors.dedup();
+        actors
+    }
+
+    /// Returns the counts of authorization denials and middleware halts in this host since it
+    /// started
+    pub fn stats(&self) -> HostStats {
+        self.bus.stats().snapshot()
+    }
+
+    /// Returns usage statistics for the given native capability provider, or `None` if no such
+    /// provider is running in this host. Statistics are reset when the provider is removed
+    pub fn provider_stats(&self, capid: &str, binding: &str) -> Option<ProviderStats> {
+        self.plugins
+            .read_or_recover()
+            .stats(binding, capid)
+            .map(|s| ProviderStats {
+                bound_actors: self.provider_bindings(capid, binding).len(),
+                ..s
+            })
+    }
+
+    /// Returns the health of each capability provider in this host as of its most recent probe,
+    /// keyed by binding name and capability ID like `capabilities`. This is empty unless health
+    /// checks were enabled with `HostBuilder::with_health_checks`
+    pub fn capability_health(&self) -> HashMap<(String, String), ProviderHealth> {
+        self.health.snapshot()
+    }
+
+    /// Returns the health of each actor in this host. In lattice mode, an actor whose
+    /// subscription to its invocation subject was torn down behind the host's back is
+    /// re-subscribed automatically, and is reported `Unhealthy` if several consecutive attempts
+    /// to do so have failed, until one succeeds. All other actors are `Healthy`
+    pub fn actor_health(&self) -> HashMap<String, HealthStatus> {
+        let failed = self.bus.failed_subscriptions();
+        self.claims
+            .read_or_recover()
+            .keys()
+            .map(|pk| {
+                let status = if failed.contains(&self.bus.actor_subject(pk)) {
+                    HealthStatus::Unhealthy
+                } else {
+                    HealthStatus::Healthy
+                };
+                (pk.to_string(), status)
+            })
+            .collect()
+    }
+
     /// Returns the list of actors in the host that contain all of the tags in the
-    /// supplied parameter. This function will not make a lattice-wide tag query
+    /// supplied parameter. Tags are compared case-insensitively, ignoring surrounding whitespace,
+    /// and the actor public keys are returned in sorted order. This function will not make a
+    /// lattice-wide tag query
     pub fn actors_by_tag(&self, tags: &[&str]) -> Vec<String> {
-        let mut actors = vec![];
+        inthost::actors_matching_tags(self.claims.read_or_recover().values(), tags, TagMatch::All)
+    }
 
-        for (actor, claims) in self.claims.read().unwrap().iter() {
-            if let Some(actor_tags) = claims.metadata.as_ref().and_then(|m| m.tags.as_ref()) {
-                if tags.iter().all(|&t| actor_tags.contains(&t.to_string())) {
-                    actors.push(actor.to_string())
-                }
-            }
+    /// Returns the annotations of an actor running in this host, which are empty if it has none,
+    /// or `None` if the actor isn't running in this host
+    pub fn actor_annotations(&self, pk: &str) -> Option<HashMap<String, String>> {
+        if !self.claims.read_or_recover().contains_key(pk) {
+            return None;
         }
+        Some(self.actor_runtime.annotations(pk).unwrap_or_default())
+    }
 
-        actors
+    /// Sets one of the annotations of an actor running in this host, replacing any value it
+    /// already had. See `add_actor_with_annotations`
+    pub fn set_actor_annotation(&self, pk: &str, key: &str, value: &str) -> Result<()> {
+        if !self.claims.read_or_recover().contains_key(pk) {
+            return Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                "Actor {} is not in this host",
+                pk
+            ))));
+        }
+        self.actor_runtime.set_annotation(pk, key, value);
+        Ok(())
+    }
+
+    /// Returns the list of actors in the host that have the given annotation, in sorted order.
+    /// This function will not make a lattice-wide query
+    pub fn actors_by_annotation(&self, key: &str, value: &str) -> Vec<String> {
+        let claims = self.claims.read_or_recover();
+        self.actor_runtime
+            .annotated(key, value)
+            .into_iter()
+            .filter(|pk| claims.contains_key(pk))
+            .collect()
+    }
+
+    /// Returns the list of actors in the host that contain any of the tags in the supplied
+    /// parameter, compared the same way as in `actors_by_tag`. This function will not make a
+    /// lattice-wide tag query
+    pub fn actors_by_tag_any(&self, tags: &[&str]) -> Vec<String> {
+        inthost::actors_matching_tags(self.claims.read_or_recover().values(), tags, TagMatch::Any)
+    }
+
+    /// Returns the list of actors running anywhere in the lattice whose tags match the supplied
+    /// parameter according to the match mode. Actors running in more than one host are only
+    /// listed once
+    #[cfg(feature = "lattice")]
+    pub fn lattice_actors_by_tag(&self, tags: &[&str], mode: TagMatch) -> Result<Vec<String>> {
+        let claims = self.bus.lattice_actors()?;
+        Ok(inthost::actors_matching_tags(claims.iter(), tags, mode))
     }
 
-    /// Attempts to perform a graceful shutdown of the host by removing all actors in
-    /// the host and then removing all capability providers. This function is not guaranteed to
-    /// block and wait for the shutdown to finish
+    /// Returns the number of claims of actors running on other hosts in the lattice that this
+    /// host has cached after looking them up to authorize bindings. The cache is bounded by
+    /// `LatticeConfig::remote_claims_capacity`, and `actors` never includes these actors
+    #[cfg(feature = "lattice")]
+    pub fn remote_claims_cached(&self) -> usize {
+        self.bus.remote_claims_cached()
+    }
+
+    /// Registers a function to be called during `shutdown`, after all actors and capability
+    /// providers have terminated but before the host disconnects from the message bus (and thus
+    /// from the lattice, if enabled). Hooks are called in the order in which they were
+    /// registered, and a panic within a hook is logged rather than propagated
+    pub fn on_shutdown(&self, hook: impl FnOnce() + Send + 'static) {
+        self.shutdown_hooks.lock_or_recover().push(Box::new(hook));
+    }
+
+    /// Attempts to perform a graceful shutdown of the host by first refusing further lattice
+    /// control plane commands (waiting for any actor or provider already being started to
+    /// finish starting), then waiting for in-flight invocations to finish, removing all actors
+    /// in the host, then removing all capability providers, running any shutdown hooks,
+    /// publishing the host stopped event and disconnecting, and finally removing the host's
+    /// work directory. In-flight invocations, and then actors and providers, are each given a
+    /// few seconds to finish before the shutdown proceeds regardless
     pub fn shutdown(&self) -> Result<()> {
-        {
-            let lock = self.claims.read().unwrap();
-            let actors: Vec<_> = lock.values().collect();
-            for claims in actors {
-                self.remove_actor(&claims.subject)?;
-            }
+        let start = Instant::now();
+        self.health.stop();
+        // No new actors or providers can be started from the lattice once the rest are stopping
+        #[cfg(feature = "lattice")]
+        self.bus
+            .stop_control_plane(Duration::from_millis(SHUTDOWN_TIMEOUT_MS));
+        let phase = Instant::now();
+        if let Err(e) = self.wait_for_idle(Duration::from_millis(SHUTDOWN_TIMEOUT_MS)) {
+            warn!("{}, shutting down anyway", e);
+        }
+        self.timings.since("shutdown.drain", phase);
+        // Removing an actor unbinds it from its providers, so the claims can't stay locked
+        let phase = Instant::now();
+        let actors: Vec<_> = self.claims.read_or_recover().keys().cloned().collect();
+        for actor in actors {
+            self.stop_actor(&actor)?;
         }
+        self.timings.since("shutdown.actors", phase);
+        let phase = Instant::now();
         let caps = self.capabilities();
         for (binding_name, capid) in caps.keys() {
             self.remove_native_capability(&capid, Some(binding_name.to_string()))?;
         }
+        self.timings.since("shutdown.providers", phase);
+        let phase = Instant::now();
+        if let Some(t) = self
+            .terminators
+            .read_or_recover()
+            .get(&self.bus.actor_subject(SYSTEM_ACTOR))
+        {
+            let _ = t.send(true);
+        }
+        if !self.await_terminations(Duration::from_millis(SHUTDOWN_TIMEOUT_MS)) {
+            warn!("Not all actors and capability providers terminated before the shutdown timeout");
+        }
+        self.timings.since("shutdown.terminations", phase);
+        let phase = Instant::now();
+        let hooks: Vec<_> = self.shutdown_hooks.lock_or_recover().drain(..).collect();
+        inthost::run_hooks("shutdown", hooks);
+        self.timings.since("shutdown.hooks", phase);
         self.bus.disconnect();
+        inthost::clean_work_dir(&self.work_dir)?;
+        self.timings.since("shutdown", start);
+        if self.timing_report {
+            for entry in self.timings().entries {
+                info!(
+                    "host_timing id={} name={} micros={}",
+                    self.pk,
+                    entry.name,
+                    entry.duration.as_micros()
+                );
+            }
+        }
         Ok(())
     }
 
+    /// Returns the durations of the phases of this host's startup and shutdown so far, and of
+    /// adding its actors and capability providers (see `HostTimings` for the phases recorded),
+    /// for tracking performance across releases
+    pub fn timings(&self) -> HostTimings {
+        self.timings.snapshot()
+    }
+
     /// Returns the public key of the host
     pub fn id(&self) -> String {
         self.pk.to_string()
     }
+
+    /// Returns the optional features this build of the crate was compiled with (including the
+    /// WebAssembly engine, `wasmtime` or `wasm3`), so that embedders can check for one at
+    /// runtime, e.g. refusing to start in production without `lattice`
+    pub fn features(&self) -> Vec<&'static str> {
+        features::enabled()
+    }
+
+    /// Returns the host's identity, uptime, and labels along with the version, engine, and
+    /// optional features of the build it is running, for fingerprinting a host with one call
+    pub fn info(&self) -> HostInfo {
+        HostInfo::new(
+            &self.pk,
+            self.started,
+            self.ns.clone(),
+            self.labels.read_or_recover().clone(),
+            self.capability_allowlist.ids(),
+        )
+    }
+
+    /// Starts signing the invocations this host sends with a new key (an encoded seed). The
+    /// key it replaces is still accepted for the grace period, so that invocations signed
+    /// before the rotation (or by hosts in the lattice that haven't rotated yet) aren't
+    /// rejected. The host's identity is unchanged. Fails if the host wasn't given a signing key
+    /// with `HostBuilder::with_invocation_signer`
+    pub fn rotate_invocation_signer(&self, new_seed: &str, grace: Duration) -> Result<()> {
+        self.signer.rotate(new_seed, grace)?;
+        info!("Rotated invocation signing key for host {}", self.pk);
+        Ok(())
+    }
+
+    /// Accepts invocations signed with the key (a public key) that the hosts sharing this
+    /// host's signing key have announced they'll rotate to, for the given window. Trusting the
+    /// next key on every host first means the hosts can then rotate one at a time, in any
+    /// order, without rejecting each other's invocations. Once this host has rotated to the
+    /// key itself, it's accepted regardless. Fails if the host wasn't given a signing key with
+    /// `HostBuilder::with_invocation_signer`
+    pub fn trust_invocation_signer(&self, public_key: &str, window: Duration) -> Result<()> {
+        self.signer.trust(public_key, window)?;
+        info!(
+            "Host {} trusting announced invocation signing key {}",
+            self.pk, public_key
+        );
+        Ok(())
+    }
+
+    /// Returns the labels currently assigned to the host, including the `hostcore.*` labels
+    pub fn labels(&self) -> HashMap<String, String> {
+        self.labels.read_or_recover().clone()
+    }
+
+    /// Returns the lattice namespace of the host, if one was set
+    pub fn namespace(&self) -> Option<String> {
+        self.ns.clone()
+    }
+
+    /// Returns the directory in which this host keeps its scratch files. The directory is
+    /// created on first use and removed when the host is shut down
+    pub fn work_dir(&self) -> &Path {
+        &self.work_dir
+    }
+
+    /// Returns a channel on which this host will deliver a copy of every [HostEvent](events/enum.HostEvent.html)
+    /// it emits from this point forward. Dropping the receiver unsubscribes it
+    pub fn events(&self) -> Receiver<HostEvent> {
+        self.bus.host_events()
+    }
+
+    /// Returns a stream on which every lattice event published in this host's namespace (by
+    /// any host) will be delivered from this point forward. Events that can't be parsed are
+    /// logged and skipped. Dropping the stream removes the underlying subscription
+    #[cfg(feature = "lattice")]
+    pub fn lattice_events(&self) -> Result<LatticeEventStream<BusEvent>> {
+        self.bus.lattice_events()
+    }
+
+    /// Returns a channel on which every extended lattice event (those without a `BusEvent`
+    /// counterpart, such as binding removals and provider health transitions) published in this
+    /// host's namespace will be delivered from this point forward. As with `lattice_events`, the
+    /// subscription is removed when the stream is dropped
+    #[cfg(feature = "lattice")]
+    pub fn lattice_extended_events(&self) -> Result<LatticeEventStream<LatticeEvent>> {
+        self.bus.lattice_extended_events()
+    }
 }
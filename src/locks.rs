@@ -0,0 +1,114 @@
+// Access to the host's shared locks that survives poisoning. A thread that panics while holding
+// a lock poisons it, and unwrapping every later access would turn that one failure into a panic
+// in every thread and host API call that touches the same state. These accessors recover the
+// data instead, since the host's shared state (maps of claims, bindings, and so on) is left
+// consistent between individual inserts and removals.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{
+    Condvar, LockResult, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+};
+
+// Recovery is only logged as a warning the first time, since a poisoned lock stays poisoned and
+// may be accessed on every invocation
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+fn recover<G>(res: LockResult<G>) -> G {
+    res.unwrap_or_else(|e| {
+        if !WARNED.swap(true, Ordering::Relaxed) {
+            warn!("Recovering from a lock poisoned by a panicked thread");
+        } else {
+            trace!("Recovering from a poisoned lock");
+        }
+        e.into_inner()
+    })
+}
+
+pub(crate) trait RwLockExt<T> {
+    fn read_or_recover(&self) -> RwLockReadGuard<'_, T>;
+    fn write_or_recover(&self) -> RwLockWriteGuard<'_, T>;
+}
+
+impl<T> RwLockExt<T> for RwLock<T> {
+    fn read_or_recover(&self) -> RwLockReadGuard<'_, T> {
+        recover(self.read())
+    }
+
+    fn write_or_recover(&self) -> RwLockWriteGuard<'_, T> {
+        recover(self.write())
+    }
+}
+
+pub(crate) trait MutexExt<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T> MutexExt<T> for Mutex<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T> {
+        recover(self.lock())
+    }
+}
+
+/// A lock for each of an open-ended set of keys, so that changes to one key are serialized
+/// without holding up changes to the others. A key takes no room once nobody holds it
+#[derive(Default)]
+pub(crate) struct KeyedLocks {
+    held: Mutex<HashSet<String>>,
+    released: Condvar,
+}
+
+impl KeyedLocks {
+    /// Waits until no one else holds the key, and holds it until the guard is dropped
+    pub(crate) fn lock(&self, key: String) -> KeyedGuard<'_> {
+        let mut held = self.held.lock_or_recover();
+        while held.contains(&key) {
+            held = recover(self.released.wait(held));
+        }
+        held.insert(key.clone());
+        KeyedGuard { locks: self, key }
+    }
+}
+
+pub(crate) struct KeyedGuard<'a> {
+    locks: &'a KeyedLocks,
+    key: String,
+}
+
+impl Drop for KeyedGuard<'_> {
+    fn drop(&mut self) {
+        self.locks.held.lock_or_recover().remove(&self.key);
+        self.locks.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::KeyedLocks;
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn keys_are_locked_independently() {
+        let locks = Arc::new(KeyedLocks::default());
+        let held = locks.lock("a".to_string());
+
+        // Another key is free while "a" is held
+        let _b = locks.lock("b".to_string());
+
+        let (s, r) = channel();
+        let l = locks.clone();
+        let waiter = thread::spawn(move || {
+            let _a = l.lock("a".to_string());
+            s.send(()).unwrap();
+        });
+        assert!(r.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(held);
+        assert!(r.recv_timeout(Duration::from_secs(1)).is_ok());
+        waiter.join().unwrap();
+        assert!(locks.held.lock().unwrap().contains("b"));
+        assert!(!locks.held.lock().unwrap().contains("a"));
+    }
+}
@@ -0,0 +1,516 @@
+//! # Logging Provider
+//!
+//! A built-in "wascc:logging" provider that forwards log records from actors to the host's
+//! [log](https://docs.rs/log) implementation. It is added with
+//! `HostBuilder::with_logging_provider`, and can't be used alongside a separately loaded
+//! provider for the same capability ID (such as the native wascc:logging plugin).
+//!
+//! Actors attested for the capability bind to it like any other provider, and log by invoking
+//! `OP_LOG` with a `LogRecord`. Records are logged with the actor's public key as the target,
+//! or `{actor}::{target}` if the record names a target of its own, so that host log filters
+//! (e.g. `RUST_LOG`) can select individual actors. A binding can set the `LOG_LEVEL` value
+//! (e.g. `debug`) to discard the actor's records below that level before they reach the host's
+//! logger.
+//!
+//! To protect the host from an actor flooding its logs, each actor may log at most
+//! `LoggingConfig::max_per_sec` records per second. Further records are dropped, and the number
+//! dropped is logged as a warning once the second is over, or when the actor is unbound.
+
+use crate::locks::{MutexExt, RwLockExt};
+use crate::{errors, Result, REVISION, VERSION};
+use log::{Level, LevelFilter};
+use std::collections::HashMap;
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, Instant};
+use wascc_codec::capabilities::{
+    CapabilityDescriptor, CapabilityProvider, Dispatcher, NullDispatcher, OperationDirection,
+    OP_GET_CAPABILITY_DESCRIPTOR,
+};
+use wascc_codec::core::{CapabilityConfiguration, OP_BIND_ACTOR, OP_REMOVE_ACTOR};
+use wascc_codec::{deserialize, serialize, SYSTEM_ACTOR};
+
+/// The capability ID of the logging provider
+pub const CAPABILITY_ID: &str = "wascc:logging";
+
+/// Writes a `LogRecord` to the host's log
+pub const OP_LOG: &str = "Log";
+
+/// The binding configuration value that sets the minimum level of the records logged for an
+/// actor, e.g. `info`. All records are logged if it isn't set
+pub const CONFIG_LOG_LEVEL: &str = "LOG_LEVEL";
+
+/// Configuration for the built-in logging provider
+#[derive(Debug, Clone)]
+pub struct LoggingConfig {
+    /// The maximum number of records logged per second for each actor. Defaults to 100
+    pub max_per_sec: u32,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig { max_per_sec: 100 }
+    }
+}
+
+/// A log record sent by an actor with `OP_LOG`. On the wire, a record is a msgpack map of
+/// strings with the keys `level` (`error`, `warn`, `info`, `debug`, or `trace`), `message`,
+/// and optionally `target`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    pub level: Level,
+    pub target: Option<String>,
+    pub message: String,
+}
+
+impl LogRecord {
+    /// Encodes the record as the payload of an `OP_LOG` invocation
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut map = HashMap::new();
+        map.insert("level", self.level.to_string().to_lowercase());
+        map.insert("message", self.message.to_string());
+        if let Some(ref t) = self.target {
+            map.insert("target", t.to_string());
+        }
+        Ok(serialize(&map)?)
+    }
+
+    /// Decodes a record from the payload of an `OP_LOG` invocation
+    pub fn from_bytes(bytes: &[u8]) -> Result<LogRecord> {
+        let mut map: HashMap<String, String> = deserialize(bytes)?;
+        let level = map
+            .get("level")
+            .and_then(|l| Level::from_str(l).ok())
+            .ok_or_else(|| {
+                errors::new(errors::ErrorKind::MiscHost(
+                    "Log record has a missing or invalid level".to_string(),
+                ))
+            })?;
+        Ok(LogRecord {
+            level,
+            target: map.remove("target"),
+            message: map.remove("message").unwrap_or_default(),
+        })
+    }
+}
+
+// How long each actor's rate limit applies to a count of its records
+const WINDOW: Duration = Duration::from_secs(1);
+
+// The records logged for an actor in the current one-second window
+struct Window {
+    started: Instant,
+    logged: u32,
+    dropped: u64,
+}
+
+impl Window {
+    fn ends(&self) -> Instant {
+        self.started + WINDOW
+    }
+}
+
+// Limits the records logged for each actor to a number per one-second window
+struct RateLimiter {
+    max_per_sec: u32,
+    windows: HashMap<String, Window>,
+}
+
+impl RateLimiter {
+    fn new(max_per_sec: u32) -> Self {
+        RateLimiter {
+            max_per_sec,
+            windows: HashMap::new(),
+        }
+    }
+
+    // Counts a record against the actor's limit, returning whether it may be logged. If the
+    // actor's window is over and hasn't been ended yet, it's ended first and its drops reported
+    fn admit(&mut self, actor: &str, now: Instant) -> bool {
+        if self.windows.get(actor).map_or(false, |w| w.ends() <= now) {
+            let dropped = self.end(actor);
+            report_dropped(dropped.into_iter().collect(), self.max_per_sec);
+        }
+        let window = self.windows.entry(actor.to_string()).or_insert(Window {
+            started: now,
+            logged: 0,
+            dropped: 0,
+        });
+        if window.logged < self.max_per_sec {
+            window.logged += 1;
+            true
+        } else {
+            window.dropped += 1;
+            false
+        }
+    }
+
+    // Ends the windows that have lasted a second, returning the actors that had records dropped
+    // in them along with how many
+    fn end_windows(&mut self, now: Instant) -> Vec<(String, u64)> {
+        let ended: Vec<String> = self
+            .windows
+            .iter()
+            .filter(|(_, w)| w.ends() <= now)
+            .map(|(actor, _)| actor.to_string())
+            .collect();
+        ended
+            .into_iter()
+            .filter_map(|actor| self.end(&actor))
+            .collect()
+    }
+
+    // When the earliest of the current windows ends, if there are any
+    fn next_end(&self) -> Option<Instant> {
+        self.windows.values().map(Window::ends).min()
+    }
+
+    // Ends the actor's window, returning how many of its records were dropped, if any
+    fn end(&mut self, actor: &str) -> Option<(String, u64)> {
+        self.windows
+            .remove(actor)
+            .filter(|w| w.dropped > 0)
+            .map(|w| (actor.to_string(), w.dropped))
+    }
+}
+
+fn report_dropped(dropped: Vec<(String, u64)>, max_per_sec: u32) {
+    for (actor, count) in dropped {
+        warn!(
+            "Dropped {} log records from actor {} that exceeded the limit of {} per second",
+            count, actor, max_per_sec
+        );
+    }
+}
+
+// Reports the records dropped in each window once it is over, whether or not the actor logs
+// again, until the limiter is dropped along with its provider. The reporter wakes when the
+// earliest window ends, or after a window's length if there are none, so that windows last no
+// longer than a second however they line up with its wakeups
+fn spawn_reporter(limiter: Weak<Mutex<RateLimiter>>) {
+    std::thread::spawn(move || {
+        let mut wait = WINDOW;
+        loop {
+            std::thread::sleep(wait);
+            let limiter = match limiter.upgrade() {
+                Some(l) => l,
+                None => break,
+            };
+            let mut limiter = limiter.lock_or_recover();
+            let now = Instant::now();
+            let dropped = limiter.end_windows(now);
+            report_dropped(dropped, limiter.max_per_sec);
+            wait = limiter
+                .next_end()
+                .map_or(WINDOW, |end| end.saturating_duration_since(now));
+        }
+    });
+}
+
+pub(crate) struct LoggingCapabilityProvider {
+    dispatcher: Arc<RwLock<Box<dyn Dispatcher>>>,
+    levels: RwLock<HashMap<String, LevelFilter>>,
+    limiter: Arc<Mutex<RateLimiter>>,
+}
+
+impl LoggingCapabilityProvider {
+    pub fn new(config: LoggingConfig) -> Self {
+        let limiter = Arc::new(Mutex::new(RateLimiter::new(config.max_per_sec)));
+        spawn_reporter(Arc::downgrade(&limiter));
+        LoggingCapabilityProvider {
+            dispatcher: Arc::new(RwLock::new(Box::new(NullDispatcher::new()))),
+            levels: RwLock::new(HashMap::new()),
+            limiter,
+        }
+    }
+
+    fn bind(&self, msg: &[u8]) -> std::result::Result<Vec<u8>, Box<dyn Error + Sync + Send>> {
+        let config: CapabilityConfiguration = deserialize(msg)?;
+        if let Some(level) = config.values.get(CONFIG_LOG_LEVEL) {
+            let filter = LevelFilter::from_str(level)
+                .map_err(|_| format!("Invalid {} value: {}", CONFIG_LOG_LEVEL, level))?;
+            self.levels.write_or_recover().insert(config.module, filter);
+        }
+        Ok(vec![])
+    }
+
+    fn remove(&self, msg: &[u8]) -> std::result::Result<Vec<u8>, Box<dyn Error + Sync + Send>> {
+        let config: CapabilityConfiguration = deserialize(msg)?;
+        self.levels.write_or_recover().remove(&config.module);
+        let mut limiter = self.limiter.lock_or_recover();
+        let dropped = limiter.end(&config.module);
+        report_dropped(dropped.into_iter().collect(), limiter.max_per_sec);
+        Ok(vec![])
+    }
+
+    fn log(
+        &self,
+        actor: &str,
+        msg: &[u8],
+    ) -> std::result::Result<Vec<u8>, Box<dyn Error + Sync + Send>> {
+        let record = LogRecord::from_bytes(msg)?;
+        if !self.enabled(actor, record.level)
+            || !self.limiter.lock_or_recover().admit(actor, Instant::now())
+        {
+            return Ok(vec![]);
+        }
+        log!(
+            target: &record_target(actor, &record),
+            record.level,
+            "{}",
+            record.message
+        );
+        Ok(vec![])
+    }
+
+    // Indicates whether the actor's binding lets records of the level through
+    fn enabled(&self, actor: &str, level: Level) -> bool {
+        self.levels
+            .read_or_recover()
+            .get(actor)
+            .map_or(true, |filter| level <= *filter)
+    }
+
+    fn get_descriptor(&self) -> std::result::Result<Vec<u8>, Box<dyn Error + Sync + Send>> {
+        Ok(serialize(
+            CapabilityDescriptor::builder()
+                .id(CAPABILITY_ID)
+                .name("waSCC Logging (Internal)")
+                .long_description("A capability provider forwarding actor logs to the host's log")
+                .version(VERSION)
+                .revision(REVISION)
+                .with_operation(
+                    OP_LOG,
+                    OperationDirection::ToProvider,
+                    "Writes a log record to the host's log",
+                )
+                .build(),
+        )?)
+    }
+}
+
+// The log target of an actor's record
+fn record_target(actor: &str, record: &LogRecord) -> String {
+    match record.target {
+        Some(ref t) => format!("{}::{}", actor, t),
+        None => actor.to_string(),
+    }
+}
+
+impl CapabilityProvider for LoggingCapabilityProvider {
+    fn configure_dispatch(
+        &self,
+        dispatcher: Box<dyn Dispatcher>,
+    ) -> std::result::Result<(), Box<dyn Error + Send + Sync>> {
+        trace!("Dispatcher received.");
+        let mut lock = self.dispatcher.write_or_recover();
+        *lock = dispatcher;
+
+        Ok(())
+    }
+
+    fn handle_call(
+        &self,
+        actor: &str,
+        op: &str,
+        msg: &[u8],
+    ) -> std::result::Result<Vec<u8>, Box<dyn Error + Sync + Send>> {
+        trace!("Received host call from {}, operation - {}", actor, op);
+
+        match op {
+            OP_GET_CAPABILITY_DESCRIPTOR if actor == SYSTEM_ACTOR => self.get_descriptor(),
+            OP_BIND_ACTOR if actor == SYSTEM_ACTOR => self.bind(msg),
+            OP_REMOVE_ACTOR if actor == SYSTEM_ACTOR => self.remove(msg),
+            OP_LOG => self.log(actor, msg),
+            _ => Err("bad dispatch".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        record_target, LogRecord, LoggingCapabilityProvider, LoggingConfig, RateLimiter,
+        CAPABILITY_ID, OP_LOG,
+    };
+    use crate::{HostBuilder, Invocation, WasccEntity};
+    use log::{Level, Metadata, Record};
+    use std::collections::HashMap;
+    use std::sync::{Mutex, Once};
+    use std::time::{Duration, Instant};
+    use wascc_codec::capabilities::CapabilityProvider;
+    use wascc_codec::core::{CapabilityConfiguration, OP_BIND_ACTOR, OP_REMOVE_ACTOR};
+    use wascc_codec::serialize;
+    use wascc_codec::SYSTEM_ACTOR;
+
+    lazy_static::lazy_static! {
+        // Records logged with a target beginning with "M", i.e. by actors
+        static ref CAPTURED: Mutex<Vec<(String, Level, String)>> = Mutex::new(Vec::new());
+    }
+
+    struct Capture;
+
+    impl log::Log for Capture {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+        fn log(&self, record: &Record) {
+            if record.target().starts_with('M') {
+                CAPTURED.lock().unwrap().push((
+                    record.target().to_string(),
+                    record.level(),
+                    record.args().to_string(),
+                ));
+            }
+        }
+        fn flush(&self) {}
+    }
+
+    fn captured(actor: &str) -> Vec<(String, Level, String)> {
+        CAPTURED
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(t, _, _)| t.starts_with(actor))
+            .cloned()
+            .collect()
+    }
+
+    fn install_capture() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_boxed_logger(Box::new(Capture)).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+    }
+
+    fn record(level: Level, target: Option<&str>, message: &str) -> LogRecord {
+        LogRecord {
+            level,
+            target: target.map(|t| t.to_string()),
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let rec = record(Level::Warn, Some("db"), "slow query");
+        assert_eq!(
+            rec,
+            LogRecord::from_bytes(&rec.to_bytes().unwrap()).unwrap()
+        );
+        let mut bad = HashMap::new();
+        bad.insert("level", "loud");
+        assert!(LogRecord::from_bytes(&serialize(&bad).unwrap()).is_err());
+    }
+
+    #[test]
+    fn records_targeted_at_actor() {
+        let actor = "MLOGGINGTESTACTORONE";
+        assert_eq!(
+            actor,
+            record_target(actor, &record(Level::Debug, None, "starting"))
+        );
+        assert_eq!(
+            format!("{}::http", actor),
+            record_target(actor, &record(Level::Info, Some("http"), "handled"))
+        );
+    }
+
+    #[test]
+    fn actor_logs_forwarded() {
+        install_capture();
+        let actor = "MLOGGINGTESTACTORONE";
+        let host = HostBuilder::new()
+            .with_logging_provider(LoggingConfig::default())
+            .build();
+        let subject = host.bus.provider_subject(CAPABILITY_ID, "default");
+        let log = |rec: LogRecord| {
+            let inv = Invocation::test_builder()
+                .with_origin(WasccEntity::Actor(actor.to_string()))
+                .with_target(WasccEntity::Capability {
+                    capid: CAPABILITY_ID.to_string(),
+                    binding: "default".to_string(),
+                })
+                .with_operation(OP_LOG)
+                .with_payload(rec.to_bytes().unwrap())
+                .build();
+            assert!(host.bus.invoke(&subject, inv).unwrap().error.is_none());
+        };
+
+        log(record(Level::Debug, None, "starting"));
+        log(record(Level::Info, Some("http"), "handled request"));
+        assert_eq!(
+            vec![
+                (actor.to_string(), Level::Debug, "starting".to_string()),
+                (
+                    format!("{}::http", actor),
+                    Level::Info,
+                    "handled request".to_string()
+                )
+            ],
+            captured(actor)
+        );
+        host.shutdown().unwrap();
+    }
+
+    #[test]
+    fn level_filter() {
+        let actor = "MLOGGINGTESTACTORTWO";
+        let provider = LoggingCapabilityProvider::new(LoggingConfig::default());
+        let mut values = HashMap::new();
+        values.insert("LOG_LEVEL".to_string(), "info".to_string());
+        let config = serialize(CapabilityConfiguration {
+            module: actor.to_string(),
+            values,
+        })
+        .unwrap();
+        provider
+            .handle_call(SYSTEM_ACTOR, OP_BIND_ACTOR, &config)
+            .unwrap();
+        assert!(!provider.enabled(actor, Level::Debug));
+        assert!(provider.enabled(actor, Level::Error));
+        assert!(provider.enabled("MUNBOUNDACTOR", Level::Trace));
+
+        provider
+            .handle_call(SYSTEM_ACTOR, OP_REMOVE_ACTOR, &config)
+            .unwrap();
+        assert!(provider.enabled(actor, Level::Debug));
+    }
+
+    #[test]
+    fn rate_limit() {
+        let actor = "MLOGGINGTESTACTORTWO";
+        let mut limiter = RateLimiter::new(3);
+        let start = Instant::now();
+        let admitted: Vec<bool> = (0..5).map(|_| limiter.admit(actor, start)).collect();
+        assert_eq!(vec![true, true, true, false, false], admitted);
+        assert!(limiter.admit("MOTHERACTOR", start));
+
+        // The drops are reported once the window is over, without waiting for another record
+        assert!(limiter
+            .end_windows(start + Duration::from_millis(500))
+            .is_empty());
+        assert_eq!(
+            vec![(actor.to_string(), 2)],
+            limiter.end_windows(start + Duration::from_secs(1))
+        );
+        assert!(limiter.windows.is_empty());
+        assert!(limiter.admit(actor, start + Duration::from_secs(1)));
+
+        // An actor's window is over after a second even if the reporter hasn't ended it yet
+        for _ in 0..3 {
+            limiter.admit(actor, start + Duration::from_millis(1500));
+        }
+        assert!(limiter.admit(actor, start + Duration::from_secs(2)));
+        assert_eq!(Some(start + Duration::from_secs(3)), limiter.next_end());
+
+        // Drops are also reported when the actor is unbound before its window is over
+        for _ in 0..3 {
+            limiter.admit(actor, start + Duration::from_secs(2));
+        }
+        assert_eq!(Some((actor.to_string(), 1)), limiter.end(actor));
+        assert_eq!(None, limiter.end(actor));
+    }
+}
@@ -1,14 +1,57 @@
 use std::collections::HashMap;
 
+/// The newest manifest schema version understood by this host
+pub const MAX_MANIFEST_VERSION: u32 = 1;
+
+/// The placeholders that can be used in the binding name and values of a binding template
+pub const TEMPLATE_PLACEHOLDERS: &[&str] = &["actor_index", "actor_pk"];
+
+const MANIFEST_FIELDS: &[&str] = &[
+    "version",
+    "labels",
+    "actors",
+    "capabilities",
+    "bindings",
+    "binding_templates",
+    "bindings_from_template",
+];
+const CAPABILITY_FIELDS: &[&str] = &["path", "binding_name"];
+const BINDING_FIELDS: &[&str] = &["actor", "actors", "capability", "binding", "values"];
+const TEMPLATE_FIELDS: &[&str] = &["capability", "binding", "values"];
+const TEMPLATE_BINDING_FIELDS: &[&str] = &["actor", "template", "overrides"];
+
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "manifest", derive(serde::Serialize, serde::Deserialize))]
 pub struct HostManifest {
+    /// The manifest schema version, defaults to 1 if not specified
+    #[serde(default = "default_version")]
+    pub version: u32,
     #[serde(default)]
     #[serde(skip_serializing_if = "HashMap::is_empty")]
     pub labels: HashMap<String, String>,
+    #[serde(default)]
     pub actors: Vec<String>,
+    #[serde(default)]
     pub capabilities: Vec<Capability>,
+    #[serde(default)]
     pub bindings: Vec<BindingEntry>,
+    /// Named binding definitions that can be applied to many actors with
+    /// `bindings_from_template`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub binding_templates: HashMap<String, BindingTemplate>,
+    /// Bindings created from `binding_templates`, applied after `bindings`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bindings_from_template: Vec<TemplateBinding>,
+    /// Problems found while parsing the manifest that did not prevent it from being loaded,
+    /// such as unrecognized fields. These are carried into the `ManifestReport` when applied
+    #[serde(skip)]
+    pub warnings: Vec<String>,
+}
+
+fn default_version() -> u32 {
+    1
 }
 
 #[derive(Debug, Clone)]
@@ -21,12 +64,233 @@ pub struct Capability {
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "manifest", derive(serde::Serialize, serde::Deserialize))]
 pub struct BindingEntry {
+    /// The actor to bind. Either this or `actors` (or both) must be supplied
+    #[serde(default)]
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub actor: String,
+    /// A group of actors to bind to the same capability with the same values, applied with
+    /// `Host::set_group_binding`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub actors: Vec<String>,
     pub capability: String,
     pub binding: Option<String>,
     pub values: Option<HashMap<String, String>>,
 }
 
+/// A binding definition shared by several actors. The binding name and values may contain
+/// placeholders, written `{{name}}`, that are replaced for each actor the template is applied
+/// to: `{{actor_pk}}` with the actor's public key, and `{{actor_index}}` with the position
+/// (starting at 0) of the actor's entry among the `bindings_from_template` entries that use
+/// the same template
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "manifest", derive(serde::Serialize, serde::Deserialize))]
+pub struct BindingTemplate {
+    pub capability: String,
+    pub binding: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub values: HashMap<String, String>,
+}
+
+/// A binding of an actor created from a named binding template
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "manifest", derive(serde::Serialize, serde::Deserialize))]
+pub struct TemplateBinding {
+    pub actor: String,
+    pub template: String,
+    /// Values that replace or add to the template's values. These may contain placeholders
+    #[serde(default)]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub overrides: HashMap<String, String>,
+}
+
+/// The outcome of applying a single manifest entry
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "manifest", derive(serde::Serialize, serde::Deserialize))]
+pub struct EntryResult {
+    /// A description of the entry, e.g. the actor path or `actor -> capability (binding)`
+    pub entry: String,
+    /// The error produced while applying the entry, or `None` if it was applied
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl EntryResult {
+    /// Indicates whether the entry was applied successfully
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// A per-entry account of applying a manifest to a host, produced by
+/// `Host::apply_manifest_with_report`. Entries appear in the order they were applied
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "manifest", derive(serde::Serialize, serde::Deserialize))]
+pub struct ManifestReport {
+    pub actors: Vec<EntryResult>,
+    pub capabilities: Vec<EntryResult>,
+    pub bindings: Vec<EntryResult>,
+    /// Warnings produced while parsing the manifest
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+impl ManifestReport {
+    /// Indicates whether every entry in the manifest was applied successfully
+    pub fn is_success(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    /// The entries that could not be applied
+    pub fn failures(&self) -> impl Iterator<Item = &EntryResult> {
+        self.actors
+            .iter()
+            .chain(self.capabilities.iter())
+            .chain(self.bindings.iter())
+            .filter(|e| !e.is_ok())
+    }
+
+    pub(crate) fn record<T>(
+        entries: &mut Vec<EntryResult>,
+        entry: String,
+        result: crate::Result<T>,
+        continue_on_error: bool,
+    ) -> crate::Result<()> {
+        match result {
+            Ok(_) => {
+                entries.push(EntryResult { entry, error: None });
+                Ok(())
+            }
+            Err(e) if continue_on_error => {
+                warn!("Failed to apply manifest entry {}: {}", entry, e);
+                entries.push(EntryResult {
+                    entry,
+                    error: Some(e.to_string()),
+                });
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// Applies the function to each of a manifest's entries on up to `concurrency` threads at once,
+// returning the results in the order of the entries. Run serially, the entries after the first
+// failure are skipped unless continuing on errors
+pub(crate) fn run_entries<T: Sync, R: Send>(
+    entries: &[T],
+    concurrency: usize,
+    continue_on_error: bool,
+    f: impl Fn(&T) -> crate::Result<R> + Sync,
+) -> Vec<crate::Result<R>> {
+    if concurrency <= 1 {
+        let mut results = Vec::new();
+        for entry in entries {
+            let res = f(entry);
+            let failed = res.is_err();
+            results.push(res);
+            if failed && !continue_on_error {
+                break;
+            }
+        }
+        return results;
+    }
+
+    let (job_s, job_r) = crossbeam_channel::unbounded();
+    for i in 0..entries.len() {
+        job_s.send(i).unwrap();
+    }
+    drop(job_s);
+    let (res_s, res_r) = crossbeam_channel::unbounded();
+    let f = &f;
+    crossbeam_utils::thread::scope(|s| {
+        for _ in 0..concurrency.min(entries.len()) {
+            let (job_r, res_s) = (job_r.clone(), res_s.clone());
+            s.spawn(move |_| {
+                for i in job_r.iter() {
+                    let _ = res_s.send((i, f(&entries[i])));
+                }
+            });
+        }
+    })
+    .unwrap();
+    drop(res_s);
+
+    let mut results: Vec<_> = res_r.iter().collect();
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, res)| res).collect()
+}
+
+/// The outcome of validating a manifest with `Host::preflight` without applying it. Each entry
+/// records the first problem found with it, if any, so the report can be printed for an
+/// operator or serialized for tooling
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "manifest", derive(serde::Serialize, serde::Deserialize))]
+pub struct PreflightReport {
+    /// Set if the manifest's schema version isn't supported by this host
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    pub labels: Vec<EntryResult>,
+    pub actors: Vec<EntryResult>,
+    pub capabilities: Vec<EntryResult>,
+    pub bindings: Vec<EntryResult>,
+    /// Warnings produced while parsing the manifest
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+impl PreflightReport {
+    /// Indicates whether the manifest could be applied without any of the problems checked for
+    pub fn is_success(&self) -> bool {
+        self.version.is_none() && self.failures().next().is_none()
+    }
+
+    /// The entries with problems
+    pub fn failures(&self) -> impl Iterator<Item = &EntryResult> {
+        self.labels
+            .iter()
+            .chain(self.actors.iter())
+            .chain(self.capabilities.iter())
+            .chain(self.bindings.iter())
+            .filter(|e| !e.is_ok())
+    }
+}
+
+impl std::fmt::Display for PreflightReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(ref e) = self.version {
+            writeln!(f, "version: {}", e)?;
+        }
+        let sections = [
+            ("labels", &self.labels),
+            ("actors", &self.actors),
+            ("capabilities", &self.capabilities),
+            ("bindings", &self.bindings),
+        ];
+        for (name, entries) in sections.iter() {
+            if entries.is_empty() {
+                continue;
+            }
+            writeln!(f, "{}:", name)?;
+            for e in entries.iter() {
+                match e.error {
+                    Some(ref err) => writeln!(f, "  FAIL {}: {}", e.entry, err)?,
+                    None => writeln!(f, "  ok   {}", e.entry)?,
+                }
+            }
+        }
+        for w in self.warnings.iter() {
+            writeln!(f, "warning: {}", w)?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(feature = "manifest")]
 use std::{fs::File, io::Read, path::Path};
 #[cfg(feature = "manifest")]
@@ -34,10 +298,14 @@ impl HostManifest {
     /// Creates an instance of a host manifest from a file path. The de-serialization
     /// type will be chosen based on the file path extension, selecting YAML for .yaml
     /// or .yml files, and JSON for all other file extensions. If the path has no extension, the
-    /// de-serialization type chosen will be YAML.
+    /// de-serialization type chosen will be YAML. Unrecognized fields (e.g. a misspelled
+    /// `bindngs`) are rejected if `strict` is true, otherwise they are logged and recorded
+    /// in the manifest's `warnings`. A manifest with a newer `version` than this host
+    /// supports is always rejected.
     pub fn from_path(
         path: impl AsRef<Path>,
         expand_env: bool,
+        strict: bool,
     ) -> std::result::Result<HostManifest, Box<dyn std::error::Error + Send + Sync>> {
         let mut contents = String::new();
         let mut file = File::open(path.as_ref())?;
@@ -49,13 +317,109 @@ impl HostManifest {
             Some(e) => {
                 let e = e.to_str().unwrap().to_lowercase(); // convert away from the FFI str
                 if e == "yaml" || e == "yml" {
-                    serde_yaml::from_str::<HostManifest>(&contents).map_err(|e| e.into())
+                    Self::from_value(serde_yaml::from_str(&contents)?, strict)
                 } else {
-                    serde_json::from_str::<HostManifest>(&contents).map_err(|e| e.into())
+                    Self::from_value(serde_json::from_str(&contents)?, strict)
                 }
             }
-            None => serde_yaml::from_str::<HostManifest>(&contents).map_err(|e| e.into()),
+            None => Self::from_value(serde_yaml::from_str(&contents)?, strict),
+        }
+    }
+
+    /// Ensures this host understands the manifest's schema version
+    pub fn check_version(&self) -> std::result::Result<(), String> {
+        if self.version > MAX_MANIFEST_VERSION {
+            Err(format!(
+                "manifest version {} not supported by this host (max {})",
+                self.version, MAX_MANIFEST_VERSION
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Expands each of the manifest's `bindings_from_template` entries into a binding entry, in
+    /// order. An entry fails to expand if it names a template that doesn't exist or if the
+    /// template (or its overrides) uses an unknown placeholder
+    pub fn expand_templates(&self) -> Vec<std::result::Result<BindingEntry, String>> {
+        let mut indexes: HashMap<&str, usize> = HashMap::new();
+        self.bindings_from_template
+            .iter()
+            .map(|tb| {
+                let index = indexes.entry(&tb.template).or_insert(0);
+                let actor_index = *index;
+                *index += 1;
+                let fail = |e: String| {
+                    format!(
+                        "binding template {} for actor {}: {}",
+                        tb.template, tb.actor, e
+                    )
+                };
+                let template = self
+                    .binding_templates
+                    .get(&tb.template)
+                    .ok_or_else(|| fail("no such template".to_string()))?;
+                let vars = [
+                    ("actor_index", actor_index.to_string()),
+                    ("actor_pk", tb.actor.to_string()),
+                ];
+                let mut values = template.values.clone();
+                values.extend(tb.overrides.clone());
+                let values = values
+                    .into_iter()
+                    .map(|(k, v)| expand_placeholders(&v, &vars).map(|v| (k, v)))
+                    .collect::<std::result::Result<HashMap<_, _>, _>>()
+                    .map_err(fail)?;
+                let binding = match template.binding {
+                    Some(ref b) => Some(expand_placeholders(b, &vars).map_err(fail)?),
+                    None => None,
+                };
+                Ok(BindingEntry {
+                    actor: tb.actor.to_string(),
+                    actors: vec![],
+                    capability: template.capability.to_string(),
+                    binding,
+                    values: Some(values),
+                })
+            })
+            .collect()
+    }
+
+    // The manifest's bindings followed by those expanded from templates, along with the
+    // entries of the templated bindings that failed to expand and why
+    pub(crate) fn expanded_bindings(&self) -> (Vec<BindingEntry>, Vec<(String, String)>) {
+        let mut bindings = self.bindings.clone();
+        let mut failures = Vec::new();
+        for (tb, res) in self
+            .bindings_from_template
+            .iter()
+            .zip(self.expand_templates())
+        {
+            match res {
+                Ok(b) => bindings.push(b),
+                Err(e) => failures.push((format!("{} -> template {}", tb.actor, tb.template), e)),
+            }
         }
+        (bindings, failures)
+    }
+
+    fn from_value(
+        value: serde_json::Value,
+        strict: bool,
+    ) -> std::result::Result<HostManifest, Box<dyn std::error::Error + Send + Sync>> {
+        let unknown = unknown_fields(&value);
+        let mut manifest: HostManifest = serde_json::from_value(value)?;
+        manifest.check_version()?;
+        if !unknown.is_empty() {
+            if strict {
+                return Err(format!("manifest contains {}", unknown.join(", ")).into());
+            }
+            for w in unknown.iter() {
+                warn!("Ignoring {}", w);
+            }
+            manifest.warnings = unknown;
+        }
+        Ok(manifest)
     }
 
     fn expand_env(contents: &str) -> String {
@@ -67,6 +431,74 @@ impl HostManifest {
     }
 }
 
+// Replaces each `{{name}}` placeholder in the input with the value of the named variable
+fn expand_placeholders(
+    input: &str,
+    vars: &[(&str, String)],
+) -> std::result::Result<String, String> {
+    let mut res = String::new();
+    let mut rest = input;
+    while let Some(start) = rest.find("{{") {
+        res.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| format!("unterminated placeholder in `{}`", input))?;
+        let name = after[..end].trim();
+        match vars.iter().find(|(n, _)| *n == name) {
+            Some((_, v)) => res.push_str(v),
+            None => {
+                return Err(format!(
+                    "unknown placeholder `{}` (expected one of {})",
+                    name,
+                    TEMPLATE_PLACEHOLDERS.join(", ")
+                ))
+            }
+        }
+        rest = &after[end + 2..];
+    }
+    res.push_str(rest);
+    Ok(res)
+}
+
+/// Lists the fields of the manifest, and of its capability and binding entries, that
+/// are not part of the schema
+#[cfg(feature = "manifest")]
+fn unknown_fields(value: &serde_json::Value) -> Vec<String> {
+    fn check(value: &serde_json::Value, known: &[&str], location: &str, res: &mut Vec<String>) {
+        if let Some(map) = value.as_object() {
+            for key in map.keys().filter(|k| !known.contains(&k.as_str())) {
+                res.push(format!("unknown field `{}` in {}", key, location));
+            }
+        }
+    }
+
+    let mut res = Vec::new();
+    check(value, MANIFEST_FIELDS, "manifest", &mut res);
+    for (section, known) in &[
+        ("capabilities", CAPABILITY_FIELDS),
+        ("bindings", BINDING_FIELDS),
+        ("bindings_from_template", TEMPLATE_BINDING_FIELDS),
+    ] {
+        if let Some(entries) = value.get(section).and_then(|v| v.as_array()) {
+            for (i, entry) in entries.iter().enumerate() {
+                check(entry, known, &format!("{}[{}]", section, i), &mut res);
+            }
+        }
+    }
+    if let Some(templates) = value.get("binding_templates").and_then(|v| v.as_object()) {
+        for (name, template) in templates {
+            check(
+                template,
+                TEMPLATE_FIELDS,
+                &format!("binding_templates.{}", name),
+                &mut res,
+            );
+        }
+    }
+    res
+}
+
 #[cfg(feature = "manifest")]
 #[cfg(test)]
 mod test {
@@ -76,6 +508,7 @@ mod test {
     #[test]
     fn round_trip() {
         let manifest = super::HostManifest {
+            version: 1,
             labels: HashMap::new(),
             actors: vec!["a".to_string(), "b".to_string(), "c".to_string()],
             capabilities: vec![
@@ -90,18 +523,23 @@ mod test {
             ],
             bindings: vec![BindingEntry {
                 actor: "a".to_string(),
+                actors: vec![],
                 binding: Some("default".to_string()),
                 capability: "wascc:one".to_string(),
                 values: Some(gen_values()),
             }],
+            binding_templates: HashMap::new(),
+            bindings_from_template: vec![],
+            warnings: vec![],
         };
         let yaml = serde_yaml::to_string(&manifest).unwrap();
-        assert_eq!(yaml, "---\nactors:\n  - a\n  - b\n  - c\ncapabilities:\n  - path: one\n    binding_name: default\n  - path: two\n    binding_name: default\nbindings:\n  - actor: a\n    capability: \"wascc:one\"\n    binding: default\n    values:\n      ROOT: /tmp");
+        assert_eq!(yaml, "---\nversion: 1\nactors:\n  - a\n  - b\n  - c\ncapabilities:\n  - path: one\n    binding_name: default\n  - path: two\n    binding_name: default\nbindings:\n  - actor: a\n    capability: \"wascc:one\"\n    binding: default\n    values:\n      ROOT: /tmp");
     }
 
     #[test]
     fn round_trip_with_labels() {
         let manifest = super::HostManifest {
+            version: 1,
             labels: {
                 let mut hm = HashMap::new();
                 hm.insert("test".to_string(), "value".to_string());
@@ -120,13 +558,17 @@ mod test {
             ],
             bindings: vec![BindingEntry {
                 actor: "a".to_string(),
+                actors: vec![],
                 binding: Some("default".to_string()),
                 capability: "wascc:one".to_string(),
                 values: Some(gen_values()),
             }],
+            binding_templates: HashMap::new(),
+            bindings_from_template: vec![],
+            warnings: vec![],
         };
         let yaml = serde_yaml::to_string(&manifest).unwrap();
-        assert_eq!(yaml, "---\nlabels:\n  test: value\nactors:\n  - a\n  - b\n  - c\ncapabilities:\n  - path: one\n    binding_name: default\n  - path: two\n    binding_name: default\nbindings:\n  - actor: a\n    capability: \"wascc:one\"\n    binding: default\n    values:\n      ROOT: /tmp");
+        assert_eq!(yaml, "---\nversion: 1\nlabels:\n  test: value\nactors:\n  - a\n  - b\n  - c\ncapabilities:\n  - path: one\n    binding_name: default\n  - path: two\n    binding_name: default\nbindings:\n  - actor: a\n    capability: \"wascc:one\"\n    binding: default\n    values:\n      ROOT: /tmp");
     }
 
     #[test]
@@ -159,10 +601,160 @@ mod test {
         envmnt::remove("TEST_EXPAND_ENV_TEMP");
     }
 
+    #[test]
+    fn unknown_fields_warned() {
+        let value = serde_json::json!({
+            "actors": ["a"],
+            "capabilities": [{"path": "one", "binding": "default"}],
+            "bindngs": [{"actor": "a", "capability": "wascc:one"}]
+        });
+        let manifest = super::HostManifest::from_value(value.clone(), false).unwrap();
+        assert_eq!(1, manifest.version);
+        assert!(manifest.bindings.is_empty());
+        assert_eq!(
+            manifest.warnings,
+            vec![
+                "unknown field `bindngs` in manifest",
+                "unknown field `binding` in capabilities[0]"
+            ]
+        );
+
+        let err = super::HostManifest::from_value(value, true).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("unknown field `bindngs` in manifest"));
+    }
+
+    #[test]
+    fn group_bindings_parsed() {
+        let value = serde_json::json!({
+            "bindings": [{"actors": ["a", "b"], "capability": "wascc:one"}]
+        });
+        let manifest = super::HostManifest::from_value(value, true).unwrap();
+        assert_eq!("", manifest.bindings[0].actor);
+        assert_eq!(vec!["a", "b"], manifest.bindings[0].actors);
+    }
+
+    #[test]
+    fn future_version_rejected() {
+        let value = serde_json::json!({ "version": 2, "actors": [] });
+        let err = super::HostManifest::from_value(value, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "manifest version 2 not supported by this host (max 1)"
+        );
+    }
+
+    #[test]
+    fn templates_round_trip() {
+        let yaml = r#"
+actors: []
+binding_templates:
+  web:
+    capability: "wascc:http_server"
+    values:
+      PORT: "80{{actor_index}}"
+bindings_from_template:
+  - actor: Ma
+    template: web
+  - actor: Mb
+    template: web
+    overrides:
+      OWNER: "{{actor_pk}}"
+"#;
+        let manifest: super::HostManifest = serde_yaml::from_str(yaml).unwrap();
+        assert!(super::unknown_fields(&serde_yaml::from_str(yaml).unwrap()).is_empty());
+        let again: super::HostManifest =
+            serde_yaml::from_str(&serde_yaml::to_string(&manifest).unwrap()).unwrap();
+        assert_eq!(manifest.binding_templates, again.binding_templates);
+        assert_eq!(
+            manifest.bindings_from_template,
+            again.bindings_from_template
+        );
+        assert!(manifest.bindings_from_template[0].overrides.is_empty());
+    }
+
+    #[test]
+    fn templates_expanded_per_actor() {
+        let value = serde_json::json!({
+            "binding_templates": {
+                "queue": {
+                    "capability": "wascc:messaging",
+                    "binding": "q{{ actor_index }}",
+                    "values": {"SUBSCRIPTION": "jobs.{{actor_pk}}", "QUEUE": "jobs"}
+                },
+                "broken": {"capability": "wascc:messaging", "values": {"X": "{{actor_name}}"}}
+            },
+            "bindings_from_template": [
+                {"actor": "Ma", "template": "queue"},
+                {"actor": "Mb", "template": "broken"},
+                {"actor": "Mc", "template": "queue", "overrides": {"QUEUE": "{{actor_index}}"}},
+                {"actor": "Md", "template": "missing"}
+            ]
+        });
+        let manifest = super::HostManifest::from_value(value, true).unwrap();
+        let expanded = manifest.expand_templates();
+        assert_eq!(4, expanded.len());
+
+        let a = expanded[0].as_ref().unwrap();
+        assert_eq!("Ma", a.actor);
+        assert_eq!("wascc:messaging", a.capability);
+        assert_eq!(Some("q0".to_string()), a.binding);
+        let values = a.values.as_ref().unwrap();
+        assert_eq!("jobs.Ma", values["SUBSCRIPTION"]);
+        assert_eq!("jobs", values["QUEUE"]);
+
+        let c = expanded[2].as_ref().unwrap();
+        assert_eq!(Some("q1".to_string()), c.binding);
+        assert_eq!("1", c.values.as_ref().unwrap()["QUEUE"]);
+
+        let err = expanded[1].as_ref().unwrap_err();
+        assert!(err.contains("broken"));
+        assert!(err.contains("Mb"));
+        assert!(err.contains("unknown placeholder `actor_name`"));
+        assert_eq!(
+            "binding template missing for actor Md: no such template",
+            expanded[3].as_ref().unwrap_err()
+        );
+    }
+
     fn gen_values() -> HashMap<String, String> {
         let mut hm = HashMap::new();
         hm.insert("ROOT".to_string(), "/tmp".to_string());
 
         hm
     }
+
+    #[test]
+    fn entries_run_in_order_within_bounds() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let fail = |i: &usize| -> crate::Result<usize> {
+            if *i == 2 {
+                Err(crate::errors::new(crate::errors::ErrorKind::MiscHost(
+                    "two".to_string(),
+                )))
+            } else {
+                Ok(*i * 10)
+            }
+        };
+        let entries: Vec<usize> = (0..6).collect();
+        assert_eq!(3, super::run_entries(&entries, 1, false, fail).len());
+        assert_eq!(6, super::run_entries(&entries, 1, true, fail).len());
+
+        let (running, peak) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        let results = super::run_entries(&entries, 3, true, |i| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            running.fetch_sub(1, Ordering::SeqCst);
+            fail(i)
+        });
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+        let results: Vec<_> = results.into_iter().map(|r| r.ok()).collect();
+        assert_eq!(
+            vec![Some(0), Some(10), None, Some(30), Some(40), Some(50)],
+            results
+        );
+    }
 }
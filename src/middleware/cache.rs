@@ -0,0 +1,311 @@
+//! # Response Caching Middleware
+//!
+//! Caches successful invocation responses so that repeated, identical invocations of read-heavy
+//! actors or capability providers can be answered without invoking the target again. Hits are
+//! served by halting the middleware chain with the cached response.
+//!
+//! ```
+//! use std::time::Duration;
+//! use wascc_host::middleware::cache::{CacheConfig, CachingMiddleware};
+//!
+//! let cache = CachingMiddleware::new(CacheConfig {
+//!     ttl: Duration::from_secs(30),
+//!     max_entries: 512,
+//!     ..Default::default()
+//! });
+//! let host = wascc_host::Host::new();
+//! host.add_middleware(cache.clone()); // keep a handle for metrics and invalidation
+//! assert_eq!(0, cache.hits());
+//! ```
+//!
+//! ## Consistency
+//!
+//! A cached response is returned for as long as its TTL, even if the target would now answer
+//! differently, and the target never sees the invocations served from the cache. Only cache
+//! operations that are free of side effects and whose result depends only on the payload;
+//! wrapping this middleware in a `ScopedMiddleware` restricts it to those operations.
+//! The cache is local to the middleware instance: invalidating it on one host doesn't affect
+//! other hosts in a lattice. The default key strategy includes the caller, so a response is
+//! only ever served to the actor or provider whose invocation produced it.
+//!
+//! Invocations made by the host itself (from the system actor) and the operations the host
+//! uses to manage actors and providers, such as binding, removal and health checks, are never
+//! cached, whatever the key strategy: each of them has to reach its target.
+
+use super::{InvocationHandler, Middleware, MiddlewareResponse};
+use crate::locks::MutexExt;
+use crate::{Invocation, InvocationResponse, Result, WasccEntity};
+use data_encoding::HEXUPPER;
+use ring::digest::{Context, SHA256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use wascc_codec::capabilities::OP_GET_CAPABILITY_DESCRIPTOR;
+use wascc_codec::core::{
+    OP_BIND_ACTOR, OP_HEALTH_REQUEST, OP_PERFORM_LIVE_UPDATE, OP_REMOVE_ACTOR,
+};
+use wascc_codec::SYSTEM_ACTOR;
+
+// Operations the host uses to manage actors and providers, which always reach their target
+const CONTROL_OPERATIONS: &[&str] = &[
+    OP_BIND_ACTOR,
+    OP_REMOVE_ACTOR,
+    OP_HEALTH_REQUEST,
+    OP_GET_CAPABILITY_DESCRIPTOR,
+    OP_PERFORM_LIVE_UPDATE,
+];
+
+/// Derives the cache key for an invocation, or `None` if the invocation shouldn't be cached
+pub type KeyFn = dyn Fn(&Invocation) -> Option<String> + Send + Sync;
+
+/// How the cache key is derived from an invocation
+#[derive(Clone)]
+pub enum KeyStrategy {
+    /// A hash of the origin URL, the target URL (which includes the operation) and the payload
+    TargetOperationPayload,
+    /// A custom function
+    Custom(Arc<KeyFn>),
+}
+
+impl Default for KeyStrategy {
+    fn default() -> Self {
+        KeyStrategy::TargetOperationPayload
+    }
+}
+
+impl std::fmt::Debug for KeyStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyStrategy::TargetOperationPayload => write!(f, "TargetOperationPayload"),
+            KeyStrategy::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+/// Configuration for a `CachingMiddleware`
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// How long a response is served from the cache after it was stored
+    pub ttl: Duration,
+    /// The maximum number of cached responses. When full, the least recently used
+    /// response is evicted
+    pub max_entries: usize,
+    /// How cache keys are derived from invocations
+    pub key: KeyStrategy,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            ttl: Duration::from_secs(60),
+            max_entries: 1024,
+            key: KeyStrategy::default(),
+        }
+    }
+}
+
+struct Entry {
+    target: String,
+    msg: Vec<u8>,
+    stored_at: Instant,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<String, Entry>,
+    // Keys ordered by their last use, least recent first
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl CacheState {
+    fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(e) = self.entries.get_mut(key) {
+            self.recency.remove(&e.last_used);
+            e.last_used = tick;
+            self.recency.insert(tick, key.to_string());
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(e) = self.entries.remove(key) {
+            self.recency.remove(&e.last_used);
+        }
+    }
+}
+
+/// Middleware that caches successful responses. Clones share the same cache, so keep a clone
+/// of the instance added to the host to read its metrics or invalidate entries
+#[derive(Clone)]
+pub struct CachingMiddleware {
+    config: CacheConfig,
+    state: Arc<Mutex<CacheState>>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+impl CachingMiddleware {
+    pub fn new(config: CacheConfig) -> Self {
+        CachingMiddleware {
+            config,
+            state: Arc::new(Mutex::new(CacheState::default())),
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// The number of invocations answered from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::SeqCst)
+    }
+
+    /// The number of cacheable invocations that had to be passed on to their target
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::SeqCst)
+    }
+
+    /// The number of responses currently cached, including any that have expired but
+    /// haven't been requested since
+    pub fn len(&self) -> usize {
+        self.state.lock_or_recover().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Discards every cached response for invocations targeting the given actor or capability
+    pub fn invalidate_target(&self, entity: &WasccEntity) {
+        let target = entity.url();
+        let mut state = self.state.lock_or_recover();
+        let keys: Vec<_> = state
+            .entries
+            .iter()
+            .filter(|(_, e)| e.target == target)
+            .map(|(k, _)| k.to_string())
+            .collect();
+        for k in keys {
+            state.remove(&k);
+        }
+    }
+
+    /// Discards every cached response
+    pub fn invalidate_all(&self) {
+        let mut state = self.state.lock_or_recover();
+        state.entries.clear();
+        state.recency.clear();
+    }
+
+    fn key(&self, inv: &Invocation) -> Option<String> {
+        match self.config.key {
+            KeyStrategy::TargetOperationPayload => {
+                let mut context = Context::new(&SHA256);
+                context.update(inv.origin_url().as_bytes());
+                context.update(inv.target_url().as_bytes());
+                context.update(&inv.msg);
+                Some(HEXUPPER.encode(context.finish().as_ref()))
+            }
+            KeyStrategy::Custom(ref f) => f(inv),
+        }
+    }
+
+    fn lookup(&self, key: &str) -> Option<Vec<u8>> {
+        let mut state = self.state.lock_or_recover();
+        let expired = match state.entries.get(key) {
+            Some(e) => e.stored_at.elapsed() >= self.config.ttl,
+            None => return None,
+        };
+        if expired {
+            state.remove(key);
+            None
+        } else {
+            state.touch(key);
+            state.entries.get(key).map(|e| e.msg.clone())
+        }
+    }
+
+    fn store(&self, key: String, target: String, msg: Vec<u8>) {
+        if self.config.max_entries == 0 {
+            return;
+        }
+        let mut state = self.state.lock_or_recover();
+        state.remove(&key);
+        while state.entries.len() >= self.config.max_entries {
+            let oldest = match state.recency.iter().next() {
+                Some((_, k)) => k.to_string(),
+                None => break,
+            };
+            state.remove(&oldest);
+        }
+        state.entries.insert(
+            key.to_string(),
+            Entry {
+                target,
+                msg,
+                stored_at: Instant::now(),
+                last_used: 0,
+            },
+        );
+        state.touch(&key);
+    }
+
+    fn invoke(&self, inv: Invocation, handler: InvocationHandler) -> Result<MiddlewareResponse> {
+        let key = match self.key(&inv) {
+            Some(k) if cacheable(&inv) => k,
+            _ => return Ok(MiddlewareResponse::Continue(handler.invoke(inv))),
+        };
+        if let Some(msg) = self.lookup(&key) {
+            self.hits.fetch_add(1, Ordering::SeqCst);
+            return Ok(MiddlewareResponse::Halt(InvocationResponse::success(
+                &inv, msg,
+            )));
+        }
+        self.misses.fetch_add(1, Ordering::SeqCst);
+        let target = inv.target.url();
+        let response = handler.invoke(inv);
+        if response.error.is_none() {
+            self.store(key, target, response.msg.clone());
+        }
+        Ok(MiddlewareResponse::Continue(response))
+    }
+}
+
+// Whether the invocation is one that may be answered from the cache at all
+fn cacheable(inv: &Invocation) -> bool {
+    let from_host = matches!(inv.origin, WasccEntity::Actor(ref pk) if pk == SYSTEM_ACTOR);
+    !from_host && !CONTROL_OPERATIONS.contains(&inv.operation.as_str())
+}
+
+impl Middleware for CachingMiddleware {
+    fn actor_pre_invoke(&self, inv: Invocation) -> Result<Invocation> {
+        Ok(inv)
+    }
+    fn actor_invoke(
+        &self,
+        inv: Invocation,
+        handler: InvocationHandler,
+    ) -> Result<MiddlewareResponse> {
+        self.invoke(inv, handler)
+    }
+    fn actor_post_invoke(&self, response: InvocationResponse) -> Result<InvocationResponse> {
+        Ok(response)
+    }
+
+    fn capability_pre_invoke(&self, inv: Invocation) -> Result<Invocation> {
+        Ok(inv)
+    }
+    fn capability_invoke(
+        &self,
+        inv: Invocation,
+        handler: InvocationHandler,
+    ) -> Result<MiddlewareResponse> {
+        self.invoke(inv, handler)
+    }
+    fn capability_post_invoke(&self, response: InvocationResponse) -> Result<InvocationResponse> {
+        Ok(response)
+    }
+}
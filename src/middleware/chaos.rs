@@ -0,0 +1,336 @@
+//! # Chaos Middleware
+//!
+//! Injects faults into invocations so that the way actors and their callers cope with slow or
+//! failing targets can be tested. Each invocation of a target (capability provider or actor)
+//! matching one of the configured URL patterns may, independently and with the configured
+//! probabilities:
+//!
+//! * be delayed by a random duration within the configured range before reaching the target
+//! * fail with an error response without reaching the target
+//! * have the payload of its response truncated to a random shorter length
+//!
+//! Enable this middleware using the feature flag `chaos`. Give it a seed to make the injected
+//! faults reproducible from one run to the next.
+//!
+//! ```
+//! use wascc_host::middleware::chaos::{ChaosConfig, ChaosMiddleware};
+//!
+//! let chaos = ChaosMiddleware::new(ChaosConfig::default())
+//!     .with_target(
+//!         "wasmbus://wascc/keyvalue/*",
+//!         ChaosConfig {
+//!             error_probability: 0.1,
+//!             ..Default::default()
+//!         },
+//!     )
+//!     .with_seed(42);
+//! let host = wascc_host::Host::new();
+//! host.add_middleware(chaos.clone()); // keep a handle to toggle it and read its counters
+//! ```
+
+use super::{glob_match, InvocationHandler, Middleware, MiddlewareResponse};
+use crate::locks::MutexExt;
+use crate::{Invocation, InvocationResponse, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The faults injected into the invocations of targets matching a pattern. Probabilities range
+/// from 0 (never) to 1 (every invocation)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChaosConfig {
+    /// The probability that an invocation is delayed
+    pub latency_probability: f64,
+    /// The shortest and longest delay injected
+    pub latency: (Duration, Duration),
+    /// The probability that an invocation fails with an error response
+    pub error_probability: f64,
+    /// The probability that the payload of an invocation's response is truncated
+    pub truncate_probability: f64,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        ChaosConfig {
+            latency_probability: 0.0,
+            latency: (Duration::from_millis(0), Duration::from_millis(0)),
+            error_probability: 0.0,
+            truncate_probability: 0.0,
+        }
+    }
+}
+
+/// The number of faults of each kind injected so far
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChaosStats {
+    pub delays: u64,
+    pub errors: u64,
+    pub truncations: u64,
+}
+
+/// Middleware that injects faults into invocations. Clones share the same switch, counters, and
+/// random number generator, so keep a clone of the instance added to the host to control it
+#[derive(Clone)]
+pub struct ChaosMiddleware {
+    default: ChaosConfig,
+    targets: Vec<(String, ChaosConfig)>,
+    enabled: Arc<AtomicBool>,
+    rng: Arc<Mutex<StdRng>>,
+    delays: Arc<AtomicU64>,
+    errors: Arc<AtomicU64>,
+    truncations: Arc<AtomicU64>,
+}
+
+impl ChaosMiddleware {
+    /// Creates an enabled chaos middleware that injects the given faults into the invocations
+    /// of every target that doesn't match a more specific pattern
+    pub fn new(config: ChaosConfig) -> Self {
+        ChaosMiddleware {
+            default: config,
+            targets: Vec::new(),
+            enabled: Arc::new(AtomicBool::new(true)),
+            rng: Arc::new(Mutex::new(StdRng::from_entropy())),
+            delays: Arc::new(AtomicU64::new(0)),
+            errors: Arc::new(AtomicU64::new(0)),
+            truncations: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Injects the given faults into the invocations of targets whose URLs match the pattern,
+    /// such as `wasmbus://wascc/keyvalue/*` or `wasmbus://Mxxxx`. The pattern may use the glob
+    /// wildcards `*` and `?`. When several patterns match a target, the first one added applies
+    pub fn with_target(self, pattern: &str, config: ChaosConfig) -> Self {
+        let mut targets = self.targets;
+        targets.push((pattern.to_string(), config));
+        ChaosMiddleware { targets, ..self }
+    }
+
+    /// Seeds the random number generator that decides which faults are injected, so that the
+    /// same sequence of invocations always receives the same faults
+    pub fn with_seed(self, seed: u64) -> Self {
+        ChaosMiddleware {
+            rng: Arc::new(Mutex::new(StdRng::seed_from_u64(seed))),
+            ..self
+        }
+    }
+
+    /// Resumes injecting faults
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::SeqCst);
+    }
+
+    /// Stops injecting faults. Invocations pass through untouched until the middleware is
+    /// enabled again
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    /// The number of faults injected so far
+    pub fn stats(&self) -> ChaosStats {
+        ChaosStats {
+            delays: self.delays.load(Ordering::SeqCst),
+            errors: self.errors.load(Ordering::SeqCst),
+            truncations: self.truncations.load(Ordering::SeqCst),
+        }
+    }
+
+    fn config_for(&self, url: &str) -> ChaosConfig {
+        self.targets
+            .iter()
+            .find(|(p, _)| glob_match(p.as_bytes(), url.as_bytes()))
+            .map_or(self.default, |(_, c)| *c)
+    }
+
+    fn invoke(&self, inv: Invocation, handler: InvocationHandler) -> Result<MiddlewareResponse> {
+        if !self.is_enabled() {
+            return Ok(MiddlewareResponse::Continue(handler.invoke(inv)));
+        }
+        let config = self.config_for(&inv.target.url());
+        // Every decision is drawn up front so that a seeded sequence doesn't depend on timing
+        let (delay, fail, truncate) = {
+            let mut rng = self.rng.lock_or_recover();
+            let delay = if rng.gen::<f64>() < config.latency_probability {
+                let (min, max) = config.latency;
+                Some(if max > min {
+                    rng.gen_range(min, max)
+                } else {
+                    min
+                })
+            } else {
+                None
+            };
+            let fail = rng.gen::<f64>() < config.error_probability;
+            let truncate = rng.gen::<f64>() < config.truncate_probability;
+            // the fraction of the response payload kept if it's truncated
+            let kept = rng.gen::<f64>();
+            (delay, fail, if truncate { Some(kept) } else { None })
+        };
+
+        if let Some(delay) = delay {
+            self.delays.fetch_add(1, Ordering::SeqCst);
+            std::thread::sleep(delay);
+        }
+        if fail {
+            self.errors.fetch_add(1, Ordering::SeqCst);
+            return Ok(MiddlewareResponse::Halt(InvocationResponse::error(
+                &inv,
+                &format!("Chaos: injected failure of {}", inv.target.url()),
+            )));
+        }
+        let mut response = handler.invoke(inv);
+        if let Some(kept) = truncate {
+            if !response.msg.is_empty() {
+                self.truncations.fetch_add(1, Ordering::SeqCst);
+                let len = (response.msg.len() as f64 * kept) as usize;
+                response.msg.truncate(len);
+            }
+        }
+        Ok(MiddlewareResponse::Continue(response))
+    }
+}
+
+impl Middleware for ChaosMiddleware {
+    fn actor_pre_invoke(&self, inv: Invocation) -> Result<Invocation> {
+        Ok(inv)
+    }
+    fn actor_invoke(
+        &self,
+        inv: Invocation,
+        handler: InvocationHandler,
+    ) -> Result<MiddlewareResponse> {
+        self.invoke(inv, handler)
+    }
+    fn actor_post_invoke(&self, response: InvocationResponse) -> Result<InvocationResponse> {
+        Ok(response)
+    }
+
+    fn capability_pre_invoke(&self, inv: Invocation) -> Result<Invocation> {
+        Ok(inv)
+    }
+    fn capability_invoke(
+        &self,
+        inv: Invocation,
+        handler: InvocationHandler,
+    ) -> Result<MiddlewareResponse> {
+        self.invoke(inv, handler)
+    }
+    fn capability_post_invoke(&self, response: InvocationResponse) -> Result<InvocationResponse> {
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ChaosConfig, ChaosMiddleware};
+    use crate::middleware::{InvocationContext, Middleware};
+    use crate::{Invocation, InvocationResponse, WasccEntity};
+    use std::time::{Duration, Instant};
+    use wascap::prelude::KeyPair;
+
+    fn run(chaos: &ChaosMiddleware, target: WasccEntity, count: usize) -> Vec<InvocationResponse> {
+        let mids: Vec<Box<dyn Middleware>> = vec![Box::new(chaos.clone())];
+        let hk = KeyPair::new_server();
+        let ctx = InvocationContext::default();
+        let operation = |inv: Invocation| InvocationResponse::success(&inv, vec![1; 16]);
+        (0..count)
+            .map(|_| {
+                let inv = Invocation::new(
+                    &hk,
+                    WasccEntity::Actor("Mxxxx".to_string()),
+                    target.clone(),
+                    "Get",
+                    vec![],
+                );
+                crate::middleware::run_invoke(
+                    &mids,
+                    inv,
+                    &operation,
+                    &|m, inv, h| m.capability_invoke_ctx(inv, h, &ctx),
+                    &Default::default(),
+                )
+                .unwrap()
+            })
+            .collect()
+    }
+
+    fn kv() -> WasccEntity {
+        WasccEntity::Capability {
+            capid: "wascc:keyvalue".to_string(),
+            binding: "default".to_string(),
+        }
+    }
+
+    #[test]
+    fn error_rate_honored() {
+        let chaos = ChaosMiddleware::new(ChaosConfig::default())
+            .with_target(
+                "wasmbus://wascc/keyvalue/*",
+                ChaosConfig {
+                    error_probability: 0.2,
+                    truncate_probability: 0.5,
+                    ..Default::default()
+                },
+            )
+            .with_seed(7);
+        let responses = run(&chaos, kv(), 2000);
+        let errors = responses.iter().filter(|r| r.error.is_some()).count();
+        assert!(errors > 300 && errors < 500, "{} errors", errors);
+        let truncated = responses
+            .iter()
+            .filter(|r| r.error.is_none() && r.msg.len() < 16)
+            .count();
+        assert!(
+            truncated > 600 && truncated < 1000,
+            "{} truncated",
+            truncated
+        );
+        let stats = chaos.stats();
+        assert_eq!(errors as u64, stats.errors);
+        assert_eq!(truncated as u64, stats.truncations);
+
+        // Targets that don't match a pattern use the default configuration
+        let other = run(&chaos, WasccEntity::Actor("Mxxxx".to_string()), 100);
+        assert!(other.iter().all(|r| r.error.is_none() && r.msg.len() == 16));
+
+        // The same seed injects the same faults
+        let failures = || {
+            let chaos = ChaosMiddleware::new(ChaosConfig {
+                error_probability: 0.2,
+                ..Default::default()
+            })
+            .with_seed(11);
+            run(&chaos, kv(), 50)
+                .iter()
+                .map(|r| r.error.is_some())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(failures(), failures());
+    }
+
+    #[test]
+    fn latency_and_toggling() {
+        let chaos = ChaosMiddleware::new(ChaosConfig {
+            latency_probability: 1.0,
+            latency: (Duration::from_millis(20), Duration::from_millis(30)),
+            ..Default::default()
+        });
+        let start = Instant::now();
+        run(&chaos, kv(), 3);
+        assert!(start.elapsed() >= Duration::from_millis(60));
+        assert_eq!(3, chaos.stats().delays);
+
+        chaos.disable();
+        assert!(!chaos.is_enabled());
+        run(&chaos, kv(), 3);
+        assert_eq!(3, chaos.stats().delays);
+        chaos.enable();
+        run(&chaos, kv(), 1);
+        assert_eq!(4, chaos.stats().delays);
+    }
+}
@@ -0,0 +1,376 @@
+//! # Circuit Breaker Middleware
+//!
+//! Stops invocations of a failing target from waiting out their full timeout, one after another,
+//! by rejecting them immediately once the target has failed repeatedly. Each target (capability
+//! provider or actor) matching one of the configured URL patterns gets its own breaker:
+//!
+//! * **Closed** - invocations pass through. After `failure_threshold` consecutive failures,
+//!   the breaker opens.
+//! * **Open** - invocations are answered with an error response describing
+//!   `ErrorKind::CircuitOpen`, without reaching the target. Once `open_duration` has elapsed,
+//!   the breaker becomes half-open.
+//! * **Half-open** - up to `half_open_probes` invocations are let through as probes, and the
+//!   rest are rejected. If every probe succeeds the breaker closes, and if any fails it opens
+//!   again.
+//!
+//! An invocation fails if its response carries an error. The outcome is recorded as soon as the
+//! target answers, so an admitted probe is always accounted for, whatever the rest of the
+//! middleware chain does with the response.
+//!
+//! ```
+//! use wascc_host::middleware::circuitbreaker::{CircuitBreakerConfig, CircuitBreakerMiddleware};
+//!
+//! let breaker = CircuitBreakerMiddleware::new()
+//!     .with_target("wasmbus://wascc/keyvalue/*", CircuitBreakerConfig::default());
+//! let host = wascc_host::Host::new();
+//! host.add_middleware(breaker.clone()); // keep a handle to inspect breaker states
+//! ```
+
+use super::{glob_match, InvocationHandler, Middleware, MiddlewareResponse};
+use crate::errors::{self, ErrorKind};
+use crate::locks::MutexExt;
+use crate::{Invocation, InvocationResponse, Result, WasccEntity};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Configuration for the breakers of targets matching a pattern
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CircuitBreakerConfig {
+    /// The number of consecutive failures that opens the breaker
+    pub failure_threshold: u32,
+    /// How long the breaker stays open before letting probe invocations through
+    pub open_duration: Duration,
+    /// The number of probe invocations that must succeed while half-open to close the breaker
+    pub half_open_probes: u32,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(30),
+            half_open_probes: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// The state of a single target's breaker
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircuitStatus {
+    pub state: CircuitState,
+    /// When the breaker entered its current state
+    pub since: SystemTime,
+    /// The number of consecutive failed invocations of the target
+    pub consecutive_failures: u32,
+}
+
+struct Breaker {
+    config: CircuitBreakerConfig,
+    status: CircuitStatus,
+    entered: Instant,
+    probes_admitted: u32,
+    probe_successes: u32,
+}
+
+impl Breaker {
+    fn new(config: CircuitBreakerConfig) -> Breaker {
+        Breaker {
+            config,
+            status: CircuitStatus {
+                state: CircuitState::Closed,
+                since: SystemTime::now(),
+                consecutive_failures: 0,
+            },
+            entered: Instant::now(),
+            probes_admitted: 0,
+            probe_successes: 0,
+        }
+    }
+
+    fn transition(&mut self, state: CircuitState) {
+        debug!("Circuit {:?} -> {:?}", self.status.state, state);
+        self.status.state = state;
+        self.status.since = SystemTime::now();
+        self.entered = Instant::now();
+        self.probes_admitted = 0;
+        self.probe_successes = 0;
+        if state == CircuitState::Closed {
+            self.status.consecutive_failures = 0;
+        }
+    }
+
+    fn probes(&self) -> u32 {
+        self.config.half_open_probes.max(1)
+    }
+
+    fn admit(&mut self) -> bool {
+        match self.status.state {
+            CircuitState::Closed => true,
+            CircuitState::Open => {
+                if self.entered.elapsed() >= self.config.open_duration {
+                    self.transition(CircuitState::HalfOpen);
+                    self.probes_admitted = 1;
+                    true
+                } else {
+                    false
+                }
+            }
+            CircuitState::HalfOpen => {
+                if self.probes_admitted < self.probes() {
+                    self.probes_admitted += 1;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    fn record(&mut self, success: bool) {
+        match (self.status.state, success) {
+            (CircuitState::Closed, true) => self.status.consecutive_failures = 0,
+            (CircuitState::Closed, false) => {
+                self.status.consecutive_failures += 1;
+                if self.status.consecutive_failures >= self.config.failure_threshold {
+                    self.transition(CircuitState::Open);
+                }
+            }
+            (CircuitState::HalfOpen, true) => {
+                self.probe_successes += 1;
+                if self.probe_successes >= self.probes() {
+                    self.transition(CircuitState::Closed);
+                }
+            }
+            (CircuitState::HalfOpen, false) => {
+                self.status.consecutive_failures += 1;
+                self.transition(CircuitState::Open);
+            }
+            // Responses to invocations admitted before the breaker opened
+            (CircuitState::Open, _) => {}
+        }
+    }
+}
+
+/// Middleware that rejects invocations of targets that keep failing. Clones share the same
+/// breakers, so keep a clone of the instance added to the host to inspect their states
+#[derive(Clone, Default)]
+pub struct CircuitBreakerMiddleware {
+    targets: Vec<(String, CircuitBreakerConfig)>,
+    breakers: Arc<Mutex<HashMap<String, Breaker>>>,
+}
+
+impl CircuitBreakerMiddleware {
+    /// Creates a circuit breaker middleware with no targets. Invocations of targets that don't
+    /// match any pattern pass through untouched
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds breakers for the targets whose URLs match the pattern, such as
+    /// `wasmbus://wascc/keyvalue/*` or `wasmbus://Mxxxx`. The pattern may use the glob
+    /// wildcards `*` and `?`. When several patterns match a target, the first one added applies
+    pub fn with_target(self, pattern: &str, config: CircuitBreakerConfig) -> Self {
+        let mut targets = self.targets;
+        targets.push((pattern.to_string(), config));
+        CircuitBreakerMiddleware { targets, ..self }
+    }
+
+    /// The state of the target's breaker, or `None` if the target doesn't match any pattern or
+    /// hasn't been invoked yet
+    pub fn state(&self, target: &WasccEntity) -> Option<CircuitStatus> {
+        self.breakers
+            .lock_or_recover()
+            .get(&target.url())
+            .map(|b| b.status.clone())
+    }
+
+    fn config_for(&self, url: &str) -> Option<CircuitBreakerConfig> {
+        self.targets
+            .iter()
+            .find(|(p, _)| glob_match(p.as_bytes(), url.as_bytes()))
+            .map(|(_, c)| *c)
+    }
+
+    fn invoke(&self, inv: Invocation, handler: InvocationHandler) -> Result<MiddlewareResponse> {
+        let target = inv.target.url();
+        let config = match self.config_for(&target) {
+            Some(c) => c,
+            None => return Ok(MiddlewareResponse::Continue(handler.invoke(inv))),
+        };
+        let admitted = self
+            .breakers
+            .lock_or_recover()
+            .entry(target.to_string())
+            .or_insert_with(|| Breaker::new(config))
+            .admit();
+        if !admitted {
+            // Halted rather than failed, so that no error policy lets the invocation through
+            let e = errors::new(ErrorKind::CircuitOpen { target });
+            return Ok(MiddlewareResponse::Halt(InvocationResponse::error(
+                &inv,
+                &e.to_string(),
+            )));
+        }
+        let response = handler.invoke(inv);
+        if let Some(b) = self.breakers.lock_or_recover().get_mut(&target) {
+            b.record(response.error.is_none());
+        }
+        Ok(MiddlewareResponse::Continue(response))
+    }
+}
+
+impl Middleware for CircuitBreakerMiddleware {
+    fn actor_pre_invoke(&self, inv: Invocation) -> Result<Invocation> {
+        Ok(inv)
+    }
+    fn actor_invoke(
+        &self,
+        inv: Invocation,
+        handler: InvocationHandler,
+    ) -> Result<MiddlewareResponse> {
+        self.invoke(inv, handler)
+    }
+    fn actor_post_invoke(&self, response: InvocationResponse) -> Result<InvocationResponse> {
+        Ok(response)
+    }
+
+    fn capability_pre_invoke(&self, inv: Invocation) -> Result<Invocation> {
+        Ok(inv)
+    }
+    fn capability_invoke(
+        &self,
+        inv: Invocation,
+        handler: InvocationHandler,
+    ) -> Result<MiddlewareResponse> {
+        self.invoke(inv, handler)
+    }
+    fn capability_post_invoke(&self, response: InvocationResponse) -> Result<InvocationResponse> {
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Breaker, CircuitBreakerConfig, CircuitBreakerMiddleware, CircuitState};
+    use crate::middleware::{InvocationContext, Middleware};
+    use crate::{Invocation, InvocationResponse, Result, WasccEntity};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::Duration;
+    use wascap::prelude::KeyPair;
+
+    fn kv() -> WasccEntity {
+        WasccEntity::Capability {
+            capid: "wascc:keyvalue".to_string(),
+            binding: "default".to_string(),
+        }
+    }
+
+    #[test]
+    fn open_half_open_closed() {
+        let breaker = CircuitBreakerMiddleware::new().with_target(
+            "wasmbus://wascc/keyvalue/*",
+            CircuitBreakerConfig {
+                failure_threshold: 2,
+                open_duration: Duration::from_millis(100),
+                half_open_probes: 1,
+            },
+        );
+        let mids: Vec<Box<dyn Middleware>> = vec![Box::new(breaker.clone())];
+        let hk = KeyPair::new_server();
+        let ctx = InvocationContext::default();
+        let failing = AtomicBool::new(true);
+        let calls = AtomicUsize::new(0);
+        let operation = |inv: Invocation| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            if failing.load(Ordering::SeqCst) {
+                InvocationResponse::error(&inv, "backend down")
+            } else {
+                InvocationResponse::success(&inv, vec![])
+            }
+        };
+        let call = |target: WasccEntity| -> Result<InvocationResponse> {
+            let inv = Invocation::new(
+                &hk,
+                WasccEntity::Actor("Mxxxx".to_string()),
+                target,
+                "Get",
+                vec![],
+            );
+            let policy = crate::middleware::ErrorPolicy::default();
+            let resp = crate::middleware::run_invoke(
+                &mids,
+                inv,
+                &operation,
+                &|m, inv, h| m.capability_invoke_ctx(inv, h, &ctx),
+                &Default::default(),
+            )?;
+            crate::middleware::run_capability_post_invoke(resp, &mids, &ctx, policy)
+        };
+        let state = || breaker.state(&kv()).unwrap();
+
+        call(kv()).unwrap();
+        assert_eq!(CircuitState::Closed, state().state);
+        assert_eq!(1, state().consecutive_failures);
+        call(kv()).unwrap();
+        assert_eq!(CircuitState::Open, state().state);
+
+        // Rejected without reaching the target
+        let error = call(kv()).unwrap().error.unwrap();
+        assert!(error.contains("Circuit open"), "{}", error);
+        assert!(
+            error.contains("wasmbus://wascc/keyvalue/default"),
+            "{}",
+            error
+        );
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+
+        // Targets that don't match a pattern are unaffected
+        call(WasccEntity::Actor("Mother".to_string())).unwrap();
+        assert!(breaker
+            .state(&WasccEntity::Actor("Mother".to_string()))
+            .is_none());
+
+        // A failed probe reopens the breaker
+        std::thread::sleep(Duration::from_millis(150));
+        let opened = state().since;
+        call(kv()).unwrap();
+        assert_eq!(CircuitState::Open, state().state);
+        assert!(state().since > opened);
+
+        // A successful probe closes it
+        failing.store(false, Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(150));
+        call(kv()).unwrap();
+        assert_eq!(CircuitState::Closed, state().state);
+        assert_eq!(0, state().consecutive_failures);
+        assert_eq!(5, calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn half_open_limits_probes() {
+        let mut b = Breaker::new(CircuitBreakerConfig {
+            failure_threshold: 1,
+            open_duration: Duration::from_millis(0),
+            half_open_probes: 2,
+        });
+        assert!(b.admit());
+        b.record(false);
+        assert_eq!(CircuitState::Open, b.status.state);
+
+        assert!(b.admit() && b.admit());
+        assert_eq!(CircuitState::HalfOpen, b.status.state);
+        assert!(!b.admit());
+        b.record(true);
+        assert_eq!(CircuitState::HalfOpen, b.status.state);
+        b.record(true);
+        assert_eq!(CircuitState::Closed, b.status.state);
+    }
+}
@@ -1,9 +1,23 @@
+use crate::audit::{AuditLog, InvocationAuditEntry};
+use crate::errors::{self, ErrorKind};
+use crate::inthost::with_origin_chain;
+use crate::locks::{MutexExt, RwLockExt};
+use crate::stats::StatsCounters;
 use crate::Result;
-use crate::{plugins::PluginManager, Invocation, InvocationResponse};
+use crate::WasccEntity;
+use crate::{bindings::Bindings, plugins::PluginManager, Invocation, InvocationResponse, RouteKey};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use std::sync::RwLock;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 use wapc::WapcHost;
+use wascap::jwt::Claims;
+use wascc_codec::capabilities::CapabilityDescriptor;
 
+pub mod cache;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod circuitbreaker;
 #[cfg(feature = "prometheus_middleware")]
 pub mod prometheus;
 
@@ -24,6 +38,394 @@ pub trait Middleware: Send + Sync + 'static {
         handler: InvocationHandler,
     ) -> Result<MiddlewareResponse>;
     fn capability_post_invoke(&self, response: InvocationResponse) -> Result<InvocationResponse>;
+
+    /// The name identifying this middleware in logs and in the error responses of invocations
+    /// it causes to be rejected. Defaults to the name of the implementing type
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
+    /// How the host treats errors returned by this middleware's hooks. By default this is
+    /// `None`, and the host's policy applies (see `HostBuilder::with_middleware_error_policy`)
+    fn error_policy(&self) -> Option<ErrorPolicy> {
+        None
+    }
+
+    /// Called when an actor in this host becomes ready for invocations, with how long it took to
+    /// start. Middleware added after the actor started isn't called for it
+    fn actor_started(&self, _actor: &str, _start_duration: Duration) {}
+
+    /// Called when a native capability provider in this host becomes ready for invocations,
+    /// with how long it took to load. Middleware added after the provider started isn't called
+    /// for it
+    fn capability_started(&self, _capid: &str, _binding: &str, _load_duration: Duration) {}
+
+    /// Called when the host refuses an actor access to the target, either because the actor's
+    /// claims don't include the capability or because the host's `Authorizer` vetoed the call.
+    /// The reason is one of `REASON_MISSING_ATTESTATION` and `REASON_AUTHORIZER_DENIED`
+    fn authorization_denied(&self, _origin: &str, _target: &WasccEntity, _reason: &str) {}
+
+    /// Called when an invocation is answered by the named middleware halting the pipeline,
+    /// including when this middleware is the one that halted it
+    fn invocation_halted(&self, _middleware: &str, _inv: &Invocation) {}
+
+    /// Called once the host is done with an invocation, whether it was answered, failed, or was
+    /// rejected before reaching this middleware's post-invoke hook. Middleware that keeps state
+    /// between its hooks can release it here
+    fn invocation_completed(&self, _inv: &Invocation) {}
+
+    // The following variants receive the host's context for the invocation. The host only ever
+    // calls these, and by default they ignore the context and delegate to the methods above, so
+    // middleware only needs to override them if it makes decisions based on the context.
+
+    fn actor_pre_invoke_ctx(
+        &self,
+        inv: Invocation,
+        _ctx: &InvocationContext,
+    ) -> Result<Invocation> {
+        self.actor_pre_invoke(inv)
+    }
+    fn actor_invoke_ctx(
+        &self,
+        inv: Invocation,
+        handler: InvocationHandler,
+        _ctx: &InvocationContext,
+    ) -> Result<MiddlewareResponse> {
+        self.actor_invoke(inv, handler)
+    }
+    fn actor_post_invoke_ctx(
+        &self,
+        response: InvocationResponse,
+        _ctx: &InvocationContext,
+    ) -> Result<InvocationResponse> {
+        self.actor_post_invoke(response)
+    }
+
+    fn capability_pre_invoke_ctx(
+        &self,
+        inv: Invocation,
+        _ctx: &InvocationContext,
+    ) -> Result<Invocation> {
+        self.capability_pre_invoke(inv)
+    }
+    fn capability_invoke_ctx(
+        &self,
+        inv: Invocation,
+        handler: InvocationHandler,
+        _ctx: &InvocationContext,
+    ) -> Result<MiddlewareResponse> {
+        self.capability_invoke(inv, handler)
+    }
+    fn capability_post_invoke_ctx(
+        &self,
+        response: InvocationResponse,
+        _ctx: &InvocationContext,
+    ) -> Result<InvocationResponse> {
+        self.capability_post_invoke(response)
+    }
+}
+
+/// How the host treats an error returned by one of a middleware's pre- or post-invoke hooks. An
+/// error returned by an invoke hook always rejects the invocation, since the middleware may
+/// already have made the call it wraps, or failed so that it wouldn't be made
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// The error is logged, and the invocation proceeds as though the failing hook hadn't run.
+    /// This is the default, which suits middleware that only observes invocations
+    FailOpen,
+    /// The invocation is rejected with an error response naming the failing middleware. This
+    /// suits middleware that enforces security, such as an authentication filter
+    FailClosed,
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> Self {
+        ErrorPolicy::FailOpen
+    }
+}
+
+// Applies the policy to a failed hook: fail-open logs the error and returns None so that the
+// caller can carry on, fail-closed returns the error that rejects the invocation
+fn hook_failed(
+    m: &dyn Middleware,
+    stage: &str,
+    e: errors::Error,
+    policy: ErrorPolicy,
+) -> Option<errors::Error> {
+    match m.error_policy().unwrap_or(policy) {
+        ErrorPolicy::FailOpen => {
+            error!("Middleware failure ({} {}): {}", m.name(), stage, e);
+            None
+        }
+        ErrorPolicy::FailClosed => Some(rejection(m, stage, e)),
+    }
+}
+
+fn rejection(m: &dyn Middleware, stage: &str, e: errors::Error) -> errors::Error {
+    errors::new(ErrorKind::Middleware(format!(
+        "Invocation rejected by middleware {} ({}): {}",
+        m.name(),
+        stage,
+        e
+    )))
+}
+
+/// Information the host knows about an invocation that isn't carried in the invocation itself
+#[derive(Debug, Clone, Default)]
+pub struct InvocationContext {
+    /// The claims of the origin actor, when the invocation originated from an actor in this host
+    pub origin_claims: Option<Claims<wascap::jwt::Actor>>,
+    /// The descriptor of the target capability provider, when the target is a capability
+    pub target_descriptor: Option<CapabilityDescriptor>,
+    /// The configuration values recorded for the binding between the actor and the capability
+    /// provider involved in the invocation, if such a binding exists
+    pub binding_values: Option<HashMap<String, String>>,
+}
+
+impl InvocationContext {
+    pub(crate) fn gather(
+        inv: &Invocation,
+        claims: &Arc<RwLock<HashMap<String, Claims<wascap::jwt::Actor>>>>,
+        caps: &Arc<RwLock<HashMap<RouteKey, CapabilityDescriptor>>>,
+        bindings: &Arc<RwLock<Bindings>>,
+    ) -> InvocationContext {
+        let origin_claims = match inv.origin {
+            WasccEntity::Actor(ref pk) => claims.read_or_recover().get(pk).cloned(),
+            _ => None,
+        };
+        let target_descriptor = match inv.target {
+            WasccEntity::Capability {
+                ref capid,
+                ref binding,
+            } => caps
+                .read_or_recover()
+                .get(&RouteKey::new(binding, capid))
+                .cloned(),
+            _ => None,
+        };
+        // The binding is the one between the actor and the capability, regardless of direction
+        let binding_values = match (&inv.origin, &inv.target) {
+            (WasccEntity::Actor(a), WasccEntity::Capability { capid, binding })
+            | (WasccEntity::Capability { capid, binding }, WasccEntity::Actor(a)) => bindings
+                .read_or_recover()
+                .get(a, capid, binding)
+                .map(|cfg| cfg.values.clone()),
+            _ => None,
+        };
+
+        InvocationContext {
+            origin_claims,
+            target_descriptor,
+            binding_values,
+        }
+    }
+}
+
+/// Describes which invocations a `ScopedMiddleware` applies to. Each list that is empty places
+/// no restriction. If either target list is non-empty, the invocation's target must be one of
+/// the listed capabilities or actors. Operation names may use the glob wildcards `*` (any
+/// sequence of characters) and `?` (any single character)
+#[derive(Debug, Clone, Default)]
+pub struct MiddlewareScope {
+    /// Capability IDs (e.g. `wascc:keyvalue`) of target providers
+    pub target_capids: Vec<String>,
+    /// Public keys of target actors
+    pub target_actors: Vec<String>,
+    /// Operation name patterns, e.g. `HandleRequest` or `Op*`
+    pub operations: Vec<String>,
+}
+
+impl MiddlewareScope {
+    /// Indicates whether the invocation falls within this scope
+    pub fn matches(&self, inv: &Invocation) -> bool {
+        let target_ok = (self.target_capids.is_empty() && self.target_actors.is_empty())
+            || match inv.target {
+                WasccEntity::Actor(ref pk) => self.target_actors.contains(pk),
+                WasccEntity::Capability { ref capid, .. } => self.target_capids.contains(capid),
+            };
+        target_ok
+            && (self.operations.is_empty()
+                || self
+                    .operations
+                    .iter()
+                    .any(|p| glob_match(p.as_bytes(), inv.operation.as_bytes())))
+    }
+}
+
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            glob_match(&pattern[1..], text) || (!text.is_empty() && glob_match(pattern, &text[1..]))
+        }
+        (Some(b'?'), Some(_)) => glob_match(&pattern[1..], &text[1..]),
+        (Some(p), Some(t)) if p == t => glob_match(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+/// Wraps another middleware so that it only sees invocations within a `MiddlewareScope`.
+/// Invocations outside the scope pass through untouched: the pre- and post-invoke hooks return
+/// their input, and the invoke hooks continue by invoking the handler directly. Because the
+/// post-invoke hooks only receive the response, the adapter tracks the IDs of in-scope
+/// invocations between the pre- and post-invoke hooks, and forgets them when the host reports
+/// the invocation completed
+pub struct ScopedMiddleware<M: Middleware> {
+    inner: M,
+    scope: MiddlewareScope,
+    in_scope: Mutex<HashSet<String>>,
+}
+
+impl<M: Middleware> ScopedMiddleware<M> {
+    pub fn new(inner: M, scope: MiddlewareScope) -> Self {
+        ScopedMiddleware {
+            inner,
+            scope,
+            in_scope: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn enter(&self, inv: &Invocation) -> bool {
+        let matched = self.scope.matches(inv);
+        if matched {
+            self.in_scope.lock_or_recover().insert(inv.id.to_string());
+        }
+        matched
+    }
+
+    fn leave(&self, response: &InvocationResponse) -> bool {
+        self.in_scope
+            .lock_or_recover()
+            .remove(&response.invocation_id)
+    }
+
+    fn invoke(
+        &self,
+        inv: Invocation,
+        handler: InvocationHandler,
+        f: impl Fn(Invocation, InvocationHandler) -> Result<MiddlewareResponse>,
+    ) -> Result<MiddlewareResponse> {
+        if !self.scope.matches(&inv) {
+            return Ok(MiddlewareResponse::Continue(handler.invoke(inv)));
+        }
+        f(inv, handler)
+    }
+}
+
+impl<M: Middleware> Middleware for ScopedMiddleware<M> {
+    fn actor_pre_invoke(&self, inv: Invocation) -> Result<Invocation> {
+        self.actor_pre_invoke_ctx(inv, &InvocationContext::default())
+    }
+    fn actor_invoke(
+        &self,
+        inv: Invocation,
+        handler: InvocationHandler,
+    ) -> Result<MiddlewareResponse> {
+        self.actor_invoke_ctx(inv, handler, &InvocationContext::default())
+    }
+    fn actor_post_invoke(&self, response: InvocationResponse) -> Result<InvocationResponse> {
+        self.actor_post_invoke_ctx(response, &InvocationContext::default())
+    }
+
+    fn capability_pre_invoke(&self, inv: Invocation) -> Result<Invocation> {
+        self.capability_pre_invoke_ctx(inv, &InvocationContext::default())
+    }
+    fn capability_invoke(
+        &self,
+        inv: Invocation,
+        handler: InvocationHandler,
+    ) -> Result<MiddlewareResponse> {
+        self.capability_invoke_ctx(inv, handler, &InvocationContext::default())
+    }
+    fn capability_post_invoke(&self, response: InvocationResponse) -> Result<InvocationResponse> {
+        self.capability_post_invoke_ctx(response, &InvocationContext::default())
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+    fn error_policy(&self) -> Option<ErrorPolicy> {
+        self.inner.error_policy()
+    }
+    fn actor_started(&self, actor: &str, start_duration: Duration) {
+        self.inner.actor_started(actor, start_duration)
+    }
+    fn capability_started(&self, capid: &str, binding: &str, load_duration: Duration) {
+        self.inner.capability_started(capid, binding, load_duration)
+    }
+    fn authorization_denied(&self, origin: &str, target: &WasccEntity, reason: &str) {
+        self.inner.authorization_denied(origin, target, reason)
+    }
+    fn invocation_halted(&self, middleware: &str, inv: &Invocation) {
+        self.inner.invocation_halted(middleware, inv)
+    }
+    fn invocation_completed(&self, inv: &Invocation) {
+        // The post-invoke hooks don't run for rejected invocations
+        self.in_scope.lock_or_recover().remove(&inv.id);
+        self.inner.invocation_completed(inv)
+    }
+
+    fn actor_pre_invoke_ctx(&self, inv: Invocation, ctx: &InvocationContext) -> Result<Invocation> {
+        if self.enter(&inv) {
+            self.inner.actor_pre_invoke_ctx(inv, ctx)
+        } else {
+            Ok(inv)
+        }
+    }
+    fn actor_invoke_ctx(
+        &self,
+        inv: Invocation,
+        handler: InvocationHandler,
+        ctx: &InvocationContext,
+    ) -> Result<MiddlewareResponse> {
+        self.invoke(inv, handler, |inv, handler| {
+            self.inner.actor_invoke_ctx(inv, handler, ctx)
+        })
+    }
+    fn actor_post_invoke_ctx(
+        &self,
+        response: InvocationResponse,
+        ctx: &InvocationContext,
+    ) -> Result<InvocationResponse> {
+        if self.leave(&response) {
+            self.inner.actor_post_invoke_ctx(response, ctx)
+        } else {
+            Ok(response)
+        }
+    }
+
+    fn capability_pre_invoke_ctx(
+        &self,
+        inv: Invocation,
+        ctx: &InvocationContext,
+    ) -> Result<Invocation> {
+        if self.enter(&inv) {
+            self.inner.capability_pre_invoke_ctx(inv, ctx)
+        } else {
+            Ok(inv)
+        }
+    }
+    fn capability_invoke_ctx(
+        &self,
+        inv: Invocation,
+        handler: InvocationHandler,
+        ctx: &InvocationContext,
+    ) -> Result<MiddlewareResponse> {
+        self.invoke(inv, handler, |inv, handler| {
+            self.inner.capability_invoke_ctx(inv, handler, ctx)
+        })
+    }
+    fn capability_post_invoke_ctx(
+        &self,
+        response: InvocationResponse,
+        ctx: &InvocationContext,
+    ) -> Result<InvocationResponse> {
+        if self.leave(&response) {
+            self.inner.capability_post_invoke_ctx(response, ctx)
+        } else {
+            Ok(response)
+        }
+    }
 }
 
 pub enum MiddlewareResponse {
@@ -36,7 +438,9 @@ pub struct InvocationHandler<'a> {
 }
 
 impl<'a> InvocationHandler<'a> {
-    fn new(operation: &'a dyn Fn(Invocation) -> InvocationResponse) -> Self {
+    /// Wraps the function that performs an invocation. The host creates handlers itself; this is
+    /// public so that middleware can be unit tested without a host
+    pub fn new(operation: &'a dyn Fn(Invocation) -> InvocationResponse) -> Self {
         Self { operation }
     }
 
@@ -50,28 +454,18 @@ pub(crate) fn invoke_native_capability(
     middlewares: Arc<RwLock<Vec<Box<dyn Middleware>>>>,
     inv: Invocation,
     plugins: Arc<RwLock<PluginManager>>,
+    ctx: &InvocationContext,
+    stats: &StatsCounters,
+    policy: ErrorPolicy,
 ) -> Result<InvocationResponse> {
-    let inv = match run_capability_pre_invoke(inv.clone(), &middlewares.read().unwrap()) {
-        Ok(i) => i,
-        Err(e) => {
-            error!("Middleware failure: {}", e);
-            inv
-        }
-    };
-
-    match run_native_capability_invoke(&middlewares.read().unwrap(), &plugins.read().unwrap(), inv)
-    {
-        Ok(response) => {
-            match run_capability_post_invoke(response.clone(), &middlewares.read().unwrap()) {
-                Ok(r) => Ok(r),
-                Err(e) => {
-                    error!("Middleware failure: {}", e);
-                    Ok(response)
-                }
-            }
-        }
-        Err(e) => Err(e),
-    }
+    let mids = middlewares.read_or_recover();
+    Ok(complete_chain(
+        &mids,
+        inv,
+        |inv| run_capability_pre_invoke(inv, &mids, ctx, policy),
+        |inv| run_native_capability_invoke(&mids, &plugins, inv, ctx, stats),
+        |resp| run_capability_post_invoke(resp, &mids, ctx, policy),
+    ))
 }
 
 /// Follows a chain of middleware, ultimately executing a portable capability provider function
@@ -79,65 +473,112 @@ pub(crate) fn invoke_portable_capability(
     middlewares: Arc<RwLock<Vec<Box<dyn Middleware>>>>,
     inv: Invocation,
     guest: &WapcHost,
+    ctx: &InvocationContext,
+    stats: &StatsCounters,
+    policy: ErrorPolicy,
 ) -> Result<InvocationResponse> {
-    let inv = match run_capability_pre_invoke(inv.clone(), &middlewares.read().unwrap()) {
-        Ok(i) => i,
-        Err(e) => {
-            error!("Middleware failure: {}", e);
-            inv
-        }
-    };
+    let mids = middlewares.read_or_recover();
+    let response = complete_chain(
+        &mids,
+        inv,
+        |inv| run_capability_pre_invoke(inv, &mids, ctx, policy),
+        |inv| run_portable_capability_invoke(&mids, inv, guest, ctx, stats),
+        |resp| run_capability_post_invoke(resp, &mids, ctx, policy),
+    );
+    // Denials of the provider's own calls out, made while the pipeline was held
+    stats.notify_deferred(&mids);
+    Ok(response)
+}
 
-    match run_portable_capability_invoke(&middlewares.read().unwrap(), inv, guest) {
-        Ok(response) => {
-            match run_capability_post_invoke(response.clone(), &middlewares.read().unwrap()) {
-                Ok(r) => Ok(r),
-                Err(e) => {
-                    error!("Middleware failure: {}", e);
-                    Ok(response)
-                }
-            }
-        }
-        Err(e) => Err(e),
+pub(crate) fn invoke_actor(
+    middlewares: Arc<RwLock<Vec<Box<dyn Middleware>>>>,
+    inv: Invocation,
+    guest: &WapcHost,
+    ctx: &InvocationContext,
+    stats: &StatsCounters,
+    audit: &AuditLog,
+    policy: ErrorPolicy,
+) -> Result<InvocationResponse> {
+    let timestamp = SystemTime::now();
+    let start = Instant::now();
+    let res = invoke_actor_chain(middlewares, inv.clone(), guest, ctx, stats, policy);
+    if let WasccEntity::Actor(ref actor) = inv.target {
+        audit.record(
+            actor,
+            InvocationAuditEntry {
+                timestamp,
+                origin: inv.origin.url(),
+                operation: inv.operation.to_string(),
+                payload_size: inv.msg.len(),
+                error: match res {
+                    Ok(ref r) => r.error.clone(),
+                    Err(ref e) => Some(e.to_string()),
+                },
+                duration: start.elapsed(),
+            },
+        );
     }
+    res
 }
 
-pub(crate) fn invoke_actor(
+fn invoke_actor_chain(
     middlewares: Arc<RwLock<Vec<Box<dyn Middleware>>>>,
     inv: Invocation,
     guest: &WapcHost,
+    ctx: &InvocationContext,
+    stats: &StatsCounters,
+    policy: ErrorPolicy,
 ) -> Result<InvocationResponse> {
-    let inv = match run_actor_pre_invoke(inv.clone(), &middlewares.read().unwrap()) {
-        Ok(i) => i,
-        Err(e) => {
-            error!("Middleware failure: {}", e);
-            inv
-        }
-    };
+    let mids = middlewares.read_or_recover();
+    let response = complete_chain(
+        &mids,
+        inv,
+        |inv| run_actor_pre_invoke(inv, &mids, ctx, policy),
+        |inv| run_actor_invoke(&mids, inv, guest, ctx, stats),
+        |resp| run_actor_post_invoke(resp, &mids, ctx, policy),
+    );
+    // Denials of the actor's own calls out, made while the pipeline was held
+    stats.notify_deferred(&mids);
+    Ok(response)
+}
 
-    match run_actor_invoke(&middlewares.read().unwrap(), inv, guest) {
-        Ok(response) => {
-            match run_actor_post_invoke(response.clone(), &middlewares.read().unwrap()) {
-                Ok(r) => Ok(r),
-                Err(e) => {
-                    error!("Middleware failure: {}", e);
-                    Ok(response)
-                }
-            }
-        }
-        Err(e) => Err(e),
+// Runs the pre-invoke, invoke, and post-invoke stages of a middleware chain, then tells each
+// middleware that the invocation is complete. Errors only reach this far from failed invoke
+// hooks and fail-closed middleware, and they reject the invocation with an error response
+fn complete_chain(
+    middlewares: &[Box<dyn Middleware>],
+    inv: Invocation,
+    pre: impl FnOnce(Invocation) -> Result<Invocation>,
+    invoke: impl FnOnce(Invocation) -> Result<InvocationResponse>,
+    post: impl FnOnce(InvocationResponse) -> Result<InvocationResponse>,
+) -> InvocationResponse {
+    let original = inv.clone();
+    let rejected = |e: errors::Error| InvocationResponse::error(&original, &e.to_string());
+    let response = pre(inv)
+        .and_then(invoke)
+        .and_then(post)
+        .unwrap_or_else(rejected);
+    for m in middlewares {
+        m.invocation_completed(&original);
     }
+    response
 }
 
 fn run_actor_pre_invoke(
     inv: Invocation,
     middlewares: &[Box<dyn Middleware>],
+    ctx: &InvocationContext,
+    policy: ErrorPolicy,
 ) -> Result<Invocation> {
     let mut cur_inv = inv;
     for m in middlewares {
-        match m.actor_pre_invoke(cur_inv) {
-            Ok(i) => cur_inv = i.clone(),
-            Err(e) => return Err(e),
+        match m.actor_pre_invoke_ctx(cur_inv.clone(), ctx) {
+            Ok(i) => cur_inv = i,
+            Err(e) => {
+                if let Some(e) = hook_failed(m.as_ref(), "pre-invoke", e, policy) {
+                    return Err(e);
+                }
+            }
         }
     }
     Ok(cur_inv)
@@ -147,24 +588,40 @@ fn run_actor_invoke(
     middlewares: &[Box<dyn Middleware>],
     inv: Invocation,
     guest: &WapcHost,
+    ctx: &InvocationContext,
+    stats: &StatsCounters,
 ) -> Result<InvocationResponse> {
-    let invoke_operation = |inv: Invocation| match guest.call(&inv.operation, &inv.msg) {
+    let invoke_operation = |inv: Invocation| match with_origin_chain(&inv.origin_chain, || {
+        guest.call(&inv.operation, &inv.msg)
+    }) {
         Ok(v) => InvocationResponse::success(&inv, v),
         Err(e) => InvocationResponse::error(&inv, &format!("failed to invoke actor: {}", e)),
     };
 
-    run_invoke(middlewares, inv, &invoke_operation)
+    run_invoke(
+        middlewares,
+        inv,
+        &invoke_operation,
+        &|m, inv, handler| m.actor_invoke_ctx(inv, handler, ctx),
+        stats,
+    )
 }
 
 fn run_actor_post_invoke(
     resp: InvocationResponse,
     middlewares: &[Box<dyn Middleware>],
+    ctx: &InvocationContext,
+    policy: ErrorPolicy,
 ) -> Result<InvocationResponse> {
     let mut cur_resp = resp;
     for m in middlewares {
-        match m.actor_post_invoke(cur_resp) {
-            Ok(i) => cur_resp = i.clone(),
-            Err(e) => return Err(e),
+        match m.actor_post_invoke_ctx(cur_resp.clone(), ctx) {
+            Ok(i) => cur_resp = i,
+            Err(e) => {
+                if let Some(e) = hook_failed(m.as_ref(), "post-invoke", e, policy) {
+                    return Err(e);
+                }
+            }
         }
     }
     Ok(cur_resp)
@@ -173,12 +630,18 @@ fn run_actor_post_invoke(
 pub(crate) fn run_capability_pre_invoke(
     inv: Invocation,
     middlewares: &[Box<dyn Middleware>],
+    ctx: &InvocationContext,
+    policy: ErrorPolicy,
 ) -> Result<Invocation> {
     let mut cur_inv = inv;
     for m in middlewares {
-        match m.capability_pre_invoke(cur_inv) {
-            Ok(i) => cur_inv = i.clone(),
-            Err(e) => return Err(e),
+        match m.capability_pre_invoke_ctx(cur_inv.clone(), ctx) {
+            Ok(i) => cur_inv = i,
+            Err(e) => {
+                if let Some(e) = hook_failed(m.as_ref(), "pre-invoke", e, policy) {
+                    return Err(e);
+                }
+            }
         }
     }
     Ok(cur_inv)
@@ -186,34 +649,58 @@ pub(crate) fn run_capability_pre_invoke(
 
 pub(crate) fn run_native_capability_invoke(
     middlewares: &[Box<dyn Middleware>],
-    plugins: &PluginManager,
+    plugins: &RwLock<PluginManager>,
     inv: Invocation,
+    ctx: &InvocationContext,
+    stats: &StatsCounters,
 ) -> Result<InvocationResponse> {
-    let invoke_operation = |inv: Invocation| match plugins.call(&inv) {
+    let invoke_operation = |inv: Invocation| match PluginManager::call(plugins, &inv) {
         Ok(r) => r,
         Err(e) => InvocationResponse::error(&inv, &format!("failed to invoke capability: {}", e)),
     };
 
-    run_invoke(middlewares, inv, &invoke_operation)
+    run_invoke(
+        middlewares,
+        inv,
+        &invoke_operation,
+        &|m, inv, handler| m.capability_invoke_ctx(inv, handler, ctx),
+        stats,
+    )
 }
 
 pub(crate) fn run_portable_capability_invoke(
     middlewares: &[Box<dyn Middleware>],
     inv: Invocation,
     guest: &WapcHost,
+    ctx: &InvocationContext,
+    stats: &StatsCounters,
 ) -> Result<InvocationResponse> {
-    let invoke_operation = |inv: Invocation| match guest.call(&inv.operation, &inv.msg) {
+    let invoke_operation = |inv: Invocation| match with_origin_chain(&inv.origin_chain, || {
+        guest.call(&inv.operation, &inv.msg)
+    }) {
         Ok(v) => InvocationResponse::success(&inv, v),
         Err(e) => InvocationResponse::error(&inv, &format!("failed to invoke capability: {}", e)),
     };
 
-    run_invoke(middlewares, inv, &invoke_operation)
+    run_invoke(
+        middlewares,
+        inv,
+        &invoke_operation,
+        &|m, inv, handler| m.capability_invoke_ctx(inv, handler, ctx),
+        stats,
+    )
 }
 
 fn run_invoke(
     middlewares: &[Box<dyn Middleware>],
     inv: Invocation,
     invoke_operation: &dyn Fn(Invocation) -> InvocationResponse,
+    invoke_middleware: &dyn Fn(
+        &dyn Middleware,
+        Invocation,
+        InvocationHandler,
+    ) -> Result<MiddlewareResponse>,
+    stats: &StatsCounters,
 ) -> Result<InvocationResponse> {
     let mut cur_resp = Ok(InvocationResponse::error(
         &inv,
@@ -221,12 +708,21 @@ fn run_invoke(
     ));
 
     for m in middlewares.iter() {
-        match m.capability_invoke(inv.clone(), InvocationHandler::new(&invoke_operation)) {
+        match invoke_middleware(
+            m.as_ref(),
+            inv.clone(),
+            InvocationHandler::new(&invoke_operation),
+        ) {
             Ok(mr) => match mr {
                 MiddlewareResponse::Continue(res) => cur_resp = Ok(res),
-                MiddlewareResponse::Halt(res) => return Ok(res),
+                MiddlewareResponse::Halt(res) => {
+                    stats.halted(middlewares, m.as_ref(), &inv);
+                    return Ok(res);
+                }
             },
-            Err(e) => return Err(e),
+            // The middleware may already have made the call, or failed precisely so that it
+            // wouldn't be made, so the error ends the invocation whatever the policy
+            Err(e) => return Err(rejection(m.as_ref(), "invoke", e)),
         }
     }
 
@@ -240,12 +736,18 @@ fn run_invoke(
 pub(crate) fn run_capability_post_invoke(
     resp: InvocationResponse,
     middlewares: &[Box<dyn Middleware>],
+    ctx: &InvocationContext,
+    policy: ErrorPolicy,
 ) -> Result<InvocationResponse> {
     let mut cur_resp = resp;
     for m in middlewares {
-        match m.capability_post_invoke(cur_resp) {
-            Ok(i) => cur_resp = i.clone(),
-            Err(e) => return Err(e),
+        match m.capability_post_invoke_ctx(cur_resp.clone(), ctx) {
+            Ok(i) => cur_resp = i,
+            Err(e) => {
+                if let Some(e) = hook_failed(m.as_ref(), "post-invoke", e, policy) {
+                    return Err(e);
+                }
+            }
         }
     }
     Ok(cur_resp)
@@ -257,7 +759,7 @@ mod tests {
 
     use super::Middleware;
     use crate::inthost::{Invocation, InvocationResponse, WasccEntity};
-    use crate::middleware::{InvocationHandler, MiddlewareResponse};
+    use crate::middleware::{InvocationContext, InvocationHandler, MiddlewareResponse};
     use crate::Result;
     use wascap::prelude::KeyPair;
 
@@ -330,10 +832,445 @@ mod tests {
             "testing",
             b"abc1234".to_vec(),
         );
-        let res = super::run_actor_pre_invoke(inv.clone(), &mids);
+        let ctx = InvocationContext::default();
+        let policy = super::ErrorPolicy::default();
+        let res = super::run_actor_pre_invoke(inv.clone(), &mids, &ctx, policy);
         assert!(res.is_ok());
-        let res2 = super::run_actor_pre_invoke(inv, &mids);
+        let res2 = super::run_actor_pre_invoke(inv, &mids, &ctx, policy);
         assert!(res2.is_ok());
         assert_eq!(PRE.fetch_add(0, Ordering::SeqCst), 2);
     }
+
+    struct RecordingMiddleware {
+        calls: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl RecordingMiddleware {
+        fn record(&self, hook: &str, op: &str) {
+            self.calls.lock().unwrap().push(format!("{}:{}", hook, op));
+        }
+    }
+
+    impl Middleware for RecordingMiddleware {
+        fn actor_pre_invoke(&self, inv: Invocation) -> Result<Invocation> {
+            Ok(inv)
+        }
+        fn actor_invoke(
+            &self,
+            inv: Invocation,
+            handler: InvocationHandler,
+        ) -> Result<MiddlewareResponse> {
+            Ok(MiddlewareResponse::Continue(handler.invoke(inv)))
+        }
+        fn actor_post_invoke(&self, response: InvocationResponse) -> Result<InvocationResponse> {
+            Ok(response)
+        }
+        fn capability_pre_invoke(&self, inv: Invocation) -> Result<Invocation> {
+            self.record("pre", &inv.operation);
+            Ok(inv)
+        }
+        fn capability_invoke(
+            &self,
+            inv: Invocation,
+            _handler: InvocationHandler,
+        ) -> Result<MiddlewareResponse> {
+            self.record("invoke", &inv.operation);
+            Ok(MiddlewareResponse::Halt(InvocationResponse::success(
+                &inv,
+                b"cached".to_vec(),
+            )))
+        }
+        fn capability_post_invoke(
+            &self,
+            response: InvocationResponse,
+        ) -> Result<InvocationResponse> {
+            self.record("post", "");
+            Ok(response)
+        }
+        fn invocation_completed(&self, inv: &Invocation) {
+            self.record("completed", &inv.operation);
+        }
+    }
+
+    #[test]
+    fn scoped_middleware_passes_through() {
+        use super::{MiddlewareScope, ScopedMiddleware};
+        use std::sync::{Arc, Mutex};
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let scoped = ScopedMiddleware::new(
+            RecordingMiddleware {
+                calls: calls.clone(),
+            },
+            MiddlewareScope {
+                target_capids: vec!["wascc:keyvalue".to_string()],
+                operations: vec!["Get*".to_string()],
+                ..Default::default()
+            },
+        );
+        let mids: Vec<Box<dyn Middleware>> = vec![Box::new(scoped)];
+        let hk = KeyPair::new_server();
+        let cap = |capid: &str| WasccEntity::Capability {
+            capid: capid.to_string(),
+            binding: "default".to_string(),
+        };
+        let workload = vec![
+            (cap("wascc:keyvalue"), "GetValue", true),
+            (cap("wascc:keyvalue"), "SetValue", false),
+            (cap("wascc:messaging"), "GetValue", false),
+            (WasccEntity::Actor("Mxxxx".to_string()), "GetValue", false),
+        ];
+        let ctx = InvocationContext::default();
+        let stats = crate::stats::StatsCounters::default();
+        let operation = |inv: Invocation| InvocationResponse::success(&inv, b"live".to_vec());
+
+        for (target, op, in_scope) in workload {
+            let inv = Invocation::new(
+                &hk,
+                WasccEntity::Actor("test".to_string()),
+                target,
+                op,
+                vec![],
+            );
+            let policy = super::ErrorPolicy::default();
+            let inv = super::run_capability_pre_invoke(inv, &mids, &ctx, policy).unwrap();
+            let resp = super::run_invoke(
+                &mids,
+                inv,
+                &operation,
+                &|m, inv, handler| m.capability_invoke_ctx(inv, handler, &ctx),
+                &stats,
+            )
+            .unwrap();
+            let resp = super::run_capability_post_invoke(resp, &mids, &ctx, policy).unwrap();
+            let expected: &[u8] = if in_scope { b"cached" } else { b"live" };
+            assert_eq!(expected, resp.msg.as_slice());
+        }
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["pre:GetValue", "invoke:GetValue", "post:"]
+        );
+        // The scoped middleware is named after the middleware it wraps
+        let halts = stats.snapshot().middleware_halts;
+        assert_eq!(1, halts.len());
+        assert_eq!(1, halts[std::any::type_name::<RecordingMiddleware>()]);
+    }
+
+    #[test]
+    fn scoped_middleware_forgets_rejected_invocations() {
+        use super::{ErrorPolicy, MiddlewareScope, ScopedMiddleware};
+        use std::sync::{Arc, Mutex};
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mids: Vec<Box<dyn Middleware>> = vec![
+            Box::new(ScopedMiddleware::new(
+                RecordingMiddleware {
+                    calls: calls.clone(),
+                },
+                MiddlewareScope {
+                    target_capids: vec!["wascc:keyvalue".to_string()],
+                    ..Default::default()
+                },
+            )),
+            Box::new(FailingMiddleware {
+                failing: vec!["pre"],
+                policy: Some(ErrorPolicy::FailClosed),
+                calls: Default::default(),
+            }),
+        ];
+        let hk = KeyPair::new_server();
+        let inv = Invocation::new(
+            &hk,
+            WasccEntity::Actor("test".to_string()),
+            WasccEntity::Capability {
+                capid: "wascc:keyvalue".to_string(),
+                binding: "default".to_string(),
+            },
+            "GetValue",
+            vec![],
+        );
+        let ctx = InvocationContext::default();
+        let operation = |inv: Invocation| InvocationResponse::success(&inv, vec![]);
+        let resp = super::complete_chain(
+            &mids,
+            inv.clone(),
+            |inv| super::run_capability_pre_invoke(inv, &mids, &ctx, ErrorPolicy::default()),
+            |inv| {
+                super::run_invoke(
+                    &mids,
+                    inv,
+                    &operation,
+                    &|m, inv, h| m.capability_invoke_ctx(inv, h, &ctx),
+                    &Default::default(),
+                )
+            },
+            |resp| super::run_capability_post_invoke(resp, &mids, &ctx, ErrorPolicy::default()),
+        );
+        assert!(resp.error.is_some());
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["pre:GetValue", "completed:GetValue"]
+        );
+
+        // Once the invocation is complete, a response bearing its ID is out of scope
+        let late = InvocationResponse::success(&inv, vec![]);
+        mids[0].capability_post_invoke_ctx(late, &ctx).unwrap();
+        assert_eq!(2, calls.lock().unwrap().len());
+    }
+
+    // Fails whichever hooks are named in `failing`, and records the hooks that run
+    struct FailingMiddleware {
+        failing: Vec<&'static str>,
+        policy: Option<super::ErrorPolicy>,
+        calls: std::sync::Arc<std::sync::Mutex<Vec<&'static str>>>,
+    }
+
+    impl FailingMiddleware {
+        fn hook(&self, hook: &'static str) -> Result<()> {
+            self.calls.lock().unwrap().push(hook);
+            if self.failing.contains(&hook) {
+                Err(crate::errors::new(crate::errors::ErrorKind::Middleware(
+                    "deliberate failure".to_string(),
+                )))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl Middleware for FailingMiddleware {
+        fn actor_pre_invoke(&self, inv: Invocation) -> Result<Invocation> {
+            self.capability_pre_invoke(inv)
+        }
+        fn actor_invoke(
+            &self,
+            inv: Invocation,
+            handler: InvocationHandler,
+        ) -> Result<MiddlewareResponse> {
+            self.capability_invoke(inv, handler)
+        }
+        fn actor_post_invoke(&self, response: InvocationResponse) -> Result<InvocationResponse> {
+            self.capability_post_invoke(response)
+        }
+        fn capability_pre_invoke(&self, mut inv: Invocation) -> Result<Invocation> {
+            self.hook("pre")?;
+            inv.msg = b"altered".to_vec();
+            Ok(inv)
+        }
+        fn capability_invoke(
+            &self,
+            inv: Invocation,
+            handler: InvocationHandler,
+        ) -> Result<MiddlewareResponse> {
+            self.hook("invoke")?;
+            Ok(MiddlewareResponse::Continue(handler.invoke(inv)))
+        }
+        fn capability_post_invoke(
+            &self,
+            response: InvocationResponse,
+        ) -> Result<InvocationResponse> {
+            self.hook("post")?;
+            Ok(response)
+        }
+        fn name(&self) -> &str {
+            "failing"
+        }
+        fn error_policy(&self) -> Option<super::ErrorPolicy> {
+            self.policy
+        }
+    }
+
+    #[test]
+    fn error_policies() {
+        use super::ErrorPolicy;
+        use std::cell::Cell;
+        use std::sync::{Arc, Mutex};
+
+        let hk = KeyPair::new_server();
+        let ctx = InvocationContext::default();
+        // Echoes the payload, so the response shows whether pre-invoke altered it
+        let operations = Cell::new(0);
+        let operation = |inv: Invocation| {
+            operations.set(operations.get() + 1);
+            InvocationResponse::success(&inv, inv.msg.clone())
+        };
+        let run = |failing: Vec<&'static str>,
+                   mid_policy: Option<ErrorPolicy>,
+                   host_policy: ErrorPolicy| {
+            let calls = Arc::new(Mutex::new(Vec::new()));
+            let mids: Vec<Box<dyn Middleware>> = vec![Box::new(FailingMiddleware {
+                failing,
+                policy: mid_policy,
+                calls: calls.clone(),
+            })];
+            let inv = Invocation::new(
+                &hk,
+                WasccEntity::Actor("test".to_string()),
+                WasccEntity::Actor("Mxxxx".to_string()),
+                "Test",
+                b"original".to_vec(),
+            );
+            let resp = super::complete_chain(
+                &mids,
+                inv,
+                |inv| super::run_actor_pre_invoke(inv, &mids, &ctx, host_policy),
+                |inv| {
+                    super::run_invoke(
+                        &mids,
+                        inv,
+                        &operation,
+                        &|m, inv, h| m.actor_invoke_ctx(inv, h, &ctx),
+                        &Default::default(),
+                    )
+                },
+                |resp| super::run_actor_post_invoke(resp, &mids, &ctx, host_policy),
+            );
+            let calls = calls.lock().unwrap().clone();
+            (resp, calls)
+        };
+
+        // Failing open, the failed hook is skipped and the invocation carries on
+        let (resp, calls) = run(vec!["pre"], None, ErrorPolicy::FailOpen);
+        assert!(resp.error.is_none());
+        assert_eq!(b"original".to_vec(), resp.msg);
+        assert_eq!(vec!["pre", "invoke", "post"], calls);
+        let (resp, _) = run(vec!["post"], None, ErrorPolicy::FailOpen);
+        assert!(resp.error.is_none());
+        assert_eq!(b"altered".to_vec(), resp.msg);
+
+        // but a failed invoke hook always rejects the invocation, without making it again
+        operations.set(0);
+        let (resp, calls) = run(vec!["invoke"], None, ErrorPolicy::FailOpen);
+        let error = resp.error.unwrap();
+        assert!(
+            error.contains("failing") && error.contains("invoke"),
+            "{}",
+            error
+        );
+        assert_eq!(vec!["pre", "invoke"], calls);
+        assert_eq!(0, operations.get());
+
+        // Failing closed, the invocation is rejected at the failed hook
+        for (stage, hook, ran) in &[
+            ("pre-invoke", "pre", vec!["pre"]),
+            ("invoke", "invoke", vec!["pre", "invoke"]),
+            ("post-invoke", "post", vec!["pre", "invoke", "post"]),
+        ] {
+            let (resp, calls) = run(vec![hook], None, ErrorPolicy::FailClosed);
+            let error = resp.error.unwrap();
+            assert!(error.contains("failing"), "{}", error);
+            assert!(error.contains(stage), "{}", error);
+            assert!(resp.msg.is_empty());
+            assert_eq!(ran, &calls);
+        }
+
+        // A middleware's own policy overrides the host's
+        let (resp, _) = run(
+            vec!["pre"],
+            Some(ErrorPolicy::FailClosed),
+            ErrorPolicy::FailOpen,
+        );
+        assert!(resp.error.is_some());
+        let (resp, _) = run(
+            vec!["pre"],
+            Some(ErrorPolicy::FailOpen),
+            ErrorPolicy::FailClosed,
+        );
+        assert!(resp.error.is_none());
+    }
+
+    #[test]
+    fn scope_globs() {
+        use super::glob_match;
+        assert!(glob_match(b"Get*", b"GetValue"));
+        assert!(glob_match(b"Get*", b"Get"));
+        assert!(glob_match(b"*Value", b"SetValue"));
+        assert!(glob_match(b"?et*e", b"SetValue"));
+        assert!(glob_match(b"HandleRequest", b"HandleRequest"));
+        assert!(!glob_match(b"Get*", b"SetValue"));
+        assert!(!glob_match(b"?", b""));
+        assert!(!glob_match(b"Handle", b"HandleRequest"));
+    }
+
+    #[test]
+    fn context_gathers_claims_and_binding() {
+        use crate::RouteKey;
+        use std::collections::HashMap;
+        use std::sync::{Arc, RwLock};
+        use wascap::prelude::{Actor, ClaimsBuilder};
+        use wascc_codec::capabilities::CapabilityDescriptor;
+        use wascc_codec::core::CapabilityConfiguration;
+
+        let hk = KeyPair::new_server();
+        let module = KeyPair::new_module();
+        let claims = ClaimsBuilder::<Actor>::new()
+            .issuer(&KeyPair::new_account().public_key())
+            .subject(&module.public_key())
+            .with_metadata(Actor {
+                name: Some("test".to_string()),
+                caps: Some(vec!["testing:sample".to_string()]),
+                ..Default::default()
+            })
+            .build();
+        let mut values = HashMap::new();
+        values.insert("KEY".to_string(), "value".to_string());
+
+        let claimsmap = Arc::new(RwLock::new(HashMap::new()));
+        claimsmap
+            .write()
+            .unwrap()
+            .insert(module.public_key(), claims);
+        let caps = Arc::new(RwLock::new(HashMap::new()));
+        caps.write().unwrap().insert(
+            RouteKey::new("default", "testing:sample"),
+            CapabilityDescriptor::builder()
+                .id("testing:sample")
+                .name("sample")
+                .build(),
+        );
+        let bindings = Arc::new(RwLock::new(crate::bindings::Bindings::default()));
+        bindings.write().unwrap().insert(
+            &module.public_key(),
+            "testing:sample",
+            "default",
+            CapabilityConfiguration {
+                module: module.public_key(),
+                values: values.clone(),
+            },
+        );
+
+        let inv = Invocation::new(
+            &hk,
+            WasccEntity::Actor(module.public_key()),
+            WasccEntity::Capability {
+                capid: "testing:sample".to_string(),
+                binding: "default".to_string(),
+            },
+            "testing",
+            vec![],
+        );
+        let ctx = InvocationContext::gather(&inv, &claimsmap, &caps, &bindings);
+        assert_eq!(
+            ctx.origin_claims.unwrap().subject,
+            module.public_key().to_string()
+        );
+        assert_eq!(ctx.target_descriptor.unwrap().id, "testing:sample");
+        assert_eq!(ctx.binding_values, Some(values));
+
+        // Same binding applies when the provider is the one calling the actor
+        let inv = Invocation::new(
+            &hk,
+            WasccEntity::Capability {
+                capid: "testing:sample".to_string(),
+                binding: "default".to_string(),
+            },
+            WasccEntity::Actor(module.public_key()),
+            "testing",
+            vec![],
+        );
+        let ctx = InvocationContext::gather(&inv, &claimsmap, &caps, &bindings);
+        assert!(ctx.origin_claims.is_none());
+        assert!(ctx.target_descriptor.is_none());
+        assert!(ctx.binding_values.is_some());
+    }
 }
@@ -18,14 +18,33 @@
 //!     metrics_server_addr: Some(server_addr),
 //!     pushgateway_config: None,
 //!     moving_average_window_size: None,
+//!     max_operation_labels: None,
+//!     legacy_metric_names: false,
 //! };
 //! let middleware = wascc_host::middleware::prometheus::PrometheusMiddleware::new(config).unwrap();
 //! ```
 //!
 //! This will expose metrics at `http://127.0.0.1:9898/metrics`. This can be
-//! used as a scraping target in [Prometheus][prometheus].
+//! used as a scraping target in [Prometheus][prometheus]. If the address can't be bound, e.g.
+//! because the port is already in use, `new` returns an error. `server_addr` returns the address
+//! actually bound, which is useful with port 0, and when pushing to a Pushgateway, `push_health`
+//! reports whether the pushes are succeeding.
 //!
-//! All metrics are prefixed with 'wascc_'.
+//! All metrics are prefixed with 'wascc_'. Invocation counts and average invocation times are
+//! labeled with the `actor`, or the `capid` and `binding`, that was invoked and, for the
+//! per-operation metrics, the `operation`, e.g. `wascc_actor_operation_inv_count{actor="M...",
+//! operation="HandleRequest"}`. Label values are restricted to ASCII letters, digits and
+//! `_:.-/`, other characters being replaced with `_`. Only the first `max_operation_labels`
+//! operations seen for each actor or capability get their own label; invocations of any further
+//! operations are counted under the operation `other`, so that arbitrary operation names can't
+//! grow the registry without bound. The distributions of invocation times are recorded in the
+//! histograms `wascc_actor_inv_seconds` and `wascc_cap_inv_seconds`, labeled the same way but
+//! without the operation.
+//!
+//! Earlier releases registered a separate metric for each actor, capability and operation, e.g.
+//! `wascc_{actor}_{operation}_inv_count`. Setting `legacy_metric_names` registers these as well,
+//! for dashboards that haven't moved to the labeled metrics yet. It will be removed in the next
+//! release.
 //!
 //! Here is a simple [Prometheus][prometheus] configuration that scrapes the above target and
 //! the [Prometheus Pushgateway][prometheus_pushgateway] (save the file as `prometheus.yml`):
@@ -90,60 +109,125 @@
 //! [docker_compose]: https://docs.docker.com/compose/
 //! [grafana]: https://grafana.com/
 
+use crate::locks::RwLockExt;
 use crate::middleware::{InvocationHandler, MiddlewareResponse};
 use crate::{errors, Invocation, InvocationResponse, Middleware, Result, WasccEntity};
 use hyper::header::CONTENT_TYPE;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Request, Response, Server, StatusCode};
-use prometheus::{labels, Encoder, Gauge, IntCounter, Registry, TextEncoder};
+use prometheus::{
+    labels, Encoder, Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter,
+    IntCounterVec, Opts, Registry, TextEncoder,
+};
 use std::cmp::min;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
-use std::sync::{Arc, RwLock, RwLockWriteGuard};
+use std::sync::{Arc, RwLock};
 use std::thread::JoinHandle;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 // The default number of invocations to include when calculating average invocation times
 const DEFAULT_MOVING_AVERAGE_WINDOW_SIZE: i64 = 100;
+// The default number of distinct operations labeled for each actor or capability
+const DEFAULT_MAX_OPERATION_LABELS: usize = 50;
+// The operation label for invocations beyond an actor's or capability's operation labels
+const OTHER_OPERATION: &str = "other";
+const MAX_LABEL_VALUE_LEN: usize = 128;
 const WASCC: &str = "wascc";
 
 /// A Prometheus middleware that can serve or push metrics.
 pub struct PrometheusMiddleware {
     metrics: Arc<RwLock<Metrics>>,
     registry: Arc<RwLock<Registry>>,
+    metrics_server_addr: Option<SocketAddr>,
     metrics_server_handle: Option<JoinHandle<()>>,
     metrics_server_kill_switch: Option<tokio::sync::oneshot::Sender<()>>,
+    push_health: Option<Arc<RwLock<PushHealth>>>,
     metrics_push_handle: Option<JoinHandle<()>>,
     metrics_push_kill_switch: Option<tokio::sync::oneshot::Sender<()>>,
 }
 
+/// The outcome of the middleware's recent pushes of metrics to the Pushgateway, as returned by
+/// `PrometheusMiddleware::push_health`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PushHealth {
+    /// The time of the most recent successful push, if any
+    pub last_success: Option<SystemTime>,
+    /// The error from the most recent push, if it failed
+    pub last_error: Option<String>,
+    /// The number of pushes that have failed in a row
+    pub consecutive_failures: u32,
+}
+
+impl PushHealth {
+    /// Whether the most recent push succeeded. This is `false` until the first push completes
+    pub fn is_healthy(&self) -> bool {
+        self.last_success.is_some() && self.consecutive_failures == 0
+    }
+
+    fn record(&mut self, res: std::result::Result<(), String>) {
+        match res {
+            Ok(()) => {
+                self.last_success = Some(SystemTime::now());
+                self.last_error = None;
+                self.consecutive_failures = 0;
+            }
+            Err(e) => {
+                self.last_error = Some(e);
+                self.consecutive_failures += 1;
+            }
+        }
+    }
+}
+
 /// Holds all the different metrics collected by the middleware.
 struct Metrics {
     /// Total number of invocations of capabilities
     cap_total_inv_count: IntCounter,
     /// Number of invocations per capability
-    cap_inv_count: HashMap<String, IntCounter>,
+    cap_inv_count: IntCounterVec,
     /// Number of invocations per operation on each capability
-    cap_operation_inv_count: HashMap<String, IntCounter>,
+    cap_operation_inv_count: IntCounterVec,
     /// Average invocation time across all capabilities
     cap_total_average_inv_time: Gauge,
     /// Average invocation time per capability
-    cap_average_inv_time: HashMap<String, Gauge>,
+    cap_average_inv_time: GaugeVec,
     /// Average invocation time per operation on each capability
-    cap_operation_average_inv_time: HashMap<String, Gauge>,
+    cap_operation_average_inv_time: GaugeVec,
+    /// Distribution of invocation times per capability
+    cap_inv_seconds: HistogramVec,
 
     /// Total number of invocations of actors
     actor_total_inv_count: IntCounter,
     /// Number of invocations per actor
-    actor_inv_count: HashMap<String, IntCounter>,
+    actor_inv_count: IntCounterVec,
     /// Number of invocations per operation on each actor
-    actor_operation_inv_count: HashMap<String, IntCounter>,
+    actor_operation_inv_count: IntCounterVec,
     /// Average invocation time across all actors
     actor_total_average_inv_time: Gauge,
     /// Average invocation time per actor
-    actor_average_inv_time: HashMap<String, Gauge>,
+    actor_average_inv_time: GaugeVec,
     /// Average invocation time per operation on each actor
-    actor_operation_average_inv_time: HashMap<String, Gauge>,
+    actor_operation_average_inv_time: GaugeVec,
+    /// Distribution of invocation times per actor
+    actor_inv_seconds: HistogramVec,
+
+    /// Time taken by actors to become ready for invocations
+    actor_start_seconds: Histogram,
+    /// Time taken by native capability providers to become ready for invocations
+    provider_start_seconds: Histogram,
+
+    /// Calls refused by the host's authorization checks, by reason
+    authz_denials_total: IntCounterVec,
+    /// Invocations answered by a middleware halting the pipeline, by middleware
+    middleware_halts_total: IntCounterVec,
+
+    /// The operation labels given out for each capability or actor
+    operation_labels: HashMap<String, HashSet<String>>,
+    max_operation_labels: usize,
+
+    /// The per-entity metrics under their old names, if enabled
+    legacy: Option<LegacyMetrics>,
 
     /// State of active invocations
     active_inv_state: HashMap<String, InvocationState>,
@@ -151,16 +235,39 @@ struct Metrics {
     moving_average_window_size: i64,
 }
 
+/// The per-entity metrics as registered by earlier releases, one metric per capability, actor
+/// and operation. Kept for `PrometheusConfig::legacy_metric_names`
+#[derive(Default)]
+struct LegacyMetrics {
+    cap_inv_count: HashMap<String, IntCounter>,
+    cap_operation_inv_count: HashMap<String, IntCounter>,
+    cap_average_inv_time: HashMap<String, Gauge>,
+    cap_operation_average_inv_time: HashMap<String, Gauge>,
+    actor_inv_count: HashMap<String, IntCounter>,
+    actor_operation_inv_count: HashMap<String, IntCounter>,
+    actor_average_inv_time: HashMap<String, Gauge>,
+    actor_operation_average_inv_time: HashMap<String, Gauge>,
+}
+
 /// Configuration parameters.
 #[derive(Clone)]
 pub struct PrometheusConfig {
-    /// The address that Prometheus can scrape (pull model).
+    /// The address that Prometheus can scrape (pull model). Port 0 binds an available port,
+    /// which `PrometheusMiddleware::server_addr` reports.
     pub metrics_server_addr: Option<SocketAddr>,
     /// Configuration for the Prometheus client (push model).
     pub pushgateway_config: Option<PushgatewayConfig>,
     /// The number of invocations to include when calculating all average invocation times.
     /// The default is 100.
     pub moving_average_window_size: Option<i64>,
+    /// The number of distinct operations that get their own label for each actor and
+    /// capability. Invocations of any further operations are counted under the operation
+    /// `other`. The default is 50.
+    pub max_operation_labels: Option<usize>,
+    /// Also registers the per-actor, per-capability and per-operation metrics under the names
+    /// used by earlier releases, e.g. `wascc_{actor}_inv_count`. This will be removed in the
+    /// next release.
+    pub legacy_metric_names: bool,
 }
 
 /// Configuration parameters for pushing metrics to the Pushgateway.
@@ -188,9 +295,14 @@ struct InvocationState {
     metric_key: String,
     /// Key to find metrics for an operation on a capability or an actor
     operation_metric_key: String,
+    /// The operation's label, `other` if the target was out of operation labels
+    operation_label: String,
 }
 
 impl PrometheusMiddleware {
+    /// Creates the middleware, starting the metrics server and the pushing of metrics as
+    /// configured. The metrics server's address is bound before this returns, so an address
+    /// that's already in use is reported here rather than only logged
     pub fn new(config: PrometheusConfig) -> Result<Self>
     where
         Self: Send + Sync,
@@ -200,9 +312,16 @@ impl PrometheusMiddleware {
         let metrics = Arc::new(RwLock::new(metrics));
         let registry = Arc::new(RwLock::new(registry));
 
-        let (metrics_server_handle, metrics_server_kill_switch) =
+        let (metrics_server_addr, metrics_server_handle, metrics_server_kill_switch) =
             if let Some(addr) = config.metrics_server_addr {
-                let (metrics_server_kill_switch, mut server_kill_switch_rx) =
+                let listener = std::net::TcpListener::bind(addr).map_err(|e| {
+                    errors::new(errors::ErrorKind::Middleware(format!(
+                        "Failed to bind the metrics server to {}: {}",
+                        addr, e
+                    )))
+                })?;
+                let bound_addr = listener.local_addr()?;
+                let (metrics_server_kill_switch, server_kill_switch_rx) =
                     tokio::sync::oneshot::channel();
                 let registry2 = registry.clone();
 
@@ -210,52 +329,93 @@ impl PrometheusMiddleware {
                     let mut rt =
                         tokio::runtime::Runtime::new().expect("Failed to create tokio runtime");
                     rt.block_on(async {
-                        tokio::select! {
-                            _ = serve_metrics(addr, registry2) => {}
-                            _ = (&mut server_kill_switch_rx) => {}
-                        }
+                        let stopped = async {
+                            let _ = server_kill_switch_rx.await;
+                        };
+                        serve_metrics(listener, registry2, stopped).await
                     })
                 });
+                info!("Serving metrics on {}", bound_addr);
 
-                (Some(thread_handle), Some(metrics_server_kill_switch))
+                (
+                    Some(bound_addr),
+                    Some(thread_handle),
+                    Some(metrics_server_kill_switch),
+                )
             } else {
-                (None, None)
+                (None, None, None)
             };
 
-        let (metrics_push_handle, metrics_push_kill_switch) =
+        let (push_health, metrics_push_handle, metrics_push_kill_switch) =
             if let Some(push_config) = config.pushgateway_config {
                 let (metrics_push_kill_switch, metrics_push_kill_switch_rx) =
                     tokio::sync::oneshot::channel();
                 let registry2 = registry.clone();
+                let health = Arc::new(RwLock::new(PushHealth::default()));
+                let health2 = health.clone();
 
                 // need a separate thread for pushing metrics because the reqwest sync client
                 // is used in the prometheus library. reqwest sync creates a tokio runtime internally
                 // so the tokio runtime created for the metrics server can't be reused.
                 let thread_handle = std::thread::spawn(move || {
-                    push_metrics(registry2, push_config, metrics_push_kill_switch_rx);
+                    push_metrics(registry2, push_config, health2, metrics_push_kill_switch_rx);
                 });
 
-                (Some(thread_handle), Some(metrics_push_kill_switch))
+                (
+                    Some(health),
+                    Some(thread_handle),
+                    Some(metrics_push_kill_switch),
+                )
             } else {
-                (None, None)
+                (None, None, None)
             };
 
         Ok(Self {
             metrics,
             registry,
+            metrics_server_addr,
             metrics_server_handle,
             metrics_server_kill_switch,
+            push_health,
             metrics_push_handle,
             metrics_push_kill_switch,
         })
     }
 
+    /// The address the metrics server is listening on, which differs from the configured one
+    /// if that had port 0. `None` if no metrics server was configured
+    pub fn server_addr(&self) -> Option<SocketAddr> {
+        self.metrics_server_addr
+    }
+
+    /// The outcome of the recent pushes of metrics to the Pushgateway, so that connectivity
+    /// problems can be noticed. `None` if pushing wasn't configured
+    pub fn push_health(&self) -> Option<PushHealth> {
+        self.push_health
+            .as_ref()
+            .map(|h| h.read_or_recover().clone())
+    }
+
     fn init_registry(metrics: &Metrics) -> Result<Registry> {
         let registry = Registry::new();
         registry.register(Box::new(metrics.cap_total_inv_count.clone()))?;
         registry.register(Box::new(metrics.cap_total_average_inv_time.clone()))?;
+        registry.register(Box::new(metrics.cap_inv_count.clone()))?;
+        registry.register(Box::new(metrics.cap_operation_inv_count.clone()))?;
+        registry.register(Box::new(metrics.cap_average_inv_time.clone()))?;
+        registry.register(Box::new(metrics.cap_operation_average_inv_time.clone()))?;
+        registry.register(Box::new(metrics.cap_inv_seconds.clone()))?;
         registry.register(Box::new(metrics.actor_total_inv_count.clone()))?;
         registry.register(Box::new(metrics.actor_total_average_inv_time.clone()))?;
+        registry.register(Box::new(metrics.actor_inv_count.clone()))?;
+        registry.register(Box::new(metrics.actor_operation_inv_count.clone()))?;
+        registry.register(Box::new(metrics.actor_average_inv_time.clone()))?;
+        registry.register(Box::new(metrics.actor_operation_average_inv_time.clone()))?;
+        registry.register(Box::new(metrics.actor_inv_seconds.clone()))?;
+        registry.register(Box::new(metrics.actor_start_seconds.clone()))?;
+        registry.register(Box::new(metrics.provider_start_seconds.clone()))?;
+        registry.register(Box::new(metrics.authz_denials_total.clone()))?;
+        registry.register(Box::new(metrics.middleware_halts_total.clone()))?;
         Ok(registry)
     }
 
@@ -265,27 +425,126 @@ impl PrometheusMiddleware {
                 format!("{}_cap_total_inv_count", WASCC),
                 "Total number of capability invocations".to_owned(),
             )?,
-            cap_inv_count: HashMap::new(),
-            cap_operation_inv_count: HashMap::new(),
+            cap_inv_count: IntCounterVec::new(
+                Opts::new(
+                    format!("{}_cap_inv_count", WASCC),
+                    "Number of invocations of each capability".to_owned(),
+                ),
+                &["capid", "binding"],
+            )?,
+            cap_operation_inv_count: IntCounterVec::new(
+                Opts::new(
+                    format!("{}_cap_operation_inv_count", WASCC),
+                    "Number of invocations of each operation on each capability".to_owned(),
+                ),
+                &["capid", "binding", "operation"],
+            )?,
             cap_total_average_inv_time: Gauge::new(
                 format!("{}_cap_total_average_inv_time", WASCC),
                 "Average invocation time (ms) across all capabilities".to_owned(),
             )?,
-            cap_average_inv_time: HashMap::new(),
-            cap_operation_average_inv_time: HashMap::new(),
+            cap_average_inv_time: GaugeVec::new(
+                Opts::new(
+                    format!("{}_cap_average_inv_time", WASCC),
+                    "Average time (ms) to invoke each capability".to_owned(),
+                ),
+                &["capid", "binding"],
+            )?,
+            cap_operation_average_inv_time: GaugeVec::new(
+                Opts::new(
+                    format!("{}_cap_operation_average_inv_time", WASCC),
+                    "Average time (ms) to invoke each operation on each capability".to_owned(),
+                ),
+                &["capid", "binding", "operation"],
+            )?,
+            cap_inv_seconds: HistogramVec::new(
+                HistogramOpts::new(
+                    format!("{}_cap_inv_seconds", WASCC),
+                    "Time (s) taken to invoke each capability".to_owned(),
+                ),
+                &["capid", "binding"],
+            )?,
 
             actor_total_inv_count: IntCounter::new(
                 format!("{}_actor_total_inv_count", WASCC),
                 "Total number of actor invocations".to_owned(),
             )?,
-            actor_inv_count: HashMap::new(),
-            actor_operation_inv_count: HashMap::new(),
+            actor_inv_count: IntCounterVec::new(
+                Opts::new(
+                    format!("{}_actor_inv_count", WASCC),
+                    "Number of invocations of each actor".to_owned(),
+                ),
+                &["actor"],
+            )?,
+            actor_operation_inv_count: IntCounterVec::new(
+                Opts::new(
+                    format!("{}_actor_operation_inv_count", WASCC),
+                    "Number of invocations of each operation on each actor".to_owned(),
+                ),
+                &["actor", "operation"],
+            )?,
             actor_total_average_inv_time: Gauge::new(
                 format!("{}_actor_total_average_inv_time", WASCC),
                 "Average invocation time (ms) across all actors".to_owned(),
             )?,
-            actor_average_inv_time: HashMap::new(),
-            actor_operation_average_inv_time: HashMap::new(),
+            actor_average_inv_time: GaugeVec::new(
+                Opts::new(
+                    format!("{}_actor_average_inv_time", WASCC),
+                    "Average time (ms) to invoke each actor".to_owned(),
+                ),
+                &["actor"],
+            )?,
+            actor_operation_average_inv_time: GaugeVec::new(
+                Opts::new(
+                    format!("{}_actor_operation_average_inv_time", WASCC),
+                    "Average time (ms) to invoke each operation on each actor".to_owned(),
+                ),
+                &["actor", "operation"],
+            )?,
+            actor_inv_seconds: HistogramVec::new(
+                HistogramOpts::new(
+                    format!("{}_actor_inv_seconds", WASCC),
+                    "Time (s) taken to invoke each actor".to_owned(),
+                ),
+                &["actor"],
+            )?,
+
+            actor_start_seconds: Histogram::with_opts(HistogramOpts::new(
+                format!("{}_actor_start_seconds", WASCC),
+                "Time (s) taken by actors to become ready for invocations".to_owned(),
+            ))?,
+            provider_start_seconds: Histogram::with_opts(HistogramOpts::new(
+                format!("{}_provider_start_seconds", WASCC),
+                "Time (s) taken by native capability providers to become ready for invocations"
+                    .to_owned(),
+            ))?,
+
+            authz_denials_total: IntCounterVec::new(
+                Opts::new(
+                    format!("{}_authz_denials_total", WASCC),
+                    "Number of calls refused by the host's authorization checks".to_owned(),
+                ),
+                &["reason"],
+            )?,
+            middleware_halts_total: IntCounterVec::new(
+                Opts::new(
+                    format!("{}_middleware_halts_total", WASCC),
+                    "Number of invocations answered by a middleware halting the pipeline"
+                        .to_owned(),
+                ),
+                &["middleware"],
+            )?,
+
+            operation_labels: HashMap::new(),
+            max_operation_labels: config
+                .max_operation_labels
+                .unwrap_or(DEFAULT_MAX_OPERATION_LABELS),
+
+            legacy: if config.legacy_metric_names {
+                Some(LegacyMetrics::default())
+            } else {
+                None
+            },
 
             active_inv_state: HashMap::new(),
             moving_average_window_size: config
@@ -297,8 +556,9 @@ impl PrometheusMiddleware {
 
 impl Middleware for PrometheusMiddleware {
     fn actor_pre_invoke(&self, inv: Invocation) -> Result<Invocation> {
-        pre_invoke_count_inv(&self.metrics, &self.registry, &inv.target, &inv.operation);
-        pre_invoke_measure_inv_time(&self.metrics, &inv);
+        let operation_label =
+            pre_invoke_count_inv(&self.metrics, &self.registry, &inv.target, &inv.operation);
+        pre_invoke_measure_inv_time(&self.metrics, &inv, operation_label);
         Ok(inv)
     }
 
@@ -316,8 +576,9 @@ impl Middleware for PrometheusMiddleware {
     }
 
     fn capability_pre_invoke(&self, inv: Invocation) -> Result<Invocation> {
-        pre_invoke_count_inv(&self.metrics, &self.registry, &inv.target, &inv.operation);
-        pre_invoke_measure_inv_time(&self.metrics, &inv);
+        let operation_label =
+            pre_invoke_count_inv(&self.metrics, &self.registry, &inv.target, &inv.operation);
+        pre_invoke_measure_inv_time(&self.metrics, &inv, operation_label);
         Ok(inv)
     }
 
@@ -333,6 +594,36 @@ impl Middleware for PrometheusMiddleware {
         post_invoke_measure_inv_time(&self.metrics, &self.registry, &response);
         Ok(response)
     }
+
+    fn actor_started(&self, _actor: &str, start_duration: Duration) {
+        let metrics = self.metrics.read_or_recover();
+        metrics
+            .actor_start_seconds
+            .observe(start_duration.as_secs_f64());
+    }
+
+    fn capability_started(&self, _capid: &str, _binding: &str, load_duration: Duration) {
+        let metrics = self.metrics.read_or_recover();
+        metrics
+            .provider_start_seconds
+            .observe(load_duration.as_secs_f64());
+    }
+
+    fn authorization_denied(&self, _origin: &str, _target: &WasccEntity, reason: &str) {
+        let metrics = self.metrics.read_or_recover();
+        metrics
+            .authz_denials_total
+            .with_label_values(&[reason])
+            .inc();
+    }
+
+    fn invocation_halted(&self, middleware: &str, _inv: &Invocation) {
+        let metrics = self.metrics.read_or_recover();
+        metrics
+            .middleware_halts_total
+            .with_label_values(&[middleware])
+            .inc();
+    }
 }
 
 impl From<prometheus::Error> for errors::Error {
@@ -355,13 +646,54 @@ fn pre_invoke_count_inv(
     registry: &Arc<RwLock<Registry>>,
     target: &WasccEntity,
     operation: &str,
-) {
-    let mut metrics = metrics.write().unwrap();
+) -> String {
+    let mut metrics = metrics.write_or_recover();
+    let metrics = &mut *metrics;
+
+    let operation_label = get_operation_label(metrics, target, operation);
+    let labels = get_labels(target);
+    let operation_labels = with_operation(&labels, &operation_label);
 
     match target {
-        WasccEntity::Actor(actor) => {
+        WasccEntity::Actor(_) => {
             metrics.actor_total_inv_count.inc();
+            metrics
+                .actor_inv_count
+                .with_label_values(&as_strs(&labels))
+                .inc();
+            metrics
+                .actor_operation_inv_count
+                .with_label_values(&as_strs(&operation_labels))
+                .inc();
+        }
+        WasccEntity::Capability { .. } => {
+            metrics.cap_total_inv_count.inc();
+            metrics
+                .cap_inv_count
+                .with_label_values(&as_strs(&labels))
+                .inc();
+            metrics
+                .cap_operation_inv_count
+                .with_label_values(&as_strs(&operation_labels))
+                .inc();
+        }
+    }
+
+    if let Some(legacy) = metrics.legacy.as_mut() {
+        legacy_count_inv(legacy, registry, target, operation);
+    }
 
+    operation_label
+}
+
+fn legacy_count_inv(
+    metrics: &mut LegacyMetrics,
+    registry: &Arc<RwLock<Registry>>,
+    target: &WasccEntity,
+    operation: &str,
+) {
+    match target {
+        WasccEntity::Actor(actor) => {
             let actor_key = get_metric_key(target);
             if let Some(value) = metrics.actor_inv_count.get(&actor_key) {
                 value.inc();
@@ -396,8 +728,6 @@ fn pre_invoke_count_inv(
             }
         }
         WasccEntity::Capability { capid, binding } => {
-            metrics.cap_total_inv_count.inc();
-
             let cap_key = get_metric_key(target);
             if let Some(value) = metrics.cap_inv_count.get(&cap_key) {
                 value.inc();
@@ -449,7 +779,7 @@ fn register_counter(
         }
     };
 
-    let reg = registry.write().unwrap();
+    let reg = registry.write_or_recover();
     if let Err(e) = reg.register(Box::new(counter.clone())) {
         error!("Error registering counter '{}': {}", &name, e);
     }
@@ -468,14 +798,75 @@ fn get_metric_key(target: &WasccEntity) -> String {
     }
 }
 
-fn pre_invoke_measure_inv_time(metrics: &Arc<RwLock<Metrics>>, inv: &Invocation) {
-    let mut metrics = metrics.write().unwrap();
+// The label values identifying the actor, or the capability and binding
+fn get_labels(target: &WasccEntity) -> Vec<String> {
+    match target {
+        WasccEntity::Actor(actor) => vec![sanitize_label_value(actor)],
+        WasccEntity::Capability { capid, binding } => {
+            vec![sanitize_label_value(capid), sanitize_label_value(binding)]
+        }
+    }
+}
+
+fn with_operation(labels: &[String], operation_label: &str) -> Vec<String> {
+    let mut labels = labels.to_vec();
+    labels.push(operation_label.to_string());
+    labels
+}
+
+fn as_strs(labels: &[String]) -> Vec<&str> {
+    labels.iter().map(String::as_str).collect()
+}
+
+// Gives the operation a label of its own until the target has used up its operation labels,
+// after which new operations are counted as `other`
+fn get_operation_label(metrics: &mut Metrics, target: &WasccEntity, operation: &str) -> String {
+    let label = sanitize_label_value(operation);
+    let max_operation_labels = metrics.max_operation_labels;
+    let labels = metrics
+        .operation_labels
+        .entry(get_metric_key(target))
+        .or_insert_with(HashSet::new);
+
+    if labels.contains(&label) {
+        label
+    } else if labels.len() < max_operation_labels {
+        labels.insert(label.clone());
+        label
+    } else {
+        OTHER_OPERATION.to_string()
+    }
+}
+
+// Label values may be any UTF-8, but operation names and the like are kept to a predictable
+// character set and length so they're easy to query and can't bloat the exposition
+fn sanitize_label_value(value: &str) -> String {
+    value
+        .chars()
+        .take(MAX_LABEL_VALUE_LEN)
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "_:.-/".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn pre_invoke_measure_inv_time(
+    metrics: &Arc<RwLock<Metrics>>,
+    inv: &Invocation,
+    operation_label: String,
+) {
+    let mut metrics = metrics.write_or_recover();
     let state = InvocationState {
         start_time: Instant::now(),
         operation: inv.operation.clone(),
         target: inv.target.clone(),
         metric_key: get_metric_key(&inv.target),
         operation_metric_key: get_operation_metric_key(&inv.target, &inv.operation),
+        operation_label,
     };
 
     if metrics
@@ -492,13 +883,14 @@ fn post_invoke_measure_inv_time(
     registry: &Arc<RwLock<Registry>>,
     response: &InvocationResponse,
 ) {
-    let mut metrics = metrics.write().unwrap();
+    let mut metrics = metrics.write_or_recover();
+    let metrics = &mut *metrics;
     let inv_end_time = Instant::now();
 
     // get the state for this invocation
     if let Some(state) = metrics.active_inv_state.remove(&response.invocation_id) {
-        let inv_time = if let Some(time) = inv_end_time.checked_duration_since(state.start_time) {
-            time.as_millis()
+        let elapsed = if let Some(time) = inv_end_time.checked_duration_since(state.start_time) {
+            time
         } else {
             error!(
                 "Unable to compute invocation time for id '{}'",
@@ -506,132 +898,210 @@ fn post_invoke_measure_inv_time(
             );
             return;
         };
+        let inv_time = elapsed.as_millis();
 
-        set_new_total_avg(&metrics, &state.target, inv_time);
+        set_new_total_avg(metrics, &state.target, inv_time);
+
+        let labels = get_labels(&state.target);
+        let operation_labels = with_operation(&labels, &state.operation_label);
+        let window_size = metrics.moving_average_window_size;
 
         // was an actor or a capability invoked?
         match &state.target {
-            WasccEntity::Actor(actor) => {
-                if let Some(gauge) = metrics.actor_average_inv_time.get(&state.metric_key) {
-                    set_gauge_avg(
-                        gauge,
-                        &metrics.actor_inv_count,
-                        metrics.moving_average_window_size,
-                        inv_time,
-                        &state.metric_key,
-                    );
-                } else {
-                    let name = format!("{}_{}_average_inv_time", WASCC, actor.clone());
-                    let help = format!("Average time (ms) to invoke actor '{}'", actor.clone());
-
-                    register_gauge(
-                        registry,
-                        &mut metrics.actor_average_inv_time,
-                        &state.metric_key,
-                        &name,
-                        &help,
-                        inv_time,
-                    );
-                }
-
-                if let Some(gauge) = metrics
-                    .actor_operation_average_inv_time
-                    .get(&state.operation_metric_key)
-                {
-                    set_gauge_avg(
-                        gauge,
-                        &metrics.actor_operation_inv_count,
-                        metrics.moving_average_window_size,
-                        inv_time,
-                        &state.operation_metric_key,
-                    );
-                } else {
-                    let name = format!(
-                        "{}_{}_{}_average_inv_time",
-                        WASCC,
-                        actor.clone(),
-                        &state.operation
-                    );
-                    let help = format!(
-                        "Average time (ms) to invoke operation '{}' on actor '{}'",
-                        &state.operation,
-                        actor.clone()
-                    );
-
-                    register_gauge(
-                        registry,
-                        &mut metrics.actor_operation_average_inv_time,
-                        &state.operation_metric_key,
-                        &name,
-                        &help,
-                        inv_time,
-                    );
-                }
+            WasccEntity::Actor(_) => {
+                metrics
+                    .actor_inv_seconds
+                    .with_label_values(&as_strs(&labels))
+                    .observe(elapsed.as_secs_f64());
+                set_gauge_avg(
+                    &metrics.actor_average_inv_time,
+                    &metrics.actor_inv_count,
+                    &labels,
+                    window_size,
+                    inv_time,
+                );
+                set_gauge_avg(
+                    &metrics.actor_operation_average_inv_time,
+                    &metrics.actor_operation_inv_count,
+                    &operation_labels,
+                    window_size,
+                    inv_time,
+                );
             }
-            WasccEntity::Capability { capid, binding } => {
-                if let Some(gauge) = metrics.cap_average_inv_time.get(&state.metric_key) {
-                    set_gauge_avg(
-                        gauge,
-                        &metrics.cap_inv_count,
-                        metrics.moving_average_window_size,
-                        inv_time,
-                        &state.metric_key,
-                    );
-                } else {
-                    let name = format!("{}_{}_{}_average_inv_time", WASCC, capid, binding);
-                    let help = format!(
-                        "Average time (ms) to invoke capability '{}' with binding '{}'",
-                        capid, binding
-                    );
-
-                    register_gauge(
-                        registry,
-                        &mut metrics.cap_average_inv_time,
-                        &state.metric_key,
-                        &name,
-                        &help,
-                        inv_time,
-                    );
-                }
-
-                if let Some(gauge) = metrics
-                    .cap_operation_average_inv_time
-                    .get(&state.operation_metric_key)
-                {
-                    set_gauge_avg(
-                        gauge,
-                        &metrics.cap_operation_inv_count,
-                        metrics.moving_average_window_size,
-                        inv_time,
-                        &state.operation_metric_key,
-                    );
-                } else {
-                    let name = format!(
-                        "{}_{}_{}_{}_average_inv_time",
-                        WASCC, capid, binding, &state.operation
-                    );
-                    let help = format!(
-                        "Average time (ms) to invoke operation '{}' on capability '{}' with binding '{}'",
-                        &state.operation, capid, binding
-                    );
-
-                    register_gauge(
-                        registry,
-                        &mut metrics.cap_operation_average_inv_time,
-                        &state.operation_metric_key,
-                        &name,
-                        &help,
-                        inv_time,
-                    );
-                }
+            WasccEntity::Capability { .. } => {
+                metrics
+                    .cap_inv_seconds
+                    .with_label_values(&as_strs(&labels))
+                    .observe(elapsed.as_secs_f64());
+                set_gauge_avg(
+                    &metrics.cap_average_inv_time,
+                    &metrics.cap_inv_count,
+                    &labels,
+                    window_size,
+                    inv_time,
+                );
+                set_gauge_avg(
+                    &metrics.cap_operation_average_inv_time,
+                    &metrics.cap_operation_inv_count,
+                    &operation_labels,
+                    window_size,
+                    inv_time,
+                );
             }
         }
+
+        if let Some(legacy) = metrics.legacy.as_mut() {
+            legacy_measure_inv_time(legacy, registry, window_size, &state, inv_time);
+        }
     } else {
         error!("No active invocation with id '{}'", &response.invocation_id);
     }
 }
 
+fn legacy_measure_inv_time(
+    metrics: &mut LegacyMetrics,
+    registry: &Arc<RwLock<Registry>>,
+    moving_average_window_size: i64,
+    state: &InvocationState,
+    inv_time: u128,
+) {
+    match &state.target {
+        WasccEntity::Actor(actor) => {
+            if let Some(gauge) = metrics.actor_average_inv_time.get(&state.metric_key) {
+                set_legacy_gauge_avg(
+                    gauge,
+                    &metrics.actor_inv_count,
+                    moving_average_window_size,
+                    inv_time,
+                    &state.metric_key,
+                );
+            } else {
+                let name = format!("{}_{}_average_inv_time", WASCC, actor.clone());
+                let help = format!("Average time (ms) to invoke actor '{}'", actor.clone());
+
+                register_gauge(
+                    registry,
+                    &mut metrics.actor_average_inv_time,
+                    &state.metric_key,
+                    &name,
+                    &help,
+                    inv_time,
+                );
+            }
+
+            if let Some(gauge) = metrics
+                .actor_operation_average_inv_time
+                .get(&state.operation_metric_key)
+            {
+                set_legacy_gauge_avg(
+                    gauge,
+                    &metrics.actor_operation_inv_count,
+                    moving_average_window_size,
+                    inv_time,
+                    &state.operation_metric_key,
+                );
+            } else {
+                let name = format!(
+                    "{}_{}_{}_average_inv_time",
+                    WASCC,
+                    actor.clone(),
+                    &state.operation
+                );
+                let help = format!(
+                    "Average time (ms) to invoke operation '{}' on actor '{}'",
+                    &state.operation,
+                    actor.clone()
+                );
+
+                register_gauge(
+                    registry,
+                    &mut metrics.actor_operation_average_inv_time,
+                    &state.operation_metric_key,
+                    &name,
+                    &help,
+                    inv_time,
+                );
+            }
+        }
+        WasccEntity::Capability { capid, binding } => {
+            if let Some(gauge) = metrics.cap_average_inv_time.get(&state.metric_key) {
+                set_legacy_gauge_avg(
+                    gauge,
+                    &metrics.cap_inv_count,
+                    moving_average_window_size,
+                    inv_time,
+                    &state.metric_key,
+                );
+            } else {
+                let name = format!("{}_{}_{}_average_inv_time", WASCC, capid, binding);
+                let help = format!(
+                    "Average time (ms) to invoke capability '{}' with binding '{}'",
+                    capid, binding
+                );
+
+                register_gauge(
+                    registry,
+                    &mut metrics.cap_average_inv_time,
+                    &state.metric_key,
+                    &name,
+                    &help,
+                    inv_time,
+                );
+            }
+
+            if let Some(gauge) = metrics
+                .cap_operation_average_inv_time
+                .get(&state.operation_metric_key)
+            {
+                set_legacy_gauge_avg(
+                    gauge,
+                    &metrics.cap_operation_inv_count,
+                    moving_average_window_size,
+                    inv_time,
+                    &state.operation_metric_key,
+                );
+            } else {
+                let name = format!(
+                    "{}_{}_{}_{}_average_inv_time",
+                    WASCC, capid, binding, &state.operation
+                );
+                let help = format!(
+                    "Average time (ms) to invoke operation '{}' on capability '{}' with binding '{}'",
+                    &state.operation, capid, binding
+                );
+
+                register_gauge(
+                    registry,
+                    &mut metrics.cap_operation_average_inv_time,
+                    &state.operation_metric_key,
+                    &name,
+                    &help,
+                    inv_time,
+                );
+            }
+        }
+    }
+}
+
 fn set_gauge_avg(
+    gauges: &GaugeVec,
+    inv_counts: &IntCounterVec,
+    labels: &[String],
+    moving_average_window_size: i64,
+    inv_time: u128,
+) {
+    let labels = as_strs(labels);
+    let gauge = gauges.with_label_values(&labels);
+    let inv_count = inv_counts.with_label_values(&labels).get();
+    gauge.set(calc_avg(
+        moving_average_window_size,
+        inv_count,
+        inv_time,
+        gauge.get(),
+    ));
+}
+
+fn set_legacy_gauge_avg(
     gauge: &Gauge,
     inv_count: &HashMap<String, IntCounter>,
     moving_average_window_size: i64,
@@ -662,7 +1132,7 @@ fn register_gauge(
             gauge.set(initial_value);
             avg_inv_time.insert(gauge_lookup_key.to_string(), gauge.clone());
 
-            if let Err(e) = registry.write().unwrap().register(Box::new(gauge)) {
+            if let Err(e) = registry.write_or_recover().register(Box::new(gauge)) {
                 error!("Error registering gauge '{}': {}", &name, e);
             }
         }
@@ -671,7 +1141,7 @@ fn register_gauge(
 }
 
 // set new average invocation time across all actors or capabilities
-fn set_new_total_avg(metrics: &RwLockWriteGuard<Metrics>, target: &WasccEntity, inv_time: u128) {
+fn set_new_total_avg(metrics: &Metrics, target: &WasccEntity, inv_time: u128) {
     match target {
         WasccEntity::Actor(_) => {
             metrics.actor_total_average_inv_time.set(calc_avg(
@@ -742,9 +1212,7 @@ async fn serve_request(
 ) -> hyper::error::Result<Response<Body>> {
     match (req.method(), req.uri().path()) {
         (&Method::GET, "/metrics") => {
-            let reg = registry
-                .read()
-                .expect("Failed to to get read lock on registry");
+            let reg = registry.read_or_recover();
             let metric_families = reg.gather();
             let mut buffer = vec![];
             let encoder = TextEncoder::new();
@@ -763,15 +1231,29 @@ async fn serve_request(
     }
 }
 
-async fn serve_metrics(addr: SocketAddr, registry: Arc<RwLock<Registry>>) {
-    let server = Server::bind(&addr).serve(make_service_fn(move |_| {
-        let registry2 = registry.clone();
-        async move {
-            Ok::<_, hyper::error::Error>(service_fn(move |req| {
-                serve_request(req, registry2.clone())
-            }))
+async fn serve_metrics(
+    listener: std::net::TcpListener,
+    registry: Arc<RwLock<Registry>>,
+    stopped: impl std::future::Future<Output = ()>,
+) {
+    // The listener has to be handed to hyper from within the runtime that will drive it
+    let builder = match Server::from_tcp(listener) {
+        Ok(b) => b,
+        Err(e) => {
+            error!("Metrics server error: {}", e);
+            return;
         }
-    }));
+    };
+    let server = builder
+        .serve(make_service_fn(move |_| {
+            let registry2 = registry.clone();
+            async move {
+                Ok::<_, hyper::error::Error>(service_fn(move |req| {
+                    serve_request(req, registry2.clone())
+                }))
+            }
+        }))
+        .with_graceful_shutdown(stopped);
 
     if let Err(e) = server.await {
         error!("Metrics server error: {}", e);
@@ -781,6 +1263,7 @@ async fn serve_metrics(addr: SocketAddr, registry: Arc<RwLock<Registry>>) {
 fn push_metrics(
     registry: Arc<RwLock<Registry>>,
     push_config: PushgatewayConfig,
+    health: Arc<RwLock<PushHealth>>,
     mut push_kill_switch_rx: tokio::sync::oneshot::Receiver<()>,
 ) {
     let job = push_config.job.unwrap_or_else(|| WASCC.to_owned());
@@ -806,19 +1289,23 @@ fn push_metrics(
         // make sure the read lock is released before we start
         // pushing or sleeping on this thread
         let metric_families = {
-            let reg = registry.read().unwrap();
+            let reg = registry.read_or_recover();
             reg.gather()
         };
 
-        if let Err(e) = prometheus::push_metrics(
+        let res = prometheus::push_metrics(
             &job,
             labels! {},
             &pushgateway_addr,
             metric_families,
             basic_auth,
-        ) {
+        );
+        if let Err(ref e) = res {
             error!("Error pushing metrics to '{}': {}", &pushgateway_addr, e);
         }
+        health
+            .write_or_recover()
+            .record(res.map_err(|e| e.to_string()));
 
         std::thread::sleep(push_config.push_interval);
     }
@@ -833,7 +1320,6 @@ mod tests {
     use crate::{Invocation, InvocationResponse, Middleware, WasccEntity};
     use mockito::{mock, Matcher};
     use rand::random;
-    use std::net::SocketAddr;
     use std::ops::Mul;
     use std::time::Duration;
     use wascap::prelude::KeyPair;
@@ -907,13 +1393,16 @@ mod tests {
         let cap_invocation1_response = invocation_response(&cap_invocation1.id);
         let cap_invocation2_response = invocation_response(&cap_invocation2.id);
 
-        let server_addr: SocketAddr = ([127, 0, 0, 1], 9898).into();
         let config = PrometheusConfig {
-            metrics_server_addr: Some(server_addr),
+            metrics_server_addr: Some(([127, 0, 0, 1], 0).into()),
             pushgateway_config: None,
             moving_average_window_size: None,
+            max_operation_labels: None,
+            legacy_metric_names: false,
         };
         let middleware = PrometheusMiddleware::new(config).unwrap();
+        let server_addr = middleware.server_addr().unwrap();
+        assert_ne!(0, server_addr.port());
 
         let invocations_op1 = 5;
         let invocations_op2 = 7;
@@ -934,96 +1423,82 @@ mod tests {
         let total_invocations = invocations_op1 + invocations_op2;
 
         // capabilities: counts
-        assert!(body
-            .find(&format!(
-                "{}_cap_total_inv_count {}",
-                WASCC, total_invocations
-            ))
-            .is_some());
-        assert!(body
-            .find(&format!(
-                "{}_{}_{}_inv_count {}",
-                WASCC, CAPID1, BINDING1, invocations_op1
-            ))
-            .is_some());
-        assert!(body
-            .find(&format!(
-                "{}_{}_{}_inv_count {}",
-                WASCC, CAPID2, BINDING2, invocations_op2
-            ))
-            .is_some());
+        assert!(body.contains(&format!(
+            "{}_cap_total_inv_count {}",
+            WASCC, total_invocations
+        )));
+        assert!(body.contains(&format!(
+            "{}_cap_inv_count{{binding=\"{}\",capid=\"{}\"}} {}",
+            WASCC, BINDING1, CAPID1, invocations_op1
+        )));
+        assert!(body.contains(&format!(
+            "{}_cap_inv_count{{binding=\"{}\",capid=\"{}\"}} {}",
+            WASCC, BINDING2, CAPID2, invocations_op2
+        )));
+        assert!(body.contains(&format!(
+            "{}_cap_operation_inv_count{{binding=\"{}\",capid=\"{}\",operation=\"{}\"}} {}",
+            WASCC, BINDING1, CAPID1, CAP_OPERATION1, invocations_op1
+        )));
         // capabilities: averages
-        assert!(body
-            .find(&format!("{}_cap_total_average_inv_time", WASCC))
-            .is_some());
-        assert!(body
-            .find(&format!(
-                "{}_{}_{}_average_inv_time",
-                WASCC, CAPID1, BINDING1
-            ))
-            .is_some());
-        assert!(body
-            .find(&format!(
-                "{}_{}_{}_average_inv_time",
-                WASCC, CAPID2, BINDING2
-            ))
-            .is_some());
+        assert!(body.contains(&format!("{}_cap_total_average_inv_time", WASCC)));
+        assert!(body.contains(&format!(
+            "{}_cap_average_inv_time{{binding=\"{}\",capid=\"{}\"}}",
+            WASCC, BINDING1, CAPID1
+        )));
+        assert!(body.contains(&format!(
+            "{}_cap_average_inv_time{{binding=\"{}\",capid=\"{}\"}}",
+            WASCC, BINDING2, CAPID2
+        )));
         // capabilities: averages for operations
-        assert!(body
-            .find(&format!(
-                "{}_{}_{}_{}_average_inv_time",
-                WASCC, CAPID1, BINDING1, CAP_OPERATION1
-            ))
-            .is_some());
-        assert!(body
-            .find(&format!(
-                "{}_{}_{}_{}_average_inv_time",
-                WASCC, CAPID2, BINDING2, CAP_OPERATION2
-            ))
-            .is_some());
+        assert!(body.contains(&format!(
+            "{}_cap_operation_average_inv_time{{binding=\"{}\",capid=\"{}\",operation=\"{}\"}}",
+            WASCC, BINDING1, CAPID1, CAP_OPERATION1
+        )));
+        assert!(body.contains(&format!(
+            "{}_cap_operation_average_inv_time{{binding=\"{}\",capid=\"{}\",operation=\"{}\"}}",
+            WASCC, BINDING2, CAPID2, CAP_OPERATION2
+        )));
 
         // actors
-        assert!(body
-            .find(&format!(
-                "{}_actor_total_inv_count {}",
-                WASCC, total_invocations
-            ))
-            .is_some());
-        assert!(body
-            .find(&format!(
-                "{}_{}_inv_count {}",
-                WASCC, ACTOR1, invocations_op1
-            ))
-            .is_some());
-        assert!(body
-            .find(&format!(
-                "{}_{}_inv_count {}",
-                WASCC, ACTOR2, invocations_op2
-            ))
-            .is_some());
+        assert!(body.contains(&format!(
+            "{}_actor_total_inv_count {}",
+            WASCC, total_invocations
+        )));
+        assert!(body.contains(&format!(
+            "{}_actor_inv_count{{actor=\"{}\"}} {}",
+            WASCC, ACTOR1, invocations_op1
+        )));
+        assert!(body.contains(&format!(
+            "{}_actor_inv_count{{actor=\"{}\"}} {}",
+            WASCC, ACTOR2, invocations_op2
+        )));
+        assert!(body.contains(&format!(
+            "{}_actor_operation_inv_count{{actor=\"{}\",operation=\"{}\"}} {}",
+            WASCC, ACTOR2, ACTOR_OPERATION2, invocations_op2
+        )));
         // actors: averages
-        assert!(body
-            .find(&format!("actor_total_average_inv_time"))
-            .is_some());
-        assert!(body
-            .find(&format!("{}_{}_average_inv_time", WASCC, ACTOR1,))
-            .is_some());
-        assert!(body
-            .find(&format!("{}_{}_average_inv_time", WASCC, ACTOR2))
-            .is_some());
+        assert!(body.contains(&format!("{}_actor_total_average_inv_time", WASCC)));
+        assert!(body.contains(&format!(
+            "{}_actor_average_inv_time{{actor=\"{}\"}}",
+            WASCC, ACTOR1
+        )));
+        assert!(body.contains(&format!(
+            "{}_actor_average_inv_time{{actor=\"{}\"}}",
+            WASCC, ACTOR2
+        )));
         // actors: averages for operations
-        assert!(body
-            .find(&format!(
-                "{}_{}_{}_average_inv_time",
-                WASCC, ACTOR1, ACTOR_OPERATION1
-            ))
-            .is_some());
-        assert!(body
-            .find(&format!(
-                "{}_{}_{}_average_inv_time",
-                WASCC, ACTOR2, ACTOR_OPERATION2
-            ))
-            .is_some());
+        assert!(body.contains(&format!(
+            "{}_actor_operation_average_inv_time{{actor=\"{}\",operation=\"{}\"}}",
+            WASCC, ACTOR1, ACTOR_OPERATION1
+        )));
+        assert!(body.contains(&format!(
+            "{}_actor_operation_average_inv_time{{actor=\"{}\",operation=\"{}\"}}",
+            WASCC, ACTOR2, ACTOR_OPERATION2
+        )));
+
+        // no per-entity metric names without the compatibility flag
+        assert!(!body.contains(&format!("{}_{}_inv_count", WASCC, ACTOR1)));
+        assert!(!body.contains(&format!("{}_{}_{}_inv_count", WASCC, CAPID1, BINDING1)));
 
         // check that invocation state is cleaned up
         assert!(middleware
@@ -1036,6 +1511,211 @@ mod tests {
         Ok(())
     }
 
+    fn gather(middleware: &PrometheusMiddleware) -> String {
+        use prometheus::{Encoder, TextEncoder};
+
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&middleware.registry.read().unwrap().gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn operation_labels_sanitized_and_capped() {
+        let middleware = PrometheusMiddleware::new(PrometheusConfig {
+            metrics_server_addr: None,
+            pushgateway_config: None,
+            moving_average_window_size: None,
+            max_operation_labels: Some(2),
+            legacy_metric_names: false,
+        })
+        .unwrap();
+
+        for operation in &[
+            "wascc:http_server!HandleRequest",
+            "Handle Request \"quoted\"",
+            "third-op",
+            "fourth",
+        ] {
+            let inv = cap_invocation(CAPID1, BINDING1, operation);
+            invoke(&middleware, &inv, &invocation_response(&inv.id));
+        }
+        // an operation that already has a label keeps it once the cap is reached
+        let inv = cap_invocation(CAPID1, BINDING1, "wascc:http_server!HandleRequest");
+        invoke(&middleware, &inv, &invocation_response(&inv.id));
+        // the cap applies to each capability separately
+        let inv = cap_invocation(CAPID2, BINDING2, "third-op");
+        invoke(&middleware, &inv, &invocation_response(&inv.id));
+
+        let body = gather(&middleware);
+        let cap1_op = |operation: &str, count: u32| {
+            format!(
+                "{}_cap_operation_inv_count{{binding=\"{}\",capid=\"{}\",operation=\"{}\"}} {}",
+                WASCC, BINDING1, CAPID1, operation, count
+            )
+        };
+        assert!(body.contains(&cap1_op("wascc:http_server_HandleRequest", 2)));
+        assert!(body.contains(&cap1_op("Handle_Request__quoted_", 1)));
+        assert!(body.contains(&cap1_op("other", 2)));
+        assert!(!body.contains(&cap1_op("third-op", 1)));
+        assert!(body.contains(&format!(
+            "{}_cap_operation_inv_count{{binding=\"{}\",capid=\"{}\",operation=\"third-op\"}} 1",
+            WASCC, BINDING2, CAPID2
+        )));
+        assert!(body.contains(&format!(
+            "{}_cap_inv_count{{binding=\"{}\",capid=\"{}\"}} 5",
+            WASCC, BINDING1, CAPID1
+        )));
+    }
+
+    #[test]
+    fn legacy_metric_names_registered_when_enabled() {
+        let middleware = PrometheusMiddleware::new(PrometheusConfig {
+            metrics_server_addr: None,
+            pushgateway_config: None,
+            moving_average_window_size: None,
+            max_operation_labels: None,
+            legacy_metric_names: true,
+        })
+        .unwrap();
+        let actor_inv = actor_invocation(ACTOR1, ACTOR_OPERATION1);
+        invoke(&middleware, &actor_inv, &invocation_response(&actor_inv.id));
+        let cap_inv = cap_invocation(CAPID1, BINDING1, CAP_OPERATION1);
+        invoke(&middleware, &cap_inv, &invocation_response(&cap_inv.id));
+
+        let body = gather(&middleware);
+        assert!(body.contains(&format!("{}_{}_inv_count 1", WASCC, ACTOR1)));
+        assert!(body.contains(&format!(
+            "{}_{}_{}_inv_count 1",
+            WASCC, ACTOR1, ACTOR_OPERATION1
+        )));
+        assert!(body.contains(&format!("{}_{}_average_inv_time", WASCC, ACTOR1)));
+        assert!(body.contains(&format!("{}_{}_{}_inv_count 1", WASCC, CAPID1, BINDING1)));
+        assert!(body.contains(&format!(
+            "{}_{}_{}_{}_average_inv_time",
+            WASCC, CAPID1, BINDING1, CAP_OPERATION1
+        )));
+        // alongside the labeled metrics
+        assert!(body.contains(&format!(
+            "{}_actor_inv_count{{actor=\"{}\"}} 1",
+            WASCC, ACTOR1
+        )));
+    }
+
+    #[test]
+    fn start_times_recorded() {
+        use prometheus::{Encoder, TextEncoder};
+
+        let middleware = PrometheusMiddleware::new(PrometheusConfig {
+            metrics_server_addr: None,
+            pushgateway_config: None,
+            moving_average_window_size: None,
+            max_operation_labels: None,
+            legacy_metric_names: false,
+        })
+        .unwrap();
+        middleware.actor_started(ACTOR1, Duration::from_millis(250));
+        middleware.actor_started(ACTOR2, Duration::from_millis(750));
+        middleware.capability_started(CAPID1, BINDING1, Duration::from_millis(20));
+
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&middleware.registry.read().unwrap().gather(), &mut buffer)
+            .unwrap();
+        let body = String::from_utf8(buffer).unwrap();
+        assert!(body.contains(&format!("{}_actor_start_seconds_count 2", WASCC)));
+        assert!(body.contains(&format!("{}_actor_start_seconds_sum 1", WASCC)));
+        assert!(body.contains(&format!("{}_provider_start_seconds_count 1", WASCC)));
+    }
+
+    #[test]
+    fn denials_and_halts_counted() {
+        use prometheus::{Encoder, TextEncoder};
+
+        let middleware = PrometheusMiddleware::new(PrometheusConfig {
+            metrics_server_addr: None,
+            pushgateway_config: None,
+            moving_average_window_size: None,
+            max_operation_labels: None,
+            legacy_metric_names: false,
+        })
+        .unwrap();
+        let target = WasccEntity::Capability {
+            capid: CAPID1.to_string(),
+            binding: BINDING1.to_string(),
+        };
+        middleware.authorization_denied(ACTOR1, &target, crate::REASON_AUTHORIZER_DENIED);
+        middleware.authorization_denied(ACTOR2, &target, crate::REASON_AUTHORIZER_DENIED);
+        middleware.authorization_denied(ACTOR1, &target, crate::REASON_MISSING_ATTESTATION);
+        middleware.invocation_halted("cache", &cap_invocation(CAPID1, BINDING1, CAP_OPERATION1));
+
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&middleware.registry.read().unwrap().gather(), &mut buffer)
+            .unwrap();
+        let body = String::from_utf8(buffer).unwrap();
+        assert!(body.contains(&format!(
+            "{}_authz_denials_total{{reason=\"authorizer_denied\"}} 2",
+            WASCC
+        )));
+        assert!(body.contains(&format!(
+            "{}_authz_denials_total{{reason=\"missing_attestation\"}} 1",
+            WASCC
+        )));
+        assert!(body.contains(&format!(
+            "{}_middleware_halts_total{{middleware=\"cache\"}} 1",
+            WASCC
+        )));
+    }
+
+    #[test]
+    fn server_address_in_use_reported() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = PrometheusConfig {
+            metrics_server_addr: Some(taken.local_addr().unwrap()),
+            pushgateway_config: None,
+            moving_average_window_size: None,
+            max_operation_labels: None,
+            legacy_metric_names: false,
+        };
+        let err = PrometheusMiddleware::new(config).err().unwrap();
+        assert!(err
+            .to_string()
+            .contains("Failed to bind the metrics server"));
+    }
+
+    #[test]
+    fn push_failures_reported() {
+        // Nothing listens on a port that was bound and released
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let push_interval = Duration::from_millis(50);
+        let middleware = PrometheusMiddleware::new(PrometheusConfig {
+            metrics_server_addr: None,
+            pushgateway_config: Some(PushgatewayConfig {
+                push_interval,
+                pushgateway_addr: format!("http://{}", addr),
+                job: None,
+                push_basic_auth: None,
+            }),
+            moving_average_window_size: None,
+            max_operation_labels: None,
+            legacy_metric_names: false,
+        })
+        .unwrap();
+        assert!(middleware.server_addr().is_none());
+
+        std::thread::sleep(push_interval.mul(4));
+        let health = middleware.push_health().unwrap();
+        assert!(!health.is_healthy());
+        assert!(health.consecutive_failures >= 1);
+        assert!(health.last_success.is_none());
+        assert!(health.last_error.is_some());
+    }
+
     #[test]
     fn test_push_metrics() {
         // The data format that is used is not compatible with any current Mockito
@@ -1063,6 +1743,8 @@ mod tests {
                 push_basic_auth: None,
             }),
             moving_average_window_size: None,
+            max_operation_labels: None,
+            legacy_metric_names: false,
         };
 
         let middleware = PrometheusMiddleware::new(config).unwrap();
@@ -1094,6 +1776,9 @@ mod tests {
         std::thread::sleep(push_interval.mul(3));
 
         pushed_metrics.assert();
+        let health = middleware.push_health().unwrap();
+        assert!(health.is_healthy());
+        assert!(health.last_error.is_none());
         // check that invocation state is cleaned up
         assert!(middleware
             .metrics
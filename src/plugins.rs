@@ -3,12 +3,53 @@ use crate::dispatch::WasccNativeDispatcher;
 use crate::errors::{self, ErrorKind};
 use crate::inthost::Invocation;
 use crate::inthost::{InvocationResponse, WasccEntity};
+use crate::locks::RwLockExt;
 use crate::{Result, RouteKey};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Usage statistics for a single native capability provider (capability ID and binding name)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProviderStats {
+    /// The number of actors currently bound to the provider
+    pub bound_actors: usize,
+    /// The number of invocations the provider has handled since it was added
+    pub invocations: u64,
+    /// The number of invocations for which the provider returned an error
+    pub errors: u64,
+    /// The time of the most recent invocation, if any
+    pub last_invocation_at: Option<SystemTime>,
+}
+
+#[derive(Default)]
+struct ProviderCounters {
+    invocations: AtomicU64,
+    errors: AtomicU64,
+    last_invocation_millis: AtomicU64, // milliseconds since the epoch, 0 if never invoked
+}
+
+impl ProviderCounters {
+    fn record(&self, success: bool) {
+        self.invocations.fetch_add(1, Ordering::Relaxed);
+        if !success {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        self.last_invocation_millis.store(now, Ordering::Relaxed);
+    }
+}
 
 #[derive(Default)]
 pub(crate) struct PluginManager {
-    plugins: HashMap<RouteKey, NativeCapability>,
+    plugins: HashMap<RouteKey, Arc<NativeCapability>>,
+    // Counters live outside the plugin so they survive a provider being swapped
+    // for a new instance, but are discarded when the provider is removed
+    counters: HashMap<RouteKey, Arc<ProviderCounters>>,
 }
 
 impl PluginManager {
@@ -32,7 +73,10 @@ impl PluginManager {
         }
     }
 
-    pub fn call(&self, inv: &Invocation) -> Result<InvocationResponse> {
+    // The plugin table is only locked while the provider is looked up, never while it handles
+    // the call, since a provider may dispatch back into the host from within the call (see the
+    // lock ordering notes in inthost.rs)
+    pub fn call(plugins: &RwLock<PluginManager>, inv: &Invocation) -> Result<InvocationResponse> {
         if let WasccEntity::Capability { capid, binding } = &inv.target {
             let route_key = RouteKey::new(&binding, &capid);
             let actor = if let WasccEntity::Actor(s) = &inv.origin {
@@ -40,12 +84,25 @@ impl PluginManager {
             } else {
                 "SHOULD NEVER SEND CAP-ORIGIN INVOCATION TO ANOTHER CAP".to_string()
             };
-            match self.plugins.get(&route_key) {
+            let (plugin, counters) = {
+                let lock = plugins.read_or_recover();
+                (
+                    lock.plugins.get(&route_key).cloned(),
+                    lock.counters.get(&route_key).cloned(),
+                )
+            };
+            match plugin {
                 // native capability is registered via plugin
-                Some(c) => match c.plugin.handle_call(&actor, &inv.operation, &inv.msg) {
-                    Ok(msg) => Ok(InvocationResponse::success(inv, msg)),
-                    Err(e) => Err(errors::new(errors::ErrorKind::HostCallFailure(e))),
-                },
+                Some(c) => {
+                    let res = c.plugin.handle_call(&actor, &inv.operation, &inv.msg);
+                    if let Some(counters) = counters {
+                        counters.record(res.is_ok());
+                    }
+                    match res {
+                        Ok(msg) => Ok(InvocationResponse::success(inv, msg)),
+                        Err(e) => Err(errors::new(errors::ErrorKind::HostCallFailure(e))),
+                    }
+                }
                 // if there's no plugin, return an error
                 None => Err(errors::new(ErrorKind::CapabilityProvider(format!(
                     "No such capability ID registered as native plug-in {:?}",
@@ -68,16 +125,40 @@ impl PluginManager {
                 plugin.id()
             ))))
         } else {
-            self.plugins.insert(key, plugin);
+            self.counters.entry(key.clone()).or_default();
+            self.plugins.insert(key, Arc::new(plugin));
             Ok(())
         }
     }
 
     pub fn remove_plugin(&mut self, binding: &str, capid: &str) -> Result<()> {
         let key = RouteKey::new(&binding, &capid);
+        // A call still in progress keeps the provider alive until it returns
         if let Some(plugin) = self.plugins.remove(&key) {
             drop(plugin);
         }
+        self.counters.remove(&key);
         Ok(())
     }
+
+    /// Whether a native provider is registered under the binding name and capability ID
+    pub fn has_plugin(&self, binding: &str, capid: &str) -> bool {
+        self.plugins.contains_key(&RouteKey::new(binding, capid))
+    }
+
+    // Returns the usage statistics for a provider, less the bound actor count which is
+    // not known to the plugin manager
+    pub fn stats(&self, binding: &str, capid: &str) -> Option<ProviderStats> {
+        self.counters
+            .get(&RouteKey::new(binding, capid))
+            .map(|c| ProviderStats {
+                bound_actors: 0,
+                invocations: c.invocations.load(Ordering::Relaxed),
+                errors: c.errors.load(Ordering::Relaxed),
+                last_invocation_at: match c.last_invocation_millis.load(Ordering::Relaxed) {
+                    0 => None,
+                    ms => Some(UNIX_EPOCH + Duration::from_millis(ms)),
+                },
+            })
+    }
 }
@@ -0,0 +1,258 @@
+//! # Quickstart
+//!
+//! A chained builder for the common case of a host with a few actors loaded from files, a few
+//! native capability providers, and bindings between them, e.g.
+//!
+//! ```no_run
+//! use std::collections::HashMap;
+//! use wascc_host::Host;
+//!
+//! # fn main() -> wascc_host::Result<()> {
+//! let mut redis = HashMap::new();
+//! redis.insert("URL".to_string(), "redis://127.0.0.1:6379".to_string());
+//! let mut http = HashMap::new();
+//! http.insert("PORT".to_string(), "8080".to_string());
+//!
+//! let host = Host::quick()
+//!     .with_actor_file("./examples/.assets/kvcounter.wasm")
+//!     .with_provider_file("./examples/.assets/libwascc_httpsrv.so", None)
+//!     .with_provider_file("./examples/.assets/libwascc_redis.so", None)
+//!     .bind(0, "wascc:keyvalue", redis)
+//!     .bind(0, "wascc:http_server", http)
+//!     .start()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{errors, Actor, Host, NativeCapability, Result, WasccEntity};
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::time::Duration;
+
+// How long starting a quick host waits for each actor and provider to be reachable
+const READY_TIMEOUT_MS: u64 = 5_000;
+
+/// Refers to an actor in a `QuickHost` binding, either by the position in which it was added
+/// with `with_actor_file` (starting at 0) or by its public key
+#[derive(Debug, Clone, PartialEq)]
+pub enum ActorRef {
+    Index(usize),
+    Key(String),
+}
+
+impl From<usize> for ActorRef {
+    fn from(i: usize) -> ActorRef {
+        ActorRef::Index(i)
+    }
+}
+
+impl From<&str> for ActorRef {
+    fn from(pk: &str) -> ActorRef {
+        ActorRef::Key(pk.to_string())
+    }
+}
+
+impl From<String> for ActorRef {
+    fn from(pk: String) -> ActorRef {
+        ActorRef::Key(pk)
+    }
+}
+
+enum Step {
+    Actor(PathBuf),
+    Provider(PathBuf, Option<String>),
+    Bind(ActorRef, String, HashMap<String, String>),
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Step::Actor(path) => write!(f, "with_actor_file({})", path.display()),
+            Step::Provider(path, Some(b)) => {
+                write!(f, "with_provider_file({}, {})", path.display(), b)
+            }
+            Step::Provider(path, None) => write!(f, "with_provider_file({})", path.display()),
+            Step::Bind(ActorRef::Index(i), capid, _) => write!(f, "bind({}, {})", i, capid),
+            Step::Bind(ActorRef::Key(pk), capid, _) => write!(f, "bind({}, {})", pk, capid),
+        }
+    }
+}
+
+/// Collects the actors, capability providers, and bindings of a host, which are only added
+/// when `start` is called. Created with `Host::quick`
+pub struct QuickHost {
+    host: Host,
+    steps: Vec<Step>,
+}
+
+impl QuickHost {
+    /// Starts a quick setup of an existing host, e.g. one created with a `HostBuilder`
+    pub fn new(host: Host) -> QuickHost {
+        QuickHost {
+            host,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Adds the actor in the given module file
+    pub fn with_actor_file(self, path: impl Into<PathBuf>) -> QuickHost {
+        self.with_step(Step::Actor(path.into()))
+    }
+
+    /// Adds the native capability provider in the given library file with the given binding
+    /// name (the host's default binding name if `None`)
+    pub fn with_provider_file(self, path: impl Into<PathBuf>, binding: Option<&str>) -> QuickHost {
+        self.with_step(Step::Provider(path.into(), binding.map(String::from)))
+    }
+
+    /// Binds an actor to a capability with the given configuration values. The binding name
+    /// is the one the provider of that capability was added with, or the host's default
+    /// binding name if the provider wasn't added to this quick host. Binding fails if providers
+    /// of the capability were added with more than one binding name
+    pub fn bind(
+        self,
+        actor: impl Into<ActorRef>,
+        capid: &str,
+        values: HashMap<String, String>,
+    ) -> QuickHost {
+        self.with_step(Step::Bind(actor.into(), capid.to_string(), values))
+    }
+
+    fn with_step(mut self, step: Step) -> QuickHost {
+        self.steps.push(step);
+        self
+    }
+
+    /// Adds every actor and then every capability provider, waiting for each to be reachable,
+    /// and then applies the bindings, returning the host. If any step fails the host is shut
+    /// down, and the error names the failed step and its position in the chain (starting at 1)
+    pub fn start(self) -> Result<Host> {
+        let QuickHost { host, steps } = self;
+        match start_steps(&host, &steps) {
+            Ok(()) => Ok(host),
+            Err((i, e)) => {
+                let _ = host.shutdown();
+                Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                    "Quickstart step {} ({}) failed: {}",
+                    i + 1,
+                    steps[i],
+                    e
+                ))))
+            }
+        }
+    }
+}
+
+// Runs the steps in dependency order, returning the index of the step that failed
+fn start_steps(host: &Host, steps: &[Step]) -> std::result::Result<(), (usize, errors::Error)> {
+    let ready = Duration::from_millis(READY_TIMEOUT_MS);
+    let mut actors = Vec::new();
+    // The capability ID and binding name of each provider added
+    let mut providers: BTreeSet<(String, String)> = BTreeSet::new();
+    for (i, step) in steps.iter().enumerate() {
+        if let Step::Actor(path) = step {
+            let pk = Actor::from_file(path)
+                .and_then(|a| {
+                    let pk = a.public_key();
+                    host.add_actor(a).map(|_| pk)
+                })
+                .and_then(|pk| {
+                    host.await_ready(&WasccEntity::Actor(pk.clone()), ready)
+                        .map(|_| pk)
+                })
+                .map_err(|e| (i, e))?;
+            actors.push(pk);
+        }
+    }
+    for (i, step) in steps.iter().enumerate() {
+        if let Step::Provider(path, binding) = step {
+            let cap = NativeCapability::from_file(path, binding.clone()).map_err(|e| (i, e))?;
+            // Resolved just as adding the provider resolves it
+            let binding = if cap.implicit_binding {
+                host.default_binding.to_string()
+            } else {
+                cap.binding_name.to_string()
+            };
+            let entity = WasccEntity::Capability {
+                capid: cap.id(),
+                binding: binding.to_string(),
+            };
+            providers.insert((cap.id(), binding));
+            host.add_native_capability(cap)
+                .and_then(|_| host.await_ready(&entity, ready))
+                .map_err(|e| (i, e))?;
+        }
+    }
+    for (i, step) in steps.iter().enumerate() {
+        if let Step::Bind(actor, capid, values) = step {
+            let pk = match actor {
+                ActorRef::Index(n) => actors.get(*n).cloned().ok_or_else(|| {
+                    (
+                        i,
+                        errors::new(errors::ErrorKind::MiscHost(format!(
+                            "Only {} actors were added, there is no actor {}",
+                            actors.len(),
+                            n
+                        ))),
+                    )
+                })?,
+                ActorRef::Key(pk) => pk.to_string(),
+            };
+            let bindings: Vec<&str> = providers
+                .iter()
+                .filter(|(id, _)| id == capid)
+                .map(|(_, b)| b.as_str())
+                .collect();
+            if bindings.len() > 1 {
+                return Err((
+                    i,
+                    errors::new(errors::ErrorKind::MiscHost(format!(
+                        "Providers of {} were added with several binding names ({}), so the binding is ambiguous",
+                        capid,
+                        bindings.join(", ")
+                    ))),
+                ));
+            }
+            let binding = bindings.first().map(|b| b.to_string());
+            host.set_binding(&pk, capid, binding, values.clone())
+                .map_err(|e| (i, e))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::ActorRef;
+    use crate::Host;
+    use std::collections::HashMap;
+
+    #[test]
+    fn errors_name_the_failed_step() {
+        let err = Host::quick()
+            .with_actor_file("./examples/.assets/echo.wasm")
+            .bind(1, "wascc:http_server", HashMap::new())
+            .start()
+            .err()
+            .unwrap();
+        assert!(err
+            .to_string()
+            .contains("step 2 (bind(1, wascc:http_server))"));
+
+        let err = Host::quick()
+            .with_actor_file("./examples/.assets/echo.wasm")
+            .with_actor_file("./examples/.assets/nosuch.wasm")
+            .start()
+            .err()
+            .unwrap();
+        assert!(err
+            .to_string()
+            .contains("step 2 (with_actor_file(./examples/.assets/nosuch.wasm))"));
+    }
+
+    #[test]
+    fn actor_refs() {
+        assert_eq!(ActorRef::Index(2), 2.into());
+        assert_eq!(ActorRef::Key("Mxxx".to_string()), "Mxxx".into());
+    }
+}
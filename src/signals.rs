@@ -0,0 +1,45 @@
+//! Blocking until the process is asked to terminate, then shutting the host down gracefully.
+//! Enabled with the `signals` feature flag.
+
+use crate::errors::{self, ErrorKind};
+use crate::{Host, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+impl Host {
+    /// Blocks the calling thread until the process receives SIGINT, SIGTERM, or SIGHUP
+    /// (Ctrl-C or Ctrl-Break on Windows), then performs a graceful `shutdown` of the host before
+    /// returning. Receiving a second signal while the shutdown is in progress exits the process
+    /// immediately. Only one handler can be installed per process, so this can only be called once
+    pub fn run_until_signal(&self) -> Result<()> {
+        let (term_s, term_r) = crossbeam_channel::bounded(1);
+        let received = Arc::new(AtomicUsize::new(0));
+
+        ctrlc::set_handler(move || {
+            if received.fetch_add(1, Ordering::SeqCst) == 0 {
+                let _ = term_s.try_send(());
+            } else {
+                warn!("Received second termination signal, exiting immediately");
+                std::process::exit(130);
+            }
+        })
+        .map_err(|e| {
+            errors::new(ErrorKind::MiscHost(format!(
+                "Failed to install termination signal handler: {}",
+                e
+            )))
+        })?;
+
+        term_r.recv().map_err(|_| {
+            errors::new(ErrorKind::MiscHost(
+                "Failed awaiting termination signal".into(),
+            ))
+        })?;
+
+        info!(
+            "Received termination signal, shutting down host {}",
+            self.id()
+        );
+        self.shutdown()
+    }
+}
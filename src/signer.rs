@@ -0,0 +1,283 @@
+//! The key used to sign the claims of the invocations a host sends, kept apart from the key
+//! that identifies the host so that it can be rotated without changing the host's identity
+
+use crate::antiforgery::AntiforgeryCache;
+use crate::errors::{self, ErrorKind};
+use crate::inthost::{Invocation, WasccEntity};
+use crate::locks::RwLockExt;
+use crate::Result;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use wascap::prelude::KeyPair;
+
+pub(crate) struct InvocationSigner {
+    host_id: String,
+    // Whether a signing key was configured. Only then are invocations required to be signed by
+    // one of the accepted keys; otherwise each invocation need only be signed by its host
+    explicit: bool,
+    keys: RwLock<SignerKeys>,
+    // Claims whose signatures have been verified, if caching them was enabled
+    cache: Option<AntiforgeryCache>,
+}
+
+struct SignerKeys {
+    seed: String,
+    // Public keys of replaced signing keys, and when they stop being accepted
+    retired: Vec<(String, Instant)>,
+    // Public keys other hosts have announced they'll rotate to, and until when they're trusted
+    announced: Vec<(String, Instant)>,
+}
+
+fn key_from_seed(seed: &str) -> Result<KeyPair> {
+    KeyPair::from_seed(seed).map_err(|e| {
+        errors::new(ErrorKind::MiscHost(format!(
+            "Invalid invocation signing key: {}",
+            e
+        )))
+    })
+}
+
+impl InvocationSigner {
+    pub fn new(host_id: &str, seed: &str, explicit: bool) -> Result<InvocationSigner> {
+        key_from_seed(seed)?;
+        Ok(InvocationSigner {
+            host_id: host_id.to_string(),
+            explicit,
+            keys: RwLock::new(SignerKeys {
+                seed: seed.to_string(),
+                retired: Vec::new(),
+                announced: Vec::new(),
+            }),
+            cache: None,
+        })
+    }
+
+    /// Caches the claims of validated invocations so that their signatures aren't verified
+    /// again
+    pub fn with_cache(self, cache: AntiforgeryCache) -> InvocationSigner {
+        InvocationSigner {
+            cache: Some(cache),
+            ..self
+        }
+    }
+
+    /// The public key of the host, which is what invocations name as their host
+    pub fn host_id(&self) -> &str {
+        &self.host_id
+    }
+
+    /// The current signing key
+    pub fn key(&self) -> KeyPair {
+        KeyPair::from_seed(&self.keys.read_or_recover().seed).unwrap()
+    }
+
+    /// Creates an invocation signed with the current key
+    pub fn invocation(
+        &self,
+        origin: WasccEntity,
+        target: WasccEntity,
+        op: &str,
+        msg: Vec<u8>,
+    ) -> Invocation {
+        let mut inv = Invocation::new(&self.key(), origin, target, op, msg);
+        inv.host_id = self.host_id.to_string();
+        inv
+    }
+
+    /// Starts signing with a new key. The replaced key is still accepted until the grace
+    /// period has elapsed. Only a configured signing key can be rotated, since otherwise
+    /// invocations are signed with the host's own key
+    pub fn rotate(&self, seed: &str, grace: Duration) -> Result<()> {
+        if !self.explicit {
+            return Err(errors::new(ErrorKind::MiscHost(
+                "Host has no invocation signing key to rotate".into(),
+            )));
+        }
+        key_from_seed(seed)?;
+        let mut keys = self.keys.write_or_recover();
+        let old = KeyPair::from_seed(&keys.seed).unwrap().public_key();
+        let now = Instant::now();
+        keys.retired.retain(|(_, until)| *until > now);
+        keys.retired.push((old, now + grace));
+        keys.seed = seed.to_string();
+        Ok(())
+    }
+
+    /// Accepts invocations signed with the key another host has announced it will rotate to,
+    /// for the given window, so that the other host can rotate before this one does. Like
+    /// rotation, this only applies when a signing key was configured
+    pub fn trust(&self, public_key: &str, window: Duration) -> Result<()> {
+        if !self.explicit {
+            return Err(errors::new(ErrorKind::MiscHost(
+                "Host has no invocation signing key, so it accepts any host's own key".into(),
+            )));
+        }
+        KeyPair::from_public_key(public_key).map_err(|e| {
+            errors::new(ErrorKind::MiscHost(format!(
+                "Invalid invocation signing public key: {}",
+                e
+            )))
+        })?;
+        let mut keys = self.keys.write_or_recover();
+        let now = Instant::now();
+        keys.announced
+            .retain(|(pk, until)| *until > now && pk != public_key);
+        keys.announced.push((public_key.to_string(), now + window));
+        Ok(())
+    }
+
+    /// The public keys invocations must be signed with, or `None` if no signing key was
+    /// configured
+    pub fn accepted(&self) -> Option<Vec<String>> {
+        if !self.explicit {
+            return None;
+        }
+        let keys = self.keys.read_or_recover();
+        let now = Instant::now();
+        let mut accepted = vec![KeyPair::from_seed(&keys.seed).unwrap().public_key()];
+        accepted.extend(
+            keys.retired
+                .iter()
+                .chain(keys.announced.iter())
+                .filter(|(_, until)| *until > now)
+                .map(|(pk, _)| pk.to_string()),
+        );
+        Some(accepted)
+    }
+
+    /// Checks that an invocation was signed by an accepted key, along with the rest of its
+    /// antiforgery checks
+    pub fn validate(&self, inv: &Invocation) -> Result<()> {
+        let cache = self.cache.as_ref();
+        match self.accepted() {
+            Some(accepted) => {
+                let accepted: Vec<&str> = accepted.iter().map(String::as_str).collect();
+                inv.check_antiforgery(&accepted, cache)
+            }
+            None => inv.check_antiforgery(&[inv.host_id.as_str()], cache),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::InvocationSigner;
+    use crate::{AntiforgeryCache, WasccEntity};
+    use std::time::Duration;
+    use wascap::prelude::KeyPair;
+
+    fn inv(signer: &InvocationSigner) -> crate::Invocation {
+        signer.invocation(
+            WasccEntity::Actor("system".to_string()),
+            WasccEntity::Actor("Ma".to_string()),
+            "Test",
+            vec![],
+        )
+    }
+
+    #[test]
+    fn rotated_keys_accepted_during_grace() {
+        let host = KeyPair::new_server();
+        let first = KeyPair::new_server();
+        let signer =
+            InvocationSigner::new(&host.public_key(), &first.seed().unwrap(), true).unwrap();
+        let old = inv(&signer);
+        assert_eq!(host.public_key(), old.host_id);
+        assert!(signer.validate(&old).is_ok());
+
+        let second = KeyPair::new_server();
+        signer
+            .rotate(&second.seed().unwrap(), Duration::from_millis(200))
+            .unwrap();
+        assert!(signer.validate(&old).is_ok());
+        assert!(signer.validate(&inv(&signer)).is_ok());
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(signer.validate(&old).is_err());
+        assert!(signer.validate(&inv(&signer)).is_ok());
+
+        // Other keys are never accepted, even when they sign for this host
+        let forged = crate::Invocation::new(
+            &KeyPair::new_server(),
+            WasccEntity::Actor("system".to_string()),
+            WasccEntity::Actor("Ma".to_string()),
+            "Test",
+            vec![],
+        );
+        assert!(signer.validate(&forged).is_err());
+    }
+
+    #[test]
+    fn announced_keys_trusted_ahead_of_rotation() {
+        let host = KeyPair::new_server();
+        let first = KeyPair::new_server();
+        let receiver =
+            InvocationSigner::new(&host.public_key(), &first.seed().unwrap(), true).unwrap();
+        let sender =
+            InvocationSigner::new(&host.public_key(), &first.seed().unwrap(), true).unwrap();
+
+        // The sender rotates first, before the receiver knows of the new key
+        let next = KeyPair::new_server();
+        sender
+            .rotate(&next.seed().unwrap(), Duration::from_secs(1))
+            .unwrap();
+        assert!(receiver.validate(&inv(&sender)).is_err());
+
+        receiver
+            .trust(&next.public_key(), Duration::from_millis(200))
+            .unwrap();
+        assert!(receiver.validate(&inv(&sender)).is_ok());
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(receiver.validate(&inv(&sender)).is_err());
+
+        assert!(receiver.trust("not a key", Duration::from_secs(1)).is_err());
+        let unsigned =
+            InvocationSigner::new(&host.public_key(), &host.seed().unwrap(), false).unwrap();
+        assert!(unsigned
+            .trust(&next.public_key(), Duration::from_secs(1))
+            .is_err());
+    }
+
+    #[test]
+    fn cached_claims_still_checked_against_accepted_keys() {
+        let host = KeyPair::new_server();
+        let first = KeyPair::new_server();
+        let signer = InvocationSigner::new(&host.public_key(), &first.seed().unwrap(), true)
+            .unwrap()
+            .with_cache(AntiforgeryCache::default());
+        let old = inv(&signer);
+        assert!(signer.validate(&old).is_ok());
+        signer
+            .rotate(
+                &KeyPair::new_server().seed().unwrap(),
+                Duration::from_millis(100),
+            )
+            .unwrap();
+        assert!(signer.validate(&old).is_ok());
+        std::thread::sleep(Duration::from_millis(150));
+        // The claims are still cached, but their issuer is no longer accepted
+        assert_eq!(1, signer.cache.as_ref().unwrap().len());
+        assert!(signer.validate(&old).is_err());
+    }
+
+    #[test]
+    fn host_signed_invocations_accepted_by_default() {
+        let host = KeyPair::new_server();
+        let signer =
+            InvocationSigner::new(&host.public_key(), &host.seed().unwrap(), false).unwrap();
+        assert!(signer.accepted().is_none());
+        assert!(signer
+            .rotate(
+                &KeyPair::new_server().seed().unwrap(),
+                Duration::from_secs(1)
+            )
+            .is_err());
+        let other = crate::Invocation::new(
+            &KeyPair::new_server(),
+            WasccEntity::Actor("system".to_string()),
+            WasccEntity::Actor("Ma".to_string()),
+            "Test",
+            vec![],
+        );
+        assert!(signer.validate(&other).is_ok());
+    }
+}
@@ -0,0 +1,152 @@
+// A consistent view of the actors, capability providers, bindings, and labels of a host, for
+// dashboards and inventories that would otherwise read each of them at a different moment
+
+use crate::bindings::Bindings;
+use crate::locks::RwLockExt;
+use crate::{RouteKey, SubjectClaimsPair};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::SystemTime;
+use wascap::jwt::{Actor, Claims};
+use wascc_codec::capabilities::CapabilityDescriptor;
+
+/// The state of a host at a single moment, as returned by `Host::snapshot`. Unlike calling
+/// `Host::actors`, `Host::capabilities`, and so on one after another, every binding in a
+/// snapshot is to a capability provider in the same snapshot. An actor that is being removed
+/// leaves `actors` before its bindings are released, though, so (as in a lattice, where actors
+/// may be bound from other hosts) a binding's actor isn't necessarily among `actors`
+#[derive(Debug, Clone)]
+pub struct HostSnapshot {
+    /// The public keys and claims of the actors in the host, ordered by public key
+    pub actors: Vec<SubjectClaimsPair>,
+    /// The capability providers in the host. The key is a tuple of (binding, capability ID)
+    pub capabilities: HashMap<(String, String), CapabilityDescriptor>,
+    /// The bindings between actors and the host's capability providers, ordered by actor,
+    /// capability ID, and binding name
+    pub bindings: Vec<BindingSnapshot>,
+    /// The host's labels, including the `hostcore.*` labels
+    pub labels: HashMap<String, String>,
+    /// When the snapshot was taken
+    pub taken_at: SystemTime,
+}
+
+/// A binding between an actor and a capability provider. Configuration values often hold
+/// credentials, so only their names are included
+#[derive(Debug, Clone, PartialEq)]
+pub struct BindingSnapshot {
+    pub actor: String,
+    pub capid: String,
+    pub binding: String,
+    /// The name of the configuration, if it was set with `Host::set_binding_named`
+    pub config_name: Option<String>,
+    /// The names of the configuration's values, sorted
+    pub value_names: Vec<String>,
+}
+
+// Takes the read locks in the host's lock order (see the notes in `inthost`) and holds them all
+// while copying, so that nothing can change between reading one map and the next
+pub(crate) fn take(
+    claims: &RwLock<HashMap<String, Claims<Actor>>>,
+    caps: &RwLock<HashMap<RouteKey, CapabilityDescriptor>>,
+    bindings: &RwLock<Bindings>,
+    labels: &RwLock<HashMap<String, String>>,
+) -> HostSnapshot {
+    let claims = claims.read_or_recover();
+    let caps = caps.read_or_recover();
+    let bindings = bindings.read_or_recover();
+    let labels = labels.read_or_recover();
+
+    let mut actors: Vec<_> = claims
+        .iter()
+        .map(|(pk, c)| (pk.to_string(), c.clone()))
+        .collect();
+    actors.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut bindings: Vec<_> = bindings
+        .iter()
+        .map(|(k, config)| {
+            let mut value_names: Vec<_> = config.values.keys().cloned().collect();
+            value_names.sort();
+            BindingSnapshot {
+                actor: k.actor.to_string(),
+                capid: k.capid.to_string(),
+                binding: k.binding.to_string(),
+                config_name: k.config_name.clone(),
+                value_names,
+            }
+        })
+        .collect();
+    bindings.sort_by(|a, b| {
+        (&a.actor, &a.capid, &a.binding, &a.config_name).cmp(&(
+            &b.actor,
+            &b.capid,
+            &b.binding,
+            &b.config_name,
+        ))
+    });
+    HostSnapshot {
+        actors,
+        capabilities: caps
+            .iter()
+            .map(|(rk, d)| {
+                (
+                    (rk.binding_name.to_string(), rk.capid.to_string()),
+                    d.clone(),
+                )
+            })
+            .collect(),
+        bindings,
+        labels: labels.clone(),
+        taken_at: SystemTime::now(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::take;
+    use crate::bindings::Bindings;
+    use crate::RouteKey;
+    use std::collections::HashMap;
+    use std::sync::RwLock;
+    use wascc_codec::capabilities::{CapabilityConfiguration, CapabilityDescriptor};
+
+    #[test]
+    fn snapshot_copies_host_state() {
+        let claims = RwLock::new(HashMap::new());
+        let mut caps = HashMap::new();
+        caps.insert(
+            RouteKey::new("default", "wascc:keyvalue"),
+            CapabilityDescriptor::builder().id("wascc:keyvalue").build(),
+        );
+        let mut bindings = Bindings::default();
+        let mut values = HashMap::new();
+        values.insert("URL".to_string(), "redis://secret@127.0.0.1".to_string());
+        values.insert("DB".to_string(), "0".to_string());
+        bindings.insert_named(
+            "Mxxx",
+            "wascc:keyvalue",
+            "default",
+            Some("cache"),
+            CapabilityConfiguration {
+                module: "Mxxx".to_string(),
+                values,
+            },
+        );
+        let mut labels = HashMap::new();
+        labels.insert("region".to_string(), "west".to_string());
+
+        let snapshot = take(
+            &claims,
+            &RwLock::new(caps),
+            &RwLock::new(bindings),
+            &RwLock::new(labels),
+        );
+        assert!(snapshot.actors.is_empty());
+        assert!(snapshot
+            .capabilities
+            .contains_key(&("default".to_string(), "wascc:keyvalue".to_string())));
+        assert_eq!(1, snapshot.bindings.len());
+        assert_eq!(Some("cache".to_string()), snapshot.bindings[0].config_name);
+        assert_eq!(vec!["DB", "URL"], snapshot.bindings[0].value_names);
+        assert_eq!("west", snapshot.labels["region"]);
+    }
+}
@@ -1,10 +1,23 @@
+use crate::locks::RwLockExt;
 use crate::Result;
 
+use crate::actorinfo::ActorRuntime;
+use crate::audit::AuditLog;
+use crate::authz::CapabilityAllowlist;
+use crate::bindings::Bindings;
+use crate::bus::DeliveryMode;
+use crate::errors::{self, ErrorKind};
+use crate::events::HostEvent;
+use crate::health::HealthMonitor;
+use crate::imports::ImportReport;
+use crate::inflight::InFlight;
 use crate::inthost::*;
-use crate::BindingsList;
+use crate::middleware::ErrorPolicy;
+use crate::signer::InvocationSigner;
+use crate::timings::TimingRegistry;
 use crate::{
     bus::MessageBus, dispatch::WasccNativeDispatcher, plugins::PluginManager, Authorizer,
-    Invocation, InvocationResponse, Middleware, RouteKey,
+    Invocation, InvocationResponse, Middleware, RouteKey, CONFIG_WASCC_CONFIG_NAME,
 };
 use crate::{middleware, NativeCapability};
 
@@ -16,19 +29,38 @@ use latticeclient::BusEvent;
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 use std::thread;
+use std::time::Instant;
 use wapc::{WapcHost, WasiParams};
-use wascap::{jwt::Claims, prelude::KeyPair};
+use wascap::jwt::Claims;
 use wascc_codec::{
     capabilities::{CapabilityDescriptor, OP_GET_CAPABILITY_DESCRIPTOR},
-    core::{CapabilityConfiguration, OP_BIND_ACTOR, OP_REMOVE_ACTOR},
+    core::{CapabilityConfiguration, OP_BIND_ACTOR, OP_HEALTH_REQUEST, OP_REMOVE_ACTOR},
     deserialize, serialize, SYSTEM_ACTOR,
 };
 
+/// The channels of a thread started by `spawn_actor`
+pub(crate) struct SpawnedActor {
+    pub failed: Receiver<errors::Error>,
+    pub exited: Receiver<()>,
+}
+
+// The channels on which a provider instance's root thread hands configuration operations for an
+// already-bound actor to the thread serving that binding, keyed by the binding's subject, so that
+// they're handled in turn with the actor's calls to the provider (see the bus module)
+type BoundRoutes = Arc<RwLock<HashMap<String, Sender<(Invocation, Sender<InvocationResponse>)>>>>;
+
+// Re-establishes the lattice's bindings to a provider instance in this host, returning how many
+// were delivered to it. Bindings the instance has already accepted with the same configuration
+// are skipped unless forced
+pub(crate) type Rebinder = Arc<dyn Fn(bool) -> usize + Send + Sync>;
+
 /// Spawns a new background thread in which a new `WapcHost` is created for the actor
 /// module bytes. A message bus subscription is created either for the actor's RPC
 /// subject OR for the capability provider's root subject. We then select between a receive
 /// invocation on the subscription's channel or a receive invocation on the terminator channel,
-/// which will then trigger a cleanup of the actor's resources.
+/// which will then trigger a cleanup of the actor's resources. The returned channels receive
+/// the reason a capability provider failed to start, if it did, before the wait group is released,
+/// and a signal once the thread has finished cleaning up after being terminated.
 pub(crate) fn spawn_actor(
     wg: WaitGroup,
     claims: Claims<wascap::jwt::Actor>,
@@ -36,30 +68,43 @@ pub(crate) fn spawn_actor(
     wasi: Option<WasiParams>,
     actor: bool,
     binding: Option<String>,
+    default_binding: String,
     bus: Arc<MessageBus>,
     mids: Arc<RwLock<Vec<Box<dyn Middleware>>>>,
+    policy: ErrorPolicy,
     caps: Arc<RwLock<HashMap<RouteKey, CapabilityDescriptor>>>,
-    bindings: Arc<RwLock<BindingsList>>,
+    bindings: Arc<RwLock<Bindings>>,
     claimsmap: Arc<RwLock<HashMap<String, Claims<wascap::jwt::Actor>>>>,
     terminators: Arc<RwLock<HashMap<String, Sender<bool>>>>,
-    hk: KeyPair,
+    signer: Arc<InvocationSigner>,
     auth: Arc<RwLock<Box<dyn Authorizer>>>,
     image_map: Arc<RwLock<HashMap<String, String>>>,
     imgref: Option<String>,
-) -> Result<()> {
+    labels: Arc<RwLock<HashMap<String, String>>>,
+    ns: Option<String>,
+    audit: Arc<AuditLog>,
+    health: Arc<HealthMonitor>,
+    in_flight: Arc<InFlight>,
+    runtime: Arc<ActorRuntime>,
+    allowlist: Arc<CapabilityAllowlist>,
+    delivery: DeliveryMode,
+    timings: Arc<TimingRegistry>,
+) -> Result<SpawnedActor> {
+    let (failed_s, failed_r) = channel::bounded(1);
+    let (exited_s, exited_r) = channel::bounded(1);
     let c = claims.clone();
     let b = bus.clone();
-    let seed = hk.seed().unwrap();
-    let s = seed.clone();
-    let hostkey = KeyPair::from_seed(&hk.seed().unwrap()).unwrap();
+    let s = signer.clone();
+    let host_id = signer.host_id().to_string();
     let authorizer = auth.clone();
+    let allowlist2 = allowlist.clone();
 
     thread::spawn(move || {
-        let hk = KeyPair::from_seed(&seed).unwrap();
+        let started = Instant::now();
         if actor {
             #[cfg(feature = "lattice")]
             let _ = bus.publish_event(BusEvent::ActorStarting {
-                host: hostkey.public_key(),
+                host: host_id.to_string(),
                 actor: claims.subject.to_string(),
             });
         }
@@ -68,37 +113,69 @@ pub(crate) fn spawn_actor(
         #[cfg(feature = "wasm3")]
         let engine = wasm3_provider::Wasm3EngineProvider::new(&buf);
 
-        let mut guest = WapcHost::new(Box::new(engine), move |_id, bd, ns, op, payload| {
-            let key = KeyPair::from_seed(&s).unwrap();
+        let mut guest = WapcHost::new(Box::new(engine), move |_id, bd, nsarg, op, payload| {
+            let authz_ctx = crate::authz::authorization_context(
+                s.host_id(),
+                &labels,
+                ns.as_ref().map(String::as_str),
+            );
             wapc_host_callback(
-                key,
+                &s,
                 c.clone(),
                 bus.clone(),
                 bd,
-                ns,
+                &default_binding,
+                nsarg,
                 op,
                 payload,
                 authorizer.clone(),
+                &authz_ctx,
+                &health,
+                &allowlist2,
             )
         })
         .unwrap();
+        let mut phases = vec![("engine", started.elapsed())];
         let mut d: Option<CapabilityDescriptor> = None;
+        let mut dialect = DescriptorDialect::Empty;
 
         let subscribe_subject = if actor {
             b.actor_subject(&claims.subject)
         } else {
+            let descriptor_start = Instant::now();
             d = match get_descriptor(&mut guest) {
-                Ok(d) => Some(d),
+                Ok((d, dl)) => {
+                    dialect = dl;
+                    Some(d)
+                }
                 Err(_) => None,
             };
+            phases.push(("descriptor", descriptor_start.elapsed()));
             if d.is_none() {
+                let _ = failed_s.send(errors::new(ErrorKind::CapabilityProvider(
+                    "Failed to query the provider's capability descriptor".to_string(),
+                )));
                 return "".to_string();
             }
             let capid = d.as_ref().unwrap().id.to_string();
+            if let Err(e) = allowlist.check(&capid) {
+                let msg = e.to_string();
+                let _ = failed_s.send(e);
+                return msg;
+            }
             let bname = binding.clone().unwrap();
+            let route_key = RouteKey::new(&bname, &capid);
+            let mut lock = caps.write_or_recover();
+            if lock.contains_key(&route_key) {
+                let msg = format!("Capability provider {} cannot be bound to the same name ({}) twice, loading failed.", capid, bname);
+                let _ = failed_s.send(errors::new(ErrorKind::CapabilityProvider(msg.to_string())));
+                return msg;
+            }
+            lock.insert(route_key, d.clone().unwrap());
+            drop(lock);
             #[cfg(feature = "lattice")]
             let _ = b.publish_event(BusEvent::ProviderLoaded {
-                host: hostkey.public_key(),
+                host: host_id.to_string(),
                 capid: capid.to_string(),
                 instance_name: bname.to_string(),
             });
@@ -114,82 +191,165 @@ pub(crate) fn spawn_actor(
         if subscribe_subject.is_empty() {
             return "can't subscribe to message bus".to_string();
         }
+        // Lets this thread clean up after itself if its bus subscription goes away
+        let own_term = term_s.clone();
         terminators
-            .write()
-            .unwrap()
+            .write_or_recover()
             .insert(subscribe_subject.clone(), term_s);
-        let _ = b.subscribe(&subscribe_subject, inv_s, resp_r).unwrap();
+        // Every instance of a portable provider in a lattice needs to receive binding configuration,
+        // whereas invocations of an actor reach its instances according to its delivery mode
+        let config_subject = match d {
+            Some(ref d) => b.provider_config_subject(&d.id, binding.as_ref().unwrap()),
+            None => String::new(),
+        };
+        let subscribe_start = Instant::now();
+        if actor {
+            let _ = b
+                .subscribe_with_mode(&subscribe_subject, &delivery, inv_s, resp_r)
+                .unwrap();
+        } else {
+            let _ = b
+                .subscribe_provider(&subscribe_subject, &config_subject, inv_s, resp_r)
+                .unwrap();
+        }
+        phases.push(("subscribe", subscribe_start.elapsed()));
+        let start_duration = started.elapsed();
+        // An actor's total is recorded by the host, which also validates it first
+        let prefix = match d {
+            Some(ref d) => {
+                phases.push(("add", start_duration));
+                format!("provider.{}.{}", d.id, binding.as_ref().unwrap())
+            }
+            None => format!("actor.{}", claims.subject),
+        };
+        for (phase, duration) in phases {
+            timings.record(&format!("{}.{}", prefix, phase), duration);
+        }
+        let counters = if actor {
+            b.publish_host_event(HostEvent::ActorStarted {
+                actor: claims.subject.to_string(),
+                start_duration,
+                annotations: runtime.annotations(&claims.subject),
+            });
+            for m in mids.read_or_recover().iter() {
+                m.actor_started(&claims.subject, start_duration);
+            }
+            let imports = ImportReport::from_module(&buf).unwrap_or_default();
+            Some(runtime.start(&claims.subject, start_duration, imports))
+        } else {
+            None
+        };
         drop(wg); // Let the Host wrapper function return
         if actor {
             #[cfg(feature = "lattice")]
             let _ = b.publish_event(BusEvent::ActorStarted {
-                host: hostkey.public_key(),
+                host: host_id.to_string(),
                 actor: claims.subject.to_string(),
             });
-            info!("Actor {} up and running.", &claims.subject);
+            info!(
+                "Actor {} up and running (started in {:?}).",
+                &claims.subject, start_duration
+            );
         }
+        let mut stopping = false;
         loop {
-            let key = KeyPair::from_seed(&seed).unwrap();
             select! {
                 recv(inv_r) -> inv => {
                     if let Ok(inv) = inv {
+                        let _busy = in_flight.begin(&inv.target);
+                        let ctx = middleware::InvocationContext::gather(&inv, &claimsmap, &caps, &bindings);
                         let inv_r = if actor {
-                            middleware::invoke_actor(mids.clone(), inv.clone(), &mut guest).unwrap()
+                            middleware::invoke_actor(mids.clone(), inv.clone(), &mut guest, &ctx, bus.stats(), &audit, policy).unwrap()
                         } else {
                             if inv.operation != OP_BIND_ACTOR && inv.operation != OP_GET_CAPABILITY_DESCRIPTOR {
                                 InvocationResponse::error(&inv, "Attempted to invoke binding-required operation on unbound provider")
+                            } else if inv.operation == OP_GET_CAPABILITY_DESCRIPTOR && inv.msg != dialect.payload() {
+                                // Descriptor requests are passed on in the form the provider answered when it was loaded. Changing
+                                // the payload would break the request's signature, so a new request is signed in its place and
+                                // the answer is returned as the answer to the original
+                                let mut request = signer.invocation(inv.origin.clone(), inv.target.clone(), &inv.operation, dialect.payload());
+                                request.origin_chain = inv.origin_chain.clone();
+                                debug!("Passing descriptor request {} to {} as {:?} request {}", inv.id, claims.subject, dialect, request.id);
+                                let mut inv_r = middleware::invoke_portable_capability(mids.clone(), request, &mut guest, &ctx, bus.stats(), policy).unwrap();
+                                inv_r.invocation_id = inv.id.to_string();
+                                inv_r
                             } else {
-                                middleware::invoke_portable_capability(mids.clone(), inv.clone(), &mut guest).unwrap()
+                                middleware::invoke_portable_capability(mids.clone(), inv.clone(), &mut guest, &ctx, bus.stats(), policy).unwrap()
                             }
                         };
-                        resp_s.send(inv_r.clone()).unwrap();
-                        if inv.operation == OP_BIND_ACTOR && !actor && inv_r.error.is_none() {
+                        if let Some(ref c) = counters {
+                            c.record(&inv_r);
+                        }
+                        if resp_s.send(inv_r.clone()).is_err() {
+                            response_undeliverable(&b, &subscribe_subject);
+                            stop_once(&own_term, &mut stopping);
+                        } else if inv.operation == OP_BIND_ACTOR && !actor && inv_r.error.is_none() {
                             spawn_bound_portable_capability();
                         }
+                    } else {
+                        stop_once(&own_term, &mut stopping);
                     }
                 },
                 recv(term_r) -> _term => {
                     info!("Terminating {} {}", if actor { "actor" } else { "capability" }, &claims.subject);
                     let _ = b.unsubscribe(&subscribe_subject);
-                    terminators.write().unwrap().remove(&subscribe_subject);
                     if !actor {
+                        let _ = b.unsubscribe(&config_subject);
                         //#[cfg(feature = "lattice")]
-                        //let _ = bus.publish_event(BusEvent::ProviderRemoved{ host: hostkey.public_key(), actor: claims.subject.to_string() });
-                        remove_cap(caps.clone(), &d.as_ref().unwrap().id, binding.as_ref().unwrap()); // for cap providers, route key is the capid
-                        unbind_all_from_cap(bindings.clone(), &d.unwrap().id, binding.as_ref().unwrap());
+                        //let _ = bus.publish_event(BusEvent::ProviderRemoved{ host: host_id.to_string(), actor: claims.subject.to_string() });
+                        // Bindings go first, so the host never lists a binding to a provider it no longer has
+                        unbind_all_from_cap(bindings.clone(), &d.as_ref().unwrap().id, binding.as_ref().unwrap());
+                        remove_cap(caps.clone(), &d.unwrap().id, binding.as_ref().unwrap()); // for cap providers, route key is the capid
+                        // Only once the provider is gone from the host, which `Host::remove_capability` waits for
+                        terminators.write_or_recover().remove(&subscribe_subject);
                     } else {
+                        terminators.write_or_recover().remove(&subscribe_subject);
                         #[cfg(feature = "lattice")]
-                        let _ = b.publish_event(BusEvent::ActorStopped{ host: hostkey.public_key(), actor: claims.subject.to_string() });
+                        let _ = b.publish_event(BusEvent::ActorStopped{ host: host_id.to_string(), actor: claims.subject.to_string() });
 
-                        let mut lock = claimsmap.write().unwrap();
+                        let mut lock = claimsmap.write_or_recover();
                         let _ = lock.remove(&claims.subject);
                         drop(lock);
                         if let Some(ref ir) = imgref { // if this actor was added via OCI image ref, remove the mapping
-                            let mut lock = image_map.write().unwrap();
+                            let mut lock = image_map.write_or_recover();
                             let _ = lock.remove(ir);
                             drop(lock);
                         }
-                        deconfigure_actor(key,b.clone(), bindings.clone(), &claims.subject);
+                        deconfigure_actor(&signer, b.clone(), bindings.clone(), &claims.subject);
+                        b.publish_host_event(HostEvent::ActorStopped { actor: claims.subject.to_string(), annotations: runtime.annotations(&claims.subject) });
+                        if let Some(ref c) = counters {
+                            runtime.stop(&claims.subject, c);
+                        }
                     }
+                    let _ = exited_s.send(());
                     break "".to_string(); // TODO: WHY WHY WHY does this recv arm need to return a value?!?!?
                 }
             }
         }
     });
 
-    Ok(())
+    Ok(SpawnedActor {
+        failed: failed_r,
+        exited: exited_r,
+    })
 }
 
 pub(crate) fn spawn_native_capability(
     capability: NativeCapability,
     bus: Arc<MessageBus>,
     mids: Arc<RwLock<Vec<Box<dyn Middleware>>>>,
-    bindings: Arc<RwLock<BindingsList>>,
+    policy: ErrorPolicy,
+    bindings: Arc<RwLock<Bindings>>,
+    claims: Arc<RwLock<HashMap<String, Claims<wascap::jwt::Actor>>>>,
+    caps: Arc<RwLock<HashMap<RouteKey, CapabilityDescriptor>>>,
     terminators: Arc<RwLock<HashMap<String, Sender<bool>>>>,
     plugins: Arc<RwLock<PluginManager>>,
     wg: WaitGroup,
-    hk: Arc<KeyPair>,
+    signer: Arc<InvocationSigner>,
+    in_flight: Arc<InFlight>,
+    rebinders: Arc<RwLock<HashMap<RouteKey, Rebinder>>>,
 ) -> Result<()> {
+    let started = Instant::now();
     let capid = capability.id().to_string();
     let binding = capability.binding_name.to_string();
     let b = bus.clone();
@@ -197,13 +357,19 @@ pub(crate) fn spawn_native_capability(
     let b2 = bus.clone();
     let mid2 = mids.clone();
     let binding2 = bindings.clone();
+    let claims2 = claims.clone();
+    let caps2 = caps.clone();
     let plugin2 = plugins.clone();
-    let h2 = hk.clone();
+    let h2 = signer.clone();
     let t2 = terminators.clone();
     let capid2 = capid.clone();
     let bindingname2 = binding.clone();
+    let in_flight2 = in_flight.clone();
+    let routes: BoundRoutes = Arc::new(RwLock::new(HashMap::new()));
+    let routes2 = routes.clone();
+    let rebinders2 = rebinders.clone();
 
-    plugins.write().unwrap().add_plugin(capability)?;
+    plugins.write_or_recover().add_plugin(capability)?;
 
     thread::spawn(move || {
         let (inv_s, inv_r): (Sender<Invocation>, Receiver<Invocation>) = channel::unbounded();
@@ -211,58 +377,97 @@ pub(crate) fn spawn_native_capability(
             channel::unbounded();
         let (term_s, term_r): (Sender<bool>, Receiver<bool>) = channel::unbounded();
         let subscribe_subject = bus.provider_subject(&capid, &binding);
+        let config_subject = bus.provider_config_subject(&capid, &binding);
 
-        let _ = bus.nqsubscribe(&subscribe_subject, inv_s, resp_r).unwrap();
-        let dispatcher = WasccNativeDispatcher::new(hk.clone(), bus.clone(), &capid, &binding);
+        let _ = bus
+            .subscribe_provider(&subscribe_subject, &config_subject, inv_s, resp_r)
+            .unwrap();
+        let dispatcher = WasccNativeDispatcher::new(signer.clone(), bus.clone(), &capid, &binding);
         plugins
-            .write()
-            .unwrap()
+            .write_or_recover()
             .register_dispatcher(&binding, &capid, dispatcher)
             .unwrap();
+        let own_term = term_s.clone();
         terminators
-            .write()
-            .unwrap()
+            .write_or_recover()
             .insert(subscribe_subject.to_string(), term_s);
 
-        info!("Native capability provider '({},{})' ready", binding, capid);
+        let load_duration = started.elapsed();
+        bus.publish_host_event(HostEvent::ProviderStarted {
+            capid: capid.to_string(),
+            binding: binding.to_string(),
+            load_duration,
+        });
+        for m in mids.read_or_recover().iter() {
+            m.capability_started(&capid, &binding, load_duration);
+        }
+        info!(
+            "Native capability provider '({},{})' ready (loaded in {:?})",
+            binding, capid, load_duration
+        );
 
         drop(wg);
         #[cfg(feature = "lattice")]
         let _ = b.publish_event(BusEvent::ProviderLoaded {
-            host: hk.public_key(),
+            host: signer.host_id().to_string(),
             capid: capid.to_string(),
             instance_name: binding.to_string(),
         });
 
+        let mut stopping = false;
         loop {
             select! {
                 recv(inv_r) -> inv => {
                     if let Ok(inv) = inv {
-                        let inv_r = if inv.operation != OP_BIND_ACTOR && inv.operation != OP_GET_CAPABILITY_DESCRIPTOR && inv.operation != OP_REMOVE_ACTOR {
+                        let _busy = in_flight.begin(&inv.target);
+                        let inv_r = if inv.operation != OP_BIND_ACTOR && inv.operation != OP_GET_CAPABILITY_DESCRIPTOR && inv.operation != OP_REMOVE_ACTOR && inv.operation != OP_HEALTH_REQUEST {
                             InvocationResponse::error(&inv, "Attempted to invoke binding-required operation on unbound provider")
+                        } else if let Some(inv_r) = route_to_bound(&routes, &bus, &inv, &capid, &binding) {
+                            inv_r
                         } else {
-                            middleware::invoke_native_capability(mids.clone(), inv.clone(), plugins.clone()).unwrap()
+                            let ctx = middleware::InvocationContext::gather(&inv, &claims, &caps, &bindings);
+                            middleware::invoke_native_capability(mids.clone(), inv.clone(), plugins.clone(), &ctx, bus.stats(), policy).unwrap()
                         };
-                        resp_s.send(inv_r.clone()).unwrap();
+                        if resp_s.send(inv_r.clone()).is_err() {
+                            response_undeliverable(&bus, &subscribe_subject);
+                            stop_once(&own_term, &mut stopping);
+                        }
                         if inv.operation == OP_BIND_ACTOR && inv_r.error.is_none() {
-                            spawn_bound_native_capability(bus.clone(), inv.clone(), &capid, &binding, mids.clone(), plugins.clone(), terminators.clone(), bindings.clone(), hk.clone());
+                            #[cfg(feature = "lattice")]
+                            ack_binding(&bindings, &inv, &capid, &binding, signer.host_id());
+                            spawn_bound_native_capability(bus.clone(), inv.clone(), &capid, &binding, mids.clone(), policy, plugins.clone(), terminators.clone(), bindings.clone(), claims.clone(), caps.clone(), signer.clone(), in_flight.clone(), routes.clone());
                         }
                         if inv.operation == OP_REMOVE_ACTOR && inv_r.error.is_none() {
-                            let actor = actor_from_config(&inv.msg);
-                            let key = bus.provider_subject_bound_actor(&capid, &binding, &actor);
-                            terminators.read().unwrap()[&key].send(true).unwrap();
+                            // Whatever this host's bindings say, the instance no longer holds the configuration
+                            if let Some(actor) = actor_from_config(&inv.msg) {
+                                bindings.write_or_recover().forget_acks(&actor, &capid, &binding);
+                            }
+                        }
+                        if inv.operation == OP_REMOVE_ACTOR && inv_r.error.is_none() && last_binding_removed(&inv.msg, &bindings, &capid, &binding) {
+                            if let Some(actor) = actor_from_config(&inv.msg) {
+                                let key = bus.provider_subject_bound_actor(&capid, &binding, &actor);
+                                match terminators.read_or_recover().get(&key) {
+                                    Some(t) => { let _ = t.send(true); },
+                                    None => warn!("No bound provider thread on {} to terminate", key),
+                                }
+                            }
                         }
+                    } else {
+                        stop_once(&own_term, &mut stopping);
                     }
                 },
                 recv(term_r) -> _term => {
                     info!("Terminating native capability provider {},{}", binding, capid);
+                    // Unsubscribe before forgetting the bindings, otherwise there's nothing left to unsubscribe
+                    unsub_all_bindings(bindings.clone(), bus.clone(), &capid, &binding);
                     unbind_all_from_cap(bindings.clone(), &capid, &binding);
-                    unsub_all_bindings(bindings.clone(), bus.clone(), &capid);
+                    rebinders.write_or_recover().remove(&RouteKey::new(&binding, &capid));
                     let _ = bus.unsubscribe(&subscribe_subject);
-                    plugins.write().unwrap().remove_plugin(&binding, &capid).unwrap();
-                    terminators.write().unwrap().remove(&subscribe_subject);
+                    let _ = bus.unsubscribe(&config_subject);
+                    let _ = plugins.write_or_recover().remove_plugin(&binding, &capid);
+                    terminators.write_or_recover().remove(&subscribe_subject);
                     #[cfg(feature="lattice")]
-                    let _ = b.publish_event(BusEvent::ProviderRemoved{ host: hk.public_key(), capid: capid.to_string(), instance_name: binding.to_string()});
+                    let _ = b.publish_event(BusEvent::ProviderRemoved{ host: signer.host_id().to_string(), capid: capid.to_string(), instance_name: binding.to_string()});
                     break;
                 }
             }
@@ -270,16 +475,29 @@ pub(crate) fn spawn_native_capability(
     });
 
     #[cfg(feature = "lattice")]
-    reestablish_bindings(
-        b2.clone(),
-        mid2.clone(),
-        binding2.clone(),
-        plugin2.clone(),
-        t2.clone(),
-        h2.clone(),
-        &capid2,
-        &bindingname2,
-    );
+    {
+        let route_key = RouteKey::new(&bindingname2, &capid2);
+        let rebind: Rebinder = Arc::new(move |force| {
+            reestablish_bindings(
+                b2.clone(),
+                mid2.clone(),
+                policy,
+                binding2.clone(),
+                claims2.clone(),
+                caps2.clone(),
+                plugin2.clone(),
+                t2.clone(),
+                h2.clone(),
+                in_flight2.clone(),
+                routes2.clone(),
+                &capid2,
+                &bindingname2,
+                force,
+            )
+        });
+        rebind(false);
+        rebinders2.write_or_recover().insert(route_key, rebind);
+    }
     Ok(())
 }
 
@@ -287,65 +505,275 @@ pub(crate) fn spawn_native_capability(
 fn reestablish_bindings(
     bus: Arc<MessageBus>,
     mids: Arc<RwLock<Vec<Box<dyn Middleware>>>>,
-    bindings: Arc<RwLock<BindingsList>>,
+    policy: ErrorPolicy,
+    bindings: Arc<RwLock<Bindings>>,
+    claims: Arc<RwLock<HashMap<String, Claims<wascap::jwt::Actor>>>>,
+    caps: Arc<RwLock<HashMap<RouteKey, CapabilityDescriptor>>>,
     plugins: Arc<RwLock<PluginManager>>,
     terminators: Arc<RwLock<HashMap<String, Sender<bool>>>>,
-    hk: Arc<KeyPair>,
+    signer: Arc<InvocationSigner>,
+    in_flight: Arc<InFlight>,
+    routes: BoundRoutes,
     capid: &str,
     binding_name: &str,
-) {
+    force: bool,
+) -> usize {
     // 1. load pre-existing bindings from bus
-    // 2. for each binding, invoke OP_BIND_ACTOR on the root capability
+    // 2. for each binding, invoke OP_BIND_ACTOR on the root capability, retrying with backoff,
+    //    unless the instance has already accepted the same configuration (a few bindings at a
+    //    time, each after a random delay)
     // 3.    if successful,  spawn the bound actor-capability comms thread
-    if let Ok(blist) = bus.query_bindings() {
-        for b in blist {
-            if b.capability_id == capid && b.binding_name == binding_name {
-                let cfgvals = CapabilityConfiguration {
-                    module: b.actor.to_string(),
-                    values: b.configuration.clone(),
-                };
-                let payload = serialize(&cfgvals).unwrap();
-                let inv = Invocation::new(
-                    &hk,
+    // 4. report the bindings that couldn't be re-established so they can be reconciled
+    let blist = match bus.query_bindings() {
+        Ok(blist) => blist,
+        Err(e) => {
+            error!(
+                "Failed to query bindings to re-establish for {},{}: {}",
+                capid, binding_name, e
+            );
+            return 0;
+        }
+    };
+    // There's only ever one instance of a provider in a host, and what it accepted is
+    // forgotten when it's removed
+    let instance = signer.host_id().to_string();
+    let mut pending = Vec::new();
+    for b in blist {
+        if b.capability_id != capid || b.binding_name != binding_name {
+            continue;
+        }
+        let cfgvals = CapabilityConfiguration {
+            module: b.actor.to_string(),
+            values: b.configuration.clone(),
+        };
+        let key = crate::bindings::BindingKey::named(
+            &b.actor,
+            capid,
+            binding_name,
+            b.configuration
+                .get(CONFIG_WASCC_CONFIG_NAME)
+                .map(String::as_str),
+        );
+        let hash = crate::bindings::config_hash(&cfgvals);
+        if !force && bindings.read_or_recover().acked(&key, &instance) == Some(hash) {
+            trace!(
+                "Binding between {} and {},{} is unchanged, not re-sending it",
+                &b.actor,
+                capid,
+                binding_name
+            );
+            continue;
+        }
+        pending.push((key, hash, serialize(&cfgvals).unwrap()));
+    }
+
+    // Each binding is delivered after a random delay, so the instances of a provider that all
+    // (re)start at once don't all rebind at once, and a few are delivered at a time, so a
+    // provider with many failing bindings isn't held up for the sum of their retries
+    let rebind = |actor: &str, payload: &[u8]| {
+        thread::sleep(crate::bus::stagger(crate::bus::REBIND_STAGGER));
+        crate::bus::REBIND_BACKOFF.retry(
+            &format!(
+                "re-establish binding {} to {},{}",
+                actor, capid, binding_name
+            ),
+            |_| {
+                let inv = signer.invocation(
                     WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
                     WasccEntity::Capability {
                         capid: capid.to_string(),
                         binding: binding_name.to_string(),
                     },
                     OP_BIND_ACTOR,
-                    payload,
+                    payload.to_vec(),
                 );
+                let ctx = middleware::InvocationContext::gather(&inv, &claims, &caps, &bindings);
                 let inv_r = middleware::invoke_native_capability(
                     mids.clone(),
                     inv.clone(),
                     plugins.clone(),
+                    &ctx,
+                    bus.stats(),
+                    policy,
                 )
-                .unwrap();
-                if inv_r.error.is_none() {
-                    info!(
-                        "Re-establishing binding between {} and {},{}",
-                        &b.actor, &capid, &binding_name
-                    );
-                    spawn_bound_native_capability(
-                        bus.clone(),
-                        inv.clone(),
-                        &capid,
-                        &binding_name,
-                        mids.clone(),
-                        plugins.clone(),
-                        terminators.clone(),
-                        bindings.clone(),
-                        hk.clone(),
-                    );
+                .map_err(|e| e.to_string())?;
+                match inv_r.error {
+                    None => Ok(inv),
+                    Some(e) => Err(e),
+                }
+            },
+        )
+    };
+    let (job_s, job_r) = channel::unbounded();
+    for i in 0..pending.len() {
+        let _ = job_s.send(i);
+    }
+    drop(job_s);
+    let (res_s, res_r) = channel::unbounded();
+    let (rebind, pending_ref) = (&rebind, &pending);
+    crossbeam_utils::thread::scope(|s| {
+        for _ in 0..crate::bus::REBIND_CONCURRENCY.min(pending.len()) {
+            let (job_r, res_s) = (job_r.clone(), res_s.clone());
+            s.spawn(move |_| {
+                for i in job_r.iter() {
+                    let (ref key, _, ref payload) = pending_ref[i];
+                    let _ = res_s.send((i, rebind(&key.actor, payload)));
                 }
+            });
+        }
+    })
+    .unwrap();
+    drop(res_s);
+    let mut outcomes: Vec<_> = res_r.iter().collect();
+    outcomes.sort_by_key(|(i, _)| *i);
+
+    let mut delivered = 0;
+    let mut failures = HashMap::new();
+    for (i, outcome) in outcomes {
+        let (key, hash, _) = pending[i].clone();
+        let actor = key.actor.to_string();
+        match outcome {
+            Ok(inv) => {
+                bindings.write_or_recover().ack(key, &instance, hash);
+                delivered += 1;
+                info!(
+                    "Re-establishing binding between {} and {},{}",
+                    &actor, &capid, &binding_name
+                );
+                spawn_bound_native_capability(
+                    bus.clone(),
+                    inv,
+                    &capid,
+                    &binding_name,
+                    mids.clone(),
+                    policy,
+                    plugins.clone(),
+                    terminators.clone(),
+                    bindings.clone(),
+                    claims.clone(),
+                    caps.clone(),
+                    signer.clone(),
+                    in_flight.clone(),
+                    routes.clone(),
+                );
             }
+            Err(e) => {
+                failures.insert(actor, e);
+            }
+        }
+    }
+    if failures.is_empty() {
+        return delivered;
+    }
+    let mut actors: Vec<_> = failures.keys().cloned().collect();
+    actors.sort();
+    error!(
+        "Failed to re-establish {} binding(s) to {},{}: {:?}",
+        failures.len(),
+        capid,
+        binding_name,
+        failures
+    );
+    bus.publish_host_event(HostEvent::BindingsNotReestablished {
+        capid: capid.to_string(),
+        binding: binding_name.to_string(),
+        failures,
+    });
+    let _ = bus.publish_lattice_event(crate::LatticeEvent::BindingsNotReestablished {
+        capid: capid.to_string(),
+        instance_name: binding_name.to_string(),
+        actors,
+        host: signer.host_id().to_string(),
+        reason: crate::ReasonCode::RebindFailed,
+    });
+    delivered
+}
+
+// Records that this host's instance of the provider accepted a binding, so that re-establishing
+// the bindings doesn't send it the same configuration again
+#[cfg(feature = "lattice")]
+fn ack_binding(
+    bindings: &Arc<RwLock<Bindings>>,
+    inv: &Invocation,
+    capid: &str,
+    binding: &str,
+    instance: &str,
+) {
+    if let Ok(config) = deserialize::<CapabilityConfiguration>(&inv.msg) {
+        let key = crate::bindings::BindingKey::named(
+            &config.module,
+            capid,
+            binding,
+            config
+                .values
+                .get(CONFIG_WASCC_CONFIG_NAME)
+                .map(String::as_str),
+        );
+        let hash = crate::bindings::delivered_config_hash(&config);
+        bindings.write_or_recover().ack(key, instance, hash);
+    }
+}
+
+fn actor_from_config(bytes: &[u8]) -> Option<String> {
+    match deserialize::<CapabilityConfiguration>(bytes) {
+        Ok(config) => Some(config.module),
+        Err(e) => {
+            warn!(
+                "Ignoring malformed binding configuration ({} bytes): {}",
+                bytes.len(),
+                e
+            );
+            None
         }
     }
 }
 
-fn actor_from_config(bytes: &[u8]) -> String {
-    let config: CapabilityConfiguration = deserialize(bytes).unwrap();
-    config.module
+// Hands a configuration operation for an actor that's already bound to the provider instance to
+// the thread serving the binding and waits for it to be handled there, after any of the actor's
+// calls that thread has already taken up. `None` if there's no such thread (as for an actor's
+// first binding), in which case the root thread handles the operation itself
+fn route_to_bound(
+    routes: &BoundRoutes,
+    bus: &MessageBus,
+    inv: &Invocation,
+    capid: &str,
+    binding: &str,
+) -> Option<InvocationResponse> {
+    if inv.operation != OP_BIND_ACTOR && inv.operation != OP_REMOVE_ACTOR {
+        return None;
+    }
+    let config: CapabilityConfiguration = deserialize(&inv.msg).ok()?;
+    let subject = bus.provider_subject_bound_actor(capid, binding, &config.module);
+    let route = routes.read_or_recover().get(&subject).cloned()?;
+    let (reply_s, reply_r) = channel::bounded(1);
+    route.send((inv.clone(), reply_s)).ok()?;
+    reply_r.recv().ok()
+}
+
+// Whether an OP_REMOVE_ACTOR leaves the actor with no binding to the provider, so that the
+// thread serving the actor's calls to it can stop. Removing a named binding only forgets that
+// binding, leaving the thread to the actor's others
+fn last_binding_removed(
+    bytes: &[u8],
+    bindings: &RwLock<Bindings>,
+    capid: &str,
+    binding: &str,
+) -> bool {
+    // A configuration that can't be read is never taken to release the actor's last binding
+    let config: CapabilityConfiguration = match deserialize(bytes) {
+        Ok(c) => c,
+        Err(_) => return false,
+    };
+    match config.values.get(CONFIG_WASCC_CONFIG_NAME) {
+        Some(name) => {
+            let mut bindings = bindings.write_or_recover();
+            bindings.remove_named(&config.module, capid, binding, Some(name));
+            bindings
+                .config_names(&config.module, capid, binding)
+                .is_empty()
+        }
+        None => true,
+    }
 }
 
 // This is a thread that handles the private conversations between an actor and a capability.
@@ -356,46 +784,79 @@ fn spawn_bound_native_capability(
     capid: &str,
     binding: &str,
     middlewares: Arc<RwLock<Vec<Box<dyn Middleware>>>>,
+    policy: ErrorPolicy,
     plugins: Arc<RwLock<PluginManager>>,
     terminators: Arc<RwLock<HashMap<String, Sender<bool>>>>,
-    bindings: Arc<RwLock<BindingsList>>,
-    hk: Arc<KeyPair>,
+    bindings: Arc<RwLock<Bindings>>,
+    claims: Arc<RwLock<HashMap<String, Claims<wascap::jwt::Actor>>>>,
+    caps: Arc<RwLock<HashMap<RouteKey, CapabilityDescriptor>>>,
+    signer: Arc<InvocationSigner>,
+    in_flight: Arc<InFlight>,
+    routes: BoundRoutes,
 ) {
     let capid = capid.to_string();
     let binding = binding.to_string();
 
-    let config: CapabilityConfiguration = deserialize(&inv.msg).unwrap();
-    let actor = config.module.to_string();
+    let actor = match actor_from_config(&inv.msg) {
+        Some(actor) => actor,
+        None => return,
+    };
     let mids = middlewares.clone();
-    let terms = terminators.clone();
+
+    let subscribe_subject = bus.provider_subject_bound_actor(&capid, &binding, &actor);
+    let (term_s, term_r): (Sender<bool>, Receiver<bool>) = channel::unbounded();
+    {
+        let mut terms = terminators.write_or_recover();
+        if terms.contains_key(&subscribe_subject) {
+            // The actor is already bound under another configuration name, and one thread
+            // serves all of its bindings to the provider
+            return;
+        }
+        terms.insert(subscribe_subject.to_string(), term_s.clone());
+    }
+    let (cfg_s, cfg_r) = channel::unbounded();
+    routes
+        .write_or_recover()
+        .insert(subscribe_subject.to_string(), cfg_s);
 
     thread::spawn(move || {
         let (inv_s, inv_r): (Sender<Invocation>, Receiver<Invocation>) = channel::unbounded();
         let (resp_s, resp_r): (Sender<InvocationResponse>, Receiver<InvocationResponse>) =
             channel::unbounded();
-        let subscribe_subject = bus.provider_subject_bound_actor(&capid, &binding, &actor);
-        let (term_s, term_r): (Sender<bool>, Receiver<bool>) = channel::unbounded();
 
         let _ = bus.subscribe(&subscribe_subject, inv_s, resp_r).unwrap();
-        terms
-            .write()
-            .unwrap()
-            .insert(subscribe_subject.to_string(), term_s.clone());
 
+        let mut stopping = false;
         loop {
             select! {
                 recv(inv_r) -> inv => {
                     if let Ok(inv) = inv {
-                        let inv_r = middleware::invoke_native_capability(mids.clone(), inv.clone(), plugins.clone()).unwrap();
-                        resp_s.send(inv_r).unwrap();
+                        let _busy = in_flight.begin(&inv.target);
+                        let ctx = middleware::InvocationContext::gather(&inv, &claims, &caps, &bindings);
+                        let inv_r = middleware::invoke_native_capability(mids.clone(), inv.clone(), plugins.clone(), &ctx, bus.stats(), policy).unwrap();
+                        if resp_s.send(inv_r).is_err() {
+                            response_undeliverable(&bus, &subscribe_subject);
+                            stop_once(&term_s, &mut stopping);
+                        }
+                    } else {
+                        stop_once(&term_s, &mut stopping);
+                    }
+                },
+                recv(cfg_r) -> work => {
+                    if let Ok((inv, reply)) = work {
+                        let _busy = in_flight.begin(&inv.target);
+                        let ctx = middleware::InvocationContext::gather(&inv, &claims, &caps, &bindings);
+                        let inv_r = middleware::invoke_native_capability(mids.clone(), inv.clone(), plugins.clone(), &ctx, bus.stats(), policy).unwrap();
+                        let _ = reply.send(inv_r);
                     }
                 },
                 recv(term_r) -> _term => {
                     let _ = bus.unsubscribe(&subscribe_subject);
                     remove_binding(bindings.clone(), &actor, &binding, &capid);
-                    terminators.write().unwrap().remove(&subscribe_subject);
+                    terminators.write_or_recover().remove(&subscribe_subject);
+                    routes.write_or_recover().remove(&subscribe_subject);
                     #[cfg(feature="lattice")]
-                    let _ = bus.publish_event(BusEvent::ProviderRemoved{ host: hk.public_key(), capid: capid.to_string(), instance_name: binding.to_string()});
+                    let _ = bus.publish_event(BusEvent::ProviderRemoved{ host: signer.host_id().to_string(), capid: capid.to_string(), instance_name: binding.to_string()});
                     break;
                 }
             }
@@ -406,3 +867,96 @@ fn spawn_bound_native_capability(
 fn spawn_bound_portable_capability() {
     todo!()
 }
+
+/// Subscribes to the system actor's subject, to which capability providers dispatch requests
+/// meant for the host itself rather than for an actor. The only such request is
+/// `OP_BINDING_FAILED`, which removes the binding and notifies the actor with `notify_op`.
+/// Every host in the lattice keeps its own copy of the bindings, so the subject is subscribed
+/// as a broadcast rather than shared with the other hosts
+pub(crate) fn spawn_system_subscriber(host: &crate::Host, notify_op: String) {
+    let bus = host.bus.clone();
+    let bindings = host.bindings.clone();
+    let claims = host.claims.clone();
+    let terminators = host.terminators.clone();
+    let signer = host.signer.clone();
+    let subject = bus.actor_subject(SYSTEM_ACTOR);
+
+    let (inv_s, inv_r): (Sender<Invocation>, Receiver<Invocation>) = channel::unbounded();
+    let (resp_s, resp_r): (Sender<InvocationResponse>, Receiver<InvocationResponse>) =
+        channel::unbounded();
+    let (term_s, term_r): (Sender<bool>, Receiver<bool>) = channel::unbounded();
+    if let Err(e) = bus.nqsubscribe(&subject, inv_s, resp_r) {
+        error!("Failed to subscribe to {}: {}", subject, e);
+        return;
+    }
+    terminators
+        .write_or_recover()
+        .insert(subject.clone(), term_s);
+
+    thread::spawn(move || loop {
+        select! {
+            recv(inv_r) -> inv => {
+                let inv = match inv {
+                    Ok(inv) => inv,
+                    Err(_) => break,
+                };
+                let failure = binding_failure(&inv);
+                let inv_r = match failure {
+                    Ok(_) => InvocationResponse::success(&inv, vec![]),
+                    Err(ref e) => InvocationResponse::error(&inv, e),
+                };
+                if resp_s.send(inv_r).is_err() {
+                    response_undeliverable(&bus, &subject);
+                }
+                // The provider may be waiting on this response from within its own handler, so
+                // the binding is only torn down once the request has been answered
+                // Each host that runs the actor notifies its own instance of it, and the host
+                // running the reporting provider instance releases the binding everywhere
+                if let Ok((actor, capid, binding, reason)) = failure {
+                    let notify = if claims.read_or_recover().contains_key(&actor) {
+                        Some(notify_op.as_str())
+                    } else {
+                        None
+                    };
+                    let releases = inv.host_id == signer.host_id();
+                    remove_failed_binding(&signer, &bus, &bindings, &actor, &capid, &binding, &reason, notify, releases);
+                }
+            },
+            recv(term_r) -> _term => {
+                let _ = bus.unsubscribe(&subject);
+                terminators.write_or_recover().remove(&subject);
+                break;
+            }
+        }
+    });
+}
+
+// Extracts the actor, capability ID, binding name, and reason from a provider's report of a
+// failed binding
+fn binding_failure(
+    inv: &Invocation,
+) -> std::result::Result<(String, String, String, String), String> {
+    if inv.operation != crate::OP_BINDING_FAILED {
+        return Err(format!(
+            "Operation {} is not supported by the host",
+            inv.operation
+        ));
+    }
+    let (capid, binding) = match inv.origin {
+        WasccEntity::Capability {
+            ref capid,
+            ref binding,
+        } => (capid.to_string(), binding.to_string()),
+        WasccEntity::Actor(_) => {
+            return Err("Only capability providers can report failed bindings".to_string())
+        }
+    };
+    let config: CapabilityConfiguration =
+        deserialize(&inv.msg).map_err(|e| format!("Invalid binding failure report: {}", e))?;
+    let reason = config
+        .values
+        .get("reason")
+        .cloned()
+        .unwrap_or_else(|| "No reason given".to_string());
+    Ok((config.module, capid, binding, reason))
+}
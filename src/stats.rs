@@ -0,0 +1,214 @@
+//! Counts of the invocations refused by the host's authorization checks and halted by
+//! middleware, for `Host::stats` and for middleware such as the Prometheus exporter
+
+use crate::locks::{MutexExt, RwLockExt};
+use crate::middleware::Middleware;
+use crate::{Invocation, WasccEntity};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// The reason recorded for a denial when the actor's claims don't include the capability
+pub const REASON_MISSING_ATTESTATION: &str = "missing_attestation";
+/// The reason recorded for a denial when the host's `Authorizer` vetoed the call
+pub const REASON_AUTHORIZER_DENIED: &str = "authorizer_denied";
+
+/// Counts of the calls refused by this host since it started, as returned by `Host::stats`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HostStats {
+    /// Authorization denials, by reason (`REASON_MISSING_ATTESTATION` or
+    /// `REASON_AUTHORIZER_DENIED`)
+    pub authz_denials: HashMap<String, u64>,
+    /// Authorization denials, by the public key of the actor that was refused
+    pub authz_denials_by_origin: HashMap<String, u64>,
+    /// Authorization denials, by the URL of the entity the actor was refused access to, such as
+    /// `wasmbus://wascc/keyvalue/default`
+    pub authz_denials_by_target: HashMap<String, u64>,
+    /// Invocations answered by a middleware halting the pipeline, by the middleware's name
+    pub middleware_halts: HashMap<String, u64>,
+}
+
+// A denial whose middleware notification waits for the pipeline it happened in to complete
+struct Denial {
+    origin: String,
+    target: WasccEntity,
+    reason: String,
+}
+
+// Shared by the host and the threads serving its actors and providers, through the bus
+#[derive(Default)]
+pub(crate) struct StatsCounters {
+    stats: Mutex<HostStats>,
+    // Told of each denial made outside an invocation pipeline. Halts, and denials made while a
+    // pipeline runs, are reported by the middleware runner, which already holds the pipeline
+    middlewares: Arc<RwLock<Vec<Box<dyn Middleware>>>>,
+    deferred: Mutex<Vec<Denial>>,
+}
+
+impl StatsCounters {
+    pub fn new(middlewares: Arc<RwLock<Vec<Box<dyn Middleware>>>>) -> StatsCounters {
+        StatsCounters {
+            stats: Mutex::new(HostStats::default()),
+            middlewares,
+            deferred: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Records that the origin actor was refused access to the target, telling every
+    /// middleware in the pipeline. Must not be called while the pipeline is held
+    pub fn denied(&self, origin: &str, target: &WasccEntity, reason: &str) {
+        self.count_denial(origin, target, reason);
+        for m in self.middlewares.read_or_recover().iter() {
+            m.authorization_denied(origin, target, reason);
+        }
+    }
+
+    /// Records a denial made by a call out of an actor or portable provider, which runs while
+    /// the middleware runner holds the pipeline. Taking the pipeline lock again there could
+    /// deadlock behind a waiting writer, so middleware is told once the invocation completes
+    pub fn denied_in_pipeline(&self, origin: &str, target: &WasccEntity, reason: &str) {
+        self.count_denial(origin, target, reason);
+        self.deferred.lock_or_recover().push(Denial {
+            origin: origin.to_string(),
+            target: target.clone(),
+            reason: reason.to_string(),
+        });
+    }
+
+    /// Tells every middleware in the pipeline of the denials made while it was running
+    pub fn notify_deferred(&self, middlewares: &[Box<dyn Middleware>]) {
+        let denials: Vec<_> = self.deferred.lock_or_recover().drain(..).collect();
+        for d in denials {
+            for m in middlewares {
+                m.authorization_denied(&d.origin, &d.target, &d.reason);
+            }
+        }
+    }
+
+    fn count_denial(&self, origin: &str, target: &WasccEntity, reason: &str) {
+        let mut stats = self.stats.lock_or_recover();
+        increment(&mut stats.authz_denials, reason);
+        increment(&mut stats.authz_denials_by_origin, origin);
+        increment(&mut stats.authz_denials_by_target, &target.url());
+    }
+
+    /// Records that the middleware halted the invocation, telling every middleware in the
+    /// pipeline
+    pub fn halted(
+        &self,
+        middlewares: &[Box<dyn Middleware>],
+        halting: &dyn Middleware,
+        inv: &Invocation,
+    ) {
+        increment(
+            &mut self.stats.lock_or_recover().middleware_halts,
+            halting.name(),
+        );
+        for m in middlewares {
+            m.invocation_halted(halting.name(), inv);
+        }
+    }
+
+    pub fn snapshot(&self) -> HostStats {
+        self.stats.lock_or_recover().clone()
+    }
+}
+
+fn increment(counts: &mut HashMap<String, u64>, key: &str) {
+    *counts.entry(key.to_string()).or_insert(0) += 1;
+}
+
+#[cfg(test)]
+mod test {
+    use super::{StatsCounters, REASON_AUTHORIZER_DENIED, REASON_MISSING_ATTESTATION};
+    use crate::middleware::{InvocationHandler, Middleware, MiddlewareResponse};
+    use crate::{Invocation, InvocationResponse, Result, WasccEntity};
+    use std::sync::{Arc, Mutex, RwLock};
+
+    // Records the origins of the denials it's told of
+    struct DenialRecorder(Arc<Mutex<Vec<String>>>);
+
+    impl Middleware for DenialRecorder {
+        fn actor_pre_invoke(&self, inv: Invocation) -> Result<Invocation> {
+            Ok(inv)
+        }
+        fn actor_invoke(
+            &self,
+            inv: Invocation,
+            handler: InvocationHandler,
+        ) -> Result<MiddlewareResponse> {
+            Ok(MiddlewareResponse::Continue(handler.invoke(inv)))
+        }
+        fn actor_post_invoke(&self, response: InvocationResponse) -> Result<InvocationResponse> {
+            Ok(response)
+        }
+        fn capability_pre_invoke(&self, inv: Invocation) -> Result<Invocation> {
+            Ok(inv)
+        }
+        fn capability_invoke(
+            &self,
+            inv: Invocation,
+            handler: InvocationHandler,
+        ) -> Result<MiddlewareResponse> {
+            Ok(MiddlewareResponse::Continue(handler.invoke(inv)))
+        }
+        fn capability_post_invoke(
+            &self,
+            response: InvocationResponse,
+        ) -> Result<InvocationResponse> {
+            Ok(response)
+        }
+        fn authorization_denied(&self, origin: &str, _target: &WasccEntity, _reason: &str) {
+            self.0.lock().unwrap().push(origin.to_string());
+        }
+    }
+
+    #[test]
+    fn denials_counted_by_reason_origin_and_target() {
+        let counters = StatsCounters::default();
+        let kv = WasccEntity::Capability {
+            capid: "wascc:keyvalue".to_string(),
+            binding: "default".to_string(),
+        };
+        counters.denied("Mxxxx", &kv, REASON_AUTHORIZER_DENIED);
+        counters.denied("Mxxxx", &kv, REASON_MISSING_ATTESTATION);
+        counters.denied(
+            "Myyyy",
+            &WasccEntity::Actor("Mxxxx".to_string()),
+            REASON_AUTHORIZER_DENIED,
+        );
+
+        let stats = counters.snapshot();
+        assert_eq!(2, stats.authz_denials[REASON_AUTHORIZER_DENIED]);
+        assert_eq!(1, stats.authz_denials[REASON_MISSING_ATTESTATION]);
+        assert_eq!(2, stats.authz_denials_by_origin["Mxxxx"]);
+        assert_eq!(1, stats.authz_denials_by_origin["Myyyy"]);
+        assert_eq!(
+            2,
+            stats.authz_denials_by_target["wasmbus://wascc/keyvalue/default"]
+        );
+        assert_eq!(1, stats.authz_denials_by_target["wasmbus://Mxxxx"]);
+        assert!(stats.middleware_halts.is_empty());
+    }
+
+    #[test]
+    fn denials_in_pipeline_notified_without_relocking() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let middlewares: Arc<RwLock<Vec<Box<dyn Middleware>>>> =
+            Arc::new(RwLock::new(vec![Box::new(DenialRecorder(seen.clone()))]));
+        let counters = StatsCounters::new(middlewares.clone());
+        let target = WasccEntity::Actor("Mxxxx".to_string());
+
+        // The pipeline is held for writing here, so taking it again would never return
+        let mut pipeline = middlewares.write().unwrap();
+        counters.denied_in_pipeline("Myyyy", &target, REASON_AUTHORIZER_DENIED);
+        assert_eq!(1, counters.snapshot().authz_denials_by_origin["Myyyy"]);
+        assert!(seen.lock().unwrap().is_empty());
+        counters.notify_deferred(&pipeline);
+        drop(pipeline);
+
+        assert_eq!(vec!["Myyyy".to_string()], *seen.lock().unwrap());
+        // Each deferred denial is delivered once
+        counters.notify_deferred(&middlewares.read().unwrap());
+        assert_eq!(1, seen.lock().unwrap().len());
+    }
+}
@@ -0,0 +1,297 @@
+//! # Testing Toolkit
+//!
+//! Helpers for unit testing actors against a waSCC host without having to build and load
+//! real capability providers. Enable this module with the `testing` feature flag.
+//!
+//! A [`MockCapability`](struct.MockCapability.html) is an in-process capability provider that
+//! answers operations with closures registered by the test and records every call it receives.
+//! The [`TestHost`](struct.TestHost.html) wires a host, an actor, and a mock provider together
+//! with a default binding in a single call.
+//!
+//! ```no_run
+//! use wascc_host::testing::TestHost;
+//!
+//! # fn main() -> wascc_host::Result<()> {
+//! let bytes = std::fs::read("./examples/.assets/kvcounter.wasm")?;
+//! let th = TestHost::with_actor_for(&bytes, "wascc:keyvalue")?;
+//! th.mock().on("AtomicAdd", |_actor, _msg| Ok(vec![]));
+//! // ... call the actor via th.host().call_actor(...) and assert on th.mock().calls()
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::locks::RwLockExt;
+use crate::{Actor, Host, NativeCapability, Result};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use wascc_codec::capabilities::{
+    CapabilityDescriptor, CapabilityProvider, Dispatcher, NullDispatcher,
+    OP_GET_CAPABILITY_DESCRIPTOR,
+};
+use wascc_codec::core::{CapabilityConfiguration, OP_BIND_ACTOR, OP_REMOVE_ACTOR};
+use wascc_codec::{serialize, SYSTEM_ACTOR};
+
+type OperationHandler =
+    dyn Fn(&str, &[u8]) -> std::result::Result<Vec<u8>, Box<dyn Error + Sync + Send>> + Send + Sync;
+
+/// A single call received by a [`MockCapability`](struct.MockCapability.html)
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedCall {
+    pub actor: String,
+    pub operation: String,
+    pub msg: Vec<u8>,
+}
+
+/// A capability provider for use in tests. Operations are answered by closures registered
+/// with `on`, bind and remove operations succeed by default, and every call (except the
+/// host's descriptor query) is recorded for later assertions. Clones of a mock share the
+/// same handlers and call log, so a test can keep a clone after installing the provider
+/// in a host via `NativeCapability::from_instance`.
+#[derive(Clone)]
+pub struct MockCapability {
+    capid: String,
+    handlers: Arc<RwLock<HashMap<String, Arc<OperationHandler>>>>,
+    calls: Arc<RwLock<Vec<RecordedCall>>>,
+    dispatcher: Arc<RwLock<Box<dyn Dispatcher>>>,
+}
+
+impl MockCapability {
+    /// Creates a new mock provider for the given capability ID, e.g. `wascc:keyvalue`
+    pub fn new(capid: &str) -> MockCapability {
+        MockCapability {
+            capid: capid.to_string(),
+            handlers: Arc::new(RwLock::new(HashMap::new())),
+            calls: Arc::new(RwLock::new(Vec::new())),
+            dispatcher: Arc::new(RwLock::new(Box::new(NullDispatcher::new()))),
+        }
+    }
+
+    /// Registers a handler for an operation. The closure receives the calling actor's public key
+    /// and the raw payload, and returns the raw response payload. Registering a handler for an
+    /// operation that already has one replaces it.
+    pub fn on<F>(&self, operation: &str, handler: F) -> &Self
+    where
+        F: Fn(&str, &[u8]) -> std::result::Result<Vec<u8>, Box<dyn Error + Sync + Send>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.handlers
+            .write_or_recover()
+            .insert(operation.to_string(), Arc::new(handler));
+        self
+    }
+
+    /// Returns the capability ID of this mock
+    pub fn id(&self) -> String {
+        self.capid.to_string()
+    }
+
+    /// Returns all of the calls received by this mock so far, in the order they arrived
+    pub fn calls(&self) -> Vec<RecordedCall> {
+        self.calls.read_or_recover().clone()
+    }
+
+    /// Returns all of the calls received by this mock for the given operation
+    pub fn calls_for(&self, operation: &str) -> Vec<RecordedCall> {
+        self.calls
+            .read_or_recover()
+            .iter()
+            .filter(|c| c.operation == operation)
+            .cloned()
+            .collect()
+    }
+
+    /// Clears the call log
+    pub fn clear_calls(&self) {
+        self.calls.write_or_recover().clear();
+    }
+
+    /// Sends a message to an actor through the dispatcher the host gave this provider, exactly
+    /// as a real provider would (e.g. an HTTP server delivering a request)
+    pub fn dispatch(
+        &self,
+        actor: &str,
+        operation: &str,
+        msg: &[u8],
+    ) -> std::result::Result<Vec<u8>, Box<dyn Error + Sync + Send>> {
+        self.dispatcher
+            .read_or_recover()
+            .dispatch(actor, operation, msg)
+    }
+
+    /// Reports to the host that this provider can no longer serve the actor's binding, as a
+    /// real provider would if, for example, the credentials it was configured with were
+    /// revoked. The host removes the binding (sending this mock `OP_REMOVE_ACTOR`) and
+    /// notifies the actor. See `OP_BINDING_FAILED`
+    pub fn fail_binding(
+        &self,
+        actor: &str,
+        reason: &str,
+    ) -> std::result::Result<(), Box<dyn Error + Sync + Send>> {
+        let mut values = HashMap::new();
+        values.insert("reason".to_string(), reason.to_string());
+        let report = CapabilityConfiguration {
+            module: actor.to_string(),
+            values,
+        };
+        self.dispatch(SYSTEM_ACTOR, crate::OP_BINDING_FAILED, &serialize(report)?)
+            .map(|_| ())
+    }
+
+    fn descriptor(&self) -> std::result::Result<Vec<u8>, Box<dyn Error + Sync + Send>> {
+        Ok(serialize(
+            CapabilityDescriptor::builder()
+                .id(&self.capid)
+                .name("Mock Capability Provider (Testing)")
+                .long_description("An in-process capability provider used for testing actors")
+                .version(crate::VERSION)
+                .revision(crate::REVISION)
+                .build(),
+        )?)
+    }
+}
+
+impl CapabilityProvider for MockCapability {
+    fn configure_dispatch(
+        &self,
+        dispatcher: Box<dyn Dispatcher>,
+    ) -> std::result::Result<(), Box<dyn Error + Send + Sync>> {
+        let mut lock = self.dispatcher.write_or_recover();
+        *lock = dispatcher;
+
+        Ok(())
+    }
+
+    fn handle_call(
+        &self,
+        actor: &str,
+        op: &str,
+        msg: &[u8],
+    ) -> std::result::Result<Vec<u8>, Box<dyn Error + Sync + Send>> {
+        if op == OP_GET_CAPABILITY_DESCRIPTOR && actor == SYSTEM_ACTOR {
+            return self.descriptor();
+        }
+        self.calls.write_or_recover().push(RecordedCall {
+            actor: actor.to_string(),
+            operation: op.to_string(),
+            msg: msg.to_vec(),
+        });
+        // Clone the handler out so the lock isn't held while test code runs
+        let handler = self.handlers.read_or_recover().get(op).cloned();
+        match handler {
+            Some(h) => h(actor, msg),
+            None if op == OP_BIND_ACTOR || op == OP_REMOVE_ACTOR => Ok(vec![]),
+            None => Err(format!("No mock handler registered for operation {}", op).into()),
+        }
+    }
+}
+
+/// A host with a single actor bound to a single mock capability provider
+pub struct TestHost {
+    host: Host,
+    mock: MockCapability,
+    actor: String,
+}
+
+impl TestHost {
+    /// Creates a host, adds the actor from the given module bytes, and binds it (with the
+    /// default binding name and no configuration values) to a mock provider for the first
+    /// capability the actor is attested for, other than `wascc:extras` and `wascc:hostmeta`.
+    pub fn with_actor(bytes: &[u8]) -> Result<TestHost> {
+        let actor = Actor::from_slice(bytes)?;
+        let capid = actor
+            .capabilities()
+            .into_iter()
+            .find(|c| c != crate::extras::CAPABILITY_ID && c != crate::hostmeta::CAPABILITY_ID)
+            .ok_or_else(|| {
+                crate::errors::Error::from(format!(
+                    "Actor {} is not attested for any capabilities to mock",
+                    actor.public_key()
+                ))
+            })?;
+        Self::start(actor, &capid)
+    }
+
+    /// Creates a host, adds the actor from the given module bytes, and binds it (with the
+    /// default binding name and no configuration values) to a mock provider for the given
+    /// capability ID
+    pub fn with_actor_for(bytes: &[u8], capid: &str) -> Result<TestHost> {
+        Self::start(Actor::from_slice(bytes)?, capid)
+    }
+
+    fn start(actor: Actor, capid: &str) -> Result<TestHost> {
+        let host = Host::new();
+        let pk = actor.public_key();
+        let mock = MockCapability::new(capid);
+        host.add_actor(actor)?;
+        host.add_native_capability(NativeCapability::from_instance(mock.clone(), None)?)?;
+        host.set_binding(&pk, capid, None, HashMap::new())?;
+        Ok(TestHost {
+            host,
+            mock,
+            actor: pk,
+        })
+    }
+
+    /// The running host
+    pub fn host(&self) -> &Host {
+        &self.host
+    }
+
+    /// The mock provider bound to the actor
+    pub fn mock(&self) -> &MockCapability {
+        &self.mock
+    }
+
+    /// The public key of the actor under test
+    pub fn actor(&self) -> String {
+        self.actor.to_string()
+    }
+
+    /// Invokes an operation on the actor under test
+    pub fn call_actor(&self, operation: &str, msg: &[u8]) -> Result<Vec<u8>> {
+        self.host.call_actor(&self.actor, operation, msg)
+    }
+
+    /// Shuts down the underlying host
+    pub fn shutdown(&self) -> Result<()> {
+        self.host.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MockCapability;
+    use wascc_codec::capabilities::{CapabilityProvider, OP_GET_CAPABILITY_DESCRIPTOR};
+    use wascc_codec::core::OP_BIND_ACTOR;
+    use wascc_codec::SYSTEM_ACTOR;
+
+    #[test]
+    fn mock_records_calls() {
+        let mock = MockCapability::new("testing:mock");
+        mock.on("DoThing", |actor, msg| {
+            let mut v = actor.as_bytes().to_vec();
+            v.extend_from_slice(msg);
+            Ok(v)
+        });
+        let clone = mock.clone();
+
+        assert!(mock
+            .handle_call(SYSTEM_ACTOR, OP_GET_CAPABILITY_DESCRIPTOR, &[])
+            .is_ok());
+        assert!(mock.handle_call(SYSTEM_ACTOR, OP_BIND_ACTOR, &[1]).is_ok());
+        assert_eq!(
+            mock.handle_call("Mxxx", "DoThing", b"abc").unwrap(),
+            b"Mxxxabc".to_vec()
+        );
+        assert!(mock.handle_call("Mxxx", "Unknown", &[]).is_err());
+
+        // Descriptor queries are not recorded, and clones share the call log
+        assert_eq!(clone.calls().len(), 3);
+        assert_eq!(clone.calls_for("DoThing")[0].msg, b"abc".to_vec());
+        clone.clear_calls();
+        assert!(mock.calls().is_empty());
+    }
+}
@@ -0,0 +1,135 @@
+// Durations of the phases of a host's startup and shutdown, and of adding actors and capability
+// providers, for tracking performance across releases without parsing logs
+
+use crate::locks::MutexExt;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// The most timings a host keeps. Adding and removing actors for long enough would otherwise
+// grow the registry without bound, so the oldest are dropped first
+const TIMINGS_CAPACITY: usize = 1_000;
+
+/// The time taken by a single phase of the host's work, such as building an actor's engine
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    any(feature = "lattice", feature = "manifest", feature = "admin_api"),
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct TimingEntry {
+    /// The phase, as dot-separated segments naming what was timed, e.g.
+    /// `actor.MB4OLDIC....engine` or `shutdown.actors`
+    pub name: String,
+    pub duration: Duration,
+}
+
+/// The timings recorded by a host, as returned by `Host::timings`. The phases recorded are:
+///
+/// | Name | Phase |
+/// |------|-------|
+/// | `host.generate.keys` | Creating the host's keys and invocation signer |
+/// | `host.generate.builtins` | Registering the built-in providers (extras, hostmeta, logging) |
+/// | `host.generate` | Building the host, in all |
+/// | `actor.{pk}.fetch` | Downloading the actor from a registry |
+/// | `actor.{pk}.validate` | Checking the actor's claims, imports, and authorization |
+/// | `actor.{pk}.engine` | Building the actor's WebAssembly engine and waPC host |
+/// | `actor.{pk}.subscribe` | Subscribing to the actor's subject |
+/// | `actor.{pk}.add` | Adding the actor, in all |
+/// | `provider.{capid}.{binding}.fetch` | Downloading a native provider from a registry |
+/// | `provider.{capid}.{binding}.dlopen` | Loading a native provider's library |
+/// | `provider.{capid}.{binding}.descriptor` | Querying the provider for its descriptor |
+/// | `provider.{capid}.{binding}.engine` | Building a portable provider's engine |
+/// | `provider.{capid}.{binding}.subscribe` | Subscribing to a portable provider's subject |
+/// | `provider.{capid}.{binding}.add` | Adding the provider to the host, in all |
+/// | `manifest.apply` | Applying a manifest, in all |
+/// | `shutdown.drain` | Waiting for invocations in flight |
+/// | `shutdown.actors` | Removing the actors |
+/// | `shutdown.providers` | Removing the capability providers |
+/// | `shutdown.terminations` | Waiting for every processing thread to exit |
+/// | `shutdown.hooks` | Running the shutdown hooks |
+/// | `shutdown` | Shutting down, in all |
+///
+/// Each name appears at most once, with the latest duration recorded for it (e.g. for an actor
+/// that has been removed and added again)
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(
+    any(feature = "lattice", feature = "manifest", feature = "admin_api"),
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct HostTimings {
+    /// The timings, in the order in which they were recorded
+    pub entries: Vec<TimingEntry>,
+}
+
+impl HostTimings {
+    /// Returns the duration recorded for the named phase, if any
+    pub fn get(&self, name: &str) -> Option<Duration> {
+        self.entries
+            .iter()
+            .find(|e| e.name == name)
+            .map(|e| e.duration)
+    }
+}
+
+/// Collects the timings of a host's phases
+#[derive(Default)]
+pub(crate) struct TimingRegistry {
+    entries: Mutex<VecDeque<TimingEntry>>,
+}
+
+impl TimingRegistry {
+    /// Records the duration of the named phase, replacing any recorded before
+    pub(crate) fn record(&self, name: &str, duration: Duration) {
+        let mut entries = self.entries.lock_or_recover();
+        entries.retain(|e| e.name != name);
+        if entries.len() >= TIMINGS_CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(TimingEntry {
+            name: name.to_string(),
+            duration,
+        });
+    }
+
+    /// Records the time elapsed since the given instant for the named phase
+    pub(crate) fn since(&self, name: &str, start: Instant) {
+        self.record(name, start.elapsed());
+    }
+
+    pub(crate) fn snapshot(&self) -> HostTimings {
+        HostTimings {
+            entries: self.entries.lock_or_recover().iter().cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TimingRegistry, TIMINGS_CAPACITY};
+    use std::time::Duration;
+
+    #[test]
+    fn latest_timing_kept_per_name() {
+        let registry = TimingRegistry::default();
+        registry.record("a", Duration::from_millis(1));
+        registry.record("b", Duration::from_millis(2));
+        registry.record("a", Duration::from_millis(3));
+        let timings = registry.snapshot();
+        assert_eq!(2, timings.entries.len());
+        assert_eq!("b", timings.entries[0].name);
+        assert_eq!(Some(Duration::from_millis(3)), timings.get("a"));
+        assert_eq!(None, timings.get("c"));
+    }
+
+    #[test]
+    fn oldest_timings_dropped_at_capacity() {
+        let registry = TimingRegistry::default();
+        for i in 0..TIMINGS_CAPACITY + 2 {
+            registry.record(&format!("t{}", i), Duration::from_millis(1));
+        }
+        let timings = registry.snapshot();
+        assert_eq!(TIMINGS_CAPACITY, timings.entries.len());
+        assert_eq!(None, timings.get("t1"));
+        assert!(timings.get("t2").is_some());
+    }
+}
@@ -0,0 +1,121 @@
+//! Watching actor module files for changes so they can be automatically hot-swapped during
+//! development. Enabled with the `watch` feature flag.
+
+use crate::errors::{self, ErrorKind};
+use crate::locks::RwLockExt;
+use crate::{Actor, Host, HostEvent, Result};
+use crossbeam::{Receiver, Sender};
+use crossbeam_channel as channel;
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+// Editors and build tools frequently write a file several times in quick succession
+const WATCH_DEBOUNCE_MS: u64 = 500;
+const WATCH_POLL_MS: u64 = 250;
+
+/// A handle to an active actor file watch. Dropping the handle stops the watch.
+pub struct WatchHandle {
+    stop: Sender<bool>,
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        let _ = self.stop.send(true);
+    }
+}
+
+impl Host {
+    /// Watches the given WebAssembly module file and, whenever it changes, reloads it and
+    /// replaces the running actor with the new module via `replace_actor`. The new module
+    /// must have the same subject (public key) as the running actor. Failures to load or validate
+    /// a new version of the file are logged and emitted as host events, but do not stop the watch.
+    /// The watch remains active until the returned handle is dropped.
+    pub fn watch_actor(&self, pk: &str, path: PathBuf) -> Result<WatchHandle> {
+        if !self.claims.read_or_recover().contains_key(pk) {
+            return Err(errors::new(ErrorKind::MiscHost(format!(
+                "Cannot watch file for actor {}, actor is not running",
+                pk
+            ))));
+        }
+        let path = path.canonicalize()?;
+        // Watch the parent directory rather than the file so that tools which replace
+        // the file (rather than writing to it) don't end the watch
+        let dir = path.parent().map(Path::to_path_buf).ok_or_else(|| {
+            errors::new(ErrorKind::MiscHost(format!(
+                "Cannot determine directory of {}",
+                path.display()
+            )))
+        })?;
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut watcher = notify::watcher(tx, Duration::from_millis(WATCH_DEBOUNCE_MS))
+            .map_err(|e| errors::new(ErrorKind::MiscHost(format!("File watch failed: {}", e))))?;
+        watcher
+            .watch(&dir, RecursiveMode::NonRecursive)
+            .map_err(|e| errors::new(ErrorKind::MiscHost(format!("File watch failed: {}", e))))?;
+
+        let (stop_s, stop_r): (Sender<bool>, Receiver<bool>) = channel::bounded(1);
+        let host = self.clone();
+        let pk = pk.to_string();
+        info!("Watching {} for changes to actor {}", path.display(), pk);
+
+        thread::spawn(move || {
+            let _watcher = watcher; // Dropping the watcher ends the watch
+            loop {
+                if stop_r.try_recv().is_ok() {
+                    info!("Stopped watching {}", path.display());
+                    break;
+                }
+                match rx.recv_timeout(Duration::from_millis(WATCH_POLL_MS)) {
+                    Ok(DebouncedEvent::Write(p))
+                    | Ok(DebouncedEvent::Create(p))
+                    | Ok(DebouncedEvent::Rename(_, p))
+                        if p == path =>
+                    {
+                        host.swap_watched_actor(&pk, &path)
+                    }
+                    Ok(_) => {}
+                    Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+                    Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+
+        Ok(WatchHandle { stop: stop_s })
+    }
+
+    fn swap_watched_actor(&self, pk: &str, path: &Path) {
+        let res = Actor::from_file(path).and_then(|a| {
+            if a.public_key() == pk {
+                self.replace_actor(a)
+            } else {
+                Err(errors::new(ErrorKind::MiscHost(format!(
+                    "Module subject {} does not match watched actor",
+                    a.public_key()
+                ))))
+            }
+        });
+        match res {
+            Ok(_) => {
+                info!("Actor {} updated from {}", pk, path.display());
+                self.bus.publish_host_event(HostEvent::ActorUpdated {
+                    actor: pk.to_string(),
+                });
+            }
+            Err(e) => {
+                error!(
+                    "Failed to update actor {} from {}: {}",
+                    pk,
+                    path.display(),
+                    e
+                );
+                self.bus.publish_host_event(HostEvent::ActorUpdateFailed {
+                    actor: pk.to_string(),
+                    reason: e.to_string(),
+                });
+            }
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use std::error::Error;
-use wascc_host::{Actor, Authorizer, Host, HostBuilder, NativeCapability};
+use wascc_host::{
+    Actor, AuthorizationContext, Authorizer, Host, HostBuilder, HostEvent, NativeCapability,
+    REASON_AUTHORIZER_DENIED, REASON_MISSING_ATTESTATION,
+};
 
 pub(crate) fn default_authorizer_enforces_cap_attestations() -> Result<(), Box<dyn Error>> {
     // Attempt to bind an actor to a capability for which it isn't authorized.
@@ -18,13 +21,18 @@ pub(crate) fn default_authorizer_enforces_cap_attestations() -> Result<(), Box<d
         None,
     )?)?;
 
+    let events = host.events();
     let res = host.set_binding(
         "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ",
         "wascc:messaging",
         None,
         crate::common::empty_config(),
     );
-    assert_eq!(res.err().unwrap().to_string(), "WebAssembly module authorization failure: Unauthorized binding: actor MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ is not authorized to use capability wascc:messaging.");
+    assert_eq!(res.err().unwrap().to_string(), "WebAssembly module authorization failure: Unauthorized binding: actor MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ is not attested for capability wascc:messaging (operation BindActor on binding default).");
+    assert!(matches!(
+        events.try_recv()?,
+        HostEvent::AuthorizationDenied { ref capid, .. } if capid == "wascc:messaging"
+    ));
     host.shutdown()?;
     std::thread::sleep(::std::time::Duration::from_millis(500));
     Ok(())
@@ -67,6 +75,50 @@ pub(crate) fn authorizer_blocks_bindings() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+pub(crate) fn authorizer_denials_counted() -> Result<(), Box<dyn Error>> {
+    // Denials are counted by reason, by the refused actor, and by the target
+    #[cfg(not(feature = "lattice"))]
+    let host = HostBuilder::new()
+        .with_authorizer(DenyAuthorizer::new(false, true))
+        .build();
+
+    #[cfg(feature = "lattice")]
+    let host = HostBuilder::new()
+        .with_lattice_namespace("authorizerdenials")
+        .with_authorizer(DenyAuthorizer::new(false, true))
+        .build();
+
+    host.add_actor(Actor::from_file("./examples/.assets/kvcounter.wasm")?)?;
+    assert!(host.stats().authz_denials.is_empty());
+
+    let actor = "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ";
+    // Attested, but vetoed by the authorizer
+    assert!(host
+        .set_binding(actor, "wascc:keyvalue", None, crate::common::empty_config())
+        .is_err());
+    // Not attested
+    assert!(host
+        .set_binding(
+            actor,
+            "wascc:messaging",
+            None,
+            crate::common::empty_config()
+        )
+        .is_err());
+
+    let stats = host.stats();
+    assert_eq!(1, stats.authz_denials[REASON_AUTHORIZER_DENIED]);
+    assert_eq!(1, stats.authz_denials[REASON_MISSING_ATTESTATION]);
+    assert_eq!(2, stats.authz_denials_by_origin[actor]);
+    assert_eq!(
+        1,
+        stats.authz_denials_by_target["wasmbus://wascc/keyvalue/default"]
+    );
+    host.shutdown()?;
+    std::thread::sleep(::std::time::Duration::from_millis(500));
+    Ok(())
+}
+
 pub(crate) fn authorizer_blocks_load() -> Result<(), Box<dyn Error>> {
     // Set the authorizer before calling a bind_actor, and bind_actor should
     // return permission denied / Err if the authorizer denies that invocation.
@@ -82,6 +134,225 @@ pub(crate) fn authorizer_blocks_load() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+pub(crate) fn authorizer_chain_blocks_load() -> Result<(), Box<dyn Error>> {
+    // Every authorizer in the chain has to allow the load
+    let host = HostBuilder::new()
+        .with_authorizers(vec![
+            Box::new(DenyAuthorizer::new(false, false)) as Box<dyn Authorizer>,
+            Box::new(DenyAuthorizer::new(true, false)),
+        ])
+        .build();
+
+    let res = host.add_actor(Actor::from_file("./examples/.assets/kvcounter.wasm")?);
+
+    assert!(res.is_err());
+    host.shutdown()?;
+    std::thread::sleep(::std::time::Duration::from_millis(500));
+    Ok(())
+}
+
+pub(crate) fn authorizer_uses_host_labels() -> Result<(), Box<dyn Error>> {
+    // An authorizer that only permits loading actors on hosts labeled env=prod
+    let host = HostBuilder::new()
+        .with_label("env", "dev")
+        .with_authorizer(LabelAuthorizer::new("env", "prod"))
+        .build();
+    let res = host.add_actor(Actor::from_file("./examples/.assets/kvcounter.wasm")?);
+    assert!(res.is_err());
+    host.shutdown()?;
+
+    let host = HostBuilder::new()
+        .with_label("env", "prod")
+        .with_authorizer(LabelAuthorizer::new("env", "prod"))
+        .build();
+    host.add_actor(Actor::from_file("./examples/.assets/kvcounter.wasm")?)?;
+    host.shutdown()?;
+    std::thread::sleep(::std::time::Duration::from_millis(500));
+    Ok(())
+}
+
+pub(crate) fn authorizer_blocks_replace() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use wascc_codec::http::{Request, Response, OP_HANDLE_REQUEST};
+    use wascc_codec::{deserialize, serialize};
+
+    let kvcounter = "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ";
+    let host = HostBuilder::new()
+        .with_authorizer(ChangeAuthorizer::new(true, false))
+        .build();
+    let events = host.events();
+    host.add_actor(Actor::from_file("./examples/.assets/kvcounter.wasm")?)?;
+    host.add_native_capability(NativeCapability::from_file(
+        "./examples/.assets/libkeyvalue.so",
+        None,
+    )?)?;
+    host.set_binding(kvcounter, "wascc:keyvalue", None, HashMap::new())?;
+
+    let res = host.replace_actor(Actor::from_file(
+        "./examples/.assets/kvcounter_tweaked.wasm",
+    )?);
+    assert!(res.is_err());
+    assert!(events.try_iter().any(|e| e
+        == HostEvent::ActorReplaceDenied {
+            actor: kvcounter.to_string()
+        }));
+
+    // The running actor is untouched and keeps serving requests
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/counter".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    let resp: Response = deserialize(&host.call_actor(kvcounter, OP_HANDLE_REQUEST, &req)?)?;
+    assert_eq!(200, resp.status_code);
+
+    host.shutdown()?;
+    std::thread::sleep(::std::time::Duration::from_millis(500));
+    Ok(())
+}
+
+pub(crate) fn authorizer_blocks_terminate() -> Result<(), Box<dyn Error>> {
+    use wascc_host::REQUESTED_BY_HOST;
+
+    let echo = "MB4OLDIC3TCZ4Q4TGGOVAZC43VXFE2JQVRAXQMQFXUCREOOFEKOKZTY2";
+    let host = HostBuilder::new()
+        .with_authorizer(ChangeAuthorizer::new(false, true))
+        .build();
+    let events = host.events();
+    host.add_actor(Actor::from_file("./examples/.assets/echo.wasm")?)?;
+
+    assert!(host.remove_actor(echo).is_err());
+    assert!(events.try_iter().any(|e| e
+        == HostEvent::ActorTerminationDenied {
+            actor: echo.to_string(),
+            requested_by: REQUESTED_BY_HOST.to_string(),
+        }));
+    assert!(host.actors().iter().any(|(pk, _)| pk == echo));
+
+    // Shutting down removes the actor regardless
+    host.shutdown()?;
+    std::thread::sleep(::std::time::Duration::from_millis(500));
+    Ok(())
+}
+
+pub(crate) fn actor_only_host_rejects_capabilities() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use wascc_codec::http::{Request, Response};
+    use wascc_codec::{deserialize, serialize};
+    use wascc_host::{WasccEntity, WasiParams};
+
+    let echo = "MB4OLDIC3TCZ4Q4TGGOVAZC43VXFE2JQVRAXQMQFXUCREOOFEKOKZTY2";
+    let kvcounter = "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ";
+    let host = HostBuilder::new()
+        .actor_only()
+        .with_extras_provider(true)
+        .build();
+    let events = host.events();
+
+    // Not even the built-in providers are added
+    assert!(host.capabilities().is_empty());
+    assert_eq!(Some(vec![]), host.info().capability_allowlist);
+    for err in vec![
+        host.add_native_capability(NativeCapability::from_file(
+            "./examples/.assets/libkeyvalue.so",
+            None,
+        )?)
+        .unwrap_err(),
+        host.add_capability(
+            Actor::from_file("./examples/.assets/wasi_provider.wasm")?,
+            None,
+            WasiParams::default(),
+        )
+        .unwrap_err(),
+    ] {
+        assert!(
+            err.to_string()
+                .contains("capabilities disabled on this host"),
+            "{}",
+            err
+        );
+    }
+    assert!(host.capabilities().is_empty());
+
+    host.add_actor(Actor::from_file("./examples/.assets/echo.wasm")?)?;
+    host.add_actor(Actor::from_file("./examples/.assets/kvcounter.wasm")?)?;
+    let req = serialize(&Request {
+        method: "GET".to_string(),
+        path: "/counter".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    assert!(host.call_actor(echo, "HandleRequest", &req).is_ok());
+
+    // The counter's call to its key-value store is turned away before reaching the bus
+    assert!(host.call_actor(kvcounter, "HandleRequest", &req).is_err());
+    assert!(events.try_iter().any(|e| matches!(
+        e,
+        HostEvent::AuthorizationDenied { ref actor, ref capid, ref reason, .. }
+            if actor == kvcounter
+                && capid == "wascc:keyvalue"
+                && reason == "capabilities disabled on this host"
+    )));
+
+    // Actors can still call each other
+    let forwarder = crate::common::generate_forwarder_actor(&[echo])?;
+    let forwarder_pk = forwarder.public_key();
+    host.add_actor(forwarder)?;
+    let mut msg = echo.as_bytes().to_vec();
+    msg.extend_from_slice(&req);
+    let resp: Response = deserialize(&host.call_actor(&forwarder_pk, "HandleRequest", &msg)?)?;
+    assert_eq!(200, resp.status_code);
+    let body: serde_json::Value = serde_json::from_slice(&resp.body)?;
+    assert_eq!("/counter", body["path"]);
+    let last = host.actor_recent_invocations(echo).pop().unwrap();
+    assert_eq!(
+        WasccEntity::Actor(forwarder_pk.to_string()).url(),
+        last.origin
+    );
+
+    host.shutdown()?;
+    std::thread::sleep(::std::time::Duration::from_millis(500));
+    Ok(())
+}
+
+struct LabelAuthorizer {
+    key: String,
+    value: String,
+}
+
+impl LabelAuthorizer {
+    pub(crate) fn new(key: &str, value: &str) -> LabelAuthorizer {
+        LabelAuthorizer {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+}
+
+impl Authorizer for LabelAuthorizer {
+    fn can_load(&self, _claims: &wascap::prelude::Claims<wascap::prelude::Actor>) -> bool {
+        false
+    }
+    fn can_invoke(
+        &self,
+        _claims: &wascap::prelude::Claims<wascap::prelude::Actor>,
+        _target: &wascc_host::WasccEntity,
+        _operation: &str,
+    ) -> bool {
+        true
+    }
+    fn can_load_ctx(
+        &self,
+        _claims: &wascap::prelude::Claims<wascap::prelude::Actor>,
+        ctx: &AuthorizationContext,
+    ) -> bool {
+        ctx.labels.get(&self.key) == Some(&self.value)
+    }
+}
+
 struct DenyAuthorizer {
     deny_load: bool,
     deny_invoke: bool,
@@ -108,3 +379,45 @@ impl Authorizer for DenyAuthorizer {
         !self.deny_invoke
     }
 }
+
+struct ChangeAuthorizer {
+    deny_replace: bool,
+    deny_terminate: bool,
+}
+
+impl ChangeAuthorizer {
+    pub(crate) fn new(deny_replace: bool, deny_terminate: bool) -> ChangeAuthorizer {
+        ChangeAuthorizer {
+            deny_replace,
+            deny_terminate,
+        }
+    }
+}
+
+impl Authorizer for ChangeAuthorizer {
+    fn can_load(&self, _claims: &wascap::prelude::Claims<wascap::prelude::Actor>) -> bool {
+        true
+    }
+    fn can_invoke(
+        &self,
+        _claims: &wascap::prelude::Claims<wascap::prelude::Actor>,
+        _target: &wascc_host::WasccEntity,
+        _operation: &str,
+    ) -> bool {
+        true
+    }
+    fn can_replace(
+        &self,
+        _old_claims: &wascap::prelude::Claims<wascap::prelude::Actor>,
+        _new_claims: &wascap::prelude::Claims<wascap::prelude::Actor>,
+    ) -> bool {
+        !self.deny_replace
+    }
+    fn can_terminate(
+        &self,
+        _claims: &wascap::prelude::Claims<wascap::prelude::Actor>,
+        _requested_by: &str,
+    ) -> bool {
+        !self.deny_terminate
+    }
+}
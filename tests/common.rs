@@ -1,7 +1,17 @@
 use std::io::{Read, Write};
+use std::sync::{Mutex, MutexGuard};
 use std::{collections::HashMap, error::Error};
 use wascc_host::{Actor, Host, NativeCapability};
 
+lazy_static::lazy_static! {
+    static ref ENV_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Serializes tests that modify the process environment
+pub fn env_lock() -> MutexGuard<'static, ()> {
+    ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
 pub fn get_hello_actor() -> Result<Actor, Box<dyn Error>> {
     Actor::from_file("./examples/.assets/echo.wasm").map_err(|e| e.into())
 }
@@ -10,6 +20,21 @@ pub fn get_hello2_actor() -> Result<Actor, Box<dyn Error>> {
     Actor::from_file("./examples/.assets/echo2.wasm").map_err(|e| e.into())
 }
 
+/// Serves "images" from the example assets directory, e.g. the reference
+/// `localhost/echo:v1` resolves to `./examples/.assets/echo.wasm`
+pub struct AssetFetcher {}
+
+impl wascc_host::ImageFetcher for AssetFetcher {
+    fn fetch(&self, image_ref: &str) -> wascc_host::Result<Vec<u8>> {
+        let name = image_ref
+            .rsplit('/')
+            .next()
+            .and_then(|s| s.split(':').next())
+            .unwrap_or_default();
+        std::fs::read(format!("./examples/.assets/{}.wasm", name)).map_err(|e| e.into())
+    }
+}
+
 pub fn gen_stock_host(first_port: u16) -> Result<Host, Box<dyn Error>> {
     let host = Host::new();
     host.add_actor(get_hello_actor()?)?;
@@ -101,3 +126,107 @@ pub fn generate_resigned_actor(bytes: &[u8]) -> Result<Actor, Box<dyn Error>> {
 
     Ok(wascc_host::Actor::from_slice(&embedded)?)
 }
+
+// Signs the module with claims that expired an hour ago, returning the module bytes
+pub fn generate_expired_actor(bytes: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use wascap::prelude::*;
+
+    let (issuer, module) = (KeyPair::new_account(), KeyPair::new_module());
+    let mut claims = ClaimsBuilder::<Actor>::new()
+        .issuer(&issuer.public_key())
+        .subject(&module.public_key())
+        .with_metadata(Actor {
+            name: Some("expired".to_string()),
+            caps: Some(vec![caps::KEY_VALUE.to_string()]),
+            ..Default::default()
+        })
+        .build();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    claims.issued_at = now - 7200;
+    claims.expires = Some(now - 3600);
+
+    Ok(wasm::embed_claims(&bytes, &claims, &issuer)?)
+}
+
+// Signs the module with claims valid from and until the given offsets (in seconds) from now,
+// returning the module bytes
+pub fn generate_timed_actor(
+    bytes: &[u8],
+    not_before: Option<i64>,
+    expires: Option<i64>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use wascap::prelude::*;
+
+    let (issuer, module) = (KeyPair::new_account(), KeyPair::new_module());
+    let mut claims = ClaimsBuilder::<Actor>::new()
+        .issuer(&issuer.public_key())
+        .subject(&module.public_key())
+        .with_metadata(Actor {
+            name: Some("timed".to_string()),
+            ..Default::default()
+        })
+        .build();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    claims.issued_at = (now - 7200) as u64;
+    claims.not_before = not_before.map(|o| (now + o) as u64);
+    claims.expires = expires.map(|o| (now + o) as u64);
+
+    Ok(wasm::embed_claims(&bytes, &claims, &issuer)?)
+}
+
+pub fn generate_tagged_actor(bytes: &[u8], tags: &[&str]) -> Result<Actor, Box<dyn Error>> {
+    use wascap::prelude::*;
+
+    let (issuer, module) = (KeyPair::new_account(), KeyPair::new_module());
+    let claims = ClaimsBuilder::<Actor>::new()
+        .issuer(&issuer.public_key())
+        .subject(&module.public_key())
+        .with_metadata(Actor {
+            name: Some("tagged".to_string()),
+            tags: Some(tags.iter().map(|t| t.to_string()).collect()),
+            ..Default::default()
+        })
+        .build();
+    let embedded = wasm::embed_claims(&bytes, &claims, &issuer)?;
+
+    Ok(wascc_host::Actor::from_slice(&embedded)?)
+}
+
+// Signs the forwarding actor in the fixtures (see `forwarder.wat`), allowing it to call the
+// given actors
+pub fn generate_forwarder_actor(targets: &[&str]) -> Result<Actor, Box<dyn Error>> {
+    sign_fixture("forwarder", targets)
+}
+
+// Signs the actor in the fixtures that reads the host metadata provider (see
+// `hostmeta_reader.wat`), attesting it for that capability
+pub fn generate_hostmeta_reader_actor() -> Result<Actor, Box<dyn Error>> {
+    sign_fixture("hostmeta_reader", &[wascc_host::hostmeta::CAPABILITY_ID])
+}
+
+// Signs the actor in the fixtures that keeps the configuration delivered to it (see
+// `config_recorder.wat`)
+pub fn generate_config_recorder_actor() -> Result<Actor, Box<dyn Error>> {
+    sign_fixture("config_recorder", &[])
+}
+
+fn sign_fixture(name: &str, caps: &[&str]) -> Result<Actor, Box<dyn Error>> {
+    use wascap::prelude::*;
+
+    let bytes = std::fs::read(format!("./tests/fixtures/{}.wasm", name))?;
+    let (issuer, module) = (KeyPair::new_account(), KeyPair::new_module());
+    let claims = ClaimsBuilder::<Actor>::new()
+        .issuer(&issuer.public_key())
+        .subject(&module.public_key())
+        .with_metadata(Actor {
+            name: Some(name.to_string()),
+            caps: Some(caps.iter().map(|c| c.to_string()).collect()),
+            ..Default::default()
+        })
+        .build();
+    let embedded = wasm::embed_claims(&bytes, &claims, &issuer)?;
+
+    Ok(wascc_host::Actor::from_slice(&embedded)?)
+}
@@ -31,8 +31,17 @@ pub(crate) fn stock_host() -> Result<(), Box<dyn Error>> {
 pub(crate) fn kv_host() -> Result<(), Box<dyn Error>> {
     use redis::Commands;
 
-    let host = crate::common::gen_kvcounter_host(8083, Host::new())?;
-    std::thread::sleep(::std::time::Duration::from_millis(100));
+    let host = Host::quick()
+        .with_actor_file("./examples/.assets/kvcounter.wasm")
+        .with_provider_file("./examples/.assets/libwascc_httpsrv.so", None)
+        .with_provider_file("./examples/.assets/libwascc_redis.so", None)
+        .bind(0, "wascc:keyvalue", crate::common::redis_config())
+        .bind(
+            0,
+            "wascc:http_server",
+            crate::common::generate_port_config(8083),
+        )
+        .start()?;
     let key = uuid::Uuid::new_v4().to_string();
     let rkey = format!(":{}", key); // the kv wasm logic does a replace on '/' with ':'
     let url = format!("http://localhost:8083/{}", key);
@@ -50,3 +59,2901 @@ pub(crate) fn kv_host() -> Result<(), Box<dyn Error>> {
     let _: () = con.del(&rkey)?;
     Ok(())
 }
+
+pub(crate) fn quick_host_default_binding() -> Result<(), Box<dyn Error>> {
+    use wascc_host::{HostBuilder, QuickHost};
+
+    // The provider is added without a binding name, so it takes the host's default
+    let host = QuickHost::new(
+        HostBuilder::new()
+            .with_default_binding_name("staging")
+            .build(),
+    )
+    .with_actor_file("./examples/.assets/echo.wasm")
+    .with_provider_file("./examples/.assets/libwascc_httpsrv.so", None)
+    .bind(
+        0,
+        "wascc:http_server",
+        crate::common::generate_port_config(8086),
+    )
+    .start()?;
+    assert_eq!(
+        vec![crate::common::get_hello_actor()?.public_key()],
+        host.provider_bindings("wascc:http_server", "staging")
+    );
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn actor_from_stub_registry() -> Result<(), Box<dyn Error>> {
+    use wascc_host::HostBuilder;
+
+    let host = HostBuilder::new()
+        .with_image_fetcher(crate::common::AssetFetcher {})
+        .build();
+    let pk = host.add_actor_from_registry("localhost/echo:v1")?;
+    assert_eq!(
+        pk,
+        "MB4OLDIC3TCZ4Q4TGGOVAZC43VXFE2JQVRAXQMQFXUCREOOFEKOKZTY2"
+    );
+    assert!(host.claims_for_actor(&pk).is_some());
+
+    assert!(host.add_actor_from_registry("localhost/nosuch:v1").is_err());
+    // An actor module is not a provider archive
+    assert!(host
+        .add_native_capability_from_registry("localhost/echo2:v1", None)
+        .is_err());
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn reserved_config_keys_rejected() -> Result<(), Box<dyn Error>> {
+    let host = Host::new();
+    host.add_actor(wascc_host::Actor::from_file(
+        "./examples/.assets/kvcounter.wasm",
+    )?)?;
+    let mut config = std::collections::HashMap::new();
+    config.insert(
+        wascc_host::CONFIG_WASCC_HOST_ID.to_string(),
+        "spoofed".to_string(),
+    );
+    let res = host.set_binding(
+        "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ",
+        "wascc:keyvalue",
+        None,
+        config,
+    );
+    assert!(res.is_err());
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn actor_invocations_audited() -> Result<(), Box<dyn Error>> {
+    use wascc_host::{Actor, HostBuilder, NativeCapability};
+
+    let echo = "MB4OLDIC3TCZ4Q4TGGOVAZC43VXFE2JQVRAXQMQFXUCREOOFEKOKZTY2";
+    let host = HostBuilder::new().with_audit_capacity(2).build();
+    host.add_actor(Actor::from_file("./examples/.assets/echo.wasm")?)?;
+    host.add_native_capability(NativeCapability::from_file(
+        "./examples/.assets/libwascc_httpsrv.so",
+        None,
+    )?)?;
+    host.set_binding(
+        echo,
+        "wascc:http_server",
+        None,
+        crate::common::generate_port_config(9095),
+    )?;
+    std::thread::sleep(::std::time::Duration::from_millis(500));
+    assert!(host.actor_recent_invocations(echo).is_empty());
+
+    for path in &["one", "two", "three"] {
+        let resp = reqwest::blocking::get(&format!("http://localhost:9095/{}", path))?;
+        assert!(resp.status().is_success());
+    }
+
+    // Only the two most recent invocations are kept
+    let entries = host.actor_recent_invocations(echo);
+    assert_eq!(2, entries.len());
+    for e in entries.iter() {
+        assert_eq!("HandleRequest", e.operation);
+        assert_eq!("wasmbus://wascc/http_server/default", e.origin);
+        assert!(e.payload_size > 0);
+        assert!(e.error.is_none());
+    }
+    assert!(entries[0].timestamp <= entries[1].timestamp);
+
+    host.shutdown()?;
+    assert!(host.actor_recent_invocations(echo).is_empty());
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+pub(crate) fn call_actor_with_codec_types() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use wascc_codec::http::{Request, Response};
+    use wascc_host::Actor;
+
+    let echo = "MB4OLDIC3TCZ4Q4TGGOVAZC43VXFE2JQVRAXQMQFXUCREOOFEKOKZTY2";
+    let host = Host::new();
+    host.add_actor(Actor::from_file("./examples/.assets/echo.wasm")?)?;
+
+    let req = Request {
+        method: "GET".to_string(),
+        path: "/typed".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    };
+    let resp: Response = host.call_actor_serialized(echo, "HandleRequest", &req)?;
+    assert_eq!(200, resp.status_code);
+    let body: serde_json::Value = serde_json::from_slice(&resp.body)?;
+    assert_eq!("/typed", body["path"]);
+
+    // A reply that doesn't match the expected type explains what it was replying to
+    let err = host
+        .call_actor_serialized::<_, Vec<u64>>(echo, "HandleRequest", &req)
+        .unwrap_err();
+    assert!(err.to_string().contains("reply to HandleRequest"));
+    assert!(err.to_string().contains("-byte"));
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn actors_by_tag_modes() -> Result<(), Box<dyn Error>> {
+    use crate::common::generate_tagged_actor;
+
+    let bytes = std::fs::read("./examples/.assets/echo.wasm")?;
+    let payments = generate_tagged_actor(&bytes, &["payment", "billing"])?;
+    let shipping = generate_tagged_actor(&bytes, &[" Shipping ", "billing"])?;
+    let untagged = generate_tagged_actor(&bytes, &[])?;
+    let (pay_pk, ship_pk) = (payments.public_key(), shipping.public_key());
+    let mut both = vec![pay_pk.to_string(), ship_pk.to_string()];
+    both.sort();
+
+    let host = Host::new();
+    host.add_actor(payments)?;
+    host.add_actor(shipping)?;
+    host.add_actor(untagged)?;
+
+    assert_eq!(vec![pay_pk.to_string()], host.actors_by_tag(&["payment"]));
+    assert_eq!(both, host.actors_by_tag(&["BILLING"]));
+    assert_eq!(
+        vec![ship_pk.to_string()],
+        host.actors_by_tag(&["shipping", "billing"])
+    );
+    assert!(host.actors_by_tag(&["payment", "shipping"]).is_empty());
+    assert_eq!(both, host.actors_by_tag_any(&["payment", "shipping "]));
+    assert!(host.actors_by_tag_any(&["inventory"]).is_empty());
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn lifecycle_hooks_run_in_order() -> Result<(), Box<dyn Error>> {
+    use std::sync::{Arc, Mutex};
+    use wascc_host::{Actor, HostBuilder};
+
+    let record = Arc::new(Mutex::new(Vec::new()));
+    let r = record.clone();
+    let host = HostBuilder::new()
+        .on_started(move || r.lock().unwrap().push("started".to_string()))
+        .build();
+    host.add_actor(Actor::from_file("./examples/.assets/echo.wasm")?)?;
+    assert_eq!(vec!["started"], *record.lock().unwrap());
+
+    let (r, h) = (record.clone(), host.clone());
+    host.on_shutdown(move || {
+        // All actors have terminated by the time the shutdown hooks run
+        r.lock()
+            .unwrap()
+            .push(format!("first:{}", h.actors().len()))
+    });
+    host.on_shutdown(|| panic!("a broken hook doesn't stop the others"));
+    let r = record.clone();
+    host.on_shutdown(move || r.lock().unwrap().push("second".to_string()));
+
+    host.shutdown()?;
+    assert_eq!(
+        vec!["started", "first:0", "second"],
+        *record.lock().unwrap()
+    );
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+pub(crate) fn kv_host_mocked() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use wascc_codec::http::{Request, Response, OP_HANDLE_REQUEST};
+    use wascc_codec::keyvalue::OP_ADD;
+    use wascc_codec::{deserialize, serialize};
+    use wascc_host::testing::TestHost;
+
+    // Same flow as kv_host, but with the HTTP server and redis providers replaced by a mock
+    let bytes = std::fs::read("./examples/.assets/kvcounter.wasm")?;
+    let th = TestHost::with_actor_for(&bytes, "wascc:keyvalue")?;
+    th.mock().on(OP_ADD, |_actor, _msg| {
+        let mut hm = HashMap::new();
+        hm.insert("value", 3);
+        serialize(&hm)
+    });
+
+    let req = Request {
+        method: "GET".to_string(),
+        path: "/counter".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    };
+    let res = th.call_actor(OP_HANDLE_REQUEST, &serialize(&req)?)?;
+    let resp: Response = deserialize(&res)?;
+    assert_eq!(resp.body, b"{\"counter\":3}".to_vec());
+
+    let adds = th.mock().calls_for(OP_ADD);
+    assert_eq!(1, adds.len());
+    assert_eq!(th.actor(), adds[0].actor);
+    th.shutdown()?;
+    std::thread::sleep(::std::time::Duration::from_millis(500));
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+pub(crate) fn circuit_breaker_stops_calls() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+    use wascc_codec::keyvalue::OP_ADD;
+    use wascc_codec::serialize;
+    use wascc_host::middleware::circuitbreaker::{
+        CircuitBreakerConfig, CircuitBreakerMiddleware, CircuitState,
+    };
+    use wascc_host::testing::TestHost;
+    use wascc_host::WasccEntity;
+
+    let bytes = std::fs::read("./examples/.assets/kvcounter.wasm")?;
+    let th = TestHost::with_actor_for(&bytes, "wascc:keyvalue")?;
+    th.mock()
+        .on(OP_ADD, |_actor, _msg| Err("backend down".into()));
+    let breaker = CircuitBreakerMiddleware::new().with_target(
+        "wasmbus://wascc/keyvalue/*",
+        CircuitBreakerConfig {
+            failure_threshold: 2,
+            open_duration: Duration::from_secs(60),
+            half_open_probes: 1,
+        },
+    );
+    th.host().add_middleware(breaker.clone());
+
+    let req = serialize(&Request {
+        method: "GET".to_string(),
+        path: "/counter".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    for _ in 0..5 {
+        let _ = th.call_actor(OP_HANDLE_REQUEST, &req);
+    }
+
+    // Only the calls made before the breaker opened reached the provider
+    assert_eq!(2, th.mock().calls_for(OP_ADD).len());
+    let kv = WasccEntity::Capability {
+        capid: "wascc:keyvalue".to_string(),
+        binding: "default".to_string(),
+    };
+    assert_eq!(CircuitState::Open, breaker.state(&kv).unwrap().state);
+    th.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+pub(crate) fn provider_stats_count_bindings() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+    use wascc_codec::keyvalue::OP_ADD;
+    use wascc_codec::serialize;
+    use wascc_host::testing::TestHost;
+    use wascc_host::Actor;
+
+    let bytes = std::fs::read("./examples/.assets/kvcounter.wasm")?;
+    let th = TestHost::with_actor_for(&bytes, "wascc:keyvalue")?;
+    th.mock().on(OP_ADD, |_actor, _msg| {
+        let mut hm = HashMap::new();
+        hm.insert("value", 1);
+        serialize(&hm)
+    });
+    let other = Actor::from_file("./examples/.assets/multibinding.wasm")?;
+    let other_pk = other.public_key();
+    th.host().add_actor(other)?;
+    th.host()
+        .set_binding(&other_pk, "wascc:keyvalue", None, HashMap::new())?;
+
+    let mut bound = th.host().provider_bindings("wascc:keyvalue", "default");
+    bound.sort();
+    let mut expected = vec![th.actor(), other_pk];
+    expected.sort();
+    assert_eq!(expected, bound);
+
+    let req = Request {
+        method: "GET".to_string(),
+        path: "/counter".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    };
+    th.call_actor(OP_HANDLE_REQUEST, &serialize(&req)?)?;
+
+    let stats = th
+        .host()
+        .provider_stats("wascc:keyvalue", "default")
+        .unwrap();
+    assert_eq!(2, stats.bound_actors);
+    assert_eq!(3, stats.invocations); // two bindings and one add
+    assert_eq!(0, stats.errors);
+    assert!(stats.last_invocation_at.is_some());
+    assert!(th
+        .host()
+        .provider_stats("wascc:keyvalue", "nosuch")
+        .is_none());
+    th.shutdown()?;
+    std::thread::sleep(::std::time::Duration::from_millis(500));
+    Ok(())
+}
+
+#[cfg(feature = "watch")]
+pub(crate) fn watched_actor_is_replaced() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+    use wascc_host::{Actor, HostEvent};
+
+    let dir = std::env::temp_dir().join(format!("wascc-watch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("echo.wasm");
+    let bytes = std::fs::read("./examples/.assets/echo.wasm")?;
+    std::fs::write(&path, &bytes)?;
+
+    let host = Host::new();
+    let actor = Actor::from_file(&path)?;
+    let pk = actor.public_key();
+    host.add_actor(actor)?;
+    let events = host.events();
+    let handle = host.watch_actor(&pk, path.clone())?;
+
+    std::fs::write(&path, &bytes)?;
+    match events.recv_timeout(Duration::from_secs(5))? {
+        HostEvent::ActorUpdated { actor } => assert_eq!(actor, pk),
+        e => panic!("Unexpected event: {:?}", e),
+    }
+
+    // A module with a different subject is rejected without ending the watch
+    std::fs::write(&path, std::fs::read("./examples/.assets/echo2.wasm")?)?;
+    assert!(matches!(
+        events.recv_timeout(Duration::from_secs(5))?,
+        HostEvent::ActorUpdateFailed { .. }
+    ));
+
+    drop(handle);
+    host.shutdown()?;
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[cfg(feature = "manifest")]
+pub(crate) fn manifest_continues_past_failures() -> Result<(), Box<dyn Error>> {
+    use wascc_host::{HostBuilder, HostManifest};
+
+    let echo = "MB4OLDIC3TCZ4Q4TGGOVAZC43VXFE2JQVRAXQMQFXUCREOOFEKOKZTY2";
+    let manifest: HostManifest = serde_json::from_str(&format!(
+        r#"{{
+            "actors": ["./examples/.assets/nosuch.wasm", "./examples/.assets/echo.wasm"],
+            "capabilities": [{{"path": "./examples/.assets/libwascc_httpsrv.so"}}],
+            "bindings": [{{
+                "actor": "{}",
+                "capability": "wascc:http_server",
+                "values": {{"PORT": "9096"}}
+            }}]
+        }}"#,
+        echo
+    ))?;
+
+    // Without continuing, the missing actor aborts the rest of the manifest
+    let host = HostBuilder::new()
+        .with_image_fetcher(crate::common::AssetFetcher {})
+        .build();
+    assert!(host.apply_manifest(manifest.clone()).is_err());
+    assert!(host.actors().is_empty());
+    assert!(!host
+        .capabilities()
+        .keys()
+        .any(|(_, capid)| capid == "wascc:http_server"));
+    host.shutdown()?;
+
+    let host = HostBuilder::new()
+        .with_image_fetcher(crate::common::AssetFetcher {})
+        .build();
+    let report = host.apply_manifest_with_report(manifest, true)?;
+    assert!(!report.is_success());
+    let failed: Vec<_> = report.failures().map(|e| e.entry.to_string()).collect();
+    assert_eq!(vec!["./examples/.assets/nosuch.wasm"], failed);
+    assert!(report.actors[1].is_ok());
+    assert!(report.capabilities[0].is_ok());
+    assert!(report.bindings[0].is_ok());
+    assert_eq!(
+        "./examples/.assets/nosuch.wasm",
+        serde_json::to_value(&report)?["actors"][0]["entry"]
+    );
+
+    assert!(host.claims_for_actor(echo).is_some());
+    std::thread::sleep(::std::time::Duration::from_millis(500));
+    let resp = reqwest::blocking::get("http://localhost:9096")?;
+    assert!(resp.status().is_success());
+    host.shutdown()?;
+    std::thread::sleep(::std::time::Duration::from_millis(500));
+    Ok(())
+}
+
+pub(crate) fn host_from_env() -> Result<(), Box<dyn Error>> {
+    use wascc_host::HostBuilder;
+
+    let _lock = crate::common::env_lock();
+    let dir = std::env::temp_dir().join(format!("wascc-env-{}", std::process::id()));
+    let vars = [
+        ("WASCC_LABEL_REGION", "us-east"),
+        ("WASCC_LABEL_RACK", "r1"),
+        ("WASCC_WORK_DIR", dir.to_str().unwrap()),
+        ("WASCC_AUDIT_CAPACITY", "4"),
+        ("WASCC_BINDING_METADATA", "region, rack"),
+    ];
+    for (k, v) in vars.iter() {
+        std::env::set_var(k, v);
+    }
+    // Builder calls take precedence over the environment
+    let host = HostBuilder::from_env().map(|b| b.with_label("rack", "r2").build());
+
+    std::env::set_var("WASCC_AUDIT_CAPACITY", "lots");
+    let invalid = HostBuilder::from_env();
+    for (k, _) in vars.iter() {
+        std::env::remove_var(k);
+    }
+
+    let host = host?;
+    let labels = host.labels();
+    assert_eq!("us-east", labels["region"]);
+    assert_eq!("r2", labels["rack"]);
+    assert!(labels.contains_key("hostcore.os"));
+    assert!(host.work_dir().starts_with(&dir));
+    host.shutdown()?;
+
+    let err = invalid.err().unwrap().to_string();
+    assert!(err.contains("WASCC_AUDIT_CAPACITY"));
+    let _ = std::fs::remove_dir_all(&dir);
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+pub(crate) fn unhealthy_provider_fails_fast() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use wascc_codec::core::{HealthResponse, OP_HEALTH_REQUEST};
+    use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+    use wascc_codec::keyvalue::OP_ADD;
+    use wascc_codec::serialize;
+    use wascc_host::testing::MockCapability;
+    use wascc_host::{
+        Actor, HealthCheckConfig, HealthStatus, HostBuilder, HostEvent, NativeCapability,
+    };
+
+    let host = HostBuilder::new()
+        .with_health_checks(HealthCheckConfig {
+            interval: Duration::from_millis(50),
+            timeout: Duration::from_millis(50),
+            unhealthy_threshold: 2,
+            fail_fast: true,
+        })
+        .build();
+    let actor = Actor::from_file("./examples/.assets/kvcounter.wasm")?;
+    let pk = actor.public_key();
+    host.add_actor(actor)?;
+
+    // A provider whose health checks hang for as long as it's stalled
+    let stalled = Arc::new(AtomicBool::new(false));
+    let s = stalled.clone();
+    let mock = MockCapability::new("wascc:keyvalue");
+    mock.on(OP_HEALTH_REQUEST, move |_actor, _msg| {
+        while s.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        serialize(HealthResponse {
+            healthy: true,
+            message: "".to_string(),
+        })
+    });
+    mock.on(OP_ADD, |_actor, _msg| {
+        let mut hm = HashMap::new();
+        hm.insert("value", 1);
+        serialize(&hm)
+    });
+    host.add_native_capability(NativeCapability::from_instance(mock.clone(), None)?)?;
+    host.set_binding(&pk, "wascc:keyvalue", None, HashMap::new())?;
+
+    let req = serialize(&Request {
+        method: "GET".to_string(),
+        path: "/counter".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    let key = ("default".to_string(), "wascc:keyvalue".to_string());
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(HealthStatus::Healthy, host.capability_health()[&key].status);
+    host.call_actor(&pk, OP_HANDLE_REQUEST, &req)?;
+
+    let events = host.events();
+    stalled.store(true, Ordering::SeqCst);
+    match events.recv_timeout(Duration::from_secs(2))? {
+        HostEvent::ProviderUnhealthy { capid, binding, .. } => {
+            assert_eq!("wascc:keyvalue", capid);
+            assert_eq!("default", binding);
+        }
+        e => panic!("Unexpected event {:?}", e),
+    }
+    let health = &host.capability_health()[&key];
+    assert_eq!(HealthStatus::Unhealthy, health.status);
+    assert!(health.consecutive_failures >= 2);
+    assert!(host.call_actor(&pk, OP_HANDLE_REQUEST, &req).is_err());
+    assert_eq!(1, mock.calls_for(OP_ADD).len()); // never reached the provider
+
+    stalled.store(false, Ordering::SeqCst);
+    assert_eq!(
+        HostEvent::ProviderRecovered {
+            capid: "wascc:keyvalue".to_string(),
+            binding: "default".to_string(),
+        },
+        events.recv_timeout(Duration::from_secs(2))?
+    );
+    host.call_actor(&pk, OP_HANDLE_REQUEST, &req)?;
+    assert_eq!(2, mock.calls_for(OP_ADD).len());
+
+    host.shutdown()?;
+    std::thread::sleep(Duration::from_millis(500));
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+pub(crate) fn group_binding_skips_unattested() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use wascc_host::errors::ErrorKind;
+    use wascc_host::testing::TestHost;
+    use wascc_host::Actor;
+
+    let bytes = std::fs::read("./examples/.assets/kvcounter.wasm")?;
+    let th = TestHost::with_actor_for(&bytes, "wascc:keyvalue")?;
+    let attested = Actor::from_file("./examples/.assets/multibinding.wasm")?;
+    let attested_pk = attested.public_key();
+    let unattested = Actor::from_file("./examples/.assets/echo.wasm")?;
+    let unattested_pk = unattested.public_key();
+    th.host().add_actor(attested)?;
+    th.host().add_actor(unattested)?;
+
+    let results = th.host().set_group_binding(
+        &[&attested_pk, &unattested_pk, "Mnosuchactor"],
+        "wascc:keyvalue",
+        None,
+        HashMap::new(),
+    );
+    assert_eq!(3, results.len());
+    assert!(results[&attested_pk].is_ok());
+    match results[&unattested_pk] {
+        Err(ref e) => match e.kind() {
+            ErrorKind::Authorization(_) => {}
+            k => panic!("Unexpected error {:?}", k),
+        },
+        Ok(_) => panic!("Unattested actor was bound"),
+    }
+    assert!(results["Mnosuchactor"].is_err());
+
+    let mut bound = th.host().provider_bindings("wascc:keyvalue", "default");
+    bound.sort();
+    let mut expected = vec![th.actor(), attested_pk];
+    expected.sort();
+    assert_eq!(expected, bound);
+    th.shutdown()?;
+    std::thread::sleep(::std::time::Duration::from_millis(500));
+    Ok(())
+}
+
+pub(crate) fn caching_middleware_expires_and_evicts() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+    use wascc_codec::serialize;
+    use wascc_host::middleware::cache::{CacheConfig, CachingMiddleware};
+    use wascc_host::{Actor, WasccEntity};
+
+    let echo = "MB4OLDIC3TCZ4Q4TGGOVAZC43VXFE2JQVRAXQMQFXUCREOOFEKOKZTY2";
+    let host = Host::new();
+    host.add_actor(Actor::from_file("./examples/.assets/echo.wasm")?)?;
+    // Calls made by the host itself aren't cached, so echo is called through other actors
+    let forwarder = crate::common::generate_forwarder_actor(&[echo])?;
+    let other = crate::common::generate_forwarder_actor(&[echo])?;
+    let (fwd, other_fwd) = (forwarder.public_key(), other.public_key());
+    host.add_actor(forwarder)?;
+    host.add_actor(other)?;
+    let cache = CachingMiddleware::new(CacheConfig {
+        ttl: Duration::from_millis(500),
+        max_entries: 2,
+        ..Default::default()
+    });
+    host.add_middleware(cache.clone());
+
+    let req = |path: &str| -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut msg = echo.as_bytes().to_vec();
+        msg.extend_from_slice(&serialize(&Request {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            query_string: "".to_string(),
+            header: HashMap::new(),
+            body: vec![],
+        })?);
+        Ok(msg)
+    };
+    let (a, b, c) = (req("/a")?, req("/b")?, req("/c")?);
+
+    let first = host.call_actor(&fwd, OP_HANDLE_REQUEST, &a)?;
+    assert_eq!(first, host.call_actor(&fwd, OP_HANDLE_REQUEST, &a)?);
+    assert_eq!((1, 1), (cache.hits(), cache.misses()));
+    // Another caller's identical invocation isn't answered with the first caller's response
+    host.call_actor(&other_fwd, OP_HANDLE_REQUEST, &a)?;
+    assert_eq!((1, 2), (cache.hits(), cache.misses()));
+    cache.invalidate_all();
+
+    // Filling the cache with b and c evicts a, the least recently used
+    host.call_actor(&fwd, OP_HANDLE_REQUEST, &a)?;
+    host.call_actor(&fwd, OP_HANDLE_REQUEST, &b)?;
+    host.call_actor(&fwd, OP_HANDLE_REQUEST, &c)?;
+    assert_eq!(2, cache.len());
+    host.call_actor(&fwd, OP_HANDLE_REQUEST, &a)?;
+    assert_eq!((1, 6), (cache.hits(), cache.misses()));
+    host.call_actor(&fwd, OP_HANDLE_REQUEST, &c)?;
+    assert_eq!((2, 6), (cache.hits(), cache.misses()));
+
+    // Entries expire after the TTL
+    std::thread::sleep(Duration::from_millis(600));
+    host.call_actor(&fwd, OP_HANDLE_REQUEST, &c)?;
+    assert_eq!((2, 7), (cache.hits(), cache.misses()));
+
+    cache.invalidate_target(&WasccEntity::Actor(echo.to_string()));
+    assert!(cache.is_empty());
+
+    host.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+pub(crate) fn caching_middleware_skips_control_operations() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use wascc_codec::core::OP_BIND_ACTOR;
+    use wascc_host::middleware::cache::{CacheConfig, CachingMiddleware};
+    use wascc_host::testing::TestHost;
+
+    let bytes = std::fs::read("./examples/.assets/kvcounter.wasm")?;
+    let th = TestHost::with_actor_for(&bytes, "wascc:keyvalue")?;
+    let cache = CachingMiddleware::new(CacheConfig::default());
+    th.host().add_middleware(cache.clone());
+    th.mock().clear_calls();
+
+    // Binding the actor again with the same configuration reaches the provider every time
+    for _ in 0..2 {
+        th.host()
+            .remove_binding(&th.actor(), "wascc:keyvalue", None)?;
+        th.host()
+            .set_binding(&th.actor(), "wascc:keyvalue", None, HashMap::new())?;
+    }
+    assert_eq!(2, th.mock().calls_for(OP_BIND_ACTOR).len());
+    assert_eq!((0, 0), (cache.hits(), cache.misses()));
+    assert!(cache.is_empty());
+
+    th.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+pub(crate) fn in_flight_invocations_drain() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+    use wascc_codec::keyvalue::OP_ADD;
+    use wascc_codec::serialize;
+    use wascc_host::testing::TestHost;
+
+    // The mock provider sleeps, keeping both the actor and the provider busy
+    let bytes = std::fs::read("./examples/.assets/kvcounter.wasm")?;
+    let th = TestHost::with_actor_for(&bytes, "wascc:keyvalue")?;
+    th.mock().on(OP_ADD, |_actor, _msg| {
+        std::thread::sleep(Duration::from_millis(500));
+        let mut hm = HashMap::new();
+        hm.insert("value", 1);
+        serialize(&hm)
+    });
+    let host = th.host().clone();
+    assert!(host.in_flight().is_empty());
+    host.wait_for_idle(Duration::from_millis(10))?;
+
+    let req = serialize(&Request {
+        method: "GET".to_string(),
+        path: "/counter".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    let (h, pk) = (host.clone(), th.actor());
+    let caller = std::thread::spawn(move || h.call_actor(&pk, OP_HANDLE_REQUEST, &req).is_ok());
+    std::thread::sleep(Duration::from_millis(150));
+
+    let in_flight = host.in_flight();
+    assert_eq!(
+        Some(&1),
+        in_flight.get(&format!("wasmbus://{}", th.actor()))
+    );
+    assert_eq!(Some(&1), in_flight.get("wasmbus://wascc/keyvalue/default"));
+    assert!(host.wait_for_idle(Duration::from_millis(50)).is_err());
+    host.wait_for_idle(Duration::from_secs(2))?;
+    assert!(host.in_flight().is_empty());
+    assert!(caller.join().unwrap());
+
+    th.shutdown()?;
+    std::thread::sleep(Duration::from_millis(500));
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+pub(crate) fn binding_updates_ordered_with_calls() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use wascc_codec::core::{CapabilityConfiguration, OP_BIND_ACTOR};
+    use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+    use wascc_codec::keyvalue::OP_ADD;
+    use wascc_codec::{deserialize, serialize};
+    use wascc_host::testing::TestHost;
+
+    let bytes = std::fs::read("./examples/.assets/kvcounter.wasm")?;
+    let th = TestHost::with_actor_for(&bytes, "wascc:keyvalue")?;
+
+    // The provider records when each operation starts and ends, and the bucket each call observed
+    let log = Arc::new(Mutex::new(Vec::<(String, String)>::new()));
+    let bucket = Arc::new(Mutex::new("initial".to_string()));
+    let (l, b) = (log.clone(), bucket.clone());
+    th.mock().on(OP_BIND_ACTOR, move |_actor, msg| {
+        let config: CapabilityConfiguration = deserialize(msg)?;
+        let value = config.values["BUCKET"].to_string();
+        l.lock()
+            .unwrap()
+            .push(("bind start".to_string(), value.clone()));
+        std::thread::sleep(Duration::from_millis(100));
+        *b.lock().unwrap() = value.clone();
+        l.lock().unwrap().push(("bind end".to_string(), value));
+        Ok(vec![])
+    });
+    let (l, b) = (log.clone(), bucket.clone());
+    th.mock().on(OP_ADD, move |_actor, _msg| {
+        let observed = b.lock().unwrap().clone();
+        l.lock()
+            .unwrap()
+            .push(("call start".to_string(), observed.clone()));
+        std::thread::sleep(Duration::from_millis(20));
+        l.lock().unwrap().push(("call end".to_string(), observed));
+        let mut hm = HashMap::new();
+        hm.insert("value", 1);
+        serialize(&hm)
+    });
+
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/counter".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    let (host, pk) = (th.host().clone(), th.actor());
+    let caller = std::thread::spawn(move || {
+        (0..30).all(|_| host.call_actor(&pk, OP_HANDLE_REQUEST, &req).is_ok())
+    });
+    for i in 1..=3 {
+        std::thread::sleep(Duration::from_millis(60));
+        let mut values = HashMap::new();
+        values.insert("BUCKET".to_string(), format!("b{}", i));
+        th.host()
+            .update_binding_values(&th.actor(), "wascc:keyvalue", None, values)?;
+    }
+    assert!(caller.join().unwrap());
+
+    // No call overlaps a configuration update, and every call observes the latest one applied
+    let log = log.lock().unwrap().clone();
+    assert_eq!(6, log.iter().filter(|(e, _)| e.starts_with("bind")).count());
+    assert_eq!(
+        60,
+        log.iter().filter(|(e, _)| e.starts_with("call")).count()
+    );
+    let mut current = "initial".to_string();
+    for pair in log.chunks(2) {
+        let (start, end) = (&pair[0], &pair[1]);
+        assert_eq!(start.0.replace("start", "end"), end.0, "{:?}", log);
+        assert_eq!(start.1, end.1);
+        if start.0 == "bind start" {
+            current = start.1.to_string();
+        } else {
+            assert_eq!(current, start.1, "{:?}", log);
+        }
+    }
+    assert_eq!("b3", current);
+
+    th.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+pub(crate) fn removed_actor_fails_fast() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+    use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+    use wascc_codec::serialize;
+    use wascc_host::errors::{self, BusError, ErrorKind};
+    use wascc_host::testing::TestHost;
+
+    let bytes = std::fs::read("./examples/.assets/echo.wasm")?;
+    let th = TestHost::with_actor_for(&bytes, "wascc:http_server")?;
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    th.host().remove_actor(&th.actor())?;
+
+    // Removal is asynchronous, so invocations may succeed for a moment. Once they fail, they
+    // must fail right away with an error saying why, rather than waiting for a timeout. The
+    // provider invokes the actor over the bus, which the host's own checks for a removed actor
+    // don't stand in front of
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let err = loop {
+        let start = Instant::now();
+        let res = th.mock().dispatch(&th.actor(), OP_HANDLE_REQUEST, &req);
+        assert!(start.elapsed() < Duration::from_millis(500));
+        if let Err(e) = res {
+            break e;
+        }
+        assert!(Instant::now() < deadline, "actor still answering");
+    };
+    match err.downcast::<errors::Error>().unwrap().into_kind() {
+        ErrorKind::Bus(BusError::NoResponders { .. })
+        | ErrorKind::Bus(BusError::Disconnected { .. }) => {}
+        k => panic!("unexpected error {:?}", k),
+    }
+    th.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "manifest")]
+pub(crate) fn manifest_waits_for_readiness() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use wascc_codec::http::{Request, Response, OP_HANDLE_REQUEST};
+    use wascc_codec::{deserialize, serialize};
+    use wascc_host::{HostManifest, WasccEntity};
+
+    let kvcounter = "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ";
+    let manifest: HostManifest = serde_json::from_str(&format!(
+        r#"{{
+            "actors": ["./examples/.assets/kvcounter.wasm"],
+            "capabilities": [{{"path": "./examples/.assets/libkeyvalue.so"}}],
+            "bindings": [{{"actor": "{}", "capability": "wascc:keyvalue"}}]
+        }}"#,
+        kvcounter
+    ))?;
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/counter".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+
+    // Each run starts the actor, the provider, and the binding from scratch
+    for _ in 0..10 {
+        let host = Host::new();
+        let report = host.apply_manifest_with_report(manifest.clone(), false)?;
+        assert!(report.is_success());
+        let provider = WasccEntity::Capability {
+            capid: "wascc:keyvalue".to_string(),
+            binding: "default".to_string(),
+        };
+        host.await_ready(&provider, Duration::from_millis(100))?;
+        host.await_ready(
+            &WasccEntity::Actor(kvcounter.to_string()),
+            Duration::from_millis(100),
+        )?;
+
+        let resp: Response = deserialize(&host.call_actor(kvcounter, OP_HANDLE_REQUEST, &req)?)?;
+        assert_eq!(200, resp.status_code);
+        assert!(resp.body.starts_with(b"{\"counter\":"));
+        host.shutdown()?;
+    }
+
+    let host = Host::new();
+    let err = host
+        .await_ready(
+            &WasccEntity::Actor(kvcounter.to_string()),
+            Duration::from_millis(50),
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains("was not ready within 50ms"));
+    host.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "manifest")]
+pub(crate) fn preflight_reports_problems() -> Result<(), Box<dyn Error>> {
+    use wascc_host::HostManifest;
+
+    let dir = std::env::temp_dir().join(format!("wascc-preflight-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let expired = dir.join("expired.wasm");
+    let bytes = std::fs::read("./examples/.assets/kvcounter.wasm")?;
+    std::fs::write(&expired, crate::common::generate_expired_actor(&bytes)?)?;
+
+    let manifest: HostManifest = serde_json::from_str(&format!(
+        r#"{{
+            "actors": ["{}", "./examples/.assets/kvcounter.wasm"],
+            "capabilities": [
+                {{"path": "./examples/.assets/libkeyvalue.so"}},
+                {{"path": "./examples/.assets/libmissing.so", "binding_name": "other"}}
+            ],
+            "bindings": [
+                {{"actor": "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ",
+                  "capability": "wascc:keyvalue"}}
+            ]
+        }}"#,
+        expired.display()
+    ))?;
+
+    let host = wascc_host::HostBuilder::new()
+        .with_work_dir(dir.join("work"))
+        .build();
+    let caps = host.capabilities().len();
+    let report = host.preflight(&manifest);
+    let failures: Vec<_> = report.failures().map(|e| e.entry.to_string()).collect();
+    assert_eq!(
+        vec![
+            expired.display().to_string(),
+            "./examples/.assets/libmissing.so (other)".to_string()
+        ],
+        failures
+    );
+    assert!(report.actors[0]
+        .error
+        .as_ref()
+        .unwrap()
+        .contains("Expired token"));
+    assert!(report.bindings[0].is_ok());
+    assert!(!report.is_success());
+    assert!(report
+        .to_string()
+        .contains("FAIL ./examples/.assets/libmissing.so"));
+    let json = serde_json::to_string(&report)?;
+    assert_eq!(report, serde_json::from_str(&json)?);
+
+    // Nothing was started, or written to the host's work directory
+    assert!(host.actors().is_empty());
+    assert_eq!(caps, host.capabilities().len());
+    assert!(!host.work_dir().exists());
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+pub(crate) fn provider_reported_binding_failure() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+    use wascc_codec::core::OP_REMOVE_ACTOR;
+    use wascc_host::testing::TestHost;
+    use wascc_host::{HostEvent, OP_BINDING_REMOVED};
+
+    let bytes = std::fs::read("./examples/.assets/kvcounter.wasm")?;
+    let th = TestHost::with_actor_for(&bytes, "wascc:keyvalue")?;
+    let events = th.host().events();
+    assert_eq!(
+        vec![th.actor()],
+        th.host().provider_bindings("wascc:keyvalue", "default")
+    );
+
+    th.mock().fail_binding(&th.actor(), "credentials revoked")?;
+    loop {
+        if let HostEvent::BindingRemoved {
+            actor,
+            capid,
+            binding,
+            reason,
+        } = events.recv_timeout(Duration::from_secs(2))?
+        {
+            assert_eq!(th.actor(), actor);
+            assert_eq!("wascc:keyvalue", capid);
+            assert_eq!("default", binding);
+            assert_eq!("credentials revoked", reason);
+            break;
+        }
+    }
+    assert_eq!(1, th.mock().calls_for(OP_REMOVE_ACTOR).len());
+    assert!(th
+        .host()
+        .provider_bindings("wascc:keyvalue", "default")
+        .is_empty());
+
+    // The actor is told about it, even though this one doesn't handle the notification
+    std::thread::sleep(Duration::from_millis(200));
+    let notices: Vec<_> = th
+        .host()
+        .actor_recent_invocations(&th.actor())
+        .into_iter()
+        .filter(|e| e.operation == OP_BINDING_REMOVED)
+        .collect();
+    assert_eq!(1, notices.len());
+    assert_eq!("wasmbus://wascc/keyvalue/default", notices[0].origin);
+
+    th.shutdown()?;
+    Ok(())
+}
+
+#[cfg(all(feature = "manifest", feature = "testing"))]
+pub(crate) fn manifest_binding_templates() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use wascc_codec::core::{CapabilityConfiguration, OP_BIND_ACTOR};
+    use wascc_codec::deserialize;
+    use wascc_host::testing::MockCapability;
+    use wascc_host::{Actor, HostManifest, NativeCapability};
+
+    let echo = Actor::from_file("./examples/.assets/echo.wasm")?.public_key();
+    let echo2 = Actor::from_file("./examples/.assets/echo2.wasm")?.public_key();
+    let manifest: HostManifest = serde_json::from_str(&format!(
+        r#"{{
+            "actors": ["./examples/.assets/echo.wasm", "./examples/.assets/echo2.wasm"],
+            "binding_templates": {{
+                "web": {{
+                    "capability": "wascc:http_server",
+                    "values": {{"PORT": "808{{{{actor_index}}}}", "OWNER": "{{{{actor_pk}}}}"}}
+                }}
+            }},
+            "bindings_from_template": [
+                {{"actor": "{}", "template": "web"}},
+                {{"actor": "{}", "template": "web", "overrides": {{"OWNER": "ops"}}}}
+            ]
+        }}"#,
+        echo, echo2
+    ))?;
+
+    let host = Host::new();
+    let mock = MockCapability::new("wascc:http_server");
+    host.add_native_capability(NativeCapability::from_instance(mock.clone(), None)?)?;
+    let report = host.apply_manifest_with_report(manifest, false)?;
+    assert!(report.is_success());
+    assert_eq!(2, report.bindings.len());
+
+    let configs: HashMap<String, HashMap<String, String>> = mock
+        .calls_for(OP_BIND_ACTOR)
+        .iter()
+        .map(|c| {
+            let cfg: CapabilityConfiguration = deserialize(&c.msg).unwrap();
+            (cfg.module, cfg.values)
+        })
+        .collect();
+    assert_eq!("8080", configs[&echo]["PORT"]);
+    assert_eq!(echo, configs[&echo]["OWNER"]);
+    assert_eq!("8081", configs[&echo2]["PORT"]);
+    assert_eq!("ops", configs[&echo2]["OWNER"]);
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn for_each_actor_matches_actors() -> Result<(), Box<dyn Error>> {
+    let host = Host::new();
+    host.add_actor(crate::common::get_hello_actor()?)?;
+    host.add_actor(crate::common::get_hello2_actor()?)?;
+
+    let mut visited = Vec::new();
+    host.for_each_actor(|pk, claims| {
+        assert_eq!(pk, claims.subject);
+        visited.push(pk.to_string());
+    });
+    visited.sort();
+    let mut listed: Vec<_> = host.actors().into_iter().map(|(pk, _)| pk).collect();
+    listed.sort();
+    assert_eq!(2, visited.len());
+    assert_eq!(listed, visited);
+
+    // The function may call back into the host, even to remove the actors it's given
+    host.for_each_actor(|pk, _| {
+        host.remove_actor_sync(pk, std::time::Duration::from_secs(5))
+            .unwrap()
+    });
+    assert!(host.actors().is_empty());
+    host.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+pub(crate) fn hosts_in_one_process_are_isolated() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use wascc_codec::http::{Request, Response, OP_HANDLE_REQUEST};
+    use wascc_codec::keyvalue::OP_ADD;
+    use wascc_codec::{deserialize, serialize};
+    use wascc_host::testing::TestHost;
+
+    const CALLS: usize = 20;
+
+    // The same actor (and so the same bus subjects) in two hosts, each bound to its own mock
+    let bytes = std::fs::read("./examples/.assets/kvcounter.wasm")?;
+    let start = |value: i32| -> Result<TestHost, Box<dyn Error>> {
+        let th = TestHost::with_actor_for(&bytes, "wascc:keyvalue")?;
+        th.mock().on(OP_ADD, move |_actor, _msg| {
+            let mut hm = HashMap::new();
+            hm.insert("value", value);
+            serialize(&hm)
+        });
+        Ok(th)
+    };
+    let hosts = vec![(start(3)?, "3"), (start(7)?, "7")];
+    assert_eq!(hosts[0].0.actor(), hosts[1].0.actor());
+
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/counter".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    crossbeam_utils::thread::scope(|s| {
+        for (th, expected) in hosts.iter() {
+            let req = &req;
+            s.spawn(move |_| {
+                for _ in 0..CALLS {
+                    let res = th.call_actor(OP_HANDLE_REQUEST, req).unwrap();
+                    let resp: Response = deserialize(&res).unwrap();
+                    assert_eq!(
+                        format!("{{\"counter\":{}}}", expected).into_bytes(),
+                        resp.body
+                    );
+                }
+            });
+        }
+    })
+    .unwrap();
+
+    for (th, _) in hosts.iter() {
+        assert_eq!(CALLS, th.mock().calls_for(OP_ADD).len());
+        th.shutdown()?;
+    }
+    Ok(())
+}
+
+pub(crate) fn undeclared_codec_version_loaded() -> Result<(), Box<dyn Error>> {
+    use wascc_host::NativeCapability;
+
+    // Providers built before the codec version could be declared are still loaded
+    assert!(NativeCapability::from_file("./examples/.assets/libkeyvalue.so", None).is_ok());
+    Ok(())
+}
+
+pub(crate) fn portable_provider_duplicates_refused() -> Result<(), Box<dyn Error>> {
+    use wascc_host::{Actor, WasiParams};
+
+    let host = Host::new();
+    let provider = || Actor::from_file("./examples/.assets/wasi_provider.wasm");
+    host.add_capability(provider()?, None, WasiParams::default())?;
+    let key = ("default".to_string(), "wascc:wasidemo".to_string());
+    assert_eq!("wascc:wasidemo", host.capabilities()[&key].id);
+
+    // The same provider can't be loaded under the same binding twice, but can under another
+    assert!(host
+        .add_capability(provider()?, None, WasiParams::default())
+        .is_err());
+    host.add_capability(provider()?, Some("other"), WasiParams::default())?;
+    assert_eq!(
+        2,
+        host.capabilities()
+            .values()
+            .filter(|d| d.id == "wascc:wasidemo")
+            .count()
+    );
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn timings_recorded() -> Result<(), Box<dyn Error>> {
+    use wascc_host::{Actor, HostBuilder, NativeCapability};
+
+    let kvcounter = "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ";
+    let host = HostBuilder::new().with_timing_report(true).build();
+    host.add_actor(Actor::from_file("./examples/.assets/kvcounter.wasm")?)?;
+    host.add_native_capability(NativeCapability::from_file(
+        "./examples/.assets/libkeyvalue.so",
+        None,
+    )?)?;
+
+    let timings = host.timings();
+    for phase in &["validate", "engine", "subscribe", "add"] {
+        let name = format!("actor.{}.{}", kvcounter, phase);
+        assert!(timings.get(&name).is_some(), "{} missing", name);
+    }
+    for phase in &["dlopen", "descriptor", "add"] {
+        let name = format!("provider.wascc:keyvalue.default.{}", phase);
+        assert!(timings.get(&name).is_some(), "{} missing", name);
+    }
+    let add = timings.get(&format!("actor.{}.add", kvcounter)).unwrap();
+    assert!(
+        add >= timings
+            .get(&format!("actor.{}.validate", kvcounter))
+            .unwrap()
+    );
+    for name in &[
+        "host.generate.keys",
+        "host.generate.builtins",
+        "host.generate",
+        "provider.wascc:extras.default.add",
+    ] {
+        assert!(timings.get(name).is_some(), "{} missing", name);
+    }
+    assert!(timings.get("shutdown").is_none());
+
+    host.shutdown()?;
+    let timings = host.timings();
+    for name in &[
+        "shutdown.drain",
+        "shutdown.actors",
+        "shutdown.providers",
+        "shutdown.terminations",
+        "shutdown.hooks",
+        "shutdown",
+    ] {
+        assert!(timings.get(name).is_some(), "{} missing", name);
+    }
+    assert!(timings.get("shutdown").unwrap() >= timings.get("shutdown.actors").unwrap());
+    Ok(())
+}
+
+pub(crate) fn remove_portable_capability() -> Result<(), Box<dyn Error>> {
+    use wascc_host::{Actor, NativeCapability, WasiParams};
+
+    let host = Host::new();
+    host.add_capability(
+        Actor::from_file("./examples/.assets/wasi_provider.wasm")?,
+        None,
+        WasiParams::default(),
+    )?;
+    let key = ("default".to_string(), "wascc:wasidemo".to_string());
+    assert!(host.capabilities().contains_key(&key));
+
+    host.remove_capability("wascc:wasidemo", None)?;
+    assert!(!host.capabilities().contains_key(&key));
+    assert!(host.remove_capability("wascc:wasidemo", None).is_err());
+
+    // Once removed, it can be added again
+    host.add_capability(
+        Actor::from_file("./examples/.assets/wasi_provider.wasm")?,
+        None,
+        WasiParams::default(),
+    )?;
+    assert!(host.capabilities().contains_key(&key));
+
+    // Native providers are left to the native API
+    host.add_native_capability(NativeCapability::from_file(
+        "./examples/.assets/libkeyvalue.so",
+        None,
+    )?)?;
+    let err = host.remove_capability("wascc:keyvalue", None).unwrap_err();
+    assert!(err.to_string().contains("remove_native_capability"));
+    assert!(host
+        .capabilities()
+        .contains_key(&("default".to_string(), "wascc:keyvalue".to_string())));
+
+    host.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+pub(crate) fn custom_default_binding_name() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use wascc_codec::core::OP_BIND_ACTOR;
+    use wascc_host::testing::MockCapability;
+    use wascc_host::{Actor, HostBuilder, NativeCapability};
+
+    let kvcounter = "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ";
+    let host = HostBuilder::new()
+        .with_default_binding_name("staging")
+        .build();
+    host.add_actor(Actor::from_file("./examples/.assets/kvcounter.wasm")?)?;
+
+    // A provider loaded without a binding name is loaded under the host's default
+    let staging = MockCapability::new("wascc:keyvalue");
+    host.add_native_capability(NativeCapability::from_instance(staging.clone(), None)?)?;
+    assert!(host
+        .capabilities()
+        .contains_key(&("staging".to_string(), "wascc:keyvalue".to_string())));
+    host.set_binding(kvcounter, "wascc:keyvalue", None, HashMap::new())?;
+    assert_eq!(
+        vec![kvcounter.to_string()],
+        host.provider_bindings("wascc:keyvalue", "staging")
+    );
+    assert!(host
+        .provider_bindings("wascc:keyvalue", "default")
+        .is_empty());
+
+    // Explicit names still take precedence
+    let other = MockCapability::new("wascc:keyvalue");
+    host.add_native_capability(NativeCapability::from_instance(
+        other.clone(),
+        Some("default".to_string()),
+    )?)?;
+    host.set_binding(
+        kvcounter,
+        "wascc:keyvalue",
+        Some("default".to_string()),
+        HashMap::new(),
+    )?;
+    assert_eq!(
+        vec![kvcounter.to_string()],
+        host.provider_bindings("wascc:keyvalue", "default")
+    );
+    assert_eq!(1, staging.calls_for(OP_BIND_ACTOR).len());
+    assert_eq!(1, other.calls_for(OP_BIND_ACTOR).len());
+
+    // Names that can't be used in a bus subject are refused
+    assert!(
+        std::panic::catch_unwind(|| HostBuilder::new().with_default_binding_name("prod.east"))
+            .is_err()
+    );
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn claims_skew_and_quarantine() -> Result<(), Box<dyn Error>> {
+    use crate::common::generate_timed_actor;
+    use std::time::Duration;
+    use wascc_host::{Actor, HostBuilder};
+
+    let bytes = std::fs::read("./examples/.assets/echo.wasm")?;
+    let recent = generate_timed_actor(&bytes, None, Some(-30))?;
+    let stale = generate_timed_actor(&bytes, None, Some(-120))?;
+    let soon = generate_timed_actor(&bytes, Some(30), None)?;
+    let later = generate_timed_actor(&bytes, Some(120), None)?;
+
+    // Without a tolerance, claims are evaluated against the host's clock as-is
+    let strict = Host::new();
+    assert!(strict.add_actor(Actor::from_slice(&recent)?).is_err());
+    assert!(strict.add_actor(Actor::from_slice(&soon)?).is_err());
+    assert!(strict.quarantined_actors().is_empty());
+    strict.shutdown()?;
+
+    let host = HostBuilder::new()
+        .with_claims_skew_tolerance(Duration::from_secs(60))
+        .with_actor_quarantine(true)
+        .build();
+    host.add_actor(Actor::from_slice(&recent)?)?;
+    host.add_actor(Actor::from_slice(&soon)?)?;
+    let err = host.add_actor(Actor::from_slice(&stale)?).unwrap_err();
+    assert!(err.to_string().contains("Expired token"));
+    let err = host.add_actor(Actor::from_slice(&later)?).unwrap_err();
+    assert!(err.to_string().contains("cannot be used before"));
+    assert_eq!(2, host.actors().len());
+
+    let quarantined = host.quarantined_actors();
+    assert_eq!(
+        vec![
+            Actor::from_slice(&stale)?.public_key(),
+            Actor::from_slice(&later)?.public_key()
+        ],
+        quarantined
+            .iter()
+            .map(|q| q.public_key.to_string())
+            .collect::<Vec<_>>()
+    );
+    assert!(quarantined[0].reason.contains("Expired token"));
+    assert!(quarantined[0].expires.unwrap() + 60 < quarantined[0].quarantined_at);
+    assert!(quarantined[1].not_before.unwrap() > quarantined[1].quarantined_at + 60);
+
+    // A failing actor is recorded once, however many times it's added
+    assert!(host.add_actor(Actor::from_slice(&stale)?).is_err());
+    assert_eq!(2, host.quarantined_actors().len());
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn broadcast_to_actors() -> Result<(), Box<dyn Error>> {
+    use crossbeam_channel::{bounded, Receiver, Sender};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+    use wascc_codec::serialize;
+    use wascc_host::middleware::{InvocationHandler, Middleware, MiddlewareResponse};
+    use wascc_host::{ActorFilter, Invocation, InvocationResponse, TagMatch};
+
+    // Holds up the first actor invocation until it's released
+    struct Gate {
+        held: AtomicBool,
+        reached: Sender<()>,
+        release: Receiver<()>,
+    }
+
+    impl Middleware for Gate {
+        fn actor_pre_invoke(&self, inv: Invocation) -> wascc_host::Result<Invocation> {
+            if !self.held.swap(true, Ordering::SeqCst) {
+                self.reached.send(()).unwrap();
+                self.release.recv().unwrap();
+            }
+            Ok(inv)
+        }
+        fn actor_invoke(
+            &self,
+            inv: Invocation,
+            handler: InvocationHandler,
+        ) -> wascc_host::Result<MiddlewareResponse> {
+            Ok(MiddlewareResponse::Continue(handler.invoke(inv)))
+        }
+        fn actor_post_invoke(
+            &self,
+            response: InvocationResponse,
+        ) -> wascc_host::Result<InvocationResponse> {
+            Ok(response)
+        }
+        fn capability_pre_invoke(&self, inv: Invocation) -> wascc_host::Result<Invocation> {
+            Ok(inv)
+        }
+        fn capability_invoke(
+            &self,
+            inv: Invocation,
+            handler: InvocationHandler,
+        ) -> wascc_host::Result<MiddlewareResponse> {
+            Ok(MiddlewareResponse::Continue(handler.invoke(inv)))
+        }
+        fn capability_post_invoke(
+            &self,
+            response: InvocationResponse,
+        ) -> wascc_host::Result<InvocationResponse> {
+            Ok(response)
+        }
+    }
+
+    let bytes = std::fs::read("./examples/.assets/echo.wasm")?;
+    let host = Host::new();
+    let mut tagged = vec![];
+    for _ in 0..3 {
+        let actor = crate::common::generate_tagged_actor(&bytes, &["cache"])?;
+        tagged.push(actor.public_key());
+        host.add_actor(actor)?;
+    }
+    tagged.sort();
+    let untagged = crate::common::get_hello_actor()?;
+    let untagged_pk = untagged.public_key();
+    host.add_actor(untagged)?;
+
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/flush".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    let results = host.broadcast_to_actors(
+        ActorFilter::Capability("wascc:http_server".to_string()),
+        OP_HANDLE_REQUEST,
+        &req,
+        4,
+    );
+    assert_eq!(1, results.len());
+    assert_eq!(untagged_pk, results[0].0);
+    assert!(results[0].1.is_ok());
+    assert_eq!(
+        4,
+        host.broadcast_to_actors(ActorFilter::All, OP_HANDLE_REQUEST, &req, 2)
+            .len()
+    );
+
+    // The last actor is removed while the first is being invoked
+    let (reached_s, reached_r) = bounded(1);
+    let (release_s, release_r) = bounded(1);
+    host.add_middleware(Gate {
+        held: AtomicBool::new(false),
+        reached: reached_s,
+        release: release_r,
+    });
+    let filter = ActorFilter::Tags(vec!["cache".to_string()], TagMatch::All);
+    let results = crossbeam_utils::thread::scope(|s| {
+        let broadcast = s.spawn(|_| host.broadcast_to_actors(filter, OP_HANDLE_REQUEST, &req, 1));
+        reached_r.recv().unwrap();
+        host.remove_actor(&tagged[2]).unwrap();
+        while host.claims_for_actor(&tagged[2]).is_some() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        release_s.send(()).unwrap();
+        broadcast.join().unwrap()
+    })
+    .unwrap();
+
+    assert_eq!(
+        tagged,
+        results
+            .iter()
+            .map(|(pk, _)| pk.to_string())
+            .collect::<Vec<_>>()
+    );
+    assert!(results[0].1.is_ok());
+    assert!(results[1].1.is_ok());
+    assert!(results[2].1.is_err());
+    host.shutdown()?;
+    Ok(())
+}
+
+#[cfg(all(feature = "chaos", feature = "prometheus_middleware"))]
+pub(crate) fn chaos_latency_in_metrics() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+    use wascc_codec::serialize;
+    use wascc_host::middleware::chaos::{ChaosConfig, ChaosMiddleware};
+    use wascc_host::middleware::prometheus::{PrometheusConfig, PrometheusMiddleware};
+    use wascc_host::Actor;
+
+    let echo = "MB4OLDIC3TCZ4Q4TGGOVAZC43VXFE2JQVRAXQMQFXUCREOOFEKOKZTY2";
+    let host = Host::new();
+    // Port 0 lets the OS choose a free port
+    let prometheus = PrometheusMiddleware::new(PrometheusConfig {
+        metrics_server_addr: Some(([127, 0, 0, 1], 0).into()),
+        pushgateway_config: None,
+        moving_average_window_size: None,
+        max_operation_labels: None,
+        legacy_metric_names: false,
+    })?;
+    let server_addr: SocketAddr = prometheus.server_addr().unwrap();
+    host.add_middleware(prometheus);
+    let chaos = ChaosMiddleware::new(ChaosConfig::default()).with_target(
+        &format!("wasmbus://{}", echo),
+        ChaosConfig {
+            latency_probability: 1.0,
+            latency: (Duration::from_millis(100), Duration::from_millis(120)),
+            ..Default::default()
+        },
+    );
+    host.add_middleware(chaos.clone());
+    host.add_actor(Actor::from_file("./examples/.assets/echo.wasm")?)?;
+
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    for _ in 0..3 {
+        host.call_actor(echo, OP_HANDLE_REQUEST, &req)?;
+    }
+    assert_eq!(3, chaos.stats().delays);
+
+    let body = reqwest::blocking::get(&format!("http://{}/metrics", server_addr))?.text()?;
+    // Every invocation was delayed by at least 100ms, and none by as much as 250ms
+    let bucket = |le: &str| {
+        format!(
+            "wascc_actor_inv_seconds_bucket{{actor=\"{}\",le=\"{}\"}}",
+            echo, le
+        )
+    };
+    assert!(body.contains(&format!("{} 0", bucket("0.1"))), "{}", body);
+    assert!(body.contains(&format!("{} 3", bucket("0.25"))), "{}", body);
+    assert!(body.contains(&format!(
+        "wascc_actor_inv_seconds_count{{actor=\"{}\"}} 3",
+        echo
+    )));
+    assert!(body.contains(&format!(
+        "wascc_actor_operation_inv_count{{actor=\"{}\",operation=\"{}\"}} 3",
+        echo, OP_HANDLE_REQUEST
+    )));
+
+    host.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+pub(crate) fn remove_and_readd_actor() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use wascc_codec::core::{OP_BIND_ACTOR, OP_REMOVE_ACTOR};
+    use wascc_host::testing::MockCapability;
+    use wascc_host::{Actor, NativeCapability};
+
+    let kvcounter = "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ";
+    let host = Host::new();
+    let kv = MockCapability::new("wascc:keyvalue");
+    host.add_native_capability(NativeCapability::from_instance(kv.clone(), None)?)?;
+
+    for i in 1..=10 {
+        host.add_actor(Actor::from_file("./examples/.assets/kvcounter.wasm")?)?;
+        host.set_binding(kvcounter, "wascc:keyvalue", None, HashMap::new())?;
+        host.remove_actor_sync(kvcounter, Duration::from_secs(5))?;
+        assert!(host.claims_for_actor(kvcounter).is_none());
+        assert!(host
+            .provider_bindings("wascc:keyvalue", "default")
+            .is_empty());
+        assert_eq!(2 * i - 1, kv.calls_for(OP_REMOVE_ACTOR).len());
+
+        // The old instance's unbinding has finished, so it can't undo the new instance's binding
+        host.add_actor(Actor::from_file("./examples/.assets/kvcounter.wasm")?)?;
+        host.set_binding(kvcounter, "wascc:keyvalue", None, HashMap::new())?;
+        assert_eq!(
+            vec![kvcounter.to_string()],
+            host.provider_bindings("wascc:keyvalue", "default")
+        );
+        assert_eq!(2 * i, kv.calls_for(OP_BIND_ACTOR).len());
+        host.remove_actor_sync(kvcounter, Duration::from_secs(5))?;
+    }
+    assert_eq!(20, kv.calls_for(OP_REMOVE_ACTOR).len());
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn actor_runtime_info() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::{Duration, SystemTime};
+    use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+    use wascc_codec::serialize;
+
+    let echo = "MB4OLDIC3TCZ4Q4TGGOVAZC43VXFE2JQVRAXQMQFXUCREOOFEKOKZTY2";
+    let host = Host::new();
+    let before = SystemTime::now();
+    host.add_actor(crate::common::get_hello_actor()?)?;
+    let info = host.actor_runtime_info(echo).unwrap();
+    assert!(info.started_at >= before);
+    assert_eq!(0, info.invocations);
+    assert!(info.last_invocation_at.is_none());
+
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    for _ in 0..3 {
+        host.call_actor(echo, OP_HANDLE_REQUEST, &req)?;
+    }
+    let info = host.actor_runtime_info(echo).unwrap();
+    assert_eq!(3, info.invocations);
+    assert_eq!(0, info.errors);
+    assert!(info.last_error.is_none());
+    assert!(info.last_invocation_at.is_some());
+
+    std::thread::sleep(Duration::from_millis(10));
+    assert!(host.call_actor(echo, "NoSuchOperation", &[]).is_err());
+    let failed = host.actor_runtime_info(echo).unwrap();
+    assert_eq!(4, failed.invocations);
+    assert_eq!(1, failed.errors);
+    assert!(failed.last_error.is_some());
+    assert!(failed.last_error_at.unwrap() > info.last_invocation_at.unwrap());
+    assert_eq!(failed.last_invocation_at, failed.last_error_at);
+
+    host.remove_actor_sync(echo, Duration::from_secs(5))?;
+    assert!(host.actor_runtime_info(echo).is_none());
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn middleware_error_policy() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+    use wascc_codec::serialize;
+    use wascc_host::middleware::{ErrorPolicy, InvocationHandler, Middleware, MiddlewareResponse};
+    use wascc_host::{HostBuilder, Invocation, InvocationResponse};
+
+    // Rejects every actor invocation before it's made
+    struct Gatekeeper;
+
+    impl Middleware for Gatekeeper {
+        fn actor_pre_invoke(&self, _inv: Invocation) -> wascc_host::Result<Invocation> {
+            Err("no credentials".to_string().into())
+        }
+        fn actor_invoke(
+            &self,
+            inv: Invocation,
+            handler: InvocationHandler,
+        ) -> wascc_host::Result<MiddlewareResponse> {
+            Ok(MiddlewareResponse::Continue(handler.invoke(inv)))
+        }
+        fn actor_post_invoke(
+            &self,
+            response: InvocationResponse,
+        ) -> wascc_host::Result<InvocationResponse> {
+            Ok(response)
+        }
+        fn capability_pre_invoke(&self, inv: Invocation) -> wascc_host::Result<Invocation> {
+            Ok(inv)
+        }
+        fn capability_invoke(
+            &self,
+            inv: Invocation,
+            handler: InvocationHandler,
+        ) -> wascc_host::Result<MiddlewareResponse> {
+            Ok(MiddlewareResponse::Continue(handler.invoke(inv)))
+        }
+        fn capability_post_invoke(
+            &self,
+            response: InvocationResponse,
+        ) -> wascc_host::Result<InvocationResponse> {
+            Ok(response)
+        }
+        fn name(&self) -> &str {
+            "gatekeeper"
+        }
+    }
+
+    let echo = "MB4OLDIC3TCZ4Q4TGGOVAZC43VXFE2JQVRAXQMQFXUCREOOFEKOKZTY2";
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+
+    // By default the failure is logged and the invocation goes ahead
+    let host = Host::new();
+    host.add_middleware(Gatekeeper);
+    host.add_actor(crate::common::get_hello_actor()?)?;
+    assert!(host.call_actor(echo, OP_HANDLE_REQUEST, &req).is_ok());
+    host.shutdown()?;
+
+    let host = HostBuilder::new()
+        .with_middleware_error_policy(ErrorPolicy::FailClosed)
+        .build();
+    host.add_middleware(Gatekeeper);
+    host.add_actor(crate::common::get_hello_actor()?)?;
+    let err = host
+        .call_actor(echo, OP_HANDLE_REQUEST, &req)
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("gatekeeper"), "{}", err);
+    assert!(err.contains("no credentials"), "{}", err);
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn actor_start_time() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+    use wascc_host::{HostEvent, NativeCapability};
+
+    let echo = "MB4OLDIC3TCZ4Q4TGGOVAZC43VXFE2JQVRAXQMQFXUCREOOFEKOKZTY2";
+    let host = Host::new();
+    let events = host.events();
+    host.add_actor(crate::common::get_hello_actor()?)?;
+
+    let start_duration = host.actor_runtime_info(echo).unwrap().start_duration;
+    assert!(start_duration > Duration::from_millis(0));
+    assert_eq!(
+        HostEvent::ActorStarted {
+            actor: echo.to_string(),
+            start_duration,
+            annotations: None,
+        },
+        events.recv_timeout(Duration::from_secs(1))?
+    );
+
+    host.add_native_capability(NativeCapability::from_file(
+        "./examples/.assets/libwascc_httpsrv.so",
+        None,
+    )?)?;
+    match events.recv_timeout(Duration::from_secs(1))? {
+        HostEvent::ProviderStarted {
+            capid,
+            load_duration,
+            ..
+        } => {
+            assert_eq!("wascc:http_server", capid);
+            assert!(load_duration > Duration::from_millis(0));
+        }
+        e => panic!("Unexpected event {:?}", e),
+    }
+
+    host.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+pub(crate) fn update_binding_values() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use wascc_codec::core::{CapabilityConfiguration, OP_BIND_ACTOR, OP_REMOVE_ACTOR};
+    use wascc_codec::deserialize;
+    use wascc_host::testing::MockCapability;
+    use wascc_host::{Actor, HostEvent, NativeCapability, CONFIG_WASCC_UPDATE};
+
+    let host = Host::new();
+    let actor = Actor::from_file("./examples/.assets/kvcounter.wasm")?;
+    let pk = actor.public_key();
+    host.add_actor(actor)?;
+    let mock = MockCapability::new("wascc:keyvalue");
+    host.add_native_capability(NativeCapability::from_instance(mock.clone(), None)?)?;
+    let values = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    };
+    let delivered = || -> Vec<HashMap<String, String>> {
+        mock.calls_for(OP_BIND_ACTOR)
+            .iter()
+            .map(|c| {
+                deserialize::<CapabilityConfiguration>(&c.msg)
+                    .unwrap()
+                    .values
+            })
+            .collect()
+    };
+
+    // Only existing bindings can be updated
+    assert!(host
+        .update_binding_values(&pk, "wascc:keyvalue", None, values(&[("PASSWORD", "b")]))
+        .is_err());
+
+    host.set_binding(
+        &pk,
+        "wascc:keyvalue",
+        None,
+        values(&[("URL", "redis://x"), ("PASSWORD", "a")]),
+    )?;
+    assert!(!delivered()[0].contains_key(CONFIG_WASCC_UPDATE));
+
+    let events = host.events();
+    host.update_binding_values(&pk, "wascc:keyvalue", None, values(&[("PASSWORD", "b")]))?;
+    let update = &delivered()[1];
+    assert_eq!("true", update[CONFIG_WASCC_UPDATE]);
+    assert_eq!("redis://x", update["URL"]);
+    assert_eq!("b", update["PASSWORD"]);
+    assert_eq!(
+        HostEvent::BindingUpdated {
+            actor: pk.to_string(),
+            capid: "wascc:keyvalue".to_string(),
+            binding: "default".to_string(),
+        },
+        events.recv_timeout(Duration::from_secs(1))?
+    );
+
+    // The stored configuration reflects the merge (and not the marker), so later updates
+    // build on it
+    host.update_binding_values(&pk, "wascc:keyvalue", None, values(&[("DB", "2")]))?;
+    let update = &delivered()[2];
+    assert_eq!("redis://x", update["URL"]);
+    assert_eq!("b", update["PASSWORD"]);
+    assert_eq!("2", update["DB"]);
+    assert!(mock.calls_for(OP_REMOVE_ACTOR).is_empty());
+
+    // Concurrent updates are applied in turn, so none of their values are lost
+    const THREADS: usize = 4;
+    let barrier = std::sync::Arc::new(std::sync::Barrier::new(THREADS));
+    let threads: Vec<_> = (0..THREADS)
+        .map(|i| {
+            let (host, pk, barrier) = (host.clone(), pk.clone(), barrier.clone());
+            let delta = values(&[(format!("KEY{}", i).as_str(), "v")]);
+            std::thread::spawn(move || {
+                barrier.wait();
+                host.update_binding_values(&pk, "wascc:keyvalue", None, delta)
+                    .map_err(|e| e.to_string())
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap()?;
+    }
+    let last = delivered().pop().unwrap();
+    assert_eq!("2", last["DB"]);
+    assert!((0..THREADS).all(|i| last.contains_key(&format!("KEY{}", i))));
+
+    host.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+pub(crate) fn named_bindings() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use wascc_codec::core::{CapabilityConfiguration, OP_BIND_ACTOR, OP_REMOVE_ACTOR};
+    use wascc_codec::deserialize;
+    use wascc_host::testing::MockCapability;
+    use wascc_host::{Actor, NativeCapability, CONFIG_WASCC_CONFIG_NAME};
+
+    let host = Host::new();
+    let actor = Actor::from_file("./examples/.assets/kvcounter.wasm")?;
+    let pk = actor.public_key();
+    host.add_actor(actor)?;
+    let mock = MockCapability::new("wascc:keyvalue");
+    host.add_native_capability(NativeCapability::from_instance(mock.clone(), None)?)?;
+    let topic = |t: &str| -> HashMap<String, String> {
+        let mut values = HashMap::new();
+        values.insert("TOPIC".to_string(), t.to_string());
+        values
+    };
+    let delivered = |op: &str| -> Vec<HashMap<String, String>> {
+        mock.calls_for(op)
+            .iter()
+            .map(|c| {
+                deserialize::<CapabilityConfiguration>(&c.msg)
+                    .unwrap()
+                    .values
+            })
+            .collect()
+    };
+
+    host.set_binding(&pk, "wascc:keyvalue", None, topic("default"))?;
+    host.set_binding_named(&pk, "wascc:keyvalue", None, "orders", topic("orders"))?;
+    host.set_binding_named(&pk, "wascc:keyvalue", None, "audit", topic("audit"))?;
+    assert!(host
+        .set_binding_named(&pk, "wascc:keyvalue", None, "", topic("x"))
+        .is_err());
+
+    // Each binding arrives separately, the named ones carrying their names
+    let binds = delivered(OP_BIND_ACTOR);
+    assert_eq!(3, binds.len());
+    assert!(!binds[0].contains_key(CONFIG_WASCC_CONFIG_NAME));
+    assert_eq!("orders", binds[1][CONFIG_WASCC_CONFIG_NAME]);
+    assert_eq!("orders", binds[1]["TOPIC"]);
+    assert_eq!("audit", binds[2][CONFIG_WASCC_CONFIG_NAME]);
+    assert_eq!("audit", binds[2]["TOPIC"]);
+    assert_eq!(
+        vec![pk.to_string()],
+        host.provider_bindings("wascc:keyvalue", "default")
+    );
+
+    // Removing a named binding leaves the actor's others in place
+    host.remove_binding_named(&pk, "wascc:keyvalue", None, "orders")?;
+    let removes = delivered(OP_REMOVE_ACTOR);
+    assert_eq!(1, removes.len());
+    assert_eq!("orders", removes[0][CONFIG_WASCC_CONFIG_NAME]);
+    assert_eq!(
+        vec![pk.to_string()],
+        host.provider_bindings("wascc:keyvalue", "default")
+    );
+
+    // Removing the binding removes the remaining named ones with it
+    host.remove_binding(&pk, "wascc:keyvalue", None)?;
+    let removes = delivered(OP_REMOVE_ACTOR);
+    assert_eq!(3, removes.len());
+    assert_eq!("audit", removes[1][CONFIG_WASCC_CONFIG_NAME]);
+    assert!(!removes[2].contains_key(CONFIG_WASCC_CONFIG_NAME));
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(host
+        .provider_bindings("wascc:keyvalue", "default")
+        .is_empty());
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn actor_reads_host_metadata() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use wascc_codec::deserialize;
+    use wascc_host::hostmeta::{OP_GET_HOST_ID, OP_GET_LABELS, OP_GET_NAMESPACE};
+    use wascc_host::HostBuilder;
+
+    let host = HostBuilder::new().with_label("region", "east").build();
+    let actor = crate::common::generate_hostmeta_reader_actor()?;
+    let pk = actor.public_key();
+    // An actor attested for the capability is bound to the provider when it's added
+    host.add_actor(actor)?;
+
+    let id: String = deserialize(&host.call_actor(&pk, OP_GET_HOST_ID, &[])?)?;
+    assert_eq!(host.id(), id);
+    let ns: Option<String> = deserialize(&host.call_actor(&pk, OP_GET_NAMESPACE, &[])?)?;
+    assert_eq!(None, ns);
+    let labels: HashMap<String, String> =
+        deserialize(&host.call_actor(&pk, OP_GET_LABELS, &[])?)?;
+    assert_eq!("east", labels["region"]);
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn extras_bindings_released() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+    use wascc_host::{extras, Actor};
+
+    let host = Host::new();
+    let actor = Actor::from_file("./examples/.assets/extras.wasm")?;
+    let pk = actor.public_key();
+    host.add_actor(actor)?;
+    let served = |binding: &str| {
+        host.capabilities()
+            .contains_key(&(binding.to_string(), extras::CAPABILITY_ID.to_string()))
+    };
+
+    // The provider for another binding name stays until the last of the actor's bindings
+    // with that name is removed
+    host.set_binding(
+        &pk,
+        extras::CAPABILITY_ID,
+        Some("orders".into()),
+        HashMap::new(),
+    )?;
+    host.set_binding_named(
+        &pk,
+        extras::CAPABILITY_ID,
+        Some("orders".into()),
+        "audit",
+        HashMap::new(),
+    )?;
+    assert!(served("orders"));
+    host.remove_binding_named(&pk, extras::CAPABILITY_ID, Some("orders".into()), "audit")?;
+    assert!(served("orders"));
+    host.remove_binding(&pk, extras::CAPABILITY_ID, Some("orders".into()))?;
+    assert!(!served("orders"));
+    assert!(served("default"));
+
+    // Nor does it outlive the actor
+    host.set_binding(
+        &pk,
+        extras::CAPABILITY_ID,
+        Some("orders".into()),
+        HashMap::new(),
+    )?;
+    assert!(served("orders"));
+    host.remove_actor(&pk)?;
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while served("orders") {
+        assert!(Instant::now() < deadline, "extras provider wasn't removed");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(served("default"));
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn configure_actor() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use wascc_codec::core::{CapabilityConfiguration, OP_BIND_ACTOR};
+    use wascc_codec::{deserialize, SYSTEM_ACTOR};
+    use wascc_host::middleware::{InvocationHandler, Middleware, MiddlewareResponse};
+    use wascc_host::{Invocation, InvocationResponse, WasccEntity};
+
+    // Records the configuration invocations on their way to actors
+    #[derive(Clone, Default)]
+    struct Recorder {
+        configs: Arc<Mutex<Vec<Invocation>>>,
+    }
+
+    impl Middleware for Recorder {
+        fn actor_pre_invoke(&self, inv: Invocation) -> wascc_host::Result<Invocation> {
+            if inv.operation == OP_BIND_ACTOR {
+                self.configs.lock().unwrap().push(inv.clone());
+            }
+            Ok(inv)
+        }
+        fn actor_invoke(
+            &self,
+            inv: Invocation,
+            handler: InvocationHandler,
+        ) -> wascc_host::Result<MiddlewareResponse> {
+            Ok(MiddlewareResponse::Continue(handler.invoke(inv)))
+        }
+        fn actor_post_invoke(
+            &self,
+            response: InvocationResponse,
+        ) -> wascc_host::Result<InvocationResponse> {
+            Ok(response)
+        }
+        fn capability_pre_invoke(&self, inv: Invocation) -> wascc_host::Result<Invocation> {
+            Ok(inv)
+        }
+        fn capability_invoke(
+            &self,
+            inv: Invocation,
+            handler: InvocationHandler,
+        ) -> wascc_host::Result<MiddlewareResponse> {
+            Ok(MiddlewareResponse::Continue(handler.invoke(inv)))
+        }
+        fn capability_post_invoke(
+            &self,
+            response: InvocationResponse,
+        ) -> wascc_host::Result<InvocationResponse> {
+            Ok(response)
+        }
+    }
+
+    let host = Host::new();
+    let recorder = Recorder::default();
+    host.add_middleware(recorder.clone());
+    // The actor keeps the configuration it's given, and answers other operations with it
+    let actor = crate::common::generate_config_recorder_actor()?;
+    let pk = actor.public_key();
+    host.add_actor(actor)?;
+
+    let mut values = HashMap::new();
+    values.insert("GREETING".to_string(), "hello".to_string());
+    host.configure_actor(&pk, values.clone())?;
+
+    let configs = recorder.configs.lock().unwrap().clone();
+    assert_eq!(1, configs.len());
+    assert_eq!(
+        WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
+        configs[0].origin
+    );
+    assert_eq!(WasccEntity::Actor(pk.to_string()), configs[0].target);
+    let cfg: CapabilityConfiguration = deserialize(&configs[0].msg)?;
+    assert_eq!(pk, cfg.module);
+    assert_eq!("hello", cfg.values["GREETING"]);
+    // The actor itself handled the configuration
+    let handled: Vec<_> = host
+        .actor_recent_invocations(&pk)
+        .into_iter()
+        .filter(|e| e.operation == OP_BIND_ACTOR)
+        .collect();
+    assert_eq!(1, handled.len());
+    assert_eq!(
+        WasccEntity::Actor(SYSTEM_ACTOR.to_string()).url(),
+        handled[0].origin
+    );
+    assert!(handled[0].error.is_none());
+    let kept: CapabilityConfiguration = deserialize(&host.call_actor(&pk, "GetConfig", &[])?)?;
+    assert_eq!(pk, kept.module);
+    assert_eq!("hello", kept.values["GREETING"]);
+
+    // Reserved keys and unknown actors are refused
+    values.insert("__wascc_host_id".to_string(), "Nxxx".to_string());
+    assert!(host.configure_actor(&pk, values).is_err());
+    assert!(host
+        .configure_actor("Mnosuchactor", HashMap::new())
+        .is_err());
+    assert_eq!(1, recorder.configs.lock().unwrap().len());
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn concurrent_add_actor() -> Result<(), Box<dyn Error>> {
+    use std::sync::{Arc, Barrier};
+    use wascc_host::{Actor, NativeCapability};
+
+    const THREADS: usize = 8;
+    let host = Host::new();
+    let bytes = std::fs::read("./examples/.assets/echo.wasm")?;
+    let barrier = Arc::new(Barrier::new(THREADS));
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let (host, bytes, barrier) = (host.clone(), bytes.clone(), barrier.clone());
+            std::thread::spawn(move || {
+                let actor = Actor::from_slice(&bytes).unwrap();
+                barrier.wait();
+                host.add_actor(actor).map_err(|e| e.to_string())
+            })
+        })
+        .collect();
+    let results: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+    assert_eq!(1, results.iter().filter(|r| r.is_ok()).count());
+    for e in results.iter().filter_map(|r| r.as_ref().err()) {
+        assert!(e.contains("already in this host"), "{}", e);
+    }
+    assert_eq!(1, host.actors().len());
+
+    let barrier = Arc::new(Barrier::new(THREADS));
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let (host, barrier) = (host.clone(), barrier.clone());
+            std::thread::spawn(move || {
+                let cap =
+                    NativeCapability::from_file("./examples/.assets/libkeyvalue.so", None).unwrap();
+                barrier.wait();
+                host.add_native_capability(cap).is_ok()
+            })
+        })
+        .collect();
+    let results: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+    assert_eq!(1, results.iter().filter(|ok| **ok).count());
+
+    host.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+pub(crate) fn invocation_origin_chain() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+    use wascc_codec::serialize;
+    use wascc_host::middleware::{InvocationHandler, Middleware, MiddlewareResponse};
+    use wascc_host::testing::MockCapability;
+    use wascc_host::{Actor, Invocation, InvocationResponse, NativeCapability, WasccEntity};
+
+    // Records the chains of invocations reaching actors and capability providers
+    #[derive(Clone, Default)]
+    struct Chains {
+        actors: Arc<Mutex<Vec<(String, Vec<String>)>>>,
+        capabilities: Arc<Mutex<Vec<Vec<String>>>>,
+    }
+
+    impl Middleware for Chains {
+        fn actor_pre_invoke(&self, inv: Invocation) -> wascc_host::Result<Invocation> {
+            self.actors
+                .lock()
+                .unwrap()
+                .push((inv.target.url(), inv.origin_chain.clone()));
+            Ok(inv)
+        }
+        fn actor_invoke(
+            &self,
+            inv: Invocation,
+            handler: InvocationHandler,
+        ) -> wascc_host::Result<MiddlewareResponse> {
+            Ok(MiddlewareResponse::Continue(handler.invoke(inv)))
+        }
+        fn actor_post_invoke(
+            &self,
+            response: InvocationResponse,
+        ) -> wascc_host::Result<InvocationResponse> {
+            Ok(response)
+        }
+        fn capability_pre_invoke(&self, inv: Invocation) -> wascc_host::Result<Invocation> {
+            self.capabilities
+                .lock()
+                .unwrap()
+                .push(inv.origin_chain.clone());
+            Ok(inv)
+        }
+        fn capability_invoke(
+            &self,
+            inv: Invocation,
+            handler: InvocationHandler,
+        ) -> wascc_host::Result<MiddlewareResponse> {
+            Ok(MiddlewareResponse::Continue(handler.invoke(inv)))
+        }
+        fn capability_post_invoke(
+            &self,
+            response: InvocationResponse,
+        ) -> wascc_host::Result<InvocationResponse> {
+            Ok(response)
+        }
+    }
+
+    let host = Host::new();
+    let chains = Chains::default();
+    host.add_middleware(chains.clone());
+    let actor = Actor::from_file("./examples/.assets/kvcounter.wasm")?;
+    let pk = actor.public_key();
+    host.add_actor(actor)?;
+    let mock = MockCapability::new("wascc:keyvalue");
+    host.add_native_capability(NativeCapability::from_instance(mock.clone(), None)?)?;
+    host.set_binding(&pk, "wascc:keyvalue", None, HashMap::new())?;
+    // The counter is called by another actor rather than the host
+    let forwarder = crate::common::generate_forwarder_actor(&[&pk])?;
+    let forwarder_pk = forwarder.public_key();
+    host.add_actor(forwarder)?;
+    chains.actors.lock().unwrap().clear();
+    chains.capabilities.lock().unwrap().clear();
+
+    let mut msg = pk.as_bytes().to_vec();
+    msg.extend_from_slice(&serialize(Request {
+        method: "GET".to_string(),
+        path: "/counter".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?);
+    // The mock has no handler for the counter's operation, so only the calls themselves matter
+    let _ = host.call_actor(&forwarder_pk, OP_HANDLE_REQUEST, &msg);
+
+    let actors = chains.actors.lock().unwrap().clone();
+    assert!(actors.contains(&(WasccEntity::Actor(forwarder_pk.to_string()).url(), vec![])));
+    assert!(actors.contains(&(
+        WasccEntity::Actor(pk.to_string()).url(),
+        vec![forwarder_pk.to_string()]
+    )));
+    assert!(!mock.calls().is_empty());
+    let seen = chains.capabilities.lock().unwrap().clone();
+    assert!(!seen.is_empty());
+    for chain in seen {
+        assert_eq!(vec![forwarder_pk.to_string(), pk.to_string()], chain);
+    }
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn call_actor_typed() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+    use wascc_codec::serialize;
+    use wascc_host::middleware::{InvocationHandler, Middleware, MiddlewareResponse};
+    use wascc_host::{Actor, Invocation, InvocationResponse};
+
+    // Records the content types of invocations reaching actors
+    #[derive(Clone, Default)]
+    struct ContentTypes {
+        seen: Arc<Mutex<Vec<Option<String>>>>,
+    }
+
+    impl Middleware for ContentTypes {
+        fn actor_pre_invoke(&self, inv: Invocation) -> wascc_host::Result<Invocation> {
+            self.seen.lock().unwrap().push(inv.content_type.clone());
+            Ok(inv)
+        }
+        fn actor_invoke(
+            &self,
+            inv: Invocation,
+            handler: InvocationHandler,
+        ) -> wascc_host::Result<MiddlewareResponse> {
+            Ok(MiddlewareResponse::Continue(handler.invoke(inv)))
+        }
+        fn actor_post_invoke(
+            &self,
+            response: InvocationResponse,
+        ) -> wascc_host::Result<InvocationResponse> {
+            Ok(response)
+        }
+        fn capability_pre_invoke(&self, inv: Invocation) -> wascc_host::Result<Invocation> {
+            Ok(inv)
+        }
+        fn capability_invoke(
+            &self,
+            inv: Invocation,
+            handler: InvocationHandler,
+        ) -> wascc_host::Result<MiddlewareResponse> {
+            Ok(MiddlewareResponse::Continue(handler.invoke(inv)))
+        }
+        fn capability_post_invoke(
+            &self,
+            response: InvocationResponse,
+        ) -> wascc_host::Result<InvocationResponse> {
+            Ok(response)
+        }
+    }
+
+    let echo = "MB4OLDIC3TCZ4Q4TGGOVAZC43VXFE2JQVRAXQMQFXUCREOOFEKOKZTY2";
+    let host = Host::new();
+    let content_types = ContentTypes::default();
+    host.add_middleware(content_types.clone());
+    host.add_actor(Actor::from_file("./examples/.assets/echo.wasm")?)?;
+
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    let typed = host.call_actor_typed(echo, OP_HANDLE_REQUEST, &req, "application/msgpack")?;
+    let untyped = host.call_actor(echo, OP_HANDLE_REQUEST, &req)?;
+    assert_eq!(typed, untyped);
+    assert_eq!(
+        vec![Some("application/msgpack".to_string()), None],
+        *content_types.seen.lock().unwrap()
+    );
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn actor_annotations() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use wascc_host::{Actor, HostEvent};
+
+    let echo = "MB4OLDIC3TCZ4Q4TGGOVAZC43VXFE2JQVRAXQMQFXUCREOOFEKOKZTY2";
+    let kvcounter = "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ";
+    let host = Host::new();
+    let events = host.events();
+
+    let mut annotations = HashMap::new();
+    annotations.insert("team".to_string(), "payments".to_string());
+    annotations.insert("canary".to_string(), "true".to_string());
+    host.add_actor_with_annotations(crate::common::get_hello_actor()?, annotations.clone())?;
+    let mut kv_annotations = HashMap::new();
+    kv_annotations.insert("team".to_string(), "payments".to_string());
+    host.add_actor_with_annotations(
+        Actor::from_file("./examples/.assets/kvcounter.wasm")?,
+        kv_annotations,
+    )?;
+    host.add_actor(crate::common::get_hello2_actor()?)?;
+
+    match events.recv_timeout(Duration::from_secs(1))? {
+        HostEvent::ActorStarted {
+            actor,
+            annotations: a,
+            ..
+        } => {
+            assert_eq!(echo, actor);
+            assert_eq!(Some(annotations.clone()), a);
+        }
+        e => panic!("unexpected event {:?}", e),
+    }
+
+    assert_eq!(
+        vec![kvcounter.to_string(), echo.to_string()],
+        host.actors_by_annotation("team", "payments")
+    );
+    assert_eq!(
+        vec![echo.to_string()],
+        host.actors_by_annotation("canary", "true")
+    );
+    assert!(host.actors_by_annotation("team", "search").is_empty());
+    assert_eq!(Some(annotations.clone()), host.actor_annotations(echo));
+    assert_eq!(
+        annotations,
+        host.actor_runtime_info(echo).unwrap().annotations
+    );
+
+    host.set_actor_annotation(kvcounter, "canary", "true")?;
+    assert_eq!(
+        vec![kvcounter.to_string(), echo.to_string()],
+        host.actors_by_annotation("canary", "true")
+    );
+    assert!(host
+        .set_actor_annotation("Mnothere", "canary", "true")
+        .is_err());
+    assert!(host.actor_annotations("Mnothere").is_none());
+
+    // Replacing the module keeps the actor's annotations
+    host.replace_actor(Actor::from_file(
+        "./examples/.assets/kvcounter_tweaked.wasm",
+    )?)?;
+    assert_eq!(
+        Some("true"),
+        host.actor_annotations(kvcounter)
+            .unwrap()
+            .get("canary")
+            .map(String::as_str)
+    );
+
+    host.remove_actor_sync(echo, Duration::from_secs(5))?;
+    assert!(host.actor_annotations(echo).is_none());
+    assert_eq!(
+        vec![kvcounter.to_string()],
+        host.actors_by_annotation("team", "payments")
+    );
+    let stopped = events
+        .try_iter()
+        .find(|e| match e {
+            HostEvent::ActorStopped { actor, .. } => actor == echo,
+            _ => false,
+        })
+        .unwrap();
+    assert_eq!(
+        HostEvent::ActorStopped {
+            actor: echo.to_string(),
+            annotations: Some(annotations),
+        },
+        stopped
+    );
+
+    host.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "admin_api")]
+pub(crate) fn admin_socket() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use wascc_host::{Actor, HostBuilder, NativeCapability};
+
+    const ADDR: &str = "127.0.0.1:9911";
+    let host = HostBuilder::new()
+        .with_admin_socket(ADDR)
+        .with_admin_token("s3cret")
+        .build();
+    let echo = Actor::from_file("./examples/.assets/echo.wasm")?;
+    let pk = echo.public_key();
+    host.add_actor(echo)?;
+    host.add_native_capability(NativeCapability::from_file(
+        "./examples/.assets/libwascc_httpsrv.so",
+        None,
+    )?)?;
+    let mut config = HashMap::new();
+    config.insert("PORT".to_string(), "9912".to_string());
+    host.set_binding(&pk, "wascc:http_server", None, config)?;
+
+    let get = |path: &str| -> Result<serde_json::Value, Box<dyn Error>> {
+        let resp = reqwest::blocking::get(&format!("http://{}{}", ADDR, path))?;
+        assert!(resp.status().is_success());
+        Ok(serde_json::from_str(&resp.text()?)?)
+    };
+
+    let info = get("/info")?;
+    assert_eq!(host.id(), info["id"]);
+    let actors = get("/actors")?;
+    assert_eq!(1, actors.as_array().unwrap().len());
+    assert_eq!(pk, actors[0]["id"]);
+    let caps = get("/capabilities")?;
+    assert!(caps
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|c| c["capid"] == "wascc:http_server" && c["binding"] == "default"));
+    let bindings = get("/bindings")?;
+    let binding = bindings
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|b| b["capid"] == "wascc:http_server")
+        .unwrap();
+    assert_eq!(pk, binding["actor"]);
+    // only the names of configuration values are exposed
+    assert_eq!(serde_json::json!(["PORT"]), binding["values"]);
+    let snapshot = get("/snapshot")?;
+    assert_eq!(actors, snapshot["actors"]);
+    assert_eq!(bindings, snapshot["bindings"]);
+    assert_eq!(
+        host.labels()["hostcore.os"],
+        snapshot["labels"]["hostcore.os"]
+    );
+    let stats = get("/stats")?;
+    assert_eq!(1, stats["actors"]);
+
+    let client = reqwest::blocking::Client::new();
+    let url = format!("http://{}/shutdown", ADDR);
+    let resp = client.post(&url).send()?;
+    assert_eq!(reqwest::StatusCode::UNAUTHORIZED, resp.status());
+    let resp = client.post(&url).bearer_auth("wrong").send()?;
+    assert_eq!(reqwest::StatusCode::UNAUTHORIZED, resp.status());
+    assert_eq!(1, host.actors().len());
+    let resp = client.post(&url).bearer_auth("s3cret").send()?;
+    assert_eq!(reqwest::StatusCode::ACCEPTED, resp.status());
+    drop(client);
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(10);
+    while !host.actors().is_empty() && std::time::Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert!(host.actors().is_empty());
+    // the admin server stops with the host
+    std::thread::sleep(Duration::from_millis(500));
+    assert!(reqwest::blocking::get(&format!("http://{}/info", ADDR)).is_err());
+    Ok(())
+}
+
+#[cfg(feature = "manifest")]
+pub(crate) fn manifest_parallel() -> Result<(), Box<dyn Error>> {
+    use wascc_host::HostManifest;
+
+    // Ten copies of the echo actor, each signed with its own subject
+    let dir = std::env::temp_dir().join(format!("wascc-parallel-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let bytes = std::fs::read("./examples/.assets/echo.wasm")?;
+    let mut paths = Vec::new();
+    for i in 0..10 {
+        let path = dir.join(format!("echo{}.wasm", i));
+        std::fs::write(
+            &path,
+            crate::common::generate_timed_actor(&bytes, None, None)?,
+        )?;
+        paths.push(path.to_string_lossy().to_string());
+    }
+    let mut actors = paths.clone();
+    actors.insert(3, "./examples/.assets/nosuch.wasm".to_string());
+    let manifest: HostManifest = serde_json::from_value(serde_json::json!({ "actors": actors }))?;
+
+    let host = Host::new();
+    let serial = host.apply_manifest_with_report(manifest.clone(), true)?;
+    host.shutdown()?;
+
+    let host = Host::new();
+    let parallel = host.apply_manifest_parallel(manifest, 4)?;
+
+    // Failures are reported per entry, in manifest order, just as when applied serially
+    assert_eq!(serial, parallel);
+    assert_eq!(11, parallel.actors.len());
+    assert_eq!(1, parallel.failures().count());
+    assert!(parallel.actors[3].error.is_some());
+    assert_eq!(10, host.actors().len());
+    host.shutdown()?;
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+pub(crate) fn capability_allowlist() -> Result<(), Box<dyn Error>> {
+    use wascc_host::errors::ErrorKind;
+    use wascc_host::{HostBuilder, NativeCapability};
+
+    let host = HostBuilder::new()
+        .with_capability_allowlist(vec!["wascc:KeyValue".to_string()])
+        .build();
+    let err = host
+        .add_native_capability(NativeCapability::from_file(
+            "./examples/.assets/libwascc_fs.so",
+            None,
+        )?)
+        .unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Authorization(_)));
+    host.add_native_capability(NativeCapability::from_file(
+        "./examples/.assets/libkeyvalue.so",
+        None,
+    )?)?;
+
+    // Of the built-in providers only extras is implicitly allowed
+    let mut capids: Vec<_> = host
+        .capabilities()
+        .keys()
+        .map(|(_, capid)| capid.to_string())
+        .collect();
+    capids.sort();
+    assert_eq!(vec!["wascc:extras", "wascc:keyvalue"], capids);
+    assert_eq!(
+        Some(vec![
+            "wascc:extras".to_string(),
+            "wascc:keyvalue".to_string()
+        ]),
+        host.info().capability_allowlist
+    );
+    host.shutdown()?;
+
+    let host = HostBuilder::new()
+        .with_extras_provider(false)
+        .with_capability_allowlist(vec!["wascc:keyvalue".to_string()])
+        .build();
+    assert!(host.capabilities().is_empty());
+    assert_eq!(
+        Some(vec!["wascc:keyvalue".to_string()]),
+        host.info().capability_allowlist
+    );
+    host.shutdown()?;
+
+    assert!(Host::new().info().capability_allowlist.is_none());
+    Ok(())
+}
+
+pub(crate) fn host_features() -> Result<(), Box<dyn Error>> {
+    use wascc_host::Host;
+
+    // Which features are reported is checked by the unit tests; the host reports them the
+    // same way wherever it's asked
+    let host = Host::new();
+    let features = host.features();
+    assert!(!features.is_empty());
+    assert_eq!(
+        features,
+        host.info()
+            .features
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+    );
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn actor_imports() -> Result<(), Box<dyn Error>> {
+    use wascc_host::{Actor, HostBuilder};
+
+    // A portable capability provider imports WASI, which plain actors aren't given
+    let wasi = Actor::from_file("./examples/.assets/wasi_provider.wasm")?;
+    assert!(wasi.imports().wascc_host_calls);
+    assert_eq!(
+        vec!["wasi_snapshot_preview1".to_string()],
+        wasi.imports().wasi_modules
+    );
+    assert!(wasi.imports().unknown_imports.is_empty());
+
+    let host = HostBuilder::new().with_strict_imports(true).build();
+    let err = host.add_actor(wasi).unwrap_err();
+    assert!(err.to_string().contains("WASI"), "{}", err);
+    assert!(host.actors().is_empty());
+
+    let echo = Actor::from_file("./examples/.assets/echo.wasm")?;
+    let pk = echo.public_key();
+    host.add_actor(echo)?;
+    let imports = host.actor_runtime_info(&pk).unwrap().imports;
+    assert!(imports.wascc_host_calls);
+    assert!(imports.wasi_modules.is_empty());
+    assert!(imports.unknown_imports.is_empty());
+    host.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+pub(crate) fn bind_handler_dispatches_to_actor() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use wascc_codec::core::{CapabilityConfiguration, OP_BIND_ACTOR};
+    use wascc_codec::http::{Request, Response, OP_HANDLE_REQUEST};
+    use wascc_codec::keyvalue::OP_ADD;
+    use wascc_codec::{deserialize, serialize};
+    use wascc_host::testing::MockCapability;
+    use wascc_host::{Actor, NativeCapability};
+
+    let host = Host::new();
+    let actor = Actor::from_file("./examples/.assets/kvcounter.wasm")?;
+    let pk = actor.public_key();
+    host.add_actor(actor)?;
+    let kv = MockCapability::new("wascc:keyvalue");
+    kv.on(OP_ADD, |_actor, _msg| {
+        let mut hm = HashMap::new();
+        hm.insert("value", 1);
+        serialize(&hm)
+    });
+    host.add_native_capability(NativeCapability::from_instance(kv.clone(), None)?)?;
+    host.set_binding(&pk, "wascc:keyvalue", None, HashMap::new())?;
+
+    // A provider that sends each actor bound to it a request, which the actor answers by calling
+    // the key-value provider, while yet another provider is being added to the host
+    let web = MockCapability::new("wascc:http_server");
+    let (h, w) = (host.clone(), web.clone());
+    web.on(OP_BIND_ACTOR, move |_actor, msg| {
+        let cfg: CapabilityConfiguration = deserialize(msg)?;
+        let h = h.clone();
+        let adding = std::thread::spawn(move || {
+            let cap =
+                NativeCapability::from_instance(MockCapability::new("wascc:messaging"), None)?;
+            h.add_native_capability(cap)
+        });
+        std::thread::sleep(Duration::from_millis(100));
+        let req = Request {
+            method: "GET".to_string(),
+            path: "/counter".to_string(),
+            query_string: "".to_string(),
+            header: HashMap::new(),
+            body: vec![],
+        };
+        let res: Response =
+            deserialize(&w.dispatch(&cfg.module, OP_HANDLE_REQUEST, &serialize(&req)?)?)?;
+        if res.body != b"{\"counter\":1}".to_vec() {
+            return Err("Unexpected response from the actor".into());
+        }
+        adding.join().unwrap().map_err(|e| e.to_string())?;
+        Ok(vec![])
+    });
+    host.add_native_capability(NativeCapability::from_instance(web.clone(), None)?)?;
+
+    let (s, r) = std::sync::mpsc::channel();
+    let (h, actor) = (host.clone(), pk.clone());
+    std::thread::spawn(move || {
+        let res = h.set_binding(&actor, "wascc:http_server", None, HashMap::new());
+        let _ = s.send(res.map_err(|e| e.to_string()));
+    });
+    r.recv_timeout(Duration::from_secs(10))
+        .expect("set_binding did not complete")?;
+
+    assert_eq!(1, kv.calls_for(OP_ADD).len());
+    assert!(host
+        .capabilities()
+        .contains_key(&("default".to_string(), "wascc:messaging".to_string())));
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn bind_before_add() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use wascc_codec::http::{Request, Response, OP_HANDLE_REQUEST};
+    use wascc_codec::{deserialize, serialize};
+    use wascc_host::{Actor, HostBuilder, NativeCapability};
+
+    let host = Host::new();
+    let actor = Actor::from_file("./examples/.assets/kvcounter.wasm")?;
+    let pk = actor.public_key();
+    host.add_native_capability(NativeCapability::from_file(
+        "./examples/.assets/libkeyvalue.so",
+        None,
+    )?)?;
+    assert!(host
+        .set_binding(&pk, "wascc:keyvalue", None, HashMap::new())
+        .is_err());
+
+    // Registrations lapse, and can be withdrawn
+    let short = HostBuilder::new()
+        .with_claims_registration_ttl(Duration::from_millis(100))
+        .build();
+    short.add_native_capability(NativeCapability::from_file(
+        "./examples/.assets/libkeyvalue.so",
+        None,
+    )?)?;
+    short.register_actor_claims(actor.claims())?;
+    std::thread::sleep(Duration::from_millis(150));
+    assert!(short
+        .set_binding(&pk, "wascc:keyvalue", None, HashMap::new())
+        .is_err());
+    short.shutdown()?;
+    host.register_actor_claims(actor.claims())?;
+    host.unregister_actor_claims(&pk)?;
+    assert!(host.unregister_actor_claims(&pk).is_err());
+    assert!(host
+        .set_binding(&pk, "wascc:keyvalue", None, HashMap::new())
+        .is_err());
+
+    host.register_actor_claims(actor.claims())?;
+    host.set_binding(&pk, "wascc:keyvalue", None, HashMap::new())?;
+    assert!(host.actors().is_empty());
+    assert!(host.register_actor_claims(actor.claims()).is_ok());
+
+    // Registered claims that disagree with the module's are refused when the actor is added
+    let echo = Actor::from_file("./examples/.assets/echo.wasm")?;
+    let mut claims = echo.claims();
+    claims.metadata.as_mut().unwrap().caps = Some(vec!["wascc:keyvalue".to_string()]);
+    host.register_actor_claims(claims)?;
+    let err = host.add_actor(echo).unwrap_err();
+    assert!(err.to_string().contains("don't match"), "{}", err);
+
+    // Once added, the actor goes by the signed claims in its module
+    let signed = actor.claims();
+    host.add_actor(actor)?;
+    assert_eq!(Some(signed), host.claims_for_actor(&pk));
+    assert!(host
+        .register_actor_claims(host.claims_for_actor(&pk).unwrap())
+        .is_err());
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/counter".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    let resp: Response = deserialize(&host.call_actor(&pk, OP_HANDLE_REQUEST, &req)?)?;
+    assert_eq!(200, resp.status_code);
+    assert!(resp.body.starts_with(b"{\"counter\":"));
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn snapshots_are_consistent() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use wascc_host::{Actor, NativeCapability};
+
+    let host = Arc::new(Host::new());
+    let actor = Actor::from_file("./examples/.assets/kvcounter.wasm")?;
+    let pk = actor.public_key();
+    host.add_actor(actor)?;
+
+    let done = Arc::new(AtomicBool::new(false));
+    let reader = {
+        let host = host.clone();
+        let done = done.clone();
+        std::thread::spawn(move || {
+            let mut taken = 0;
+            while !done.load(Ordering::SeqCst) {
+                let snapshot = host.snapshot();
+                for b in &snapshot.bindings {
+                    assert!(
+                        snapshot
+                            .capabilities
+                            .contains_key(&(b.binding.to_string(), b.capid.to_string())),
+                        "binding {:?} to a provider missing from the snapshot",
+                        b
+                    );
+                }
+                assert!(snapshot.actors.windows(2).all(|w| w[0].0 < w[1].0));
+                taken += 1;
+            }
+            taken
+        })
+    };
+
+    for _ in 0..10 {
+        host.add_native_capability(NativeCapability::from_file(
+            "./examples/.assets/libkeyvalue.so",
+            None,
+        )?)?;
+        host.set_binding(&pk, "wascc:keyvalue", None, HashMap::new())?;
+        let snapshot = host.snapshot();
+        assert_eq!(1, snapshot.bindings.len());
+        assert_eq!(pk, snapshot.actors[0].0);
+        host.remove_native_capability("wascc:keyvalue", None)?;
+    }
+    done.store(true, Ordering::SeqCst);
+    assert!(reader.join().unwrap() > 0);
+    assert!(host.snapshot().bindings.is_empty());
+    host.shutdown()?;
+    Ok(())
+}
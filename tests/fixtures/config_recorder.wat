@@ -0,0 +1,45 @@
+;; A waPC actor that keeps the payload of the last `BindActor` invocation it receives (the
+;; configuration the host delivered) and returns it when invoked with any other operation.
+;; Built with `wat2wasm config_recorder.wat`, and signed by the tests that use it
+(module
+  (import "wapc" "__guest_request" (func $guest_request (param i32 i32)))
+  (import "wapc" "__guest_response" (func $guest_response (param i32 i32)))
+
+  ;; 0: the bind operation, 64: operation, 1024: payload, 65536: kept configuration
+  (memory (export "memory") 4)
+  (data (i32.const 0) "BindActor")
+  (global $kept_len (mut i32) (i32.const 0))
+
+  (func $is_bind (param $op_len i32) (result i32)
+    (local $i i32)
+    (if (i32.ne (local.get $op_len) (i32.const 9))
+      (then (return (i32.const 0))))
+    (block $done
+      (loop $next
+        (br_if $done (i32.eq (local.get $i) (i32.const 9)))
+        (if (i32.ne
+              (i32.load8_u (i32.add (i32.const 64) (local.get $i)))
+              (i32.load8_u (local.get $i)))
+          (then (return (i32.const 0))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (i32.const 1))
+
+  (func (export "__guest_call") (param $op_len i32) (param $msg_len i32) (result i32)
+    (local $i i32)
+    (call $guest_request (i32.const 64) (i32.const 1024))
+    (if (call $is_bind (local.get $op_len))
+      (then
+        (block $done
+          (loop $next
+            (br_if $done (i32.eq (local.get $i) (local.get $msg_len)))
+            (i32.store8
+              (i32.add (i32.const 65536) (local.get $i))
+              (i32.load8_u (i32.add (i32.const 1024) (local.get $i))))
+            (local.set $i (i32.add (local.get $i) (i32.const 1)))
+            (br $next)))
+        (global.set $kept_len (local.get $msg_len))
+        (call $guest_response (i32.const 0) (i32.const 0))
+        (return (i32.const 1))))
+    (call $guest_response (i32.const 65536) (global.get $kept_len))
+    (i32.const 1)))
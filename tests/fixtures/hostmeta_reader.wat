@@ -0,0 +1,37 @@
+;; A waPC actor that passes each invocation's operation on to the host metadata provider
+;; (`wascc:hostmeta`, default binding) and returns the provider's response, or the host's error.
+;; Built with `wat2wasm hostmeta_reader.wat`, and signed by the tests that use it
+(module
+  (import "wapc" "__guest_request" (func $guest_request (param i32 i32)))
+  (import "wapc" "__host_call"
+    (func $host_call (param i32 i32 i32 i32 i32 i32 i32 i32) (result i32)))
+  (import "wapc" "__host_response" (func $host_response (param i32)))
+  (import "wapc" "__host_response_len" (func $host_response_len (result i32)))
+  (import "wapc" "__host_error" (func $host_error (param i32)))
+  (import "wapc" "__host_error_len" (func $host_error_len (result i32)))
+  (import "wapc" "__guest_response" (func $guest_response (param i32 i32)))
+  (import "wapc" "__guest_error" (func $guest_error (param i32 i32)))
+
+  ;; 0: binding name, 8: capability ID, 64: operation, 1024: payload, 65536: response
+  (memory (export "memory") 4)
+  (data (i32.const 0) "default")
+  (data (i32.const 8) "wascc:hostmeta")
+
+  (func (export "__guest_call") (param $op_len i32) (param $msg_len i32) (result i32)
+    (local $len i32)
+    (call $guest_request (i32.const 64) (i32.const 1024))
+    (if (i32.eqz
+          (call $host_call
+            (i32.const 0) (i32.const 7)
+            (i32.const 8) (i32.const 14)
+            (i32.const 64) (local.get $op_len)
+            (i32.const 1024) (i32.const 0)))
+      (then
+        (local.set $len (call $host_error_len))
+        (call $host_error (i32.const 65536))
+        (call $guest_error (i32.const 65536) (local.get $len))
+        (return (i32.const 0))))
+    (local.set $len (call $host_response_len))
+    (call $host_response (i32.const 65536))
+    (call $guest_response (i32.const 65536) (local.get $len))
+    (i32.const 1)))
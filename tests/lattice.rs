@@ -1,11 +1,15 @@
-use latticeclient::Client;
 use std::error::Error;
 
+#[cfg(feature = "test-lattice")]
 pub(crate) fn lattice_single_host() -> Result<(), Box<dyn Error>> {
     use std::time::Duration;
+    use wascc_host::memlattice::{Client, MemBroker};
     use wascc_host::{Host, HostBuilder};
 
+    let broker = MemBroker::new();
+
     let host = HostBuilder::new()
+        .with_mem_broker(broker.clone())
         .with_label("integration", "test")
         .with_label("hostcore.arch", "FOOBAR")
         .with_lattice_namespace("singlehost")
@@ -14,7 +18,7 @@ pub(crate) fn lattice_single_host() -> Result<(), Box<dyn Error>> {
     let delay = Duration::from_millis(500);
     std::thread::sleep(delay);
 
-    let lc = Client::new("127.0.0.1", None, delay, Some("singlehost".to_string()));
+    let lc = Client::with_connection(broker.connect(), delay, Some("singlehost".to_string()));
     let hosts = lc.get_hosts()?;
     assert_eq!(hosts.len(), 1);
     assert_eq!(hosts[0].labels["hostcore.os"], std::env::consts::OS);
@@ -29,14 +33,21 @@ pub(crate) fn lattice_single_host() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[cfg(feature = "test-lattice")]
 pub(crate) fn lattice_isolation() -> Result<(), Box<dyn Error>> {
     use std::time::Duration;
+    use wascc_host::memlattice::{Client, MemBroker};
     use wascc_host::{Host, HostBuilder};
+
+    let broker = MemBroker::new();
+
     let host1 = HostBuilder::new()
+        .with_mem_broker(broker.clone())
         .with_lattice_namespace("system1")
         .with_label("testval", "1")
         .build();
     let host2 = HostBuilder::new()
+        .with_mem_broker(broker.clone())
         .with_lattice_namespace("system2")
         .with_label("testval", "2")
         .build();
@@ -44,17 +55,17 @@ pub(crate) fn lattice_isolation() -> Result<(), Box<dyn Error>> {
     let delay = Duration::from_millis(500);
     std::thread::sleep(delay);
 
-    let lc1 = Client::new("127.0.0.1", None, delay, Some("system1".to_string()));
+    let lc1 = Client::with_connection(broker.connect(), delay, Some("system1".to_string()));
     let hosts1 = lc1.get_hosts()?;
     assert_eq!(hosts1.len(), 1);
     assert_eq!(hosts1[0].labels["testval"], "1");
 
-    let lc2 = Client::new("127.0.0.1", None, delay, Some("system2".to_string()));
+    let lc2 = Client::with_connection(broker.connect(), delay, Some("system2".to_string()));
     let hosts2 = lc2.get_hosts()?;
     assert_eq!(hosts2.len(), 1);
     assert_eq!(hosts2[0].labels["testval"], "2");
 
-    let lc3 = Client::new("127.0.0.1", None, delay, Some("systemnope".to_string()));
+    let lc3 = Client::with_connection(broker.connect(), delay, Some("systemnope".to_string()));
     let hosts3 = lc3.get_hosts()?;
     assert_eq!(hosts3.len(), 0);
 
@@ -64,29 +75,42 @@ pub(crate) fn lattice_isolation() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[cfg(feature = "test-lattice")]
 pub(crate) fn lattice_events() -> Result<(), Box<dyn Error>> {
-    use crossbeam_channel::unbounded;
-    use latticeclient::{BusEvent, CloudEvent};
+    use latticeclient::BusEvent;
     use std::time::Duration;
-    use wascc_host::Host;
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::{HostBuilder, LatticeEvent, ReasonCode};
 
-    let (s, r) = unbounded();
-    let nc = nats::connect("127.0.0.1")?;
+    let broker = MemBroker::new();
 
-    let _sub = nc.subscribe("wasmbus.events")?.with_handler(move |msg| {
-        let ce: CloudEvent = serde_json::from_slice(&msg.data).unwrap();
-        let be: BusEvent = serde_json::from_str(&ce.data).unwrap();
-        let _ = s.send(be);
-        Ok(())
-    });
+    // A second host in the same namespace observes the lattice events of the host under test
+    let observer = HostBuilder::new().with_mem_broker(broker.clone()).build();
+    let r = observer.lattice_events()?;
+    let ext = observer.lattice_extended_events()?;
+    let delay = Duration::from_millis(500);
+    std::thread::sleep(delay);
+    while r.try_recv().is_ok() {} // discard the observer's own startup events
+
+    // Malformed events are skipped without ending the stream
+    let nc = broker.connect();
+    nc.publish("wasmbus.events", "not a cloud event")?;
 
     // K/V counter host:
     // add_actor x 2
     // add_native_capability
     // bind_actor x 2
-    let host = crate::common::gen_kvcounter_host(3666, Host::new())?;
-    let delay = Duration::from_millis(500);
+    let host = crate::common::gen_kvcounter_host(
+        3666,
+        HostBuilder::new().with_mem_broker(broker.clone()).build(),
+    )?;
     std::thread::sleep(delay);
+    // remove_binding (an extended event, not delivered as a BusEvent)
+    host.remove_binding(
+        "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ",
+        "wascc:keyvalue",
+        None,
+    )?;
     host.shutdown()?;
     std::thread::sleep(delay);
     nc.close();
@@ -100,6 +124,7 @@ pub(crate) fn lattice_events() -> Result<(), Box<dyn Error>> {
     let a = [
         r.recv().unwrap(), // host started
         r.recv().unwrap(), // extras prov loaded
+        r.recv().unwrap(), // hostmeta prov loaded
         r.recv().unwrap(), // actor starting
         r.recv().unwrap(), // actor started
         r.recv().unwrap(), // prov loaded
@@ -111,6 +136,7 @@ pub(crate) fn lattice_events() -> Result<(), Box<dyn Error>> {
         r.recv().unwrap(), // provider removed
         r.recv().unwrap(), // provider removed
         r.recv().unwrap(), // provider removed (remember "extras" is an omnipresent provider)
+        r.recv().unwrap(), // provider removed (as is "hostmeta")
         r.recv().unwrap(), // host stop -- the last gasp
     ];
 
@@ -170,17 +196,44 @@ pub(crate) fn lattice_events() -> Result<(), Box<dyn Error>> {
         host: host.id(),
         instance_name: "default".to_string(),
     }));
+    assert!(a.contains(&BusEvent::ProviderLoaded {
+        capid: "wascc:hostmeta".to_string(),
+        instance_name: "default".to_string(),
+        host: host.id(),
+    }));
+    assert!(a.contains(&BusEvent::ProviderRemoved {
+        capid: "wascc:hostmeta".to_string(),
+        host: host.id(),
+        instance_name: "default".to_string(),
+    }));
     assert!(a.contains(&BusEvent::HostStopped(host.id())));
+
+    assert_eq!(
+        LatticeEvent::ActorBindingRemoved {
+            actor: "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ".to_string(),
+            capid: "wascc:keyvalue".to_string(),
+            instance_name: "default".to_string(),
+            host: host.id(),
+            reason: ReasonCode::Requested,
+        },
+        ext.recv_timeout(delay)?
+    );
+    observer.shutdown()?;
     Ok(())
 }
 
+#[cfg(feature = "test-lattice")]
 pub(crate) fn can_bind_from_any_host() -> Result<(), Box<dyn Error>> {
     use redis::Commands;
     use std::time::Duration;
+    use wascc_host::memlattice::MemBroker;
     use wascc_host::{Actor, Host, HostBuilder, NativeCapability};
 
+    let broker = MemBroker::new();
+
     let port = 6203_u16;
     let host = HostBuilder::new()
+        .with_mem_broker(broker.clone())
         .with_lattice_namespace("bindanywhere")
         .build();
 
@@ -198,6 +251,7 @@ pub(crate) fn can_bind_from_any_host() -> Result<(), Box<dyn Error>> {
     // be able to invoke "set binding" from any host in the lattice
     // and have the binding take effect.
     let host2 = HostBuilder::new()
+        .with_mem_broker(broker.clone())
         .with_lattice_namespace("bindanywhere")
         .build();
     host2.set_binding(
@@ -244,15 +298,22 @@ pub(crate) fn can_bind_from_any_host() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[cfg(feature = "test-lattice")]
 pub(crate) fn instance_count() -> Result<(), Box<dyn Error>> {
-    use latticeclient::{BusEvent, Client, CloudEvent};
+    use latticeclient::{BusEvent, CloudEvent};
     use redis::Commands;
     use std::time::Duration;
+    use wascc_host::memlattice::{Client, MemBroker};
     use wascc_host::{Actor, Host, HostBuilder, NativeCapability};
 
+    let broker = MemBroker::new();
+
     let port = 6209_u16;
 
-    let host1 = HostBuilder::new().with_lattice_namespace("icount").build();
+    let host1 = HostBuilder::new()
+        .with_mem_broker(broker.clone())
+        .with_lattice_namespace("icount")
+        .build();
     host1.add_actor(Actor::from_file("./examples/.assets/kvcounter.wasm")?)?;
     host1.add_native_capability(NativeCapability::from_file(
         "./examples/.assets/libwascc_httpsrv.so",
@@ -276,7 +337,10 @@ pub(crate) fn instance_count() -> Result<(), Box<dyn Error>> {
         crate::common::generate_port_config(port),
     )?;
 
-    let host2 = HostBuilder::new().with_lattice_namespace("icount").build();
+    let host2 = HostBuilder::new()
+        .with_mem_broker(broker.clone())
+        .with_lattice_namespace("icount")
+        .build();
     host2.add_actor(Actor::from_file("./examples/.assets/kvcounter.wasm")?)?;
 
     host2.add_native_capability(NativeCapability::from_file(
@@ -286,8 +350,11 @@ pub(crate) fn instance_count() -> Result<(), Box<dyn Error>> {
 
     std::thread::sleep(Duration::from_millis(300));
 
-    let nc = nats::connect("127.0.0.1")?;
-    let lc = Client::with_connection(nc, Duration::from_secs(1), Some("icount".to_string()));
+    let lc = Client::with_connection(
+        broker.connect(),
+        Duration::from_secs(1),
+        Some("icount".to_string()),
+    );
     let actors = lc.get_actors()?;
     let anames: Vec<_> = actors
         .values()
@@ -320,17 +387,22 @@ pub(crate) fn instance_count() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[cfg(feature = "test-lattice")]
 pub(crate) fn unload_reload_actor_retains_bindings() -> Result<(), Box<dyn Error>> {
     use latticeclient::{BusEvent, CloudEvent};
     use redis::Commands;
     use std::time::Duration;
+    use wascc_host::memlattice::MemBroker;
     use wascc_host::{Actor, Host, HostBuilder, NativeCapability};
 
-    let nc = nats::connect("127.0.0.1")?;
+    let broker = MemBroker::new();
+
+    let nc = broker.connect();
 
     let port = 6201_u16;
 
     let host1 = HostBuilder::new()
+        .with_mem_broker(broker.clone())
         .with_lattice_namespace("unloadreload")
         .with_label("testval", "1")
         .build();
@@ -361,6 +433,7 @@ pub(crate) fn unload_reload_actor_retains_bindings() -> Result<(), Box<dyn Error
     )?;
 
     let host2 = HostBuilder::new()
+        .with_mem_broker(broker.clone())
         .with_lattice_namespace("unloadreload")
         .with_label("testval", "2")
         .build();
@@ -414,3 +487,1309 @@ pub(crate) fn unload_reload_actor_retains_bindings() -> Result<(), Box<dyn Error
 
     Ok(())
 }
+
+#[cfg(feature = "test-lattice")]
+pub(crate) fn inventory_responsive_during_provider_download() -> Result<(), Box<dyn Error>> {
+    use latticeclient::controlplane::{CPLANE_PREFIX, LAUNCH_PROVIDER};
+    use std::net::TcpListener;
+    use std::time::{Duration, Instant};
+    use wascc_host::memlattice::{Client, MemBroker};
+    use wascc_host::HostBuilder;
+
+    let broker = MemBroker::new();
+
+    // A "registry" that accepts connections and never answers, so the provider download stalls
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let port = listener.local_addr()?.port();
+    std::thread::spawn(move || {
+        let _conns: Vec<_> = listener.incoming().collect();
+    });
+
+    let host = HostBuilder::new()
+        .with_mem_broker(broker.clone())
+        .with_lattice_namespace("slowprovider")
+        .build();
+    let delay = Duration::from_millis(500);
+    std::thread::sleep(delay);
+
+    let nc = broker.connect();
+    let cmd = serde_json::json!({
+        "provider_ref": format!("127.0.0.1:{}/slow/provider:0.0.1", port),
+        "binding_name": "default",
+    });
+    nc.request_timeout(
+        &format!(
+            "slowprovider.wasmbus.{}.{}.{}",
+            CPLANE_PREFIX,
+            host.id(),
+            LAUNCH_PROVIDER
+        ),
+        &serde_json::to_vec(&cmd)?,
+        Duration::from_secs(2),
+    )?;
+
+    let lc = Client::with_connection(broker.connect(), delay, Some("slowprovider".to_string()));
+    let start = Instant::now();
+    let hosts = lc.get_hosts()?;
+    assert_eq!(hosts.len(), 1);
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    host.shutdown()?;
+    std::thread::sleep(delay);
+    Ok(())
+}
+
+#[cfg(feature = "test-lattice")]
+pub(crate) fn inventory_includes_image_refs() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::HostBuilder;
+
+    let broker = MemBroker::new();
+
+    let host = HostBuilder::new()
+        .with_mem_broker(broker.clone())
+        .with_lattice_namespace("imagerefs")
+        .with_image_fetcher(crate::common::AssetFetcher {})
+        .build();
+    host.add_actor_from_registry("localhost/kvcounter:v1")?;
+    host.set_actor_annotation(
+        "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ",
+        "deployment",
+        "d-42",
+    )?;
+    let delay = Duration::from_millis(500);
+    std::thread::sleep(delay);
+
+    let nc = broker.connect();
+    let res = nc.request_timeout(
+        "imagerefs.wasmbus.inventory.actors_ex",
+        "",
+        Duration::from_secs(2),
+    )?;
+    let inv: serde_json::Value = serde_json::from_slice(&res.data)?;
+    assert_eq!(inv["host"], host.id());
+    assert_eq!(
+        inv["actors"][0]["subject"],
+        "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ"
+    );
+    assert_eq!(inv["actors"][0]["image_ref"], "localhost/kvcounter:v1");
+    assert!(inv["actors"][0]["started_at"].as_u64().unwrap() > 0);
+    assert!(inv["actors"][0]["invocations"].is_u64());
+    assert_eq!(inv["actors"][0]["errors"], 0);
+    assert_eq!(inv["actors"][0]["annotations"]["deployment"], "d-42");
+
+    host.shutdown()?;
+    std::thread::sleep(delay);
+    Ok(())
+}
+
+#[cfg(feature = "test-lattice")]
+pub(crate) fn binding_reaches_all_provider_instances() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use wascc_codec::core::OP_BIND_ACTOR;
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::middleware::{InvocationHandler, Middleware, MiddlewareResponse};
+    use wascc_host::{Actor, HostBuilder, Invocation, InvocationResponse, NativeCapability};
+
+    let broker = MemBroker::new();
+
+    // Counts the binding invocations received by the providers in a host
+    struct BindCounter {
+        binds: Arc<AtomicUsize>,
+    }
+
+    impl Middleware for BindCounter {
+        fn actor_pre_invoke(&self, inv: Invocation) -> wascc_host::Result<Invocation> {
+            Ok(inv)
+        }
+        fn actor_invoke(
+            &self,
+            inv: Invocation,
+            handler: InvocationHandler,
+        ) -> wascc_host::Result<MiddlewareResponse> {
+            Ok(MiddlewareResponse::Continue(handler.invoke(inv)))
+        }
+        fn actor_post_invoke(
+            &self,
+            response: InvocationResponse,
+        ) -> wascc_host::Result<InvocationResponse> {
+            Ok(response)
+        }
+        fn capability_pre_invoke(&self, inv: Invocation) -> wascc_host::Result<Invocation> {
+            if inv.operation == OP_BIND_ACTOR {
+                self.binds.fetch_add(1, Ordering::SeqCst);
+            }
+            Ok(inv)
+        }
+        fn capability_invoke(
+            &self,
+            inv: Invocation,
+            handler: InvocationHandler,
+        ) -> wascc_host::Result<MiddlewareResponse> {
+            Ok(MiddlewareResponse::Continue(handler.invoke(inv)))
+        }
+        fn capability_post_invoke(
+            &self,
+            response: InvocationResponse,
+        ) -> wascc_host::Result<InvocationResponse> {
+            Ok(response)
+        }
+    }
+
+    let mut counters = vec![];
+    let mut hosts = vec![];
+    for _ in 0..2 {
+        let binds = Arc::new(AtomicUsize::new(0));
+        let host = HostBuilder::new()
+            .with_mem_broker(broker.clone())
+            .with_lattice_namespace("bindfanout")
+            .build();
+        host.add_middleware(BindCounter {
+            binds: binds.clone(),
+        });
+        host.add_native_capability(NativeCapability::from_file(
+            "./examples/.assets/libkeyvalue.so",
+            None,
+        )?)?;
+        counters.push(binds);
+        hosts.push(host);
+    }
+    hosts[0].add_actor(Actor::from_file("./examples/.assets/kvcounter.wasm")?)?;
+    std::thread::sleep(Duration::from_millis(500));
+
+    hosts[0].set_binding(
+        "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ",
+        "wascc:keyvalue",
+        None,
+        HashMap::new(),
+    )?;
+
+    // Both provider instances received (and acknowledged) the binding, not just one of them
+    assert_eq!(1, counters[0].load(Ordering::SeqCst));
+    assert_eq!(1, counters[1].load(Ordering::SeqCst));
+
+    for host in hosts {
+        host.shutdown()?;
+    }
+    std::thread::sleep(Duration::from_millis(500));
+    Ok(())
+}
+
+#[cfg(feature = "test-lattice")]
+pub(crate) fn administer_other_namespace() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use wascc_codec::http::{Request, Response};
+    use wascc_codec::{deserialize, serialize};
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::{Actor, HostBuilder, NativeCapability};
+
+    let broker = MemBroker::new();
+
+    let kvcounter = "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ";
+    let admin = HostBuilder::new()
+        .with_mem_broker(broker.clone())
+        .with_lattice_namespace("nsadmin")
+        .build();
+    let worker = HostBuilder::new()
+        .with_mem_broker(broker.clone())
+        .with_lattice_namespace("nsworker")
+        .build();
+    worker.add_actor(Actor::from_file("./examples/.assets/kvcounter.wasm")?)?;
+    worker.add_native_capability(NativeCapability::from_file(
+        "./examples/.assets/libkeyvalue.so",
+        None,
+    )?)?;
+    std::thread::sleep(Duration::from_millis(500));
+
+    // The actor isn't visible in the admin host's own namespace
+    assert!(admin
+        .set_binding(kvcounter, "wascc:keyvalue", None, HashMap::new())
+        .is_err());
+    admin.set_binding_in_namespace(
+        "nsworker",
+        kvcounter,
+        "wascc:keyvalue",
+        None,
+        HashMap::new(),
+    )?;
+
+    let req = Request {
+        method: "GET".to_string(),
+        path: "/admin".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    };
+    assert!(admin
+        .call_actor(kvcounter, "HandleRequest", &serialize(&req)?)
+        .is_err());
+    let resp: Response = deserialize(&admin.call_actor_in_namespace(
+        "nsworker",
+        kvcounter,
+        "HandleRequest",
+        &serialize(&req)?,
+    )?)?;
+    assert_eq!(200, resp.status_code);
+    assert_eq!("{\"counter\":1}", String::from_utf8(resp.body)?);
+
+    admin.shutdown()?;
+    worker.shutdown()?;
+    std::thread::sleep(Duration::from_millis(500));
+    Ok(())
+}
+
+#[cfg(feature = "test-lattice")]
+pub(crate) fn lattice_actors_by_tag() -> Result<(), Box<dyn Error>> {
+    use crate::common::generate_tagged_actor;
+    use std::time::Duration;
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::{HostBuilder, TagMatch};
+
+    let broker = MemBroker::new();
+
+    let bytes = std::fs::read("./examples/.assets/echo.wasm")?;
+    let payments = generate_tagged_actor(&bytes, &["payment"])?;
+    let pay_pk = payments.public_key();
+    let shipping = generate_tagged_actor(&bytes, &["shipping"])?;
+    let ship_pk = shipping.public_key();
+    let mut both = vec![pay_pk.to_string(), ship_pk.to_string()];
+    both.sort();
+
+    let host1 = HostBuilder::new()
+        .with_mem_broker(broker.clone())
+        .with_lattice_namespace("tagquery")
+        .build();
+    let host2 = HostBuilder::new()
+        .with_mem_broker(broker.clone())
+        .with_lattice_namespace("tagquery")
+        .build();
+    host1.add_actor(payments)?;
+    host2.add_actor(shipping)?;
+    std::thread::sleep(Duration::from_millis(500));
+
+    assert!(host1.actors_by_tag(&["shipping"]).is_empty());
+    assert_eq!(
+        vec![ship_pk.to_string()],
+        host1.lattice_actors_by_tag(&["Shipping"], TagMatch::All)?
+    );
+    assert_eq!(
+        both,
+        host1.lattice_actors_by_tag(&["payment", "shipping"], TagMatch::Any)?
+    );
+    assert!(host1
+        .lattice_actors_by_tag(&["payment", "shipping"], TagMatch::All)?
+        .is_empty());
+
+    host1.shutdown()?;
+    host2.shutdown()?;
+    std::thread::sleep(Duration::from_millis(500));
+    Ok(())
+}
+
+#[cfg(feature = "test-lattice")]
+pub(crate) fn lost_actor_subscription_restored() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+    use wascc_codec::serialize;
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::{HealthStatus, HostBuilder, HostEvent};
+
+    let broker = MemBroker::new();
+    let host = HostBuilder::new()
+        .with_mem_broker(broker.clone())
+        .with_lattice_namespace("resubscribe")
+        .build();
+    let events = host.events();
+    let echo = crate::common::get_hello_actor()?;
+    let pk = echo.public_key();
+    host.add_actor(echo)?;
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    host.call_actor(&pk, OP_HANDLE_REQUEST, &req)?;
+
+    // The "server" tears down the actor's subscription without the host asking
+    let subject = format!("resubscribe.wasmbus.actor.{}", pk);
+    assert_eq!(1, broker.drop_subscriptions(&subject));
+    let mut lost = false;
+    loop {
+        match events.recv_timeout(Duration::from_secs(2))? {
+            HostEvent::SubscriptionLost {
+                subject: s,
+                attempt,
+            } if s == subject => {
+                assert_eq!(1, attempt);
+                lost = true;
+            }
+            HostEvent::SubscriptionRestored { subject: s } if s == subject => break,
+            _ => {}
+        }
+    }
+    assert!(lost);
+    assert_eq!(HealthStatus::Healthy, host.actor_health()[&pk]);
+    host.call_actor(&pk, OP_HANDLE_REQUEST, &req)?;
+
+    // Intentionally removed subscriptions stay removed
+    host.remove_actor(&pk)?;
+    std::thread::sleep(Duration::from_millis(500));
+    assert!(events
+        .try_iter()
+        .all(|e| !matches!(e, HostEvent::SubscriptionLost { .. })));
+
+    host.shutdown()?;
+    Ok(())
+}
+
+#[cfg(all(feature = "test-lattice", feature = "testing"))]
+pub(crate) fn binding_timeout_outlasts_invocation_timeout() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use wascc_codec::core::OP_BIND_ACTOR;
+    use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+    use wascc_codec::keyvalue::OP_ADD;
+    use wascc_codec::serialize;
+    use wascc_host::errors::{BusError, ErrorKind};
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::testing::MockCapability;
+    use wascc_host::{Actor, HostBuilder, LatticeConfig, NativeCapability};
+
+    let kvcounter = "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ";
+    let host = HostBuilder::new()
+        .with_mem_broker(MemBroker::new())
+        .with_lattice_config(LatticeConfig {
+            invocation_timeout: Some(Duration::from_millis(1000)),
+            binding_timeout: Some(Duration::from_millis(5000)),
+            ..Default::default()
+        })
+        .build();
+    let mock = MockCapability::new("wascc:keyvalue");
+    mock.on(OP_BIND_ACTOR, |_actor, _msg| {
+        std::thread::sleep(Duration::from_millis(1500));
+        Ok(vec![])
+    });
+    mock.on(OP_ADD, |_actor, _msg| {
+        std::thread::sleep(Duration::from_millis(300));
+        let mut hm = HashMap::new();
+        hm.insert("value", 1);
+        serialize(&hm)
+    });
+    host.add_native_capability(NativeCapability::from_instance(mock.clone(), None)?)?;
+    host.add_actor(Actor::from_file("./examples/.assets/kvcounter.wasm")?)?;
+
+    // Slower than the invocation timeout, but within the binding timeout
+    host.set_binding(kvcounter, "wascc:keyvalue", None, HashMap::new())?;
+    assert_eq!(1, mock.calls_for(OP_BIND_ACTOR).len());
+
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/counter".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    host.call_actor(kvcounter, OP_HANDLE_REQUEST, &req)?;
+
+    // A per-call timeout takes precedence over the configured one
+    let err = host
+        .call_actor_with_timeout(
+            kvcounter,
+            OP_HANDLE_REQUEST,
+            &req,
+            Duration::from_millis(100),
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.kind(),
+        ErrorKind::Bus(BusError::Timeout { .. })
+    ));
+
+    host.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "test-lattice")]
+pub(crate) fn signed_control_commands() -> Result<(), Box<dyn Error>> {
+    use latticeclient::controlplane::{CPLANE_PREFIX, LAUNCH_ACTOR};
+    use std::time::Duration;
+    use wascap::prelude::KeyPair;
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::{sign_control_command, HostBuilder, HostEvent};
+
+    let echo = "MB4OLDIC3TCZ4Q4TGGOVAZC43VXFE2JQVRAXQMQFXUCREOOFEKOKZTY2";
+    let issuer = KeyPair::new_operator();
+    let broker = MemBroker::new();
+    let host = HostBuilder::new()
+        .with_mem_broker(broker.clone())
+        .with_lattice_namespace("signedcmds")
+        .with_image_fetcher(crate::common::AssetFetcher {})
+        .with_control_issuers(vec![issuer.public_key()])
+        .build();
+    let events = host.events();
+    let nc = broker.connect();
+    let subject = format!(
+        "signedcmds.wasmbus.{}.{}.{}",
+        CPLANE_PREFIX,
+        host.id(),
+        LAUNCH_ACTOR
+    );
+    let cmd = serde_json::to_vec(&serde_json::json!({ "actor_id": "localhost/echo:v1" }))?;
+    let assert_rejected = |payload: &[u8]| -> Result<(), Box<dyn Error>> {
+        nc.publish(&subject, payload)?;
+        loop {
+            match events.recv_timeout(Duration::from_secs(2))? {
+                HostEvent::ControlCommandRejected { subject: s, .. } if s == subject => break,
+                _ => {}
+            }
+        }
+        assert!(!host.actors().iter().any(|(pk, _)| pk == echo));
+        Ok(())
+    };
+
+    // Unsigned, and signed by an issuer the host doesn't trust
+    assert_rejected(&cmd)?;
+    let forged = sign_control_command(
+        &KeyPair::new_operator(),
+        &subject,
+        &cmd,
+        Duration::from_secs(60),
+    )?;
+    assert_rejected(&forged)?;
+
+    let signed = sign_control_command(&issuer, &subject, &cmd, Duration::from_secs(60))?;
+    nc.request_timeout(&subject, &signed, Duration::from_secs(2))?;
+    std::thread::sleep(Duration::from_millis(500));
+    assert!(host.actors().iter().any(|(pk, _)| pk == echo));
+
+    // The same signed command can't be replayed, even though it hasn't expired
+    host.remove_actor(echo)?;
+    std::thread::sleep(Duration::from_millis(500));
+    assert_rejected(&signed)?;
+
+    host.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "test-lattice")]
+pub(crate) fn invocation_signer_rotation() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use wascap::prelude::KeyPair;
+    use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+    use wascc_codec::serialize;
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::HostBuilder;
+
+    let broker = MemBroker::new();
+    let first = KeyPair::new_server().seed()?;
+    let second = KeyPair::new_server().seed()?;
+    let host = |seed: &str| {
+        HostBuilder::new()
+            .with_mem_broker(broker.clone())
+            .with_lattice_namespace("signerrotation")
+            .with_invocation_signer(seed)
+            .build()
+    };
+    // The actor runs in one host and is called from another
+    let runner = host(&first);
+    let caller = host(&first);
+    let echo = crate::common::get_hello_actor()?;
+    let pk = echo.public_key();
+    runner.add_actor(echo)?;
+    let runner_id = runner.id();
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+
+    let done = AtomicBool::new(false);
+    crossbeam_utils::thread::scope(|s| {
+        let traffic = s.spawn(|_| {
+            let mut calls = 0;
+            while !done.load(Ordering::SeqCst) {
+                caller.call_actor(&pk, OP_HANDLE_REQUEST, &req).unwrap();
+                calls += 1;
+            }
+            calls
+        });
+        // Every host trusts the announced key, then the calling host rotates ahead of the
+        // receiving one, both within the grace period
+        let next = KeyPair::from_seed(&second).unwrap().public_key();
+        for h in &[&runner, &caller] {
+            h.trust_invocation_signer(&next, Duration::from_secs(5))
+                .unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(200));
+        caller
+            .rotate_invocation_signer(&second, Duration::from_millis(1500))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        runner
+            .rotate_invocation_signer(&second, Duration::from_millis(1500))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        done.store(true, Ordering::SeqCst);
+        assert!(traffic.join().unwrap() > 0);
+    })
+    .unwrap();
+    assert_eq!(runner_id, runner.id());
+
+    // Once the grace period is over, the old key is no longer accepted
+    let stale = host(&first);
+    assert!(stale.call_actor(&pk, OP_HANDLE_REQUEST, &req).is_ok());
+    std::thread::sleep(Duration::from_millis(1500));
+    assert!(stale.call_actor(&pk, OP_HANDLE_REQUEST, &req).is_err());
+    caller.call_actor(&pk, OP_HANDLE_REQUEST, &req)?;
+
+    // Only a configured signing key can be rotated
+    let unsigned = HostBuilder::new().with_mem_broker(broker.clone()).build();
+    assert!(unsigned
+        .rotate_invocation_signer(&second, Duration::from_secs(1))
+        .is_err());
+
+    for h in vec![runner, caller, stale, unsigned] {
+        h.shutdown()?;
+    }
+    Ok(())
+}
+
+#[cfg(all(feature = "test-lattice", feature = "testing"))]
+pub(crate) fn bindings_synced_from_lattice() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::testing::MockCapability;
+    use wascc_host::{Actor, HostBuilder, NativeCapability};
+
+    let kvcounter = "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ";
+    let broker = MemBroker::new();
+    let provider_host = HostBuilder::new().with_mem_broker(broker.clone()).build();
+    let actor_host = HostBuilder::new().with_mem_broker(broker.clone()).build();
+    provider_host.add_native_capability(NativeCapability::from_instance(
+        MockCapability::new("wascc:keyvalue"),
+        None,
+    )?)?;
+    actor_host.add_actor(Actor::from_file("./examples/.assets/kvcounter.wasm")?)?;
+
+    let mut config = HashMap::new();
+    config.insert("bucket".to_string(), "first".to_string());
+    provider_host.set_binding(kvcounter, "wascc:keyvalue", None, config.clone())?;
+    assert!(actor_host
+        .provider_bindings("wascc:keyvalue", "default")
+        .is_empty());
+
+    assert_eq!(1, actor_host.sync_bindings_from_lattice()?);
+    assert_eq!(
+        vec![kvcounter.to_string()],
+        actor_host.provider_bindings("wascc:keyvalue", "default")
+    );
+    assert_eq!(0, actor_host.sync_bindings_from_lattice()?);
+
+    // The lattice's configuration replaces a different local one
+    config.insert("bucket".to_string(), "second".to_string());
+    provider_host.set_binding(kvcounter, "wascc:keyvalue", None, config)?;
+    assert_eq!(1, actor_host.sync_bindings_from_lattice()?);
+    assert_eq!(0, actor_host.sync_bindings_from_lattice()?);
+
+    // Bindings for actors and providers not in the host aren't merged
+    let empty_host = HostBuilder::new().with_mem_broker(broker.clone()).build();
+    assert_eq!(0, empty_host.sync_bindings_from_lattice()?);
+
+    // With sync enabled, bindings are merged as actors are added
+    let synced_host = HostBuilder::new()
+        .with_mem_broker(broker.clone())
+        .with_lattice_binding_sync(true)
+        .build();
+    synced_host.add_actor(Actor::from_file("./examples/.assets/kvcounter.wasm")?)?;
+    assert_eq!(
+        vec![kvcounter.to_string()],
+        synced_host.provider_bindings("wascc:keyvalue", "default")
+    );
+
+    // Where the other hosts disagree, the host's own configuration is kept, however often the
+    // hosts are asked and in whatever order they answer
+    let value_names = |h: &wascc_host::Host| -> Vec<Vec<String>> {
+        h.snapshot()
+            .bindings
+            .into_iter()
+            .map(|b| b.value_names)
+            .collect()
+    };
+    let other_provider_host = HostBuilder::new().with_mem_broker(broker.clone()).build();
+    other_provider_host.add_native_capability(NativeCapability::from_instance(
+        MockCapability::new("wascc:keyvalue"),
+        None,
+    )?)?;
+    let mut config = HashMap::new();
+    config.insert("bucket".to_string(), "third".to_string());
+    config.insert("region".to_string(), "east".to_string());
+    other_provider_host.set_binding(kvcounter, "wascc:keyvalue", None, config)?;
+    for _ in 0..5 {
+        assert_eq!(0, actor_host.sync_bindings_from_lattice()?);
+        assert_eq!(vec![vec!["bucket".to_string()]], value_names(&actor_host));
+    }
+
+    for h in vec![
+        provider_host,
+        actor_host,
+        empty_host,
+        synced_host,
+        other_provider_host,
+    ] {
+        h.shutdown()?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "test-lattice")]
+pub(crate) fn call_actor_anywhere() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+    use wascc_codec::http::{Request, Response, OP_HANDLE_REQUEST};
+    use wascc_codec::{deserialize, serialize};
+    use wascc_host::errors::{BusError, ErrorKind};
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::{HostBuilder, LatticeConfig};
+
+    let broker = MemBroker::new();
+    let host = || {
+        HostBuilder::new()
+            .with_mem_broker(broker.clone())
+            .with_lattice_namespace("anywhere")
+            .with_lattice_config(LatticeConfig {
+                invocation_timeout: Some(Duration::from_millis(500)),
+                ..Default::default()
+            })
+            .build()
+    };
+    let caller = host();
+    let runner = host();
+    let echo = crate::common::get_hello_actor()?;
+    let pk = echo.public_key();
+    runner.add_actor(echo)?;
+
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/anywhere".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    let resp: Response = deserialize(&caller.call_actor_anywhere(&pk, OP_HANDLE_REQUEST, &req)?)?;
+    assert_eq!(200, resp.status_code);
+
+    // Once no host runs the actor, the call fails without a responder
+    runner.remove_actor(&pk)?;
+    std::thread::sleep(Duration::from_millis(200));
+    let start = Instant::now();
+    let err = caller
+        .call_actor_anywhere(&pk, OP_HANDLE_REQUEST, &req)
+        .unwrap_err();
+    assert!(
+        matches!(err.kind(), ErrorKind::Bus(BusError::NoResponders { .. })),
+        "{}",
+        err
+    );
+    assert!(start.elapsed() < Duration::from_secs(3));
+
+    caller.shutdown()?;
+    runner.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "test-lattice")]
+pub(crate) fn remote_claims_bounded() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::{Actor, HostBuilder, LatticeConfig};
+
+    let broker = MemBroker::new();
+    let caller = HostBuilder::new()
+        .with_mem_broker(broker.clone())
+        .with_lattice_namespace("remoteclaims")
+        .with_lattice_config(LatticeConfig {
+            binding_timeout: Some(Duration::from_millis(200)),
+            remote_claims_capacity: Some(2),
+            ..Default::default()
+        })
+        .build();
+    let runner = HostBuilder::new()
+        .with_mem_broker(broker.clone())
+        .with_lattice_namespace("remoteclaims")
+        .build();
+    caller.add_actor(crate::common::get_hello_actor()?)?;
+    let mut remote = Vec::new();
+    for path in &[
+        "./examples/.assets/echo2.wasm",
+        "./examples/.assets/kvcounter.wasm",
+        "./examples/.assets/multibinding.wasm",
+    ] {
+        let actor = Actor::from_file(path)?;
+        remote.push(actor.public_key());
+        runner.add_actor(actor)?;
+    }
+
+    // Every binding looks up the remote actor's claims, whether or not it is applied
+    for _ in 0..5 {
+        for pk in remote.iter() {
+            let _ = caller.set_binding(pk, "wascc:http_server", None, HashMap::new());
+            assert!(caller.remote_claims_cached() <= 2);
+        }
+    }
+    assert_eq!(2, caller.remote_claims_cached());
+    let local: Vec<_> = caller.actors().into_iter().map(|(pk, _)| pk).collect();
+    assert_eq!(
+        vec!["MB4OLDIC3TCZ4Q4TGGOVAZC43VXFE2JQVRAXQMQFXUCREOOFEKOKZTY2".to_string()],
+        local
+    );
+
+    caller.shutdown()?;
+    runner.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "test-lattice")]
+pub(crate) fn remote_claims_rechecked() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use wascap::jwt::{Actor as ActorClaims, Claims};
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::{Actor, Authorizer, HostBuilder, LatticeConfig, WasccEntity};
+
+    // Revokes every actor but the caller's own once the flag is set
+    struct RevokingAuthorizer(Arc<AtomicBool>);
+    impl Authorizer for RevokingAuthorizer {
+        fn can_load(&self, claims: &Claims<ActorClaims>) -> bool {
+            !self.0.load(Ordering::SeqCst)
+                || claims.subject == "MB4OLDIC3TCZ4Q4TGGOVAZC43VXFE2JQVRAXQMQFXUCREOOFEKOKZTY2"
+        }
+        fn can_invoke(&self, _: &Claims<ActorClaims>, _: &WasccEntity, _: &str) -> bool {
+            true
+        }
+    }
+
+    let revoked = Arc::new(AtomicBool::new(false));
+    let broker = MemBroker::new();
+    let caller = HostBuilder::new()
+        .with_mem_broker(broker.clone())
+        .with_lattice_namespace("rechecked")
+        .with_lattice_config(LatticeConfig {
+            binding_timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        })
+        .with_authorizer(RevokingAuthorizer(revoked.clone()))
+        .build();
+    let runner = HostBuilder::new()
+        .with_mem_broker(broker.clone())
+        .with_lattice_namespace("rechecked")
+        .build();
+    caller.add_actor(crate::common::get_hello_actor()?)?;
+    let echo2 = Actor::from_file("./examples/.assets/echo2.wasm")?;
+    let pk = echo2.public_key();
+    runner.add_actor(echo2)?;
+
+    let _ = caller.set_binding(&pk, "wascc:http_server", None, HashMap::new());
+    assert_eq!(1, caller.remote_claims_cached());
+
+    // The cached claims aren't used once the actor has been revoked
+    revoked.store(true, Ordering::SeqCst);
+    let err = caller
+        .set_binding(&pk, "wascc:http_server", None, HashMap::new())
+        .unwrap_err();
+    assert!(err.to_string().contains("denied access"), "{}", err);
+    assert_eq!(0, caller.remote_claims_cached());
+
+    caller.shutdown()?;
+    runner.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "test-lattice")]
+pub(crate) fn broadcast_delivery() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+    use wascc_codec::serialize;
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::{DeliveryMode, HostBuilder};
+
+    let broker = MemBroker::new();
+    let host = |prefer_local| {
+        HostBuilder::new()
+            .with_mem_broker(broker.clone())
+            .with_lattice_namespace("broadcast")
+            .with_lattice_prefer_local(prefer_local)
+            .build()
+    };
+    let caller = host(false);
+    let runners = [host(true), host(true)];
+    let mut pk = String::new();
+    for runner in runners.iter() {
+        let echo = crate::common::get_hello_actor()?;
+        pk = echo.public_key();
+        runner.add_actor_with_delivery(echo, DeliveryMode::Broadcast)?;
+    }
+
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/broadcast".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    caller.call_actor_anywhere(&pk, OP_HANDLE_REQUEST, &req)?;
+    // The caller only waits for the first response
+    std::thread::sleep(Duration::from_millis(200));
+    for runner in runners.iter() {
+        assert_eq!(1, runner.actor_runtime_info(&pk).unwrap().invocations);
+    }
+    // Preferring local delivery doesn't keep a broadcast from the other instances
+    runners[0].call_actor(&pk, OP_HANDLE_REQUEST, &req)?;
+    std::thread::sleep(Duration::from_millis(200));
+    for runner in runners.iter() {
+        assert_eq!(2, runner.actor_runtime_info(&pk).unwrap().invocations);
+    }
+
+    caller.shutdown()?;
+    for runner in runners.iter() {
+        runner.shutdown()?;
+    }
+    Ok(())
+}
+
+#[cfg(all(feature = "test-lattice", feature = "testing"))]
+pub(crate) fn launch_refused_while_draining() -> Result<(), Box<dyn Error>> {
+    use latticeclient::controlplane::{CPLANE_PREFIX, LAUNCH_ACTOR};
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+    use wascc_codec::keyvalue::OP_ADD;
+    use wascc_codec::serialize;
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::testing::MockCapability;
+    use wascc_host::{Actor, HostBuilder, NativeCapability};
+
+    let broker = MemBroker::new();
+    let host = HostBuilder::new()
+        .with_mem_broker(broker.clone())
+        .with_lattice_namespace("draining")
+        .with_image_fetcher(crate::common::AssetFetcher {})
+        .build();
+    let subject = format!(
+        "draining.wasmbus.{}.{}.{}",
+        CPLANE_PREFIX,
+        host.id(),
+        LAUNCH_ACTOR
+    );
+
+    // An invocation that's held in flight keeps the shutdown from stopping the actors
+    let actor = Actor::from_file("./examples/.assets/kvcounter.wasm")?;
+    let pk = actor.public_key();
+    host.add_actor(actor)?;
+    let (release_s, release_r) = crossbeam_channel::bounded::<()>(1);
+    let mock = MockCapability::new("wascc:keyvalue");
+    mock.on(OP_ADD, move |_actor, _msg| {
+        let _ = release_r.recv_timeout(Duration::from_secs(5));
+        let mut hm = HashMap::new();
+        hm.insert("value", 1);
+        serialize(&hm)
+    });
+    host.add_native_capability(NativeCapability::from_instance(mock, None)?)?;
+    host.set_binding(&pk, "wascc:keyvalue", None, HashMap::new())?;
+    let req = serialize(&Request {
+        method: "GET".to_string(),
+        path: "/counter".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    let (h, call_pk) = (host.clone(), pk.clone());
+    let call = std::thread::spawn(move || h.call_actor(&call_pk, OP_HANDLE_REQUEST, &req).is_ok());
+    std::thread::sleep(Duration::from_millis(100));
+    let h = host.clone();
+    let shutdown = std::thread::spawn(move || h.shutdown().map_err(|e| e.to_string()));
+    std::thread::sleep(Duration::from_millis(200));
+
+    let cmd = serde_json::json!({ "actor_id": "localhost/echo:v1" }).to_string();
+    let reply = broker
+        .connect()
+        .request_timeout(&subject, &cmd, Duration::from_secs(2))?;
+    let reply: serde_json::Value = serde_json::from_slice(&reply.data)?;
+    assert_eq!("host draining", reply["error"]);
+    assert_eq!(host.id(), reply["host"]);
+    assert_eq!(
+        vec![pk],
+        host.actors()
+            .into_iter()
+            .map(|(pk, _)| pk)
+            .collect::<Vec<_>>()
+    );
+
+    release_s.send(())?;
+    assert!(call.join().unwrap());
+    shutdown.join().unwrap()?;
+    assert!(host.actors().is_empty());
+    Ok(())
+}
+
+#[cfg(all(feature = "test-lattice", feature = "testing"))]
+pub(crate) fn bindings_reestablished_with_retries() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use wascc_codec::core::OP_BIND_ACTOR;
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::testing::MockCapability;
+    use wascc_host::{HostBuilder, HostEvent, NativeCapability};
+
+    let kvcounter = "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ";
+    let broker = MemBroker::new();
+    let host = || HostBuilder::new().with_mem_broker(broker.clone()).build();
+    let bound_host = host();
+    bound_host.add_native_capability(NativeCapability::from_instance(
+        MockCapability::new("wascc:keyvalue"),
+        None,
+    )?)?;
+    bound_host.set_binding(kvcounter, "wascc:keyvalue", None, HashMap::new())?;
+
+    // A new provider instance that times out twice before it can bind the actor
+    let flaky_host = host();
+    let events = flaky_host.events();
+    let flaky = MockCapability::new("wascc:keyvalue");
+    let attempts = Arc::new(AtomicU32::new(0));
+    let a = attempts.clone();
+    flaky.on(OP_BIND_ACTOR, move |_actor, _msg| {
+        if a.fetch_add(1, Ordering::SeqCst) < 2 {
+            Err("timed out".into())
+        } else {
+            Ok(vec![])
+        }
+    });
+    flaky_host.add_native_capability(NativeCapability::from_instance(flaky.clone(), None)?)?;
+    assert_eq!(3, flaky.calls_for(OP_BIND_ACTOR).len());
+    assert!(events
+        .try_iter()
+        .all(|e| !matches!(e, HostEvent::BindingsNotReestablished { .. })));
+
+    // One that never can is reported
+    let broken_host = host();
+    let events = broken_host.events();
+    let broken = MockCapability::new("wascc:keyvalue");
+    broken.on(OP_BIND_ACTOR, |_actor, _msg| Err("unavailable".into()));
+    broken_host.add_native_capability(NativeCapability::from_instance(broken.clone(), None)?)?;
+    assert!(broken.calls_for(OP_BIND_ACTOR).len() > 1);
+    let failures = events
+        .try_iter()
+        .find_map(|e| match e {
+            HostEvent::BindingsNotReestablished {
+                capid,
+                binding,
+                failures,
+            } => {
+                assert_eq!("wascc:keyvalue", capid);
+                assert_eq!("default", binding);
+                Some(failures)
+            }
+            _ => None,
+        })
+        .expect("no BindingsNotReestablished event");
+    assert_eq!(vec![kvcounter], failures.keys().collect::<Vec<_>>());
+
+    for h in vec![bound_host, flaky_host, broken_host] {
+        h.shutdown()?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "test-lattice")]
+pub(crate) fn prefer_local_dispatch() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use wascc_codec::http::{Request, Response, OP_HANDLE_REQUEST};
+    use wascc_codec::{deserialize, serialize};
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::HostBuilder;
+
+    let broker = MemBroker::new();
+    let local = HostBuilder::new()
+        .with_mem_broker(broker.clone())
+        .with_lattice_namespace("preferlocal")
+        .with_lattice_prefer_local(true)
+        .build();
+    let remote = HostBuilder::new()
+        .with_mem_broker(broker.clone())
+        .with_lattice_namespace("preferlocal")
+        .build();
+    let pk = crate::common::get_hello_actor()?.public_key();
+    local.add_actor(crate::common::get_hello_actor()?)?;
+    remote.add_actor(crate::common::get_hello_actor()?)?;
+
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/local".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    for _ in 0..10 {
+        let resp: Response = deserialize(&local.call_actor(&pk, OP_HANDLE_REQUEST, &req)?)?;
+        assert_eq!(200, resp.status_code);
+    }
+    // Every call was answered by the instance in the calling host, never the queue group peer
+    assert_eq!(10, local.actor_recent_invocations(&pk).len());
+    assert!(remote.actor_recent_invocations(&pk).is_empty());
+
+    // Invocations arriving over the lattice are still served by the same instance
+    remote.remove_actor(&pk)?;
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let resp: Response = deserialize(&remote.call_actor_anywhere(&pk, OP_HANDLE_REQUEST, &req)?)?;
+    assert_eq!(200, resp.status_code);
+    assert_eq!(11, local.actor_recent_invocations(&pk).len());
+
+    local.shutdown()?;
+    remote.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "test-lattice")]
+pub(crate) fn malformed_messages_ignored() -> Result<(), Box<dyn Error>> {
+    use latticeclient::controlplane::{
+        AUCTION_REQ, CPLANE_PREFIX, LAUNCH_ACTOR, LAUNCH_PROVIDER, PROVIDER_AUCTION_REQ,
+        TERMINATE_ACTOR, TERMINATE_PROVIDER,
+    };
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+    use wascc_codec::http::{Request, Response, OP_HANDLE_REQUEST};
+    use wascc_codec::{deserialize, serialize};
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::{HostBuilder, InvocationResponse};
+
+    let broker = MemBroker::new();
+    let host = HostBuilder::new()
+        .with_mem_broker(broker.clone())
+        .with_lattice_namespace("malformed")
+        .with_image_fetcher(crate::common::AssetFetcher {})
+        .build();
+    let nc = broker.connect();
+    let timeout = Duration::from_secs(2);
+    let garbage = b"\xff\x00{not json";
+    let size = format!("{} bytes", garbage.len());
+    let command = |op: &str| format!("malformed.wasmbus.{}.{}.{}", CPLANE_PREFIX, host.id(), op);
+
+    // Malformed commands are answered with an error naming the payload's size
+    for op in &[
+        LAUNCH_ACTOR,
+        TERMINATE_ACTOR,
+        LAUNCH_PROVIDER,
+        TERMINATE_PROVIDER,
+    ] {
+        let reply = nc.request_timeout(&command(op), &garbage[..], timeout)?;
+        let reply: serde_json::Value = serde_json::from_slice(&reply.data)?;
+        assert_eq!(host.id(), reply["host"]);
+        assert!(reply["error"].as_str().unwrap().contains(&size));
+    }
+    // Any reply to an auction would be taken as a bid, so malformed ones go unanswered
+    for op in &[AUCTION_REQ, PROVIDER_AUCTION_REQ] {
+        let subject = format!("malformed.wasmbus.{}.{}", CPLANE_PREFIX, op);
+        assert!(nc.request_timeout(&subject, &garbage[..], timeout).is_err());
+    }
+
+    // The control plane is still serving
+    let cmd = serde_json::json!({ "actor_id": "localhost/echo:v1" }).to_string();
+    nc.request_timeout(&command(LAUNCH_ACTOR), &cmd, timeout)?;
+    let started = Instant::now();
+    while host.actors().is_empty() && started.elapsed() < Duration::from_secs(5) {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let pk = crate::common::get_hello_actor()?.public_key();
+    assert_eq!(pk, host.actors()[0].0);
+
+    // So is the actor, after a malformed invocation
+    let subject = format!("malformed.wasmbus.actor.{}", pk);
+    let reply = nc.request_timeout(&subject, &garbage[..], timeout)?;
+    let reply: InvocationResponse = deserialize(&reply.data)?;
+    assert!(reply.error.unwrap().contains(&size));
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/malformed".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+    let resp: Response = deserialize(&host.call_actor(&pk, OP_HANDLE_REQUEST, &req)?)?;
+    assert_eq!(200, resp.status_code);
+
+    host.shutdown()?;
+    Ok(())
+}
+
+#[cfg(all(feature = "test-lattice", feature = "testing"))]
+pub(crate) fn unchanged_bindings_not_resent() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use wascc_codec::core::OP_BIND_ACTOR;
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::testing::MockCapability;
+    use wascc_host::{HostBuilder, NativeCapability};
+
+    let kvcounter = "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ";
+    let broker = MemBroker::new();
+    let host = || HostBuilder::new().with_mem_broker(broker.clone()).build();
+    let bound_host = host();
+    bound_host.add_native_capability(NativeCapability::from_instance(
+        MockCapability::new("wascc:keyvalue"),
+        None,
+    )?)?;
+    let mut config = HashMap::new();
+    config.insert("URL".to_string(), "redis://127.0.0.1".to_string());
+    bound_host.set_binding(kvcounter, "wascc:keyvalue", None, config)?;
+
+    // A new instance is always given the lattice's bindings
+    let recording_host = host();
+    let recorder = MockCapability::new("wascc:keyvalue");
+    recording_host
+        .add_native_capability(NativeCapability::from_instance(recorder.clone(), None)?)?;
+    assert_eq!(1, recorder.calls_for(OP_BIND_ACTOR).len());
+
+    // but not again while their configuration is unchanged, unless forced
+    assert_eq!(
+        0,
+        recording_host.reestablish_bindings("wascc:keyvalue", None, false)?
+    );
+    assert_eq!(1, recorder.calls_for(OP_BIND_ACTOR).len());
+    assert_eq!(
+        1,
+        recording_host.reestablish_bindings("wascc:keyvalue", None, true)?
+    );
+    assert_eq!(2, recorder.calls_for(OP_BIND_ACTOR).len());
+    assert!(recording_host
+        .reestablish_bindings("wascc:keyvalue", Some("nosuch".to_string()), false)
+        .is_err());
+
+    // What an instance accepted is forgotten along with it
+    recording_host.remove_native_capability("wascc:keyvalue", None)?;
+    let replacement = MockCapability::new("wascc:keyvalue");
+    recording_host
+        .add_native_capability(NativeCapability::from_instance(replacement.clone(), None)?)?;
+    assert_eq!(1, replacement.calls_for(OP_BIND_ACTOR).len());
+
+    for h in vec![bound_host, recording_host] {
+        h.shutdown()?;
+    }
+    Ok(())
+}
+
+#[cfg(all(feature = "test-lattice", feature = "testing"))]
+pub(crate) fn delivered_bindings_not_resent() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use wascc_codec::core::OP_BIND_ACTOR;
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::testing::MockCapability;
+    use wascc_host::{HostBuilder, NativeCapability};
+
+    let kvcounter = "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ";
+    let host = HostBuilder::new().with_mem_broker(MemBroker::new()).build();
+    let recorder = MockCapability::new("wascc:keyvalue");
+    host.add_native_capability(NativeCapability::from_instance(recorder.clone(), None)?)?;
+
+    // A binding the instance accepted from set_binding isn't sent to it again
+    let mut config = HashMap::new();
+    config.insert("URL".to_string(), "redis://127.0.0.1".to_string());
+    host.set_binding(kvcounter, "wascc:keyvalue", None, config)?;
+    assert_eq!(1, recorder.calls_for(OP_BIND_ACTOR).len());
+    assert_eq!(0, host.reestablish_bindings("wascc:keyvalue", None, false)?);
+    assert_eq!(1, recorder.calls_for(OP_BIND_ACTOR).len());
+
+    // and neither is a configuration it accepted as an update
+    let mut delta = HashMap::new();
+    delta.insert("URL".to_string(), "redis://10.0.0.1".to_string());
+    host.update_binding_values(kvcounter, "wascc:keyvalue", None, delta)?;
+    assert_eq!(2, recorder.calls_for(OP_BIND_ACTOR).len());
+    assert_eq!(0, host.reestablish_bindings("wascc:keyvalue", None, false)?);
+    assert_eq!(2, recorder.calls_for(OP_BIND_ACTOR).len());
+
+    host.shutdown()?;
+    Ok(())
+}
+
+#[cfg(all(feature = "test-lattice", feature = "testing"))]
+pub(crate) fn binding_failure_reaches_every_host() -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+    use wascc_codec::core::OP_REMOVE_ACTOR;
+    use wascc_host::memlattice::MemBroker;
+    use wascc_host::testing::MockCapability;
+    use wascc_host::{Actor, HostBuilder, LatticeEvent, NativeCapability, OP_BINDING_REMOVED};
+
+    let kvcounter = "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ";
+    let broker = MemBroker::new();
+    let hosts: Vec<_> = (0..2)
+        .map(|_| HostBuilder::new().with_mem_broker(broker.clone()).build())
+        .collect();
+    let events = hosts[1].lattice_extended_events()?;
+    let mocks: Vec<_> = (0..2)
+        .map(|_| MockCapability::new("wascc:keyvalue"))
+        .collect();
+    for (h, m) in hosts.iter().zip(mocks.iter()) {
+        h.add_native_capability(NativeCapability::from_instance(m.clone(), None)?)?;
+        h.add_actor(Actor::from_file("./examples/.assets/kvcounter.wasm")?)?;
+        h.set_binding(kvcounter, "wascc:keyvalue", None, HashMap::new())?;
+    }
+
+    for m in mocks.iter() {
+        m.clear_calls();
+    }
+
+    // One provider's report removes the binding from every host, not just one of them
+    mocks[0].fail_binding(kvcounter, "credentials revoked")?;
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while hosts
+        .iter()
+        .any(|h| !h.provider_bindings("wascc:keyvalue", "default").is_empty())
+    {
+        assert!(Instant::now() < deadline, "binding not removed everywhere");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+
+    // and each host tells its own instance of the actor, once
+    std::thread::sleep(Duration::from_millis(200));
+    for h in hosts.iter() {
+        let notices = h
+            .actor_recent_invocations(kvcounter)
+            .into_iter()
+            .filter(|e| e.operation == OP_BINDING_REMOVED)
+            .count();
+        assert_eq!(1, notices);
+    }
+
+    // Every provider instance is asked to release the binding once, and the removal is
+    // announced to the lattice once, however many hosts held the binding
+    for m in mocks.iter() {
+        assert_eq!(1, m.calls_for(OP_REMOVE_ACTOR).len());
+    }
+    let mut removals = 0;
+    while let Ok(e) = events.recv_timeout(Duration::from_millis(200)) {
+        if let LatticeEvent::ActorBindingRemoved { .. } = e {
+            removals += 1;
+        }
+    }
+    assert_eq!(1, removals);
+
+    for h in hosts {
+        h.shutdown()?;
+    }
+    Ok(())
+}
@@ -17,11 +17,41 @@ fn authorizer_blocks_bindings() -> Result<(), Box<dyn Error>> {
     auth::authorizer_blocks_bindings()
 }
 
+#[test]
+fn authorizer_denials_counted() -> Result<(), Box<dyn Error>> {
+    auth::authorizer_denials_counted()
+}
+
 #[test]
 fn authorizer_blocks_load() -> Result<(), Box<dyn Error>> {
     auth::authorizer_blocks_load()
 }
 
+#[test]
+fn authorizer_chain_blocks_load() -> Result<(), Box<dyn Error>> {
+    auth::authorizer_chain_blocks_load()
+}
+
+#[test]
+fn authorizer_uses_host_labels() -> Result<(), Box<dyn Error>> {
+    auth::authorizer_uses_host_labels()
+}
+
+#[test]
+fn authorizer_blocks_replace() -> Result<(), Box<dyn Error>> {
+    auth::authorizer_blocks_replace()
+}
+
+#[test]
+fn authorizer_blocks_terminate() -> Result<(), Box<dyn Error>> {
+    auth::authorizer_blocks_terminate()
+}
+
+#[test]
+fn actor_only_host_rejects_capabilities() -> Result<(), Box<dyn Error>> {
+    auth::actor_only_host_rejects_capabilities()
+}
+
 #[test]
 fn stock_host() -> Result<(), Box<dyn Error>> {
     core::stock_host()
@@ -33,41 +63,468 @@ fn kv_host() -> Result<(), Box<dyn Error>> {
 }
 
 #[test]
-#[cfg(feature = "lattice")]
+fn quick_host_default_binding() -> Result<(), Box<dyn Error>> {
+    core::quick_host_default_binding()
+}
+
+#[test]
+fn actor_from_stub_registry() -> Result<(), Box<dyn Error>> {
+    core::actor_from_stub_registry()
+}
+
+#[test]
+fn reserved_config_keys_rejected() -> Result<(), Box<dyn Error>> {
+    core::reserved_config_keys_rejected()
+}
+
+#[test]
+fn actor_invocations_audited() -> Result<(), Box<dyn Error>> {
+    core::actor_invocations_audited()
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn call_actor_with_codec_types() -> Result<(), Box<dyn Error>> {
+    core::call_actor_with_codec_types()
+}
+
+#[test]
+fn actors_by_tag_modes() -> Result<(), Box<dyn Error>> {
+    core::actors_by_tag_modes()
+}
+
+#[test]
+fn lifecycle_hooks_run_in_order() -> Result<(), Box<dyn Error>> {
+    core::lifecycle_hooks_run_in_order()
+}
+
+#[test]
+fn host_from_env() -> Result<(), Box<dyn Error>> {
+    core::host_from_env()
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn kv_host_mocked() -> Result<(), Box<dyn Error>> {
+    core::kv_host_mocked()
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn circuit_breaker_stops_calls() -> Result<(), Box<dyn Error>> {
+    core::circuit_breaker_stops_calls()
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn provider_stats_count_bindings() -> Result<(), Box<dyn Error>> {
+    core::provider_stats_count_bindings()
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn bind_handler_dispatches_to_actor() -> Result<(), Box<dyn Error>> {
+    core::bind_handler_dispatches_to_actor()
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn unhealthy_provider_fails_fast() -> Result<(), Box<dyn Error>> {
+    core::unhealthy_provider_fails_fast()
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn group_binding_skips_unattested() -> Result<(), Box<dyn Error>> {
+    core::group_binding_skips_unattested()
+}
+
+#[test]
+fn caching_middleware_expires_and_evicts() -> Result<(), Box<dyn Error>> {
+    core::caching_middleware_expires_and_evicts()
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn caching_middleware_skips_control_operations() -> Result<(), Box<dyn Error>> {
+    core::caching_middleware_skips_control_operations()
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn removed_actor_fails_fast() -> Result<(), Box<dyn Error>> {
+    core::removed_actor_fails_fast()
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn in_flight_invocations_drain() -> Result<(), Box<dyn Error>> {
+    core::in_flight_invocations_drain()
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn binding_updates_ordered_with_calls() -> Result<(), Box<dyn Error>> {
+    core::binding_updates_ordered_with_calls()
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn provider_reported_binding_failure() -> Result<(), Box<dyn Error>> {
+    core::provider_reported_binding_failure()
+}
+
+#[test]
+#[cfg(feature = "watch")]
+fn watched_actor_is_replaced() -> Result<(), Box<dyn Error>> {
+    core::watched_actor_is_replaced()
+}
+
+#[test]
+#[cfg(feature = "manifest")]
+fn manifest_continues_past_failures() -> Result<(), Box<dyn Error>> {
+    core::manifest_continues_past_failures()
+}
+
+#[test]
+#[cfg(feature = "manifest")]
+fn manifest_waits_for_readiness() -> Result<(), Box<dyn Error>> {
+    core::manifest_waits_for_readiness()
+}
+
+#[test]
+#[cfg(feature = "manifest")]
+fn preflight_reports_problems() -> Result<(), Box<dyn Error>> {
+    core::preflight_reports_problems()
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn hosts_in_one_process_are_isolated() -> Result<(), Box<dyn Error>> {
+    core::hosts_in_one_process_are_isolated()
+}
+
+#[test]
+fn undeclared_codec_version_loaded() -> Result<(), Box<dyn Error>> {
+    core::undeclared_codec_version_loaded()
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn custom_default_binding_name() -> Result<(), Box<dyn Error>> {
+    core::custom_default_binding_name()
+}
+
+#[test]
+fn claims_skew_and_quarantine() -> Result<(), Box<dyn Error>> {
+    core::claims_skew_and_quarantine()
+}
+
+#[test]
+#[cfg(all(feature = "chaos", feature = "prometheus_middleware"))]
+fn chaos_latency_in_metrics() -> Result<(), Box<dyn Error>> {
+    core::chaos_latency_in_metrics()
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn remove_and_readd_actor() -> Result<(), Box<dyn Error>> {
+    core::remove_and_readd_actor()
+}
+
+#[test]
+fn actor_runtime_info() -> Result<(), Box<dyn Error>> {
+    core::actor_runtime_info()
+}
+
+#[test]
+fn middleware_error_policy() -> Result<(), Box<dyn Error>> {
+    core::middleware_error_policy()
+}
+
+#[test]
+fn actor_start_time() -> Result<(), Box<dyn Error>> {
+    core::actor_start_time()
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn update_binding_values() -> Result<(), Box<dyn Error>> {
+    core::update_binding_values()
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn named_bindings() -> Result<(), Box<dyn Error>> {
+    core::named_bindings()
+}
+
+#[test]
+fn actor_reads_host_metadata() -> Result<(), Box<dyn Error>> {
+    core::actor_reads_host_metadata()
+}
+
+#[test]
+fn extras_bindings_released() -> Result<(), Box<dyn Error>> {
+    core::extras_bindings_released()
+}
+
+#[test]
+fn configure_actor() -> Result<(), Box<dyn Error>> {
+    core::configure_actor()
+}
+
+#[test]
+fn concurrent_add_actor() -> Result<(), Box<dyn Error>> {
+    core::concurrent_add_actor()
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn invocation_origin_chain() -> Result<(), Box<dyn Error>> {
+    core::invocation_origin_chain()
+}
+
+#[test]
+fn call_actor_typed() -> Result<(), Box<dyn Error>> {
+    core::call_actor_typed()
+}
+
+#[test]
+fn actor_annotations() -> Result<(), Box<dyn Error>> {
+    core::actor_annotations()
+}
+
+#[test]
+#[cfg(feature = "admin_api")]
+fn admin_socket() -> Result<(), Box<dyn Error>> {
+    core::admin_socket()
+}
+
+#[test]
+#[cfg(feature = "manifest")]
+fn manifest_parallel() -> Result<(), Box<dyn Error>> {
+    core::manifest_parallel()
+}
+
+#[test]
+fn capability_allowlist() -> Result<(), Box<dyn Error>> {
+    core::capability_allowlist()
+}
+
+#[test]
+fn host_features() -> Result<(), Box<dyn Error>> {
+    core::host_features()
+}
+
+#[test]
+fn actor_imports() -> Result<(), Box<dyn Error>> {
+    core::actor_imports()
+}
+
+#[test]
+fn bind_before_add() -> Result<(), Box<dyn Error>> {
+    core::bind_before_add()
+}
+
+#[test]
+fn broadcast_to_actors() -> Result<(), Box<dyn Error>> {
+    core::broadcast_to_actors()
+}
+
+#[test]
+fn portable_provider_duplicates_refused() -> Result<(), Box<dyn Error>> {
+    core::portable_provider_duplicates_refused()
+}
+
+#[test]
+fn timings_recorded() -> Result<(), Box<dyn Error>> {
+    core::timings_recorded()
+}
+
+#[test]
+fn remove_portable_capability() -> Result<(), Box<dyn Error>> {
+    core::remove_portable_capability()
+}
+
+#[test]
+fn for_each_actor_matches_actors() -> Result<(), Box<dyn Error>> {
+    core::for_each_actor_matches_actors()
+}
+
+#[test]
+fn snapshots_are_consistent() -> Result<(), Box<dyn Error>> {
+    core::snapshots_are_consistent()
+}
+
+#[test]
+#[cfg(all(feature = "manifest", feature = "testing"))]
+fn manifest_binding_templates() -> Result<(), Box<dyn Error>> {
+    core::manifest_binding_templates()
+}
+
+#[test]
+#[cfg(feature = "test-lattice")]
 fn unload_reload_actor_retains_bindings() -> Result<(), Box<dyn Error>> {
     lattice::unload_reload_actor_retains_bindings()
 }
 
 #[test]
-#[cfg(feature = "lattice")]
+#[cfg(feature = "test-lattice")]
 fn can_bind_from_any_host() -> Result<(), Box<dyn Error>> {
     lattice::can_bind_from_any_host()
 }
 
 #[test]
-#[cfg(feature = "lattice")]
+#[cfg(feature = "test-lattice")]
 fn instance_count() -> Result<(), Box<dyn Error>> {
     lattice::instance_count()
 }
 
 #[test]
-#[cfg(feature = "lattice")]
+#[cfg(feature = "test-lattice")]
 fn lattice_single_host() -> Result<(), Box<dyn Error>> {
     lattice::lattice_single_host()
 }
 
 #[test]
-#[cfg(feature = "lattice")]
+#[cfg(feature = "test-lattice")]
 fn lattice_isolation() -> Result<(), Box<dyn Error>> {
     lattice::lattice_isolation()
 }
 
 #[test]
-#[cfg(feature = "lattice")]
+#[cfg(feature = "test-lattice")]
+fn inventory_responsive_during_provider_download() -> Result<(), Box<dyn Error>> {
+    lattice::inventory_responsive_during_provider_download()
+}
+
+#[test]
+#[cfg(feature = "test-lattice")]
+fn inventory_includes_image_refs() -> Result<(), Box<dyn Error>> {
+    lattice::inventory_includes_image_refs()
+}
+
+#[test]
+#[cfg(feature = "test-lattice")]
+fn binding_reaches_all_provider_instances() -> Result<(), Box<dyn Error>> {
+    lattice::binding_reaches_all_provider_instances()
+}
+
+#[test]
+#[cfg(feature = "test-lattice")]
+fn administer_other_namespace() -> Result<(), Box<dyn Error>> {
+    lattice::administer_other_namespace()
+}
+
+#[test]
+#[cfg(feature = "test-lattice")]
+fn lattice_actors_by_tag() -> Result<(), Box<dyn Error>> {
+    lattice::lattice_actors_by_tag()
+}
+
+#[test]
+#[cfg(feature = "test-lattice")]
 fn lattice_events() -> Result<(), Box<dyn Error>> {
     lattice::lattice_events()
 }
 
+#[test]
+#[cfg(feature = "test-lattice")]
+fn lost_actor_subscription_restored() -> Result<(), Box<dyn Error>> {
+    lattice::lost_actor_subscription_restored()
+}
+
+#[test]
+#[cfg(all(feature = "test-lattice", feature = "testing"))]
+fn binding_timeout_outlasts_invocation_timeout() -> Result<(), Box<dyn Error>> {
+    lattice::binding_timeout_outlasts_invocation_timeout()
+}
+
+#[test]
+#[cfg(all(feature = "test-lattice", feature = "testing"))]
+fn bindings_synced_from_lattice() -> Result<(), Box<dyn Error>> {
+    lattice::bindings_synced_from_lattice()
+}
+
+#[test]
+#[cfg(all(feature = "test-lattice", feature = "testing"))]
+fn bindings_reestablished_with_retries() -> Result<(), Box<dyn Error>> {
+    lattice::bindings_reestablished_with_retries()
+}
+
+#[test]
+#[cfg(feature = "test-lattice")]
+fn invocation_signer_rotation() -> Result<(), Box<dyn Error>> {
+    lattice::invocation_signer_rotation()
+}
+
+#[test]
+#[cfg(feature = "test-lattice")]
+fn signed_control_commands() -> Result<(), Box<dyn Error>> {
+    lattice::signed_control_commands()
+}
+
+#[test]
+#[cfg(feature = "test-lattice")]
+fn call_actor_anywhere() -> Result<(), Box<dyn Error>> {
+    lattice::call_actor_anywhere()
+}
+
+#[test]
+#[cfg(feature = "test-lattice")]
+fn remote_claims_bounded() -> Result<(), Box<dyn Error>> {
+    lattice::remote_claims_bounded()
+}
+
+#[test]
+#[cfg(feature = "test-lattice")]
+fn remote_claims_rechecked() -> Result<(), Box<dyn Error>> {
+    lattice::remote_claims_rechecked()
+}
+
+#[test]
+#[cfg(feature = "test-lattice")]
+fn broadcast_delivery() -> Result<(), Box<dyn Error>> {
+    lattice::broadcast_delivery()
+}
+
+#[test]
+#[cfg(all(feature = "test-lattice", feature = "testing"))]
+fn launch_refused_while_draining() -> Result<(), Box<dyn Error>> {
+    lattice::launch_refused_while_draining()
+}
+
+#[test]
+#[cfg(feature = "test-lattice")]
+fn prefer_local_dispatch() -> Result<(), Box<dyn Error>> {
+    lattice::prefer_local_dispatch()
+}
+
+#[test]
+#[cfg(feature = "test-lattice")]
+fn malformed_messages_ignored() -> Result<(), Box<dyn Error>> {
+    lattice::malformed_messages_ignored()
+}
+
+#[test]
+#[cfg(all(feature = "test-lattice", feature = "testing"))]
+fn unchanged_bindings_not_resent() -> Result<(), Box<dyn Error>> {
+    lattice::unchanged_bindings_not_resent()
+}
+
+#[test]
+#[cfg(all(feature = "test-lattice", feature = "testing"))]
+fn delivered_bindings_not_resent() -> Result<(), Box<dyn Error>> {
+    lattice::delivered_bindings_not_resent()
+}
+
+#[test]
+#[cfg(all(feature = "test-lattice", feature = "testing"))]
+fn binding_failure_reaches_every_host() -> Result<(), Box<dyn Error>> {
+    lattice::binding_failure_reaches_every_host()
+}
+
 //#[test]
 //fn simple_load() -> Result<(), Box<dyn Error>> {
 //    load::simple_load()
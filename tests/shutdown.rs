@@ -0,0 +1,62 @@
+//! Runs in its own test binary so that the panic hook installed here doesn't
+//! observe (or interfere with) any of the other integration tests.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use wascc_codec::http::{Request, OP_HANDLE_REQUEST};
+use wascc_codec::serialize;
+use wascc_host::{Actor, Host};
+
+#[test]
+fn shutdown_under_load() -> Result<(), Box<dyn Error>> {
+    // Panics on the host's own threads never reach the caller, so count every
+    // panic in the process rather than relying on join handles
+    let panics = Arc::new(AtomicUsize::new(0));
+    let counter = panics.clone();
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        previous(info);
+    }));
+
+    let host = Host::new();
+    let actor = Actor::from_file("./examples/.assets/echo.wasm")?;
+    let pk = actor.public_key();
+    host.add_actor(actor)?;
+    let req = serialize(Request {
+        method: "GET".to_string(),
+        path: "/".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })?;
+
+    let callers: Vec<_> = (0..4)
+        .map(|_| {
+            let (host, pk, req) = (host.clone(), pk.clone(), req.clone());
+            std::thread::spawn(move || {
+                // Calls that race the shutdown must fail rather than panic
+                let mut calls = 0;
+                while host.call_actor(&pk, OP_HANDLE_REQUEST, &req).is_ok() {
+                    calls += 1;
+                }
+                calls
+            })
+        })
+        .collect();
+    std::thread::sleep(Duration::from_millis(200));
+    host.shutdown()?;
+    let mut calls = 0;
+    for caller in callers {
+        calls += caller.join().expect("caller panicked");
+    }
+    // Give any straggling host threads a moment to unwind
+    std::thread::sleep(Duration::from_millis(200));
+
+    assert!(calls > 0);
+    assert_eq!(0, panics.load(Ordering::SeqCst));
+    Ok(())
+}